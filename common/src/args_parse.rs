@@ -66,6 +66,15 @@ pub fn out_ips_parse(ips: &Vec<String>) -> Result<Vec<(u32, u32)>, String> {
     Ok(in_ips_c)
 }
 
+/// 网卡驱动，返回是否使用tap
+pub fn driver_parse(driver: &str) -> Result<bool, String> {
+    match driver.to_lowercase().trim() {
+        "tun" | "wintun" => Ok(false),
+        "tap" => Ok(true),
+        _ => Err(format!("driver {:?}, options tun/tap", driver)),
+    }
+}
+
 pub fn to_ip(mask: &str) -> Result<u32, String> {
     if let Ok(m) = mask.parse::<u32>() {
         if m > 32 {
@@ -1,51 +1,77 @@
 use std::process;
 
 use console::style;
-use vnt::{ConnectInfo, ErrorInfo, ErrorType, HandshakeInfo, RegisterInfo, VntCallback};
+use vnt::{ConnectInfo, ErrorInfo, HandshakeInfo, RegisterInfo, VntCallback};
 
-#[derive(Clone)]
-pub struct VntHandler {}
+use crate::exit;
+
+#[derive(Clone, Default)]
+pub struct VntHandler {
+    // 停止后不退出进程，由调用方处理，作为系统服务运行时使用
+    pub keep_process: bool,
+    // 同时运行多个网络时输出前加上配置组名
+    pub network: Option<String>,
+}
+
+impl VntHandler {
+    fn prefix(&self) -> String {
+        match self.network.as_ref() {
+            Some(network) => format!("[{}] ", network),
+            None => String::new(),
+        }
+    }
+}
 
 impl VntCallback for VntHandler {
     fn success(&self) {
-        println!(" {} ", style("====== Connect Successfully ======").green())
+        println!(
+            "{} {} ",
+            self.prefix(),
+            style("====== Connect Successfully ======").green()
+        )
     }
     #[cfg(feature = "integrated_tun")]
     fn create_tun(&self, info: vnt::DeviceInfo) {
-        println!("create_tun {}", info)
+        println!("{}create_tun {}", self.prefix(), info)
     }
 
     fn connect(&self, info: ConnectInfo) {
-        println!("connect {}", info)
+        println!("{}connect {}", self.prefix(), info)
     }
 
     fn handshake(&self, info: HandshakeInfo) -> bool {
-        println!("handshake {}", info);
+        println!("{}handshake {}", self.prefix(), info);
         true
     }
 
     fn register(&self, info: RegisterInfo) -> bool {
-        println!("register {}", style(info).green());
+        println!("{}register {}", self.prefix(), style(info).green());
         true
     }
 
     fn error(&self, info: ErrorInfo) {
-        log::error!("error {:?}", info);
-        println!("{}", style(format!("error {}", info)).red());
-        match info.code {
-            ErrorType::TokenError
-            | ErrorType::AddressExhausted
-            | ErrorType::IpAlreadyExists
-            | ErrorType::InvalidIp
-            | ErrorType::LocalIpExists => {
-                self.stop();
-            }
-            _ => {}
+        log::error!("{}error {:?}", self.prefix(), info);
+        println!(
+            "{}{}",
+            self.prefix(),
+            style(format!("error {}", info)).red()
+        );
+        if let Some(code) = exit::error_code(info.code) {
+            exit::record(code, &info.to_string());
+            self.stop();
         }
     }
 
     fn stop(&self) {
-        println!("stopped");
-        process::exit(0)
+        log::info!("{}stopped", self.prefix());
+        println!("{}stopped", self.prefix());
+        // 切换组网时由启动方重新连接
+        #[cfg(feature = "command")]
+        if crate::command::switch::pending() {
+            return;
+        }
+        if !self.keep_process {
+            process::exit(exit::recorded())
+        }
     }
 }
@@ -0,0 +1,206 @@
+use std::io;
+use std::io::Write;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::json;
+use vnt::core::Vnt;
+use vnt::VntEvent;
+
+use crate::command::http::{self, Request, Response};
+
+const CONTENT_TYPE: &str = "application/json; charset=utf-8";
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+// 订阅事件的最长等待时间
+const MAX_EVENTS_TIMEOUT: u64 = 60;
+
+/// 启动本地管理接口，请求需要带上 `Authorization: Bearer <token>`。
+/// 没有配置token时随机生成，保存到程序目录下的env/api-token，图形界面等本地程序从这里读取
+pub fn start(addr: SocketAddr, token: Option<String>, vnt: Vnt) -> io::Result<()> {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let token = uuid::Uuid::new_v4().simple().to_string();
+            save_token(&token)?;
+            token
+        }
+    };
+    if !addr.ip().is_loopback() {
+        log::warn!("管理接口监听在非本地地址 {}", addr);
+    }
+    http::serve(addr, "api", move |request| {
+        if !authorized(request, &token) {
+            return error(401, "unauthorized");
+        }
+        handle(request, &crate::command::switch::current(&vnt))
+    })
+}
+
+fn save_token(token: &str) -> io::Result<()> {
+    let path_buf = crate::cli::app_home()?.join("api-token");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path_buf)?;
+    file.write_all(token.as_bytes())?;
+    file.sync_all()
+}
+
+fn authorized(request: &Request, token: &str) -> bool {
+    let value = match request.header("authorization") {
+        Some(value) => value,
+        None => return false,
+    };
+    match value.strip_prefix("Bearer ") {
+        // 逐字节比较完，不提前返回
+        Some(v) => {
+            v.len() == token.len()
+                && v.bytes()
+                    .zip(token.bytes())
+                    .fold(0, |acc, (a, b)| acc | (a ^ b))
+                    == 0
+        }
+        None => false,
+    }
+}
+
+fn ok<T: Serialize>(value: &T) -> Response {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::new(200, CONTENT_TYPE, body),
+        Err(e) => error(500, &e.to_string()),
+    }
+}
+
+fn error(status: u16, msg: &str) -> Response {
+    Response::new(status, CONTENT_TYPE, json!({ "error": msg }).to_string())
+}
+
+fn handle(request: &Request, vnt: &Vnt) -> Response {
+    let body = String::from_utf8_lossy(&request.body);
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/api/status") => ok(&crate::command::command_status(vnt)),
+        ("GET", "/api/info") => ok(&crate::command::command_info(vnt)),
+        ("GET", "/api/list") => ok(&crate::command::command_list(vnt)),
+        ("GET", "/api/route") => ok(&crate::command::command_route(vnt)),
+        ("GET", "/api/stats") => ok(&crate::command::command_stats(vnt)),
+        ("GET", "/api/events") => {
+            let since = request
+                .query_param("since")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0);
+            let timeout = request
+                .query_param("timeout")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(30)
+                .min(MAX_EVENTS_TIMEOUT);
+            let (next, events) = vnt.events(since, Duration::from_secs(timeout));
+            let events: Vec<_> = events
+                .into_iter()
+                .map(|(seq, event)| event_json(seq, event))
+                .collect();
+            ok(&json!({ "next": next, "events": events }))
+        }
+        ("GET", "/api/mapping") => ok(&crate::command::command_mapping_list(vnt)),
+        ("POST", "/api/mapping") => match mapping_add(vnt, body.trim()) {
+            Ok(_) => ok(&json!({ "added": body.trim() })),
+            Err(e) => error(400, &e.to_string()),
+        },
+        ("DELETE", "/api/mapping") => match mapping_remove(vnt, body.trim()) {
+            Ok(true) => ok(&json!({ "removed": body.trim() })),
+            Ok(false) => error(404, "port mapping not found"),
+            Err(e) => error(400, &e.to_string()),
+        },
+        ("POST", "/api/reconnect") => {
+            vnt.reconnect();
+            ok(&json!({ "reconnecting": true }))
+        }
+        ("POST", "/api/log_level") => match request.query_param("level") {
+            Some(level) => match set_log_level(level) {
+                Ok(levels) => ok(&json!({ "levels": levels })),
+                Err(e) => error(400, &e.to_string()),
+            },
+            None => error(400, "missing level"),
+        },
+        ("POST", "/api/ping") => {
+            let peer = match request.query_param("peer").map(|v| v.parse::<Ipv4Addr>()) {
+                Some(Ok(peer)) => peer,
+                _ => return error(400, "invalid peer"),
+            };
+            match vnt.ping_peer(&peer, PING_TIMEOUT) {
+                Ok(Some(route)) => ok(&json!({
+                    "peer": peer.to_string(),
+                    "reachable": true,
+                    "connect": if route.is_p2p() { "p2p" } else { "relay" },
+                    "rt": route.rt,
+                })),
+                Ok(None) => ok(&json!({ "peer": peer.to_string(), "reachable": false })),
+                Err(e) => error(400, &e.to_string()),
+            }
+        }
+        (
+            _,
+            "/api/status" | "/api/info" | "/api/list" | "/api/route" | "/api/stats" | "/api/events"
+            | "/api/mapping" | "/api/reconnect" | "/api/log_level" | "/api/ping",
+        ) => error(405, "method not allowed"),
+        _ => error(404, "not found"),
+    }
+}
+
+fn event_json(seq: u64, event: VntEvent) -> serde_json::Value {
+    let mut value = match &event {
+        VntEvent::ServerConnected { server } => json!({ "server": server.to_string() }),
+        VntEvent::ServerDisconnected => json!({}),
+        VntEvent::IpChanged { old, new } => {
+            json!({ "old": old.to_string(), "new": new.to_string() })
+        }
+        VntEvent::PeerOnline { virtual_ip, name } | VntEvent::PeerOffline { virtual_ip, name } => {
+            json!({ "virtual_ip": virtual_ip.to_string(), "name": name })
+        }
+        VntEvent::DirectLinkUp { virtual_ip, addr } => {
+            json!({ "virtual_ip": virtual_ip.to_string(), "addr": addr.to_string() })
+        }
+        VntEvent::DirectLinkDown { virtual_ip } => {
+            json!({ "virtual_ip": virtual_ip.to_string() })
+        }
+    };
+    value["seq"] = json!(seq);
+    value["type"] = json!(event.name());
+    value
+}
+
+/// 返回运行中修改过的级别，根日志的模块名为空
+#[cfg(feature = "log")]
+fn set_log_level(spec: &str) -> anyhow::Result<Vec<(String, String)>> {
+    crate::logging::set_log_levels(spec)?;
+    Ok(crate::logging::log_levels()
+        .into_iter()
+        .map(|(module, level)| (module, level.to_string().to_lowercase()))
+        .collect())
+}
+
+#[cfg(not(feature = "log"))]
+fn set_log_level(_spec: &str) -> anyhow::Result<Vec<(String, String)>> {
+    Err(anyhow::anyhow!("log feature is not enabled"))
+}
+
+#[cfg(feature = "port_mapping")]
+fn mapping_add(vnt: &Vnt, rule: &str) -> anyhow::Result<()> {
+    vnt.add_port_mapping(rule.parse()?)
+}
+
+#[cfg(feature = "port_mapping")]
+fn mapping_remove(vnt: &Vnt, rule: &str) -> anyhow::Result<bool> {
+    vnt.remove_port_mapping(&rule.parse()?)
+}
+
+#[cfg(not(feature = "port_mapping"))]
+fn mapping_add(_vnt: &Vnt, _rule: &str) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("port_mapping feature is not enabled"))
+}
+
+#[cfg(not(feature = "port_mapping"))]
+fn mapping_remove(_vnt: &Vnt, _rule: &str) -> anyhow::Result<bool> {
+    Err(anyhow::anyhow!("port_mapping feature is not enabled"))
+}
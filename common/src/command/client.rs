@@ -4,7 +4,9 @@ use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
 use std::str::FromStr;
 use std::time::Duration;
 
-use crate::command::entity::{ChartA, ChartB, DeviceItem, Info, RouteItem};
+use crate::command::entity::{
+    ChartA, ChartB, DeviceItem, Info, RouteItem, RouteTableItem, Stats, Status,
+};
 
 pub struct CommandClient {
     buf: Vec<u8>,
@@ -29,9 +31,24 @@ impl CommandClient {
         })
     }
 }
+// 同时运行多个网络时，命令发送到'--profile'指定的网络
+static NETWORK: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+pub fn select_network(network: Option<String>) {
+    *NETWORK.lock().unwrap() = network;
+}
+
 fn read_command_port() -> io::Result<u16> {
-    let path_buf = crate::cli::app_home()?.join("command-port");
-    let port = std::fs::read_to_string(path_buf)?;
+    let home = crate::cli::app_home()?;
+    let network = NETWORK.lock().unwrap().clone();
+    // 只运行一个网络时没有按配置组保存的端口
+    let port = match network
+        .map(|network| std::fs::read_to_string(home.join(format!("command-port-{}", network))))
+    {
+        Some(Ok(port)) => port,
+        Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => std::fs::read_to_string(home.join("command-port"))?,
+    };
     match u16::from_str(&port) {
         Ok(port) => Ok(port),
         Err(_) => {
@@ -50,9 +67,18 @@ impl CommandClient {
     pub fn route(&mut self) -> io::Result<Vec<RouteItem>> {
         self.send_cmd(b"route")
     }
+    pub fn route_table(&mut self) -> io::Result<Vec<RouteTableItem>> {
+        self.send_cmd(b"route_table")
+    }
     pub fn info(&mut self) -> io::Result<Info> {
         self.send_cmd(b"info")
     }
+    pub fn status(&mut self) -> io::Result<Status> {
+        self.send_cmd(b"status")
+    }
+    pub fn stats(&mut self) -> io::Result<Stats> {
+        self.send_cmd(b"stats")
+    }
     pub fn chart_a(&mut self) -> io::Result<ChartA> {
         self.send_cmd(b"chart_a")
     }
@@ -89,4 +115,50 @@ impl CommandClient {
         let len = self.udp.recv(&mut buf)?;
         Ok(String::from_utf8(buf[..len].to_vec()).unwrap())
     }
+    pub fn reload(&self) -> io::Result<String> {
+        self.send_text(b"reload")
+    }
+    pub fn mapping_list(&mut self) -> io::Result<Vec<String>> {
+        self.send_cmd(b"mapping_list")
+    }
+    pub fn mapping_add(&self, rule: &str) -> io::Result<String> {
+        self.send_text(format!("mapping_add:{}", rule).as_bytes())
+    }
+    pub fn mapping_remove(&self, rule: &str) -> io::Result<String> {
+        self.send_text(format!("mapping_remove:{}", rule).as_bytes())
+    }
+    pub fn log_level(&self, spec: &str) -> io::Result<String> {
+        self.send_text(format!("log_level:{}", spec).as_bytes())
+    }
+    pub fn ping(&self, args: &str) -> io::Result<String> {
+        // 最多100次，每次间隔1秒
+        self.send_text_timeout(format!("ping:{}", args).as_bytes(), 210)
+    }
+    pub fn traceroute(&self, args: &str) -> io::Result<String> {
+        self.send_text_timeout(format!("traceroute:{}", args).as_bytes(), 10)
+    }
+    pub fn iperf(&self, args: &str) -> io::Result<String> {
+        // 最长60秒，再加上等待结果的时间
+        self.send_text_timeout(format!("iperf:{}", args).as_bytes(), 70)
+    }
+    pub fn firewall(&self, args: &str) -> io::Result<String> {
+        self.send_text(format!("firewall:{}", args).as_bytes())
+    }
+    pub fn switch(&self, args: &str) -> io::Result<String> {
+        // 需要解析新的服务端地址
+        self.send_text_timeout(format!("switch:{}", args).as_bytes(), 15)
+    }
+    // 执行时间较长的命令
+    fn send_text_timeout(&self, cmd: &[u8], secs: u64) -> io::Result<String> {
+        self.udp.set_read_timeout(Some(Duration::from_secs(secs)))?;
+        let rs = self.send_text(cmd);
+        self.udp.set_read_timeout(Some(Duration::from_secs(5)))?;
+        rs
+    }
+    fn send_text(&self, cmd: &[u8]) -> io::Result<String> {
+        self.udp.send(cmd)?;
+        let mut buf = [0; 10240];
+        let len = self.udp.recv(&mut buf)?;
+        Ok(String::from_utf8_lossy(&buf[..len]).to_string())
+    }
 }
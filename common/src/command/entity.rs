@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::Ipv4Addr;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Info {
@@ -14,13 +14,26 @@ pub struct Info {
     pub public_ips: String,
     pub local_addr: String,
     pub ipv6_addr: String,
-    pub port_mapping_list: Vec<(bool, SocketAddr, String)>,
+    pub port_mapping_list: Vec<String>,
     pub in_ips: Vec<(u32, u32, Ipv4Addr)>,
     pub out_ips: Vec<(u32, u32)>,
     pub udp_listen_addr: Vec<String>,
     pub tcp_listen_addr: String,
 }
 
+// 后台运行时的简要状态
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Status {
+    pub pid: u32,
+    pub name: String,
+    pub virtual_ip: String,
+    pub connect_status: String,
+    // 运行时长(秒)
+    pub uptime: u64,
+    pub peer_online: usize,
+    pub peer_total: usize,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RouteItem {
     pub destination: String,
@@ -30,6 +43,24 @@ pub struct RouteItem {
     pub interface: String,
 }
 
+// 导出的路由表，rt为-1表示还没有测得延迟
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RouteTableItem {
+    pub destination: String,
+    pub endpoint: String,
+    pub protocol: String,
+    pub metric: u8,
+    pub rt: i64,
+    // direct(直连)或者relay(中继)
+    pub kind: String,
+    // 经客户端中继时是中继的虚拟ip
+    pub next_hop: String,
+    // 固定的通道relay/p2p，没有固定时为空
+    pub pin: String,
+    // 开启路径切换后，直连不如服务端中转时为true
+    pub relay_preferred: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DeviceItem {
     pub name: String,
@@ -46,6 +77,8 @@ pub struct DeviceItem {
     pub current_client_secret: bool,
     pub current_client_secret_hash: Vec<u8>,
     pub wire_guard: bool,
+    // 身份公钥指纹，对端没有开启身份认证时为空
+    pub fingerprint: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -66,3 +99,55 @@ pub struct ChartB {
     pub down_total: u64,
     pub down_list: Vec<usize>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Stats {
+    pub disable_stats: bool,
+    pub peers: Vec<PeerStatsItem>,
+    // 按流量排序，只返回前面的部分
+    pub flows: Vec<FlowItem>,
+    // 按原因丢弃的包数，不需要开启流量统计
+    pub drops: Vec<DropItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DropItem {
+    pub reason: String,
+    pub packets: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PeerStatsItem {
+    pub name: String,
+    pub virtual_ip: String,
+    // 当前使用的路径，p2p或relay
+    pub connect: String,
+    // 延迟(ms)，未知时为-1
+    pub rt: i64,
+    // 心跳丢包率(百分比)
+    pub loss: u32,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_direct: u64,
+    pub tx_relay: u64,
+    pub tx_drops: u64,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_direct: u64,
+    pub rx_relay: u64,
+    pub rx_drops: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FlowItem {
+    pub peer: String,
+    pub protocol: String,
+    pub local: String,
+    pub remote: String,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    // 空闲时长(秒)
+    pub idle: u64,
+}
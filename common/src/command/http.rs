@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// 请求头和请求体的最大长度，只用于本地管理接口，不需要太大
+const MAX_HEAD_LEN: usize = 16 * 1024;
+const MAX_BODY_LEN: usize = 64 * 1024;
+// 同时处理的最大连接数，超过时直接关闭新连接
+const MAX_CONNECTIONS: usize = 32;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    // 不包含'?'
+    pub query: String,
+    // 名称都是小写
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|v| v.as_str())
+    }
+    /// 查询参数，不做url解码
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|v| v.split_once('=').or(Some((v, ""))))
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v)
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new<B: Into<Vec<u8>>>(status: u16, content_type: &'static str, body: B) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+    pub fn text<B: Into<Vec<u8>>>(status: u16, body: B) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body)
+    }
+    pub fn not_found() -> Self {
+        Self::text(404, "not found")
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl ConnectionGuard {
+    fn acquire(count: &Arc<AtomicUsize>) -> Option<Self> {
+        if count.fetch_add(1, Ordering::AcqRel) >= MAX_CONNECTIONS {
+            count.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        Some(Self(count.clone()))
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// 简单的http服务，每个连接在单独的线程中处理一个请求，只用于本地的管理接口
+pub fn serve<F>(addr: SocketAddr, name: &str, handler: F) -> io::Result<()>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(addr)?;
+    log::info!("启动{} http:{:?}", name, addr);
+    let handler = Arc::new(handler);
+    let thread_name = name.to_string();
+    let connections = Arc::new(AtomicUsize::new(0));
+    thread::Builder::new()
+        .name(thread_name.clone())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("{} accept {:?}", thread_name, e);
+                        continue;
+                    }
+                };
+                let guard = match ConnectionGuard::acquire(&connections) {
+                    Some(guard) => guard,
+                    None => {
+                        log::warn!("{} 连接数过多，丢弃 {:?}", thread_name, stream.peer_addr());
+                        continue;
+                    }
+                };
+                let handler = handler.clone();
+                let rs = thread::Builder::new()
+                    .name(thread_name.clone())
+                    .spawn(move || {
+                        let _guard = guard;
+                        if let Err(e) = handle(stream, &*handler) {
+                            log::debug!("http {:?}", e);
+                        }
+                    });
+                if let Err(e) = rs {
+                    log::warn!("{} spawn {:?}", thread_name, e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn handle<F>(mut stream: TcpStream, handler: &F) -> io::Result<()>
+where
+    F: Fn(&Request) -> Response,
+{
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let response = match read_request(&stream) {
+        Ok(Some(request)) => handler(&request),
+        Ok(None) => Response::text(413, "request too large"),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::text(400, "bad request"),
+        Err(e) => return Err(e),
+    };
+    write_response(&mut stream, &response)
+}
+
+/// 请求过大时返回None
+fn read_request(stream: &TcpStream) -> io::Result<Option<Request>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid request");
+    // 限制读取的总长度，避免一直不换行的请求占用过多内存
+    let mut reader = BufReader::new(stream.take(MAX_HEAD_LEN as u64));
+    let mut head_len = 0;
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    head_len += line.len();
+    if head_len >= MAX_HEAD_LEN {
+        return Ok(None);
+    }
+    let mut items = line.split_whitespace();
+    let method = items.next().ok_or_else(invalid)?.to_uppercase();
+    let target = items.next().ok_or_else(invalid)?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid());
+        }
+        head_len += line.len();
+        if head_len >= MAX_HEAD_LEN {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = header.split_once(':') {
+            headers.insert(k.trim().to_lowercase(), v.trim().to_string());
+        }
+    }
+    let body_len = match headers.get("content-length") {
+        Some(v) => v.parse::<usize>().map_err(|_| invalid())?,
+        None => 0,
+    };
+    if body_len > MAX_BODY_LEN {
+        return Ok(None);
+    }
+    // 缓冲区中可能已经有部分请求体，剩余的不会超过body_len
+    reader.get_mut().set_limit(body_len as u64);
+    let mut body = vec![0; body_len];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    }))
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
@@ -0,0 +1,328 @@
+use std::fmt::{Display, Write};
+use std::io;
+use std::net::SocketAddr;
+
+use vnt::channel::ConnectProtocol;
+use vnt::core::Vnt;
+
+use crate::command::http::{self, Response};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// 启动Prometheus指标的http接口，GET /metrics
+pub fn start(addr: SocketAddr, vnt: Vnt) -> io::Result<()> {
+    http::serve(addr, "metrics", move |request| {
+        if request.method != "GET" {
+            return Response::text(405, "method not allowed");
+        }
+        match request.path.as_str() {
+            "/metrics" => Response::new(
+                200,
+                CONTENT_TYPE,
+                render(&crate::command::switch::current(&vnt)),
+            ),
+            _ => Response::not_found(),
+        }
+    })
+}
+
+struct Metrics {
+    out: String,
+}
+
+impl Metrics {
+    fn head(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+    fn value<V: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (i, (k, v)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                let _ = write!(self.out, "{}=\"{}\"", k, escape(v));
+            }
+            self.out.push('}');
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+    fn single<V: Display>(&mut self, name: &str, kind: &str, help: &str, value: V) {
+        self.head(name, kind, help);
+        self.value(name, &[], value);
+    }
+}
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn render(vnt: &Vnt) -> String {
+    let mut m = Metrics { out: String::new() };
+    let current_device = vnt.current_device();
+    let virtual_ip = current_device.virtual_ip.to_string();
+    m.head("vnt_info", "gauge", "Version and identity of this node");
+    m.value(
+        "vnt_info",
+        &[
+            ("version", vnt::VNT_VERSION),
+            ("name", vnt.name()),
+            ("virtual_ip", &virtual_ip),
+        ],
+        1,
+    );
+    m.single(
+        "vnt_connected",
+        "gauge",
+        "Whether the node is connected to the server",
+        vnt.connection_status().online() as u8,
+    );
+    m.single(
+        "vnt_uptime_seconds",
+        "gauge",
+        "Seconds since start",
+        vnt.uptime().as_secs(),
+    );
+
+    let device_list = vnt.device_list();
+    let online = device_list
+        .iter()
+        .filter(|peer| peer.status.is_online())
+        .count();
+    m.head("vnt_peers", "gauge", "Number of peers by status");
+    m.value("vnt_peers", &[("status", "online")], online);
+    m.value(
+        "vnt_peers",
+        &[("status", "offline")],
+        device_list.len() - online,
+    );
+
+    if vnt.config().enable_traffic {
+        m.single(
+            "vnt_up_bytes_total",
+            "counter",
+            "Bytes sent to peers and server",
+            vnt.up_stream(),
+        );
+        m.single(
+            "vnt_down_bytes_total",
+            "counter",
+            "Bytes received from peers and server",
+            vnt.down_stream(),
+        );
+    }
+
+    // 对端的流量统计，关闭流量统计时没有
+    if let Some(mut peer_stats) = vnt.peer_stats() {
+        peer_stats.sort_by_key(|(ip, _)| *ip);
+        let list: Vec<(String, String, _)> = peer_stats
+            .into_iter()
+            .map(|(ip, counter)| {
+                let name = device_list
+                    .iter()
+                    .find(|peer| peer.virtual_ip == ip)
+                    .map_or(String::new(), |peer| peer.name.clone());
+                (ip.to_string(), name, counter)
+            })
+            .collect();
+        m.head(
+            "vnt_peer_tx_bytes_total",
+            "counter",
+            "Bytes sent to the peer",
+        );
+        for (ip, name, counter) in &list {
+            m.value(
+                "vnt_peer_tx_bytes_total",
+                &[("peer", ip), ("name", name)],
+                counter.tx_bytes,
+            );
+        }
+        m.head(
+            "vnt_peer_rx_bytes_total",
+            "counter",
+            "Bytes received from the peer",
+        );
+        for (ip, name, counter) in &list {
+            m.value(
+                "vnt_peer_rx_bytes_total",
+                &[("peer", ip), ("name", name)],
+                counter.rx_bytes,
+            );
+        }
+        m.head(
+            "vnt_peer_tx_packets_total",
+            "counter",
+            "Packets sent to the peer by path",
+        );
+        for (ip, name, counter) in &list {
+            m.value(
+                "vnt_peer_tx_packets_total",
+                &[("peer", ip), ("name", name), ("path", "direct")],
+                counter.tx_direct,
+            );
+            m.value(
+                "vnt_peer_tx_packets_total",
+                &[("peer", ip), ("name", name), ("path", "relay")],
+                counter.tx_relay,
+            );
+        }
+        m.head(
+            "vnt_peer_rx_packets_total",
+            "counter",
+            "Packets received from the peer by path",
+        );
+        for (ip, name, counter) in &list {
+            m.value(
+                "vnt_peer_rx_packets_total",
+                &[("peer", ip), ("name", name), ("path", "direct")],
+                counter.rx_direct,
+            );
+            m.value(
+                "vnt_peer_rx_packets_total",
+                &[("peer", ip), ("name", name), ("path", "relay")],
+                counter.rx_relay,
+            );
+        }
+        m.head(
+            "vnt_peer_drops_total",
+            "counter",
+            "Packets to or from the peer that were dropped",
+        );
+        for (ip, name, counter) in &list {
+            m.value(
+                "vnt_peer_drops_total",
+                &[("peer", ip), ("name", name), ("direction", "tx")],
+                counter.tx_drops,
+            );
+            m.value(
+                "vnt_peer_drops_total",
+                &[("peer", ip), ("name", name), ("direction", "rx")],
+                counter.rx_drops,
+            );
+        }
+        m.head(
+            "vnt_peer_loss_ratio",
+            "gauge",
+            "Heartbeat loss ratio of the peer",
+        );
+        for (ip, name, counter) in &list {
+            m.value(
+                "vnt_peer_loss_ratio",
+                &[("peer", ip), ("name", name)],
+                counter.loss() as f64 / 100.0,
+            );
+        }
+    }
+
+    m.head(
+        "vnt_peer_rtt_milliseconds",
+        "gauge",
+        "Round trip time of the current path to the peer",
+    );
+    for peer in device_list.iter() {
+        if !peer.status.is_online() {
+            continue;
+        }
+        if let Some(route) = vnt.route(&peer.virtual_ip) {
+            if route.rt < 0 {
+                continue;
+            }
+            let path = if route.is_p2p() { "p2p" } else { "relay" };
+            m.value(
+                "vnt_peer_rtt_milliseconds",
+                &[
+                    ("peer", &peer.virtual_ip.to_string()),
+                    ("name", &peer.name),
+                    ("path", path),
+                ],
+                route.rt,
+            );
+        }
+    }
+    m.head(
+        "vnt_route_rtt_milliseconds",
+        "gauge",
+        "Round trip time of each channel",
+    );
+    for (destination, routes) in vnt.route_table() {
+        let destination = destination.to_string();
+        for route in routes {
+            if route.rt < 0 {
+                continue;
+            }
+            let protocol = match route.protocol {
+                ConnectProtocol::UDP => "udp",
+                ConnectProtocol::TCP => "tcp",
+                ConnectProtocol::WS => "ws",
+                ConnectProtocol::WSS => "wss",
+                ConnectProtocol::QUIC => "quic",
+                ConnectProtocol::TLS => "tls",
+            };
+            m.value(
+                "vnt_route_rtt_milliseconds",
+                &[
+                    ("peer", &destination),
+                    ("protocol", protocol),
+                    ("addr", &route.addr.to_string()),
+                    ("metric", &route.metric.to_string()),
+                ],
+                route.rt,
+            );
+        }
+    }
+
+    let counters = vnt.counters();
+    m.single(
+        "vnt_handshake_failures_total",
+        "counter",
+        "Registrations rejected by the server",
+        counters.handshake_failures,
+    );
+    m.single(
+        "vnt_punch_attempts_total",
+        "counter",
+        "Hole punching attempts",
+        counters.punch_attempts,
+    );
+    m.single(
+        "vnt_direct_links_total",
+        "counter",
+        "Direct links established, divide by punch attempts for the punch success rate",
+        counters.direct_links,
+    );
+    m.single(
+        "vnt_cipher_errors_total",
+        "counter",
+        "Packets that failed to decrypt",
+        counters.cipher_errors,
+    );
+    m.single(
+        "vnt_tun_drops_total",
+        "counter",
+        "Packets that failed to write to the virtual interface",
+        counters.tun_drops,
+    );
+    m.single(
+        "vnt_acl_denied_total",
+        "counter",
+        "Packets denied by acl",
+        counters.acl_denied,
+    );
+    m.head(
+        "vnt_packet_drops_total",
+        "counter",
+        "Packets dropped on the data path by reason",
+    );
+    for (reason, packets) in counters.drops() {
+        m.value(
+            "vnt_packet_drops_total",
+            &[("reason", reason.name())],
+            packets,
+        );
+    }
+    m.out
+}
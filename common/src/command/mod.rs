@@ -1,30 +1,65 @@
 use std::collections::HashSet;
 use std::io;
 use std::net::Ipv4Addr;
-use vnt::channel::ConnectProtocol;
+use std::time::Duration;
+use vnt::acl::firewall::FirewallRule;
+use vnt::channel::punch::NatBehavior;
+use vnt::channel::{ConnectProtocol, UseChannelType};
 use vnt::core::Vnt;
 
-use crate::command::entity::{ChartA, ChartB, DeviceItem, Info, RouteItem};
+use crate::command::entity::{
+    ChartA, ChartB, DeviceItem, DropItem, FlowItem, Info, PeerStatsItem, RouteItem, RouteTableItem,
+    Stats, Status,
+};
 use crate::console_out;
 
+pub mod api;
 pub mod client;
 pub mod entity;
+pub mod http;
+pub mod metrics;
 pub mod server;
+pub mod switch;
+pub mod web;
+
+// 诊断等待响应的时间
+const DIAG_TIMEOUT: Duration = Duration::from_secs(2);
+// ping的间隔
+const DIAG_INTERVAL: Duration = Duration::from_secs(1);
 
 pub enum CommandEnum {
     Route,
+    RouteJson,
     List,
     All,
     Info,
     ChartA,
     ChartB(String),
     Stop,
+    Reload,
+    Status,
+    MappingList,
+    MappingAdd(String),
+    MappingRemove(String),
+    Stats(bool),
+    LogLevel(String),
+    Ping(String),
+    Traceroute(String),
+    Iperf(String),
+    Switch(String),
+    Firewall(String),
 }
 
 pub fn command_str(cmd: &str, vnt: &Vnt) -> bool {
     if cmd.is_empty() {
         return false;
     }
+    let vnt = &switch::current(vnt);
+    // token区分大小写
+    if let Some(args) = cmd.trim().strip_prefix("switch:") {
+        println!("{}", switch::request(vnt, args));
+        return true;
+    }
     let cmd = cmd.to_lowercase();
     let cmd = cmd.trim();
     match cmd {
@@ -44,6 +79,10 @@ pub fn command_str(cmd: &str, vnt: &Vnt) -> bool {
             let list = command_list(&vnt);
             console_out::console_device_list_all(list);
         }
+        "status" => {
+            let status = command_status(&vnt);
+            console_out::console_status(status);
+        }
         "chart_a" => {
             let chart = command_chart_a(&vnt);
             console_out::console_chart_a(chart);
@@ -52,9 +91,35 @@ pub fn command_str(cmd: &str, vnt: &Vnt) -> bool {
             let _ = vnt.stop();
             return false;
         }
+        "reload" => match crate::config::reload(vnt) {
+            Ok(changed) => println!("reloaded {:?}", changed),
+            Err(e) => println!("reload error {}", e),
+        },
+        "mapping_list" => {
+            let list = command_mapping_list(&vnt);
+            console_out::console_mapping_list(list);
+        }
+        "stats" => {
+            let stats = command_stats(&vnt);
+            console_out::console_stats(stats);
+        }
         _ => {}
     }
-    if let Some(ip) = cmd.strip_prefix("chart_b") {
+    if let Some(rule) = cmd.strip_prefix("mapping_add:") {
+        println!("{}", command_mapping_add(&vnt, rule));
+    } else if let Some(rule) = cmd.strip_prefix("mapping_remove:") {
+        println!("{}", command_mapping_remove(&vnt, rule));
+    } else if let Some(spec) = cmd.strip_prefix("log_level:") {
+        println!("{}", command_log_level(spec));
+    } else if let Some(args) = cmd.strip_prefix("ping:") {
+        println!("{}", command_ping(&vnt, args));
+    } else if let Some(args) = cmd.strip_prefix("traceroute:") {
+        println!("{}", command_traceroute(&vnt, args));
+    } else if let Some(args) = cmd.strip_prefix("iperf:") {
+        println!("{}", command_iperf(&vnt, args));
+    } else if let Some(args) = cmd.strip_prefix("firewall") {
+        println!("{}", command_firewall(&vnt, args.trim_start_matches(':')));
+    } else if let Some(ip) = cmd.strip_prefix("chart_b") {
         let chart = if ip.is_empty() {
             command_chart_b(&vnt, &vnt.current_device().virtual_gateway.to_string())
         } else {
@@ -79,6 +144,13 @@ fn command_(cmd: CommandEnum) -> io::Result<()> {
             let list = command_client.route()?;
             console_out::console_route_table(list);
         }
+        CommandEnum::RouteJson => {
+            let list = command_client.route_table()?;
+            match serde_json::to_string_pretty(&list) {
+                Ok(s) => println!("{}", s),
+                Err(e) => println!("route: {:?}", e),
+            }
+        }
         CommandEnum::List => {
             let list = command_client.list()?;
             console_out::console_device_list(list);
@@ -102,10 +174,320 @@ fn command_(cmd: CommandEnum) -> io::Result<()> {
         CommandEnum::Stop => {
             command_client.stop()?;
         }
+        CommandEnum::Reload => {
+            println!("{}", command_client.reload()?);
+        }
+        CommandEnum::Status => {
+            let status = command_client.status()?;
+            console_out::console_status(status);
+        }
+        CommandEnum::MappingList => {
+            let list = command_client.mapping_list()?;
+            console_out::console_mapping_list(list);
+        }
+        CommandEnum::MappingAdd(rule) => {
+            println!("{}", command_client.mapping_add(&rule)?);
+        }
+        CommandEnum::MappingRemove(rule) => {
+            println!("{}", command_client.mapping_remove(&rule)?);
+        }
+        CommandEnum::LogLevel(spec) => {
+            println!("{}", command_client.log_level(&spec)?);
+        }
+        CommandEnum::Ping(args) => {
+            println!("{}", command_client.ping(&args)?);
+        }
+        CommandEnum::Traceroute(args) => {
+            println!("{}", command_client.traceroute(&args)?);
+        }
+        CommandEnum::Iperf(args) => {
+            println!("{}", command_client.iperf(&args)?);
+        }
+        CommandEnum::Switch(args) => {
+            println!("{}", command_client.switch(&args)?);
+        }
+        CommandEnum::Firewall(args) => {
+            println!("{}", command_client.firewall(&args)?);
+        }
+        CommandEnum::Stats(json) => {
+            let stats = command_client.stats()?;
+            if json {
+                match serde_json::to_string_pretty(&stats) {
+                    Ok(s) => println!("{}", s),
+                    Err(e) => println!("stats: {:?}", e),
+                }
+            } else {
+                console_out::console_stats(stats);
+            }
+        }
     }
     Ok(())
 }
 
+#[cfg(feature = "port_mapping")]
+pub fn command_mapping_list(vnt: &Vnt) -> Vec<String> {
+    vnt.port_mapping_list()
+        .iter()
+        .map(|v| v.to_string())
+        .collect()
+}
+
+/// 运行中添加端口映射，返回执行结果
+#[cfg(feature = "port_mapping")]
+pub fn command_mapping_add(vnt: &Vnt, rule: &str) -> String {
+    match rule.parse::<vnt::port_mapping::MappingRule>() {
+        Ok(rule) => match vnt.add_port_mapping(rule.clone()) {
+            Ok(_) => format!("added {}", rule),
+            Err(e) => format!("add error {}", e),
+        },
+        Err(e) => format!("add error {}", e),
+    }
+}
+
+/// 运行中删除端口映射，返回执行结果
+#[cfg(feature = "port_mapping")]
+pub fn command_mapping_remove(vnt: &Vnt, rule: &str) -> String {
+    match rule.parse::<vnt::port_mapping::MappingRule>() {
+        Ok(rule) => match vnt.remove_port_mapping(&rule) {
+            Ok(true) => format!("removed {}", rule),
+            Ok(false) => format!("remove error {} not found", rule),
+            Err(e) => format!("remove error {}", e),
+        },
+        Err(e) => format!("remove error {}", e),
+    }
+}
+
+/// 查看防火墙的放行规则，参数 `add <rule>`、`remove <rule>` 时运行中增删规则，不修改配置文件
+pub fn command_firewall(vnt: &Vnt, args: &str) -> String {
+    let args = args.trim();
+    let (action, rule) = args.split_once(' ').unwrap_or((args, ""));
+    match action {
+        "" | "list" => match vnt.firewall_rules() {
+            Ok((enabled, rules)) => {
+                let mut out = format!(
+                    "Firewall {}, allow {}",
+                    if enabled { "enabled" } else { "disabled" },
+                    rules.len()
+                );
+                for rule in rules {
+                    out.push_str(&format!("\n  {}", rule));
+                }
+                out
+            }
+            Err(e) => format!("firewall error {}", e),
+        },
+        "add" => match rule.parse::<FirewallRule>() {
+            Ok(rule) => match vnt.add_firewall_rule(rule) {
+                Ok(true) => format!("added {}", rule),
+                Ok(false) => format!("add error {} already exists", rule),
+                Err(e) => format!("add error {}", e),
+            },
+            Err(e) => format!("add error {}", e),
+        },
+        "remove" => match rule.parse::<FirewallRule>() {
+            Ok(rule) => match vnt.remove_firewall_rule(&rule) {
+                Ok(true) => format!("removed {}", rule),
+                Ok(false) => format!("remove error {} not found", rule),
+                Err(e) => format!("remove error {}", e),
+            },
+            Err(e) => format!("remove error {}", e),
+        },
+        _ => "usage: firewall [add|remove <rule>]".to_string(),
+    }
+}
+
+/// 运行中修改日志级别，spec为空时只查看，返回运行中修改过的级别
+#[cfg(feature = "log")]
+pub fn command_log_level(spec: &str) -> String {
+    if !spec.trim().is_empty() {
+        if let Err(e) = crate::logging::set_log_levels(spec) {
+            return format!("log_level error {}", e);
+        }
+    }
+    let levels: Vec<String> = crate::logging::log_levels()
+        .iter()
+        .map(|(module, level)| {
+            let level = level.to_string().to_lowercase();
+            if module.is_empty() {
+                level
+            } else {
+                format!("{}={}", module, level)
+            }
+        })
+        .collect();
+    format!("log_level [{}]", levels.join(","))
+}
+
+#[cfg(not(feature = "log"))]
+pub fn command_log_level(_spec: &str) -> String {
+    "log_level error log feature is not enabled".to_string()
+}
+
+// 诊断使用的路径，直连的显示协议和地址，中转的显示中转节点
+fn diag_path(vnt: &Vnt, route: Option<vnt::channel::Route>) -> String {
+    match route {
+        Some(route) if route.is_p2p() => format!("direct {:?} {}", route.protocol, route.addr),
+        Some(route) => match vnt.route_key(&route.route_key()) {
+            Some(ip) if ip != vnt.current_device().virtual_gateway => {
+                format!("relay via client {}", ip)
+            }
+            _ => "relay via server".to_string(),
+        },
+        None => "relay via server".to_string(),
+    }
+}
+
+// 参数格式 `<ip> [数量]`
+fn diag_args(args: &str, default: u64, max: u64) -> Result<(Ipv4Addr, u64), String> {
+    let mut iter = args.split_whitespace();
+    let ip = match iter.next().map(|v| v.parse::<Ipv4Addr>()) {
+        Some(Ok(ip)) => ip,
+        _ => return Err(format!("invalid ip '{}'", args.trim())),
+    };
+    let num = match iter.next().map(|v| v.parse::<u64>()) {
+        Some(Ok(num)) if num > 0 => num.min(max),
+        Some(_) => return Err(format!("invalid number '{}'", args.trim())),
+        None => default,
+    };
+    Ok((ip, num))
+}
+
+/// vnt协议层的ping，参数 `<ip> [次数]`，不经过虚拟网卡
+pub fn command_ping(vnt: &Vnt, args: &str) -> String {
+    let (ip, count) = match diag_args(args, 4, 100) {
+        Ok(v) => v,
+        Err(e) => return format!("ping error {}", e),
+    };
+    let mut out = String::new();
+    let mut rtts = Vec::new();
+    for seq in 0..count {
+        let start = std::time::Instant::now();
+        match vnt.diag_echo(&ip, DIAG_TIMEOUT) {
+            Ok(echo) => match echo.rtt {
+                Some(rtt) => {
+                    out.push_str(&format!(
+                        "seq={} {} time={:.2}ms hops={}\n",
+                        seq,
+                        diag_path(vnt, echo.route),
+                        rtt.as_secs_f64() * 1000.0,
+                        echo.hops
+                    ));
+                    rtts.push(rtt);
+                }
+                None => out.push_str(&format!(
+                    "seq={} {} timeout\n",
+                    seq,
+                    diag_path(vnt, echo.route)
+                )),
+            },
+            Err(e) => return format!("{}ping error {}", out, e),
+        }
+        if seq + 1 < count {
+            std::thread::sleep(DIAG_INTERVAL.saturating_sub(start.elapsed()));
+        }
+    }
+    let received = rtts.len() as u64;
+    out.push_str(&format!(
+        "{} sent, {} received, {:.1}% loss",
+        count,
+        received,
+        (count - received) as f64 * 100.0 / count as f64
+    ));
+    if !rtts.is_empty() {
+        let min = rtts.iter().min().unwrap().as_secs_f64() * 1000.0;
+        let max = rtts.iter().max().unwrap().as_secs_f64() * 1000.0;
+        let avg = rtts.iter().map(|v| v.as_secs_f64()).sum::<f64>() * 1000.0 / received as f64;
+        out.push_str(&format!(
+            ", rtt min/avg/max={:.2}/{:.2}/{:.2}ms",
+            min, avg, max
+        ));
+    }
+    out
+}
+
+/// 到对端经过的服务端或中转客户端，以及每一跳的往返时间
+pub fn command_traceroute(vnt: &Vnt, args: &str) -> String {
+    let (ip, _) = match diag_args(args, 1, 1) {
+        Ok(v) => v,
+        Err(e) => return format!("traceroute error {}", e),
+    };
+    let hops = match vnt.diag_traceroute(&ip, DIAG_TIMEOUT) {
+        Ok(hops) => hops,
+        Err(e) => return format!("traceroute error {}", e),
+    };
+    let mut out = String::new();
+    for (index, hop) in hops.iter().enumerate() {
+        let kind = if hop.server {
+            "server"
+        } else if hop.ip == ip {
+            "peer"
+        } else {
+            "relay"
+        };
+        let rtt = match hop.rtt {
+            Some(rtt) if hop.server => format!("{}ms (heartbeat)", rtt.as_millis()),
+            Some(rtt) => format!("{:.2}ms", rtt.as_secs_f64() * 1000.0),
+            None => "*".to_string(),
+        };
+        out.push_str(&format!("{} {} {} {}\n", index + 1, hop.ip, kind, rtt));
+    }
+    out.trim_end().to_string()
+}
+
+/// vnt协议层的带宽测试，参数 `<ip> [秒数]`，测试的是单向发送到对端的吞吐
+pub fn command_iperf(vnt: &Vnt, args: &str) -> String {
+    let (ip, seconds) = match diag_args(args, 5, 60) {
+        Ok(v) => v,
+        Err(e) => return format!("iperf error {}", e),
+    };
+    let rs = match vnt.diag_bandwidth(&ip, Duration::from_secs(seconds)) {
+        Ok(rs) => rs,
+        Err(e) => return format!("iperf error {}", e),
+    };
+    let secs = rs.duration.as_secs_f64().max(0.001);
+    let mut out = format!(
+        "{} {:.2}s\nsent     {:.2} MB {} packets {:.2} Mbit/s\n",
+        diag_path(vnt, rs.route),
+        secs,
+        rs.sent_bytes as f64 / (1024.0 * 1024.0),
+        rs.sent_packets,
+        rs.sent_bytes as f64 * 8.0 / secs / 1_000_000.0
+    );
+    match rs.received {
+        Some((bytes, packets)) => {
+            let loss = if rs.sent_packets == 0 {
+                0.0
+            } else {
+                rs.sent_packets.saturating_sub(packets) as f64 * 100.0 / rs.sent_packets as f64
+            };
+            out.push_str(&format!(
+                "received {:.2} MB {} packets {:.2} Mbit/s loss {:.1}%",
+                bytes as f64 / (1024.0 * 1024.0),
+                packets,
+                bytes as f64 * 8.0 / secs / 1_000_000.0,
+                loss
+            ));
+        }
+        None => out.push_str("received unknown, the peer did not report"),
+    }
+    out
+}
+
+#[cfg(not(feature = "port_mapping"))]
+pub fn command_mapping_list(_vnt: &Vnt) -> Vec<String> {
+    vec![]
+}
+
+#[cfg(not(feature = "port_mapping"))]
+pub fn command_mapping_add(_vnt: &Vnt, _rule: &str) -> String {
+    "add error port_mapping feature is not enabled".to_string()
+}
+
+#[cfg(not(feature = "port_mapping"))]
+pub fn command_mapping_remove(_vnt: &Vnt, _rule: &str) -> String {
+    "remove error port_mapping feature is not enabled".to_string()
+}
+
 pub fn command_route(vnt: &Vnt) -> Vec<RouteItem> {
     let route_table = vnt.route_table();
     let server_addr = vnt.config().server_address_str.clone();
@@ -127,6 +509,12 @@ pub fn command_route(vnt: &Vnt) -> Vec<RouteItem> {
                     format!("tcp@{}", route.addr)
                 }
                 ConnectProtocol::WS | ConnectProtocol::WSS => server_addr.clone(),
+                ConnectProtocol::QUIC => {
+                    format!("quic@{}", route.addr)
+                }
+                ConnectProtocol::TLS => {
+                    format!("tls@{}", route.addr)
+                }
             };
 
             let item = RouteItem {
@@ -142,6 +530,42 @@ pub fn command_route(vnt: &Vnt) -> Vec<RouteItem> {
     route_list
 }
 
+/// 导出路由表，包含固定的通道和路径切换的状态
+pub fn command_route_table(vnt: &Vnt) -> Vec<RouteTableItem> {
+    let mut list = Vec::new();
+    for (destination, routes) in vnt.route_table() {
+        let pin = match vnt.peer_channel(&destination) {
+            Some(UseChannelType::Relay) => "relay",
+            Some(UseChannelType::P2p) => "p2p",
+            _ => "",
+        };
+        let relay_preferred = vnt
+            .path_quality(&destination)
+            .map_or(false, |v| v.relay_preferred);
+        for route in routes {
+            let next_hop = if route.is_p2p() {
+                String::new()
+            } else {
+                vnt.route_key(&route.route_key())
+                    .map_or(String::new(), |v| v.to_string())
+            };
+            list.push(RouteTableItem {
+                destination: destination.to_string(),
+                endpoint: route.addr.to_string(),
+                protocol: format!("{:?}", route.protocol).to_lowercase(),
+                metric: route.metric,
+                rt: route.rt,
+                kind: if route.is_p2p() { "direct" } else { "relay" }.to_string(),
+                next_hop,
+                pin: pin.to_string(),
+                relay_preferred,
+            });
+        }
+    }
+    list.sort_by_key(|v| v.destination.parse::<Ipv4Addr>().ok());
+    list
+}
+
 pub fn command_list(vnt: &Vnt) -> Vec<DeviceItem> {
     let info = vnt.current_device();
     let device_list = vnt.device_list();
@@ -205,6 +629,11 @@ pub fn command_list(vnt: &Vnt) -> Vec<DeviceItem> {
         };
         let status = format!("{:?}", peer.status);
         let client_secret = peer.client_secret;
+        let fingerprint = if peer.identity_public_key.is_empty() {
+            "".to_string()
+        } else {
+            vnt::identity::fingerprint(&peer.identity_public_key)
+        };
         let item = DeviceItem {
             name,
             virtual_ip,
@@ -220,12 +649,30 @@ pub fn command_list(vnt: &Vnt) -> Vec<DeviceItem> {
             current_client_secret,
             current_client_secret_hash: client_encrypt_hash.to_vec(),
             wire_guard: peer.wireguard,
+            fingerprint,
         };
         list.push(item);
     }
     list
 }
 
+pub fn command_status(vnt: &Vnt) -> Status {
+    let device_list = vnt.device_list();
+    let peer_online = device_list
+        .iter()
+        .filter(|peer| peer.status.is_online())
+        .count();
+    Status {
+        pid: std::process::id(),
+        name: vnt.name().to_string(),
+        virtual_ip: vnt.current_device().virtual_ip().to_string(),
+        connect_status: format!("{:?}", vnt.connection_status()),
+        uptime: vnt.uptime().as_secs(),
+        peer_online,
+        peer_total: device_list.len(),
+    }
+}
+
 pub fn command_info(vnt: &Vnt) -> Info {
     let config = vnt.config();
     let current_device = vnt.current_device();
@@ -240,7 +687,14 @@ pub fn command_info(vnt: &Vnt) -> Info {
     } else {
         current_device.connect_server.to_string()
     };
-    let nat_type = format!("{:?}", nat_info.nat_type);
+    let nat_type = if nat_info.mapping == NatBehavior::Unknown {
+        format!("{:?}", nat_info.nat_type)
+    } else {
+        format!(
+            "{:?}(mapping:{:?},filtering:{:?})",
+            nat_info.nat_type, nat_info.mapping, nat_info.filtering
+        )
+    };
     let public_ips: Vec<String> = nat_info.public_ips.iter().map(|v| v.to_string()).collect();
     let public_ips = public_ips.join(",");
     let local_addr = nat_info
@@ -251,10 +705,7 @@ pub fn command_info(vnt: &Vnt) -> Info {
         .ipv6()
         .map(|v| v.to_string())
         .unwrap_or("None".to_string());
-    #[cfg(feature = "port_mapping")]
-    let port_mapping_list = vnt.config().port_mapping_list.clone();
-    #[cfg(not(feature = "port_mapping"))]
-    let port_mapping_list = vec![];
+    let port_mapping_list = command_mapping_list(vnt);
     let in_ips = vnt.config().in_ips.clone();
     let out_ips = vnt.config().out_ips.clone();
     let udp_listen_addr = nat_info
@@ -300,6 +751,88 @@ pub fn command_chart_a(vnt: &Vnt) -> ChartA {
     }
 }
 
+// 命令返回的流数量，结果要放进一个udp包
+const MAX_FLOW_ITEMS: usize = 100;
+
+pub fn command_stats(vnt: &Vnt) -> Stats {
+    let drops = vnt
+        .counters()
+        .drops()
+        .into_iter()
+        .map(|(reason, packets)| DropItem {
+            reason: reason.name().to_string(),
+            packets,
+        })
+        .collect();
+    let (mut peer_stats, mut flow_stats) = match (vnt.peer_stats(), vnt.flow_stats()) {
+        (Some(peer_stats), Some(flow_stats)) => (peer_stats, flow_stats),
+        _ => {
+            let mut stats = Stats::default();
+            stats.disable_stats = true;
+            stats.drops = drops;
+            return stats;
+        }
+    };
+    peer_stats.sort_by_key(|(ip, _)| *ip);
+    let device_list = vnt.device_list();
+    let mut peers = Vec::with_capacity(peer_stats.len());
+    for (ip, counter) in peer_stats {
+        let name = device_list
+            .iter()
+            .find(|peer| peer.virtual_ip == ip)
+            .map_or(String::new(), |peer| peer.name.clone());
+        let (connect, rt) = match vnt.route(&ip) {
+            Some(route) if route.is_p2p() => ("p2p".to_string(), route.rt),
+            Some(route) => ("relay".to_string(), route.rt),
+            None => ("relay".to_string(), -1),
+        };
+        peers.push(PeerStatsItem {
+            name,
+            virtual_ip: ip.to_string(),
+            connect,
+            rt,
+            loss: counter.loss(),
+            tx_bytes: counter.tx_bytes,
+            tx_packets: counter.tx_packets,
+            tx_direct: counter.tx_direct,
+            tx_relay: counter.tx_relay,
+            tx_drops: counter.tx_drops,
+            rx_bytes: counter.rx_bytes,
+            rx_packets: counter.rx_packets,
+            rx_direct: counter.rx_direct,
+            rx_relay: counter.rx_relay,
+            rx_drops: counter.rx_drops,
+        });
+    }
+    flow_stats.sort_by_key(|(_, v)| std::cmp::Reverse(v.tx_bytes + v.rx_bytes));
+    let flows = flow_stats
+        .into_iter()
+        .take(MAX_FLOW_ITEMS)
+        .map(|(key, counter)| FlowItem {
+            peer: key.peer.to_string(),
+            protocol: match key.protocol {
+                1 => "icmp".to_string(),
+                6 => "tcp".to_string(),
+                17 => "udp".to_string(),
+                p => p.to_string(),
+            },
+            local: key.local.to_string(),
+            remote: key.remote.to_string(),
+            tx_bytes: counter.tx_bytes,
+            tx_packets: counter.tx_packets,
+            rx_bytes: counter.rx_bytes,
+            rx_packets: counter.rx_packets,
+            idle: counter.last_active.elapsed().as_secs(),
+        })
+        .collect();
+    Stats {
+        disable_stats: false,
+        peers,
+        flows,
+        drops,
+    }
+}
+
 pub fn command_chart_b(vnt: &Vnt, input_str: &str) -> ChartB {
     let disable_stats = !vnt.config().enable_traffic;
     if disable_stats {
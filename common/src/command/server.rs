@@ -4,31 +4,75 @@ use std::io::Write;
 use std::net::UdpSocket;
 use vnt::core::Vnt;
 
-pub struct CommandServer {}
+pub struct CommandServer {
+    // 同时运行多个网络时的配置组名
+    network: Option<String>,
+    // 是否使用默认端口，不指定配置组的命令连接这个网络
+    primary: bool,
+}
 
 impl CommandServer {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            network: None,
+            primary: true,
+        }
+    }
+    /// 同时运行多个网络时每个网络一个命令端口，保存到command-port-<配置组名>
+    pub fn with_network(network: String, primary: bool) -> Self {
+        Self {
+            network: Some(network),
+            primary,
+        }
     }
 }
 
 impl CommandServer {
     pub fn start(self, vnt: Vnt) -> io::Result<()> {
-        let udp = if let Ok(udp) = UdpSocket::bind("127.0.0.1:39271") {
+        let udp = if !self.primary {
+            UdpSocket::bind("127.0.0.1:0")?
+        } else if let Ok(udp) = UdpSocket::bind("127.0.0.1:39271") {
             udp
         } else {
             UdpSocket::bind("127.0.0.1:0")?
         };
         let addr = udp.local_addr()?;
-        log::info!("启动后台cmd:{:?}", addr);
-        if let Err(e) = save_port(addr.port()) {
-            log::warn!("保存后台命令端口失败：{:?}", e);
+        log::info!("启动后台cmd:{:?},network={:?}", addr, self.network);
+        if let Some(network) = self.network.as_ref() {
+            if let Err(e) = save_port(&format!("command-port-{}", network), addr.port()) {
+                log::warn!("保存后台命令端口失败：{:?}", e);
+            }
+        }
+        if self.primary {
+            if let Err(e) = save_port("command-port", addr.port()) {
+                log::warn!("保存后台命令端口失败：{:?}", e);
+            }
         }
 
-        let mut buf = [0u8; 64];
+        let mut buf = [0u8; 1024];
         loop {
             let (len, addr) = udp.recv_from(&mut buf)?;
+            // 切换组网后使用新的连接
+            let vnt = crate::command::switch::current(&vnt);
             match std::str::from_utf8(&buf[..len]) {
+                Ok(cmd) if is_diag(cmd) => {
+                    // 诊断命令耗时较长，在其他线程执行，不影响停止等命令
+                    let udp = udp.try_clone()?;
+                    let cmd = cmd.to_string();
+                    let vnt = vnt.clone();
+                    let rs = std::thread::Builder::new()
+                        .name("cmdDiag".into())
+                        .spawn(move || {
+                            if let Ok(out) = command(&cmd, &vnt) {
+                                if let Err(e) = udp.send_to(out.as_bytes(), addr) {
+                                    log::warn!("cmd={},err={:?}", cmd, e);
+                                }
+                            }
+                        });
+                    if let Err(e) = rs {
+                        log::warn!("cmdDiag {:?}", e);
+                    }
+                }
                 Ok(cmd) => {
                     if let Ok(out) = command(cmd, &vnt) {
                         if let Err(e) = udp.send_to(out.as_bytes(), addr) {
@@ -47,8 +91,13 @@ impl CommandServer {
         Ok(())
     }
 }
-fn save_port(port: u16) -> io::Result<()> {
-    let path_buf = crate::cli::app_home()?.join("command-port");
+fn is_diag(cmd: &str) -> bool {
+    let cmd = cmd.trim();
+    cmd.starts_with("ping:") || cmd.starts_with("traceroute:") || cmd.starts_with("iperf:")
+}
+
+fn save_port(file_name: &str, port: u16) -> io::Result<()> {
+    let path_buf = crate::cli::app_home()?.join(file_name);
     let mut file = std::fs::File::create(path_buf)?;
     file.write_all(port.to_string().as_bytes())?;
     file.sync_all()
@@ -59,18 +108,46 @@ fn command(cmd: &str, vnt: &Vnt) -> io::Result<String> {
     let out_str = match cmd {
         "route" => serde_yaml::to_string(&crate::command::command_route(vnt))
             .unwrap_or_else(|e| format!("error {:?}", e)),
+        "route_table" => serde_yaml::to_string(&crate::command::command_route_table(vnt))
+            .unwrap_or_else(|e| format!("error {:?}", e)),
         "list" => serde_yaml::to_string(&crate::command::command_list(vnt))
             .unwrap_or_else(|e| format!("error {:?}", e)),
         "info" => serde_yaml::to_string(&crate::command::command_info(vnt))
             .unwrap_or_else(|e| format!("error {:?}", e)),
+        "status" => serde_yaml::to_string(&crate::command::command_status(vnt))
+            .unwrap_or_else(|e| format!("error {:?}", e)),
         "chart_a" => serde_yaml::to_string(&crate::command::command_chart_a(vnt))
             .unwrap_or_else(|e| format!("error {:?}", e)),
         "stop" => {
             vnt.stop();
             "stopped".to_string()
         }
+        "reload" => match crate::config::reload(vnt) {
+            Ok(changed) => format!("reloaded {:?}", changed),
+            Err(e) => format!("reload error {}", e),
+        },
+        "stats" => serde_yaml::to_string(&crate::command::command_stats(vnt))
+            .unwrap_or_else(|e| format!("error {:?}", e)),
+        "mapping_list" => serde_yaml::to_string(&crate::command::command_mapping_list(vnt))
+            .unwrap_or_else(|e| format!("error {:?}", e)),
         _ => {
-            if let Some(ip) = cmd.strip_prefix("chart_b") {
+            if let Some(rule) = cmd.strip_prefix("mapping_add:") {
+                crate::command::command_mapping_add(vnt, rule)
+            } else if let Some(rule) = cmd.strip_prefix("mapping_remove:") {
+                crate::command::command_mapping_remove(vnt, rule)
+            } else if let Some(spec) = cmd.strip_prefix("log_level:") {
+                crate::command::command_log_level(spec)
+            } else if let Some(args) = cmd.strip_prefix("ping:") {
+                crate::command::command_ping(vnt, args)
+            } else if let Some(args) = cmd.strip_prefix("traceroute:") {
+                crate::command::command_traceroute(vnt, args)
+            } else if let Some(args) = cmd.strip_prefix("iperf:") {
+                crate::command::command_iperf(vnt, args)
+            } else if let Some(args) = cmd.strip_prefix("switch:") {
+                crate::command::switch::request(vnt, args)
+            } else if let Some(args) = cmd.strip_prefix("firewall:") {
+                crate::command::command_firewall(vnt, args)
+            } else if let Some(ip) = cmd.strip_prefix("chart_b") {
                 let chart = if ip.is_empty() {
                     command_chart_b(&vnt, &vnt.current_device().virtual_gateway.to_string())
                 } else {
@@ -0,0 +1,84 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use vnt::core::{Config, Vnt};
+
+/// 切换到其他组网，server为None时使用原来的服务端
+#[derive(Clone, Debug)]
+pub struct SwitchRequest {
+    pub token: String,
+    pub server: Option<String>,
+}
+
+impl FromStr for SwitchRequest {
+    type Err = String;
+
+    /// 格式为'<token> [server]'
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.split_whitespace();
+        let token = match split.next() {
+            Some(token) => token.to_string(),
+            None => return Err("token is empty".to_string()),
+        };
+        let server = split.next().map(|v| v.to_string());
+        if split.next().is_some() {
+            return Err("usage: <token> [server]".to_string());
+        }
+        Ok(Self { token, server })
+    }
+}
+
+// 只有启动方支持重新连接时才能切换
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// 切换后使用的配置，当前的连接停止后由启动方取出
+static PENDING: Mutex<Option<Config>> = Mutex::new(None);
+// 正在运行的vnt，命令端口等持有的可能是切换前的vnt
+static CURRENT: Mutex<Option<Vnt>> = Mutex::new(None);
+
+/// 启动方在每次连接后调用，允许切换组网
+pub fn enable(vnt: Vnt) {
+    CURRENT.lock().unwrap().replace(vnt);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// 正在运行的vnt，没有切换过时就是传入的vnt
+pub fn current(vnt: &Vnt) -> Vnt {
+    match CURRENT.lock().unwrap().as_ref() {
+        Some(current) => current.clone(),
+        None => vnt.clone(),
+    }
+}
+
+/// 请求切换组网，停止当前的连接，进程不退出，命令端口和日志不变
+pub fn request(vnt: &Vnt, args: &str) -> String {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return "switch is only supported when running a single network".to_string();
+    }
+    let request = match SwitchRequest::from_str(args) {
+        Ok(request) => request,
+        Err(e) => return format!("switch error {}", e),
+    };
+    let vnt = current(vnt);
+    // 先生成新的配置，停止后再失败就无法恢复
+    let config = match vnt
+        .config()
+        .switch(request.token, request.server.as_deref())
+    {
+        Ok(config) => config,
+        Err(e) => return format!("switch error {}", e),
+    };
+    log::info!("切换组网 server={}", config.server_address_str);
+    PENDING.lock().unwrap().replace(config);
+    vnt.stop();
+    "switching".to_string()
+}
+
+/// 当前的连接是否因为切换组网而停止
+pub fn pending() -> bool {
+    PENDING.lock().unwrap().is_some()
+}
+
+pub fn take() -> Option<Config> {
+    PENDING.lock().unwrap().take()
+}
@@ -0,0 +1,112 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+use serde_json::json;
+use vnt::core::Vnt;
+
+use crate::command::http::{self, Request, Response};
+
+const INDEX: &str = include_str!("web.html");
+// 日志只读取文件末尾的这些数据
+const LOG_TAIL_LEN: u64 = 64 * 1024;
+const LOG_TAIL_LINES: usize = 200;
+
+/// 启动内置的网页面板，只读，没有身份验证。
+/// 只接受Host为IP地址或localhost的请求，防止其他网站通过DNS重绑定读取数据
+pub fn start(addr: SocketAddr, vnt: Vnt) -> io::Result<()> {
+    if !addr.ip().is_loopback() {
+        log::warn!("网页面板监听在非本地地址 {}", addr);
+    }
+    http::serve(addr, "web", move |request| {
+        if !host_allowed(request, addr.ip()) {
+            return Response::text(403, "forbidden");
+        }
+        if request.method != "GET" {
+            return Response::text(405, "method not allowed");
+        }
+        match request.path.as_str() {
+            "/" | "/index.html" => Response::new(200, "text/html; charset=utf-8", INDEX),
+            "/data" => Response::new(
+                200,
+                "application/json; charset=utf-8",
+                data(&crate::command::switch::current(&vnt)),
+            ),
+            "/logs" => Response::text(200, logs()),
+            _ => Response::not_found(),
+        }
+    })
+}
+
+fn host_allowed(request: &Request, bind: IpAddr) -> bool {
+    let host = match request.header("host") {
+        Some(host) => host,
+        None => return false,
+    };
+    // 去掉端口，ipv6格式为[::1]:port
+    let host = if let Some(v) = host.strip_prefix('[') {
+        match v.split_once(']') {
+            Some((ip, _)) => ip,
+            None => return false,
+        }
+    } else {
+        match host.split_once(':') {
+            Some((host, _)) => host,
+            None => host,
+        }
+    };
+    if host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback() || bind.is_unspecified() || ip == bind,
+        Err(_) => false,
+    }
+}
+
+fn data(vnt: &Vnt) -> String {
+    let info = crate::command::command_info(vnt);
+    json!({
+        "status": crate::command::command_status(vnt),
+        "nat_type": info.nat_type,
+        "relay_server": info.relay_server,
+        "list": crate::command::command_list(vnt),
+        "traffic": crate::command::command_chart_a(vnt),
+    })
+    .to_string()
+}
+
+fn logs() -> String {
+    let path = match log_file() {
+        Some(path) => path,
+        None => return "no file appender in log4rs.yaml".to_string(),
+    };
+    match tail(&path) {
+        Ok(logs) => logs,
+        Err(e) => format!("read {:?} error {}", path, e),
+    }
+}
+
+/// log4rs.yaml中第一个写文件的appender的路径
+fn log_file() -> Option<PathBuf> {
+    let conf = std::fs::read_to_string("log4rs.yaml").ok()?;
+    let value: serde_yaml::Value = serde_yaml::from_str(&conf).ok()?;
+    value
+        .get("appenders")?
+        .as_mapping()?
+        .values()
+        .find_map(|v| v.get("path")?.as_str().map(PathBuf::from))
+}
+
+fn tail(path: &PathBuf) -> io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(LOG_TAIL_LEN)))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    let text = String::from_utf8_lossy(&buf);
+    let lines: Vec<&str> = text.lines().collect();
+    let start = lines.len().saturating_sub(LOG_TAIL_LINES);
+    Ok(lines[start..].join("\n"))
+}
@@ -0,0 +1,218 @@
+use anyhow::anyhow;
+use serde_yaml::{Mapping, Value};
+
+// 环境变量名是VNT_加上配置文件中字段名的大写，例如VNT_TOKEN、VNT_SERVER_ADDRESS
+const ENV_PREFIX: &str = "VNT_";
+
+#[derive(Copy, Clone)]
+enum Kind {
+    Str,
+    Num,
+    // true/false，也可以用1/0
+    Bool,
+    // 用分隔符分隔的多个值，命令行中对应多次指定的参数
+    List(char),
+    // 逗号分隔的数字，命令行中是一个参数
+    NumList,
+}
+
+// 配置文件中的字段名，对应的命令行参数，值的类型
+// in_ips、wg_peers和allowed_ips的单个值中就有逗号，所以用分号分隔
+const ENV_KEYS: [(&str, &str, Kind); 94] = [
+    ("tap", "a", Kind::Bool),
+    ("driver", "driver", Kind::Str),
+    ("wintun_path", "wintun-path", Kind::Str),
+    ("layer2", "tap", Kind::Bool),
+    ("token", "k", Kind::Str),
+    ("device_id", "d", Kind::Str),
+    ("name", "n", Kind::Str),
+    ("server_address", "s", Kind::Str),
+    ("server_prefer", "server-prefer", Kind::Str),
+    ("server_cert", "server-cert", Kind::Str),
+    ("outbound_proxy", "outbound-proxy", Kind::Str),
+    ("stun_server", "e", Kind::List(',')),
+    ("dns", "dns", Kind::List(',')),
+    ("dns_mode", "dns-mode", Kind::Str),
+    ("dns_refresh", "dns-refresh", Kind::Num),
+    ("in_ips", "i", Kind::List(';')),
+    ("out_ips", "o", Kind::List(',')),
+    ("password", "w", Kind::Str),
+    ("mtu", "u", Kind::Num),
+    ("ip", "ip", Kind::Str),
+    ("allow_ip_change", "allow-ip-change", Kind::Bool),
+    ("network", "network", Kind::Str),
+    ("use_channel", "use-channel", Kind::Str),
+    ("peer_channel", "peer-channel", Kind::List(',')),
+    ("path_policy", "path-policy", Kind::List(',')),
+    ("no_proxy", "no-proxy", Kind::Bool),
+    ("server_encrypt", "W", Kind::Bool),
+    ("cipher_model", "model", Kind::Str),
+    ("finger", "finger", Kind::Bool),
+    ("encrypt_scope", "encrypt-scope", Kind::Str),
+    ("punch_model", "punch", Kind::Str),
+    ("ports", "ports", Kind::NumList),
+    ("cmd", "cmd", Kind::Bool),
+    ("first_latency", "first-latency", Kind::Bool),
+    ("device_name", "nic", Kind::Str),
+    ("packet_loss", "packet-loss", Kind::Num),
+    ("packet_delay", "packet-delay", Kind::Num),
+    ("mapping", "mapping", Kind::List(',')),
+    ("vnt_mapping", "vnt-mapping", Kind::List(',')),
+    ("forward", "forward", Kind::List(',')),
+    ("compressor", "compressor", Kind::Str),
+    ("obfs", "obfs", Kind::Str),
+    ("fec", "fec", Kind::Str),
+    ("aggregate", "aggregate", Kind::Num),
+    ("disable_stats", "disable-stats", Kind::Bool),
+    ("allow_wire_guard", "allow-wg", Kind::Bool),
+    ("disable_peer_relay", "disable-peer-relay", Kind::Bool),
+    ("heartbeat", "heartbeat", Kind::Num),
+    ("route_timeout", "route-timeout", Kind::Num),
+    ("dead_path", "dead-path", Kind::Num),
+    ("punch_rate", "punch-rate", Kind::Num),
+    ("punch_concurrency", "punch-concurrency", Kind::Num),
+    ("peer_cache", "peer-cache", Kind::Str),
+    ("wg_key", "wg-key", Kind::Str),
+    ("wg_port", "wg-port", Kind::Num),
+    ("wg_peers", "wg-peer", Kind::List(';')),
+    ("peer_key", "peer-key", Kind::Str),
+    ("trusted_keys", "trusted-key", Kind::List(',')),
+    ("pq_kem", "pq-kem", Kind::Bool),
+    ("local_ipv4", "local-ipv4", Kind::Str),
+    ("multipath", "multipath", Kind::List(',')),
+    ("multipath_mode", "multipath-mode", Kind::Str),
+    ("acl", "acl", Kind::List(',')),
+    ("allowed_ips", "allowed-ips", Kind::List(';')),
+    ("firewall", "firewall", Kind::Bool),
+    ("firewall_allow", "firewall-allow", Kind::List(',')),
+    ("host_firewall", "host-firewall", Kind::Bool),
+    ("identity", "identity", Kind::Str),
+    ("identity_cert", "identity-cert", Kind::Str),
+    ("invite", "invite", Kind::Str),
+    ("totp_secret", "totp-secret", Kind::Str),
+    ("integrity", "integrity", Kind::Bool),
+    ("integrity_compat", "integrity-compat", Kind::Bool),
+    ("watchdog", "watchdog", Kind::Num),
+    ("mdns_reflect", "mdns-reflect", Kind::Bool),
+    ("mdns_services", "mdns-service", Kind::List(',')),
+    ("mdns_rate", "mdns-rate", Kind::Num),
+    ("advertise_routes", "advertise-route", Kind::List(',')),
+    ("exit_node", "exit-node", Kind::Str),
+    ("allow_exit", "allow-exit", Kind::Bool),
+    ("route_policy", "route-policy", Kind::List(',')),
+    ("out_sources", "out-source", Kind::List(',')),
+    ("bandwidth_limit", "bandwidth-limit", Kind::Str),
+    ("per_peer_limit", "per-peer-limit", Kind::List(',')),
+    ("qos", "qos", Kind::Bool),
+    ("metrics", "metrics", Kind::Str),
+    ("api", "api", Kind::Str),
+    ("api_token", "api-token", Kind::Str),
+    ("web", "web", Kind::Str),
+    ("path_switch", "path-switch", Kind::Bool),
+    ("capture", "capture", Kind::Str),
+    ("log_json", "log-json", Kind::Bool),
+    ("log_rotate", "log-rotate", Kind::Str),
+    ("log_keep", "log-keep", Kind::Num),
+];
+
+fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase())
+}
+
+// 空值视为没有设置，方便容器中留空
+fn env_var(key: &str) -> Option<String> {
+    std::env::var(env_name(key))
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_bool(key: &str, value: &str) -> anyhow::Result<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => Err(anyhow!("{} must be true/false", env_name(key))),
+    }
+}
+
+fn split(value: &str, sep: char) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(sep)
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// 命令行中没有指定的参数，使用环境变量转换成的命令行参数，命令行优先
+pub fn env_args(matches: &getopts::Matches) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    for (key, opt, kind) in ENV_KEYS {
+        if matches.opt_present(opt) {
+            continue;
+        }
+        let value = match env_var(key) {
+            Some(value) => value,
+            None => continue,
+        };
+        let opt = if opt.len() == 1 {
+            format!("-{}", opt)
+        } else {
+            format!("--{}", opt)
+        };
+        match kind {
+            Kind::Bool => {
+                if parse_bool(key, &value)? {
+                    args.push(opt);
+                }
+            }
+            Kind::List(sep) => {
+                for v in split(&value, sep) {
+                    args.push(opt.clone());
+                    args.push(v);
+                }
+            }
+            Kind::Str | Kind::Num | Kind::NumList => {
+                args.push(opt);
+                args.push(value);
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// 用环境变量覆盖配置文件中的字段，返回是否有覆盖
+pub fn override_config(mapping: &mut Mapping) -> anyhow::Result<bool> {
+    let mut changed = false;
+    for (key, _, kind) in ENV_KEYS {
+        let value = match env_var(key) {
+            Some(value) => value,
+            None => continue,
+        };
+        let value = match kind {
+            Kind::Str => Value::String(value),
+            Kind::Num => number(key, &value)?,
+            Kind::Bool => Value::Bool(parse_bool(key, &value)?),
+            Kind::List(sep) => Value::Sequence(split(&value, sep).map(Value::String).collect()),
+            Kind::NumList => {
+                let mut list = Vec::new();
+                for v in split(&value, ',') {
+                    list.push(number(key, &v)?);
+                }
+                Value::Sequence(list)
+            }
+        };
+        if key == "server_address" {
+            // server是server_address的别名，同时存在会报重复字段
+            mapping.remove("server");
+        }
+        mapping.insert(Value::String(key.to_string()), value);
+        changed = true;
+    }
+    Ok(changed)
+}
+
+fn number(key: &str, value: &str) -> anyhow::Result<Value> {
+    match serde_yaml::from_str::<Value>(value) {
+        Ok(value @ Value::Number(_)) => Ok(value),
+        _ => Err(anyhow!("{} must be a number", env_name(key))),
+    }
+}
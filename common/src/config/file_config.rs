@@ -1,54 +1,173 @@
 use anyhow::anyhow;
-use std::net::Ipv4Addr;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::str::FromStr;
 
 use crate::config::get_device_id;
 use crate::{args_parse, config};
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer};
+use serde_yaml::{Mapping, Value};
+use vnt::acl::allowed_ips::AllowedIps;
+use vnt::acl::firewall::FirewallRule;
+use vnt::acl::AclRule;
+use vnt::channel::capture::CaptureConfig;
+use vnt::channel::multipath::MultipathModel;
+use vnt::channel::policy::PathPolicyRule;
 use vnt::channel::punch::PunchModel;
-use vnt::channel::UseChannelType;
-use vnt::cipher::CipherModel;
+use vnt::channel::{PeerChannel, UseChannelType};
+use vnt::cipher::{CipherModel, EncryptScope};
 use vnt::compression::Compressor;
 use vnt::core::Config;
+use vnt::external_route::{OutSourceRule, RoutePolicy};
+use vnt::fec::FecConfig;
+use vnt::identity::totp::TotpSecret;
+use vnt::identity::{IdentityConfig, Invite};
+use vnt::obfs::ObfsModel;
+use vnt::peer_key::PeerKeyConfig;
+use vnt::reflector::{ReflectConfig, DEFAULT_MDNS_RATE};
+use vnt::util::limit::{BandwidthLimit, PeerLimit};
+use vnt::util::{AddressPrefer, DnsMode};
+use vnt::wireguard::{WgConfig, DEFAULT_WG_PORT};
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(default)]
+/// 配置文件的结构，不认识的字段直接报错，字段的格式在解析时校验，错误信息带上字段所在的行列
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
 pub struct FileConfig {
-    #[cfg(target_os = "windows")]
+    // 只在windows上生效，其他平台忽略，方便共用配置文件
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
     pub tap: bool,
+    // windows网卡驱动tun/tap，设置后覆盖tap
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub driver: Option<String>,
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub wintun_path: Option<String>,
     pub token: String,
     pub device_id: String,
     pub name: String,
+    #[serde(alias = "server", deserialize_with = "server_address")]
     pub server_address: String,
+    #[serde(deserialize_with = "option_from_str")]
+    pub server_prefer: Option<AddressPrefer>,
+    pub server_cert: Option<String>,
+    pub outbound_proxy: Option<String>,
     pub stun_server: Vec<String>,
     pub dns: Vec<String>,
-    pub in_ips: Vec<String>,
-    pub out_ips: Vec<String>,
+    #[serde(deserialize_with = "option_from_str")]
+    pub dns_mode: Option<DnsMode>,
+    pub dns_refresh: u32,
+    #[serde(deserialize_with = "in_ips")]
+    pub in_ips: Vec<(u32, u32, Ipv4Addr)>,
+    #[serde(deserialize_with = "out_ips")]
+    pub out_ips: Vec<(u32, u32)>,
     pub password: Option<String>,
     pub mtu: Option<u32>,
     pub tcp: bool,
-    pub ip: Option<String>,
-    pub use_channel: String,
-    #[cfg(feature = "ip_proxy")]
+    pub ip: Option<Ipv4Addr>,
+    pub allow_ip_change: bool,
+    #[serde(deserialize_with = "network")]
+    pub network: Option<(u32, u32)>,
+    #[serde(deserialize_with = "from_str")]
+    pub use_channel: UseChannelType,
+    #[serde(deserialize_with = "vec_from_str")]
+    pub peer_channel: Vec<PeerChannel>,
+    #[serde(deserialize_with = "vec_from_str")]
+    pub path_policy: Vec<PathPolicyRule>,
+    #[cfg_attr(not(feature = "ip_proxy"), allow(dead_code))]
     pub no_proxy: bool,
     pub server_encrypt: bool,
-    pub cipher_model: Option<String>,
+    #[serde(deserialize_with = "option_from_str")]
+    pub cipher_model: Option<CipherModel>,
     pub finger: bool,
-    pub punch_model: String,
+    #[serde(deserialize_with = "from_str")]
+    pub punch_model: PunchModel,
     pub ports: Option<Vec<u16>>,
     pub cmd: bool,
     pub first_latency: bool,
     pub device_name: Option<String>,
     pub packet_loss: Option<f64>,
     pub packet_delay: u32,
-    #[cfg(feature = "port_mapping")]
     pub mapping: Vec<String>,
-    pub compressor: Option<String>,
+    #[serde(deserialize_with = "option_from_str")]
+    pub compressor: Option<Compressor>,
+    #[serde(deserialize_with = "option_from_str")]
+    pub obfs: Option<ObfsModel>,
+    #[serde(deserialize_with = "option_from_str")]
+    pub fec: Option<FecConfig>,
+    pub aggregate: Option<u32>,
     pub vnt_mapping: Vec<String>,
+    // 和vnt_mapping相同，格式为127.0.0.1:2222->10.26.0.5:22
+    pub forward: Vec<String>,
     pub disable_stats: bool,
     // 允许传递wg流量
     pub allow_wire_guard: bool,
+    // 二层模式
+    pub layer2: bool,
+    pub disable_peer_relay: bool,
+    pub heartbeat: u32,
+    pub route_timeout: u32,
+    pub dead_path: u32,
+    pub punch_rate: u32,
+    pub punch_concurrency: u32,
+    pub peer_cache: Option<String>,
+    pub wg_key: Option<String>,
+    pub wg_port: u16,
+    pub wg_peers: Vec<String>,
+    pub peer_key: Option<String>,
+    pub trusted_keys: Vec<String>,
+    pub pq_kem: bool,
     pub local_ipv4: Option<Ipv4Addr>,
+    pub multipath: Vec<Ipv4Addr>,
+    #[serde(deserialize_with = "option_from_str")]
+    pub multipath_mode: Option<MultipathModel>,
+    #[serde(deserialize_with = "vec_from_str")]
+    pub acl: Vec<AclRule>,
+    #[serde(deserialize_with = "vec_from_str")]
+    pub allowed_ips: Vec<AllowedIps>,
+    pub firewall: bool,
+    #[serde(deserialize_with = "vec_from_str")]
+    pub firewall_allow: Vec<FirewallRule>,
+    pub host_firewall: bool,
+    pub identity: Option<String>,
+    pub identity_cert: Option<String>,
+    #[serde(deserialize_with = "option_from_str")]
+    pub invite: Option<Invite>,
+    pub totp_secret: Option<String>,
+    pub integrity: bool,
+    pub integrity_compat: bool,
+    #[serde(deserialize_with = "option_from_str")]
+    pub encrypt_scope: Option<EncryptScope>,
+    pub watchdog: u32,
+    pub mdns_reflect: bool,
+    pub mdns_services: Vec<String>,
+    pub mdns_rate: u32,
+    #[serde(deserialize_with = "out_ips")]
+    pub advertise_routes: Vec<(u32, u32)>,
+    pub exit_node: Option<Ipv4Addr>,
+    pub allow_exit: bool,
+    #[serde(deserialize_with = "vec_from_str")]
+    pub route_policy: Vec<RoutePolicy>,
+    #[serde(deserialize_with = "vec_from_str")]
+    pub out_sources: Vec<OutSourceRule>,
+    #[serde(deserialize_with = "option_from_str")]
+    pub bandwidth_limit: Option<BandwidthLimit>,
+    #[serde(deserialize_with = "vec_from_str")]
+    pub per_peer_limit: Vec<PeerLimit>,
+    pub qos: bool,
+    pub metrics: Option<SocketAddr>,
+    pub api: Option<SocketAddr>,
+    pub api_token: Option<String>,
+    pub web: Option<SocketAddr>,
+    pub path_switch: bool,
+    #[serde(deserialize_with = "option_from_str")]
+    pub capture: Option<CaptureConfig>,
+    pub log_json: bool,
+    pub log_rotate: Option<String>,
+    pub log_keep: u32,
+    // 命名的配置组，通过--profile选择，组内的字段覆盖外层的同名字段
+    pub profiles: HashMap<String, Mapping>,
+    // 没有指定--profile时同时运行的配置组
+    pub run_profiles: Vec<String>,
 }
 
 impl Default for FileConfig {
@@ -58,8 +177,9 @@ impl Default for FileConfig {
             stun_server.push(x.to_string());
         }
         Self {
-            #[cfg(target_os = "windows")]
             tap: false,
+            driver: None,
+            wintun_path: None,
             token: "".to_string(),
             device_id: get_device_id(),
             name: gethostname::gethostname()
@@ -67,69 +187,304 @@ impl Default for FileConfig {
                 .unwrap_or("UnknownName")
                 .to_string(),
             server_address: "nat1.wherewego.top:29872".to_string(),
+            server_prefer: None,
+            server_cert: None,
+            outbound_proxy: None,
             stun_server,
             dns: vec![],
+            dns_mode: None,
+            dns_refresh: 300,
             in_ips: vec![],
             out_ips: vec![],
             password: None,
             mtu: None,
             tcp: false,
             ip: None,
-            use_channel: "all".to_string(),
-            #[cfg(feature = "ip_proxy")]
+            allow_ip_change: false,
+            network: None,
+            use_channel: UseChannelType::All,
+            peer_channel: vec![],
+            path_policy: vec![],
             no_proxy: false,
             server_encrypt: false,
             cipher_model: None,
             finger: false,
-            punch_model: "all".to_string(),
+            punch_model: PunchModel::All,
             ports: None,
             cmd: false,
             first_latency: false,
             device_name: None,
             packet_loss: None,
             packet_delay: 0,
-            #[cfg(feature = "port_mapping")]
             mapping: vec![],
             compressor: None,
+            obfs: None,
+            fec: None,
+            aggregate: None,
             vnt_mapping: vec![],
+            forward: vec![],
             disable_stats: false,
             allow_wire_guard: false,
+            layer2: false,
+            disable_peer_relay: false,
+            heartbeat: 3,
+            route_timeout: 10,
+            dead_path: 2,
+            punch_rate: 10,
+            punch_concurrency: 4,
+            peer_cache: None,
+            wg_key: None,
+            wg_port: DEFAULT_WG_PORT,
+            wg_peers: vec![],
+            peer_key: None,
+            trusted_keys: vec![],
+            pq_kem: false,
             local_ipv4: None,
+            multipath: vec![],
+            multipath_mode: None,
+            acl: vec![],
+            allowed_ips: vec![],
+            firewall: false,
+            firewall_allow: vec![],
+            host_firewall: false,
+            identity: None,
+            identity_cert: None,
+            invite: None,
+            totp_secret: None,
+            integrity: false,
+            integrity_compat: false,
+            encrypt_scope: None,
+            watchdog: 0,
+            mdns_reflect: false,
+            mdns_services: vec![],
+            mdns_rate: DEFAULT_MDNS_RATE,
+            advertise_routes: vec![],
+            exit_node: None,
+            allow_exit: false,
+            route_policy: vec![],
+            out_sources: vec![],
+            bandwidth_limit: None,
+            per_peer_limit: vec![],
+            qos: false,
+            metrics: None,
+            api: None,
+            api_token: None,
+            web: None,
+            path_switch: false,
+            capture: None,
+            log_json: false,
+            log_rotate: None,
+            log_keep: 5,
+            profiles: HashMap::new(),
+            run_profiles: Vec::new(),
         }
     }
 }
 
-pub fn read_config(file_path: &str) -> anyhow::Result<(Config, Vec<String>, bool)> {
+/// profile为配置文件中profiles下的配置组名
+pub fn read_config(
+    file_path: &str,
+    profile: Option<&str>,
+) -> anyhow::Result<(Config, Vec<String>, bool)> {
     let conf = std::fs::read_to_string(file_path)?;
-    let file_conf = match serde_yaml::from_str::<FileConfig>(&conf) {
-        Ok(val) => val,
-        Err(e) => {
-            log::error!("{:?}", e);
-            return Err(anyhow!("{}", e));
-        }
+    parse_config(&conf, profile)
+}
+
+/// 同时运行多个网络，profile用逗号分隔多个配置组名，没有指定时使用配置文件中的run_profiles，
+/// 返回每个网络的配置组名和配置
+pub fn read_networks(
+    file_path: &str,
+    profile: Option<&str>,
+) -> anyhow::Result<(Vec<(Option<String>, Config)>, Vec<String>, bool)> {
+    let conf = std::fs::read_to_string(file_path)?;
+    let names: Vec<String> = match profile {
+        Some(profile) => profile
+            .split(',')
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect(),
+        None => run_profiles(&conf),
     };
-    if file_conf.token.is_empty() {
-        return Err(anyhow!("token is_empty"));
+    if names.len() <= 1 {
+        let profile = names.into_iter().next();
+        let (config, vnt_mapping, cmd) = parse_config(&conf, profile.as_deref())?;
+        return Ok((vec![(profile, config)], vnt_mapping, cmd));
+    }
+    let mut networks: Vec<(Option<String>, Config)> = Vec::with_capacity(names.len());
+    let mut vnt_mapping = Vec::new();
+    let mut cmd = false;
+    for name in names {
+        if networks.iter().any(|(v, _)| v.as_ref() == Some(&name)) {
+            return Err(anyhow!("profile '{}' is repeated", name));
+        }
+        let (config, mapping, c) =
+            parse_config(&conf, Some(&name)).map_err(|e| anyhow!("profile '{}' {}", name, e))?;
+        vnt_mapping.extend(mapping);
+        cmd |= c;
+        networks.push((Some(name), config));
     }
+    check_networks(&networks)?;
+    Ok((networks, vnt_mapping, cmd))
+}
 
-    let in_ips = match args_parse::ips_parse(&file_conf.in_ips) {
-        Ok(in_ips) => in_ips,
-        Err(e) => {
-            return Err(anyhow!("in_ips {:?} error:{}", &file_conf.in_ips, e));
+// 配置文件格式错误时由parse_config报错
+fn run_profiles(conf: &str) -> Vec<String> {
+    match serde_yaml::from_str::<Value>(conf) {
+        Ok(Value::Mapping(mapping)) => match mapping.get("run_profiles") {
+            Some(Value::Sequence(list)) => list
+                .iter()
+                .filter_map(|v| v.as_str().map(|v| v.to_string()))
+                .collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// 同时运行的网络不能使用相同的网卡、监听地址和虚拟网段，否则会互相抢占
+fn check_networks(networks: &[(Option<String>, Config)]) -> anyhow::Result<()> {
+    for (i, (name, config)) in networks.iter().enumerate() {
+        let name = name.as_deref().unwrap_or_default();
+        for (other_name, other) in &networks[..i] {
+            let other_name = other_name.as_deref().unwrap_or_default();
+            if config.device_name.is_some() && config.device_name == other.device_name {
+                return Err(anyhow!(
+                    "profile '{}' and '{}' use the same device_name {:?}",
+                    other_name,
+                    name,
+                    config.device_name.as_deref().unwrap_or_default()
+                ));
+            }
+            let listen = [
+                ("metrics", config.metrics, other.metrics),
+                ("api", config.api, other.api),
+                ("web", config.web, other.web),
+            ];
+            for (key, addr, other_addr) in listen {
+                if addr.is_some() && addr == other_addr {
+                    return Err(anyhow!(
+                        "profile '{}' and '{}' use the same {} address",
+                        other_name,
+                        name,
+                        key
+                    ));
+                }
+            }
+            if let (Some(ports), Some(other_ports)) = (&config.ports, &other.ports) {
+                if let Some(port) = ports.iter().find(|v| **v != 0 && other_ports.contains(v)) {
+                    return Err(anyhow!(
+                        "profile '{}' and '{}' use the same port {}",
+                        other_name,
+                        name,
+                        port
+                    ));
+                }
+            }
+            if let (Some((network, netmask)), Some((other_network, other_netmask))) =
+                (config.network, other.network)
+            {
+                let mask = netmask & other_netmask;
+                if network & mask == other_network & mask {
+                    return Err(anyhow!(
+                        "profile '{}' and '{}' have overlapping networks, their routes would conflict",
+                        other_name,
+                        name
+                    ));
+                }
+            }
         }
+    }
+    Ok(())
+}
+
+/// 解析配置内容，json也是合法的yaml，嵌入到其他程序时可以直接传入json
+pub fn parse_config(
+    conf: &str,
+    profile: Option<&str>,
+) -> anyhow::Result<(Config, Vec<String>, bool)> {
+    // 加密的敏感字段只在内存中解密
+    #[cfg(feature = "config_encrypt")]
+    let conf = if config::secret::is_encrypted(conf) {
+        config::secret::decrypt_config(conf, &config::secret::passphrase(false)?)?
+    } else {
+        conf.to_string()
     };
-    let out_ips = match args_parse::out_ips_parse(&file_conf.out_ips) {
-        Ok(out_ips) => out_ips,
+    #[cfg(not(feature = "config_encrypt"))]
+    if config::secret::is_encrypted(&conf) {
+        return Err(anyhow!("encrypted config requires config_encrypt feature"));
+    }
+    let mut file_conf = match serde_yaml::from_str::<FileConfig>(&conf) {
+        Ok(val) => val,
         Err(e) => {
-            return Err(anyhow!("out_ips {:?} error:{}", &file_conf.out_ips, e));
+            log::error!("{:?}", e);
+            return Err(anyhow!("{}", e));
         }
     };
-    let virtual_ip = match file_conf.ip.clone().map(|v| Ipv4Addr::from_str(&v)) {
-        None => None,
-        Some(r) => Some(r.map_err(|e| anyhow!("ip {:?} error:{}", &file_conf.ip, e))?),
+    // 先单独校验配置文件，错误信息才有行列，再合并配置组和环境变量
+    let mut mapping = match serde_yaml::from_str::<Value>(&conf)? {
+        Value::Mapping(mapping) => mapping,
+        _ => Mapping::new(),
     };
+    let mut changed = false;
+    if let Some(profile) = profile {
+        let profile_mapping = match file_conf.profiles.remove(profile) {
+            Some(profile_mapping) => profile_mapping,
+            None => {
+                let mut names: Vec<&String> = file_conf.profiles.keys().collect();
+                names.sort();
+                return Err(anyhow!(
+                    "profile '{}' not found, profiles: {:?}",
+                    profile,
+                    names
+                ));
+            }
+        };
+        if profile_mapping.contains_key("profiles") {
+            return Err(anyhow!("profile '{}' cannot contain profiles", profile));
+        }
+        mapping.remove("profiles");
+        for (key, value) in profile_mapping {
+            if key.as_str() == Some("server_address") {
+                mapping.remove("server");
+            } else if key.as_str() == Some("server") {
+                mapping.remove("server_address");
+            }
+            mapping.insert(key, value);
+        }
+        changed = true;
+    }
+    if config::env::override_config(&mut mapping)? {
+        changed = true;
+    }
+    if changed {
+        file_conf = serde_yaml::from_value::<FileConfig>(Value::Mapping(mapping))
+            .map_err(|e| anyhow!("{}", e))?;
+    }
+    if let Some(profile) = profile {
+        // 不同配置组默认使用不同的网卡名称，同时运行时不会冲突
+        if file_conf.device_name.is_none() {
+            file_conf.device_name = Some(profile_device_name(profile));
+        }
+    }
+    let invite = file_conf.invite.take();
+    // token为空时使用邀请码中的token和服务端地址
+    if let Some(invite) = invite.as_ref() {
+        if file_conf.token.is_empty() {
+            file_conf.token = invite.token.clone();
+            if !invite.server.is_empty() {
+                file_conf.server_address = invite.server.clone();
+            }
+        }
+    }
+    if file_conf.token.is_empty() {
+        return Err(anyhow!("token is_empty"));
+    }
+    #[cfg(not(feature = "port_mapping"))]
+    if !file_conf.mapping.is_empty() {
+        return Err(anyhow!("mapping requires port_mapping feature"));
+    }
     let cipher_model = if let Some(v) = file_conf.cipher_model {
-        CipherModel::from_str(&v).map_err(|e| anyhow!("{}", e))?
+        v
     } else {
         #[cfg(not(any(feature = "aes_gcm", feature = "server_encrypt")))]
         if file_conf.password.is_some() {
@@ -140,51 +495,272 @@ pub fn read_config(file_path: &str) -> anyhow::Result<(Config, Vec<String>, bool
         #[cfg(any(feature = "aes_gcm", feature = "server_encrypt"))]
         CipherModel::AesGcm
     };
-
-    let punch_model = PunchModel::from_str(&file_conf.punch_model).map_err(|e| anyhow!("{}", e))?;
-    let use_channel_type =
-        UseChannelType::from_str(&file_conf.use_channel).map_err(|e| anyhow!("{}", e))?;
-    let compressor = if let Some(compressor) = file_conf.compressor.as_ref() {
-        Compressor::from_str(compressor).map_err(|e| anyhow!("{}", e))?
+    let wireguard = if let Some(wg_key) = file_conf.wg_key.as_ref() {
+        Some(WgConfig::new(
+            wg_key,
+            file_conf.wg_port,
+            &file_conf.wg_peers,
+        )?)
     } else {
-        Compressor::None
+        None
     };
+    let mdns_reflect = if file_conf.mdns_reflect {
+        Some(ReflectConfig::new(
+            &file_conf.mdns_services,
+            file_conf.mdns_rate,
+        )?)
+    } else {
+        None
+    };
+    let peer_key = if let Some(peer_key) = file_conf.peer_key.as_ref() {
+        Some(PeerKeyConfig::new(peer_key, &file_conf.trusted_keys)?)
+    } else {
+        None
+    };
+    // 没有配置身份私钥时，使用邀请码加入时生成的私钥
+    #[cfg(feature = "identity")]
+    if file_conf.identity.is_none() {
+        file_conf.identity = config::get_identity(invite.is_some())?;
+    }
+    let identity = if let Some(identity) = file_conf.identity.as_ref() {
+        Some(IdentityConfig::new(
+            identity,
+            file_conf.identity_cert.as_deref(),
+            invite,
+        )?)
+    } else if invite.is_some() {
+        return Err(anyhow!("invite requires identity"));
+    } else {
+        None
+    };
+    let totp_secret = if let Some(totp_secret) = file_conf.totp_secret.as_ref() {
+        Some(TotpSecret::new(totp_secret).map_err(|e| anyhow!("totp_secret {}", e))?)
+    } else {
+        None
+    };
+    #[cfg(target_os = "windows")]
+    if let Some(driver) = file_conf.driver.as_ref() {
+        file_conf.tap = args_parse::driver_parse(driver).map_err(|e| anyhow!("driver {}", e))?;
+    }
+    #[cfg(feature = "log")]
+    {
+        let log_rotate = match file_conf.log_rotate.as_ref() {
+            Some(log_rotate) => Some(
+                log_rotate
+                    .parse::<crate::logging::LogRotate>()
+                    .map_err(|e| anyhow!("log_rotate {}", e))?,
+            ),
+            None => None,
+        };
+        let _ = crate::logging::set_log_json(file_conf.log_json);
+        let _ = crate::logging::set_log_rotate(log_rotate, file_conf.log_keep);
+    }
     let config = Config::new(
         #[cfg(target_os = "windows")]
         #[cfg(feature = "integrated_tun")]
         file_conf.tap,
+        #[cfg(target_os = "windows")]
+        #[cfg(feature = "integrated_tun")]
+        file_conf.wintun_path,
         file_conf.token,
         file_conf.device_id,
         file_conf.name,
         file_conf.server_address,
+        file_conf.server_prefer.unwrap_or(AddressPrefer::Any),
+        file_conf.server_cert,
+        file_conf.outbound_proxy,
         file_conf.dns,
+        file_conf.dns_mode.unwrap_or(DnsMode::Udp),
+        file_conf.dns_refresh,
         file_conf.stun_server,
-        in_ips,
-        out_ips,
+        file_conf.in_ips,
+        file_conf.out_ips,
         file_conf.password,
         file_conf.mtu,
-        virtual_ip,
+        file_conf.ip,
         #[cfg(feature = "integrated_tun")]
         #[cfg(feature = "ip_proxy")]
         file_conf.no_proxy,
         file_conf.server_encrypt,
         cipher_model,
         file_conf.finger,
-        punch_model,
+        file_conf.punch_model,
         file_conf.ports,
         file_conf.first_latency,
         #[cfg(feature = "integrated_tun")]
         file_conf.device_name,
-        use_channel_type,
+        file_conf.use_channel,
         file_conf.packet_loss,
         file_conf.packet_delay,
         #[cfg(feature = "port_mapping")]
         file_conf.mapping,
-        compressor,
+        file_conf.compressor.unwrap_or(Compressor::None),
+        file_conf.obfs.unwrap_or(ObfsModel::None),
+        file_conf.fec,
+        file_conf.aggregate,
         !file_conf.disable_stats,
         file_conf.allow_wire_guard,
+        file_conf.layer2,
+        !file_conf.disable_peer_relay,
+        file_conf.heartbeat,
+        file_conf.route_timeout,
+        file_conf.dead_path,
+        file_conf.punch_rate,
+        file_conf.punch_concurrency,
+        file_conf.peer_cache,
+        wireguard,
+        peer_key,
+        file_conf.pq_kem,
+        file_conf.acl,
+        identity,
+        totp_secret,
+        file_conf.integrity,
+        file_conf.encrypt_scope.unwrap_or(EncryptScope::All),
         file_conf.local_ipv4,
+        file_conf.multipath,
+        file_conf.multipath_mode.unwrap_or(MultipathModel::Failover),
+        file_conf.watchdog,
+        mdns_reflect,
+        file_conf.advertise_routes,
+        file_conf.exit_node,
+        file_conf.allow_exit,
+        file_conf.route_policy,
+        file_conf.out_sources,
+        file_conf.bandwidth_limit,
+        file_conf.per_peer_limit,
+        file_conf.qos,
+        file_conf.metrics,
+        file_conf.api,
+        file_conf.api_token,
+        file_conf.web,
+        file_conf.path_switch,
+        file_conf.peer_channel,
+        file_conf.capture,
+        file_conf.allow_ip_change,
+        file_conf.network,
+        file_conf.allowed_ips,
+        file_conf.firewall,
+        file_conf.firewall_allow,
+        file_conf.host_firewall,
+        file_conf.path_policy,
+        file_conf.integrity_compat,
     )?;
 
-    Ok((config, file_conf.vnt_mapping, file_conf.cmd))
+    let mut vnt_mapping = file_conf.vnt_mapping;
+    vnt_mapping.extend(file_conf.forward);
+    Ok((config, vnt_mapping, file_conf.cmd))
+}
+
+/// 配置组默认的网卡名称，linux下网卡名称最长15个字符
+fn profile_device_name(profile: &str) -> String {
+    let name: String = profile
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .take(11)
+        .collect();
+    format!("vnt-{}", name)
+}
+
+// 以下用于解析时直接转换字段的类型，出错时serde_yaml会带上字段名和所在的行列
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let v = String::deserialize(deserializer)?;
+    T::from_str(&v).map_err(de::Error::custom)
+}
+
+fn option_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(v) => T::from_str(&v).map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+fn vec_from_str<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let list = Vec::<String>::deserialize(deserializer)?;
+    let mut rs = Vec::with_capacity(list.len());
+    for v in list {
+        rs.push(T::from_str(&v).map_err(de::Error::custom)?);
+    }
+    Ok(rs)
+}
+
+fn in_ips<'de, D>(deserializer: D) -> Result<Vec<(u32, u32, Ipv4Addr)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let list = Vec::<String>::deserialize(deserializer)?;
+    args_parse::ips_parse(&list)
+        .map_err(|e| de::Error::custom(format!("{}, example: 192.168.1.0/24,10.26.0.3", e)))
+}
+
+fn out_ips<'de, D>(deserializer: D) -> Result<Vec<(u32, u32)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let list = Vec::<String>::deserialize(deserializer)?;
+    args_parse::out_ips_parse(&list)
+        .map_err(|e| de::Error::custom(format!("{}, example: 192.168.1.0/24", e)))
+}
+
+fn network<'de, D>(deserializer: D) -> Result<Option<(u32, u32)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(v) => args_parse::out_ips_parse(&vec![v])
+            .map(|list| list.first().copied())
+            .map_err(|e| de::Error::custom(format!("{}, example: 100.64.0.0/16", e))),
+        None => Ok(None),
+    }
+}
+
+fn server_address<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let v = String::deserialize(deserializer)?;
+    for address in v.split(',') {
+        check_server_address(address.trim()).map_err(de::Error::custom)?;
+    }
+    Ok(v)
+}
+
+/// 服务端地址必须是host:port，可以带协议头，ws/wss是完整的url，多个地址用逗号分隔
+fn check_server_address(address: &str) -> Result<(), String> {
+    if address.is_empty() {
+        return Ok(());
+    }
+    let lower = address.to_lowercase();
+    let host_port = match lower.split_once("://") {
+        Some(("ws", _)) | Some(("wss", _)) => return Ok(()),
+        Some(("udp", v)) | Some(("tcp", v)) | Some(("tls", v)) | Some(("quic", v)) => v,
+        Some((protocol, _)) => {
+            return Err(format!(
+                "'{}' unknown protocol '{}', enum: udp/tcp/tls/quic/ws/wss",
+                address, protocol
+            ));
+        }
+        None => lower.as_str(),
+    };
+    match host_port.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && u16::from_str(port).is_ok() => Ok(()),
+        _ => Err(format!(
+            "'{}' must be host:port, example: nat1.wherewego.top:29872",
+            address
+        )),
+    }
 }
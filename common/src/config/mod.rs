@@ -4,18 +4,62 @@ pub const PUB_STUN: [&'static str; 4] = [
     "stun.hitv.com",
     "stun.cdnbye.com",
 ];
+pub mod env;
 #[cfg(feature = "file_config")]
 mod file_config;
+#[cfg(feature = "file_config")]
+pub mod secret;
 
 use crate::identifier;
 #[cfg(feature = "file_config")]
-pub use file_config::read_config;
+pub use file_config::{parse_config, read_config, read_networks};
 
 #[cfg(not(feature = "file_config"))]
-pub fn read_config(_file_path: &str) -> anyhow::Result<(vnt::core::Config, Vec<String>, bool)> {
+pub fn read_config(
+    _file_path: &str,
+    _profile: Option<&str>,
+) -> anyhow::Result<(vnt::core::Config, Vec<String>, bool)> {
     unimplemented!()
 }
 
+#[cfg(not(feature = "file_config"))]
+pub fn read_networks(
+    _file_path: &str,
+    _profile: Option<&str>,
+) -> anyhow::Result<(Vec<(Option<String>, vnt::core::Config)>, Vec<String>, bool)> {
+    unimplemented!()
+}
+
+/// 重新读取启动时的配置文件和日志配置，应用可以在运行时修改的配置项，返回有变化的配置项
+pub fn reload(vnt: &vnt::core::Vnt) -> anyhow::Result<Vec<&'static str>> {
+    #[cfg(feature = "log")]
+    if let Err(e) = crate::logging::reload_log() {
+        log::warn!("reload log4rs.yaml {:?}", e);
+    }
+    let (path, profile) = match crate::cli::config_path() {
+        Some(path) => path,
+        None => return Err(anyhow::anyhow!("not started with a config file '-f'")),
+    };
+    let (mut networks, _, _) = read_networks(&path, profile.as_deref())?;
+    // 同时运行多个网络时按网卡名称找到对应的配置组
+    let device_name = vnt.config().device_name.clone();
+    let config = if networks.len() == 1 {
+        networks.pop().map(|(_, config)| config)
+    } else {
+        networks
+            .into_iter()
+            .map(|(_, config)| config)
+            .find(|config| config.device_name == device_name)
+    };
+    match config {
+        Some(config) => vnt.reload(&config),
+        None => Err(anyhow::anyhow!(
+            "network with device_name {:?} not found in the config file",
+            device_name
+        )),
+    }
+}
+
 pub fn get_device_id() -> String {
     if let Some(id) = identifier::get_unique_identifier() {
         id
@@ -36,3 +80,19 @@ pub fn get_device_id() -> String {
         }
     }
 }
+
+/// 通过邀请码加入时生成的身份私钥，保存在程序目录下，之后没有指定身份私钥时自动使用
+#[cfg(feature = "identity")]
+pub fn get_identity(create: bool) -> anyhow::Result<Option<String>> {
+    let path_buf = crate::cli::app_home()?.join("identity");
+    if let Ok(key) = std::fs::read_to_string(path_buf.as_path()) {
+        return Ok(Some(key.trim().to_string()));
+    }
+    if !create {
+        return Ok(None);
+    }
+    let (private_key, _) = vnt::identity::generate_key();
+    let key = vnt::wireguard::encode_key(&private_key);
+    std::fs::write(path_buf, &key)?;
+    Ok(Some(key))
+}
@@ -0,0 +1,305 @@
+#[cfg(feature = "config_encrypt")]
+use aes_gcm::aead::rand_core::RngCore;
+#[cfg(feature = "config_encrypt")]
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+#[cfg(feature = "config_encrypt")]
+use aes_gcm::{Aes256Gcm, Nonce};
+#[cfg(feature = "config_encrypt")]
+use anyhow::anyhow;
+#[cfg(feature = "config_encrypt")]
+use base64::Engine;
+
+// 加密后的字段值以此开头
+const ENCRYPTED_PREFIX: &str = "enc:";
+// 配置文件中需要加密的敏感字段
+const SECRET_FIELDS: [&str; 8] = [
+    "token",
+    "password",
+    "wg_key",
+    "peer_key",
+    "identity",
+    "invite",
+    "totp_secret",
+    "api_token",
+];
+
+/// 配置文件中是否有加密的字段，包括profiles中的字段
+pub fn is_encrypted(conf: &str) -> bool {
+    conf.lines().any(|line| match line.split_once(':') {
+        Some((key, value)) => {
+            SECRET_FIELDS.contains(&key.trim_start()) && value.trim().starts_with(ENCRYPTED_PREFIX)
+        }
+        None => false,
+    })
+}
+
+// 行首为敏感字段时返回字段名
+#[cfg(feature = "config_encrypt")]
+fn secret_field(line: &str) -> Option<&'static str> {
+    SECRET_FIELDS.into_iter().find(|field| {
+        line.strip_prefix(field)
+            .map_or(false, |v| v.starts_with(':'))
+    })
+}
+
+// 口令的环境变量，适合作为服务运行时使用
+#[cfg(feature = "config_encrypt")]
+const PASSPHRASE_ENV: &str = "VNT_CONFIG_PASSPHRASE";
+#[cfg(feature = "config_encrypt")]
+const KEYRING_SERVICE: &str = "vnt";
+#[cfg(feature = "config_encrypt")]
+const KEYRING_USER: &str = "config";
+#[cfg(feature = "config_encrypt")]
+const SALT_LEN: usize = 16;
+#[cfg(feature = "config_encrypt")]
+const NONCE_LEN: usize = 12;
+
+/// 依次从环境变量、系统密钥环和终端输入获取口令，confirm为true时终端输入需要确认一次
+#[cfg(feature = "config_encrypt")]
+pub fn passphrase(confirm: bool) -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        if !passphrase.is_empty() {
+            return Ok(passphrase);
+        }
+    }
+    match keyring_entry()?.get_password() {
+        Ok(passphrase) => return Ok(passphrase),
+        Err(keyring::Error::NoEntry) => {}
+        Err(e) => {
+            log::warn!("keyring {:?}", e);
+        }
+    }
+    prompt(confirm)
+}
+
+/// 从终端输入口令并保存到系统密钥环(Windows凭据管理器/macOS钥匙串/Secret Service)
+#[cfg(feature = "config_encrypt")]
+pub fn save_passphrase() -> anyhow::Result<()> {
+    let passphrase = prompt(true)?;
+    keyring_entry()?
+        .set_password(&passphrase)
+        .map_err(|e| anyhow!("keyring {}", e))
+}
+
+#[cfg(feature = "config_encrypt")]
+fn keyring_entry() -> anyhow::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).map_err(|e| anyhow!("keyring {}", e))
+}
+
+#[cfg(feature = "config_encrypt")]
+fn prompt(confirm: bool) -> anyhow::Result<String> {
+    let passphrase = rpassword::prompt_password("config passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(anyhow!("passphrase is empty"));
+    }
+    if confirm && rpassword::prompt_password("confirm passphrase: ")? != passphrase {
+        return Err(anyhow!("passphrase mismatch"));
+    }
+    Ok(passphrase)
+}
+
+/// 加密配置文件中的敏感字段，其他内容和注释保持不变
+#[cfg(feature = "config_encrypt")]
+pub fn encrypt_config(conf: &str, passphrase: &str) -> anyhow::Result<String> {
+    replace_fields(conf, |value| {
+        if value.is_empty() || value.starts_with(ENCRYPTED_PREFIX) {
+            return Ok(None);
+        }
+        Ok(Some(format!(
+            "{}{}",
+            ENCRYPTED_PREFIX,
+            encrypt(passphrase, value)?
+        )))
+    })
+}
+
+/// 解密配置文件中的敏感字段
+#[cfg(feature = "config_encrypt")]
+pub fn decrypt_config(conf: &str, passphrase: &str) -> anyhow::Result<String> {
+    replace_fields(conf, |value| match value.strip_prefix(ENCRYPTED_PREFIX) {
+        Some(data) => {
+            let value = decrypt(passphrase, data)?;
+            // 解密后的值可能包含yaml的特殊字符
+            Ok(Some(serde_yaml::to_string(&value)?.trim_end().to_string()))
+        }
+        None => Ok(None),
+    })
+}
+
+// 逐行替换顶层和profiles下各配置组中的敏感字段，只改动需要替换的行
+#[cfg(feature = "config_encrypt")]
+fn replace_fields<F>(conf: &str, mut f: F) -> anyhow::Result<String>
+where
+    F: FnMut(&str) -> anyhow::Result<Option<String>>,
+{
+    let mapping: serde_yaml::Mapping = serde_yaml::from_str(conf)?;
+    let mut out = String::with_capacity(conf.len() * 2);
+    // 是否在profiles下
+    let mut in_profiles = false;
+    // 当前配置组的名称、缩进和组内字段的缩进
+    let mut profile: Option<(&str, usize, Option<usize>)> = None;
+    for line in conf.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        let mut target = None;
+        if indent == 0 {
+            in_profiles = line.starts_with("profiles:");
+            profile = None;
+            target = secret_field(line).map(|field| (field, mapping.get(field)));
+        } else if in_profiles {
+            match &mut profile {
+                Some((name, profile_indent, field_indent)) if indent > *profile_indent => {
+                    if field_indent.is_none() {
+                        *field_indent = Some(indent);
+                    }
+                    if *field_indent == Some(indent) {
+                        target = secret_field(trimmed).map(|field| {
+                            let value = mapping
+                                .get("profiles")
+                                .and_then(|v| v.get(*name))
+                                .and_then(|v| v.get(field));
+                            (field, value)
+                        });
+                    }
+                }
+                _ => {
+                    profile = trimmed
+                        .split_once(':')
+                        .map(|(name, _)| (name.trim().trim_matches(['"', '\'']), indent, None));
+                }
+            }
+        }
+        let mut new_line = None;
+        if let Some((field, Some(value))) = target {
+            let value = match value {
+                serde_yaml::Value::String(value) => Some(value.as_str()),
+                serde_yaml::Value::Null => None,
+                _ => return Err(anyhow!("'{}' is not a string", field)),
+            };
+            if let Some(value) = value {
+                // 只能替换写在同一行的值，块标量(|、>)和跨行的值需要改成单行
+                let inline = line.split_once(':').map_or("", |(_, v)| v);
+                let inline = serde_yaml::from_str::<serde_yaml::Value>(inline).ok();
+                if inline.as_ref().and_then(|v| v.as_str()) != Some(value) {
+                    return Err(anyhow!("'{}' must be a single-line string", field));
+                }
+                if let Some(value) = f(value).map_err(|e| anyhow!("{} {}", field, e))? {
+                    new_line = Some(format!("{}{}: {}", &line[..indent], field, value));
+                }
+            }
+        }
+        out.push_str(new_line.as_deref().unwrap_or(line));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(feature = "config_encrypt")]
+fn cipher(passphrase: &str, salt: &[u8]) -> anyhow::Result<Aes256Gcm> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("argon2 {}", e))?;
+    Ok(Aes256Gcm::new(&key.into()))
+}
+
+// salt(16) | nonce(12) | 密文 | tag(16)，每个字段使用独立的salt
+#[cfg(feature = "config_encrypt")]
+fn encrypt(passphrase: &str, value: &str) -> anyhow::Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(&nonce, value.as_bytes())
+        .map_err(|e| anyhow!("encrypt {}", e))?;
+    let mut data = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    Ok(base64::engine::general_purpose::STANDARD.encode(data))
+}
+
+#[cfg(feature = "config_encrypt")]
+fn decrypt(passphrase: &str, data: &str) -> anyhow::Result<String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|e| anyhow!("decode {}", e))?;
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return Err(anyhow!("data len {}", data.len()));
+    }
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let plaintext = cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("wrong passphrase"))?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+#[cfg(feature = "config_encrypt")]
+#[test]
+fn test_encrypt_config() {
+    let conf = "# vnt\n\
+                server_address: tcp://vnt.example.com:29872\n\
+                token: top-token\n\
+                password: 'p: w#d'\n\
+                profiles:\n  \
+                  work:\n    \
+                    token: work-token\n    \
+                    ip: 10.26.0.5\n  \
+                  \"home\":\n    \
+                    # comment\n    \
+                    password: home-password\n";
+    let encrypted = encrypt_config(conf, "passphrase").unwrap();
+    assert!(is_encrypted(&encrypted));
+    for secret in ["top-token", "p: w#d", "work-token", "home-password"] {
+        assert!(!encrypted.contains(secret), "{}", encrypted);
+    }
+    assert!(encrypted.contains("ip: 10.26.0.5"));
+    assert_eq!(encrypted.lines().count(), conf.lines().count());
+    // 已加密的字段不会重复加密
+    assert_eq!(encrypt_config(&encrypted, "passphrase").unwrap(), encrypted);
+
+    let decrypted = decrypt_config(&encrypted, "passphrase").unwrap();
+    assert!(!is_encrypted(&decrypted));
+    let mapping: serde_yaml::Value = serde_yaml::from_str(&decrypted).unwrap();
+    assert_eq!(
+        mapping,
+        serde_yaml::from_str::<serde_yaml::Value>(conf).unwrap()
+    );
+    assert!(decrypted.contains("    token: work-token\n"));
+}
+
+#[cfg(feature = "config_encrypt")]
+#[test]
+fn test_decrypt_wrong_passphrase() {
+    let encrypted =
+        encrypt_config("token: abc\nprofiles:\n  a:\n    token: def\n", "right").unwrap();
+    assert!(decrypt_config(&encrypted, "wrong").is_err());
+    assert!(decrypt("right", "invalid base64").is_err());
+    assert!(decrypt("right", "AAAA").is_err());
+}
+
+#[cfg(feature = "config_encrypt")]
+#[test]
+fn test_encrypt_multiline() {
+    // 块标量和跨行的值不能逐行替换，不是字符串的值无法加密
+    for conf in [
+        "token: |\n  abc\n",
+        "token: >-\n  abc\n",
+        "token:\n  abc\n",
+        "profiles:\n  a:\n    password: |\n      abc\n",
+        "token: [abc]\n",
+    ] {
+        assert!(encrypt_config(conf, "passphrase").is_err(), "{}", conf);
+    }
+    // 空值跳过
+    assert_eq!(
+        encrypt_config("token:\nip: 10.26.0.5\n", "passphrase").unwrap(),
+        "token:\nip: 10.26.0.5\n"
+    );
+}
@@ -2,7 +2,7 @@ use console::{style, Style};
 use std::collections::HashSet;
 use std::net::Ipv4Addr;
 
-use crate::command::entity::{ChartA, ChartB, DeviceItem, Info, RouteItem};
+use crate::command::entity::{ChartA, ChartB, DeviceItem, Info, RouteItem, Stats, Status};
 
 pub mod table;
 
@@ -34,12 +34,8 @@ pub fn console_info(status: Info) {
     if !status.port_mapping_list.is_empty() {
         println!("------------------------------------------");
         println!("Port mapping {}", status.port_mapping_list.len());
-        for (is_tcp, addr, dest) in status.port_mapping_list {
-            if is_tcp {
-                println!("  TCP: {} -> {}", addr, dest)
-            } else {
-                println!("  UDP: {} -> {}", addr, dest)
-            }
+        for rule in status.port_mapping_list {
+            println!("  {}", rule)
         }
     }
     if !status.in_ips.is_empty() || !status.out_ips.is_empty() {
@@ -64,6 +60,47 @@ pub fn console_info(status: Info) {
     }
 }
 
+pub fn console_mapping_list(list: Vec<String>) {
+    println!("Port mapping {}", list.len());
+    for rule in list {
+        println!("  {}", style(rule).green())
+    }
+}
+
+pub fn console_status(status: Status) {
+    println!("Pid: {}", style(status.pid).green());
+    println!("Name: {}", style(status.name).green());
+    println!("Virtual ip: {}", style(status.virtual_ip).green());
+    if status.connect_status.eq_ignore_ascii_case("Connected") {
+        println!(
+            "Connection status: {}",
+            style(status.connect_status).green()
+        );
+    } else {
+        println!("Connection status: {}", style(status.connect_status).red());
+    }
+    let uptime = status.uptime;
+    println!(
+        "Uptime: {}",
+        style(format!(
+            "{}d {:02}:{:02}:{:02}",
+            uptime / 86400,
+            uptime % 86400 / 3600,
+            uptime % 3600 / 60,
+            uptime % 60
+        ))
+        .green()
+    );
+    println!(
+        "Peers: {}",
+        style(format!(
+            "{} online / {} total",
+            status.peer_online, status.peer_total
+        ))
+        .green()
+    );
+}
+
 fn convert(num: u64) -> String {
     let gigabytes = num / (1024 * 1024 * 1024);
     let remaining_bytes = num % (1024 * 1024 * 1024);
@@ -87,6 +124,121 @@ fn convert(num: u64) -> String {
     s
 }
 
+// 表格中使用的简短格式
+fn convert_short(num: u64) -> String {
+    if num >= 1024 * 1024 * 1024 {
+        format!("{:.1}G", num as f64 / (1024.0 * 1024.0 * 1024.0))
+    } else if num >= 1024 * 1024 {
+        format!("{:.1}M", num as f64 / (1024.0 * 1024.0))
+    } else if num >= 1024 {
+        format!("{:.1}K", num as f64 / 1024.0)
+    } else {
+        format!("{}B", num)
+    }
+}
+
+pub fn console_stats(stats: Stats) {
+    let drops: Vec<String> = stats
+        .drops
+        .iter()
+        .filter(|item| item.packets > 0)
+        .map(|item| format!("{}={}", item.reason, item.packets))
+        .collect();
+    if !drops.is_empty() {
+        println!("Drops: {}", drops.join(" "));
+    }
+    if stats.disable_stats {
+        println!("Traffic statistics not enabled");
+        return;
+    }
+    if stats.peers.is_empty() {
+        println!("No peer statistics");
+        return;
+    }
+    let mut out_list = Vec::with_capacity(stats.peers.len() + 1);
+    out_list.push(vec![
+        ("Name".to_string(), Style::new()),
+        ("Virtual Ip".to_string(), Style::new()),
+        ("Connect".to_string(), Style::new()),
+        ("Rt".to_string(), Style::new()),
+        ("Loss".to_string(), Style::new()),
+        ("Tx".to_string(), Style::new()),
+        ("Rx".to_string(), Style::new()),
+        ("Tx P2P/Relay".to_string(), Style::new()),
+        ("Rx P2P/Relay".to_string(), Style::new()),
+        ("Drops Tx/Rx".to_string(), Style::new()),
+    ]);
+    for item in stats.peers {
+        let rt = if item.rt < 0 {
+            String::new()
+        } else {
+            item.rt.to_string()
+        };
+        let style = if item.connect == "p2p" {
+            Style::new().green()
+        } else {
+            Style::new().yellow()
+        };
+        out_list.push(vec![
+            (item.name, style.clone()),
+            (item.virtual_ip, style.clone()),
+            (item.connect, style.clone()),
+            (rt, style.clone()),
+            (format!("{}%", item.loss), style.clone()),
+            (
+                format!("{}/{}", convert_short(item.tx_bytes), item.tx_packets),
+                style.clone(),
+            ),
+            (
+                format!("{}/{}", convert_short(item.rx_bytes), item.rx_packets),
+                style.clone(),
+            ),
+            (
+                format!("{}/{}", item.tx_direct, item.tx_relay),
+                style.clone(),
+            ),
+            (
+                format!("{}/{}", item.rx_direct, item.rx_relay),
+                style.clone(),
+            ),
+            (format!("{}/{}", item.tx_drops, item.rx_drops), style),
+        ]);
+    }
+    table::println_table(out_list);
+    if stats.flows.is_empty() {
+        return;
+    }
+    println!();
+    let mut out_list = Vec::with_capacity(stats.flows.len() + 1);
+    out_list.push(vec![
+        ("Peer".to_string(), Style::new()),
+        ("Protocol".to_string(), Style::new()),
+        ("Local".to_string(), Style::new()),
+        ("Remote".to_string(), Style::new()),
+        ("Tx".to_string(), Style::new()),
+        ("Rx".to_string(), Style::new()),
+        ("Idle".to_string(), Style::new()),
+    ]);
+    for item in stats.flows {
+        out_list.push(vec![
+            (item.peer, Style::new().green()),
+            (item.protocol, Style::new().green()),
+            (item.local, Style::new().green()),
+            (item.remote, Style::new().green()),
+            (
+                format!("{}/{}", convert_short(item.tx_bytes), item.tx_packets),
+                Style::new().green(),
+            ),
+            (
+                format!("{}/{}", convert_short(item.rx_bytes), item.rx_packets),
+                Style::new().green(),
+            ),
+            (format!("{}s", item.idle), Style::new().green()),
+        ]);
+    }
+    table::println_table(out_list)
+}
+
 pub fn console_route_table(mut list: Vec<RouteItem>) {
     if list.is_empty() {
         println!("No route found");
@@ -122,6 +274,8 @@ pub fn console_device_list(mut list: Vec<DeviceItem>) {
     }
     list.sort_by(|t1, t2| t1.virtual_ip.cmp(&t2.virtual_ip));
     list.sort_by(|t1, t2| t1.status.cmp(&t2.status));
+    // 有客户端开启身份认证时才显示指纹
+    let show_fingerprint = list.iter().any(|v| !v.fingerprint.is_empty());
     let mut out_list = Vec::with_capacity(list.len());
     //表头
     out_list.push(vec![
@@ -131,6 +285,9 @@ pub fn console_device_list(mut list: Vec<DeviceItem>) {
         ("P2P/Relay".to_string(), Style::new()),
         ("Rt".to_string(), Style::new()),
     ]);
+    if show_fingerprint {
+        out_list[0].push(("Fingerprint".to_string(), Style::new()));
+    }
     for item in list {
         let name = if item.wire_guard {
             format!("{}(wg)", item.name)
@@ -180,6 +337,12 @@ pub fn console_device_list(mut list: Vec<DeviceItem>) {
                 ("".to_string(), Style::new().color256(102)),
             ]);
         }
+        if show_fingerprint {
+            if let Some(row) = out_list.last_mut() {
+                let style = row[0].1.clone();
+                row.push((item.fingerprint, style));
+            }
+        }
     }
     table::println_table(out_list)
 }
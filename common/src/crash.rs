@@ -0,0 +1,153 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::{mpsc, Mutex, Once};
+use std::time::Duration;
+
+use log::Record;
+use log4rs::append::Append;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::Encode;
+use vnt::core::Vnt;
+
+/// 在内存中保留最近日志的appender，加在根日志上
+pub(crate) const APPENDER: &str = "crash_buffer";
+// 内存中保留的日志条数
+const CAPACITY: usize = 500;
+// 收集配置和对端列表的最长时间，panic的线程可能还持有锁
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(2);
+
+static RECORDS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static CRASH_VNT: Mutex<Option<Vnt>> = Mutex::new(None);
+static HOOK: Once = Once::new();
+
+#[derive(Debug)]
+pub(crate) struct RingAppender {
+    encoder: PatternEncoder,
+}
+
+impl RingAppender {
+    pub(crate) fn new() -> Self {
+        Self {
+            encoder: PatternEncoder::new("{d(%Y-%m-%d %H:%M:%S%.3f)} {l} [{T}] {M}:{L} {m}{n}"),
+        }
+    }
+}
+
+impl Append for RingAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let mut writer = SimpleWriter(Vec::new());
+        self.encoder.encode(&mut writer, record)?;
+        let line = String::from_utf8_lossy(&writer.0).into_owned();
+        let mut guard = RECORDS.lock().unwrap_or_else(|e| e.into_inner());
+        if guard.len() >= CAPACITY {
+            guard.pop_front();
+        }
+        guard.push_back(line);
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// panic时把最近的日志、配置和对端列表写到程序目录下的env/crash-<时间戳>.log，
+/// 多次调用时只更新使用的vnt
+pub fn set_panic_hook(vnt: Vnt) {
+    CRASH_VNT.lock().unwrap().replace(vnt);
+    HOOK.call_once(|| {
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+            match write_report(info) {
+                Ok(path) => eprintln!("crash report {:?}", path),
+                Err(e) => eprintln!("write crash report error {:?}", e),
+            }
+        }));
+    });
+}
+
+fn write_report(info: &dyn std::fmt::Display) -> std::io::Result<PathBuf> {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |v| v.as_secs());
+    let thread = std::thread::current();
+    let mut report = String::new();
+    let _ = writeln!(report, "time: {}", time);
+    let _ = writeln!(report, "version: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "thread: {}", thread.name().unwrap_or("<unnamed>"));
+    let _ = writeln!(report, "{}\n", info);
+    let _ = writeln!(
+        report,
+        "backtrace:\n{}",
+        std::backtrace::Backtrace::force_capture()
+    );
+    report.push_str(&snapshot());
+    report.push_str("\nrecent logs:\n");
+    // panic可能发生在写日志时，拿不到锁就不写
+    if let Ok(records) = RECORDS.try_lock() {
+        for line in records.iter() {
+            report.push_str(line);
+        }
+    }
+    let path = crate::cli::app_home()?.join(format!("crash-{}.log", time));
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
+
+// 在其他线程收集，panic的线程持有锁时不会卡住
+fn snapshot() -> String {
+    let vnt = match CRASH_VNT.try_lock() {
+        Ok(guard) => guard.clone(),
+        Err(_) => None,
+    };
+    let vnt = match vnt {
+        Some(vnt) => vnt,
+        None => return "no snapshot\n".to_string(),
+    };
+    let (sender, receiver) = mpsc::channel();
+    let rs = std::thread::Builder::new()
+        .name("crashSnapshot".into())
+        .spawn(move || {
+            let _ = sender.send(snapshot0(&vnt));
+        });
+    if let Err(e) = rs {
+        return format!("snapshot error {:?}\n", e);
+    }
+    receiver
+        .recv_timeout(SNAPSHOT_TIMEOUT)
+        .unwrap_or_else(|_| "snapshot timeout\n".to_string())
+}
+
+fn snapshot0(vnt: &Vnt) -> String {
+    let mut config = vnt.config().clone();
+    // 其他密钥的Debug不会输出内容
+    config.token = "***".to_string();
+    config.password = config.password.map(|_| "***".to_string());
+    config.api_token = config.api_token.map(|_| "***".to_string());
+    let current_device = vnt.current_device();
+    let mut s = String::new();
+    let _ = writeln!(s, "config:\n{:#?}\n", config);
+    let _ = writeln!(
+        s,
+        "status: {:?} virtual_ip: {} server: {} uptime: {}s\n",
+        vnt.connection_status(),
+        current_device.virtual_ip,
+        current_device.connect_server,
+        vnt.uptime().as_secs()
+    );
+    let routes: HashMap<_, _> = vnt.route_table().into_iter().collect();
+    let _ = writeln!(s, "peers:");
+    for peer in vnt.device_list() {
+        let _ = write!(s, "{} {} {:?}", peer.virtual_ip, peer.name, peer.status);
+        for route in routes.get(&peer.virtual_ip).into_iter().flatten() {
+            let _ = write!(
+                s,
+                " [{:?} {} metric:{} rt:{}]",
+                route.protocol, route.addr, route.metric, route.rt
+            );
+        }
+        s.push('\n');
+    }
+    s
+}
@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use vnt::ErrorType;
+
+// 进程退出码，和sysexits的取值一致，服务管理器和安装程序据此区分失败原因
+
+/// 其他错误
+pub const OTHER: i32 = 1;
+/// 无法连接服务端，只有开启看门狗时才会因此退出
+pub const SERVER_UNREACHABLE: i32 = 69;
+/// 创建虚拟网卡失败，如缺少wintun.dll、没有tun驱动
+pub const DEVICE_ERROR: i32 = 71;
+/// 看门狗无法恢复
+pub const WATCHDOG: i32 = 75;
+/// 虚拟ip冲突或者服务端无法分配地址
+pub const IP_CONFLICT: i32 = 76;
+/// 认证失败，token、身份、邀请码或totp错误，或者被服务端封禁
+pub const AUTH_FAILURE: i32 = 77;
+/// 参数或配置文件错误
+pub const CONFIG_ERROR: i32 = 78;
+
+// '--error-json'指定的文件
+static ERROR_JSON: Mutex<Option<PathBuf>> = Mutex::new(None);
+// 第一个致命错误的退出码
+static RECORDED: AtomicI32 = AtomicI32::new(0);
+
+#[derive(Serialize)]
+struct FatalError<'a> {
+    code: i32,
+    kind: &'a str,
+    message: &'a str,
+    time: u64,
+}
+
+/// 设置退出时写入致命错误的文件，上次运行留下的文件会被删除
+pub fn set_error_json(path: PathBuf) {
+    if path.exists() {
+        if let Err(e) = std::fs::remove_file(&path) {
+            log::warn!("remove {:?} {}", path, e);
+        }
+    }
+    ERROR_JSON.lock().unwrap().replace(path);
+}
+
+/// 需要退出的错误对应的退出码，其他错误返回None
+pub fn error_code(error_type: ErrorType) -> Option<i32> {
+    match error_type {
+        ErrorType::TokenError
+        | ErrorType::IdentityError
+        | ErrorType::InviteError
+        | ErrorType::TotpError
+        | ErrorType::Banned => Some(AUTH_FAILURE),
+        ErrorType::AddressExhausted
+        | ErrorType::IpAlreadyExists
+        | ErrorType::InvalidIp
+        | ErrorType::LocalIpExists => Some(IP_CONFLICT),
+        ErrorType::DeviceError => Some(DEVICE_ERROR),
+        ErrorType::ServerUnreachable => Some(SERVER_UNREACHABLE),
+        ErrorType::WatchdogExpired => Some(WATCHDOG),
+        ErrorType::Disconnect | ErrorType::Kicked | ErrorType::Unknown => None,
+    }
+}
+
+fn kind(code: i32) -> &'static str {
+    match code {
+        SERVER_UNREACHABLE => "server_unreachable",
+        DEVICE_ERROR => "device_error",
+        WATCHDOG => "watchdog",
+        IP_CONFLICT => "ip_conflict",
+        AUTH_FAILURE => "auth_failure",
+        CONFIG_ERROR => "config_error",
+        _ => "other",
+    }
+}
+
+/// 写入'--error-json'指定的文件后以code退出
+pub fn exit(code: i32, message: &str) -> ! {
+    record(code, message);
+    std::process::exit(code)
+}
+
+/// 记录致命错误，不退出进程，只保留第一个错误
+pub fn record(code: i32, message: &str) {
+    log::error!("exit {} {}", code, message);
+    if RECORDED
+        .compare_exchange(0, code, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+    {
+        write_error_json(code, message);
+    }
+}
+
+/// 记录的退出码，没有致命错误时返回0
+pub fn recorded() -> i32 {
+    RECORDED.load(Ordering::Acquire)
+}
+
+fn write_error_json(code: i32, message: &str) {
+    let path = match ERROR_JSON.lock().unwrap().clone() {
+        Some(path) => path,
+        None => return,
+    };
+    let error = FatalError {
+        code,
+        kind: kind(code),
+        message,
+        time: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |v| v.as_secs()),
+    };
+    let rs = serde_json::to_string_pretty(&error)
+        .map_err(|e| e.to_string())
+        .and_then(|json| std::fs::write(&path, json).map_err(|e| e.to_string()));
+    if let Err(e) = rs {
+        log::warn!("write {:?} {}", path, e);
+    }
+}
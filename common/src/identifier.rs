@@ -76,3 +76,24 @@ pub fn get_unique_identifier() -> Option<String> {
 
     None
 }
+
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+pub fn get_unique_identifier() -> Option<String> {
+    use std::process::Command;
+    #[cfg(target_os = "freebsd")]
+    let name = "kern.hostuuid";
+    #[cfg(target_os = "openbsd")]
+    let name = "hw.uuid";
+    let output = match Command::new("sysctl").args(&["-n", name]).output() {
+        Ok(output) => output,
+        Err(_) => {
+            return None;
+        }
+    };
+    let identifier = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if identifier.is_empty() {
+        None
+    } else {
+        Some(identifier)
+    }
+}
@@ -2,9 +2,14 @@ pub mod args_parse;
 #[cfg(feature = "command")]
 pub mod command;
 pub mod config;
+#[cfg(feature = "log")]
+pub mod crash;
+pub mod exit;
 #[cfg(feature = "command")]
 mod console_out;
 pub mod identifier;
+#[cfg(feature = "log")]
+pub mod logging;
 
 pub mod cli;
 mod generated_serial_number;
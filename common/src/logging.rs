@@ -0,0 +1,248 @@
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use log::LevelFilter;
+use log4rs::config::{Appender, Config, RawConfig, Root};
+use serde_yaml::{Mapping, Value};
+
+const CONFIG_FILE: &str = "log4rs.yaml";
+
+static LOG_HANDLE: Mutex<Option<log4rs::Handle>> = Mutex::new(None);
+// 运行中修改的日志级别，模块为空表示根日志，重新读取log4rs.yaml后仍然生效
+static LOG_LEVELS: Mutex<Vec<(String, LevelFilter)>> = Mutex::new(Vec::new());
+// 所有appender都输出json行
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
+// 写文件的appender的切分条件和保留的文件数
+static LOG_ROTATE: Mutex<Option<(LogRotate, u32)>> = Mutex::new(None);
+
+/// 日志文件的切分条件
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogRotate {
+    /// 文件超过这个字节数
+    Size(u64),
+    /// 时间间隔，例如 `1 day`
+    Time(String),
+}
+
+impl FromStr for LogRotate {
+    type Err = String;
+
+    /// 大小 `10mb`、`512kb`，或者时间 `1hour`、`1day`、`1week`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let index = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let num = match s[..index].parse::<u64>() {
+            Ok(num) if num > 0 => num,
+            _ => return Err(format!("invalid number '{}'", s)),
+        };
+        let unit = s[index..].trim();
+        let size = match unit {
+            "" | "b" => 1,
+            "k" | "kb" => 1 << 10,
+            "m" | "mb" => 1 << 20,
+            "g" | "gb" => 1 << 30,
+            _ => {
+                let unit = unit.trim_end_matches('s');
+                return match unit {
+                    "minute" | "hour" | "day" | "week" | "month" => {
+                        Ok(LogRotate::Time(format!("{} {}", num, unit)))
+                    }
+                    _ => Err(format!("invalid unit '{}', example: 10mb,1day", s)),
+                };
+            }
+        };
+        Ok(LogRotate::Size(num * size))
+    }
+}
+
+/// 重新读取log4rs.yaml，再应用运行中修改的级别和json格式
+pub fn reload_log() -> anyhow::Result<()> {
+    let config = load_config()?;
+    let mut guard = LOG_HANDLE.lock().unwrap();
+    match guard.as_ref() {
+        Some(handle) => handle.set_config(config),
+        None => *guard = Some(log4rs::init_config(config)?),
+    }
+    Ok(())
+}
+
+/// 所有appender改用json格式，每行一条记录，包含时间、级别、模块、线程和消息
+pub fn set_log_json(json: bool) -> anyhow::Result<()> {
+    if LOG_JSON.swap(json, Ordering::Relaxed) == json {
+        return Ok(());
+    }
+    reload_log()
+}
+
+/// 写文件的appender按大小或时间切分，切分出的文件用gzip压缩，最多保留keep个
+pub fn set_log_rotate(rotate: Option<LogRotate>, keep: u32) -> anyhow::Result<()> {
+    let rotate = rotate.map(|rotate| (rotate, keep.max(1)));
+    {
+        let mut guard = LOG_ROTATE.lock().unwrap();
+        if *guard == rotate {
+            return Ok(());
+        }
+        *guard = rotate;
+    }
+    reload_log()
+}
+
+/// 运行中修改日志级别，格式 `[模块=]级别`，多个用逗号分隔，例如 `info,channel=debug`。
+/// 省略模块时修改根日志级别，模块名不以vnt、common、vnt_cli开头时是vnt中的模块，
+/// `reset` 恢复log4rs.yaml中的级别
+pub fn set_log_levels(spec: &str) -> anyhow::Result<()> {
+    let levels = parse_levels(spec)?;
+    {
+        let mut guard = LOG_LEVELS.lock().unwrap();
+        match levels {
+            Some(levels) => {
+                for (module, level) in levels {
+                    guard.retain(|(v, _)| v != &module);
+                    guard.push((module, level));
+                }
+            }
+            None => guard.clear(),
+        }
+    }
+    reload_log()
+}
+
+/// 运行中修改过的日志级别
+pub fn log_levels() -> Vec<(String, LevelFilter)> {
+    LOG_LEVELS.lock().unwrap().clone()
+}
+
+/// 返回None表示恢复配置文件中的级别
+fn parse_levels(spec: &str) -> anyhow::Result<Option<Vec<(String, LevelFilter)>>> {
+    if spec.trim() == "reset" {
+        return Ok(None);
+    }
+    let mut levels = Vec::new();
+    for item in spec.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let (module, level) = match item.split_once('=') {
+            Some((module, level)) => (module_path(module.trim()), level.trim()),
+            None => (String::new(), item),
+        };
+        let level = level
+            .parse::<LevelFilter>()
+            .map_err(|_| anyhow!("invalid level '{}'", item))?;
+        levels.push((module, level));
+    }
+    if levels.is_empty() {
+        return Err(anyhow!("missing level, example: info,channel=debug"));
+    }
+    Ok(Some(levels))
+}
+
+fn module_path(module: &str) -> String {
+    match module.split("::").next() {
+        Some("vnt") | Some("common") | Some("vnt_cli") => module.to_string(),
+        _ => format!("vnt::{}", module),
+    }
+}
+
+fn load_config() -> anyhow::Result<Config> {
+    let mut value: Value = match std::fs::read_to_string(CONFIG_FILE) {
+        Ok(conf) => serde_yaml::from_str(&conf)?,
+        // 没有配置文件时不输出日志，只在内存中保留最近的日志用于崩溃报告
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            serde_yaml::from_str("root:\n  level: info")?
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let root = value
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("invalid {}", CONFIG_FILE))?;
+    if LOG_JSON.load(Ordering::Relaxed) {
+        for appender in child(root, "appenders").values_mut() {
+            if let Some(appender) = appender.as_mapping_mut() {
+                let mut encoder = Mapping::new();
+                encoder.insert("kind".into(), "json".into());
+                appender.insert("encoder".into(), Value::Mapping(encoder));
+            }
+        }
+    }
+    if let Some((rotate, keep)) = LOG_ROTATE.lock().unwrap().as_ref() {
+        for appender in child(root, "appenders").values_mut() {
+            if let Some(appender) = appender.as_mapping_mut() {
+                rolling(appender, rotate, *keep);
+            }
+        }
+    }
+    for (module, level) in LOG_LEVELS.lock().unwrap().iter() {
+        let logger = if module.is_empty() {
+            child(root, "root")
+        } else {
+            child(child(root, "loggers"), module)
+        };
+        logger.insert("level".into(), level.to_string().into());
+    }
+    let raw: RawConfig = serde_yaml::from_value(value)?;
+    let (appenders, errors) = raw.appenders_lossy(&Default::default());
+    if !errors.is_empty() {
+        return Err(anyhow!("{}", errors));
+    }
+    // 根日志再输出到内存，panic时写入崩溃报告
+    let root = raw.root();
+    let root = Root::builder()
+        .appenders(root.appenders().iter().cloned())
+        .appender(crate::crash::APPENDER)
+        .build(root.level());
+    let config = Config::builder()
+        .appenders(appenders)
+        .appender(Appender::builder().build(
+            crate::crash::APPENDER,
+            Box::new(crate::crash::RingAppender::new()),
+        ))
+        .loggers(raw.loggers())
+        .build(root)?;
+    Ok(config)
+}
+
+// 写文件的appender改为rolling_file，切分出的文件依次为 路径.1.gz 到 路径.keep.gz，
+// 已经是rolling_file的保持配置文件中的设置
+fn rolling(appender: &mut Mapping, rotate: &LogRotate, keep: u32) {
+    if appender.get("kind").and_then(|v| v.as_str()) != Some("file") {
+        return;
+    }
+    let path = match appender.get("path").and_then(|v| v.as_str()) {
+        Some(path) => path.to_string(),
+        None => return,
+    };
+    let mut trigger = Mapping::new();
+    match rotate {
+        LogRotate::Size(limit) => {
+            trigger.insert("kind".into(), "size".into());
+            trigger.insert("limit".into(), (*limit).into());
+        }
+        LogRotate::Time(interval) => {
+            trigger.insert("kind".into(), "time".into());
+            trigger.insert("interval".into(), interval.as_str().into());
+        }
+    }
+    let mut roller = Mapping::new();
+    roller.insert("kind".into(), "fixed_window".into());
+    roller.insert("pattern".into(), format!("{}.{{}}.gz", path).into());
+    roller.insert("base".into(), 1.into());
+    roller.insert("count".into(), keep.into());
+    let mut policy = Mapping::new();
+    policy.insert("kind".into(), "compound".into());
+    policy.insert("trigger".into(), Value::Mapping(trigger));
+    policy.insert("roller".into(), Value::Mapping(roller));
+    appender.insert("kind".into(), "rolling_file".into());
+    appender.insert("policy".into(), Value::Mapping(policy));
+}
+
+// key对应的mapping，没有或者不是mapping时新建
+fn child<'a>(mapping: &'a mut Mapping, key: &str) -> &'a mut Mapping {
+    let value = mapping
+        .entry(key.into())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if !value.is_mapping() {
+        *value = Value::Mapping(Mapping::new());
+    }
+    value.as_mapping_mut().unwrap()
+}
@@ -1,9 +1,9 @@
 use common::callback;
-use vn_link::config::VnLinkConfig;
+use vn_link::config::{LinkProtocol, VnLinkConfig};
 use vn_link::vnt::core::Config;
 
 fn main() {
-    let (config, vnt_link_config, cmd) = match common::cli::parse_args_config() {
+    let (mut networks, vnt_link_config, cmd) = match common::cli::parse_args_config() {
         Ok(rs) => {
             if let Some(rs) = rs {
                 rs
@@ -18,9 +18,15 @@ fn main() {
                 std::env::args().collect::<Vec<String>>()
             );
             println!("{}", e);
-            return;
+            common::exit::exit(common::exit::CONFIG_ERROR, &e.to_string());
         }
     };
+    // 只支持运行一个网络
+    if networks.len() != 1 {
+        println!("vn-link-cli does not support running several profiles");
+        common::exit::exit(common::exit::CONFIG_ERROR, "several profiles");
+    }
+    let (_, config) = networks.remove(0);
     let vnt_link_config = VnLinkConfig::new(vn_link::config::convert(vnt_link_config).unwrap());
     main0(config, vnt_link_config, cmd)
 }
@@ -28,29 +34,29 @@ fn main() {
 #[tokio::main]
 async fn main0(config: Config, vn_link_config: VnLinkConfig, _show_cmd: bool) {
     #[cfg(feature = "port_mapping")]
-    for (is_tcp, addr, dest) in config.port_mapping_list.iter() {
-        if *is_tcp {
-            println!("TCP port mapping {}->{}", addr, dest)
-        } else {
-            println!("UDP port mapping {}->{}", addr, dest)
-        }
+    for rule in config.port_mapping_list.iter() {
+        println!("port mapping {}", rule)
     }
     for x in &vn_link_config.mapping {
-        if x.protocol.is_tcp() {
-            println!("TCP vnt addr mapping 127.0.0.1:{}->{}", x.src_port, x.dest)
-        } else {
-            println!("UDP vnt addr mapping 127.0.0.1:{}->{}", x.src_port, x.dest)
+        match x.protocol {
+            LinkProtocol::Tcp => {
+                println!("TCP vnt addr mapping {}->{}", x.bind, x.dest)
+            }
+            LinkProtocol::Udp => {
+                println!("UDP vnt addr mapping {}->{}", x.bind, x.dest)
+            }
+            LinkProtocol::Proxy => println!("SOCKS5/HTTP proxy {}", x.bind),
         }
     }
 
-    let vnt_util = match vn_link::VnLink::new(config, vn_link_config, callback::VntHandler {}).await
-    {
-        Ok(vnt) => vnt,
-        Err(e) => {
-            println!("error: {:?}", e);
-            std::process::exit(1);
-        }
-    };
+    let vnt_util =
+        match vn_link::VnLink::new(config, vn_link_config, callback::VntHandler::default()).await {
+            Ok(vnt) => vnt,
+            Err(e) => {
+                println!("error: {:?}", e);
+                std::process::exit(1);
+            }
+        };
 
     #[cfg(feature = "command")]
     {
@@ -17,6 +17,8 @@ impl VnLinkConfig {
 pub enum LinkProtocol {
     Tcp,
     Udp,
+    // 本地的socks5/http代理，目标地址由客户端指定
+    Proxy,
 }
 
 impl LinkProtocol {
@@ -28,7 +30,8 @@ impl LinkProtocol {
 #[derive(Copy, Clone, Debug)]
 pub struct LinkItem {
     pub protocol: LinkProtocol,
-    pub src_port: u16,
+    // 本地监听的地址，只指定端口时为127.0.0.1
+    pub bind: SocketAddr,
     pub dest: SocketAddr,
 }
 
@@ -36,7 +39,14 @@ impl LinkItem {
     pub fn new(protocol: LinkProtocol, src_port: u16, dest: SocketAddr) -> Self {
         Self {
             protocol,
-            src_port,
+            bind: SocketAddr::from(([127, 0, 0, 1], src_port)),
+            dest,
+        }
+    }
+    pub fn new_bind(protocol: LinkProtocol, bind: SocketAddr, dest: SocketAddr) -> Self {
+        Self {
+            protocol,
+            bind,
             dest,
         }
     }
@@ -46,6 +56,12 @@ pub fn convert(vec: Vec<String>) -> anyhow::Result<Vec<LinkItem>> {
     let mut rs = Vec::with_capacity(vec.len());
     for x in vec {
         let string = x.trim().to_lowercase();
+        if string.contains("->") {
+            rs.push(forward(&string).with_context(|| {
+                format!("forward error {:?},eg: 127.0.0.1:2222->10.26.0.5:22", x)
+            })?);
+            continue;
+        }
         if let Some(udp_mapping) = string.strip_prefix("udp:") {
             let mut split = udp_mapping.split("-");
             let bind_port = split
@@ -76,6 +92,16 @@ pub fn convert(vec: Vec<String>) -> anyhow::Result<Vec<LinkItem>> {
             rs.push(LinkItem::new(LinkProtocol::Tcp, bind_port, dest_addr));
             continue;
         }
+        if let Some(proxy_port) = string.strip_prefix("proxy:") {
+            let bind_port = u16::from_str(proxy_port)
+                .with_context(|| format!("proxy_mapping error {}", proxy_port))?;
+            rs.push(LinkItem::new(
+                LinkProtocol::Proxy,
+                bind_port,
+                SocketAddr::from(([0, 0, 0, 0], 0)),
+            ));
+            continue;
+        }
         Err(anyhow::anyhow!(
             "vnt-mapping error {:?},eg: tcp:80-10.26.0.10:8080",
             x
@@ -83,3 +109,20 @@ pub fn convert(vec: Vec<String>) -> anyhow::Result<Vec<LinkItem>> {
     }
     Ok(rs)
 }
+
+/// [tcp:|udp:]127.0.0.1:2222->10.26.0.5:22，不指定协议时为tcp
+fn forward(string: &str) -> anyhow::Result<LinkItem> {
+    let (protocol, string) = if let Some(v) = string.strip_prefix("udp:") {
+        (LinkProtocol::Udp, v)
+    } else if let Some(v) = string.strip_prefix("tcp:") {
+        (LinkProtocol::Tcp, v)
+    } else {
+        (LinkProtocol::Tcp, string)
+    };
+    let (bind, dest) = string
+        .split_once("->")
+        .with_context(|| format!("{:?}", string))?;
+    let bind = SocketAddr::from_str(bind.trim()).with_context(|| format!("bind {}", bind))?;
+    let dest = SocketAddr::from_str(dest.trim()).with_context(|| format!("dest {}", dest))?;
+    Ok(LinkItem::new_bind(protocol, bind, dest))
+}
@@ -1,2 +1,3 @@
+pub mod proxy;
 pub mod tcp;
 pub mod udp;
@@ -0,0 +1,246 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use lwip_rs::tcp_stream::TcpStream as LwIpTcpStream;
+use vnt::core::Vnt;
+
+// 请求头的最大长度
+const MAX_HEAD_LEN: usize = 8192;
+const SOCKS5_VERSION: u8 = 5;
+
+/// 本地的socks5和http代理，同一个端口根据第一个字节区分协议，
+/// 目标地址可以是虚拟ip、点对网的子网地址或者对端设备名称
+pub async fn proxy_listen(tcp_listener: TcpListener, vnt: Vnt) {
+    loop {
+        let (stream, addr) = match tcp_listener.accept().await {
+            Ok((stream, addr)) => (stream, addr),
+            Err(e) => {
+                log::warn!("proxy_listen {:?}", e);
+                continue;
+            }
+        };
+        let vnt = vnt.clone();
+        tokio::spawn(async move {
+            if let Err(e) = proxy_handle(stream, addr, vnt).await {
+                log::warn!("proxy {} {:?}", addr, e);
+            }
+        });
+    }
+}
+
+async fn proxy_handle(stream: TcpStream, addr: SocketAddr, vnt: Vnt) -> anyhow::Result<()> {
+    let mut first = [0u8; 1];
+    if stream.peek(&mut first).await? == 0 {
+        return Ok(());
+    }
+    if first[0] == SOCKS5_VERSION {
+        socks5_handle(stream, addr, vnt).await
+    } else {
+        http_handle(stream, addr, vnt).await
+    }
+}
+
+async fn socks5_handle(mut stream: TcpStream, addr: SocketAddr, vnt: Vnt) -> anyhow::Result<()> {
+    // VER NMETHODS METHODS，只支持无认证
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).await?;
+    let mut methods = vec![0u8; head[1] as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&0) {
+        stream.write_all(&[SOCKS5_VERSION, 0xff]).await?;
+        return Err(anyhow!("socks5 no acceptable methods"));
+    }
+    stream.write_all(&[SOCKS5_VERSION, 0]).await?;
+    // VER CMD RSV ATYP DST.ADDR DST.PORT
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    if request[1] != 1 {
+        // 只支持CONNECT
+        socks5_reply(&mut stream, 7, None).await?;
+        return Err(anyhow!("socks5 unsupported command {}", request[1]));
+    }
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        3 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            let mut domain = vec![0u8; len[0] as usize];
+            stream.read_exact(&mut domain).await?;
+            String::from_utf8(domain)?
+        }
+        atyp => {
+            socks5_reply(&mut stream, 8, None).await?;
+            return Err(anyhow!("socks5 unsupported address type {}", atyp));
+        }
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port).await?;
+    let port = u16::from_be_bytes(port);
+    let dest = match resolve(&vnt, &host) {
+        Some(ip) => SocketAddr::new(IpAddr::V4(ip), port),
+        None => {
+            socks5_reply(&mut stream, 4, None).await?;
+            return Err(anyhow!("socks5 host unreachable {}", host));
+        }
+    };
+    match connect(&vnt, addr, dest).await {
+        Ok(lw_tcp) => {
+            socks5_reply(&mut stream, 0, Some(lw_tcp.src_addr())).await?;
+            crate::out_mapping::tcp::tcp_copy(lw_tcp, stream);
+            Ok(())
+        }
+        Err(e) => {
+            socks5_reply(&mut stream, 5, None).await?;
+            Err(e)
+        }
+    }
+}
+
+async fn socks5_reply(
+    stream: &mut TcpStream,
+    rep: u8,
+    bind: Option<SocketAddr>,
+) -> anyhow::Result<()> {
+    let (ip, port) = match bind {
+        Some(SocketAddr::V4(bind)) => (bind.ip().octets(), bind.port()),
+        _ => ([0; 4], 0),
+    };
+    let mut reply = vec![SOCKS5_VERSION, rep, 0, 1];
+    reply.extend_from_slice(&ip);
+    reply.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&reply).await?;
+    Ok(())
+}
+
+async fn http_handle(mut stream: TcpStream, addr: SocketAddr, vnt: Vnt) -> anyhow::Result<()> {
+    let mut buf = Vec::with_capacity(1024);
+    let head_len = loop {
+        if let Some(pos) = buf.windows(4).position(|v| v == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buf.len() >= MAX_HEAD_LEN {
+            return Err(anyhow!("http head too long"));
+        }
+        let mut tmp = [0u8; 1024];
+        let len = stream.read(&mut tmp).await?;
+        if len == 0 {
+            return Err(anyhow!("http head incomplete"));
+        }
+        buf.extend_from_slice(&tmp[..len]);
+    };
+    let head = std::str::from_utf8(&buf[..head_len])?;
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut split = request_line.split_whitespace();
+    let (method, target, version) = match (split.next(), split.next(), split.next()) {
+        (Some(method), Some(target), Some(version)) => (method, target, version),
+        _ => {
+            stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+                .await?;
+            return Err(anyhow!("http bad request {:?}", request_line));
+        }
+    };
+    let (authority, path, default_port) = if method.eq_ignore_ascii_case("CONNECT") {
+        (target, None, 443)
+    } else if let Some(target) = target.strip_prefix("http://") {
+        match target.find('/') {
+            Some(pos) => (&target[..pos], Some(&target[pos..]), 80),
+            None => (target, Some("/"), 80),
+        }
+    } else {
+        stream
+            .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+            .await?;
+        return Err(anyhow!("http unsupported target {:?}", target));
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, u16::from_str(port)?),
+        None => (authority, default_port),
+    };
+    let dest = match resolve(&vnt, host) {
+        Some(ip) => SocketAddr::new(IpAddr::V4(ip), port),
+        None => {
+            stream
+                .write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n")
+                .await?;
+            return Err(anyhow!("http host unreachable {}", host));
+        }
+    };
+    let lw_tcp = match connect(&vnt, addr, dest).await {
+        Ok(lw_tcp) => lw_tcp,
+        Err(e) => {
+            stream
+                .write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n")
+                .await?;
+            return Err(e);
+        }
+    };
+    let mut head_data = Vec::new();
+    match path {
+        None => {
+            stream
+                .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                .await?;
+            // CONNECT之后已经读到的数据属于隧道
+            head_data.extend_from_slice(&buf[head_len..]);
+        }
+        Some(path) => {
+            // 改成普通请求的格式再转发，去掉代理相关的头
+            head_data.extend_from_slice(format!("{} {} {}\r\n", method, path, version).as_bytes());
+            for line in head.split("\r\n").skip(1) {
+                if line.is_empty() {
+                    break;
+                }
+                if line.to_ascii_lowercase().starts_with("proxy-connection:") {
+                    continue;
+                }
+                head_data.extend_from_slice(line.as_bytes());
+                head_data.extend_from_slice(b"\r\n");
+            }
+            head_data.extend_from_slice(b"\r\n");
+            head_data.extend_from_slice(&buf[head_len..]);
+        }
+    }
+    let (mut write, mut read) = lw_tcp.into_split();
+    let (mut peer_read, mut peer_write) = stream.into_split();
+    write.write_all(&head_data).await?;
+    tokio::spawn(async move { tokio::io::copy(&mut read, &mut peer_write).await });
+    tokio::spawn(async move { tokio::io::copy(&mut peer_read, &mut write).await });
+    Ok(())
+}
+
+async fn connect(vnt: &Vnt, addr: SocketAddr, dest: SocketAddr) -> anyhow::Result<LwIpTcpStream> {
+    let virtual_ip = vnt.current_device().virtual_ip;
+    if virtual_ip.is_unspecified() {
+        return Err(anyhow!("not registered"));
+    }
+    if dest.ip() == IpAddr::V4(virtual_ip) {
+        return Err(anyhow!("目的地址不能是本地虚拟ip {}", dest));
+    }
+    let src = SocketAddr::new(IpAddr::V4(virtual_ip), addr.port());
+    LwIpTcpStream::connect(src, dest, Duration::from_secs(5))
+        .await
+        .map_err(|e| anyhow!("{}->{} {}", src, dest, e))
+}
+
+// ip直接使用，其他的按对端设备名称查找，可以带上.vnt后缀
+fn resolve(vnt: &Vnt, host: &str) -> Option<Ipv4Addr> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = Ipv4Addr::from_str(host) {
+        return Some(ip);
+    }
+    let name = host.strip_suffix(".vnt").unwrap_or(host);
+    vnt.device_list()
+        .into_iter()
+        .find(|v| v.name.eq_ignore_ascii_case(name))
+        .map(|v| v.virtual_ip)
+}
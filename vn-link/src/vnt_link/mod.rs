@@ -140,20 +140,31 @@ impl VnLink {
     pub async fn add_mapping(&self, mapping: Vec<LinkItem>) -> anyhow::Result<()> {
         for item in mapping {
             let current_device_info = self.vnt.current_device_info();
-            if item.dest.ip().is_unspecified() {
+            if item.protocol != LinkProtocol::Proxy && item.dest.ip().is_unspecified() {
                 Err(anyhow::anyhow!("dest_address {:?} is_unspecified", item))?
             }
             let mut shutdown_rx_ = self.shutdown_tx.subscribe();
             if *shutdown_rx_.borrow() {
                 Err(anyhow::anyhow!("mapping stop"))?
             }
-            if item.protocol == LinkProtocol::Udp {
+            if item.protocol == LinkProtocol::Proxy {
+                let listener = TcpListener::bind(item.bind)
+                    .await
+                    .with_context(|| format!("proxy bind failed {}", item.bind))?;
+                let vnt = self.vnt.clone();
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = shutdown_rx_.changed() => {}
+                        _ = in_mapping::proxy::proxy_listen(listener, vnt) => {}
+                    }
+                });
+            } else if item.protocol == LinkProtocol::Udp {
                 let lwip_udp_write = self.lwip_udp_write.clone();
                 let in_udp_map = self.in_udp_map.clone();
-                //只能本机访问，不然不同IP的相同来源端口会有问题
-                let udp = UdpSocket::bind(format!("127.0.0.1:{}", item.src_port))
+                // 默认只能本机访问，监听其他地址时不同IP的相同来源端口会共用映射
+                let udp = UdpSocket::bind(item.bind)
                     .await
-                    .with_context(|| format!("udp bind failed {}", item.src_port))?;
+                    .with_context(|| format!("udp bind failed {}", item.bind))?;
                 tokio::spawn(async move {
                     tokio::select! {
                         _ = shutdown_rx_.changed() => {}
@@ -167,9 +178,9 @@ impl VnLink {
                     }
                 });
             } else {
-                let listener = TcpListener::bind(format!("127.0.0.1:{}", item.src_port))
+                let listener = TcpListener::bind(item.bind)
                     .await
-                    .with_context(|| format!("tcp bind failed {}", item.src_port))?;
+                    .with_context(|| format!("tcp bind failed {}", item.bind))?;
                 tokio::spawn(async move {
                     tokio::select! {
                         _ = shutdown_rx_.changed() => {}
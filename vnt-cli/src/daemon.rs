@@ -0,0 +1,165 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+
+// 等待守护进程退出的最长时间
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+// 启动后观察子进程的时间，这段时间内退出说明启动失败
+const START_CHECK: Duration = Duration::from_secs(1);
+
+/// start/stop/restart/status子命令，管理后台运行的守护进程
+pub fn command(cmd: &str, args: Vec<String>) -> anyhow::Result<()> {
+    let mut args = args;
+    let pid_file = match crate::take_opt(&mut args, "pid-file")? {
+        Some(path) => PathBuf::from(path),
+        None => common::cli::app_home()?.join("vnt-cli.pid"),
+    };
+    let log_file = match crate::take_opt(&mut args, "log-file")? {
+        Some(path) => PathBuf::from(path),
+        None => common::cli::app_home()?.join("vnt-cli.out"),
+    };
+    match cmd {
+        "start" => {
+            crate::elevate();
+            start(&pid_file, &log_file, args)
+        }
+        "stop" => {
+            crate::elevate();
+            stop(&pid_file)
+        }
+        "restart" => {
+            crate::elevate();
+            stop(&pid_file)?;
+            start(&pid_file, &log_file, args)
+        }
+        "status" => status(&pid_file),
+        _ => Err(anyhow!("unknown command {}", cmd)),
+    }
+}
+
+fn start(pid_file: &Path, log_file: &Path, args: Vec<String>) -> anyhow::Result<()> {
+    let lock_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(pid_file)
+        .map_err(|e| anyhow!("open {:?} {}", pid_file, e))?;
+    if !try_lock(&lock_file, libc::LOCK_EX)? {
+        let pid = std::fs::read_to_string(pid_file).unwrap_or_default();
+        return Err(anyhow!("already running, pid {}", pid.trim()));
+    }
+    let lock_fd = lock_file.as_raw_fd();
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .map_err(|e| anyhow!("open {:?} {}", log_file, e))?;
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    unsafe {
+        // 脱离当前终端和会话，关闭终端后继续运行
+        command.pre_exec(move || {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // 子进程继承pid文件的锁，退出时由系统释放
+            if libc::fcntl(lock_fd, libc::F_SETFD, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    let pid = child.id();
+    lock_file
+        .set_len(0)
+        .and_then(|_| (&lock_file).write_all(pid.to_string().as_bytes()))
+        .map_err(|e| anyhow!("write {:?} {}", pid_file, e))?;
+    drop(lock_file);
+    std::thread::sleep(START_CHECK);
+    if let Some(exit) = child.try_wait()? {
+        let _ = std::fs::remove_file(pid_file);
+        return Err(anyhow!("exited {}, see {:?}", exit, log_file));
+    }
+    println!("started, pid {}", pid);
+    Ok(())
+}
+
+fn stop(pid_file: &Path) -> anyhow::Result<()> {
+    let pid = match running_pid(pid_file)? {
+        Some(pid) => pid,
+        None => {
+            let _ = std::fs::remove_file(pid_file);
+            println!("not running");
+            return Ok(());
+        }
+    };
+    if unsafe { libc::kill(pid, libc::SIGTERM) } == -1 {
+        return Err(anyhow!("kill {} {}", pid, std::io::Error::last_os_error()));
+    }
+    let start = Instant::now();
+    while running_pid(pid_file)?.is_some() {
+        if start.elapsed() > STOP_TIMEOUT {
+            return Err(anyhow!("pid {} did not stop in {:?}", pid, STOP_TIMEOUT));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let _ = std::fs::remove_file(pid_file);
+    println!("stopped, pid {}", pid);
+    Ok(())
+}
+
+fn status(pid_file: &Path) -> anyhow::Result<()> {
+    let pid = match running_pid(pid_file)? {
+        Some(pid) => pid,
+        None => {
+            println!("not running");
+            // 和LSB init脚本的约定一致，没有运行时返回3
+            std::process::exit(3);
+        }
+    };
+    println!("running, pid {}", pid);
+    #[cfg(feature = "command")]
+    common::command::command(common::command::CommandEnum::Status);
+    Ok(())
+}
+
+// 守护进程运行期间持有pid文件的锁，能加锁说明没有运行，不依赖可能被复用的pid
+fn running_pid(pid_file: &Path) -> anyhow::Result<Option<i32>> {
+    let file = match File::open(pid_file) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(anyhow!("open {:?} {}", pid_file, e)),
+    };
+    if try_lock(&file, libc::LOCK_SH)? {
+        return Ok(None);
+    }
+    let pid = std::fs::read_to_string(pid_file).unwrap_or_default();
+    match pid.trim().parse::<i32>() {
+        Ok(pid) if pid > 0 => Ok(Some(pid)),
+        _ => Err(anyhow!("{:?} is locked, pid {:?} invalid", pid_file, pid)),
+    }
+}
+
+// 非阻塞加锁，被其他进程锁住时返回false，文件关闭时释放
+fn try_lock(file: &File, operation: libc::c_int) -> anyhow::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        return Ok(false);
+    }
+    Err(anyhow!("flock {}", e))
+}
@@ -1,8 +1,64 @@
 use common::callback;
 use vnt::core::{Config, Vnt};
+#[cfg(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
+mod daemon;
 mod root_check;
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod service;
+#[cfg(feature = "file_config")]
+mod setup;
 fn main() {
-    let (config, _vnt_link_config, cmd) = match common::cli::parse_args_config() {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|v| v.as_str()) {
+        Some(cmd @ ("start" | "stop" | "restart" | "status")) => {
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd"
+            ))]
+            if let Err(e) = daemon::command(cmd, args[2..].to_vec()) {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+            #[cfg(not(any(
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd"
+            )))]
+            println!("'{}' is only supported on linux and macos", cmd);
+            return;
+        }
+        Some("service") => {
+            #[cfg(any(target_os = "linux", target_os = "windows"))]
+            if let Err(e) = service::command(args[2..].to_vec()) {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+            #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+            println!("'service' is only supported on linux and windows");
+            return;
+        }
+        Some("setup") => {
+            #[cfg(feature = "file_config")]
+            if let Err(e) = setup::command(args[2..].to_vec()) {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+            #[cfg(not(feature = "file_config"))]
+            println!("'setup' requires file_config feature");
+            return;
+        }
+        _ => {}
+    }
+    let (networks, _vnt_link_config, cmd) = match common::cli::parse_args_config() {
         Ok(rs) => {
             if let Some(rs) = rs {
                 rs
@@ -17,39 +73,104 @@ fn main() {
                 std::env::args().collect::<Vec<String>>()
             );
             println!("{}", e);
-            return;
+            common::exit::exit(common::exit::CONFIG_ERROR, &e.to_string());
         }
     };
-    main0(config, cmd)
+    main0(networks, cmd)
 }
-fn main0(config: Config, _show_cmd: bool) {
+// 需要管理员或root权限，Linux/macOS上sudo会用同样的参数重新执行
+fn elevate() {
     if !root_check::is_app_elevated() {
         println!("Please run it with administrator or root privileges");
-        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        ))]
         sudo::escalate_if_needed().unwrap();
-        return;
+        std::process::exit(1);
     }
-    #[cfg(feature = "port_mapping")]
-    for (is_tcp, addr, dest) in config.port_mapping_list.iter() {
-        if *is_tcp {
-            println!("TCP port mapping {}->{}", addr, dest)
+}
+
+// 取出子命令自己使用的参数，支持'--x v'和'--x=v'
+fn take_opt(args: &mut Vec<String>, name: &str) -> anyhow::Result<Option<String>> {
+    let opt = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+    let mut value = None;
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == opt {
+            if i + 1 >= args.len() {
+                return Err(anyhow::anyhow!("{} missing value", opt));
+            }
+            value = Some(args.remove(i + 1));
+            args.remove(i);
+        } else if let Some(v) = args[i].strip_prefix(&prefix) {
+            value = Some(v.to_string());
+            args.remove(i);
         } else {
-            println!("UDP port mapping {}->{}", addr, dest)
+            i += 1;
         }
     }
-    let vnt_util = match Vnt::new(config, callback::VntHandler {}) {
-        Ok(vnt) => vnt,
-        Err(e) => {
-            println!("error: {:?}", e);
-            std::process::exit(1);
+    Ok(value)
+}
+
+fn main0(networks: Vec<(Option<String>, Config)>, _show_cmd: bool) {
+    if !root_check::is_app_elevated() {
+        log::error!("not running with administrator or root privileges");
+        println!("Please run it with administrator or root privileges");
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        ))]
+        sudo::escalate_if_needed().unwrap();
+        return;
+    }
+    // 同时运行多个网络时，其中一个停止不退出进程
+    let multiple = networks.len() > 1;
+    let mut vnts: Vec<(Option<String>, Vnt)> = Vec::with_capacity(networks.len());
+    for (network, config) in networks {
+        let network = network.filter(|_| multiple);
+        #[cfg(feature = "port_mapping")]
+        for rule in config.port_mapping_list.iter() {
+            println!("port mapping {}", rule)
         }
-    };
-    #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let handler = callback::VntHandler {
+            keep_process: multiple,
+            network: network.clone(),
+        };
+        match Vnt::new(config, handler) {
+            Ok(vnt) => vnts.push((network, vnt)),
+            Err(e) => {
+                println!("error: {:?} network={:?}", e, network);
+                for (_, vnt) in vnts.iter() {
+                    vnt.stop();
+                }
+                common::exit::exit(common::exit::OTHER, &format!("{:?}", e));
+            }
+        }
+    }
+    // 交互式命令和panic报告使用第一个网络
+    let vnt_util = vnts[0].1.clone();
+    #[cfg(feature = "log")]
+    common::crash::set_panic_hook(vnt_util.clone());
+    #[cfg(target_os = "windows")]
+    set_console_ctrl_handler(vnts.iter().map(|(_, vnt)| vnt.clone()).collect());
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
     {
-        let vnt_c = vnt_util.clone();
+        let vnt_list: Vec<Vnt> = vnts.iter().map(|(_, vnt)| vnt.clone()).collect();
         let mut signals = signal_hook::iterator::Signals::new(&[
             signal_hook::consts::SIGINT,
             signal_hook::consts::SIGTERM,
+            signal_hook::consts::SIGHUP,
         ])
         .unwrap();
         let handle = signals.handle();
@@ -57,32 +178,78 @@ fn main0(config: Config, _show_cmd: bool) {
             for sig in signals.forever() {
                 match sig {
                     signal_hook::consts::SIGINT | signal_hook::consts::SIGTERM => {
+                        log::info!("received signal {}, stopping", sig);
                         println!("Received SIGINT, {}", sig);
-                        vnt_c.stop();
+                        for vnt in vnt_list.iter() {
+                            current(vnt).stop();
+                        }
                         handle.close();
                         break;
                     }
+                    signal_hook::consts::SIGHUP => {
+                        // 重新加载配置文件，不断开连接
+                        for vnt in vnt_list.iter() {
+                            match common::config::reload(&current(vnt)) {
+                                Ok(changed) => println!("Received SIGHUP, reloaded {:?}", changed),
+                                Err(e) => {
+                                    log::warn!("SIGHUP reload error {}", e);
+                                    println!("Received SIGHUP, reload error {}", e)
+                                }
+                            }
+                        }
+                    }
                     _ => {}
                 }
             }
         });
     }
+    // 只运行一个网络时支持切换组网
+    #[cfg(feature = "command")]
+    let switch_thread = if multiple {
+        None
+    } else {
+        common::command::switch::enable(vnt_util.clone());
+        let vnt = vnt_util.clone();
+        let thread = std::thread::Builder::new()
+            .name("vntSwitch".into())
+            .spawn(move || switch_loop(vnt))
+            .expect("vntSwitch");
+        Some(thread)
+    };
     #[cfg(feature = "command")]
     {
-        let vnt_c = vnt_util.clone();
-        std::thread::Builder::new()
-            .name("CommandServer".into())
-            .spawn(move || {
-                if let Err(e) = common::command::server::CommandServer::new().start(vnt_c) {
-                    log::warn!("cmd:{:?}", e);
+        for (index, (network, vnt)) in vnts.iter().enumerate() {
+            command_server(vnt.clone(), network.clone(), index == 0);
+            if let Some(addr) = vnt.config().metrics {
+                if let Err(e) = common::command::metrics::start(addr, vnt.clone()) {
+                    log::error!("metrics {} error: {:?}", addr, e);
+                    println!("metrics {} error: {:?}", addr, e);
+                }
+            }
+            if let Some(addr) = vnt.config().api {
+                let token = vnt.config().api_token.clone();
+                if let Err(e) = common::command::api::start(addr, token, vnt.clone()) {
+                    log::error!("api {} error: {:?}", addr, e);
+                    println!("api {} error: {:?}", addr, e);
+                }
+            }
+            if let Some(addr) = vnt.config().web {
+                if let Err(e) = common::command::web::start(addr, vnt.clone()) {
+                    log::error!("web {} error: {:?}", addr, e);
+                    println!("web {} error: {:?}", addr, e);
                 }
-            })
-            .expect("CommandServer");
+            }
+        }
         if _show_cmd {
+            if let Some(network) = vnts[0].0.as_ref() {
+                println!("input commands apply to network '{}'", network);
+            }
             let mut cmd = String::new();
             loop {
                 cmd.clear();
-                println!("======== input:list,info,route,all,stop,chart_a,chart_b[:ip] ========");
+                println!(
+                    "======== input:list,info,status,route,all,stop,reload,chart_a,chart_b[:ip],stats,mapping_list,mapping_add:<mapping>,mapping_remove:<mapping>,log_level:<level>,ping:<ip>,traceroute:<ip>,iperf:<ip> ========"
+                );
                 match std::io::stdin().read_line(&mut cmd) {
                     Ok(len) => {
                         if !common::command::command_str(&cmd[..len], &vnt_util) {
@@ -98,5 +265,97 @@ fn main0(config: Config, _show_cmd: bool) {
         }
     }
 
-    vnt_util.wait()
+    #[cfg(feature = "command")]
+    if let Some(switch_thread) = switch_thread {
+        let _ = switch_thread.join();
+    }
+    for (_, vnt) in vnts.iter() {
+        vnt.wait();
+    }
+    // 某个网络因致命错误停止时使用它的退出码
+    let code = common::exit::recorded();
+    if code != 0 {
+        std::process::exit(code);
+    }
+    if vnts.iter().any(|(_, vnt)| current(vnt).watchdog_expired()) {
+        // 看门狗无法恢复，交给服务管理器重启
+        common::exit::exit(common::exit::WATCHDOG, "watchdog expired");
+    }
+}
+
+// 切换组网后使用新的连接
+fn current(vnt: &Vnt) -> Vnt {
+    #[cfg(feature = "command")]
+    return common::command::switch::current(vnt);
+    #[cfg(not(feature = "command"))]
+    vnt.clone()
+}
+
+/// 等待连接停止，因为切换组网而停止时用新的配置重新连接，命令端口和日志不变
+#[cfg(feature = "command")]
+fn switch_loop(mut vnt: Vnt) {
+    loop {
+        vnt.wait();
+        let config = match common::command::switch::take() {
+            Some(config) => config,
+            None => return,
+        };
+        vnt = match Vnt::new(config, callback::VntHandler::default()) {
+            Ok(vnt) => vnt,
+            Err(e) => {
+                println!("switch error: {:?}", e);
+                common::exit::exit(common::exit::OTHER, &format!("{:?}", e));
+            }
+        };
+        common::command::switch::enable(vnt.clone());
+        #[cfg(feature = "log")]
+        common::crash::set_panic_hook(vnt.clone());
+    }
+}
+
+#[cfg(target_os = "windows")]
+static CONSOLE_VNT: std::sync::Mutex<Vec<Vnt>> = std::sync::Mutex::new(Vec::new());
+
+/// Ctrl-C和关闭控制台时正常退出，退出前会通知服务端和对端
+#[cfg(target_os = "windows")]
+fn set_console_ctrl_handler(vnt_list: Vec<Vnt>) {
+    unsafe extern "system" fn handler(ctrl_type: u32) -> i32 {
+        let vnt_list = CONSOLE_VNT.lock().unwrap().clone();
+        if !vnt_list.is_empty() {
+            log::info!("received console ctrl {}, stopping", ctrl_type);
+            println!("Received console ctrl {}", ctrl_type);
+            let vnt_list: Vec<Vnt> = vnt_list.iter().map(current).collect();
+            for vnt in vnt_list.iter() {
+                vnt.stop();
+            }
+            // 关闭控制台时handler返回后进程就会被结束，等待退出完成
+            for vnt in vnt_list.iter() {
+                vnt.wait_timeout(std::time::Duration::from_secs(3));
+            }
+        }
+        1
+    }
+    *CONSOLE_VNT.lock().unwrap() = vnt_list;
+    unsafe {
+        winapi::um::consoleapi::SetConsoleCtrlHandler(Some(handler), 1);
+    }
+}
+
+/// network为同时运行多个网络时的配置组名，primary的网络使用默认的命令端口
+#[cfg(feature = "command")]
+fn command_server(vnt: Vnt, network: Option<String>, primary: bool) {
+    std::thread::Builder::new()
+        .name("CommandServer".into())
+        .spawn(move || {
+            let server = match network {
+                Some(network) => {
+                    common::command::server::CommandServer::with_network(network, primary)
+                }
+                None => common::command::server::CommandServer::new(),
+            };
+            if let Err(e) = server.start(vnt) {
+                log::warn!("cmd:{:?}", e);
+            }
+        })
+        .expect("CommandServer");
 }
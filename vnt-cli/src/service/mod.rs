@@ -0,0 +1,72 @@
+use anyhow::anyhow;
+
+#[cfg(target_os = "linux")]
+mod systemd;
+#[cfg(target_os = "linux")]
+use systemd::{install, start, stop, uninstall};
+
+#[cfg(target_os = "windows")]
+mod windows;
+#[cfg(target_os = "windows")]
+use windows::{install, start, stop, uninstall};
+
+const DEFAULT_NAME: &str = "vnt-cli";
+
+/// service install|uninstall|start|stop，把当前的启动参数注册为系统服务，由系统负责开机启动和异常重启
+pub fn command(args: Vec<String>) -> anyhow::Result<()> {
+    let mut args = args;
+    if args.is_empty() {
+        return Err(anyhow!(
+            "usage: service install|uninstall|start|stop [--service-name <name>] [args]"
+        ));
+    }
+    let cmd = args.remove(0);
+    let name =
+        crate::take_opt(&mut args, "service-name")?.unwrap_or_else(|| DEFAULT_NAME.to_string());
+    match cmd.as_str() {
+        "install" => {
+            crate::elevate();
+            install(&name, absolute_config(args)?)?;
+            println!("service {} installed", name);
+        }
+        "uninstall" => {
+            crate::elevate();
+            uninstall(&name)?;
+            println!("service {} uninstalled", name);
+        }
+        "start" => {
+            crate::elevate();
+            start(&name)?;
+            println!("service {} started", name);
+        }
+        "stop" => {
+            crate::elevate();
+            stop(&name)?;
+            println!("service {} stopped", name);
+        }
+        // 服务管理器启动时使用，不需要手动执行
+        #[cfg(target_os = "windows")]
+        "run" => windows::run(name, args)?,
+        _ => {
+            return Err(anyhow!(
+                "usage: service install|uninstall|start|stop [--service-name <name>] [args]"
+            ))
+        }
+    }
+    Ok(())
+}
+
+// 服务的工作目录和当前目录不同，'-f'指定的配置文件转换成绝对路径
+fn absolute_config(mut args: Vec<String>) -> anyhow::Result<Vec<String>> {
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == "-f" && i + 1 < args.len() {
+            let path = std::fs::canonicalize(&args[i + 1])
+                .map_err(|e| anyhow!("config file {} {}", args[i + 1], e))?;
+            args[i + 1] = path.to_string_lossy().to_string();
+            i += 1;
+        }
+        i += 1;
+    }
+    Ok(args)
+}
@@ -0,0 +1,112 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::anyhow;
+
+fn unit_path(name: &str) -> anyhow::Result<PathBuf> {
+    // 服务名会拼接到路径和systemctl参数中
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '@' | '-'))
+    {
+        return Err(anyhow!("invalid service name {:?}", name));
+    }
+    Ok(PathBuf::from(format!(
+        "/etc/systemd/system/{}.service",
+        name
+    )))
+}
+
+fn systemctl(args: &[&str]) -> anyhow::Result<()> {
+    let status = Command::new("systemctl")
+        .args(args)
+        .status()
+        .map_err(|e| anyhow!("systemctl {}", e))?;
+    if !status.success() {
+        return Err(anyhow!("systemctl {} {}", args.join(" "), status));
+    }
+    Ok(())
+}
+
+// systemd的ExecStart中参数的转义，'%'和'$'也有特殊含义
+fn quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    if !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\')
+    {
+        return arg;
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// 写入并启用systemd服务，异常退出后自动重启
+pub fn install(name: &str, args: Vec<String>) -> anyhow::Result<()> {
+    let path = unit_path(name)?;
+    let exe = std::env::current_exe()?;
+    let work_dir = std::env::current_dir()?;
+    let mut exec_start = quote(&exe.to_string_lossy());
+    for arg in &args {
+        exec_start.push(' ');
+        exec_start.push_str(&quote(arg));
+    }
+    let unit = format!(
+        "[Unit]
+Description=VNT virtual network ({})
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=simple
+WorkingDirectory={}
+ExecStart={}
+Restart=always
+RestartSec=5
+
+[Install]
+WantedBy=multi-user.target
+",
+        name,
+        work_dir.to_string_lossy().replace('%', "%%"),
+        exec_start
+    );
+    // 参数中可能有token、密码，只允许root读取
+    let write = || -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        // 已存在的文件不会应用mode
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(unit.as_bytes())
+    };
+    write().map_err(|e| anyhow!("write {:?} {}", path, e))?;
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", name])
+}
+
+pub fn uninstall(name: &str) -> anyhow::Result<()> {
+    let path = unit_path(name)?;
+    if !path.exists() {
+        return Err(anyhow!("service {} not installed", name));
+    }
+    // 服务没有运行时stop也会成功
+    systemctl(&["disable", "--now", name])?;
+    std::fs::remove_file(&path).map_err(|e| anyhow!("remove {:?} {}", path, e))?;
+    systemctl(&["daemon-reload"])
+}
+
+pub fn start(name: &str) -> anyhow::Result<()> {
+    systemctl(&["start", name])
+}
+
+pub fn stop(name: &str) -> anyhow::Result<()> {
+    systemctl(&["stop", name])
+}
@@ -0,0 +1,226 @@
+use std::ffi::{OsStr, OsString};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use windows_service::service::{
+    ServiceAccess, ServiceAction, ServiceActionType, ServiceControl, ServiceControlAccept,
+    ServiceErrorControl, ServiceExitCode, ServiceFailureActions, ServiceFailureResetPeriod,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use common::callback;
+use vnt::core::Vnt;
+
+// 服务管理器启动服务时，服务名和启动参数从命令行传给服务入口
+static SERVICE: Mutex<Option<(String, Vec<String>)>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// 注册为自动启动的服务，异常退出后由服务管理器重启
+pub fn install(name: &str, args: Vec<String>) -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let mut launch_arguments: Vec<OsString> = vec![
+        "service".into(),
+        "run".into(),
+        "--service-name".into(),
+        name.into(),
+    ];
+    launch_arguments.extend(args.into_iter().map(OsString::from));
+    let info = ServiceInfo {
+        name: name.into(),
+        display_name: format!("VNT virtual network ({})", name).into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service =
+        manager.create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)?;
+    service.set_description("VNT virtual network")?;
+    // 失败后5秒重启，一天内没有失败则重新计数
+    service.update_failure_actions(ServiceFailureActions {
+        reset_period: ServiceFailureResetPeriod::After(Duration::from_secs(24 * 3600)),
+        reboot_msg: None,
+        command: None,
+        actions: Some(
+            (0..3)
+                .map(|_| ServiceAction {
+                    action_type: ServiceActionType::Restart,
+                    delay: Duration::from_secs(5),
+                })
+                .collect(),
+        ),
+    })?;
+    // 以非0退出码停止也视为失败
+    service.set_failure_actions_on_non_crash_failures(true)?;
+    Ok(())
+}
+
+pub fn uninstall(name: &str) -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        name,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+    Ok(())
+}
+
+pub fn start(name: &str) -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(name, ServiceAccess::START)?;
+    service.start::<&OsStr>(&[])?;
+    Ok(())
+}
+
+pub fn stop(name: &str) -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(name, ServiceAccess::STOP)?;
+    service.stop()?;
+    Ok(())
+}
+
+/// 服务入口，阻塞到服务停止
+pub fn run(name: String, args: Vec<String>) -> anyhow::Result<()> {
+    // 服务的工作目录是system32，切换到程序目录读取log4rs.yaml等文件
+    if let Some(dir) = std::env::current_exe()?.parent() {
+        std::env::set_current_dir(dir)?;
+    }
+    SERVICE.lock().unwrap().replace((name.clone(), args));
+    service_dispatcher::start(name, ffi_service_main)?;
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        log::error!("service {:?}", e);
+    }
+}
+
+fn run_service() -> anyhow::Result<()> {
+    let (name, args) = match SERVICE.lock().unwrap().take() {
+        Some(v) => v,
+        None => return Err(anyhow!("service args none")),
+    };
+    let (stop_sender, stop_receiver) = std::sync::mpsc::channel();
+    let status_handle = service_control_handler::register(&name, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = stop_sender.send(());
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+    set_status(&status_handle, ServiceState::StartPending, 0)?;
+    let code = match run_vnt(args, stop_receiver, &status_handle) {
+        // 收到停止命令
+        Ok(true) => 0,
+        // vnt自己退出了，返回非0让服务管理器重启
+        Ok(false) => match common::exit::recorded() {
+            0 => 1,
+            code => code,
+        },
+        Err(e) => {
+            log::error!("service {:?}", e);
+            1
+        }
+    };
+    set_status(&status_handle, ServiceState::Stopped, code as u32)?;
+    Ok(())
+}
+
+fn run_vnt(
+    args: Vec<String>,
+    stop_receiver: Receiver<()>,
+    status_handle: &ServiceStatusHandle,
+) -> anyhow::Result<bool> {
+    let mut argv = vec![std::env::current_exe()?.to_string_lossy().to_string()];
+    argv.extend(args);
+    let networks = match common::cli::parse_args(argv)? {
+        Some((networks, _, _)) => networks,
+        None => return Err(anyhow!("invalid args")),
+    };
+    let multiple = networks.len() > 1;
+    let mut vnt_list = Vec::with_capacity(networks.len());
+    for (network, config) in networks {
+        let network = network.filter(|_| multiple);
+        let handler = callback::VntHandler {
+            keep_process: true,
+            network: network.clone(),
+        };
+        let vnt = match Vnt::new(config, handler) {
+            Ok(vnt) => vnt,
+            Err(e) => {
+                for vnt in vnt_list.iter() {
+                    vnt.stop();
+                }
+                return Err(e);
+            }
+        };
+        #[cfg(feature = "command")]
+        crate::command_server(vnt.clone(), network, vnt_list.is_empty());
+        vnt_list.push(vnt);
+    }
+    #[cfg(feature = "log")]
+    common::crash::set_panic_hook(vnt_list[0].clone());
+    set_status(status_handle, ServiceState::Running, 0)?;
+    let stopped = Arc::new(AtomicBool::new(false));
+    let vnt_c = vnt_list.clone();
+    let stopped_c = stopped.clone();
+    std::thread::spawn(move || {
+        if stop_receiver.recv().is_ok() {
+            stopped_c.store(true, Ordering::Relaxed);
+            for vnt in vnt_c.iter() {
+                vnt.stop();
+            }
+        }
+    });
+    for vnt in vnt_list.iter() {
+        vnt.wait();
+    }
+    Ok(stopped.load(Ordering::Relaxed))
+}
+
+fn set_status(
+    status_handle: &ServiceStatusHandle,
+    state: ServiceState,
+    code: u32,
+) -> anyhow::Result<()> {
+    let controls_accepted = if state == ServiceState::Running {
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+    } else {
+        ServiceControlAccept::empty()
+    };
+    status_handle.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: if code == 0 {
+            ServiceExitCode::Win32(0)
+        } else {
+            ServiceExitCode::ServiceSpecific(code)
+        },
+        checkpoint: 0,
+        wait_hint: Duration::from_secs(10),
+        process_id: None,
+    })?;
+    Ok(())
+}
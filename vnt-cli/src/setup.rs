@@ -0,0 +1,175 @@
+use std::io::Write;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use serde_yaml::{Mapping, Value};
+use vnt::cipher::CipherModel;
+
+const DEFAULT_SERVER: &str = "vnt.wherewego.top:29872";
+
+/// setup子命令，交互式生成配置文件，写入前用和启动时相同的方式校验，可以选择安装为系统服务
+pub fn command(args: Vec<String>) -> anyhow::Result<()> {
+    let mut args = args;
+    let path = match crate::take_opt(&mut args, "output")? {
+        Some(path) => PathBuf::from(path),
+        None => default_path()?,
+    };
+    if !args.is_empty() {
+        return Err(anyhow!("usage: setup [--output <path>]"));
+    }
+    // 写入系统目录和安装服务都需要权限，先提权，避免填完之后才失败
+    crate::elevate();
+    if path.exists() && !confirm(&format!("{} already exists, overwrite?", path.display()))? {
+        return Ok(());
+    }
+    let mut mapping = Mapping::new();
+    let token = loop {
+        let token = prompt("token", None)?;
+        if token.is_empty() {
+            println!("token is required");
+            continue;
+        }
+        break token;
+    };
+    mapping.insert("token".into(), token.into());
+    let server = prompt("server", Some(DEFAULT_SERVER))?;
+    mapping.insert("server_address".into(), server.into());
+    let ip = loop {
+        let ip = prompt("virtual ip (empty for assigned by server)", Some(""))?;
+        if ip.is_empty() {
+            break None;
+        }
+        match Ipv4Addr::from_str(&ip) {
+            Ok(ip) => break Some(ip),
+            Err(e) => println!("invalid ip {}", e),
+        }
+    };
+    if let Some(ip) = ip {
+        mapping.insert("ip".into(), ip.to_string().into());
+    }
+    let password = prompt(
+        "password (empty for no encryption between clients)",
+        Some(""),
+    )?;
+    if !password.is_empty() {
+        let cipher_model = loop {
+            let cipher_model = prompt("cipher model", Some(default_cipher_model()))?;
+            match CipherModel::from_str(&cipher_model) {
+                Ok(_) => break cipher_model,
+                Err(e) => println!("{}", e),
+            }
+        };
+        mapping.insert("password".into(), password.into());
+        mapping.insert("cipher_model".into(), cipher_model.into());
+    }
+    #[cfg(feature = "server_encrypt")]
+    if confirm("encrypt traffic to the server?")? {
+        mapping.insert("server_encrypt".into(), true.into());
+    }
+    #[cfg(target_os = "windows")]
+    if !wintun_exists() {
+        println!("wintun.dll not found, download it from https://www.wintun.net/ and put it next to vnt-cli.exe");
+        if confirm("use tap device instead?")? {
+            mapping.insert("tap".into(), true.into());
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if !Path::new("/dev/net/tun").exists() {
+        println!("/dev/net/tun not found, load the tun module first: modprobe tun");
+    }
+    write_config(&path, &Value::Mapping(mapping))?;
+    println!("config written to {}", path.display());
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    if confirm("install as system service?")? {
+        let path = path.to_string_lossy().to_string();
+        crate::service::command(vec!["install".into(), "-f".into(), path])?;
+        crate::service::command(vec!["start".into()])?;
+        return Ok(());
+    }
+    println!("run: vnt-cli -f {}", path.display());
+    Ok(())
+}
+
+// 系统级的配置目录，服务以管理员或root运行，和当前用户无关
+fn default_path() -> anyhow::Result<PathBuf> {
+    #[cfg(target_os = "windows")]
+    let dir = match std::env::var_os("ProgramData") {
+        Some(dir) => PathBuf::from(dir).join("vnt"),
+        None => return Err(anyhow!("ProgramData not found, use --output <path>")),
+    };
+    #[cfg(not(target_os = "windows"))]
+    let dir = PathBuf::from("/etc/vnt");
+    Ok(dir.join("config.yaml"))
+}
+
+fn default_cipher_model() -> &'static str {
+    #[cfg(any(feature = "aes_gcm", feature = "server_encrypt"))]
+    {
+        "aes_gcm"
+    }
+    #[cfg(not(any(feature = "aes_gcm", feature = "server_encrypt")))]
+    {
+        "xor"
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn wintun_exists() -> bool {
+    // 和加载dll时的查找顺序一致，程序目录或系统目录
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|path| path.parent().map(|v| v.join("wintun.dll")));
+    let system_dir = std::env::var_os("SystemRoot")
+        .map(|dir| PathBuf::from(dir).join("System32").join("wintun.dll"));
+    exe_dir
+        .into_iter()
+        .chain(system_dir)
+        .any(|path| path.exists())
+}
+
+// 先写到临时文件，校验通过后再替换，校验失败不会留下错误的配置
+fn write_config(path: &Path, config: &Value) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("yaml.tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // 包含token和密码，只允许所有者读写
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&tmp)?
+        .write_all(serde_yaml::to_string(config)?.as_bytes())?;
+    if let Err(e) = common::config::read_config(&tmp.to_string_lossy(), None) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(anyhow!("invalid config {}", e));
+    }
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn prompt(label: &str, default: Option<&str>) -> anyhow::Result<String> {
+    match default {
+        Some(default) if !default.is_empty() => print!("{} [{}]: ", label, default),
+        _ => print!("{}: ", label),
+    }
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    if std::io::stdin().read_line(&mut line)? == 0 {
+        return Err(anyhow!("input closed"));
+    }
+    let line = line.trim();
+    if line.is_empty() {
+        Ok(default.unwrap_or_default().to_string())
+    } else {
+        Ok(line.to_string())
+    }
+}
+
+fn confirm(label: &str) -> anyhow::Result<bool> {
+    let answer = prompt(&format!("{} [y/N]", label), Some(""))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,115 @@
+#ifndef VNT_H
+#define VNT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* ipv4地址都是主机字节序 */
+typedef struct VntRoute {
+    uint32_t dest;
+    uint32_t mask;
+} VntRoute;
+
+typedef struct VntDeviceConfig {
+    uint32_t mtu;
+    uint32_t virtual_ip;
+    uint32_t virtual_netmask;
+    uint32_t virtual_gateway;
+    uint32_t virtual_network;
+    uint8_t virtual_ipv6[16];
+    uint8_t virtual_ipv6_prefix;
+    /* 回调返回后失效 */
+    const VntRoute *routes;
+    size_t routes_len;
+} VntDeviceConfig;
+
+/*
+ * 错误码: 1 token错误, 2 断开连接, 3 地址耗尽, 4 ip已存在, 5 ip无效, 6 和本地ip冲突,
+ * 7 身份认证失败, 8 邀请码错误, 9 动态验证码错误, 10 网卡错误, 11 无法连接服务端,
+ * 12 看门狗无法恢复, 255 其他
+ */
+typedef void (*VntWritePacketFn)(void *ctx, const uint8_t *data, size_t len);
+typedef void (*VntDeviceConfigFn)(void *ctx, const VntDeviceConfig *config);
+typedef void (*VntErrorFn)(void *ctx, int code, const char *msg);
+typedef void (*VntStopFn)(void *ctx);
+
+/*
+ * 接口内部的panic被捕获后按失败返回(NULL或-1)，只在以panic=unwind编译时有效，
+ * 工作区默认的release配置是panic=abort，panic会直接结束进程，
+ * 需要时使用CARGO_PROFILE_RELEASE_PANIC=unwind编译
+ */
+
+/* 当前线程最后一次失败的原因 */
+const char *vnt_last_error(void);
+const char *vnt_version(void);
+/* 接口有不兼容的修改时增加 */
+uint32_t vnt_abi_version(void);
+
+/* ---------- 由vnt创建虚拟网卡(编译时启用integrated_tun) ---------- */
+
+/* 事件的data为json */
+#define VNT_EVENT_SUCCESS 1     /* {} */
+#define VNT_EVENT_CREATE_TUN 2  /* {"name","version"} */
+#define VNT_EVENT_CONNECT 3     /* {"count","address"} */
+#define VNT_EVENT_HANDSHAKE 4   /* {"version","finger"} */
+#define VNT_EVENT_REGISTER 5    /* {"virtual_ip","virtual_netmask","virtual_gateway"} */
+#define VNT_EVENT_PEER_LIST 6   /* [{"virtual_ip","name","online","client_secret"}] */
+#define VNT_EVENT_ERROR 7       /* {"code","msg","source"} */
+#define VNT_EVENT_STOP 8        /* {} */
+
+/* 在vnt的线程中回调，data在回调返回后失效 */
+typedef void (*VntEventFn)(void *ctx, int event, const char *data);
+
+typedef struct VntHandle VntHandle;
+
+typedef struct VntStats {
+    /* 0连接中，1已连接 */
+    int status;
+    uint32_t virtual_ip;
+    uint32_t peer_count;
+    uint64_t up_stream;
+    uint64_t down_stream;
+    /* 秒 */
+    uint64_t uptime;
+} VntStats;
+
+/* config格式和配置文件相同(json或yaml)，失败返回NULL(panic见上方说明)，on_event可以为NULL */
+VntHandle *vnt_start(const char *config, VntEventFn on_event, void *ctx);
+/* 停止并释放，不能在回调中调用 */
+void vnt_stop(VntHandle *handle);
+/* 成功返回0，失败返回-1(panic见上方说明) */
+int vnt_stats(const VntHandle *handle, VntStats *stats);
+/* 对端列表的json数组，使用vnt_free_string释放，失败返回NULL(panic见上方说明) */
+char *vnt_peers(const VntHandle *handle);
+void vnt_free_string(char *s);
+
+/* ---------- 数据包队列模式(iOS/macOS NetworkExtension，编译时不启用integrated_tun) ---------- */
+
+typedef struct VntPacketFlowCallbacks {
+    void *ctx;
+    /* 必须设置，收到的ip包写入packetFlow */
+    VntWritePacketFn write_packet;
+    /* 以下可以为NULL */
+    VntDeviceConfigFn device_config;
+    VntErrorFn error;
+    VntStopFn stop;
+} VntPacketFlowCallbacks;
+
+typedef struct VntPacketFlow VntPacketFlow;
+
+/* config格式和配置文件相同(json或yaml)，失败返回NULL(panic见上方说明) */
+VntPacketFlow *vnt_packet_flow_start(const char *config, VntPacketFlowCallbacks callbacks);
+/* 传入从packetFlow读取的ip包，成功返回0，失败返回-1(panic见上方说明) */
+int vnt_packet_flow_input(const VntPacketFlow *packet_flow, const uint8_t *data, size_t len);
+/* 停止并释放，不能在回调中调用 */
+void vnt_packet_flow_stop(VntPacketFlow *packet_flow);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
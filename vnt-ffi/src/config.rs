@@ -0,0 +1,18 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+use anyhow::anyhow;
+use vnt::core::Config;
+
+/// 解析调用方传入的配置，格式和配置文件相同，可以是json或yaml
+///
+/// # Safety
+/// config为空或者以'\0'结尾的字符串
+pub unsafe fn parse(config: *const c_char) -> anyhow::Result<Config> {
+    if config.is_null() {
+        return Err(anyhow!("config is null"));
+    }
+    let config = CStr::from_ptr(config).to_str()?;
+    let (config, _, _) = common::config::parse_config(config, None)?;
+    Ok(config)
+}
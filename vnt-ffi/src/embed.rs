@@ -0,0 +1,241 @@
+//! 由vnt创建虚拟网卡运行，用于图形界面和其他语言的封装
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+
+use serde_json::{json, Value};
+use vnt::core::Vnt;
+use vnt::{ConnectInfo, ErrorInfo, HandshakeInfo, PeerClientInfo, RegisterInfo, VntCallback};
+
+pub const VNT_EVENT_SUCCESS: c_int = 1;
+pub const VNT_EVENT_CREATE_TUN: c_int = 2;
+pub const VNT_EVENT_CONNECT: c_int = 3;
+pub const VNT_EVENT_HANDSHAKE: c_int = 4;
+pub const VNT_EVENT_REGISTER: c_int = 5;
+pub const VNT_EVENT_PEER_LIST: c_int = 6;
+pub const VNT_EVENT_ERROR: c_int = 7;
+pub const VNT_EVENT_STOP: c_int = 8;
+
+/// 事件回调，data是json字符串，在回调返回后失效。回调在vnt的线程中执行，不能阻塞太久
+pub type EventFn = extern "C" fn(ctx: *mut c_void, event: c_int, data: *const c_char);
+
+pub struct VntHandle {
+    vnt: Vnt,
+}
+
+#[repr(C)]
+pub struct VntStats {
+    /// 0连接中，1已连接
+    pub status: c_int,
+    /// 主机字节序
+    pub virtual_ip: u32,
+    /// 在线的对端数量
+    pub peer_count: u32,
+    /// 没有开启流量统计时为0
+    pub up_stream: u64,
+    pub down_stream: u64,
+    /// 运行时间，单位秒
+    pub uptime: u64,
+}
+
+#[derive(Clone)]
+struct EventCallback {
+    on_event: Option<EventFn>,
+    ctx: *mut c_void,
+}
+
+// ctx由调用方保证可以在任意线程中使用
+unsafe impl Send for EventCallback {}
+unsafe impl Sync for EventCallback {}
+
+impl EventCallback {
+    fn emit(&self, event: c_int, data: Value) {
+        if let Some(on_event) = self.on_event {
+            let data = CString::new(data.to_string()).unwrap_or_default();
+            on_event(self.ctx, event, data.as_ptr());
+        }
+    }
+}
+
+impl VntCallback for EventCallback {
+    fn success(&self) {
+        self.emit(VNT_EVENT_SUCCESS, json!({}));
+    }
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
+    fn create_tun(&self, info: vnt::DeviceInfo) {
+        self.emit(
+            VNT_EVENT_CREATE_TUN,
+            json!({"name": info.name, "version": info.version}),
+        );
+    }
+    fn connect(&self, info: ConnectInfo) {
+        self.emit(
+            VNT_EVENT_CONNECT,
+            json!({"count": info.count, "address": info.address.to_string()}),
+        );
+    }
+    fn handshake(&self, info: HandshakeInfo) -> bool {
+        #[cfg(feature = "server_encrypt")]
+        let data = json!({"version": info.version, "finger": info.finger});
+        #[cfg(not(feature = "server_encrypt"))]
+        let data = json!({"version": info.version});
+        self.emit(VNT_EVENT_HANDSHAKE, data);
+        true
+    }
+    fn register(&self, info: RegisterInfo) -> bool {
+        self.emit(
+            VNT_EVENT_REGISTER,
+            json!({
+                "virtual_ip": info.virtual_ip.to_string(),
+                "virtual_netmask": info.virtual_netmask.to_string(),
+                "virtual_gateway": info.virtual_gateway.to_string(),
+            }),
+        );
+        true
+    }
+    fn peer_client_list(&self, info: Vec<PeerClientInfo>) {
+        let list: Vec<Value> = info
+            .iter()
+            .map(|v| {
+                json!({
+                    "virtual_ip": v.virtual_ip.to_string(),
+                    "name": v.name,
+                    "online": v.status.is_online(),
+                    "client_secret": v.client_secret,
+                })
+            })
+            .collect();
+        self.emit(VNT_EVENT_PEER_LIST, Value::Array(list));
+    }
+    fn error(&self, info: ErrorInfo) {
+        log::error!("error {:?}", info);
+        let code: u8 = info.code.into();
+        self.emit(
+            VNT_EVENT_ERROR,
+            json!({"code": code, "msg": info.msg, "source": info.source.map(|e| e.to_string())}),
+        );
+    }
+    fn stop(&self) {
+        self.emit(VNT_EVENT_STOP, json!({}));
+    }
+}
+
+/// 启动，需要管理员或root权限，失败时返回空指针，原因通过vnt_last_error获取
+/// 内部panic同样按失败返回，只在以panic=unwind编译时有效
+///
+/// # Safety
+/// config是以'\0'结尾的字符串，格式和配置文件相同；返回值要通过vnt_stop释放
+#[no_mangle]
+pub unsafe extern "C" fn vnt_start(
+    config: *const c_char,
+    on_event: Option<EventFn>,
+    ctx: *mut c_void,
+) -> *mut VntHandle {
+    crate::catch_panic(std::ptr::null_mut(), || {
+        let rs = crate::config::parse(config)
+            .and_then(|config| Vnt::new(config, EventCallback { on_event, ctx }));
+        match rs {
+            Ok(vnt) => Box::into_raw(Box::new(VntHandle { vnt })),
+            Err(e) => {
+                crate::set_last_error(&e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// 停止并释放，会等待网卡和连接关闭，不能在事件回调中调用
+///
+/// # Safety
+/// handle是vnt_start返回且没有释放的值，释放后不能再使用
+#[no_mangle]
+pub unsafe extern "C" fn vnt_stop(handle: *mut VntHandle) {
+    if handle.is_null() {
+        return;
+    }
+    let handle = Box::from_raw(handle);
+    crate::catch_panic((), || {
+        handle.vnt.stop();
+        handle.vnt.wait();
+    })
+}
+
+/// 获取运行状态，成功返回0，参数错误或内部错误返回-1
+/// 内部panic同样按失败返回，只在以panic=unwind编译时有效
+///
+/// # Safety
+/// handle是vnt_start返回且没有释放的值，stats指向可写的VntStats
+#[no_mangle]
+pub unsafe extern "C" fn vnt_stats(handle: *const VntHandle, stats: *mut VntStats) -> c_int {
+    let (handle, stats) = match (handle.as_ref(), stats.as_mut()) {
+        (Some(handle), Some(stats)) => (handle, stats),
+        _ => return -1,
+    };
+    crate::catch_panic(-1, || {
+        let vnt = &handle.vnt;
+        *stats = VntStats {
+            status: vnt.connection_status().online() as c_int,
+            virtual_ip: vnt.current_device().virtual_ip.into(),
+            peer_count: vnt
+                .device_list()
+                .iter()
+                .filter(|v| v.status.is_online())
+                .count() as u32,
+            up_stream: vnt.up_stream(),
+            down_stream: vnt.down_stream(),
+            uptime: vnt.uptime().as_secs(),
+        };
+        0
+    })
+}
+
+/// 对端列表的json数组，返回值要通过vnt_free_string释放
+/// 内部panic同样按失败返回，只在以panic=unwind编译时有效
+///
+/// # Safety
+/// handle是vnt_start返回且没有释放的值
+#[no_mangle]
+pub unsafe extern "C" fn vnt_peers(handle: *const VntHandle) -> *mut c_char {
+    let handle = match handle.as_ref() {
+        Some(handle) => handle,
+        None => return std::ptr::null_mut(),
+    };
+    crate::catch_panic(std::ptr::null_mut(), || {
+        let list: Vec<Value> = handle
+            .vnt
+            .device_list()
+            .iter()
+            .map(|v| {
+                let route = handle.vnt.route(&v.virtual_ip);
+                json!({
+                    "virtual_ip": v.virtual_ip.to_string(),
+                    "name": v.name,
+                    "online": v.status.is_online(),
+                    "client_secret": v.client_secret,
+                    "p2p": route.as_ref().map(|r| r.is_p2p()),
+                    "rt": route.as_ref().map(|r| r.rt),
+                })
+            })
+            .collect();
+        CString::new(Value::Array(list).to_string())
+            .unwrap_or_default()
+            .into_raw()
+    })
+}
+
+/// 释放接口返回的字符串
+///
+/// # Safety
+/// s是本库返回的字符串，只能释放一次
+#[no_mangle]
+pub unsafe extern "C" fn vnt_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
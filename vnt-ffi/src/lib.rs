@@ -0,0 +1,60 @@
+//! vnt的C接口，函数声明见include/vnt.h。
+//! 只通过不透明的指针、C结构体和json字符串交换数据，不依赖vnt内部类型的布局
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::c_char;
+
+mod config;
+#[cfg(feature = "integrated_tun")]
+pub mod embed;
+#[cfg(not(feature = "integrated_tun"))]
+pub mod packet_flow;
+
+/// 接口有不兼容的修改时增加
+pub const ABI_VERSION: u32 = 1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+pub(crate) fn set_last_error(e: &anyhow::Error) {
+    log::error!("{:?}", e);
+    let msg = CString::new(format!("{:#}", e)).unwrap_or_default();
+    LAST_ERROR.with(|v| *v.borrow_mut() = msg);
+}
+
+/// 接口函数的内容都在这里执行，panic展开穿过C接口是未定义行为，
+/// 捕获后记录原因并返回失败时的值。
+/// 只在以panic=unwind编译时有效，默认的release配置是abort，panic直接结束进程
+pub(crate) fn catch_panic<T>(fail: T, f: impl FnOnce() -> T) -> T {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(v) => v,
+        Err(e) => {
+            let msg = match e.downcast_ref::<&str>() {
+                Some(msg) => msg.to_string(),
+                None => match e.downcast_ref::<String>() {
+                    Some(msg) => msg.clone(),
+                    None => "unknown".to_string(),
+                },
+            };
+            set_last_error(&anyhow::anyhow!("panic {}", msg));
+            fail
+        }
+    }
+}
+
+/// 当前线程最后一次失败的原因，在下次调用失败前有效
+#[no_mangle]
+pub extern "C" fn vnt_last_error() -> *const c_char {
+    LAST_ERROR.with(|v| v.borrow().as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn vnt_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+#[no_mangle]
+pub extern "C" fn vnt_abi_version() -> u32 {
+    ABI_VERSION
+}
@@ -0,0 +1,222 @@
+//! 不创建虚拟网卡，数据包通过调用方提供的队列收发，
+//! 用于iOS/macOS的NetworkExtension(NEPacketTunnelProvider)，网卡由系统管理
+
+use std::net::Ipv4Addr;
+use std::os::raw::{c_char, c_int, c_void};
+
+use parking_lot::Mutex;
+use vnt::channel::sender::IpPacketSender;
+use vnt::channel::BUFFER_SIZE;
+use vnt::core::Vnt;
+use vnt::protocol::HEAD_LEN;
+use vnt::vnt_device::DeviceWrite;
+use vnt::{DeviceConfig, ErrorInfo, VntCallback};
+
+/// 收到发往本机的ip包，调用方写入packetFlow
+pub type WritePacketFn = extern "C" fn(ctx: *mut c_void, data: *const u8, len: usize);
+/// 虚拟ip和路由变化，调用方据此设置NEPacketTunnelNetworkSettings
+pub type DeviceConfigFn = extern "C" fn(ctx: *mut c_void, config: *const VntDeviceConfig);
+/// 出现错误，msg在回调返回后失效
+pub type ErrorFn = extern "C" fn(ctx: *mut c_void, code: c_int, msg: *const c_char);
+/// 服务停止
+pub type StopFn = extern "C" fn(ctx: *mut c_void);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VntPacketFlowCallbacks {
+    /// 原样传给每个回调
+    pub ctx: *mut c_void,
+    pub write_packet: WritePacketFn,
+    pub device_config: Option<DeviceConfigFn>,
+    pub error: Option<ErrorFn>,
+    pub stop: Option<StopFn>,
+}
+
+// ctx由调用方保证可以在任意线程中使用
+unsafe impl Send for VntPacketFlowCallbacks {}
+unsafe impl Sync for VntPacketFlowCallbacks {}
+
+/// ipv4地址都是主机字节序的u32
+#[repr(C)]
+pub struct VntRoute {
+    pub dest: u32,
+    pub mask: u32,
+}
+
+#[repr(C)]
+pub struct VntDeviceConfig {
+    pub mtu: u32,
+    pub virtual_ip: u32,
+    pub virtual_netmask: u32,
+    pub virtual_gateway: u32,
+    pub virtual_network: u32,
+    pub virtual_ipv6: [u8; 16],
+    pub virtual_ipv6_prefix: u8,
+    /// 需要经过虚拟网卡的路由，在回调返回后失效
+    pub routes: *const VntRoute,
+    pub routes_len: usize,
+}
+
+pub struct VntPacketFlow {
+    vnt: Vnt,
+    sender: IpPacketSender,
+    // 包前面要预留vnt的协议头，发送时加密还需要额外的缓冲区
+    buf: Mutex<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Clone)]
+struct PacketFlowCallback {
+    callbacks: VntPacketFlowCallbacks,
+}
+
+impl VntCallback for PacketFlowCallback {
+    fn create_device(&self, info: DeviceConfig) {
+        let device_config = match self.callbacks.device_config {
+            Some(device_config) => device_config,
+            None => return,
+        };
+        let routes: Vec<VntRoute> = info
+            .external_route
+            .iter()
+            .map(|(dest, mask)| VntRoute {
+                dest: (*dest).into(),
+                mask: (*mask).into(),
+            })
+            .collect();
+        let config = VntDeviceConfig {
+            mtu: info.mtu,
+            virtual_ip: info.virtual_ip.into(),
+            virtual_netmask: info.virtual_netmask.into(),
+            virtual_gateway: info.virtual_gateway.into(),
+            virtual_network: info.virtual_network.into(),
+            virtual_ipv6: info.virtual_ipv6.octets(),
+            virtual_ipv6_prefix: info.virtual_ipv6_prefix,
+            routes: routes.as_ptr(),
+            routes_len: routes.len(),
+        };
+        device_config(self.callbacks.ctx, &config);
+    }
+    fn error(&self, info: ErrorInfo) {
+        log::error!("error {:?}", info);
+        if let Some(error) = self.callbacks.error {
+            let msg = std::ffi::CString::new(info.to_string()).unwrap_or_default();
+            let code: u8 = info.code.into();
+            error(self.callbacks.ctx, code as c_int, msg.as_ptr());
+        }
+    }
+    fn stop(&self) {
+        if let Some(stop) = self.callbacks.stop {
+            stop(self.callbacks.ctx);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PacketFlowDevice {
+    callbacks: VntPacketFlowCallbacks,
+}
+
+impl DeviceWrite for PacketFlowDevice {
+    fn write(&self, buf: &[u8]) -> std::io::Result<usize> {
+        (self.callbacks.write_packet)(self.callbacks.ctx, buf.as_ptr(), buf.len());
+        Ok(buf.len())
+    }
+}
+
+/// 启动，失败时返回空指针，原因通过vnt_last_error获取
+/// 内部panic同样按失败返回，只在以panic=unwind编译时有效
+///
+/// # Safety
+/// config是以'\0'结尾的字符串，格式和配置文件相同；
+/// 返回值要通过vnt_packet_flow_stop释放
+#[no_mangle]
+pub unsafe extern "C" fn vnt_packet_flow_start(
+    config: *const c_char,
+    callbacks: VntPacketFlowCallbacks,
+) -> *mut VntPacketFlow {
+    crate::catch_panic(std::ptr::null_mut(), || match start(config, callbacks) {
+        Ok(packet_flow) => Box::into_raw(Box::new(packet_flow)),
+        Err(e) => {
+            crate::set_last_error(&e);
+            std::ptr::null_mut()
+        }
+    })
+}
+
+unsafe fn start(
+    config: *const c_char,
+    callbacks: VntPacketFlowCallbacks,
+) -> anyhow::Result<VntPacketFlow> {
+    let config = crate::config::parse(config)?;
+    let vnt = Vnt::new_device(
+        config,
+        PacketFlowCallback { callbacks },
+        PacketFlowDevice { callbacks },
+    )?;
+    let sender = match vnt.ipv4_packet_sender() {
+        Some(sender) => sender,
+        None => {
+            vnt.stop();
+            return Err(anyhow::anyhow!("not started"));
+        }
+    };
+    Ok(VntPacketFlow {
+        vnt,
+        sender,
+        buf: Mutex::new((vec![0; BUFFER_SIZE], vec![0; BUFFER_SIZE])),
+    })
+}
+
+/// 传入从packetFlow读取的ip包，目前只转发ipv4，其他包直接丢弃。
+/// 成功返回0，参数错误或内部错误返回-1
+/// 内部panic同样按失败返回，只在以panic=unwind编译时有效
+///
+/// # Safety
+/// packet_flow是vnt_packet_flow_start返回且没有释放的值，data至少有len字节
+#[no_mangle]
+pub unsafe extern "C" fn vnt_packet_flow_input(
+    packet_flow: *const VntPacketFlow,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let packet_flow = match packet_flow.as_ref() {
+        Some(packet_flow) => packet_flow,
+        None => return -1,
+    };
+    if data.is_null() || len < 20 || HEAD_LEN + len > BUFFER_SIZE {
+        return -1;
+    }
+    let data = std::slice::from_raw_parts(data, len);
+    crate::catch_panic(-1, || {
+        if data[0] >> 4 != 4 {
+            return 0;
+        }
+        let dest = Ipv4Addr::new(data[16], data[17], data[18], data[19]);
+        let mut guard = packet_flow.buf.lock();
+        let (buf, extend) = &mut *guard;
+        buf[HEAD_LEN..HEAD_LEN + len].copy_from_slice(data);
+        if let Err(e) = packet_flow
+            .sender
+            .send_ip(buf, HEAD_LEN + len, extend, dest)
+        {
+            log::warn!("send_ip {} {:?}", dest, e);
+        }
+        0
+    })
+}
+
+/// 停止并释放
+///
+/// # Safety
+/// packet_flow是vnt_packet_flow_start返回且没有释放的值，释放后不能再使用
+#[no_mangle]
+pub unsafe extern "C" fn vnt_packet_flow_stop(packet_flow: *mut VntPacketFlow) {
+    if packet_flow.is_null() {
+        return;
+    }
+    let packet_flow = Box::from_raw(packet_flow);
+    crate::catch_panic((), || {
+        packet_flow.vnt.stop();
+        packet_flow.vnt.wait();
+    })
+}
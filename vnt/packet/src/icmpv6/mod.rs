@@ -0,0 +1,114 @@
+use std::net::Ipv6Addr;
+
+use crate::ipv6_cal_checksum;
+
+// ipv6固定首部长度
+const IPV6_HEAD_LEN: usize = 40;
+// 逐跳选项首部，组播监听报文会带上
+const NEXT_HEADER_HOP_BY_HOP: u8 = 0;
+/// icmpv6在ipv6首部中的协议号
+pub const NEXT_HEADER_ICMPV6: u8 = 58;
+
+/// 组播监听查询
+pub const MLD_QUERY: u8 = 130;
+/// 组播监听报告
+pub const MLD_REPORT: u8 = 131;
+/// 组播监听结束
+pub const MLD_DONE: u8 = 132;
+/// 路由器请求
+pub const ROUTER_SOLICITATION: u8 = 133;
+/// 路由器通告
+pub const ROUTER_ADVERTISEMENT: u8 = 134;
+/// 邻居请求
+pub const NEIGHBOR_SOLICITATION: u8 = 135;
+/// 邻居通告
+pub const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+/// 重定向
+pub const REDIRECT: u8 = 137;
+/// 组播监听报告v2
+pub const MLD_V2_REPORT: u8 = 143;
+
+/// 取出ipv6包中的icmpv6报文，只跳过逐跳选项首部
+fn icmpv6_payload(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() < IPV6_HEAD_LEN || packet[0] >> 4 != 6 {
+        return None;
+    }
+    let mut next_header = packet[6];
+    let mut offset = IPV6_HEAD_LEN;
+    if next_header == NEXT_HEADER_HOP_BY_HOP {
+        if packet.len() < offset + 8 {
+            return None;
+        }
+        next_header = packet[offset];
+        offset += (packet[offset + 1] as usize + 1) * 8;
+    }
+    if next_header != NEXT_HEADER_ICMPV6 || packet.len() < offset + 4 {
+        return None;
+    }
+    Some(&packet[offset..])
+}
+
+/// 只在链路上有意义的控制报文(邻居发现、组播监听)，返回icmpv6类型
+pub fn link_control_kind(packet: &[u8]) -> Option<u8> {
+    let kind = icmpv6_payload(packet)?[0];
+    match kind {
+        MLD_QUERY | MLD_REPORT | MLD_DONE | MLD_V2_REPORT => Some(kind),
+        ROUTER_SOLICITATION..=REDIRECT => Some(kind),
+        _ => None,
+    }
+}
+
+/// 邻居请求的来源和目标地址
+pub fn neighbor_solicitation(packet: &[u8]) -> Option<(Ipv6Addr, Ipv6Addr)> {
+    let icmp = icmpv6_payload(packet)?;
+    if icmp[0] != NEIGHBOR_SOLICITATION || icmp.len() < 24 {
+        return None;
+    }
+    let source: [u8; 16] = packet[8..24].try_into().unwrap();
+    let target: [u8; 16] = icmp[8..24].try_into().unwrap();
+    Some((Ipv6Addr::from(source), Ipv6Addr::from(target)))
+}
+
+/// 构建邻居通告，回复给dest，声明target可达，mac不为空时带上目标链路层地址选项
+pub fn neighbor_advertisement(target: Ipv6Addr, dest: Ipv6Addr, mac: Option<[u8; 6]>) -> Vec<u8> {
+    let icmp_len = if mac.is_some() { 32 } else { 24 };
+    let mut buf = vec![0u8; IPV6_HEAD_LEN + icmp_len];
+    buf[0] = 6 << 4;
+    buf[4..6].copy_from_slice(&(icmp_len as u16).to_be_bytes());
+    buf[6] = NEXT_HEADER_ICMPV6;
+    // 邻居发现报文的跳数限制必须是255
+    buf[7] = 255;
+    buf[8..24].copy_from_slice(&target.octets());
+    buf[24..40].copy_from_slice(&dest.octets());
+    let icmp = &mut buf[IPV6_HEAD_LEN..];
+    icmp[0] = NEIGHBOR_ADVERTISEMENT;
+    // Solicited | Override
+    icmp[4] = 0b0110_0000;
+    icmp[8..24].copy_from_slice(&target.octets());
+    if let Some(mac) = mac {
+        icmp[24] = 2;
+        icmp[25] = 1;
+        icmp[26..32].copy_from_slice(&mac);
+    }
+    let checksum = ipv6_cal_checksum(icmp, &target, &dest, NEXT_HEADER_ICMPV6);
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbor_advertisement_checksum() {
+        let target: Ipv6Addr = "fd76:6e74::a1a:3".parse().unwrap();
+        let dest: Ipv6Addr = "fd76:6e74::a1a:2".parse().unwrap();
+        let packet = neighbor_advertisement(target, dest, Some([2, 0, 0, 0, 0, 1]));
+        assert_eq!(link_control_kind(&packet), Some(NEIGHBOR_ADVERTISEMENT));
+        let icmp = &packet[IPV6_HEAD_LEN..];
+        assert_eq!(
+            ipv6_cal_checksum(icmp, &target, &dest, NEXT_HEADER_ICMPV6),
+            0
+        );
+    }
+}
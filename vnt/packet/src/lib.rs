@@ -1,4 +1,4 @@
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt;
@@ -6,6 +6,7 @@ use byteorder::ReadBytesExt;
 pub mod arp;
 pub mod ethernet;
 pub mod icmp;
+pub mod icmpv6;
 pub mod igmp;
 pub mod ip;
 pub mod tcp;
@@ -129,6 +130,38 @@ pub fn ipv4_cal_checksum(
     !sum as u16
 }
 
+/// ipv6上层协议校验和计算方式，伪首部为源地址、目的地址、长度(32)和下一个首部(32)
+pub fn ipv6_cal_checksum(
+    buffer: &[u8],
+    src_ip: &Ipv6Addr,
+    dest_ip: &Ipv6Addr,
+    next_header: u8,
+) -> u16 {
+    let mut sum = 0;
+    for v in src_ip.segments() {
+        sum += u32::from(v);
+    }
+    for v in dest_ip.segments() {
+        sum += u32::from(v);
+    }
+    let length = buffer.len() as u32;
+    sum += length >> 16;
+    sum += length & 0xffff;
+    sum += u32::from(next_header);
+    let mut chunks = buffer.chunks_exact(2);
+    for v in &mut chunks {
+        sum += u32c(v[0], v[1]);
+    }
+    if let [v] = chunks.remainder() {
+        //奇数,说明还有一位
+        sum += u32c(*v, 0);
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !sum as u16
+}
+
 #[inline]
 fn u32c(x: u8, y: u8) -> u32 {
     ((x as u32) << 8) | y as u32
@@ -0,0 +1,129 @@
+use std::fmt::{Display, Formatter};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// 对端除了自己的虚拟ip外还允许使用的来源网段，格式 `<对端虚拟ip|*>=<网段>[,<网段>]`，
+/// 例如 `10.26.0.3=192.168.1.0/24` 允许10.26.0.3转发来自192.168.1.0/24的数据，`*`表示所有对端
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AllowedIps {
+    /// None表示所有对端
+    pub peer: Option<Ipv4Addr>,
+    /// 网段和掩码
+    pub networks: Vec<(u32, u32)>,
+}
+
+impl FromStr for AllowedIps {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |e: &str| {
+            format!(
+                "'{}' {}, example: 10.26.0.3=192.168.1.0/24,192.168.2.0/24",
+                s, e
+            )
+        };
+        let (peer, networks) = match s.split_once('=') {
+            Some(v) => v,
+            None => return Err(err("missing '='")),
+        };
+        let peer = match peer.trim() {
+            "*" => None,
+            peer => Some(Ipv4Addr::from_str(peer).map_err(|_| err("invalid peer ip"))?),
+        };
+        let mut list = Vec::new();
+        for network in networks.split(',') {
+            let network = network.trim();
+            if network.is_empty() {
+                continue;
+            }
+            let (ip, bits) = network.split_once('/').unwrap_or((network, "32"));
+            let ip = Ipv4Addr::from_str(ip).map_err(|_| err("invalid network"))?;
+            let bits = match u32::from_str(bits) {
+                Ok(bits) if bits <= 32 => bits,
+                _ => return Err(err("invalid mask")),
+            };
+            let mask = if bits == 0 {
+                0
+            } else {
+                u32::MAX << (32 - bits)
+            };
+            list.push((u32::from(ip) & mask, mask));
+        }
+        if list.is_empty() {
+            return Err(err("missing network"));
+        }
+        Ok(AllowedIps {
+            peer,
+            networks: list,
+        })
+    }
+}
+
+impl Display for AllowedIps {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.peer {
+            Some(peer) => write!(f, "{}=", peer)?,
+            None => write!(f, "*=")?,
+        }
+        for (index, (network, mask)) in self.networks.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}/{}", Ipv4Addr::from(*network), mask.count_ones())?;
+        }
+        Ok(())
+    }
+}
+
+/// 对端发来的数据的内层来源ip是否合法，防止组网内的成员冒用其他成员的虚拟ip。
+/// 对端自己的虚拟ip、经这个对端路由的网段(in_ips、宣告的路由、出口节点)和配置的网段是合法的，
+/// 虚拟网段内的其他ip只有配置了才合法
+pub fn allow_source(
+    rules: &[AllowedIps],
+    peer: Ipv4Addr,
+    source: Ipv4Addr,
+    virtual_network: (u32, u32),
+    gateway: Option<Ipv4Addr>,
+) -> bool {
+    if source == peer {
+        return true;
+    }
+    let src = u32::from(source);
+    let allowed = rules
+        .iter()
+        .filter(|rule| rule.peer.map_or(true, |v| v == peer))
+        .flat_map(|rule| rule.networks.iter())
+        .any(|(network, mask)| src & mask == *network);
+    if allowed {
+        return true;
+    }
+    let (network, netmask) = virtual_network;
+    if src & netmask == network & netmask {
+        return false;
+    }
+    gateway == Some(peer)
+}
+
+#[test]
+fn test_allowed_ips() {
+    let rule = AllowedIps::from_str("10.26.0.3=192.168.1.7/24, 192.168.2.0/24").unwrap();
+    assert_eq!(rule.to_string(), "10.26.0.3=192.168.1.0/24,192.168.2.0/24");
+    assert!(AllowedIps::from_str("10.26.0.3").is_err());
+    assert!(AllowedIps::from_str("10.26.0.3=").is_err());
+    let rules = vec![AllowedIps::from_str("*=10.26.0.100").unwrap(), rule];
+    let network = (u32::from(Ipv4Addr::new(10, 26, 0, 0)), 0xFFFF_FF00);
+    let peer = Ipv4Addr::new(10, 26, 0, 3);
+    let other = Ipv4Addr::new(10, 26, 0, 4);
+    let extra = Ipv4Addr::new(10, 26, 0, 100);
+    let lan = Ipv4Addr::new(192, 168, 2, 9);
+    let public = Ipv4Addr::new(8, 8, 8, 8);
+    assert!(allow_source(&rules, peer, peer, network, None));
+    // 冒用其他成员的虚拟ip，即使有经这个对端的默认路由也不允许
+    assert!(!allow_source(&[], peer, other, network, Some(peer)));
+    assert!(allow_source(&rules, peer, extra, network, None));
+    assert!(allow_source(&rules, peer, lan, network, None));
+    assert!(!allow_source(&rules, other, lan, network, None));
+    // 出口节点转发的公网数据
+    assert!(allow_source(&[], other, public, network, Some(other)));
+    assert!(!allow_source(&[], other, public, network, Some(peer)));
+}
@@ -0,0 +1,286 @@
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use packet::ip::ipv4::protocol::Protocol;
+use parking_lot::{Mutex, RwLock};
+
+use crate::acl::{AclRule, IpInfo};
+
+// tcp连接空闲超过这个时间后回包不再放行
+const TCP_EXPIRE: Duration = Duration::from_secs(600);
+// udp、icmp等
+const OTHER_EXPIRE: Duration = Duration::from_secs(120);
+// 同时跟踪的连接数，满了以后新的连接只能靠放行规则
+const MAX_FLOWS: usize = 65536;
+
+/// 入方向的放行规则，格式 `any|tcp|udp|icmp [来源ip/掩码位数] [端口[-端口]]`，
+/// 来源ip是对端的虚拟ip，不填时为所有来源，端口是本机的端口，例如 `tcp 22`、`udp 10.26.0.0/24 5000-6000`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FirewallRule(AclRule);
+
+impl FromStr for FirewallRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut items: Vec<&str> = s.split_whitespace().collect();
+        // 没有来源ip时补上0.0.0.0/0，转换成acl规则解析
+        if items.len() < 2 || !items[1].contains('.') {
+            items.insert(1.min(items.len()), "0.0.0.0/0");
+        }
+        match AclRule::from_str(&format!("allow in {}", items.join(" "))) {
+            Ok(rule) => Ok(FirewallRule(rule)),
+            Err(_) => Err(format!(
+                "'{}' invalid, format: any|tcp|udp|icmp [ip/mask] [port[-port]], example: tcp 22, udp 10.26.0.0/24 5000-6000",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for FirewallRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let rule = self.0.to_string();
+        let rule = rule.trim_start_matches("allow in ");
+        match rule.strip_suffix(" 0.0.0.0/0") {
+            Some(rule) => write!(f, "{}", rule),
+            None => write!(f, "{}", rule.replacen(" 0.0.0.0/0 ", " ", 1)),
+        }
+    }
+}
+
+// (协议, 本机端口, 对端ip, 对端端口)
+type FlowKey = (u8, u16, IpAddr, u16);
+
+#[derive(Default)]
+struct Flows {
+    // 本机发起的连接，值是过期时间
+    conns: FnvHashMap<FlowKey, Instant>,
+    // 本机发出数据的(协议, 对端ip)，放行没有端口的后续分片
+    hosts: FnvHashMap<(u8, IpAddr), Instant>,
+}
+
+/// 发给本机虚拟ip的数据的防火墙，开启后默认拒绝，只放行匹配规则的数据和本机主动发起的连接的回包。
+/// ipv4和ipv6都按对端的虚拟ipv4匹配规则，只作用于本机，in_ips转发到其他设备的数据不受影响
+pub struct Firewall {
+    enabled: AtomicBool,
+    rules: RwLock<Vec<FirewallRule>>,
+    // 本机发出的数据，回包在过期前放行
+    flows: Mutex<Flows>,
+    denied: AtomicU64,
+}
+
+impl Firewall {
+    pub fn new(enabled: bool, rules: Vec<FirewallRule>) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            rules: RwLock::new(rules),
+            flows: Mutex::new(Flows::default()),
+            denied: AtomicU64::new(0),
+        }
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+    /// 修改开关和放行规则，返回是否有变化
+    pub fn set(&self, enabled: bool, rules: Vec<FirewallRule>) -> bool {
+        let mut changed = self.enabled.swap(enabled, Ordering::Relaxed) != enabled;
+        let mut guard = self.rules.write();
+        if *guard != rules {
+            *guard = rules;
+            changed = true;
+        }
+        if !enabled {
+            *self.flows.lock() = Flows::default();
+        }
+        changed
+    }
+    pub fn rules(&self) -> Vec<FirewallRule> {
+        self.rules.read().clone()
+    }
+    /// 添加放行规则，已存在时返回false
+    pub fn add(&self, rule: FirewallRule) -> bool {
+        let mut guard = self.rules.write();
+        if guard.contains(&rule) {
+            return false;
+        }
+        guard.push(rule);
+        true
+    }
+    /// 删除放行规则，不存在时返回false
+    pub fn remove(&self, rule: &FirewallRule) -> bool {
+        let mut guard = self.rules.write();
+        let len = guard.len();
+        guard.retain(|v| v != rule);
+        guard.len() != len
+    }
+    /// 本机发出的ip包，记录连接用于放行回包
+    pub fn track_out(&self, packet: &[u8]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let info = match IpInfo::new(packet) {
+            Some(info) => info,
+            None => return,
+        };
+        let protocol: u8 = info.protocol.into();
+        let now = Instant::now();
+        let deadline = now + expire(protocol);
+        let mut guard = self.flows.lock();
+        if let Some((src_port, dest_port)) = info.ports() {
+            let key: FlowKey = (protocol, src_port, info.destination, dest_port);
+            refresh(&mut guard.conns, key, deadline, now);
+        }
+        refresh(
+            &mut guard.hosts,
+            (protocol, info.destination),
+            deadline,
+            now,
+        );
+    }
+    /// 发给本机的ip包是否放行，peer是对端的虚拟ip
+    pub fn allow_in(&self, peer: Ipv4Addr, packet: &[u8]) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let info = match IpInfo::new(packet) {
+            Some(info) => info,
+            None => return self.deny_other(),
+        };
+        let protocol: u8 = info.protocol.into();
+        let now = Instant::now();
+        let port = match info.ports() {
+            Some((src_port, dest_port)) => {
+                let key: FlowKey = (protocol, dest_port, info.source, src_port);
+                if let Some(deadline) = self.flows.lock().conns.get_mut(&key) {
+                    if *deadline > now {
+                        *deadline = now + expire(protocol);
+                        return true;
+                    }
+                }
+                match info.protocol {
+                    Protocol::Tcp | Protocol::Udp => Some(dest_port),
+                    _ => None,
+                }
+            }
+            None => {
+                // 非首个分片没有端口，发给本机发起过连接的对端的放行，否则只按来源和协议匹配规则
+                let host = (protocol, info.source);
+                if let Some(deadline) = self.flows.lock().hosts.get(&host) {
+                    if *deadline > now {
+                        return true;
+                    }
+                }
+                None
+            }
+        };
+        let fragment = info.transport.is_none();
+        let peer = u32::from(peer);
+        if self.rules.read().iter().any(|rule| {
+            let rule = if fragment {
+                AclRule {
+                    ports: None,
+                    ..rule.0
+                }
+            } else {
+                rule.0
+            };
+            rule.matches(false, peer, info.protocol, port)
+        }) {
+            return true;
+        }
+        self.denied.fetch_add(1, Ordering::Relaxed);
+        log::debug!(
+            "防火墙拦截 {}->{} {:?} {:?}",
+            info.source,
+            info.destination,
+            info.protocol,
+            port
+        );
+        false
+    }
+    /// 无法按规则过滤的数据(如二层模式中arp以外的非ip帧)，开启时拒绝
+    pub fn deny_other(&self) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        self.denied.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+    /// 被拦截的数据包数
+    pub fn denied(&self) -> u64 {
+        self.denied.load(Ordering::Relaxed)
+    }
+}
+
+/// 记录或刷新过期时间，满了时先清除过期的，还是满的就不再记录
+fn refresh<K: Eq + Hash>(
+    map: &mut FnvHashMap<K, Instant>,
+    key: K,
+    deadline: Instant,
+    now: Instant,
+) {
+    if map.len() >= MAX_FLOWS && !map.contains_key(&key) {
+        map.retain(|_, v| *v > now);
+        if map.len() >= MAX_FLOWS {
+            return;
+        }
+    }
+    map.insert(key, deadline);
+}
+
+fn expire(protocol: u8) -> Duration {
+    if Protocol::from(protocol) == Protocol::Tcp {
+        TCP_EXPIRE
+    } else {
+        OTHER_EXPIRE
+    }
+}
+
+#[test]
+fn test_firewall() {
+    let rule = FirewallRule::from_str("tcp 22").unwrap();
+    assert_eq!(rule.to_string(), "tcp 22");
+    let rule = FirewallRule::from_str("udp 10.26.0.7/24 5000-6000").unwrap();
+    assert_eq!(rule.to_string(), "udp 10.26.0.0/24 5000-6000");
+    assert_eq!(FirewallRule::from_str("ICMP").unwrap().to_string(), "icmp");
+    assert!(FirewallRule::from_str("icmp 22").is_err());
+    assert!(FirewallRule::from_str("").is_err());
+
+    let firewall = Firewall::new(true, vec![FirewallRule::from_str("tcp 22").unwrap()]);
+    let local = Ipv4Addr::new(10, 26, 0, 2);
+    let peer = Ipv4Addr::new(10, 26, 0, 3);
+    let udp = |src: Ipv4Addr, dest: Ipv4Addr, src_port: u16, dest_port: u16| {
+        let mut buf = vec![0u8; 28];
+        buf[0] = 0x45;
+        buf[3] = 28;
+        buf[8] = 64;
+        buf[9] = Protocol::Udp.into();
+        buf[12..16].copy_from_slice(&src.octets());
+        buf[16..20].copy_from_slice(&dest.octets());
+        buf[20..22].copy_from_slice(&src_port.to_be_bytes());
+        buf[22..24].copy_from_slice(&dest_port.to_be_bytes());
+        buf
+    };
+    let request = udp(peer, local, 5353, 53);
+    assert!(!firewall.allow_in(peer, &request));
+    // 非首个分片中的数据不能当作端口
+    let mut fragment = udp(peer, local, 53, 40000);
+    fragment[7] = 1;
+    assert!(!firewall.allow_in(peer, &fragment));
+    // 本机发起的连接的回包放行
+    let out = udp(local, peer, 40000, 53);
+    firewall.track_out(&out);
+    let reply = udp(peer, local, 53, 40000);
+    assert!(firewall.allow_in(peer, &reply));
+    assert!(firewall.allow_in(peer, &fragment));
+    assert_eq!(firewall.denied(), 2);
+    // 无法解析的数据
+    assert!(!firewall.allow_in(peer, &request[..10]));
+    assert!(firewall.set(false, vec![]));
+    assert!(firewall.allow_in(peer, &request));
+}
@@ -0,0 +1,460 @@
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use packet::ip::ipv4::packet::IpV4Packet;
+use packet::ip::ipv4::protocol::Protocol;
+use parking_lot::RwLock;
+
+use crate::acl::allowed_ips::AllowedIps;
+use crate::acl::firewall::Firewall;
+
+pub mod allowed_ips;
+pub mod firewall;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AclAction {
+    Allow,
+    Deny,
+}
+
+/// 规则作用的方向，in是对端发给本机的数据，out是本机发给对端的数据
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AclDirection {
+    In,
+    Out,
+    Both,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AclProtocol {
+    Any,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+/// 一条访问控制规则，格式 `allow|deny [in|out] any|tcp|udp|icmp <ip/掩码位数> [端口[-端口]]`，
+/// ip是对端的虚拟ip，端口是数据包的目的端口，例如 `deny in tcp 10.26.0.0/24 22`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AclRule {
+    pub action: AclAction,
+    pub direction: AclDirection,
+    pub protocol: AclProtocol,
+    pub network: u32,
+    pub mask: u32,
+    pub ports: Option<(u16, u16)>,
+}
+
+impl FromStr for AclRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |e: &str| {
+            format!(
+                "'{}' {}, example: deny in tcp 10.26.0.0/24 22, allow out udp 10.26.0.2/32 5000-6000",
+                s, e
+            )
+        };
+        let mut items = s.split_whitespace().peekable();
+        let action = match items.next().map(|v| v.to_lowercase()).as_deref() {
+            Some("allow") => AclAction::Allow,
+            Some("deny") => AclAction::Deny,
+            _ => return Err(err("action must be allow/deny")),
+        };
+        let direction = match items.peek().map(|v| v.to_lowercase()).as_deref() {
+            Some("in") => AclDirection::In,
+            Some("out") => AclDirection::Out,
+            _ => AclDirection::Both,
+        };
+        if direction != AclDirection::Both {
+            items.next();
+        }
+        let protocol = match items.next().map(|v| v.to_lowercase()).as_deref() {
+            Some("any") => AclProtocol::Any,
+            Some("tcp") => AclProtocol::Tcp,
+            Some("udp") => AclProtocol::Udp,
+            Some("icmp") => AclProtocol::Icmp,
+            _ => return Err(err("protocol must be any/tcp/udp/icmp")),
+        };
+        let (ip, bits) = match items.next() {
+            Some(v) => match v.split_once('/') {
+                Some((ip, bits)) => (ip, bits),
+                None => (v, "32"),
+            },
+            None => return Err(err("missing ip")),
+        };
+        let ip = Ipv4Addr::from_str(ip).map_err(|_| err("invalid ip"))?;
+        let bits = match u32::from_str(bits) {
+            Ok(bits) if bits <= 32 => bits,
+            _ => return Err(err("invalid mask")),
+        };
+        let mask = if bits == 0 {
+            0
+        } else {
+            u32::MAX << (32 - bits)
+        };
+        let ports = match items.next() {
+            Some(v) => {
+                if protocol != AclProtocol::Tcp && protocol != AclProtocol::Udp {
+                    return Err(err("port only supports tcp/udp"));
+                }
+                let (start, end) = v.split_once('-').unwrap_or((v, v));
+                let start = u16::from_str(start).map_err(|_| err("invalid port"))?;
+                let end = u16::from_str(end).map_err(|_| err("invalid port"))?;
+                if start > end {
+                    return Err(err("invalid port range"));
+                }
+                Some((start, end))
+            }
+            None => None,
+        };
+        if items.next().is_some() {
+            return Err(err("too many items"));
+        }
+        Ok(AclRule {
+            action,
+            direction,
+            protocol,
+            network: u32::from(ip) & mask,
+            mask,
+            ports,
+        })
+    }
+}
+
+impl Display for AclRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let action = match self.action {
+            AclAction::Allow => "allow",
+            AclAction::Deny => "deny",
+        };
+        let direction = match self.direction {
+            AclDirection::In => " in",
+            AclDirection::Out => " out",
+            AclDirection::Both => "",
+        };
+        let protocol = match self.protocol {
+            AclProtocol::Any => "any",
+            AclProtocol::Tcp => "tcp",
+            AclProtocol::Udp => "udp",
+            AclProtocol::Icmp => "icmp",
+        };
+        write!(
+            f,
+            "{}{} {} {}/{}",
+            action,
+            direction,
+            protocol,
+            Ipv4Addr::from(self.network),
+            self.mask.count_ones()
+        )?;
+        match self.ports {
+            Some((start, end)) if start == end => write!(f, " {}", start),
+            Some((start, end)) => write!(f, " {}-{}", start, end),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 过滤时用到的ip包信息，ipv6会跳过扩展首部，icmpv6当作icmp处理
+pub(crate) struct IpInfo<'a> {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub protocol: Protocol,
+    // 上层数据，非首个分片没有上层首部，为None
+    pub transport: Option<&'a [u8]>,
+}
+
+impl<'a> IpInfo<'a> {
+    /// 解析ipv4或ipv6包，格式不对时返回None
+    pub fn new(packet: &'a [u8]) -> Option<Self> {
+        match packet.first().map(|v| v >> 4) {
+            Some(4) => {
+                let ipv4 = IpV4Packet::new(packet).ok()?;
+                let header_len = ipv4.header_len() as usize * 4;
+                let transport = if ipv4.offset() == 0 {
+                    Some(&packet[header_len..])
+                } else {
+                    None
+                };
+                Some(Self {
+                    source: ipv4.source_ip().into(),
+                    destination: ipv4.destination_ip().into(),
+                    protocol: ipv4.protocol(),
+                    transport,
+                })
+            }
+            Some(6) => Self::ipv6(packet),
+            _ => None,
+        }
+    }
+    fn ipv6(packet: &'a [u8]) -> Option<Self> {
+        if packet.len() < 40 {
+            return None;
+        }
+        let source: [u8; 16] = packet[8..24].try_into().unwrap();
+        let destination: [u8; 16] = packet[24..40].try_into().unwrap();
+        let mut next_header = packet[6];
+        let mut offset = 40;
+        let mut first = true;
+        while first {
+            match next_header {
+                // 逐跳选项、路由、目的选项
+                0 | 43 | 60 => {
+                    if packet.len() < offset + 8 {
+                        return None;
+                    }
+                    next_header = packet[offset];
+                    offset += (packet[offset + 1] as usize + 1) * 8;
+                }
+                // 分片，偏移不为0时后面不是上层首部
+                44 => {
+                    if packet.len() < offset + 8 {
+                        return None;
+                    }
+                    next_header = packet[offset];
+                    first = u16::from_be_bytes([packet[offset + 2], packet[offset + 3]]) >> 3 == 0;
+                    offset += 8;
+                }
+                // 认证首部
+                51 => {
+                    if packet.len() < offset + 8 {
+                        return None;
+                    }
+                    next_header = packet[offset];
+                    offset += (packet[offset + 1] as usize + 2) * 4;
+                }
+                _ => break,
+            }
+        }
+        if packet.len() < offset {
+            return None;
+        }
+        let protocol = if next_header == 58 {
+            Protocol::Icmp
+        } else {
+            Protocol::from(next_header)
+        };
+        Some(Self {
+            source: Ipv6Addr::from(source).into(),
+            destination: Ipv6Addr::from(destination).into(),
+            protocol,
+            transport: first.then(|| &packet[offset..]),
+        })
+    }
+    /// 来源和目的端口，icmp查询报文用标识符当作两端的端口，其他协议为0，非首个分片返回None
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        let payload = self.transport?;
+        let ports = match self.protocol {
+            Protocol::Tcp | Protocol::Udp if payload.len() >= 4 => (
+                u16::from_be_bytes([payload[0], payload[1]]),
+                u16::from_be_bytes([payload[2], payload[3]]),
+            ),
+            Protocol::Icmp if payload.len() >= 6 => {
+                let id = u16::from_be_bytes([payload[4], payload[5]]);
+                (id, id)
+            }
+            _ => (0, 0),
+        };
+        Some(ports)
+    }
+    /// 规则匹配的端口，只有tcp和udp有
+    fn rule_port(&self) -> Option<u16> {
+        match self.protocol {
+            Protocol::Tcp | Protocol::Udp => match self.transport {
+                Some(payload) if payload.len() >= 4 => {
+                    Some(u16::from_be_bytes([payload[2], payload[3]]))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// 以太网帧中的ipv4或ipv6包
+pub(crate) fn ethernet_ip(frame: &[u8]) -> Option<&[u8]> {
+    if frame.len() < 14 {
+        return None;
+    }
+    match u16::from_be_bytes([frame[12], frame[13]]) {
+        0x0800 | 0x86DD => Some(&frame[14..]),
+        _ => None,
+    }
+}
+
+impl AclRule {
+    fn matches(&self, out: bool, peer: u32, protocol: Protocol, port: Option<u16>) -> bool {
+        match self.direction {
+            AclDirection::In if out => return false,
+            AclDirection::Out if !out => return false,
+            _ => {}
+        }
+        if peer & self.mask != self.network {
+            return false;
+        }
+        let protocol_match = match self.protocol {
+            AclProtocol::Any => true,
+            AclProtocol::Tcp => protocol == Protocol::Tcp,
+            AclProtocol::Udp => protocol == Protocol::Udp,
+            AclProtocol::Icmp => protocol == Protocol::Icmp,
+        };
+        if !protocol_match {
+            return false;
+        }
+        match (self.ports, port) {
+            (None, _) => true,
+            (Some((start, end)), Some(port)) => start <= port && port <= end,
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// 客户端之间的访问控制，对进出虚拟网卡的ip数据按顺序匹配规则，ipv6和二层模式中的ip数据按对端的虚拟ipv4匹配，
+/// 第一条匹配的规则生效，都不匹配时放行。规则是无状态的，只看单个数据包
+pub struct Acl {
+    rules: RwLock<Vec<AclRule>>,
+    denied: AtomicU64,
+    // 对端额外允许的来源网段
+    allowed_ips: RwLock<Vec<AllowedIps>>,
+    // 发给本机的数据的防火墙
+    firewall: Firewall,
+}
+
+impl Acl {
+    pub fn new(rules: Vec<AclRule>, allowed_ips: Vec<AllowedIps>, firewall: Firewall) -> Self {
+        Self {
+            rules: RwLock::new(rules),
+            denied: AtomicU64::new(0),
+            allowed_ips: RwLock::new(allowed_ips),
+            firewall,
+        }
+    }
+    pub fn firewall(&self) -> &Firewall {
+        &self.firewall
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rules.read().is_empty()
+    }
+    /// 替换全部规则，返回规则是否有变化
+    pub fn set_rules(&self, rules: Vec<AclRule>) -> bool {
+        let mut guard = self.rules.write();
+        if *guard == rules {
+            return false;
+        }
+        *guard = rules;
+        true
+    }
+    /// 替换对端允许的来源网段，返回是否有变化
+    pub fn set_allowed_ips(&self, allowed_ips: Vec<AllowedIps>) -> bool {
+        let mut guard = self.allowed_ips.write();
+        if *guard == allowed_ips {
+            return false;
+        }
+        *guard = allowed_ips;
+        true
+    }
+    /// 对端发来的数据的内层来源ip是否合法，gateway是路由到来源ip的网关
+    pub fn allow_source(
+        &self,
+        peer: Ipv4Addr,
+        source: Ipv4Addr,
+        virtual_network: (u32, u32),
+        gateway: Option<Ipv4Addr>,
+    ) -> bool {
+        let allow = allowed_ips::allow_source(
+            &self.allowed_ips.read(),
+            peer,
+            source,
+            virtual_network,
+            gateway,
+        );
+        if !allow {
+            log::debug!("丢弃来源ip不合法的数据 peer={} source={}", peer, source);
+        }
+        allow
+    }
+    /// 发给对端的ip包，peer是目的虚拟ip
+    pub fn allow_out(&self, peer: Ipv4Addr, packet: &[u8]) -> bool {
+        self.allow(true, peer, packet)
+    }
+    /// 对端发来的ip包，peer是来源虚拟ip
+    pub fn allow_in(&self, peer: Ipv4Addr, packet: &[u8]) -> bool {
+        self.allow(false, peer, packet)
+    }
+    fn allow(&self, out: bool, peer: Ipv4Addr, packet: &[u8]) -> bool {
+        let rules = self.rules.read();
+        if rules.is_empty() {
+            return true;
+        }
+        let info = match IpInfo::new(packet) {
+            Some(info) => info,
+            None => {
+                // 有规则时不放行无法解析的数据，避免绕过规则
+                self.denied.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        };
+        let port = info.rule_port();
+        let peer = u32::from(peer);
+        for rule in rules.iter() {
+            if rule.matches(out, peer, info.protocol, port) {
+                if rule.action == AclAction::Deny {
+                    self.denied.fetch_add(1, Ordering::Relaxed);
+                    log::debug!(
+                        "acl拦截 {} {}->{} {:?} {:?}",
+                        rule,
+                        info.source,
+                        info.destination,
+                        info.protocol,
+                        port
+                    );
+                    return false;
+                }
+                return true;
+            }
+        }
+        true
+    }
+    /// 被拦截的数据包数
+    pub fn denied(&self) -> u64 {
+        self.denied.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn test_acl_rule() {
+    let rule = AclRule::from_str("deny in tcp 10.26.0.7/24 22").unwrap();
+    assert_eq!(rule.to_string(), "deny in tcp 10.26.0.0/24 22");
+    let rule = AclRule::from_str("allow udp 10.26.0.2 5000-6000").unwrap();
+    assert_eq!(rule.direction, AclDirection::Both);
+    assert_eq!(rule.ports, Some((5000, 6000)));
+    assert_eq!(
+        AclRule::from_str("DENY OUT any 0.0.0.0/0")
+            .unwrap()
+            .to_string(),
+        "deny out any 0.0.0.0/0"
+    );
+    assert!(AclRule::from_str("deny icmp 10.26.0.2 22").is_err());
+    assert!(AclRule::from_str("deny tcp 10.26.0.2/33").is_err());
+    assert!(AclRule::from_str("drop tcp 10.26.0.2").is_err());
+
+    // ipv6按对端的虚拟ipv4匹配，跳过扩展首部
+    let acl = Acl::new(
+        vec![AclRule::from_str("deny in tcp 10.26.0.3 22").unwrap()],
+        vec![],
+        Firewall::new(false, vec![]),
+    );
+    let peer = Ipv4Addr::new(10, 26, 0, 3);
+    let mut ipv6 = vec![0u8; 40 + 8 + 20];
+    ipv6[0] = 0x60;
+    ipv6[6] = 0;
+    ipv6[40] = 6;
+    ipv6[48 + 2..48 + 4].copy_from_slice(&22u16.to_be_bytes());
+    assert!(!acl.allow_in(peer, &ipv6));
+    assert!(acl.allow_out(peer, &ipv6));
+    assert!(acl.allow_in(Ipv4Addr::new(10, 26, 0, 4), &ipv6));
+    assert!(!acl.allow_in(peer, &ipv6[..20]));
+}
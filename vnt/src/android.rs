@@ -0,0 +1,49 @@
+//! 供JNI层调用的入口，参数和返回值只有jlong/jint，JNI函数中可以直接转发。
+//! 句柄是Box<Vnt>的指针，由into_handle生成，vnt_android_release释放
+
+use std::os::raw::c_int;
+
+use crate::core::Vnt;
+
+/// 转换成句柄交给Java层保存
+pub fn into_handle(vnt: Vnt) -> i64 {
+    Box::into_raw(Box::new(vnt)) as i64
+}
+
+/// # Safety
+/// handle必须是into_handle返回且没有释放的值
+pub unsafe fn from_handle<'a>(handle: i64) -> Option<&'a Vnt> {
+    (handle as *const Vnt).as_ref()
+}
+
+/// 传入VpnService的fd，成功返回0，失败返回-1
+///
+/// # Safety
+/// handle必须是into_handle返回且没有释放的值
+#[no_mangle]
+pub unsafe extern "C" fn vnt_android_attach_tun_fd(handle: i64, fd: c_int) -> c_int {
+    let vnt = match from_handle(handle) {
+        Some(vnt) => vnt,
+        None => return -1,
+    };
+    match vnt.attach_tun_fd(fd) {
+        Ok(_) => 0,
+        Err(e) => {
+            log::error!("attach_tun_fd {:?}", e);
+            -1
+        }
+    }
+}
+
+/// 停止并释放句柄
+///
+/// # Safety
+/// handle必须是into_handle返回且没有释放的值，释放后不能再使用
+#[no_mangle]
+pub unsafe extern "C" fn vnt_android_release(handle: i64) {
+    if handle == 0 {
+        return;
+    }
+    let vnt = Box::from_raw(handle as *mut Vnt);
+    vnt.stop();
+}
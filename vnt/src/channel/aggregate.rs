@@ -0,0 +1,188 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+
+use crate::protocol::{NetPacket, Protocol, HEAD_LEN};
+
+/// 不超过这个长度的包才会被合并
+pub const SMALL_PACKET_LEN: usize = 256;
+// 合并后包的最大长度，留出混淆和fec的空间，避免超过路径mtu
+const MAX_AGGREGATE_LEN: usize = 1300;
+
+/// 等待合并的包
+struct Pending {
+    // 包头+多个 长度(16)+加密包
+    buf: Vec<u8>,
+    count: usize,
+    start: Instant,
+    server_addr: SocketAddr,
+    send_default: bool,
+}
+
+impl Pending {
+    fn new(head: &[u8], server_addr: SocketAddr, send_default: bool) -> Self {
+        let mut buf = Vec::with_capacity(MAX_AGGREGATE_LEN);
+        buf.extend_from_slice(&head[..HEAD_LEN]);
+        Self {
+            buf,
+            count: 0,
+            start: Instant::now(),
+            server_addr,
+            send_default,
+        }
+    }
+    fn push(&mut self, packet: &[u8]) {
+        self.buf
+            .extend_from_slice(&(packet.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(packet);
+        self.count += 1;
+    }
+    /// 只有一个包时原样发送，省掉合并的开销
+    fn finish(self) -> (Vec<u8>, SocketAddr, bool) {
+        if self.count == 1 {
+            return (
+                self.buf[HEAD_LEN + 2..].to_vec(),
+                self.server_addr,
+                self.send_default,
+            );
+        }
+        let mut buf = self.buf;
+        let mut net_packet = NetPacket::unchecked(&mut buf);
+        net_packet.set_encrypt_flag(false);
+        net_packet.set_extension_flag(false);
+        net_packet.set_gateway_flag(false);
+        net_packet.set_protocol(Protocol::Aggregate);
+        net_packet.set_transport_protocol(0);
+        (buf, self.server_addr, self.send_default)
+    }
+}
+
+/// 小包合并，发往同一对端的小包在delay时间内合并成一个udp包发送，减少每个包的开销
+pub struct Aggregator {
+    delay: Duration,
+    pending: Mutex<FnvHashMap<Ipv4Addr, Pending>>,
+}
+
+impl Aggregator {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            pending: Mutex::new(FnvHashMap::default()),
+        }
+    }
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+    /// 缓存要发给id的包，返回需要立即发送的包和当前包是否已被缓存，
+    /// 大包不缓存，但是要先发出之前缓存的小包，保证顺序
+    pub fn push<B: AsRef<[u8]>>(
+        &self,
+        net_packet: &NetPacket<B>,
+        id: &Ipv4Addr,
+        server_addr: SocketAddr,
+        send_default: bool,
+    ) -> (Option<(Vec<u8>, SocketAddr, bool)>, bool) {
+        let packet = net_packet.buffer();
+        let mut guard = self.pending.lock();
+        if packet.len() > SMALL_PACKET_LEN {
+            return (guard.remove(id).map(|pending| pending.finish()), false);
+        }
+        let mut flush = None;
+        let pending = guard
+            .entry(*id)
+            .or_insert_with(|| Pending::new(packet, server_addr, send_default));
+        if pending.buf.len() + 2 + packet.len() > MAX_AGGREGATE_LEN {
+            let old = std::mem::replace(pending, Pending::new(packet, server_addr, send_default));
+            flush.replace(old.finish());
+        }
+        pending.server_addr = server_addr;
+        pending.send_default = send_default;
+        pending.push(packet);
+        (flush, true)
+    }
+    /// 取出等待超过delay的包
+    pub fn take_expired(&self) -> Vec<(Ipv4Addr, (Vec<u8>, SocketAddr, bool))> {
+        let mut guard = self.pending.lock();
+        if guard.is_empty() {
+            return Vec::new();
+        }
+        let now = Instant::now();
+        let expired: Vec<Ipv4Addr> = guard
+            .iter()
+            .filter(|(_, pending)| now.duration_since(pending.start) >= self.delay)
+            .map(|(id, _)| *id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| guard.remove(&id).map(|pending| (id, pending.finish())))
+            .collect()
+    }
+    /// 取出所有缓存的包，退出时使用
+    pub fn take_all(&self) -> Vec<(Ipv4Addr, (Vec<u8>, SocketAddr, bool))> {
+        self.pending
+            .lock()
+            .drain()
+            .map(|(id, pending)| (id, pending.finish()))
+            .collect()
+    }
+}
+
+/// 拆分合并的包，返回每个包在payload中的范围
+pub fn split(payload: &[u8]) -> Vec<std::ops::Range<usize>> {
+    let mut list = Vec::new();
+    let mut offset = 0;
+    while offset + 2 <= payload.len() {
+        let len = u16::from_be_bytes([payload[offset], payload[offset + 1]]) as usize;
+        let start = offset + 2;
+        if len < HEAD_LEN || start + len > payload.len() {
+            break;
+        }
+        list.push(start..start + len);
+        offset = start + len;
+    }
+    list
+}
+
+#[test]
+fn test_aggregate() {
+    let aggregator = Aggregator::new(Duration::from_millis(0));
+    let id = Ipv4Addr::new(10, 26, 0, 3);
+    let server_addr: SocketAddr = "127.0.0.1:29872".parse().unwrap();
+    let mut packets = Vec::new();
+    for i in 0..3u8 {
+        let mut buf = vec![i; HEAD_LEN + 20 + i as usize];
+        let mut net_packet = NetPacket::new(&mut buf).unwrap();
+        net_packet.set_protocol(Protocol::IpTurn);
+        let (flush, pushed) = aggregator.push(&net_packet, &id, server_addr, true);
+        assert!(flush.is_none() && pushed);
+        packets.push(buf);
+    }
+    let mut expired = aggregator.take_expired();
+    assert_eq!(expired.len(), 1);
+    let (_, (buf, _, _)) = expired.pop().unwrap();
+    let net_packet = NetPacket::new(&buf).unwrap();
+    assert_eq!(net_packet.protocol(), Protocol::Aggregate);
+    let payload = net_packet.payload();
+    let list: Vec<&[u8]> = split(payload)
+        .into_iter()
+        .map(|range| &payload[range])
+        .collect();
+    assert_eq!(list, packets);
+}
+
+#[test]
+fn test_aggregate_take_all() {
+    let aggregator = Aggregator::new(Duration::from_secs(60));
+    let id = Ipv4Addr::new(10, 26, 0, 3);
+    let server_addr: SocketAddr = "127.0.0.1:29872".parse().unwrap();
+    let mut buf = vec![1; HEAD_LEN + 20];
+    let mut net_packet = NetPacket::new(&mut buf).unwrap();
+    net_packet.set_protocol(Protocol::IpTurn);
+    let (flush, pushed) = aggregator.push(&net_packet, &id, server_addr, true);
+    assert!(flush.is_none() && pushed);
+    assert!(aggregator.take_expired().is_empty());
+    assert_eq!(aggregator.take_all().len(), 1);
+    assert!(aggregator.take_all().is_empty());
+}
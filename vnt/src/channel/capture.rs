@@ -0,0 +1,349 @@
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::channel::ConnectProtocol;
+use crate::external_route::parse_network;
+
+// 写文件跟不上时最多缓存的包数，超过的直接丢弃，不能影响数据转发
+const QUEUE_LEN: usize = 4096;
+// 每个包最多保存的字节数
+const SNAP_LEN: usize = 65535;
+// pcapng的链路类型
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+// 外层的vnt协议包，wireshark中显示为原始数据
+const LINKTYPE_USER0: u16 = 147;
+// 内层ip包和外层vnt包分别写到两个接口
+const INNER_INTERFACE: u32 = 0;
+const OUTER_INTERFACE: u32 = 1;
+
+/// 抓包配置，格式 `<文件>[:过滤条件]`，过滤条件用逗号分隔，例如 `vnt.pcap:10.26.0.3,tcp,port=80,outer`
+/// - 虚拟ip或网段：源或目标在其中
+/// - tcp/udp/icmp：内层ip包的协议
+/// - port=<端口>：tcp/udp的源或目标端口
+/// - outer：同时保存收发的外层vnt协议包，包注释中带有协议和地址
+///
+/// 同一类的多个条件满足一个即可，不同类的条件都要满足
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CaptureConfig {
+    pub path: String,
+    pub filter: CaptureFilter,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CaptureFilter {
+    hosts: Vec<(u32, u32)>,
+    protocols: Vec<u8>,
+    ports: Vec<u16>,
+    outer: bool,
+}
+
+impl FromStr for CaptureConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // windows的盘符也带有冒号，例如 C:\vnt.pcap
+        let (path, filter) = match s.rsplit_once(':') {
+            Some((path, filter)) if path.len() > 1 && !filter.contains('\\') => {
+                (path, CaptureFilter::from_str(filter)?)
+            }
+            _ => (s, CaptureFilter::default()),
+        };
+        if path.is_empty() {
+            return Err(format!("'{}' missing file", s));
+        }
+        Ok(CaptureConfig {
+            path: path.to_string(),
+            filter,
+        })
+    }
+}
+
+impl FromStr for CaptureFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = CaptureFilter::default();
+        for item in s.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            match item.to_lowercase().as_str() {
+                "outer" => filter.outer = true,
+                "tcp" => filter.protocols.push(6),
+                "udp" => filter.protocols.push(17),
+                "icmp" => filter.protocols.push(1),
+                v => {
+                    if let Some(port) = v.strip_prefix("port=") {
+                        let port =
+                            u16::from_str(port).map_err(|_| format!("'{}' invalid port", item))?;
+                        filter.ports.push(port);
+                    } else {
+                        let network = parse_network(v).map_err(|e| {
+                            format!("'{}' {}, example: 10.26.0.3,tcp,port=80,outer", item, e)
+                        })?;
+                        filter.hosts.push(network);
+                    }
+                }
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl CaptureFilter {
+    fn match_host(&self, ips: &[Ipv4Addr]) -> bool {
+        self.hosts.is_empty()
+            || self
+                .hosts
+                .iter()
+                .any(|(network, mask)| ips.iter().any(|ip| u32::from(*ip) & *mask == *network))
+    }
+    /// 内层的ip包或者以太网帧
+    fn match_inner(&self, buf: &[u8], layer2: bool) -> bool {
+        let buf = if layer2 {
+            // 只看ipv4和ipv6的帧
+            match buf.get(12..14) {
+                Some([0x08, 0x00]) | Some([0x86, 0xDD]) => &buf[14..],
+                _ => {
+                    return self.hosts.is_empty()
+                        && self.protocols.is_empty()
+                        && self.ports.is_empty()
+                }
+            }
+        } else {
+            buf
+        };
+        let (ips, protocol, payload) = match buf.first().map(|v| v >> 4) {
+            Some(4) if buf.len() >= 20 => {
+                let head_len = ((buf[0] & 0x0F) as usize * 4).min(buf.len());
+                let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+                let dest = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+                (vec![src, dest], buf[9], &buf[head_len..])
+            }
+            Some(6) if buf.len() >= 40 => (vec![], buf[6], &buf[40..]),
+            _ => return false,
+        };
+        if !self.match_host(&ips) {
+            return false;
+        }
+        if !self.protocols.is_empty() && !self.protocols.contains(&protocol) {
+            return false;
+        }
+        if !self.ports.is_empty() {
+            if (protocol != 6 && protocol != 17) || payload.len() < 4 {
+                return false;
+            }
+            let src_port = u16::from_be_bytes([payload[0], payload[1]]);
+            let dest_port = u16::from_be_bytes([payload[2], payload[3]]);
+            if !self.ports.contains(&src_port) && !self.ports.contains(&dest_port) {
+                return false;
+            }
+        }
+        true
+    }
+    /// 外层的vnt协议包，按头部中的虚拟ip过滤
+    fn match_outer(&self, buf: &[u8]) -> bool {
+        if !self.outer || buf.len() < 12 {
+            return false;
+        }
+        let src = Ipv4Addr::new(buf[4], buf[5], buf[6], buf[7]);
+        let dest = Ipv4Addr::new(buf[8], buf[9], buf[10], buf[11]);
+        self.match_host(&[src, dest])
+    }
+}
+
+struct Record {
+    interface: u32,
+    inbound: bool,
+    // 微秒
+    time: u64,
+    comment: Option<String>,
+    data: Vec<u8>,
+}
+
+/// 把解密后的内层ip包(以及外层的vnt协议包)写到pcapng文件或者命名管道，
+/// 在其他线程写入，写入出错(例如wireshark关闭了管道)后停止抓包
+pub struct Capture {
+    filter: CaptureFilter,
+    layer2: bool,
+    sender: SyncSender<Record>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Capture {
+    pub fn new(config: CaptureConfig, layer2: bool) -> io::Result<Self> {
+        let (sender, receiver) = sync_channel(QUEUE_LEN);
+        let closed = Arc::new(AtomicBool::new(false));
+        let path = config.path.clone();
+        let link_type = if layer2 {
+            LINKTYPE_ETHERNET
+        } else {
+            LINKTYPE_RAW
+        };
+        let closed0 = closed.clone();
+        std::thread::Builder::new()
+            .name("captureWriter".into())
+            .spawn(move || {
+                // 命名管道在wireshark打开之前会阻塞在这里
+                if let Err(e) = write_loop(&path, link_type, receiver) {
+                    log::warn!("抓包停止 {} {:?}", path, e);
+                }
+                closed0.store(true, Ordering::Relaxed);
+            })?;
+        log::info!("抓包 {} {:?}", config.path, config.filter);
+        Ok(Self {
+            filter: config.filter,
+            layer2,
+            sender,
+            closed,
+        })
+    }
+    /// 虚拟网卡收发的数据，inbound表示写入虚拟网卡
+    pub fn inner(&self, buf: &[u8], inbound: bool) {
+        if self.filter.match_inner(buf, self.layer2) {
+            self.push(INNER_INTERFACE, inbound, None, buf);
+        }
+    }
+    /// 收发的外层数据，在注释中记录协议和对端地址
+    pub fn outer(&self, buf: &[u8], inbound: bool, protocol: ConnectProtocol, addr: SocketAddr) {
+        if self.filter.match_outer(buf) {
+            let comment = format!(
+                "{} {:?} {}",
+                if inbound { "from" } else { "to" },
+                protocol,
+                addr
+            );
+            self.push(OUTER_INTERFACE, inbound, Some(comment), buf);
+        }
+    }
+    fn push(&self, interface: u32, inbound: bool, comment: Option<String>, buf: &[u8]) {
+        if self.closed.load(Ordering::Relaxed) {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |v| v.as_micros() as u64);
+        let record = Record {
+            interface,
+            inbound,
+            time,
+            comment,
+            data: buf[..buf.len().min(SNAP_LEN)].to_vec(),
+        };
+        if let Err(TrySendError::Disconnected(_)) = self.sender.try_send(record) {
+            self.closed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+fn write_loop(path: &str, link_type: u16, receiver: Receiver<Record>) -> io::Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    let mut writer = io::BufWriter::new(file);
+    write_header(&mut writer, link_type)?;
+    writer.flush()?;
+    while let Ok(record) = receiver.recv() {
+        write_packet(&mut writer, &record)?;
+        // 没有更多的包时再刷新，wireshark实时查看时不会有延迟
+        while let Ok(record) = receiver.try_recv() {
+            write_packet(&mut writer, &record)?;
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+// 块的内容按4字节对齐
+fn pad4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn write_header<W: Write>(writer: &mut W, link_type: u16) -> io::Result<()> {
+    // Section Header Block
+    writer.write_all(&0x0A0D0D0Au32.to_le_bytes())?;
+    writer.write_all(&28u32.to_le_bytes())?;
+    writer.write_all(&0x1A2B3C4Du32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+    writer.write_all(&(-1i64).to_le_bytes())?;
+    writer.write_all(&28u32.to_le_bytes())?;
+    // Interface Description Block，时间精度默认为微秒
+    for link_type in [link_type, LINKTYPE_USER0] {
+        writer.write_all(&1u32.to_le_bytes())?;
+        writer.write_all(&20u32.to_le_bytes())?;
+        writer.write_all(&link_type.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&(SNAP_LEN as u32).to_le_bytes())?;
+        writer.write_all(&20u32.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_packet<W: Write>(writer: &mut W, record: &Record) -> io::Result<()> {
+    let data_len = record.data.len();
+    let comment_len = record.comment.as_ref().map_or(0, |v| v.len());
+    let comment_option_len = if comment_len > 0 {
+        4 + pad4(comment_len)
+    } else {
+        0
+    };
+    // epb_flags + opt_comment + opt_endofopt
+    let options_len = 8 + comment_option_len + 4;
+    let total_len = (32 + pad4(data_len) + options_len) as u32;
+    writer.write_all(&6u32.to_le_bytes())?;
+    writer.write_all(&total_len.to_le_bytes())?;
+    writer.write_all(&record.interface.to_le_bytes())?;
+    writer.write_all(&((record.time >> 32) as u32).to_le_bytes())?;
+    writer.write_all(&(record.time as u32).to_le_bytes())?;
+    writer.write_all(&(data_len as u32).to_le_bytes())?;
+    writer.write_all(&(data_len as u32).to_le_bytes())?;
+    writer.write_all(&record.data)?;
+    writer.write_all(&[0u8; 3][..pad4(data_len) - data_len])?;
+    // 方向 1:收 2:发
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&4u16.to_le_bytes())?;
+    writer.write_all(&(if record.inbound { 1u32 } else { 2u32 }).to_le_bytes())?;
+    if let Some(comment) = &record.comment {
+        if comment_len > 0 {
+            writer.write_all(&1u16.to_le_bytes())?;
+            writer.write_all(&(comment_len as u16).to_le_bytes())?;
+            writer.write_all(comment.as_bytes())?;
+            writer.write_all(&[0u8; 3][..pad4(comment_len) - comment_len])?;
+        }
+    }
+    writer.write_all(&[0u8; 4])?;
+    writer.write_all(&total_len.to_le_bytes())
+}
+
+#[test]
+fn test_capture_config() {
+    let config = CaptureConfig::from_str("vnt.pcap:10.26.0.0/24,tcp,port=80,outer").unwrap();
+    assert_eq!(config.path, "vnt.pcap");
+    assert!(config.filter.outer);
+    let config = CaptureConfig::from_str(r"C:\vnt.pcap").unwrap();
+    assert_eq!(config.path, r"C:\vnt.pcap");
+    assert_eq!(config.filter, CaptureFilter::default());
+    let config = CaptureConfig::from_str(r"C:\vnt.pcap:udp").unwrap();
+    assert_eq!(config.path, r"C:\vnt.pcap");
+    // tcp 10.26.0.2:1234 -> 10.26.0.3:80
+    let mut ipv4 = [0u8; 24];
+    ipv4[0] = 0x45;
+    ipv4[9] = 6;
+    ipv4[12..16].copy_from_slice(&[10, 26, 0, 2]);
+    ipv4[16..20].copy_from_slice(&[10, 26, 0, 3]);
+    ipv4[20..22].copy_from_slice(&1234u16.to_be_bytes());
+    ipv4[22..24].copy_from_slice(&80u16.to_be_bytes());
+    let filter = CaptureFilter::from_str("10.26.0.3,tcp,port=80").unwrap();
+    assert!(filter.match_inner(&ipv4, false));
+    let filter = CaptureFilter::from_str("10.26.0.4").unwrap();
+    assert!(!filter.match_inner(&ipv4, false));
+    let filter = CaptureFilter::from_str("udp").unwrap();
+    assert!(!filter.match_inner(&ipv4, false));
+}
@@ -1,7 +1,7 @@
 use fnv::FnvHashMap;
-use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
 use std::ops::Deref;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::{io, thread};
@@ -10,12 +10,42 @@ use crossbeam_utils::atomic::AtomicCell;
 use parking_lot::RwLock;
 use rand::Rng;
 
+use crate::acl::Acl;
+use crate::channel::aggregate::Aggregator;
+use crate::channel::capture::Capture;
+use crate::channel::diag::Diag;
+use crate::channel::guard::HandshakeGuard;
+use crate::channel::mac_table::MacTable;
+use crate::channel::multipath::Multipath;
+use crate::channel::pmtu::{path_mss, Pmtu};
+use crate::channel::policy::{prefer_protocol, PathPolicies, PathPolicy, PathPolicyRule};
 use crate::channel::punch::NatType;
+use crate::channel::qos::{self, TosMarker};
+use crate::channel::quality::PathQuality;
+use crate::channel::relay::RelayPaths;
+use crate::channel::reliable::{Reliable, ReliableTarget};
 use crate::channel::sender::{AcceptSocketSender, PacketSender};
-use crate::channel::socket::LocalInterface;
-use crate::channel::{ConnectProtocol, Route, RouteKey, UseChannelType, DEFAULT_RT};
-use crate::protocol::NetPacket;
-use crate::util::limit::TrafficMeterMultiAddress;
+use crate::channel::socket::{set_dont_fragment, LocalInterface, OutboundProxy};
+use crate::channel::stats::{Counters, DropReason, PeerStats};
+use crate::channel::{ConnectProtocol, PeerChannel, Route, RouteKey, UseChannelType, DEFAULT_RT};
+use crate::cipher::EncryptScope;
+#[cfg(feature = "integrity")]
+use crate::cipher::Integrity;
+use crate::fec::Fec;
+use crate::obfs::{Obfs, Obfuscator};
+#[cfg(feature = "peer_key")]
+use crate::peer_key::PeerKeys;
+#[cfg(feature = "integrity")]
+use crate::protocol::control_packet::FEATURE_INTEGRITY;
+use crate::protocol::control_packet::{
+    local_features, FEATURE_AGGREGATE, FEATURE_FEC, FEATURE_LAYER2, FEATURE_RELIABLE,
+};
+#[cfg(feature = "peer_key")]
+use crate::protocol::control_packet::{FEATURE_PEER_KEY, FEATURE_PQ_KEM};
+use crate::protocol::{NetPacket, Protocol};
+use crate::util::limit::{BandwidthLimiter, TrafficMeterMultiAddress};
+#[cfg(feature = "wireguard")]
+use crate::wireguard::WireGuard;
 
 /// 传输通道上下文，持有udp socket、tcp socket和路由信息
 #[derive(Clone)]
@@ -35,6 +65,22 @@ impl ChannelContext {
         up_traffic_meter: Option<TrafficMeterMultiAddress>,
         down_traffic_meter: Option<TrafficMeterMultiAddress>,
         default_interface: LocalInterface,
+        obfs: Obfs,
+        outbound_proxy: Option<OutboundProxy>,
+        multipath: Option<Multipath>,
+        quality: Option<PathQuality>,
+        fec: Fec,
+        aggregator: Option<Aggregator>,
+        layer2: bool,
+        peer_relay: bool,
+        acl: Acl,
+        encrypt_scope: EncryptScope,
+        bandwidth: Option<BandwidthLimiter>,
+        qos: bool,
+        capture: Option<Capture>,
+        #[cfg(feature = "wireguard")] wireguard: Option<WireGuard>,
+        #[cfg(feature = "peer_key")] peer_key: Option<PeerKeys>,
+        #[cfg(feature = "integrity")] integrity: Option<Integrity>,
     ) -> Self {
         let channel_num = v4_len;
         assert_ne!(channel_num, 0, "not channel");
@@ -48,18 +94,55 @@ impl ChannelContext {
                 }
             })
             .unwrap_or(0);
+        // 关闭流量统计时也不统计对端和流
+        let peer_stats = up_traffic_meter.as_ref().map(|_| PeerStats::new());
         let inner = ContextInner {
             main_udp_socket,
             v4_len,
             sub_udp_socket: RwLock::new(Vec::new()),
             packet_map: RwLock::new(FnvHashMap::default()),
-            route_table: RouteTable::new(use_channel_type, first_latency, channel_num),
+            route_table: RouteTable::new(
+                use_channel_type,
+                first_latency,
+                channel_num,
+                multipath,
+                quality,
+            ),
             protocol,
             packet_loss_rate,
             packet_delay,
             up_traffic_meter,
             down_traffic_meter,
             default_interface,
+            obfs,
+            outbound_proxy,
+            fec,
+            aggregator,
+            reliable: Reliable::new(),
+            layer2,
+            mac_table: MacTable::new(),
+            peer_relay,
+            relay_paths: RelayPaths::new(),
+            diag: Diag::new(),
+            peer_features: RwLock::new(FnvHashMap::default()),
+            session_id: rand::random::<u64>() | 1,
+            peer_sessions: RwLock::new(FnvHashMap::default()),
+            punch_cookies: RwLock::new(FnvHashMap::default()),
+            handshake_guard: HandshakeGuard::new(),
+            acl,
+            encrypt_scope,
+            bandwidth,
+            tos_marker: qos.then(|| TosMarker::new(v4_len)),
+            peer_stats,
+            counters: Counters::default(),
+            dont_fragment: RwLock::new(()),
+            capture,
+            #[cfg(feature = "wireguard")]
+            wireguard,
+            #[cfg(feature = "peer_key")]
+            peer_key,
+            #[cfg(feature = "integrity")]
+            integrity,
         };
         Self {
             inner: Arc::new(inner),
@@ -67,6 +150,17 @@ impl ChannelContext {
     }
 }
 
+/// 离开作用域时恢复允许分片，发送出错时也会恢复
+struct DontFragment<'a>(&'a UdpSocket);
+
+impl Drop for DontFragment<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = set_dont_fragment(self.0, false) {
+            log::warn!("恢复允许分片失败 {:?}", e);
+        }
+    }
+}
+
 impl Deref for ChannelContext {
     type Target = ContextInner;
 
@@ -78,6 +172,8 @@ impl Deref for ChannelContext {
 /// 对称网络增加的udp socket数目，有助于增加打洞成功率
 pub const SYMMETRIC_CHANNEL_NUM: usize = 84;
 const PACKET_LOSS_RATE_DENOMINATOR: u32 = 100_0000;
+// 探测cookie的有效时间
+const PUNCH_COOKIE_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct ContextInner {
     // 核心udp socket
@@ -98,15 +194,243 @@ pub struct ContextInner {
     pub(crate) up_traffic_meter: Option<TrafficMeterMultiAddress>,
     pub(crate) down_traffic_meter: Option<TrafficMeterMultiAddress>,
     default_interface: LocalInterface,
+    // udp数据混淆
+    obfs: Obfs,
+    // tcp类连接使用的上游代理
+    outbound_proxy: Option<OutboundProxy>,
+    // 前向纠错
+    fec: Fec,
+    // 小包合并
+    aggregator: Option<Aggregator>,
+    // 客户端之间控制数据的可靠传输
+    reliable: Reliable,
+    // 二层模式
+    layer2: bool,
+    // 二层模式下学习到的mac地址
+    mac_table: MacTable,
+    // 允许为其他客户端中转数据
+    peer_relay: bool,
+    // 其他客户端通告的中继路径
+    relay_paths: RelayPaths,
+    // vnt协议层的诊断请求
+    diag: Diag,
+    // 对端支持的能力，通过ping包获得
+    peer_features: RwLock<FnvHashMap<Ipv4Addr, u16>>,
+    // 本端会话id，本地ip变化后对端据此确认是同一个客户端
+    session_id: u64,
+    // 对端的会话id和最近一次迁移的generation
+    peer_sessions: RwLock<FnvHashMap<Ipv4Addr, (u64, u16)>>,
+    // 向缓存地址探测时使用的cookie，对端回应时原样带回
+    punch_cookies: RwLock<FnvHashMap<Ipv4Addr, (u64, Instant)>>,
+    // 未验证地址发来的握手包的限流和cookie
+    handshake_guard: HandshakeGuard,
+    // 客户端之间的访问控制
+    acl: Acl,
+    // 客户端之间的数据使用组网密码加密的范围
+    encrypt_scope: EncryptScope,
+    // 全局和对端的带宽限制
+    bandwidth: Option<BandwidthLimiter>,
+    // 开启qos时把内层的DSCP复制到外层的udp包
+    tos_marker: Option<TosMarker>,
+    // 按对端和按流的收发统计
+    peer_stats: Option<PeerStats>,
+    // 握手失败、解密失败等异常计数
+    counters: Counters,
+    // 探测路径mtu时持有写锁，udp发送持有读锁
+    dont_fragment: RwLock<()>,
+    // 抓包，None表示不开启
+    capture: Option<Capture>,
+    // wg互通的接入端
+    #[cfg(feature = "wireguard")]
+    wireguard: Option<WireGuard>,
+    // 客户端之间协商的会话密钥
+    #[cfg(feature = "peer_key")]
+    peer_key: Option<PeerKeys>,
+    // 不加密时客户端之间数据的完整性校验
+    #[cfg(feature = "integrity")]
+    integrity: Option<Integrity>,
 }
 
 impl ContextInner {
     pub fn use_channel_type(&self) -> UseChannelType {
         self.route_table.use_channel_type
     }
+    /// 到对端可以使用的通道
+    pub fn use_channel_type_of(&self, id: &Ipv4Addr) -> UseChannelType {
+        self.route_table.use_channel_type_of(id)
+    }
     pub fn default_interface(&self) -> &LocalInterface {
         &self.default_interface
     }
+    pub fn obfs(&self) -> &Obfs {
+        &self.obfs
+    }
+    pub fn fec(&self) -> &Fec {
+        &self.fec
+    }
+    pub fn aggregator(&self) -> Option<&Aggregator> {
+        self.aggregator.as_ref()
+    }
+    pub fn reliable(&self) -> &Reliable {
+        &self.reliable
+    }
+    pub fn layer2(&self) -> bool {
+        self.layer2
+    }
+    pub fn mac_table(&self) -> &MacTable {
+        &self.mac_table
+    }
+    pub fn peer_relay(&self) -> bool {
+        self.peer_relay
+    }
+    pub fn relay_paths(&self) -> &RelayPaths {
+        &self.relay_paths
+    }
+    pub fn diag(&self) -> &Diag {
+        &self.diag
+    }
+    pub fn acl(&self) -> &Acl {
+        &self.acl
+    }
+    pub fn encrypt_scope(&self) -> EncryptScope {
+        self.encrypt_scope
+    }
+    pub fn bandwidth(&self) -> Option<&BandwidthLimiter> {
+        self.bandwidth.as_ref()
+    }
+    pub fn qos(&self) -> bool {
+        self.tos_marker.is_some()
+    }
+    pub fn capture(&self) -> Option<&Capture> {
+        self.capture.as_ref()
+    }
+    pub fn peer_stats(&self) -> Option<&PeerStats> {
+        self.peer_stats.as_ref()
+    }
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+    pub fn handshake_guard(&self) -> &HandshakeGuard {
+        &self.handshake_guard
+    }
+    #[cfg(feature = "wireguard")]
+    pub fn wireguard(&self) -> Option<&WireGuard> {
+        self.wireguard.as_ref()
+    }
+    #[cfg(feature = "peer_key")]
+    pub fn peer_key(&self) -> Option<&PeerKeys> {
+        self.peer_key.as_ref()
+    }
+    #[cfg(feature = "integrity")]
+    pub fn integrity(&self) -> Option<&Integrity> {
+        self.integrity.as_ref()
+    }
+    /// 本端的能力，二层模式时带上二层标记，开启密钥协商时带上密钥协商和混合密钥交换标记，
+    /// 开启完整性校验时带上校验标记
+    pub fn local_features(&self) -> u16 {
+        let mut features = local_features();
+        if self.layer2 {
+            features |= FEATURE_LAYER2;
+        }
+        #[cfg(feature = "peer_key")]
+        if let Some(peer_key) = &self.peer_key {
+            features |= FEATURE_PEER_KEY;
+            if peer_key.hybrid() {
+                features |= FEATURE_PQ_KEM;
+            }
+        }
+        #[cfg(feature = "integrity")]
+        if self.integrity.is_some() {
+            features |= FEATURE_INTEGRITY;
+        }
+        features
+    }
+    pub fn update_peer_features(&self, ip: Ipv4Addr, features: u16) {
+        if self.peer_features.read().get(&ip) == Some(&features) {
+            return;
+        }
+        log::info!("对端{}能力 {:#b}", ip, features);
+        if (features & FEATURE_LAYER2 != 0) != self.layer2 {
+            log::warn!(
+                "对端{}的网卡模式和本端不一致(本端{})，二层和三层模式之间不互通",
+                ip,
+                if self.layer2 { "二层" } else { "三层" }
+            );
+        }
+        self.peer_features.write().insert(ip, features);
+    }
+    /// 对端支持的能力，未知时为0
+    pub fn peer_features(&self, ip: &Ipv4Addr) -> u16 {
+        self.peer_features.read().get(ip).copied().unwrap_or(0)
+    }
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+    pub fn update_peer_session(&self, ip: Ipv4Addr, session_id: u64) {
+        if session_id == 0 {
+            return;
+        }
+        if let Some((id, _)) = self.peer_sessions.read().get(&ip) {
+            if *id == session_id {
+                return;
+            }
+        }
+        // 对端重启后会话id会变化，generation重新开始
+        self.peer_sessions.write().insert(ip, (session_id, 0));
+    }
+    /// 校验迁移请求，会话id不匹配时返回None，generation比之前的新时返回true
+    pub fn migrate_peer_session(
+        &self,
+        ip: Ipv4Addr,
+        session_id: u64,
+        generation: u16,
+    ) -> Option<bool> {
+        let mut guard = self.peer_sessions.write();
+        let (id, last) = guard.get_mut(&ip)?;
+        if *id != session_id {
+            return None;
+        }
+        if (generation.wrapping_sub(*last) as i16) <= 0 {
+            return Some(false);
+        }
+        *last = generation;
+        Some(true)
+    }
+    /// 生成探测ip使用的cookie，覆盖之前的
+    pub fn new_punch_cookie(&self, ip: Ipv4Addr) -> u64 {
+        let cookie = rand::random::<u64>();
+        let mut guard = self.punch_cookies.write();
+        guard.retain(|_, (_, time)| time.elapsed() < PUNCH_COOKIE_TIMEOUT);
+        guard.insert(ip, (cookie, Instant::now()));
+        cookie
+    }
+    /// 回应中的cookie是否是最近发给ip的
+    pub fn check_punch_cookie(&self, ip: &Ipv4Addr, cookie: u64) -> bool {
+        match self.punch_cookies.read().get(ip) {
+            Some((v, time)) => *v == cookie && time.elapsed() < PUNCH_COOKIE_TIMEOUT,
+            None => false,
+        }
+    }
+    /// 对端和本端的网卡模式是否一致，能力未知的对端当作三层模式
+    pub fn same_layer(&self, ip: &Ipv4Addr) -> bool {
+        (self.peer_features(ip) & FEATURE_LAYER2 != 0) == self.layer2
+    }
+    pub fn outbound_proxy(&self) -> Option<&OutboundProxy> {
+        self.outbound_proxy.as_ref()
+    }
+    /// 混淆后发送udp数据
+    fn udp_send_to(&self, udp: &UdpSocket, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let _guard = self.dont_fragment.read();
+        self.udp_send_to0(udp, buf, addr)
+    }
+    fn udp_send_to0(&self, udp: &UdpSocket, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if self.obfs.is_none() {
+            return udp.send_to(buf, addr);
+        }
+        let mut out = Vec::with_capacity(buf.len() + 16);
+        self.obfs.encode(buf, &mut out);
+        udp.send_to(&out, addr)
+    }
     /// 通过sub_udp_socket是否为空来判断是否为锥形网络
     pub fn is_cone(&self) -> bool {
         self.sub_udp_socket.read().is_empty()
@@ -182,9 +506,25 @@ impl ContextInner {
             Err(io::Error::from(io::ErrorKind::NotFound))
         }
     }
+    /// 断开和服务端的tcp/ws/tls/quic连接，下次握手时会重新连接，udp不需要处理
+    pub fn close_server(&self, addr: SocketAddr) {
+        let addr = match self.protocol {
+            // ws连接使用固定的地址
+            ConnectProtocol::WS | ConnectProtocol::WSS => {
+                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))
+            }
+            _ => addr,
+        };
+        if self.packet_map.write().remove(&addr).is_some() {
+            log::info!("断开服务端连接 {}", addr);
+        }
+    }
     pub fn send_main_udp(&self, index: usize, buf: &[u8], addr: SocketAddr) -> io::Result<()> {
         if let Some(udp) = self.main_udp_socket.get(index) {
-            udp.send_to(buf, addr)?;
+            if let Some(tos_marker) = &self.tos_marker {
+                tos_marker.mark(index, udp);
+            }
+            self.udp_send_to(udp, buf, addr)?;
             Ok(())
         } else {
             Err(io::Error::new(io::ErrorKind::Other, "overflow"))
@@ -196,6 +536,9 @@ impl ContextInner {
         buf: &NetPacket<B>,
         addr: SocketAddr,
     ) -> io::Result<()> {
+        if let Some(capture) = &self.capture {
+            capture.outer(buf.buffer(), false, self.protocol, addr);
+        }
         if self.protocol.is_udp() {
             if addr.is_ipv4() {
                 self.send_main_udp(0, buf.buffer(), addr)?
@@ -215,12 +558,27 @@ impl ContextInner {
     pub fn try_send_all(&self, buf: &[u8], addr: SocketAddr) {
         self.try_send_all_main(buf, addr);
         for udp in self.sub_udp_socket.read().iter() {
-            if let Err(e) = udp.send_to(buf, addr) {
+            if let Err(e) = self.udp_send_to(udp, buf, addr) {
                 log::warn!("{:?},add={:?}", e, addr);
             }
             thread::sleep(Duration::from_millis(3));
         }
     }
+    /// 此方法仅用于双方都是对称网络时打洞，用前n个子端口依次向所有预测地址发送
+    pub fn try_send_burst(&self, buf: &[u8], ips: &[Ipv4Addr], ports: &[u16], n: usize) {
+        for udp in self.sub_udp_socket.read().iter().take(n) {
+            'a: for ip in ips {
+                for port in ports {
+                    let addr = SocketAddr::V4(SocketAddrV4::new(*ip, *port));
+                    if let Err(e) = self.udp_send_to(udp, buf, addr) {
+                        log::warn!("{:?},add={:?}", e, addr);
+                        break 'a;
+                    }
+                }
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
     pub fn try_send_all_main(&self, buf: &[u8], addr: SocketAddr) {
         for index in 0..self.channel_num() {
             if let Err(e) = self.send_main_udp(index, buf, addr) {
@@ -228,16 +586,102 @@ impl ContextInner {
             }
         }
     }
-    /// 发送网络数据
+    /// 发送网络数据，开启了小包合并并且对端支持时，小包会先缓存，由定时任务或后续的包带出
     pub fn send_ipv4_by_id<B: AsRef<[u8]>>(
         &self,
         buf: &NetPacket<B>,
         id: &Ipv4Addr,
         server_addr: SocketAddr,
         send_default: bool,
+    ) -> io::Result<()> {
+        if let Some(bandwidth) = &self.bandwidth {
+            // 超过带宽限制的网络数据直接丢弃，优先的小包总是发送
+            if matches!(buf.protocol(), Protocol::IpTurn | Protocol::OtherTurn)
+                && !bandwidth.allow_up(*id, buf.data_len(), qos::is_priority())
+            {
+                if let Some(peer_stats) = &self.peer_stats {
+                    peer_stats.send_drop(*id);
+                }
+                return Ok(());
+            }
+        }
+        if let Some(aggregator) = &self.aggregator {
+            if self.peer_features(id) & FEATURE_AGGREGATE != 0 {
+                let (flush, pushed) = aggregator.push(buf, id, server_addr, send_default);
+                if let Some((packet, server_addr, send_default)) = flush {
+                    self.send_ipv4_fec(
+                        &NetPacket::unchecked(packet),
+                        id,
+                        server_addr,
+                        send_default,
+                    )?;
+                }
+                if pushed {
+                    return Ok(());
+                }
+            }
+        }
+        self.send_ipv4_fec(buf, id, server_addr, send_default)
+    }
+    /// 发送缓存时间超过合并延迟的小包
+    pub fn flush_aggregate(&self) {
+        if let Some(aggregator) = &self.aggregator {
+            self.send_aggregate(aggregator.take_expired());
+        }
+    }
+    /// 发送所有缓存的小包，退出前使用
+    pub fn flush_all_aggregate(&self) {
+        if let Some(aggregator) = &self.aggregator {
+            self.send_aggregate(aggregator.take_all());
+        }
+    }
+    fn send_aggregate(&self, list: Vec<(Ipv4Addr, (Vec<u8>, SocketAddr, bool))>) {
+        for (id, (packet, server_addr, send_default)) in list {
+            if let Err(e) = self.send_ipv4_fec(
+                &NetPacket::unchecked(packet),
+                &id,
+                server_addr,
+                send_default,
+            ) {
+                log::warn!("aggregate {}:{:?}", id, e);
+            }
+        }
+    }
+    /// 开启了fec并且对端支持时，会附带冗余包
+    fn send_ipv4_fec<B: AsRef<[u8]>>(
+        &self,
+        buf: &NetPacket<B>,
+        id: &Ipv4Addr,
+        server_addr: SocketAddr,
+        send_default: bool,
+    ) -> io::Result<()> {
+        if self.fec.is_enabled() && self.peer_features(id) & FEATURE_FEC != 0 {
+            if let Some(packets) = self.fec.encode(buf, id) {
+                for packet in packets {
+                    self.send_ipv4_by_id0(
+                        &NetPacket::unchecked(packet),
+                        id,
+                        server_addr,
+                        send_default,
+                    )?;
+                }
+                return Ok(());
+            }
+        }
+        self.send_ipv4_by_id0(buf, id, server_addr, send_default)
+    }
+    fn send_ipv4_by_id0<B: AsRef<[u8]>>(
+        &self,
+        buf: &NetPacket<B>,
+        id: &Ipv4Addr,
+        server_addr: SocketAddr,
+        send_default: bool,
     ) -> io::Result<()> {
         if self.packet_loss_rate > 0 {
             if rand::thread_rng().gen_ratio(self.packet_loss_rate, PACKET_LOSS_RATE_DENOMINATOR) {
+                if let Some(peer_stats) = &self.peer_stats {
+                    peer_stats.send_drop(*id);
+                }
                 return Ok(());
             }
         }
@@ -246,19 +690,85 @@ impl ContextInner {
             thread::sleep(Duration::from_millis(self.packet_delay as _));
         }
         //优先发到直连到地址
-        if let Err(e) = self.send_by_id(buf, id) {
-            if e.kind() != io::ErrorKind::NotFound {
-                log::warn!("{}:{:?}", id, e);
+        match self.send_by_id0(buf, id) {
+            Ok(route) => {
+                if let Some(peer_stats) = &self.peer_stats {
+                    peer_stats.send(*id, buf.data_len(), route.is_p2p());
+                }
             }
-            if !self.route_table.use_channel_type.is_only_p2p() && send_default {
-                //符合条件再发到服务器转发
-                self.send_default(buf, server_addr)?;
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    log::warn!("{}:{:?}", id, e);
+                }
+                if !self.route_table.use_channel_type_of(id).is_only_p2p() && send_default {
+                    if self.encrypt_scope == EncryptScope::Relay
+                        && !buf.is_encrypt()
+                        && matches!(buf.protocol(), Protocol::IpTurn | Protocol::OtherTurn)
+                    {
+                        // 按直连不加密的数据，直连不可用时不能经服务器转发
+                        if let Some(peer_stats) = &self.peer_stats {
+                            peer_stats.send_drop(*id);
+                        }
+                        return Ok(());
+                    }
+                    //符合条件再发到服务器转发
+                    if let Err(e) = self.send_default(buf, server_addr) {
+                        if let Some(reason) = DropReason::of_send_error(&e) {
+                            self.counters.packet_drop(reason);
+                        }
+                        return Err(e);
+                    }
+                    if let Some(peer_stats) = &self.peer_stats {
+                        peer_stats.send(*id, buf.data_len(), false);
+                    }
+                } else {
+                    if let Some(reason) = DropReason::of_send_error(&e) {
+                        self.counters.packet_drop(reason);
+                    }
+                    if let Some(peer_stats) = &self.peer_stats {
+                        peer_stats.send_drop(*id);
+                    }
+                }
             }
         }
         Ok(())
     }
+    /// 可靠地发送控制数据，对端不支持时按原方式发送
+    pub fn send_reliable<B: AsRef<[u8]>>(
+        &self,
+        buf: &NetPacket<B>,
+        target: ReliableTarget,
+    ) -> io::Result<()> {
+        if self.peer_features(&buf.destination()) & FEATURE_RELIABLE == 0 {
+            return self.send_to_target(buf, target);
+        }
+        let packet = self.reliable.wrap(buf, target)?;
+        self.send_to_target(&NetPacket::unchecked(packet), target)
+    }
+    /// 重传超时未确认的可靠传输包
+    pub fn retransmit_reliable(&self) {
+        for (packet, target) in self.reliable.take_resend() {
+            if let Err(e) = self.send_to_target(&NetPacket::unchecked(packet), target) {
+                log::warn!("reliable {:?}:{:?}", target, e);
+            }
+        }
+    }
+    fn send_to_target<B: AsRef<[u8]>>(
+        &self,
+        buf: &NetPacket<B>,
+        target: ReliableTarget,
+    ) -> io::Result<()> {
+        match target {
+            ReliableTarget::Route(route_key) => self.send_by_key(buf, route_key),
+            ReliableTarget::Server(server_addr) => self.send_default(buf, server_addr),
+        }
+    }
     /// 将数据发到指定id
     pub fn send_by_id<B: AsRef<[u8]>>(&self, buf: &NetPacket<B>, id: &Ipv4Addr) -> io::Result<()> {
+        self.send_by_id0(buf, id).map(|_| ())
+    }
+    /// 返回实际使用的路由
+    fn send_by_id0<B: AsRef<[u8]>>(&self, buf: &NetPacket<B>, id: &Ipv4Addr) -> io::Result<Route> {
         let mut c = 0;
         loop {
             let route = self.route_table.get_route_by_id(c, id)?;
@@ -273,7 +783,7 @@ impl ContextInner {
                 }
                 Err(e)
             } else {
-                Ok(())
+                Ok(route)
             };
         }
     }
@@ -283,31 +793,73 @@ impl ContextInner {
         buf: &NetPacket<B>,
         route_key: RouteKey,
     ) -> io::Result<()> {
+        if let Some(capture) = &self.capture {
+            capture.outer(buf.buffer(), false, route_key.protocol(), route_key.addr);
+        }
         match route_key.protocol() {
             ConnectProtocol::UDP => {
                 if let Some(main_udp) = self.main_udp_socket.get(route_key.index) {
-                    main_udp.send_to(buf.buffer(), route_key.addr)?;
+                    if let Some(tos_marker) = &self.tos_marker {
+                        tos_marker.mark(route_key.index, main_udp);
+                    }
+                    self.udp_send_to(main_udp, buf.buffer(), route_key.addr)?;
                 } else {
                     if let Some(udp) = self
                         .sub_udp_socket
                         .read()
                         .get(route_key.index - self.main_len())
                     {
-                        udp.send_to(buf.buffer(), route_key.addr)?;
+                        self.udp_send_to(udp, buf.buffer(), route_key.addr)?;
                     } else {
                         Err(io::Error::from(io::ErrorKind::NotFound))?
                     }
                 }
             }
-            ConnectProtocol::TCP | ConnectProtocol::WS | ConnectProtocol::WSS => {
-                self.send_tcp(buf.buffer(), route_key.addr)?
-            }
+            ConnectProtocol::TCP
+            | ConnectProtocol::WS
+            | ConnectProtocol::WSS
+            | ConnectProtocol::QUIC
+            | ConnectProtocol::TLS => self.send_tcp(buf.buffer(), route_key.addr)?,
         }
         if let Some(up_traffic_meter) = &self.up_traffic_meter {
             up_traffic_meter.add_traffic(buf.destination(), buf.data_len());
         }
         Ok(())
     }
+    /// 以不允许分片的方式发送一组探测包，只支持udp路由
+    pub fn send_by_key_df<B: AsRef<[u8]>>(
+        &self,
+        bufs: &[NetPacket<B>],
+        route_key: RouteKey,
+    ) -> io::Result<()> {
+        if !route_key.protocol().is_udp() {
+            return Err(io::Error::from(io::ErrorKind::Unsupported));
+        }
+        let send = |udp: &UdpSocket| -> io::Result<()> {
+            // socket是和数据共用的，探测期间其他发送要等待，不然数据包也会带上DF标记
+            let _guard = self.dont_fragment.write();
+            set_dont_fragment(udp, true)?;
+            let _df = DontFragment(udp);
+            for buf in bufs {
+                // 超过本地网卡mtu的包会直接报错，忽略即可
+                if let Err(e) = self.udp_send_to0(udp, buf.buffer(), route_key.addr) {
+                    log::debug!("{:?} len={} {:?}", route_key.addr, buf.data_len(), e);
+                }
+            }
+            Ok(())
+        };
+        if let Some(main_udp) = self.main_udp_socket.get(route_key.index) {
+            send(main_udp)
+        } else if let Some(udp) = self
+            .sub_udp_socket
+            .read()
+            .get(route_key.index - self.main_len())
+        {
+            send(udp)
+        } else {
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        }
+    }
     pub fn remove_route(&self, ip: &Ipv4Addr, route_key: RouteKey) {
         self.route_table.remove_route(ip, route_key)
     }
@@ -319,39 +871,86 @@ pub struct RouteTable {
     first_latency: bool,
     channel_num: usize,
     use_channel_type: UseChannelType,
+    // 多路径传输，为空时按原有方式选择路由
+    multipath: Option<Multipath>,
+    // 比较直连和服务端中转的质量，为空时有直连就使用直连
+    quality: Option<PathQuality>,
+    // 固定了通道的对端，只在全局不限制通道时生效
+    peer_channel: RwLock<FnvHashMap<Ipv4Addr, UseChannelType>>,
+    // 全局和每个对端的选路策略
+    path_policy: PathPolicies,
+    // udp路径的mtu
+    pmtu: Pmtu,
+    // 新建立直连的次数
+    direct_links: AtomicU64,
 }
 
 impl RouteTable {
-    fn new(use_channel_type: UseChannelType, first_latency: bool, channel_num: usize) -> Self {
+    fn new(
+        use_channel_type: UseChannelType,
+        first_latency: bool,
+        channel_num: usize,
+        multipath: Option<Multipath>,
+        quality: Option<PathQuality>,
+    ) -> Self {
         Self {
             route_table: RwLock::new(FnvHashMap::with_capacity_and_hasher(64, Default::default())),
             use_channel_type,
             first_latency,
             channel_num,
+            multipath,
+            quality,
+            peer_channel: RwLock::new(FnvHashMap::default()),
+            path_policy: PathPolicies::new(first_latency),
+            pmtu: Pmtu::new(),
+            direct_links: AtomicU64::new(0),
         }
     }
 }
 
 impl RouteTable {
     fn get_route_by_id(&self, index: usize, id: &Ipv4Addr) -> io::Result<Route> {
-        if let Some((_count, v)) = self.route_table.read().get(id) {
-            if self.first_latency {
-                if let Some((route, _)) = v.first() {
-                    return Ok(*route);
+        if let Some(quality) = &self.quality {
+            if quality.relay_preferred(id) {
+                // 服务端中转的质量更好，由调用方发到服务端
+                return Err(io::Error::new(io::ErrorKind::NotFound, "relay preferred"));
+            }
+        }
+        let policy = self.path_policy.get(id);
+        if let Some((count, v)) = self.route_table.read().get(id) {
+            if let Some(multipath) = &self.multipath {
+                if let Some(route) = multipath.select(count, v) {
+                    return Ok(route);
                 }
             } else {
-                let len = v.len();
-                if len != 0 {
-                    let route = &v[index % len].0;
-                    // 跳过默认rt的路由(一般是刚加入的)，这有助于提升稳定性
-                    if route.rt != DEFAULT_RT {
-                        return Ok(*route);
-                    }
-                    for (route, _) in v {
-                        if route.rt != DEFAULT_RT {
+                match policy {
+                    PathPolicy::FirstLatency => {
+                        if let Some((route, _)) = v.first() {
                             return Ok(*route);
                         }
                     }
+                    PathPolicy::UdpFirst | PathPolicy::TcpFirst => {
+                        let udp = policy == PathPolicy::UdpFirst;
+                        if let Some(route) = prefer_protocol(v.iter().map(|(route, _)| route), udp)
+                        {
+                            return Ok(route);
+                        }
+                    }
+                    PathPolicy::P2p => {
+                        let len = v.len();
+                        if len != 0 {
+                            let route = &v[index % len].0;
+                            // 跳过默认rt的路由(一般是刚加入的)，这有助于提升稳定性
+                            if route.rt != DEFAULT_RT {
+                                return Ok(*route);
+                            }
+                            for (route, _) in v {
+                                if route.rt != DEFAULT_RT {
+                                    return Ok(*route);
+                                }
+                            }
+                        }
+                    }
                 }
             }
         }
@@ -373,6 +972,18 @@ impl RouteTable {
             }
             _ => {}
         }
+        match self.peer_channel.read().get(&id) {
+            Some(UseChannelType::P2p) if !route.is_p2p() => return false,
+            Some(UseChannelType::Relay) if route.is_p2p() => return false,
+            _ => {}
+        }
+        let policy = self.path_policy.get(&id);
+        let first_latency = policy == PathPolicy::FirstLatency;
+        // 按协议优先时多保留一个直连，让另一种协议的直连也能留在路由表中
+        let channel_num = match policy {
+            PathPolicy::UdpFirst | PathPolicy::TcpFirst => self.channel_num + 1,
+            _ => self.channel_num,
+        };
         let key = route.route_key();
         if only_if_absent {
             if let Some((_, list)) = self.route_table.read().get(&id) {
@@ -385,7 +996,7 @@ impl RouteTable {
                         return true;
                     }
                 }
-                if !self.first_latency && p2p_num >= self.channel_num {
+                if !first_latency && p2p_num >= channel_num {
                     // 非优先延迟的情况下，通道满了则不用再添加
                     return false;
                 }
@@ -397,7 +1008,7 @@ impl RouteTable {
             .or_insert_with(|| (AtomicUsize::new(0), Vec::with_capacity(4)));
         let mut exist = false;
         for (x, time) in list.iter_mut() {
-            if x.metric < route.metric && !self.first_latency {
+            if x.metric < route.metric && !first_latency {
                 //非优先延迟的情况下 不能比当前的路径更长
                 return false;
             }
@@ -422,35 +1033,43 @@ impl RouteTable {
                 }
             }
             //延迟优先模式需要更多的通道探测延迟最低的路线
-            let limit_len = if self.first_latency {
+            let limit_len = if first_latency {
                 self.channel_num + 2
             } else {
-                self.channel_num
+                channel_num
             };
-            self.truncate_(list, limit_len);
+            self.truncate_(list, limit_len, first_latency);
         } else {
-            if !self.first_latency {
+            if !first_latency {
                 if route.is_p2p() {
                     //非优先延迟的情况下 添加了直连的则排除非直连的
                     list.retain(|(k, _)| k.is_p2p());
                 }
-                if self.channel_num <= list.len() {
+                if channel_num <= list.len() {
                     return false;
                 }
             };
+            if route.is_p2p() && !list.iter().any(|(k, _)| k.is_p2p()) {
+                self.direct_links.fetch_add(1, Ordering::Relaxed);
+            }
             //增加路由表容量，避免波动
             let limit_len = self.channel_num * 2;
             list.sort_by_key(|(k, _)| k.rt);
-            self.truncate_(list, limit_len);
+            self.truncate_(list, limit_len, first_latency);
             list.push((route, AtomicCell::new(Instant::now())));
         }
         return true;
     }
-    fn truncate_(&self, list: &mut Vec<(Route, AtomicCell<Instant>)>, len: usize) {
+    fn truncate_(
+        &self,
+        list: &mut Vec<(Route, AtomicCell<Instant>)>,
+        len: usize,
+        first_latency: bool,
+    ) {
         if list.len() <= len {
             return;
         }
-        if self.first_latency {
+        if first_latency {
             //找到第一个p2p通道
             if let Some(index) =
                 list.iter()
@@ -482,6 +1101,18 @@ impl RouteTable {
             None
         }
     }
+    /// 到对端有路由并且都是直连的，改用服务端中转时不算直连
+    pub fn all_p2p(&self, id: &Ipv4Addr) -> bool {
+        if let Some(quality) = &self.quality {
+            if quality.relay_preferred(id) {
+                return false;
+            }
+        }
+        match self.route_table.read().get(id) {
+            Some((_, v)) => !v.is_empty() && v.iter().all(|(i, _)| i.is_p2p()),
+            None => false,
+        }
+    }
     pub fn route_one_p2p(&self, id: &Ipv4Addr) -> Option<Route> {
         if let Some((_, v)) = self.route_table.read().get(id) {
             for (i, _) in v {
@@ -503,10 +1134,27 @@ impl RouteTable {
         }
         None
     }
+    /// 最近一次从对端的任一路由收到数据到现在的时间
+    pub fn last_read(&self, id: &Ipv4Addr) -> Option<Duration> {
+        if let Some((_, v)) = self.route_table.read().get(id) {
+            v.iter().map(|(_, time)| time.load().elapsed()).min()
+        } else {
+            None
+        }
+    }
     pub fn no_need_punch(&self, id: &Ipv4Addr) -> bool {
+        let policy = self.path_policy.get(id);
         if let Some((_, v)) = self.route_table.read().get(id) {
+            // 按协议优先时还需要有优先协议的直连
+            let preferred = match policy {
+                PathPolicy::UdpFirst => v.iter().any(|(k, _)| k.is_p2p() && k.protocol.is_udp()),
+                PathPolicy::TcpFirst => v
+                    .iter()
+                    .any(|(k, _)| k.is_p2p() && k.protocol.is_base_tcp()),
+                _ => true,
+            };
             //p2p的通道数符合要求
-            return v.iter().filter(|(k, _)| k.is_p2p()).count() >= self.channel_num;
+            return preferred && v.iter().filter(|(k, _)| k.is_p2p()).count() >= self.channel_num;
         }
         false
     }
@@ -548,7 +1196,90 @@ impl RouteTable {
         }
         list
     }
+    /// 从没有直连到建立直连的次数，和打洞次数一起计算打洞成功率
+    pub fn direct_links(&self) -> u64 {
+        self.direct_links.load(Ordering::Relaxed)
+    }
+    pub fn multipath(&self) -> Option<&Multipath> {
+        self.multipath.as_ref()
+    }
+    pub fn quality(&self) -> Option<&PathQuality> {
+        self.quality.as_ref()
+    }
+    /// 到对端可以使用的通道，全局限制了通道时以全局为准
+    pub fn use_channel_type_of(&self, id: &Ipv4Addr) -> UseChannelType {
+        if !self.use_channel_type.is_all() {
+            return self.use_channel_type;
+        }
+        self.peer_channel
+            .read()
+            .get(id)
+            .copied()
+            .unwrap_or(UseChannelType::All)
+    }
+    /// 对端固定的通道，没有固定时为None
+    /// 到对端使用的选路策略
+    pub fn path_policy(&self, id: &Ipv4Addr) -> PathPolicy {
+        self.path_policy.get(id)
+    }
+    /// 设置选路策略，有变化时返回true，已有的路由不变，之后按新的策略选择和维护
+    pub fn set_path_policy(&self, list: &[PathPolicyRule]) -> bool {
+        self.path_policy.set(list)
+    }
+    pub fn peer_channel(&self, id: &Ipv4Addr) -> Option<UseChannelType> {
+        self.peer_channel.read().get(id).copied()
+    }
+    /// 设置固定通道的对端，有变化时返回true，并删除不符合的路由
+    pub fn set_peer_channel(&self, list: &[PeerChannel]) -> bool {
+        let map: FnvHashMap<Ipv4Addr, UseChannelType> =
+            list.iter().map(|v| (v.ip, v.channel)).collect();
+        {
+            let mut guard = self.peer_channel.write();
+            if *guard == map {
+                return false;
+            }
+            *guard = map.clone();
+        }
+        for (ip, channel) in map {
+            let routes = match self.route(&ip) {
+                Some(routes) => routes,
+                None => continue,
+            };
+            for route in routes {
+                let remove = match channel {
+                    UseChannelType::P2p => !route.is_p2p(),
+                    UseChannelType::Relay => route.is_p2p(),
+                    UseChannelType::All => false,
+                };
+                if remove {
+                    self.remove_route(&ip, route.route_key());
+                }
+            }
+        }
+        true
+    }
+    pub fn pmtu(&self) -> &Pmtu {
+        &self.pmtu
+    }
+    /// 按到对端的各条直连udp路径中最小的mtu计算tcp mss，没有探测结果时返回None
+    pub fn path_mss(&self, id: &Ipv4Addr) -> Option<u16> {
+        let table = self.route_table.read();
+        let (_, routes) = table.get(id)?;
+        routes
+            .iter()
+            .filter(|(route, _)| route.is_p2p() && route.protocol.is_udp())
+            .filter_map(|(route, _)| {
+                self.pmtu
+                    .route_mtu(&route.route_key())
+                    .map(|mtu| path_mss(&route.addr, mtu))
+            })
+            .min()
+    }
     pub fn remove_route(&self, id: &Ipv4Addr, route_key: RouteKey) {
+        if let Some(multipath) = &self.multipath {
+            multipath.remove(&route_key);
+        }
+        self.pmtu.remove(&route_key);
         let mut write_guard = self.route_table.write();
         if let Some((_, routes)) = write_guard.get_mut(id) {
             routes.retain(|(x, _)| x.route_key() != route_key);
@@ -0,0 +1,103 @@
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::{Condvar, Mutex};
+
+use crate::channel::RouteKey;
+
+// 带宽测试的统计超过这个时间没有收到数据就删除
+const EXPIRE_TIME: Duration = Duration::from_secs(60);
+// 同时统计的带宽测试数，防止对端用大量的id占用内存
+const MAX_TESTS: usize = 16;
+
+/// 诊断请求收到的响应
+#[derive(Copy, Clone, Debug)]
+pub struct DiagResponse {
+    pub seq: u16,
+    /// 响应经过的跳数，1表示直连
+    pub metric: u8,
+    pub route_key: RouteKey,
+    pub bytes: u64,
+    pub packets: u64,
+    pub time: Instant,
+}
+
+/// vnt协议层的诊断，作为发起方等待响应，作为带宽测试的接收方统计收到的数据
+pub struct Diag {
+    // 请求id -> 收到的响应
+    responses: Mutex<FnvHashMap<u32, Vec<DiagResponse>>>,
+    condvar: Condvar,
+    // (来源, 测试id) -> (字节数, 包数, 最后收到的时间)
+    received: Mutex<FnvHashMap<(Ipv4Addr, u32), (u64, u64, Instant)>>,
+}
+
+impl Diag {
+    pub fn new() -> Self {
+        Self {
+            responses: Mutex::new(FnvHashMap::default()),
+            condvar: Condvar::new(),
+            received: Mutex::new(FnvHashMap::default()),
+        }
+    }
+    /// 开始等待响应，返回请求id，结束后需要调用remove
+    pub fn register(&self) -> u32 {
+        let mut guard = self.responses.lock();
+        loop {
+            let id = rand::random::<u32>();
+            if !guard.contains_key(&id) {
+                guard.insert(id, Vec::new());
+                return id;
+            }
+        }
+    }
+    pub fn remove(&self, id: u32) {
+        self.responses.lock().remove(&id);
+    }
+    /// 收到响应，不是等待中的请求时忽略
+    pub fn response(&self, id: u32, response: DiagResponse) {
+        let mut guard = self.responses.lock();
+        if let Some(list) = guard.get_mut(&id) {
+            list.push(response);
+            self.condvar.notify_all();
+        }
+    }
+    /// 等待序号为seq的响应，超时返回None
+    pub fn wait(&self, id: u32, seq: u16, timeout: Duration) -> Option<DiagResponse> {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.responses.lock();
+        loop {
+            let response = guard
+                .get(&id)
+                .and_then(|list| list.iter().find(|v| v.seq == seq).copied());
+            if response.is_some() {
+                return response;
+            }
+            if self.condvar.wait_until(&mut guard, deadline).timed_out() {
+                return None;
+            }
+        }
+    }
+    /// 带宽测试收到数据
+    pub fn recv_data(&self, source: Ipv4Addr, id: u32, len: usize) {
+        let now = Instant::now();
+        let mut guard = self.received.lock();
+        if !guard.contains_key(&(source, id)) {
+            guard.retain(|_, (_, _, time)| now.duration_since(*time) < EXPIRE_TIME);
+            if guard.len() >= MAX_TESTS {
+                return;
+            }
+        }
+        let entry = guard.entry((source, id)).or_insert((0, 0, now));
+        entry.0 += len as u64;
+        entry.1 += 1;
+        entry.2 = now;
+    }
+    /// 带宽测试收到的字节数和包数，查询的响应可能丢失，查询后不删除
+    pub fn received(&self, source: Ipv4Addr, id: u32) -> (u64, u64) {
+        self.received
+            .lock()
+            .get(&(source, id))
+            .map_or((0, 0), |(bytes, packets, _)| (*bytes, *packets))
+    }
+}
@@ -0,0 +1,146 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+use sha2::Digest;
+
+use crate::util::limit::RateLimiter;
+
+// 每个来源ip允许突发的握手包数
+const BURST: usize = 20;
+// 每个来源ip每秒补充的握手包数
+const RATE: usize = 10;
+// 最多记录的来源ip数，超过后淘汰最久没有握手包的来源
+const MAX_TRACKED: usize = 4096;
+// 超过这个时间没有握手包的来源ip会被清理
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+// cookie密钥的轮换周期，上一个周期的cookie仍然有效
+const SECRET_ROTATE: Duration = Duration::from_secs(120);
+
+/// 未验证地址发来的握手类数据包的防护，按来源ip限流防止资源耗尽；
+/// 分配状态或回应更大的数据包前，先用无状态cookie确认来源地址能收到回应，防止被用于反射放大
+pub struct HandshakeGuard {
+    limiters: Mutex<Limiters>,
+    secret: Mutex<Secret>,
+}
+
+struct Limiters {
+    map: FnvHashMap<IpAddr, (RateLimiter, Instant)>,
+    clean_time: Instant,
+}
+
+struct Secret {
+    current: [u8; 32],
+    previous: [u8; 32],
+    time: Instant,
+}
+
+impl HandshakeGuard {
+    pub fn new() -> Self {
+        Self {
+            limiters: Mutex::new(Limiters {
+                map: FnvHashMap::default(),
+                clean_time: Instant::now(),
+            }),
+            secret: Mutex::new(Secret {
+                current: rand::random(),
+                previous: rand::random(),
+                time: Instant::now(),
+            }),
+        }
+    }
+    /// 来源ip的握手包是否在限速内
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        let mut guard = self.limiters.lock();
+        let limiters = &mut *guard;
+        if limiters.map.len() >= MAX_TRACKED && !limiters.map.contains_key(&ip) {
+            if limiters.clean_time.elapsed() >= Duration::from_secs(1) {
+                limiters.clean_time = Instant::now();
+                limiters
+                    .map
+                    .retain(|_, (_, time)| time.elapsed() < IDLE_TIMEOUT);
+            }
+            // 伪造大量来源时不能占满记录，否则正常的新来源会被一起限流
+            if limiters.map.len() >= MAX_TRACKED {
+                let oldest = limiters
+                    .map
+                    .iter()
+                    .min_by_key(|(_, (_, time))| *time)
+                    .map(|(ip, _)| *ip);
+                if let Some(oldest) = oldest {
+                    limiters.map.remove(&oldest);
+                }
+            }
+        }
+        let (limiter, time) = limiters
+            .map
+            .entry(ip)
+            .or_insert_with(|| (RateLimiter::new(BURST, RATE), Instant::now()));
+        *time = Instant::now();
+        limiter.try_acquire()
+    }
+    /// 和地址绑定的cookie，不需要保存状态
+    pub fn cookie(&self, addr: &SocketAddr) -> u64 {
+        let mut secret = self.secret.lock();
+        if secret.time.elapsed() >= SECRET_ROTATE {
+            secret.previous = secret.current;
+            secret.current = rand::random();
+            secret.time = Instant::now();
+        }
+        calculate_cookie(&secret.current, addr)
+    }
+    /// 回应中的cookie是否是发给这个地址的
+    pub fn check_cookie(&self, addr: &SocketAddr, cookie: u64) -> bool {
+        let secret = self.secret.lock();
+        if secret.time.elapsed() >= SECRET_ROTATE * 2 {
+            return false;
+        }
+        calculate_cookie(&secret.current, addr) == cookie
+            || calculate_cookie(&secret.previous, addr) == cookie
+    }
+}
+
+fn calculate_cookie(secret: &[u8; 32], addr: &SocketAddr) -> u64 {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(secret);
+    match addr.ip() {
+        IpAddr::V4(ip) => hasher.update(ip.octets()),
+        IpAddr::V6(ip) => hasher.update(ip.octets()),
+    }
+    hasher.update(addr.port().to_be_bytes());
+    let hash: [u8; 32] = hasher.finalize().into();
+    u64::from_be_bytes(hash[..8].try_into().unwrap())
+}
+
+#[test]
+fn test_handshake_guard() {
+    let guard = HandshakeGuard::new();
+    let ip: IpAddr = "192.168.1.2".parse().unwrap();
+    for _ in 0..BURST {
+        assert!(guard.allow(ip));
+    }
+    assert!(!guard.allow(ip));
+    assert!(guard.allow("192.168.1.3".parse().unwrap()));
+    let addr: SocketAddr = "192.168.1.2:5000".parse().unwrap();
+    let cookie = guard.cookie(&addr);
+    assert!(guard.check_cookie(&addr, cookie));
+    assert!(!guard.check_cookie(&"192.168.1.2:5001".parse().unwrap(), cookie));
+}
+
+#[test]
+fn test_handshake_guard_full() {
+    let guard = HandshakeGuard::new();
+    for i in 0..MAX_TRACKED as u32 {
+        assert!(guard.allow(std::net::Ipv4Addr::from(0x0A00_0000 + i).into()));
+    }
+    let ip: IpAddr = "192.168.1.2".parse().unwrap();
+    for _ in 0..BURST {
+        assert!(guard.allow(ip));
+    }
+    assert!(!guard.allow(ip));
+    // 记录已满时新的来源仍然有自己的限流器，不受其他来源影响
+    assert!(guard.allow("192.168.1.3".parse().unwrap()));
+    assert!(!guard.allow(ip));
+    assert_eq!(guard.limiters.lock().map.len(), MAX_TRACKED);
+}
@@ -6,17 +6,25 @@ use crate::channel::Route;
 
 pub struct Idle {
     read_idle: Duration,
+    // 超过这个时间没有收到数据，并且有其他可用路由时，提前剔除
+    dead_idle: Duration,
     context: ChannelContext,
 }
 
 impl Idle {
-    pub fn new(read_idle: Duration, context: ChannelContext) -> Self {
-        Self { read_idle, context }
+    pub fn new(read_idle: Duration, dead_idle: Duration, context: ChannelContext) -> Self {
+        Self {
+            read_idle,
+            dead_idle: dead_idle.min(read_idle),
+            context,
+        }
     }
 }
 
 pub enum IdleType {
     Timeout(Ipv4Addr, Route),
+    // 长时间没有响应，但还有其他活跃的路由
+    Dead(Ipv4Addr, Route),
     Sleep(Duration),
     None,
 }
@@ -24,22 +32,32 @@ pub enum IdleType {
 impl Idle {
     /// 获取空闲路由
     pub fn next_idle(&self) -> IdleType {
-        let mut max = Duration::from_secs(0);
+        let mut sleep_time = self.read_idle;
         let read_guard = self.context.route_table.route_table.read();
         if read_guard.is_empty() {
             return IdleType::None;
         }
         for (ip, (_, routes)) in read_guard.iter() {
+            // 还有其他活跃的路由时，失效的路由不用等到超时
+            let alive = routes
+                .iter()
+                .filter(|(_, time)| time.load().elapsed() < self.dead_idle)
+                .count();
             for (route, time) in routes {
                 let last_read = time.load().elapsed();
                 if last_read >= self.read_idle {
                     return IdleType::Timeout(*ip, *route);
-                } else if max < last_read {
-                    max = last_read;
+                }
+                if last_read >= self.dead_idle {
+                    if alive > 0 {
+                        return IdleType::Dead(*ip, *route);
+                    }
+                    sleep_time = sleep_time.min(self.read_idle - last_read);
+                } else {
+                    sleep_time = sleep_time.min(self.dead_idle - last_read);
                 }
             }
         }
-        let sleep_time = self.read_idle.checked_sub(max).unwrap_or_default();
         return IdleType::Sleep(sleep_time);
     }
 }
@@ -0,0 +1,70 @@
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::RwLock;
+
+// mac地址的老化时间，和常见交换机一致
+const AGING_TIME: Duration = Duration::from_secs(300);
+// 最多记录的mac地址数，防止对端伪造大量地址
+const MAX_ENTRIES: usize = 4096;
+
+/// 二层模式下的mac地址表，从收到的以太网帧学习源mac对应的对端虚拟ip
+pub struct MacTable {
+    table: RwLock<FnvHashMap<[u8; 6], (Ipv4Addr, Instant)>>,
+}
+
+impl MacTable {
+    pub fn new() -> Self {
+        Self {
+            table: RwLock::new(FnvHashMap::default()),
+        }
+    }
+    /// 学习源mac，组播和广播地址不记录
+    pub fn learn(&self, mac: [u8; 6], ip: Ipv4Addr) {
+        if mac[0] & 1 == 1 {
+            return;
+        }
+        let now = Instant::now();
+        if let Some((v, time)) = self.table.read().get(&mac) {
+            // 减少写锁，一秒内重复的不更新
+            if *v == ip && now.duration_since(*time) < Duration::from_secs(1) {
+                return;
+            }
+        }
+        let mut guard = self.table.write();
+        if guard.len() >= MAX_ENTRIES && !guard.contains_key(&mac) {
+            guard.retain(|_, (_, time)| now.duration_since(*time) < AGING_TIME);
+            if guard.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        if let Some((old, _)) = guard.insert(mac, (ip, now)) {
+            if old != ip {
+                log::info!("mac地址迁移 {:02x?} {}->{}", mac, old, ip);
+            }
+        }
+    }
+    /// 查找mac所在的对端，未知或已老化时返回None，由调用方泛洪
+    pub fn lookup(&self, mac: &[u8; 6]) -> Option<Ipv4Addr> {
+        self.table
+            .read()
+            .get(mac)
+            .filter(|(_, time)| time.elapsed() < AGING_TIME)
+            .map(|(ip, _)| *ip)
+    }
+}
+
+#[test]
+fn test_mac_table() {
+    let table = MacTable::new();
+    let ip = Ipv4Addr::new(10, 26, 0, 3);
+    let mac = [0x02, 0, 0, 0, 0, 1];
+    assert_eq!(table.lookup(&mac), None);
+    table.learn(mac, ip);
+    assert_eq!(table.lookup(&mac), Some(ip));
+    // 组播地址不学习
+    let multicast = [0x01, 0, 0x5e, 0, 0, 0xfb];
+    table.learn(multicast, ip);
+    assert_eq!(table.lookup(&multicast), None);
+}
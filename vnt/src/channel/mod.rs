@@ -1,27 +1,54 @@
 use anyhow::Context;
-use std::net::{SocketAddr, UdpSocket};
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
 use std::str::FromStr;
 use tokio::sync::mpsc::channel;
 
+use crate::channel::aggregate::Aggregator;
 use crate::channel::context::ChannelContext;
 use crate::channel::handler::RecvChannelHandler;
+use crate::channel::multipath::{Multipath, MultipathModel};
+use crate::channel::quality::PathQuality;
+#[cfg(feature = "quic")]
+use crate::channel::quic_channel::quic_connect_accept;
 use crate::channel::sender::{AcceptSocketSender, ConnectUtil};
-use crate::channel::socket::{bind_udp, LocalInterface};
+use crate::channel::socket::{bind_udp, LocalInterface, OutboundProxy};
 use crate::channel::tcp_channel::tcp_listen;
+#[cfg(feature = "tls")]
+use crate::channel::tls_channel::tls_connect_accept;
 use crate::channel::udp_channel::udp_listen;
 #[cfg(feature = "ws")]
 use crate::channel::ws_channel::ws_connect_accept;
+use crate::fec::Fec;
+use crate::obfs::Obfs;
 use crate::util::limit::TrafficMeterMultiAddress;
 use crate::util::StopManager;
 
+pub mod aggregate;
+pub mod capture;
 pub mod context;
+pub mod diag;
+pub mod guard;
 pub mod handler;
 pub mod idle;
+pub mod mac_table;
+pub mod multipath;
 pub mod notify;
+pub mod pmtu;
+pub mod policy;
 pub mod punch;
+pub mod qos;
+pub mod quality;
+#[cfg(feature = "quic")]
+pub mod quic_channel;
+pub mod relay;
+pub mod reliable;
 pub mod sender;
 pub mod socket;
+pub mod stats;
 pub mod tcp_channel;
+#[cfg(feature = "tls")]
+pub mod tls_channel;
 pub mod udp_channel;
 #[cfg(feature = "ws")]
 pub mod ws_channel;
@@ -69,12 +96,51 @@ impl Default for UseChannelType {
     }
 }
 
+/// 固定到某个对端使用的通道，格式 `<虚拟ip>=<relay/p2p>`，例如 `10.26.0.3=relay`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PeerChannel {
+    pub ip: Ipv4Addr,
+    pub channel: UseChannelType,
+}
+
+impl FromStr for PeerChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, channel) = match s.split_once('=') {
+            Some(v) => v,
+            None => return Err(format!("'{}' invalid, example: 10.26.0.3=relay", s)),
+        };
+        let ip = Ipv4Addr::from_str(ip.trim()).map_err(|e| format!("'{}' {}", s, e))?;
+        let channel = match UseChannelType::from_str(channel)? {
+            UseChannelType::All => {
+                return Err(format!("'{}' invalid, enum: relay/p2p", s));
+            }
+            channel => channel,
+        };
+        Ok(PeerChannel { ip, channel })
+    }
+}
+
+impl fmt::Display for PeerChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channel = match self.channel {
+            UseChannelType::Relay => "relay",
+            UseChannelType::P2p => "p2p",
+            UseChannelType::All => "all",
+        };
+        write!(f, "{}={}", self.ip, channel)
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum ConnectProtocol {
     UDP,
     TCP,
     WS,
     WSS,
+    QUIC,
+    TLS,
 }
 
 impl ConnectProtocol {
@@ -94,11 +160,20 @@ impl ConnectProtocol {
     pub fn is_wss(&self) -> bool {
         self == &ConnectProtocol::WSS
     }
+    #[inline]
+    pub fn is_quic(&self) -> bool {
+        self == &ConnectProtocol::QUIC
+    }
+    #[inline]
+    pub fn is_tls(&self) -> bool {
+        self == &ConnectProtocol::TLS
+    }
+    /// 需要解析服务端地址的协议
     pub fn is_transport(&self) -> bool {
-        self.is_tcp() || self.is_udp()
+        self.is_tcp() || self.is_udp() || self.is_quic() || self.is_tls()
     }
     pub fn is_base_tcp(&self) -> bool {
-        self.is_tcp() || self.is_ws() || self.is_wss()
+        self.is_tcp() || self.is_ws() || self.is_wss() || self.is_tls()
     }
 }
 
@@ -206,6 +281,23 @@ pub(crate) fn init_context(
     default_interface: LocalInterface,
     up_traffic_meter: Option<TrafficMeterMultiAddress>,
     down_traffic_meter: Option<TrafficMeterMultiAddress>,
+    obfs: Obfs,
+    outbound_proxy: Option<OutboundProxy>,
+    multipath_interfaces: Vec<LocalInterface>,
+    multipath_model: MultipathModel,
+    fec: Fec,
+    aggregator: Option<Aggregator>,
+    layer2: bool,
+    peer_relay: bool,
+    acl: crate::acl::Acl,
+    encrypt_scope: crate::cipher::EncryptScope,
+    bandwidth: Option<crate::util::limit::BandwidthLimiter>,
+    qos: bool,
+    path_switch: bool,
+    capture: Option<capture::Capture>,
+    #[cfg(feature = "wireguard")] wireguard: Option<crate::wireguard::WireGuard>,
+    #[cfg(feature = "peer_key")] peer_key: Option<crate::peer_key::PeerKeys>,
+    #[cfg(feature = "integrity")] integrity: Option<crate::cipher::Integrity>,
 ) -> anyhow::Result<(ChannelContext, std::net::TcpListener)> {
     assert!(!ports.is_empty(), "not channel");
     let mut main_udp_socket_v4 = Vec::with_capacity(ports.len());
@@ -230,6 +322,26 @@ pub(crate) fn init_context(
             main_udp_socket_v4.push(main_channel_v4);
         }
     }
+    // 多路径传输，每个网卡绑定一个udp socket，和普通的通道一样打洞和发送心跳
+    for interface in &multipath_interfaces {
+        let socket = bind_udp("0.0.0.0:0".parse().unwrap(), interface)?;
+        if let Err(e) = socket.set_recv_buffer_size(2 * 1024 * 1024) {
+            log::warn!("set_recv_buffer_size {:?}", e);
+        }
+        let udp: UdpSocket = socket.into();
+        log::info!("多路径网卡 {:?},{:?}", interface, udp.local_addr());
+        main_udp_socket_v4.push(udp);
+    }
+    let multipath = if multipath_interfaces.is_empty() {
+        None
+    } else {
+        Some(Multipath::new(multipath_model))
+    };
+    let quality = if path_switch && !use_channel_type.is_only_p2p() {
+        Some(PathQuality::new())
+    } else {
+        None
+    };
     let mut main_udp_socket =
         Vec::with_capacity(main_udp_socket_v4.len() + main_udp_socket_v6.len());
     let v4_len = main_udp_socket_v4.len();
@@ -246,6 +358,25 @@ pub(crate) fn init_context(
         up_traffic_meter,
         down_traffic_meter,
         default_interface,
+        obfs,
+        outbound_proxy,
+        multipath,
+        quality,
+        fec,
+        aggregator,
+        layer2,
+        peer_relay,
+        acl,
+        encrypt_scope,
+        bandwidth,
+        qos,
+        capture,
+        #[cfg(feature = "wireguard")]
+        wireguard,
+        #[cfg(feature = "peer_key")]
+        peer_key,
+        #[cfg(feature = "integrity")]
+        integrity,
     );
 
     let port = context.main_local_udp_port()?[0];
@@ -326,11 +457,24 @@ fn bind_udp_v4_and_v6(
     }
 }
 
+/// 从服务端地址中取出证书校验使用的域名，例如 vnt.example.com:29872
+#[cfg(any(feature = "quic", feature = "tls"))]
+pub(crate) fn server_name(server: &str) -> String {
+    if let Some(v6) = server.strip_prefix('[') {
+        return v6.split(']').next().unwrap_or_default().to_string();
+    }
+    match server.rfind(':') {
+        Some(index) => server[..index].to_string(),
+        None => server.to_string(),
+    }
+}
+
 pub(crate) fn init_channel<H>(
     tcp_listener: std::net::TcpListener,
     context: ChannelContext,
     stop_manager: StopManager,
     recv_handler: H,
+    _server_cert: Option<String>,
 ) -> anyhow::Result<(
     AcceptSocketSender<Option<Vec<mio::net::UdpSocket>>>,
     ConnectUtil,
@@ -340,7 +484,9 @@ where
 {
     let (tcp_connect_s, tcp_connect_r) = channel(16);
     let (ws_connect_s, _ws_connect_r) = channel(16);
-    let connect_util = ConnectUtil::new(tcp_connect_s, ws_connect_s);
+    let (quic_connect_s, _quic_connect_r) = channel(16);
+    let (tls_connect_s, _tls_connect_r) = channel(16);
+    let connect_util = ConnectUtil::new(tcp_connect_s, ws_connect_s, quic_connect_s, tls_connect_s);
     // udp监听，udp_socket_sender 用于NAT类型切换
     let udp_socket_sender =
         udp_listen(stop_manager.clone(), recv_handler.clone(), context.clone())?;
@@ -353,7 +499,27 @@ where
         stop_manager.clone(),
     )?;
     #[cfg(feature = "ws")]
-    ws_connect_accept(_ws_connect_r, recv_handler, context.clone(), stop_manager)?;
+    ws_connect_accept(
+        _ws_connect_r,
+        recv_handler.clone(),
+        context.clone(),
+        stop_manager.clone(),
+    )?;
+    #[cfg(feature = "quic")]
+    quic_connect_accept(
+        _quic_connect_r,
+        recv_handler.clone(),
+        context.clone(),
+        stop_manager.clone(),
+    )?;
+    #[cfg(feature = "tls")]
+    tls_connect_accept(
+        _tls_connect_r,
+        recv_handler,
+        context.clone(),
+        stop_manager,
+        _server_cert,
+    )?;
 
     Ok((udp_socket_sender, connect_util))
 }
@@ -0,0 +1,170 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crossbeam_utils::atomic::AtomicCell;
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+
+use crate::channel::{Route, RouteKey, DEFAULT_RT};
+
+/// 多路径传输时数据包选择路径的方式
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MultipathModel {
+    // 使用质量最好的路径，路径不可用时切换到其他路径
+    Failover,
+    // 在质量相近的路径之间轮流发送
+    Balance,
+}
+
+impl FromStr for MultipathModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim() {
+            "failover" => Ok(MultipathModel::Failover),
+            "balance" => Ok(MultipathModel::Balance),
+            _ => Err(format!("not match '{}', enum: failover/balance", s)),
+        }
+    }
+}
+
+impl Default for MultipathModel {
+    fn default() -> Self {
+        MultipathModel::Failover
+    }
+}
+
+// 统计窗口，超过后计数减半，让丢包率能跟上链路变化
+const STAT_WINDOW: u32 = 20;
+// 连续丢失的心跳数，达到后认为路径不可用
+const DOWN_STREAK: u32 = 2;
+// 每1%丢包率折算的延迟(ms)
+const LOSS_PENALTY: i64 = 10;
+// 负载均衡时，只使用分数不超过最优路径的 2倍+BALANCE_TOLERANCE 的路径
+const BALANCE_TOLERANCE: i64 = 20;
+// 丢包率超过此值的路径不参与负载均衡
+const BALANCE_MAX_LOSS: u32 = 30;
+
+/// 单条路径的心跳统计
+#[derive(Copy, Clone, Debug, Default)]
+struct PathStat {
+    sent: u32,
+    recv: u32,
+    // 最近一次心跳是否还未收到回应
+    wait: bool,
+    // 连续丢失的心跳数
+    lost_streak: u32,
+}
+
+impl PathStat {
+    /// 丢包率(百分比)，未回应的最后一个心跳不计入
+    fn loss(&self) -> u32 {
+        let sent = if self.wait { self.sent - 1 } else { self.sent };
+        if sent == 0 {
+            return 0;
+        }
+        (sent.saturating_sub(self.recv) * 100 / sent).min(100)
+    }
+    fn is_down(&self) -> bool {
+        self.lost_streak >= DOWN_STREAK
+    }
+}
+
+/// 多路径传输，根据心跳测得的延迟和丢包率为每个对端选择路径
+pub struct Multipath {
+    model: MultipathModel,
+    stats: Mutex<FnvHashMap<RouteKey, PathStat>>,
+}
+
+impl Multipath {
+    pub fn new(model: MultipathModel) -> Self {
+        Self {
+            model,
+            stats: Mutex::new(FnvHashMap::default()),
+        }
+    }
+    pub fn model(&self) -> MultipathModel {
+        self.model
+    }
+    /// 通过路径发出了心跳
+    pub fn ping(&self, route_key: &RouteKey) {
+        let mut guard = self.stats.lock();
+        let stat = guard.entry(*route_key).or_default();
+        if stat.wait {
+            stat.lost_streak += 1;
+        }
+        if stat.sent >= STAT_WINDOW {
+            stat.sent /= 2;
+            stat.recv /= 2;
+        }
+        stat.sent += 1;
+        stat.wait = true;
+    }
+    /// 收到了路径上的心跳回应
+    pub fn pong(&self, route_key: &RouteKey) {
+        if let Some(stat) = self.stats.lock().get_mut(route_key) {
+            if stat.wait {
+                stat.recv += 1;
+                stat.wait = false;
+            }
+            stat.lost_streak = 0;
+        }
+    }
+    pub fn remove(&self, route_key: &RouteKey) {
+        self.stats.lock().remove(route_key);
+    }
+    /// 路径丢包率(百分比)
+    pub fn loss(&self, route_key: &RouteKey) -> u32 {
+        self.stats
+            .lock()
+            .get(route_key)
+            .map(|stat| stat.loss())
+            .unwrap_or(0)
+    }
+    /// 选择发送数据的路径，count用于负载均衡时轮流选择
+    pub(crate) fn select(
+        &self,
+        count: &AtomicUsize,
+        routes: &[(Route, AtomicCell<Instant>)],
+    ) -> Option<Route> {
+        let stats = self.stats.lock();
+        let mut list: Vec<(Route, i64, u32)> = Vec::with_capacity(routes.len());
+        let mut all_down = true;
+        for (route, _) in routes {
+            // 跳过默认rt的路由(一般是刚加入的)
+            if route.rt == DEFAULT_RT {
+                continue;
+            }
+            let stat = stats.get(&route.route_key()).copied().unwrap_or_default();
+            if stat.is_down() {
+                continue;
+            }
+            all_down = false;
+            let loss = stat.loss();
+            list.push((*route, route.rt + loss as i64 * LOSS_PENALTY, loss));
+        }
+        drop(stats);
+        if all_down {
+            // 全部不可用时还是使用延迟最低的路径，等待路径恢复或者被剔除
+            return routes
+                .iter()
+                .map(|(route, _)| *route)
+                .find(|route| route.rt != DEFAULT_RT);
+        }
+        list.sort_by_key(|(_, score, _)| *score);
+        let (best, best_score, _) = list[0];
+        match self.model {
+            MultipathModel::Failover => Some(best),
+            MultipathModel::Balance => {
+                let limit = best_score * 2 + BALANCE_TOLERANCE;
+                list.retain(|(_, score, loss)| *score <= limit && *loss <= BALANCE_MAX_LOSS);
+                if list.is_empty() {
+                    return Some(best);
+                }
+                let index = count.fetch_add(1, Ordering::Relaxed) % list.len();
+                Some(list[index].0)
+            }
+        }
+    }
+}
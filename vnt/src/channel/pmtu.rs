@@ -0,0 +1,199 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+
+use crate::channel::RouteKey;
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::HEAD_LEN;
+
+/// 探测的路径mtu(外层ip包长度)，从大到小，所有探测包一次发出，取收到回应的最大值
+pub const PROBE_MTU: [u16; 8] = [1500, 1492, 1480, 1460, 1440, 1400, 1350, 1280];
+// 重新探测的间隔，路径可能发生变化
+const PROBE_INTERVAL: Duration = Duration::from_secs(600);
+// 没有收到任何回应时，重试的间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+// vnt包头、加密和混淆等的开销，按最大值算
+const TUNNEL_OVERHEAD: u16 = HEAD_LEN as u16 + ENCRYPTION_RESERVED as u16 + 16;
+
+/// 外层ip头+udp头的长度
+pub fn ip_udp_head_len(addr: &SocketAddr) -> u16 {
+    if addr.is_ipv4() {
+        20 + 8
+    } else {
+        40 + 8
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct RouteMtu {
+    // 已确认可用的路径mtu，0表示未知
+    mtu: u16,
+    // 本轮探测收到回应的最大值
+    round_mtu: u16,
+    probe_time: Instant,
+}
+
+/// 每条udp路径的mtu，用于钳制经过虚拟网络的tcp mss，避免大包在pppoe等链路上被静默丢弃
+pub struct Pmtu {
+    routes: Mutex<FnvHashMap<RouteKey, RouteMtu>>,
+}
+
+impl Pmtu {
+    pub fn new() -> Self {
+        Self {
+            routes: Mutex::new(FnvHashMap::default()),
+        }
+    }
+    /// 是否需要探测，需要时记录探测时间
+    pub fn start_probe(&self, route_key: &RouteKey) -> bool {
+        let now = Instant::now();
+        let mut guard = self.routes.lock();
+        match guard.get_mut(route_key) {
+            Some(v) => {
+                let interval = if v.mtu == 0 {
+                    RETRY_INTERVAL
+                } else {
+                    PROBE_INTERVAL
+                };
+                if now.duration_since(v.probe_time) < interval {
+                    return false;
+                }
+                v.round_mtu = 0;
+                v.probe_time = now;
+            }
+            None => {
+                guard.insert(
+                    *route_key,
+                    RouteMtu {
+                        mtu: 0,
+                        round_mtu: 0,
+                        probe_time: now,
+                    },
+                );
+            }
+        }
+        true
+    }
+    /// 收到探测回应
+    pub fn reply(&self, route_key: &RouteKey, mtu: u16) {
+        if let Some(v) = self.routes.lock().get_mut(route_key) {
+            if mtu > v.round_mtu {
+                v.round_mtu = mtu;
+                if v.mtu != mtu {
+                    log::info!("路径mtu {:?} {}->{}", route_key, v.mtu, mtu);
+                }
+                v.mtu = mtu;
+            }
+        }
+    }
+    pub fn route_mtu(&self, route_key: &RouteKey) -> Option<u16> {
+        self.routes
+            .lock()
+            .get(route_key)
+            .map(|v| v.mtu)
+            .filter(|mtu| *mtu != 0)
+    }
+    pub fn remove(&self, route_key: &RouteKey) {
+        self.routes.lock().remove(route_key);
+    }
+}
+
+/// 路径mtu对应的虚拟网络内tcp mss
+pub fn path_mss(addr: &SocketAddr, mtu: u16) -> u16 {
+    // 内层ip头+tcp头
+    mtu.saturating_sub(ip_udp_head_len(addr) + TUNNEL_OVERHEAD + 20 + 20)
+}
+
+/// 钳制tcp syn包的mss选项，ipv4为完整的ip包，返回是否修改
+pub fn clamp_mss(ipv4: &mut [u8], max_mss: u16) -> bool {
+    if ipv4.len() < 20 || ipv4[0] >> 4 != 4 || ipv4[9] != 6 {
+        return false;
+    }
+    // 分片包不处理
+    if u16::from_be_bytes([ipv4[6], ipv4[7]]) & 0x3FFF != 0 {
+        return false;
+    }
+    let ip_head_len = ((ipv4[0] & 0x0F) as usize) * 4;
+    let tcp = match ipv4.get_mut(ip_head_len..) {
+        Some(tcp) if tcp.len() >= 20 => tcp,
+        _ => return false,
+    };
+    // 只处理syn包
+    if tcp[13] & 0x02 == 0 {
+        return false;
+    }
+    let tcp_head_len = ((tcp[12] >> 4) as usize) * 4;
+    if tcp_head_len < 20 || tcp_head_len > tcp.len() {
+        return false;
+    }
+    let mut i = 20;
+    while i < tcp_head_len {
+        match tcp[i] {
+            // 选项结束
+            0 => break,
+            // nop
+            1 => {
+                i += 1;
+            }
+            kind => {
+                if i + 1 >= tcp_head_len {
+                    break;
+                }
+                let len = tcp[i + 1] as usize;
+                if len < 2 || i + len > tcp_head_len {
+                    break;
+                }
+                if kind == 2 && len == 4 {
+                    let mss = u16::from_be_bytes([tcp[i + 2], tcp[i + 3]]);
+                    if mss <= max_mss {
+                        return false;
+                    }
+                    tcp[i + 2..i + 4].copy_from_slice(&max_mss.to_be_bytes());
+                    // 增量更新校验和 RFC1624
+                    let checksum = u16::from_be_bytes([tcp[16], tcp[17]]);
+                    let mut sum = (!checksum) as u32 + (!mss) as u32 + max_mss as u32;
+                    while sum >> 16 != 0 {
+                        sum = (sum & 0xFFFF) + (sum >> 16);
+                    }
+                    tcp[16..18].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+                    return true;
+                }
+                i += len;
+            }
+        }
+    }
+    false
+}
+
+#[test]
+fn test_clamp_mss() {
+    // syn包，mss=1460
+    let mut buf = [
+        0x45, 0x00, 0x00, 0x2c, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 10, 26, 0, 2, 10,
+        26, 0, 3, 0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x60,
+        0x02, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4,
+    ];
+    fn checksum(ipv4: &[u8]) -> u16 {
+        let tcp = &ipv4[20..];
+        let mut sum = 0u32;
+        for i in (12..20).step_by(2) {
+            sum += u16::from_be_bytes([ipv4[i], ipv4[i + 1]]) as u32;
+        }
+        sum += 6 + tcp.len() as u32;
+        for i in (0..tcp.len()).step_by(2) {
+            sum += u16::from_be_bytes([tcp[i], tcp[i + 1]]) as u32;
+        }
+        while sum >> 16 != 0 {
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+        sum as u16
+    }
+    let sum = !checksum(&buf);
+    buf[36..38].copy_from_slice(&sum.to_be_bytes());
+    assert!(clamp_mss(&mut buf, 1300));
+    assert_eq!(&buf[42..44], &1300u16.to_be_bytes());
+    assert_eq!(checksum(&buf), 0xFFFF);
+    assert!(!clamp_mss(&mut buf, 1400));
+}
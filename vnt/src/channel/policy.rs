@@ -0,0 +1,181 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use fnv::FnvHashMap;
+use parking_lot::RwLock;
+
+use crate::channel::Route;
+
+/// 到对端的选路策略
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PathPolicy {
+    /// 有直连时只使用直连，在多个直连通道间轮流发送(默认)
+    P2p,
+    /// 使用延迟最低的路径，包括经其他客户端中转的路径
+    FirstLatency,
+    /// 依次优先udp直连、tcp直连、中转
+    UdpFirst,
+    /// 依次优先tcp直连、udp直连、中转，适合udp会被限速或者一段时间后被阻断的网络
+    TcpFirst,
+}
+
+impl FromStr for PathPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim() {
+            "p2p" => Ok(PathPolicy::P2p),
+            "first-latency" => Ok(PathPolicy::FirstLatency),
+            "udp-first" => Ok(PathPolicy::UdpFirst),
+            "tcp-first" => Ok(PathPolicy::TcpFirst),
+            _ => Err(format!(
+                "not match '{}', enum: p2p/first-latency/udp-first/tcp-first",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PathPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let policy = match self {
+            PathPolicy::P2p => "p2p",
+            PathPolicy::FirstLatency => "first-latency",
+            PathPolicy::UdpFirst => "udp-first",
+            PathPolicy::TcpFirst => "tcp-first",
+        };
+        write!(f, "{}", policy)
+    }
+}
+
+/// 选路策略，格式 `[<对端虚拟ip>=]<策略>`，不带ip时是全局的策略，例如 `udp-first`、`10.26.0.3=tcp-first`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PathPolicyRule {
+    /// None表示所有对端
+    pub ip: Option<Ipv4Addr>,
+    pub policy: PathPolicy,
+}
+
+impl FromStr for PathPolicyRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, policy) = match s.split_once('=') {
+            Some((ip, policy)) => {
+                let ip = Ipv4Addr::from_str(ip.trim()).map_err(|e| format!("'{}' {}", s, e))?;
+                (Some(ip), policy)
+            }
+            None => (None, s),
+        };
+        let policy = PathPolicy::from_str(policy)?;
+        Ok(PathPolicyRule { ip, policy })
+    }
+}
+
+impl fmt::Display for PathPolicyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ip {
+            Some(ip) => write!(f, "{}={}", ip, self.policy),
+            None => write!(f, "{}", self.policy),
+        }
+    }
+}
+
+/// 全局和每个对端的选路策略，都没有配置时由--first-latency决定
+pub struct PathPolicies {
+    first_latency: bool,
+    // (全局的策略, 对端单独的策略)
+    rules: RwLock<(Option<PathPolicy>, FnvHashMap<Ipv4Addr, PathPolicy>)>,
+}
+
+impl PathPolicies {
+    pub fn new(first_latency: bool) -> Self {
+        Self {
+            first_latency,
+            rules: RwLock::new((None, FnvHashMap::default())),
+        }
+    }
+    /// 替换全部策略，有变化时返回true，后面的规则覆盖前面的
+    pub fn set(&self, list: &[PathPolicyRule]) -> bool {
+        let mut rules = (None, FnvHashMap::default());
+        for rule in list {
+            match rule.ip {
+                Some(ip) => {
+                    rules.1.insert(ip, rule.policy);
+                }
+                None => rules.0 = Some(rule.policy),
+            }
+        }
+        let mut guard = self.rules.write();
+        if *guard == rules {
+            return false;
+        }
+        *guard = rules;
+        true
+    }
+    /// 到对端使用的策略，依次是对端单独的、全局的、--first-latency
+    pub fn get(&self, ip: &Ipv4Addr) -> PathPolicy {
+        let guard = self.rules.read();
+        match guard.1.get(ip).or(guard.0.as_ref()) {
+            Some(policy) => *policy,
+            None if self.first_latency => PathPolicy::FirstLatency,
+            None => PathPolicy::P2p,
+        }
+    }
+}
+
+/// 按协议优先选择直连，优先的协议没有直连时使用另一种协议的直连，都没有时使用经其他客户端中转的路径，
+/// 同一种协议的选延迟最低的。返回None时由调用方经服务端中转
+pub fn prefer_protocol<'a, I>(routes: I, udp: bool) -> Option<Route>
+where
+    I: Iterator<Item = &'a Route> + Clone,
+{
+    let other = |route: &Route| {
+        if udp {
+            !route.protocol.is_udp()
+        } else {
+            !route.protocol.is_base_tcp()
+        }
+    };
+    routes
+        .clone()
+        .filter(|route| route.is_p2p())
+        .min_by_key(|route| (other(route), route.rt))
+        .or_else(|| routes.min_by_key(|route| route.rt))
+        .copied()
+}
+
+#[test]
+fn test_path_policy() {
+    use crate::channel::ConnectProtocol;
+    use std::net::SocketAddr;
+
+    let rule = PathPolicyRule::from_str("10.26.0.3=TCP-first").unwrap();
+    assert_eq!(rule.to_string(), "10.26.0.3=tcp-first");
+    assert!(PathPolicyRule::from_str("10.26.0.3=fast").is_err());
+    let policies = PathPolicies::new(true);
+    let peer = Ipv4Addr::new(10, 26, 0, 3);
+    let other = Ipv4Addr::new(10, 26, 0, 4);
+    assert_eq!(policies.get(&peer), PathPolicy::FirstLatency);
+    let rules = [PathPolicyRule::from_str("udp-first").unwrap(), rule];
+    assert!(policies.set(&rules));
+    assert!(!policies.set(&rules));
+    assert_eq!(policies.get(&peer), PathPolicy::TcpFirst);
+    assert_eq!(policies.get(&other), PathPolicy::UdpFirst);
+
+    let addr = SocketAddr::from(([192, 168, 1, 2], 29872));
+    let udp = Route::new(ConnectProtocol::UDP, 0, addr, 1, 80);
+    let tcp = Route::new(ConnectProtocol::TCP, 0, addr, 1, 20);
+    let relay_addr = SocketAddr::from(([192, 168, 1, 3], 29872));
+    let relay = Route::new(ConnectProtocol::UDP, 0, relay_addr, 2, 10);
+    let routes = [relay, tcp, udp];
+    let route = prefer_protocol(routes.iter(), true).unwrap();
+    assert_eq!(route.route_key(), udp.route_key());
+    let route = prefer_protocol(routes.iter(), false).unwrap();
+    assert_eq!(route.route_key(), tcp.route_key());
+    // 没有直连时使用中转
+    let route = prefer_protocol(routes[..1].iter(), false).unwrap();
+    assert_eq!(route.metric, 2);
+    assert!(prefer_protocol([].iter(), true).is_none());
+}
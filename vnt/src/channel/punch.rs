@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::ops::{Div, Mul};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use std::{io, thread};
@@ -15,6 +16,10 @@ use crate::channel::sender::ConnectUtil;
 use crate::handle::CurrentDeviceInfo;
 use crate::nat::{is_ipv4_global, NatTest};
 
+// 双方都是对称网络时，使用的子端口数和预测的端口范围，一轮发送 BURST_SOCKETS * BURST_WINDOW 个包
+const BURST_SOCKETS: usize = 16;
+const BURST_WINDOW: u16 = 64;
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum PunchModel {
     IPv4,
@@ -73,6 +78,42 @@ impl Default for PunchModel {
     }
 }
 
+/// 打洞参数，重新加载配置时直接修改，不需要重启打洞线程
+#[derive(Clone)]
+pub struct PunchSettings {
+    model: Arc<AtomicCell<PunchModel>>,
+    // 每分钟最多发起的打洞协商次数
+    rate: Arc<AtomicU32>,
+    // 同时进行的打洞数上限
+    concurrency: Arc<AtomicUsize>,
+}
+
+impl PunchSettings {
+    pub fn new(model: PunchModel, rate: u32, concurrency: usize) -> Self {
+        Self {
+            model: Arc::new(AtomicCell::new(model)),
+            rate: Arc::new(AtomicU32::new(rate)),
+            concurrency: Arc::new(AtomicUsize::new(concurrency)),
+        }
+    }
+    pub fn model(&self) -> PunchModel {
+        self.model.load()
+    }
+    /// 发起打洞协商的间隔
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(60_000 / self.rate.load(Ordering::Relaxed).max(1) as u64)
+    }
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.load(Ordering::Relaxed)
+    }
+    /// 返回参数是否有变化
+    pub fn set(&self, model: PunchModel, rate: u32, concurrency: usize) -> bool {
+        let changed = self.model.swap(model) != model;
+        let changed = self.rate.swap(rate, Ordering::Relaxed) != rate || changed;
+        self.concurrency.swap(concurrency, Ordering::Relaxed) != concurrency || changed
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct NatInfo {
     pub public_ips: Vec<Ipv4Addr>,
@@ -83,6 +124,11 @@ pub struct NatInfo {
     pub(crate) ipv6: Option<Ipv6Addr>,
     pub udp_ports: Vec<u16>,
     pub tcp_port: u16,
+    // 额外的候选地址，本端是路由器映射的地址，对端是协商消息中携带的
+    pub candidates: Vec<Candidate>,
+    // rfc5780探测的映射行为和过滤行为
+    pub mapping: NatBehavior,
+    pub filtering: NatBehavior,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
@@ -91,6 +137,62 @@ pub enum NatType {
     Cone,
 }
 
+/// nat的映射/过滤行为(rfc5780)，映射行为非EndpointIndependent即对称网络
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum NatBehavior {
+    // 未探测或者stun服务器不支持
+    Unknown,
+    EndpointIndependent,
+    AddressDependent,
+    AddressAndPortDependent,
+}
+
+impl Default for NatBehavior {
+    fn default() -> Self {
+        NatBehavior::Unknown
+    }
+}
+
+/// 候选地址类型，参考ICE(rfc8445)，中继始终作为兜底，不参与连通性检查
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum CandidateType {
+    // 本地网卡地址
+    Host,
+    // 路由器映射(upnp/pcp/nat-pmp)的地址
+    Mapped,
+    // stun/服务端探测到的公网地址
+    ServerReflexive,
+}
+
+impl CandidateType {
+    fn preference(&self) -> u32 {
+        match self {
+            CandidateType::Host => 126,
+            CandidateType::Mapped => 110,
+            CandidateType::ServerReflexive => 100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Candidate {
+    pub kind: CandidateType,
+    pub addr: SocketAddr,
+    pub priority: u32,
+}
+
+impl Candidate {
+    /// 优先级计算同ICE：类型偏好 << 24 | 本地偏好 << 8 | (256 - 组件)，只有一个组件
+    pub fn new(kind: CandidateType, addr: SocketAddr, local_preference: u16) -> Self {
+        let priority = (kind.preference() << 24) | ((local_preference as u32) << 8) | 255;
+        Self {
+            kind,
+            addr,
+            priority,
+        }
+    }
+}
+
 impl NatInfo {
     pub fn new(
         mut public_ips: Vec<Ipv4Addr>,
@@ -131,7 +233,47 @@ impl NatInfo {
             udp_ports,
             tcp_port,
             nat_type,
+            candidates: Vec::new(),
+            mapping: NatBehavior::Unknown,
+            filtering: NatBehavior::Unknown,
+        }
+    }
+    /// 收集全部候选地址，按优先级从高到低排列
+    pub fn gather_candidates(&self) -> Vec<Candidate> {
+        let mut list = self.candidates.clone();
+        // 同类型下ipv6优先，多个端口时主端口优先
+        for (index, port) in self.udp_ports.iter().enumerate() {
+            let local_preference = u16::MAX - 1 - index as u16;
+            if let Some(ipv6) = self.ipv6 {
+                let addr = SocketAddr::V6(SocketAddrV6::new(ipv6, *port, 0, 0));
+                list.push(Candidate::new(CandidateType::Host, addr, u16::MAX));
+            }
+            if let Some(ipv4) = self.local_ipv4 {
+                let addr = SocketAddr::V4(SocketAddrV4::new(ipv4, *port));
+                list.push(Candidate::new(CandidateType::Host, addr, local_preference));
+            }
+        }
+        for ip in &self.public_ips {
+            for (index, port) in self.public_ports.iter().enumerate() {
+                let addr = SocketAddr::V4(SocketAddrV4::new(*ip, *port));
+                let local_preference = u16::MAX - 1 - index as u16;
+                list.push(Candidate::new(
+                    CandidateType::ServerReflexive,
+                    addr,
+                    local_preference,
+                ));
+            }
+            // 可能是开放了端口或者端口不变的nat，优先级最低
+            for port in &self.udp_ports {
+                let addr = SocketAddr::V4(SocketAddrV4::new(*ip, *port));
+                list.push(Candidate::new(CandidateType::ServerReflexive, addr, 0));
+            }
         }
+        list.retain(|c| c.addr.port() != 0 && !c.addr.ip().is_unspecified());
+        list.sort_by(|a, b| b.priority.cmp(&a.priority));
+        let mut set = HashSet::new();
+        list.retain(|c| set.insert(c.addr));
+        list
     }
     pub fn update_addr(&mut self, index: usize, ip: Ipv4Addr, port: u16) -> bool {
         let mut updated = false;
@@ -217,7 +359,7 @@ pub struct Punch {
     context: ChannelContext,
     port_vec: Vec<u16>,
     port_index: HashMap<Ipv4Addr, usize>,
-    punch_model: PunchModel,
+    settings: PunchSettings,
     connect_util: ConnectUtil,
     nat_test: NatTest,
     current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
@@ -226,7 +368,7 @@ pub struct Punch {
 impl Punch {
     pub fn new(
         context: ChannelContext,
-        punch_model: PunchModel,
+        settings: PunchSettings,
         connect_util: ConnectUtil,
         nat_test: NatTest,
         current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
@@ -239,7 +381,7 @@ impl Punch {
             context,
             port_vec,
             port_index: HashMap::new(),
-            punch_model,
+            settings,
             connect_util,
             nat_test,
             current_device,
@@ -266,7 +408,9 @@ impl Punch {
             log::info!("已打洞成功,无需打洞:{:?}", id);
             return Ok(());
         }
+        self.context.counters().punch_attempt();
         let device_info = self.current_device.load();
+        let punch_model = self.settings.model();
 
         nat_info
             .public_ips
@@ -278,14 +422,14 @@ impl Punch {
             .local_ipv4
             .filter(|ip| device_info.not_in_network(*ip));
 
-        if punch_tcp && self.punch_model.use_tcp() && nat_info.tcp_port != 0 {
+        if punch_tcp && punch_model.use_tcp() && nat_info.tcp_port != 0 {
             //向tcp发起连接
-            if self.punch_model.use_ipv6() {
+            if punch_model.use_ipv6() {
                 if let Some(ipv6_addr) = nat_info.local_tcp_ipv6addr() {
                     self.connect_tcp(buf, ipv6_addr)
                 }
             }
-            if self.punch_model.use_ipv4() {
+            if punch_model.use_ipv4() {
                 if let Some(ipv4_addr) = nat_info.local_tcp_ipv4addr() {
                     self.connect_tcp(buf, ipv4_addr)
                 }
@@ -295,50 +439,42 @@ impl Punch {
                 }
             }
         }
-        if !self.punch_model.use_udp() {
+        if !punch_model.use_udp() {
             return Ok(());
         }
         let channel_num = self.context.channel_num();
         let main_len = self.context.main_len();
-
-        if self.punch_model.use_ipv6() {
-            for index in channel_num..main_len {
-                if let Some(ipv6_addr) = nat_info.local_udp_ipv6addr(index) {
-                    if !self.nat_test.is_local_address(false, ipv6_addr) {
-                        let rs = self.context.send_main_udp(index, buf, ipv6_addr);
-                        log::info!("发送到ipv6地址:{:?},rs={:?} {}", ipv6_addr, rs, id);
-                        if rs.is_ok() && self.punch_model == PunchModel::IPv6 {
-                            return Ok(());
-                        }
-                    }
-                }
+        // 按优先级依次做连通性检查，每个候选地址用同协议族的全部主端口发送
+        let mut candidates = nat_info.gather_candidates();
+        candidates.retain(|c| match c.addr {
+            SocketAddr::V4(addr) => {
+                punch_model.use_ipv4()
+                    && device_info.not_in_network(*addr.ip())
+                    && (c.kind == CandidateType::Host || is_ipv4_global(addr.ip()))
             }
-        }
-        if !self.punch_model.use_ipv4() {
-            return Ok(());
-        }
-        for index in 0..channel_num {
-            if let Some(ipv4_addr) = nat_info.local_udp_ipv4addr(index) {
-                if !self.nat_test.is_local_address(false, ipv4_addr) {
-                    let _ = self.context.send_main_udp(index, buf, ipv4_addr);
-                }
+            SocketAddr::V6(_) => punch_model.use_ipv6(),
+        });
+        for candidate in &candidates {
+            if self.nat_test.is_local_address(false, candidate.addr) {
+                continue;
             }
-        }
-        // 可能是开放了端口的，需要打洞
-        for index in 0..channel_num {
-            for port in &nat_info.udp_ports {
-                if *port == 0 {
-                    continue;
-                }
-                for ip in &nat_info.public_ips {
-                    if ip.is_unspecified() {
-                        continue;
-                    }
-                    let addr = SocketAddrV4::new(*ip, *port);
-                    let _ = self.context.send_main_udp(index, buf, addr.into());
-                    thread::sleep(Duration::from_millis(3));
-                }
+            if self.context.route_table.no_need_punch(&id) {
+                log::info!("打洞成功,停止检查:{:?} {}", candidate, id);
+                return Ok(());
+            }
+            let range = if candidate.addr.is_ipv4() {
+                0..channel_num
+            } else {
+                channel_num..main_len
+            };
+            for index in range {
+                let rs = self.context.send_main_udp(index, buf, candidate.addr);
+                log::debug!("连通性检查:{:?},rs={:?} {}", candidate, rs, id);
             }
+            thread::sleep(Duration::from_millis(2));
+        }
+        if !punch_model.use_ipv4() {
+            return Ok(());
         }
 
         match nat_info.nat_type {
@@ -348,23 +484,58 @@ impl Punch {
                 // n取76，k取600，猜中的概率就超过50%了
                 // 前提 自己是锥形网络，否则猜中了也通信不了
 
+                if nat_info.filtering == NatBehavior::EndpointIndependent {
+                    // 对端不过滤来源，发到反射地址就能收到，不需要猜端口
+                    return Ok(());
+                }
+                if !self.context.is_cone() && nat_info.public_port_range <= BURST_WINDOW {
+                    // 双方都是对称网络，单个端口猜中了也收不到回应
+                    // 对端是顺序分配端口时，双方同时用多个端口向对方预测的端口范围发送，
+                    // 新建的映射落在对方的发送范围内就能打通，同时发送由服务端转发的协商消息保证
+                    let port = nat_info.public_ports.get(0).map(|e| *e).unwrap_or(0);
+                    if port != 0 {
+                        let end = port.saturating_add(BURST_WINDOW);
+                        let ports: Vec<u16> = (port.saturating_add(1)..=end).collect();
+                        log::info!(
+                            "对称网络之间打洞 {} 端口范围{}-{} {:?}",
+                            id,
+                            port,
+                            end,
+                            nat_info.public_ips
+                        );
+                        self.context.try_send_burst(
+                            buf,
+                            &nat_info.public_ips,
+                            &ports,
+                            BURST_SOCKETS,
+                        );
+                        return Ok(());
+                    }
+                }
+                // 根据对端的映射行为调整预测范围和探测规模
+                let (max_k1, k2_range, port_range) = match nat_info.mapping {
+                    // 端口只和目标ip相关，一般是顺序分配的，预测范围内多发，随机探测少发
+                    NatBehavior::AddressDependent => {
+                        (120, 300..400, nat_info.public_port_range.max(60))
+                    }
+                    _ => (60, 600..800, nat_info.public_port_range),
+                };
                 //预测范围内最多发送max_k1个包
-                let max_k1 = 60;
                 //全局最多发送max_k2个包
-                let mut max_k2: usize = rand::thread_rng().gen_range(600..800);
+                let mut max_k2: usize = rand::thread_rng().gen_range(k2_range);
                 if count > 2 {
                     //递减探测规模
                     max_k2 = max_k2.mul(2).div(count).max(max_k1 as usize);
                 }
                 let port = nat_info.public_ports.get(0).map(|e| *e).unwrap_or(0);
-                if nat_info.public_port_range < max_k1 * 3 {
+                if port_range < max_k1 * 3 {
                     //端口变化不大时，在预测的范围内随机发送
-                    let min_port = if port > nat_info.public_port_range {
-                        port - nat_info.public_port_range
+                    let min_port = if port > port_range {
+                        port - port_range
                     } else {
                         1
                     };
-                    let (max_port, overflow) = port.overflowing_add(nat_info.public_port_range);
+                    let (max_port, overflow) = port.overflowing_add(port_range);
                     let max_port = if overflow { 65535 } else { max_port };
                     let k = if max_port - min_port + 1 > max_k1 {
                         max_k1 as usize
@@ -393,26 +564,17 @@ impl Punch {
                 self.port_index.insert(id, index);
             }
             NatType::Cone => {
-                let is_cone = self.context.is_cone();
-                'a: for index in 0..nat_info.public_ports.len().min(channel_num) {
+                if !self.context.is_cone() {
+                    //只有一方是对称，则对称方要使用全部端口发送数据，符合上述计算的概率
                     for ip in &nat_info.public_ips {
-                        let port = nat_info.public_ports[index];
+                        let port = nat_info.public_ports.get(0).map(|e| *e).unwrap_or(0);
                         if port == 0 || ip.is_unspecified() {
                             continue;
                         }
                         let addr = SocketAddr::V4(SocketAddrV4::new(*ip, port));
-                        if is_cone {
-                            self.context.send_main_udp(index, buf, addr)?;
-                        } else {
-                            //只有一方是对称，则对称方要使用全部端口发送数据，符合上述计算的概率
-                            self.context.try_send_all(buf, addr);
-                        }
+                        self.context.try_send_all(buf, addr);
                         thread::sleep(Duration::from_millis(2));
                     }
-                    if !is_cone {
-                        //对称网络数据只发一遍
-                        break 'a;
-                    }
                 }
             }
         }
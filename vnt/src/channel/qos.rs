@@ -0,0 +1,93 @@
+use std::cell::Cell;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// 不超过这个长度的ip包优先发送，dns、ssh按键、游戏数据一般都是小包
+const SMALL_PACKET: usize = 256;
+// CS4及以上的DSCP(交互视频、语音、网络控制)优先发送
+const PRIORITY_DSCP: u8 = 32;
+
+thread_local! {
+    // 当前线程正在发送的内层ip包的DSCP和是否优先发送
+    static MARK: Cell<(u8, bool)> = Cell::new((0, false));
+}
+
+/// 标记当前线程接下来发送的数据，离开作用域时清除。
+/// 发送是在读取网卡的线程中同步完成的，不需要在各层发送函数之间传递
+pub struct QosMark;
+
+impl QosMark {
+    pub fn new(ip_packet: &[u8]) -> Self {
+        let dscp = ip_dscp(ip_packet);
+        let priority = ip_packet.len() <= SMALL_PACKET || dscp >= PRIORITY_DSCP;
+        MARK.with(|v| v.set((dscp, priority)));
+        QosMark
+    }
+}
+
+impl Drop for QosMark {
+    fn drop(&mut self) {
+        MARK.with(|v| v.set((0, false)));
+    }
+}
+
+/// 当前发送的数据是否优先
+pub fn is_priority() -> bool {
+    MARK.with(|v| v.get().1)
+}
+
+fn ip_dscp(ip_packet: &[u8]) -> u8 {
+    if ip_packet.len() < 2 {
+        return 0;
+    }
+    match ip_packet[0] >> 4 {
+        4 => ip_packet[1] >> 2,
+        6 => ((ip_packet[0] & 0x0f) << 2) | (ip_packet[1] >> 6),
+        _ => 0,
+    }
+}
+
+/// 把内层ip包的DSCP复制到外层的udp包，只处理ipv4的socket。
+/// 同一个socket上的TOS只在变化时设置，多个线程同时发送时可能标记不准确
+pub struct TosMarker {
+    tos: Vec<AtomicU8>,
+}
+
+impl TosMarker {
+    pub fn new(len: usize) -> Self {
+        Self {
+            tos: (0..len).map(|_| AtomicU8::new(0)).collect(),
+        }
+    }
+    pub fn mark(&self, index: usize, udp: &UdpSocket) {
+        let tos = match self.tos.get(index) {
+            Some(tos) => tos,
+            None => return,
+        };
+        let dscp = MARK.with(|v| v.get().0);
+        let value = dscp << 2;
+        if tos.swap(value, Ordering::Relaxed) != value {
+            if let Err(e) = socket2::SockRef::from(udp).set_tos(value as u32) {
+                log::warn!("set_tos {} {:?}", value, e);
+            }
+        }
+    }
+}
+
+#[test]
+fn qos_mark_test() {
+    let mut packet = [0u8; 1400];
+    packet[0] = 0x45;
+    packet[1] = 46 << 2;
+    assert_eq!(ip_dscp(&packet), 46);
+    {
+        let _mark = QosMark::new(&packet);
+        assert!(is_priority());
+    }
+    assert!(!is_priority());
+    packet[1] = 0;
+    let _mark = QosMark::new(&packet);
+    assert!(!is_priority());
+    // ipv6 traffic class 0xb8
+    assert_eq!(ip_dscp(&[0x6b, 0x80]), 46);
+}
@@ -0,0 +1,201 @@
+use std::net::Ipv4Addr;
+
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+
+// 统计窗口，超过后计数减半，让丢包率能跟上链路变化
+const STAT_WINDOW: u32 = 20;
+// 两条路径都至少探测了这么多次才比较
+const MIN_SAMPLES: u32 = 5;
+// 每1%丢包率折算的延迟(ms)
+const LOSS_PENALTY: i64 = 10;
+// 另一条路径的分数至少要好这么多(ms)才切换，避免来回切换
+const MIN_MARGIN: i64 = 20;
+// 连续这么多轮都更好才切换
+const SWITCH_ROUNDS: u32 = 3;
+
+/// 单条路径的探测统计
+#[derive(Copy, Clone, Debug, Default)]
+struct PathStat {
+    sent: u32,
+    recv: u32,
+    // 最近一次探测是否还未收到回应
+    wait: bool,
+    // 平滑后的延迟，没有收到过回应时为None
+    rt: Option<i64>,
+}
+
+impl PathStat {
+    fn ping(&mut self) {
+        if self.sent >= STAT_WINDOW {
+            self.sent /= 2;
+            self.recv /= 2;
+        }
+        self.sent += 1;
+        self.wait = true;
+    }
+    fn pong(&mut self, rt: i64) {
+        if self.wait {
+            self.recv += 1;
+            self.wait = false;
+        }
+        self.rt = Some(match self.rt {
+            Some(v) => (v * 7 + rt) / 8,
+            None => rt,
+        });
+    }
+    /// 丢包率(百分比)，未回应的最后一个探测不计入
+    fn loss(&self) -> u32 {
+        let sent = if self.wait { self.sent - 1 } else { self.sent };
+        if sent == 0 {
+            return 0;
+        }
+        (sent.saturating_sub(self.recv) * 100 / sent).min(100)
+    }
+    /// 延迟加上丢包的惩罚，越小越好，样本不够时为None
+    fn score(&self) -> Option<i64> {
+        if self.sent < MIN_SAMPLES {
+            return None;
+        }
+        // 一直没有回应时按全部丢失计算
+        let rt = self.rt.unwrap_or(0);
+        Some(rt + self.loss() as i64 * LOSS_PENALTY)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct PeerPath {
+    direct: PathStat,
+    relay: PathStat,
+    // 当前是否使用服务端中转
+    relay_preferred: bool,
+    // 另一条路径连续更好的轮数
+    better_rounds: u32,
+}
+
+/// 到对端的直连和中转路径的质量
+#[derive(Copy, Clone, Debug)]
+pub struct PathQualityInfo {
+    /// 直连的丢包率(百分比)和平滑后的延迟
+    pub direct_loss: u32,
+    pub direct_rt: Option<i64>,
+    /// 服务端中转的丢包率(百分比)和平滑后的延迟
+    pub relay_loss: u32,
+    pub relay_rt: Option<i64>,
+    /// 当前是否使用服务端中转
+    pub relay_preferred: bool,
+}
+
+/// 持续测量有直连的对端经直连和经服务端中转的丢包和延迟，
+/// 中转明显更好时改用中转，不再是有直连就一定走直连
+pub struct PathQuality {
+    peers: Mutex<FnvHashMap<Ipv4Addr, PeerPath>>,
+}
+
+impl PathQuality {
+    pub fn new() -> Self {
+        Self {
+            peers: Mutex::new(FnvHashMap::default()),
+        }
+    }
+    /// 发出了探测，relay表示经服务端中转
+    pub fn ping(&self, ip: Ipv4Addr, relay: bool) {
+        let mut guard = self.peers.lock();
+        let peer = guard.entry(ip).or_default();
+        if relay {
+            peer.relay.ping()
+        } else {
+            peer.direct.ping()
+        }
+    }
+    /// 收到了探测的回应
+    pub fn pong(&self, ip: &Ipv4Addr, relay: bool, rt: i64) {
+        if let Some(peer) = self.peers.lock().get_mut(ip) {
+            if relay {
+                peer.relay.pong(rt)
+            } else {
+                peer.direct.pong(rt)
+            }
+        }
+    }
+    /// 数据是否应该经服务端中转
+    pub fn relay_preferred(&self, ip: &Ipv4Addr) -> bool {
+        self.peers
+            .lock()
+            .get(ip)
+            .map_or(false, |peer| peer.relay_preferred)
+    }
+    /// 每轮探测后比较两条路径，切换时返回新的选择(true表示改用中转)
+    pub fn update(&self, ip: &Ipv4Addr) -> Option<bool> {
+        let mut guard = self.peers.lock();
+        let peer = guard.get_mut(ip)?;
+        let (direct, relay) = match (peer.direct.score(), peer.relay.score()) {
+            (Some(direct), Some(relay)) => (direct, relay),
+            _ => return None,
+        };
+        let (current, other) = if peer.relay_preferred {
+            (relay, direct)
+        } else {
+            (direct, relay)
+        };
+        let margin = MIN_MARGIN.max(current / 5);
+        if other + margin < current {
+            peer.better_rounds += 1;
+        } else {
+            peer.better_rounds = 0;
+        }
+        if peer.better_rounds < SWITCH_ROUNDS {
+            return None;
+        }
+        peer.better_rounds = 0;
+        peer.relay_preferred = !peer.relay_preferred;
+        Some(peer.relay_preferred)
+    }
+    /// 没有直连时不再比较，恢复为默认的选择
+    pub fn remove(&self, ip: &Ipv4Addr) {
+        self.peers.lock().remove(ip);
+    }
+    pub fn info(&self, ip: &Ipv4Addr) -> Option<PathQualityInfo> {
+        self.peers.lock().get(ip).map(|peer| PathQualityInfo {
+            direct_loss: peer.direct.loss(),
+            direct_rt: peer.direct.rt,
+            relay_loss: peer.relay.loss(),
+            relay_rt: peer.relay.rt,
+            relay_preferred: peer.relay_preferred,
+        })
+    }
+}
+
+#[test]
+fn test_switch_hysteresis() {
+    let quality = PathQuality::new();
+    let ip = Ipv4Addr::new(10, 26, 0, 2);
+    let round = |direct: Option<i64>, relay: Option<i64>| {
+        quality.ping(ip, false);
+        quality.ping(ip, true);
+        if let Some(rt) = direct {
+            quality.pong(&ip, false, rt);
+        }
+        if let Some(rt) = relay {
+            quality.pong(&ip, true, rt);
+        }
+        quality.update(&ip)
+    };
+    // 直连丢包严重时切换到中转
+    for _ in 0..MIN_SAMPLES {
+        round(None, Some(60));
+    }
+    let mut switched = None;
+    for _ in 0..SWITCH_ROUNDS {
+        switched = switched.or(round(None, Some(60)));
+    }
+    assert_eq!(switched, Some(true));
+    assert!(quality.relay_preferred(&ip));
+    // 直连只是略好时不切换回去
+    for _ in 0..STAT_WINDOW * 2 {
+        if round(Some(55), Some(60)).is_some() {
+            break;
+        }
+    }
+    assert!(quality.relay_preferred(&ip));
+}
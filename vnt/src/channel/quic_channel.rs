@@ -0,0 +1,208 @@
+use anyhow::{anyhow, Context};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+
+use crate::channel::context::ChannelContext;
+use crate::channel::handler::RecvChannelHandler;
+use crate::channel::sender::packet_channel;
+use crate::channel::{server_name, ConnectProtocol, RouteKey, BUFFER_SIZE, TCP_MAX_PACKET_SIZE};
+use crate::protocol::Protocol;
+use crate::util::StopManager;
+
+const ALPN: &[u8] = b"vnt";
+
+/// quic协议，控制数据走双向流，转发的ip数据优先走datagram(不可靠传输，避免队头阻塞)
+/// 暂时只允许用quic连服务端，不能用quic打洞/连客户端
+pub fn quic_connect_accept<H>(
+    receiver: Receiver<(Vec<u8>, SocketAddr, String)>,
+    recv_handler: H,
+    context: ChannelContext,
+    stop_manager: StopManager,
+) -> anyhow::Result<()>
+where
+    H: RecvChannelHandler,
+{
+    let (stop_sender, stop_receiver) = tokio::sync::oneshot::channel::<()>();
+    let worker = stop_manager.add_listener("quicChannel".into(), move || {
+        let _ = stop_sender.send(());
+    })?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .context("quic tokio runtime build failed")?;
+    thread::Builder::new()
+        .name("quicChannel".into())
+        .spawn(move || {
+            runtime
+                .spawn(async move { connect_quic_handle(receiver, recv_handler, context).await });
+            runtime.block_on(async {
+                let _ = stop_receiver.await;
+            });
+            runtime.shutdown_background();
+            worker.stop_all();
+        })
+        .context("quic thread build failed")?;
+    Ok(())
+}
+
+async fn connect_quic_handle<H>(
+    mut receiver: Receiver<(Vec<u8>, SocketAddr, String)>,
+    recv_handler: H,
+    context: ChannelContext,
+) where
+    H: RecvChannelHandler,
+{
+    while let Some((data, addr, server)) = receiver.recv().await {
+        let recv_handler = recv_handler.clone();
+        let context = context.clone();
+        tokio::spawn(async move {
+            if let Err(e) = connect_quic(data, addr, server, recv_handler, context).await {
+                log::warn!("发送失败,quic链接终止:{:?},{:?}", addr, e);
+            }
+        });
+    }
+}
+
+fn client_config() -> anyhow::Result<quinn::ClientConfig> {
+    let mut root_store = rustls::RootCertStore::empty();
+    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])?
+    .with_root_certificates(root_store)
+    .with_no_client_auth();
+    config.alpn_protocols = vec![ALPN.to_vec()];
+    let config = quinn::crypto::rustls::QuicClientConfig::try_from(config)?;
+    let mut config = quinn::ClientConfig::new(Arc::new(config));
+    let mut transport = quinn::TransportConfig::default();
+    transport.keep_alive_interval(Some(Duration::from_secs(5)));
+    config.transport_config(Arc::new(transport));
+    Ok(config)
+}
+
+async fn connect_quic<H>(
+    data: Vec<u8>,
+    addr: SocketAddr,
+    server: String,
+    recv_handler: H,
+    context: ChannelContext,
+) -> anyhow::Result<()>
+where
+    H: RecvChannelHandler,
+{
+    let bind_addr: SocketAddr = if addr.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = crate::channel::socket::bind_udp(bind_addr, context.default_interface())?;
+    let endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        None,
+        socket.into(),
+        Arc::new(quinn::TokioRuntime),
+    )?;
+    let server_name = server_name(&server);
+    log::info!("尝试建立quic连接 {:?},{}", server_name, addr);
+    let connecting = endpoint.connect_with(client_config()?, addr, &server_name)?;
+    let conn = tokio::time::timeout(Duration::from_secs(3), connecting).await??;
+    let (mut send, recv) = conn.open_bi().await?;
+    quic_write(&mut send, &data).await?;
+
+    let (sender, mut receiver) = packet_channel(100);
+    context.packet_map.write().insert(addr, sender);
+    {
+        let conn = conn.clone();
+        tokio::spawn(async move {
+            while let Some(data) = receiver.recv().await {
+                let rs = if use_datagram(&conn, &data) {
+                    conn.send_datagram(data.into())
+                        .map_err(|e| anyhow!("{:?}", e))
+                } else {
+                    quic_write(&mut send, &data).await
+                };
+                if let Err(e) = rs {
+                    log::info!("发送失败,quic链接终止:{:?},{:?}", addr, e);
+                    break;
+                }
+            }
+            let _ = send.finish();
+            conn.close(0u32.into(), b"");
+        });
+    }
+    {
+        let conn = conn.clone();
+        let recv_handler = recv_handler.clone();
+        let context = context.clone();
+        tokio::spawn(async move {
+            let mut extend = [0; BUFFER_SIZE];
+            let route_key = RouteKey::new(ConnectProtocol::QUIC, 0, addr);
+            while let Ok(data) = conn.read_datagram().await {
+                let mut data = data.to_vec();
+                recv_handler.handle(&mut data, &mut extend, route_key, &context);
+            }
+        });
+    }
+    if let Err(e) = quic_read(recv, addr, &context, recv_handler).await {
+        log::warn!("quic_read {:?}", e);
+    }
+    context.packet_map.write().remove(&addr);
+    conn.close(0u32.into(), b"");
+    endpoint.wait_idle().await;
+    Ok(())
+}
+
+/// 只有转发的ip数据走datagram，超过datagram长度限制时仍然走流
+fn use_datagram(conn: &quinn::Connection, data: &[u8]) -> bool {
+    if data.len() < 2 || Protocol::from(data[1]) != Protocol::IpTurn {
+        return false;
+    }
+    match conn.max_datagram_size() {
+        Some(max) => data.len() <= max,
+        None => false,
+    }
+}
+
+/// 流上的数据格式和tcp通道一致，4字节头部+数据
+async fn quic_write(w: &mut quinn::SendStream, buf: &[u8]) -> anyhow::Result<()> {
+    let len = buf.len();
+    if len > TCP_MAX_PACKET_SIZE {
+        return Err(anyhow!("超过了quic流的最大长度传输"));
+    }
+    w.write_all(&[0, (len >> 16) as u8, (len >> 8) as u8, len as u8])
+        .await?;
+    w.write_all(buf).await?;
+    Ok(())
+}
+
+async fn quic_read<H>(
+    mut read: quinn::RecvStream,
+    addr: SocketAddr,
+    context: &ChannelContext,
+    recv_handler: H,
+) -> anyhow::Result<()>
+where
+    H: RecvChannelHandler,
+{
+    let mut head = [0; 4];
+    let mut buf = [0; BUFFER_SIZE];
+    let mut extend = [0; BUFFER_SIZE];
+    let route_key = RouteKey::new(ConnectProtocol::QUIC, 0, addr);
+    loop {
+        read.read_exact(&mut head).await?;
+        if head[0] != 0 {
+            return Err(anyhow!("quic数据流错误 {}", addr));
+        }
+        let len = ((head[1] as usize) << 16) | ((head[2] as usize) << 8) | head[3] as usize;
+        if len < 12 || len > buf.len() {
+            return Err(anyhow!("quic数据长度无效 {}", addr));
+        }
+        read.read_exact(&mut buf[..len]).await?;
+        recv_handler.handle(&mut buf[..len], &mut extend, route_key, context);
+    }
+}
@@ -0,0 +1,66 @@
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::RwLock;
+
+// 通告30秒发送一次，超过这个时间没更新的路径不再使用
+const EXPIRE_TIME: Duration = Duration::from_secs(100);
+// 每个客户端最多接收的路径数，防止对端通告大量地址
+const MAX_PATHS: usize = 256;
+
+/// 允许中转的客户端通告的直连路径，用于选择经由其他客户端中转的路线
+pub struct RelayPaths {
+    // 中转客户端 -> (能直达的客户端和延迟, 通告时间)
+    paths: RwLock<FnvHashMap<Ipv4Addr, (Vec<(Ipv4Addr, u16)>, Instant)>>,
+}
+
+impl RelayPaths {
+    pub fn new() -> Self {
+        Self {
+            paths: RwLock::new(FnvHashMap::default()),
+        }
+    }
+    /// 覆盖relay之前的通告
+    pub fn update(&self, relay: Ipv4Addr, mut list: Vec<(Ipv4Addr, u16)>) {
+        list.retain(|(ip, _)| *ip != relay);
+        list.truncate(MAX_PATHS);
+        let mut guard = self.paths.write();
+        if list.is_empty() {
+            guard.remove(&relay);
+        } else {
+            guard.insert(relay, (list, Instant::now()));
+        }
+    }
+    /// 能直达dest的中转客户端和中转客户端到dest的延迟，按延迟从低到高排列
+    pub fn relays(&self, dest: &Ipv4Addr) -> Vec<(Ipv4Addr, u16)> {
+        let mut list: Vec<(Ipv4Addr, u16)> = self
+            .paths
+            .read()
+            .iter()
+            .filter(|(_, (_, time))| time.elapsed() < EXPIRE_TIME)
+            .filter_map(|(relay, (paths, _))| {
+                paths
+                    .iter()
+                    .find(|(ip, _)| ip == dest)
+                    .map(|(_, rt)| (*relay, *rt))
+            })
+            .collect();
+        list.sort_by_key(|(_, rt)| *rt);
+        list
+    }
+}
+
+#[test]
+fn test_relay_paths() {
+    let relay_paths = RelayPaths::new();
+    let a = Ipv4Addr::new(10, 26, 0, 2);
+    let b = Ipv4Addr::new(10, 26, 0, 3);
+    let dest = Ipv4Addr::new(10, 26, 0, 4);
+    relay_paths.update(a, vec![(dest, 80), (a, 0)]);
+    relay_paths.update(b, vec![(dest, 20)]);
+    assert_eq!(relay_paths.relays(&dest), vec![(b, 20), (a, 80)]);
+    assert!(relay_paths.relays(&a).is_empty());
+    relay_paths.update(b, vec![]);
+    assert_eq!(relay_paths.relays(&dest), vec![(a, 80)]);
+}
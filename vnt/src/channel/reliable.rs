@@ -0,0 +1,212 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+
+use crate::channel::RouteKey;
+use crate::cipher::Cipher;
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::reliable_packet::{ReliablePacket, RELIABLE_HEAD_LEN};
+use crate::protocol::{reliable_packet, NetPacket, Protocol, HEAD_LEN, MAX_TTL};
+
+// 首次重传的超时时间，之后每次翻倍
+const INITIAL_RTO: Duration = Duration::from_millis(300);
+const MAX_RTO: Duration = Duration::from_secs(5);
+// 最多重传次数，超过后放弃，由上层的逻辑重新发起
+const MAX_RESEND: u8 = 6;
+// 每个来源记录最近收到的序号数，用于去重
+const RECV_WINDOW: usize = 256;
+// 长时间没有数据的来源，清除去重记录
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 可靠传输的发送目标
+#[derive(Copy, Clone, Debug)]
+pub enum ReliableTarget {
+    /// 指定路由
+    Route(RouteKey),
+    /// 发到服务端转发
+    Server(SocketAddr),
+}
+
+struct Segment {
+    buf: Vec<u8>,
+    target: ReliableTarget,
+    rto: Duration,
+    resend_time: Instant,
+    count: u8,
+}
+
+struct RecvWindow {
+    seqs: VecDeque<u32>,
+    last: Instant,
+}
+
+struct ReliableInner {
+    next_seq: u32,
+    send: FnvHashMap<(Ipv4Addr, u32), Segment>,
+    recv: FnvHashMap<Ipv4Addr, RecvWindow>,
+    last_clear: Instant,
+}
+
+/// 客户端之间控制数据的可靠传输，在原有通道上增加序号、确认和超时重传，
+/// 接收端只去重不排序，控制数据之间没有顺序要求
+pub struct Reliable {
+    inner: Mutex<ReliableInner>,
+}
+
+impl Reliable {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            inner: Mutex::new(ReliableInner {
+                // 随机初始序号，避免重启后和对端记录的序号重复
+                next_seq: rand::random(),
+                send: FnvHashMap::default(),
+                recv: FnvHashMap::default(),
+                last_clear: now,
+            }),
+        }
+    }
+    /// 封装成可靠传输包并记录，等待确认
+    pub fn wrap<B: AsRef<[u8]>>(
+        &self,
+        net_packet: &NetPacket<B>,
+        target: ReliableTarget,
+    ) -> io::Result<Vec<u8>> {
+        let packet = net_packet.buffer();
+        let mut buf = vec![0u8; HEAD_LEN + RELIABLE_HEAD_LEN + packet.len()];
+        buf[..HEAD_LEN].copy_from_slice(&packet[..HEAD_LEN]);
+        let mut guard = self.inner.lock();
+        let seq = guard.next_seq;
+        guard.next_seq = seq.wrapping_add(1);
+        {
+            let mut wrap_packet = NetPacket::new(&mut buf)?;
+            wrap_packet.set_encrypt_flag(false);
+            wrap_packet.set_extension_flag(false);
+            wrap_packet.set_gateway_flag(false);
+            wrap_packet.set_protocol(Protocol::Reliable);
+            wrap_packet.set_transport_protocol(reliable_packet::Protocol::Data.into());
+            let mut reliable_packet = ReliablePacket::new(wrap_packet.payload_mut())?;
+            reliable_packet.set_seq(seq);
+            reliable_packet.payload_mut().copy_from_slice(packet);
+        }
+        guard.send.insert(
+            (net_packet.destination(), seq),
+            Segment {
+                buf: buf.clone(),
+                target,
+                rto: INITIAL_RTO,
+                resend_time: Instant::now() + INITIAL_RTO,
+                count: 0,
+            },
+        );
+        Ok(buf)
+    }
+    /// 收到确认
+    pub fn ack(&self, source: Ipv4Addr, seq: u32) {
+        self.inner.lock().send.remove(&(source, seq));
+    }
+    /// 收到数据，返回是否是新的数据
+    pub fn recv(&self, source: Ipv4Addr, seq: u32) -> bool {
+        let now = Instant::now();
+        let mut guard = self.inner.lock();
+        if now.duration_since(guard.last_clear) > IDLE_TIMEOUT {
+            guard.last_clear = now;
+            guard
+                .recv
+                .retain(|_, window| now.duration_since(window.last) < IDLE_TIMEOUT);
+        }
+        let window = guard.recv.entry(source).or_insert_with(|| RecvWindow {
+            seqs: VecDeque::with_capacity(RECV_WINDOW),
+            last: now,
+        });
+        window.last = now;
+        if window.seqs.contains(&seq) {
+            return false;
+        }
+        if window.seqs.len() >= RECV_WINDOW {
+            window.seqs.pop_front();
+        }
+        window.seqs.push_back(seq);
+        true
+    }
+    /// 取出需要重传的包
+    pub fn take_resend(&self) -> Vec<(Vec<u8>, ReliableTarget)> {
+        let now = Instant::now();
+        let mut guard = self.inner.lock();
+        let mut list = Vec::new();
+        guard.send.retain(|(dest, seq), segment| {
+            if now < segment.resend_time {
+                return true;
+            }
+            if segment.count >= MAX_RESEND {
+                log::warn!("可靠传输重传失败 dest={},seq={}", dest, seq);
+                return false;
+            }
+            segment.count += 1;
+            segment.rto = (segment.rto * 2).min(MAX_RTO);
+            segment.resend_time = now + segment.rto;
+            list.push((segment.buf.clone(), segment.target));
+            true
+        });
+        list
+    }
+}
+
+/// 构建确认包，使用组网密码加密，避免第三方伪造确认让发送端停止重传
+pub fn ack_packet(
+    cipher: &Cipher,
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+    seq: u32,
+) -> anyhow::Result<NetPacket<Vec<u8>>> {
+    let len = HEAD_LEN + RELIABLE_HEAD_LEN + ENCRYPTION_RESERVED;
+    let mut net_packet = NetPacket::new_encrypt(vec![0u8; len])?;
+    net_packet.set_default_version();
+    net_packet.set_protocol(Protocol::Reliable);
+    net_packet.set_transport_protocol(reliable_packet::Protocol::Ack.into());
+    net_packet.first_set_ttl(MAX_TTL);
+    net_packet.set_source(src);
+    net_packet.set_destination(dest);
+    let mut reliable_packet = ReliablePacket::new(net_packet.payload_mut())?;
+    reliable_packet.set_seq(seq);
+    cipher.encrypt_ipv4(&mut net_packet)?;
+    Ok(net_packet)
+}
+
+#[test]
+fn test_reliable() {
+    let src = Ipv4Addr::new(10, 26, 0, 2);
+    let dest = Ipv4Addr::new(10, 26, 0, 3);
+    let reliable = Reliable::new();
+    let mut buf = vec![1u8; HEAD_LEN + 10];
+    let mut net_packet = NetPacket::new(&mut buf).unwrap();
+    net_packet.set_source(src);
+    net_packet.set_destination(dest);
+    let target = ReliableTarget::Server("127.0.0.1:29872".parse().unwrap());
+    let wrap = reliable.wrap(&net_packet, target).unwrap();
+    let wrap_packet = NetPacket::new(&wrap).unwrap();
+    assert_eq!(wrap_packet.protocol(), Protocol::Reliable);
+    let reliable_packet = ReliablePacket::new(wrap_packet.payload()).unwrap();
+    assert_eq!(reliable_packet.payload(), net_packet.buffer());
+    let seq = reliable_packet.seq();
+    // 接收端去重
+    let peer = Reliable::new();
+    assert!(peer.recv(src, seq));
+    assert!(!peer.recv(src, seq));
+    // 未超时不重传，确认后不再重传
+    assert!(reliable.take_resend().is_empty());
+    reliable.ack(dest, seq);
+    assert!(reliable.inner.lock().send.is_empty());
+    // 确认包需要用组网密码解密，明文确认被拒绝
+    let cipher =
+        Cipher::new_password(crate::cipher::CipherModel::Xor, Some("123".into()), None).unwrap();
+    let mut ack = ack_packet(&cipher, dest, src, seq).unwrap();
+    cipher.decrypt_ipv4(&mut ack).unwrap();
+    assert_eq!(ReliablePacket::new(ack.payload()).unwrap().seq(), seq);
+    let mut plain = ack_packet(&Cipher::None, dest, src, seq).unwrap();
+    assert!(cipher.decrypt_ipv4(&mut plain).is_err());
+}
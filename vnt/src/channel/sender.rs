@@ -6,14 +6,17 @@ use std::sync::Arc;
 
 use crossbeam_utils::atomic::AtomicCell;
 use parking_lot::Mutex;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 use crate::channel::context::ChannelContext;
 use crate::channel::notify::AcceptNotify;
+use crate::channel::pmtu::clamp_mss;
+use crate::channel::qos;
 use crate::cipher::Cipher;
 use crate::compression::Compressor;
 use crate::external_route::ExternalRoute;
 use crate::handle::{CurrentDeviceInfo, PeerDeviceInfo};
+use crate::peer_key::encrypt_to_peer;
 use crate::protocol;
 use crate::protocol::{ip_turn_packet, NetPacket};
 
@@ -116,9 +119,21 @@ impl IpPacketSender {
             }
         }
 
+        if !dest_ip.is_broadcast() && dest_ip != device_info.broadcast_ip {
+            // 按路径mtu钳制tcp mss
+            if let Some(mss) = self.context.route_table.path_mss(&dest_ip) {
+                clamp_mss(net_packet.payload_mut(), mss);
+            }
+        }
         let mut auxiliary = NetPacket::new(auxiliary_buf)?;
 
-        let mut net_packet = if self.compressor.compress(&net_packet, &mut auxiliary)? {
+        // 只压缩发给支持对应解压算法的对端的包
+        let compress = !dest_ip.is_broadcast()
+            && dest_ip != device_info.broadcast_ip
+            && self
+                .compressor
+                .is_supported(self.context.peer_features(&dest_ip));
+        let mut net_packet = if compress && self.compressor.compress(&net_packet, &mut auxiliary)? {
             auxiliary.set_default_version();
             auxiliary.set_protocol(protocol::Protocol::IpTurn);
             auxiliary.set_transport_protocol(ip_turn_packet::Protocol::Ipv4.into());
@@ -129,9 +144,9 @@ impl IpPacketSender {
         } else {
             net_packet
         };
-        self.client_cipher.encrypt_ipv4(&mut net_packet)?;
         if dest_ip.is_broadcast() || dest_ip == device_info.broadcast_ip {
             //走服务端广播
+            self.client_cipher.encrypt_ipv4(&mut net_packet)?;
             self.context
                 .send_default(&net_packet, device_info.connect_server)?;
             return Ok(());
@@ -141,6 +156,12 @@ impl IpPacketSender {
             //不是一个网段的直接忽略
             return Ok(());
         }
+        encrypt_to_peer(
+            &self.context,
+            &self.client_cipher,
+            &mut net_packet,
+            &dest_ip,
+        )?;
         self.context.send_ipv4_by_id(
             &net_packet,
             &dest_ip,
@@ -213,17 +234,36 @@ impl<T> AcceptSocketSender<T> {
         }
     }
 }
+/// tcp类连接的发送队列，分为优先和普通两个队列
+pub fn packet_channel(buffer: usize) -> (PacketSender, PacketReceiver) {
+    let (sender, receiver) = channel(buffer);
+    let (priority_sender, priority_receiver) = channel(buffer);
+    (
+        PacketSender {
+            sender,
+            priority_sender,
+        },
+        PacketReceiver {
+            receiver,
+            priority_receiver,
+        },
+    )
+}
+
 #[derive(Clone)]
 pub struct PacketSender {
     sender: Sender<Vec<u8>>,
+    priority_sender: Sender<Vec<u8>>,
 }
 
 impl PacketSender {
-    pub fn new(sender: Sender<Vec<u8>>) -> Self {
-        Self { sender }
-    }
     pub fn try_send(&self, buf: &[u8]) -> io::Result<()> {
-        match self.sender.try_send(buf.to_vec()) {
+        let sender = if qos::is_priority() {
+            &self.priority_sender
+        } else {
+            &self.sender
+        };
+        match sender.try_send(buf.to_vec()) {
             Ok(_) => Ok(()),
             Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => Err(io::Error::new(
                 io::ErrorKind::WouldBlock,
@@ -237,30 +277,70 @@ impl PacketSender {
     }
 }
 
+pub struct PacketReceiver {
+    receiver: Receiver<Vec<u8>>,
+    priority_receiver: Receiver<Vec<u8>>,
+}
+
+impl PacketReceiver {
+    /// 优先队列中有数据时先发送，大流量传输时小包不用在后面排队
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        tokio::select! {
+            biased;
+            Some(data) = self.priority_receiver.recv() => Some(data),
+            data = self.receiver.recv() => data,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ConnectUtil {
-    connect_tcp: Sender<(Vec<u8>, SocketAddr)>,
+    connect_tcp: Sender<(Vec<u8>, SocketAddr, bool)>,
     connect_ws: Sender<(Vec<u8>, String)>,
+    connect_quic: Sender<(Vec<u8>, SocketAddr, String)>,
+    connect_tls: Sender<(Vec<u8>, SocketAddr, String)>,
 }
 
 impl ConnectUtil {
     pub fn new(
-        connect_tcp: Sender<(Vec<u8>, SocketAddr)>,
+        connect_tcp: Sender<(Vec<u8>, SocketAddr, bool)>,
         connect_ws: Sender<(Vec<u8>, String)>,
+        connect_quic: Sender<(Vec<u8>, SocketAddr, String)>,
+        connect_tls: Sender<(Vec<u8>, SocketAddr, String)>,
     ) -> Self {
         Self {
             connect_tcp,
             connect_ws,
+            connect_quic,
+            connect_tls,
         }
     }
     pub fn try_connect_tcp(&self, buf: Vec<u8>, addr: SocketAddr) {
-        if self.connect_tcp.try_send((buf, addr)).is_err() {
+        if self.connect_tcp.try_send((buf, addr, false)).is_err() {
             log::warn!("try_connect_tcp failed {}", addr);
         }
     }
+    /// 连接服务端，配置了上游代理时会通过代理连接
+    pub fn try_connect_server_tcp(&self, buf: Vec<u8>, addr: SocketAddr) {
+        if self.connect_tcp.try_send((buf, addr, true)).is_err() {
+            log::warn!("try_connect_server_tcp failed {}", addr);
+        }
+    }
     pub fn try_connect_ws(&self, buf: Vec<u8>, addr: String) {
         if self.connect_ws.try_send((buf, addr)).is_err() {
             log::warn!("try_connect_ws failed");
         }
     }
+    /// server为配置的服务端地址，用于证书校验
+    pub fn try_connect_quic(&self, buf: Vec<u8>, addr: SocketAddr, server: String) {
+        if self.connect_quic.try_send((buf, addr, server)).is_err() {
+            log::warn!("try_connect_quic failed {}", addr);
+        }
+    }
+    /// server为配置的服务端地址，用于证书校验
+    pub fn try_connect_tls(&self, buf: Vec<u8>, addr: SocketAddr, server: String) {
+        if self.connect_tls.try_send((buf, addr, server)).is_err() {
+            log::warn!("try_connect_tls failed {}", addr);
+        }
+    }
 }
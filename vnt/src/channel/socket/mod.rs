@@ -12,6 +12,9 @@ mod unix;
 #[cfg(windows)]
 mod windows;
 
+mod proxy;
+pub use proxy::{OutboundProxy, ProxyProtocol};
+
 pub trait VntSocketTrait {
     fn set_ip_unicast_if(&self, _interface: &LocalInterface) -> anyhow::Result<()> {
         Ok(())
@@ -33,6 +36,23 @@ pub async fn connect_tcp(
     Ok(socket.connect(addr).await?)
 }
 
+/// 配置了代理时通过代理建立连接，代理的连接和握手各自有超时，直连时3秒超时
+pub async fn connect_tcp_proxy(
+    addr: SocketAddr,
+    proxy: Option<&OutboundProxy>,
+    default_interface: &LocalInterface,
+) -> anyhow::Result<tokio::net::TcpStream> {
+    match proxy {
+        Some(proxy) => proxy.connect(&addr.to_string(), default_interface).await,
+        None => tokio::time::timeout(
+            std::time::Duration::from_secs(3),
+            connect_tcp(addr, default_interface),
+        )
+        .await
+        .with_context(|| format!("{} connect timeout", addr))?,
+    }
+}
+
 pub fn create_tcp(
     v4: bool,
     default_interface: &LocalInterface,
@@ -92,6 +112,36 @@ pub fn bind_udp(
     bind_udp_ops(addr, true, default_interface).with_context(|| format!("{}", addr))
 }
 
+/// 本地网卡中和network/netmask重叠的地址，exclude中的地址(虚拟网卡自身)不算
+pub fn overlapping_interfaces(
+    network: Ipv4Addr,
+    netmask: Ipv4Addr,
+    exclude: &[Ipv4Addr],
+) -> anyhow::Result<Vec<(String, Ipv4Addr)>> {
+    let network = u32::from(network);
+    let netmask = u32::from(netmask);
+    let mut list = Vec::new();
+    for iface in NetworkInterface::show()? {
+        for addr in iface.addr {
+            if let IpAddr::V4(ip) = addr.ip() {
+                if ip.is_loopback() || exclude.contains(&ip) {
+                    continue;
+                }
+                let local_mask = match addr.netmask() {
+                    Some(IpAddr::V4(mask)) => u32::from(mask),
+                    _ => u32::MAX,
+                };
+                // 按较短的掩码比较，一个网段包含另一个也算重叠
+                let mask = netmask & local_mask;
+                if u32::from(ip) & mask == network & mask {
+                    list.push((iface.name.clone(), ip));
+                }
+            }
+        }
+    }
+    Ok(list)
+}
+
 pub fn get_interface(dest_ip: Ipv4Addr) -> anyhow::Result<LocalInterface> {
     let network_interfaces = NetworkInterface::show()?;
     for iface in network_interfaces {
@@ -22,6 +22,16 @@ impl VntSocketTrait for socket2::Socket {
         Ok(())
     }
 }
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+impl VntSocketTrait for socket2::Socket {
+    fn set_ip_unicast_if(&self, interface: &LocalInterface) -> anyhow::Result<()> {
+        // 没有按网卡绑定的选项，只能依赖绑定的本地地址选择出口
+        if interface.index != 0 || interface.name.is_some() {
+            log::warn!("bind device is not supported {:?}", interface);
+        }
+        Ok(())
+    }
+}
 
 pub fn get_best_interface(dest_ip: Ipv4Addr) -> anyhow::Result<LocalInterface> {
     match get_interface(dest_ip) {
@@ -33,3 +43,42 @@ pub fn get_best_interface(dest_ip: Ipv4Addr) -> anyhow::Result<LocalInterface> {
     // 应该再查路由表找到默认路由的
     Ok(LocalInterface::default())
 }
+
+/// 设置udp socket发出的包不允许分片，用于探测路径mtu，
+/// 关闭时恢复为系统默认的WANT模式
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn set_dont_fragment(udp: &std::net::UdpSocket, df: bool) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let (level, name, value) = if udp.local_addr()?.is_ipv6() {
+        let value = if df {
+            libc::IPV6_PMTUDISC_PROBE
+        } else {
+            libc::IPV6_PMTUDISC_WANT
+        };
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, value)
+    } else {
+        let value = if df {
+            libc::IP_PMTUDISC_PROBE
+        } else {
+            libc::IP_PMTUDISC_WANT
+        };
+        (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, value)
+    };
+    let result = unsafe {
+        libc::setsockopt(
+            udp.as_raw_fd(),
+            level,
+            name,
+            &value as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn set_dont_fragment(_udp: &std::net::UdpSocket, _df: bool) -> std::io::Result<()> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
@@ -5,7 +5,8 @@ use std::os::windows::io::AsRawSocket;
 use windows_sys::core::PCSTR;
 use windows_sys::Win32::NetworkManagement::IpHelper::GetBestInterfaceEx;
 use windows_sys::Win32::Networking::WinSock::{
-    htonl, setsockopt, AF_INET, IPPROTO_IP, IP_UNICAST_IF, SOCKADDR, SOCKADDR_IN, SOCKET_ERROR,
+    htonl, setsockopt, AF_INET, IPPROTO_IP, IPPROTO_IPV6, IPV6_DONTFRAG, IP_DONTFRAGMENT,
+    IP_UNICAST_IF, SOCKADDR, SOCKADDR_IN, SOCKET_ERROR,
 };
 
 use crate::channel::socket::{LocalInterface, VntSocketTrait};
@@ -38,6 +39,29 @@ impl VntSocketTrait for socket2::Socket {
     }
 }
 
+/// 设置udp socket发出的包不允许分片，用于探测路径mtu
+pub fn set_dont_fragment(udp: &std::net::UdpSocket, df: bool) -> std::io::Result<()> {
+    let (level, name) = if udp.local_addr()?.is_ipv6() {
+        (IPPROTO_IPV6, IPV6_DONTFRAG)
+    } else {
+        (IPPROTO_IP, IP_DONTFRAGMENT)
+    };
+    let value: u32 = if df { 1 } else { 0 };
+    let result = unsafe {
+        setsockopt(
+            udp.as_raw_socket() as usize,
+            level,
+            name,
+            &value as *const _ as PCSTR,
+            mem::size_of_val(&value) as i32,
+        )
+    };
+    if result == SOCKET_ERROR {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
 pub fn get_best_interface(dest_ip: Ipv4Addr) -> anyhow::Result<LocalInterface> {
     // 获取最佳接口
     let index = unsafe {
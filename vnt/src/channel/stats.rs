@@ -0,0 +1,391 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use packet::ip::ipv4::packet::IpV4Packet;
+use packet::ip::ipv4::protocol::Protocol;
+use parking_lot::Mutex;
+
+// 心跳统计窗口，超过后计数减半，让丢包率能跟上链路变化
+const STAT_WINDOW: u32 = 20;
+// 最多统计的流数量，满了以后先清理空闲的流
+const MAX_FLOWS: usize = 1024;
+const FLOW_IDLE: Duration = Duration::from_secs(60);
+
+/// 和一个对端之间的收发统计。
+/// 发送按实际发出的包统计(包含冗余包和合并后的包)，接收按收到的网络数据统计
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PeerCounter {
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    // 直连发送的包数，其余的经过服务器或其他客户端中继
+    pub tx_direct: u64,
+    pub tx_relay: u64,
+    // 限速、没有可用路由等原因丢弃的包数
+    pub tx_drops: u64,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_direct: u64,
+    pub rx_relay: u64,
+    pub rx_drops: u64,
+    ping_sent: u32,
+    ping_recv: u32,
+    // 最近一次心跳是否还未收到回应
+    wait: bool,
+}
+
+impl PeerCounter {
+    /// 心跳丢包率(百分比)，未回应的最后一个心跳不计入
+    pub fn loss(&self) -> u32 {
+        let sent = if self.wait {
+            self.ping_sent - 1
+        } else {
+            self.ping_sent
+        };
+        if sent == 0 {
+            return 0;
+        }
+        (sent.saturating_sub(self.ping_recv) * 100 / sent).min(100)
+    }
+}
+
+/// 一条流，按内层ipv4包的协议和端口区分，没有端口的协议端口为0
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct FlowKey {
+    pub peer: Ipv4Addr,
+    pub protocol: u8,
+    pub local: SocketAddrV4,
+    pub remote: SocketAddrV4,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FlowCounter {
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub last_active: Instant,
+}
+
+/// 按对端和按流的统计，关闭流量统计时不启用
+pub struct PeerStats {
+    peers: Mutex<FnvHashMap<Ipv4Addr, PeerCounter>>,
+    flows: Mutex<FnvHashMap<FlowKey, FlowCounter>>,
+}
+
+impl PeerStats {
+    pub fn new() -> Self {
+        Self {
+            peers: Mutex::new(FnvHashMap::default()),
+            flows: Mutex::new(FnvHashMap::default()),
+        }
+    }
+    pub fn send(&self, peer: Ipv4Addr, len: usize, direct: bool) {
+        let mut guard = self.peers.lock();
+        let counter = guard.entry(peer).or_default();
+        counter.tx_bytes += len as u64;
+        counter.tx_packets += 1;
+        if direct {
+            counter.tx_direct += 1;
+        } else {
+            counter.tx_relay += 1;
+        }
+    }
+    pub fn send_drop(&self, peer: Ipv4Addr) {
+        self.peers.lock().entry(peer).or_default().tx_drops += 1;
+    }
+    pub fn recv(&self, peer: Ipv4Addr, len: usize, direct: bool) {
+        let mut guard = self.peers.lock();
+        let counter = guard.entry(peer).or_default();
+        counter.rx_bytes += len as u64;
+        counter.rx_packets += 1;
+        if direct {
+            counter.rx_direct += 1;
+        } else {
+            counter.rx_relay += 1;
+        }
+    }
+    pub fn recv_drop(&self, peer: Ipv4Addr) {
+        self.peers.lock().entry(peer).or_default().rx_drops += 1;
+    }
+    /// 向对端发出了心跳，每轮心跳只调用一次
+    pub fn ping(&self, peer: Ipv4Addr) {
+        let mut guard = self.peers.lock();
+        let counter = guard.entry(peer).or_default();
+        if counter.ping_sent >= STAT_WINDOW {
+            counter.ping_sent /= 2;
+            counter.ping_recv /= 2;
+        }
+        counter.ping_sent += 1;
+        counter.wait = true;
+    }
+    /// 收到了对端的心跳回应，同一个心跳从多条路径回应时只计一次
+    pub fn pong(&self, peer: Ipv4Addr) {
+        if let Some(counter) = self.peers.lock().get_mut(&peer) {
+            if counter.wait {
+                counter.ping_recv += 1;
+                counter.wait = false;
+            }
+        }
+    }
+    /// 发给对端的ipv4包
+    pub fn flow_out<B: AsRef<[u8]>>(&self, peer: Ipv4Addr, ipv4: &IpV4Packet<B>) {
+        let (protocol, src_port, dest_port) = ports(ipv4);
+        let key = FlowKey {
+            peer,
+            protocol,
+            local: SocketAddrV4::new(ipv4.source_ip(), src_port),
+            remote: SocketAddrV4::new(ipv4.destination_ip(), dest_port),
+        };
+        self.flow(key, ipv4.buffer.as_ref().len(), true);
+    }
+    /// 对端发来的ipv4包
+    pub fn flow_in<B: AsRef<[u8]>>(&self, peer: Ipv4Addr, ipv4: &IpV4Packet<B>) {
+        let (protocol, src_port, dest_port) = ports(ipv4);
+        let key = FlowKey {
+            peer,
+            protocol,
+            local: SocketAddrV4::new(ipv4.destination_ip(), dest_port),
+            remote: SocketAddrV4::new(ipv4.source_ip(), src_port),
+        };
+        self.flow(key, ipv4.buffer.as_ref().len(), false);
+    }
+    fn flow(&self, key: FlowKey, len: usize, out: bool) {
+        let now = Instant::now();
+        let mut guard = self.flows.lock();
+        if guard.len() >= MAX_FLOWS && !guard.contains_key(&key) {
+            guard.retain(|_, v| now.duration_since(v.last_active) < FLOW_IDLE);
+            if guard.len() >= MAX_FLOWS {
+                return;
+            }
+        }
+        let counter = guard.entry(key).or_insert(FlowCounter {
+            tx_bytes: 0,
+            tx_packets: 0,
+            rx_bytes: 0,
+            rx_packets: 0,
+            last_active: now,
+        });
+        if out {
+            counter.tx_bytes += len as u64;
+            counter.tx_packets += 1;
+        } else {
+            counter.rx_bytes += len as u64;
+            counter.rx_packets += 1;
+        }
+        counter.last_active = now;
+    }
+    pub fn peers(&self) -> Vec<(Ipv4Addr, PeerCounter)> {
+        self.peers.lock().iter().map(|(k, v)| (*k, *v)).collect()
+    }
+    pub fn flows(&self) -> Vec<(FlowKey, FlowCounter)> {
+        self.flows.lock().iter().map(|(k, v)| (*k, *v)).collect()
+    }
+}
+
+/// 数据被丢弃的原因
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DropReason {
+    /// 解密失败，一般是密码不一致或者数据被篡改
+    DecryptFail,
+    /// 没有到目标的路由
+    UnknownDest,
+    /// 经过的中转次数超过了ttl
+    TtlExceeded,
+    /// acl拦截
+    AclDeny,
+    /// 发送队列满了
+    QueueFull,
+    /// 超过了本地网卡或者路径的mtu
+    FragmentTooBig,
+    /// 内层来源ip不在对端允许的范围内
+    SpoofedSource,
+    /// 防火墙拦截
+    FirewallDeny,
+}
+
+impl DropReason {
+    pub const ALL: [DropReason; 8] = [
+        DropReason::DecryptFail,
+        DropReason::UnknownDest,
+        DropReason::TtlExceeded,
+        DropReason::AclDeny,
+        DropReason::QueueFull,
+        DropReason::FragmentTooBig,
+        DropReason::SpoofedSource,
+        DropReason::FirewallDeny,
+    ];
+    /// 用作指标的标签
+    pub fn name(&self) -> &'static str {
+        match self {
+            DropReason::DecryptFail => "decrypt_fail",
+            DropReason::UnknownDest => "unknown_dest",
+            DropReason::TtlExceeded => "ttl_exceeded",
+            DropReason::AclDeny => "acl_deny",
+            DropReason::QueueFull => "queue_full",
+            DropReason::FragmentTooBig => "fragment_too_big",
+            DropReason::SpoofedSource => "spoofed_source",
+            DropReason::FirewallDeny => "firewall_deny",
+        }
+    }
+    /// 发送失败对应的原因，其他错误不计入
+    pub fn of_send_error(e: &io::Error) -> Option<DropReason> {
+        #[cfg(windows)]
+        const EMSGSIZE: i32 = 10040;
+        #[cfg(not(windows))]
+        const EMSGSIZE: i32 = libc::EMSGSIZE;
+        if e.raw_os_error() == Some(EMSGSIZE) {
+            return Some(DropReason::FragmentTooBig);
+        }
+        match e.kind() {
+            io::ErrorKind::NotFound => Some(DropReason::UnknownDest),
+            io::ErrorKind::WouldBlock => Some(DropReason::QueueFull),
+            _ => None,
+        }
+    }
+}
+
+/// 全局的异常计数，用于监控
+#[derive(Default)]
+pub struct Counters {
+    handshake_failures: AtomicU64,
+    punch_attempts: AtomicU64,
+    tun_drops: AtomicU64,
+    // 按DropReason的顺序
+    drops: [AtomicU64; DropReason::ALL.len()],
+}
+
+impl Counters {
+    /// 服务端拒绝了注册，如token、身份认证、ip冲突等
+    pub fn handshake_failure(&self) {
+        self.handshake_failures.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn punch_attempt(&self) {
+        self.punch_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+    /// 解密失败，一般是密码不一致或者数据被篡改
+    pub fn cipher_error(&self) {
+        self.packet_drop(DropReason::DecryptFail);
+    }
+    /// 数据路径上丢弃了一个包
+    pub fn packet_drop(&self, reason: DropReason) {
+        self.drops[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    /// 写入虚拟网卡失败
+    pub fn tun_drop(&self) {
+        self.tun_drops.fetch_add(1, Ordering::Relaxed);
+    }
+    /// 其他地方的计数由调用方填充
+    pub fn snapshot(&self) -> CounterSnapshot {
+        let mut drops = [0; DropReason::ALL.len()];
+        for (v, counter) in drops.iter_mut().zip(self.drops.iter()) {
+            *v = counter.load(Ordering::Relaxed);
+        }
+        CounterSnapshot {
+            handshake_failures: self.handshake_failures.load(Ordering::Relaxed),
+            punch_attempts: self.punch_attempts.load(Ordering::Relaxed),
+            cipher_errors: drops[DropReason::DecryptFail as usize],
+            tun_drops: self.tun_drops.load(Ordering::Relaxed),
+            drops,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CounterSnapshot {
+    pub handshake_failures: u64,
+    pub punch_attempts: u64,
+    // 从没有直连到建立直连的次数
+    pub direct_links: u64,
+    pub cipher_errors: u64,
+    pub tun_drops: u64,
+    // acl拦截的包数
+    pub acl_denied: u64,
+    // 按DropReason的顺序
+    pub drops: [u64; DropReason::ALL.len()],
+}
+
+impl CounterSnapshot {
+    /// 按原因丢弃的包数
+    pub fn drops(&self) -> Vec<(DropReason, u64)> {
+        DropReason::ALL
+            .iter()
+            .map(|reason| (*reason, self.drops[*reason as usize]))
+            .collect()
+    }
+}
+
+fn ports<B: AsRef<[u8]>>(ipv4: &IpV4Packet<B>) -> (u8, u16, u16) {
+    let protocol = ipv4.protocol();
+    let (src_port, dest_port) = match protocol {
+        Protocol::Tcp | Protocol::Udp => {
+            let payload = ipv4.payload();
+            if payload.len() < 4 {
+                (0, 0)
+            } else {
+                (
+                    u16::from_be_bytes([payload[0], payload[1]]),
+                    u16::from_be_bytes([payload[2], payload[3]]),
+                )
+            }
+        }
+        _ => (0, 0),
+    };
+    (protocol.into(), src_port, dest_port)
+}
+
+#[test]
+fn peer_stats_test() {
+    let stats = PeerStats::new();
+    let peer = Ipv4Addr::new(10, 26, 0, 2);
+    stats.send(peer, 100, true);
+    stats.send(peer, 100, false);
+    stats.recv(peer, 50, true);
+    stats.send_drop(peer);
+    for _ in 0..4 {
+        stats.ping(peer);
+        stats.pong(peer);
+        stats.pong(peer);
+    }
+    stats.ping(peer);
+    stats.ping(peer);
+    let (_, counter) = stats.peers()[0];
+    assert_eq!(counter.tx_bytes, 200);
+    assert_eq!((counter.tx_direct, counter.tx_relay), (1, 1));
+    assert_eq!((counter.rx_packets, counter.tx_drops), (1, 1));
+    // 6个心跳，最后一个还在等待，5个中丢了1个
+    assert_eq!(counter.loss(), 20);
+
+    let mut packet = [0u8; 28];
+    packet[0] = 0x45;
+    packet[3] = 28;
+    packet[9] = 17;
+    packet[12..16].copy_from_slice(&[10, 26, 0, 1]);
+    packet[16..20].copy_from_slice(&[10, 26, 0, 2]);
+    packet[20..22].copy_from_slice(&5000u16.to_be_bytes());
+    packet[22..24].copy_from_slice(&53u16.to_be_bytes());
+    stats.flow_out(peer, &IpV4Packet::new(&packet[..]).unwrap());
+    let flows = stats.flows();
+    assert_eq!(flows.len(), 1);
+    assert_eq!(flows[0].0.protocol, 17);
+    assert_eq!(flows[0].0.remote, "10.26.0.2:53".parse().unwrap());
+    assert_eq!(flows[0].1.tx_bytes, 28);
+}
+
+#[test]
+fn drop_reason_test() {
+    let counters = Counters::default();
+    counters.cipher_error();
+    counters.packet_drop(DropReason::QueueFull);
+    counters.packet_drop(DropReason::QueueFull);
+    let snapshot = counters.snapshot();
+    assert_eq!(snapshot.cipher_errors, 1);
+    let drops = snapshot.drops();
+    assert_eq!(drops[0], (DropReason::DecryptFail, 1));
+    assert_eq!(drops[4], (DropReason::QueueFull, 2));
+    let e = io::Error::from(io::ErrorKind::WouldBlock);
+    assert_eq!(DropReason::of_send_error(&e), Some(DropReason::QueueFull));
+}
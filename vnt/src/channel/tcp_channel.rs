@@ -1,22 +1,20 @@
 use anyhow::{anyhow, Context};
 use std::net::SocketAddr;
 use std::thread;
-use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::net::tcp::OwnedReadHalf;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::mpsc::Receiver;
 
 use crate::channel::context::ChannelContext;
 use crate::channel::handler::RecvChannelHandler;
-use crate::channel::sender::PacketSender;
+use crate::channel::sender::packet_channel;
 use crate::channel::{ConnectProtocol, RouteKey, BUFFER_SIZE, TCP_MAX_PACKET_SIZE};
 use crate::util::StopManager;
 
 /// 监听tcp端口，等待客户端连接
 pub fn tcp_listen<H>(
     tcp_server: std::net::TcpListener,
-    receiver: Receiver<(Vec<u8>, SocketAddr)>,
+    receiver: Receiver<(Vec<u8>, SocketAddr, bool)>,
     recv_handler: H,
     context: ChannelContext,
     stop_manager: StopManager,
@@ -61,17 +59,17 @@ where
 }
 
 async fn connect_tcp_handle<H>(
-    mut receiver: Receiver<(Vec<u8>, SocketAddr)>,
+    mut receiver: Receiver<(Vec<u8>, SocketAddr, bool)>,
     recv_handler: H,
     context: ChannelContext,
 ) where
     H: RecvChannelHandler,
 {
-    while let Some((data, addr)) = receiver.recv().await {
+    while let Some((data, addr, is_server)) = receiver.recv().await {
         let recv_handler = recv_handler.clone();
         let context = context.clone();
         tokio::spawn(async move {
-            if let Err(e) = connect_tcp0(data, addr, recv_handler, context).await {
+            if let Err(e) = connect_tcp0(data, addr, is_server, recv_handler, context).await {
                 log::warn!("发送失败,链接终止:{:?},{:?}", addr, e);
             }
         });
@@ -81,17 +79,21 @@ async fn connect_tcp_handle<H>(
 async fn connect_tcp0<H>(
     data: Vec<u8>,
     addr: SocketAddr,
+    is_server: bool,
     recv_handler: H,
     context: ChannelContext,
 ) -> anyhow::Result<()>
 where
     H: RecvChannelHandler,
 {
-    let mut stream = tokio::time::timeout(
-        Duration::from_secs(3),
-        crate::channel::socket::connect_tcp(addr, context.default_interface()),
-    )
-    .await??;
+    // 只有连接服务端时使用上游代理
+    let proxy = if is_server {
+        context.outbound_proxy()
+    } else {
+        None
+    };
+    let mut stream =
+        crate::channel::socket::connect_tcp_proxy(addr, proxy, context.default_interface()).await?;
     tcp_write(&mut stream, &data).await?;
 
     tcp_stream_handle(stream, addr, recv_handler, context).await;
@@ -124,11 +126,8 @@ pub async fn tcp_stream_handle<H>(
 {
     let _ = stream.set_nodelay(true);
     let (r, mut w) = stream.into_split();
-    let (sender, mut receiver) = channel::<Vec<u8>>(100);
-    context
-        .packet_map
-        .write()
-        .insert(addr, PacketSender::new(sender));
+    let (sender, mut receiver) = packet_channel(100);
+    context.packet_map.write().insert(addr, sender);
     tokio::spawn(async move {
         while let Some(data) = receiver.recv().await {
             if let Err(e) = tcp_write(&mut w, &data).await {
@@ -139,14 +138,15 @@ pub async fn tcp_stream_handle<H>(
         let _ = w.shutdown().await;
     });
     tokio::spawn(async move {
-        if let Err(e) = tcp_read(r, addr, &context, recv_handler).await {
+        let route_key = RouteKey::new(ConnectProtocol::TCP, 0, addr);
+        if let Err(e) = tcp_read(r, route_key, &context, recv_handler).await {
             log::warn!("tcp_read {:?}", e)
         }
         context.packet_map.write().remove(&addr);
     });
 }
 
-async fn tcp_write<W: AsyncWrite + Unpin>(w: &mut W, buf: &[u8]) -> anyhow::Result<()> {
+pub(crate) async fn tcp_write<W: AsyncWrite + Unpin>(w: &mut W, buf: &[u8]) -> anyhow::Result<()> {
     let len = buf.len();
     if len > TCP_MAX_PACKET_SIZE {
         return Err(anyhow!("超过了tcp的最大长度传输"));
@@ -157,15 +157,17 @@ async fn tcp_write<W: AsyncWrite + Unpin>(w: &mut W, buf: &[u8]) -> anyhow::Resu
     Ok(())
 }
 
-async fn tcp_read<H>(
-    mut read: OwnedReadHalf,
-    addr: SocketAddr,
+pub(crate) async fn tcp_read<R, H>(
+    mut read: R,
+    route_key: RouteKey,
     context: &ChannelContext,
     recv_handler: H,
 ) -> anyhow::Result<()>
 where
+    R: AsyncRead + Unpin,
     H: RecvChannelHandler,
 {
+    let addr = route_key.addr;
     let mut head = [0; 4];
     let mut buf = [0; BUFFER_SIZE];
     let mut extend = [0; BUFFER_SIZE];
@@ -179,11 +181,6 @@ where
             return Err(anyhow!("tcp数据长度无效 {}", addr));
         }
         read.read_exact(&mut buf[..len]).await?;
-        recv_handler.handle(
-            &mut buf[..len],
-            &mut extend,
-            RouteKey::new(ConnectProtocol::TCP, 0, addr),
-            context,
-        );
+        recv_handler.handle(&mut buf[..len], &mut extend, route_key, context);
     }
 }
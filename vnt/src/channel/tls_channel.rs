@@ -0,0 +1,219 @@
+use anyhow::Context;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::Receiver;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{DigitallySignedStruct, SignatureScheme};
+use tokio_rustls::TlsConnector;
+
+use crate::channel::context::ChannelContext;
+use crate::channel::handler::RecvChannelHandler;
+use crate::channel::sender::packet_channel;
+use crate::channel::tcp_channel::{tcp_read, tcp_write};
+use crate::channel::{server_name, ConnectProtocol, RouteKey};
+use crate::util::StopManager;
+
+/// tcp+tls协议，数据格式和tcp通道一致
+/// 默认使用系统根证书校验服务端证书，指定了证书文件时只信任该证书(证书固定)
+/// 暂时只允许用tls连服务端
+pub fn tls_connect_accept<H>(
+    receiver: Receiver<(Vec<u8>, SocketAddr, String)>,
+    recv_handler: H,
+    context: ChannelContext,
+    stop_manager: StopManager,
+    server_cert: Option<String>,
+) -> anyhow::Result<()>
+where
+    H: RecvChannelHandler,
+{
+    let connector = TlsConnector::from(Arc::new(client_config(server_cert)?));
+    let (stop_sender, stop_receiver) = tokio::sync::oneshot::channel::<()>();
+    let worker = stop_manager.add_listener("tlsChannel".into(), move || {
+        let _ = stop_sender.send(());
+    })?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .context("tls tokio runtime build failed")?;
+    thread::Builder::new()
+        .name("tlsChannel".into())
+        .spawn(move || {
+            runtime.spawn(async move {
+                connect_tls_handle(receiver, connector, recv_handler, context).await
+            });
+            runtime.block_on(async {
+                let _ = stop_receiver.await;
+            });
+            runtime.shutdown_background();
+            worker.stop_all();
+        })
+        .context("tls thread build failed")?;
+    Ok(())
+}
+
+fn client_config(server_cert: Option<String>) -> anyhow::Result<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    if let Some(path) = server_cert {
+        let cert = CertificateDer::from_pem_file(&path)
+            .map_err(|e| anyhow::anyhow!("{:?}", e))
+            .with_context(|| format!("server cert {:?} error", path))?;
+        let verifier = PinnedCertVerifier { cert, provider };
+        return Ok(builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth());
+    }
+    let mut root_store = rustls::RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for e in native.errors {
+        log::warn!("load native certs {:?}", e);
+    }
+    let (added, _) = root_store.add_parsable_certificates(native.certs);
+    if added == 0 {
+        // 读取不到系统证书时使用内置的根证书
+        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    }
+    Ok(builder
+        .with_root_certificates(root_store)
+        .with_no_client_auth())
+}
+
+/// 服务端证书必须和指定的证书完全一致，不校验域名和有效期，适用于自签名证书
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    cert: CertificateDer<'static>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.cert.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+async fn connect_tls_handle<H>(
+    mut receiver: Receiver<(Vec<u8>, SocketAddr, String)>,
+    connector: TlsConnector,
+    recv_handler: H,
+    context: ChannelContext,
+) where
+    H: RecvChannelHandler,
+{
+    while let Some((data, addr, server)) = receiver.recv().await {
+        let connector = connector.clone();
+        let recv_handler = recv_handler.clone();
+        let context = context.clone();
+        tokio::spawn(async move {
+            if let Err(e) = connect_tls(data, addr, server, connector, recv_handler, context).await
+            {
+                log::warn!("发送失败,tls链接终止:{:?},{:?}", addr, e);
+            }
+        });
+    }
+}
+
+async fn connect_tls<H>(
+    data: Vec<u8>,
+    addr: SocketAddr,
+    server: String,
+    connector: TlsConnector,
+    recv_handler: H,
+    context: ChannelContext,
+) -> anyhow::Result<()>
+where
+    H: RecvChannelHandler,
+{
+    let stream = crate::channel::socket::connect_tcp_proxy(
+        addr,
+        context.outbound_proxy(),
+        context.default_interface(),
+    )
+    .await?;
+    let _ = stream.set_nodelay(true);
+    let server_name = ServerName::try_from(server_name(&server))
+        .with_context(|| format!("server name error {:?}", server))?;
+    let mut stream = tokio::time::timeout(
+        Duration::from_secs(3),
+        connector.connect(server_name, stream),
+    )
+    .await??;
+    tcp_write(&mut stream, &data).await?;
+
+    let (r, mut w) = tokio::io::split(stream);
+    let (sender, mut receiver) = packet_channel(100);
+    context.packet_map.write().insert(addr, sender);
+    tokio::spawn(async move {
+        while let Some(data) = receiver.recv().await {
+            if let Err(e) = tcp_write(&mut w, &data).await {
+                log::info!("发送失败,tls链接终止:{:?},{:?}", addr, e);
+                break;
+            }
+        }
+        let _ = w.shutdown().await;
+    });
+    let route_key = RouteKey::new(ConnectProtocol::TLS, 0, addr);
+    if let Err(e) = tcp_read(r, route_key, &context, recv_handler).await {
+        log::warn!("tls_read {:?}", e)
+    }
+    context.packet_map.write().remove(&addr);
+    Ok(())
+}
@@ -121,8 +121,15 @@ where
                         loop {
                             match udp_socket.recv_from(&mut buf) {
                                 Ok((len, addr)) => {
+                                    let range = match context.obfs().decode(&mut buf[..len]) {
+                                        Ok(range) => range,
+                                        Err(e) => {
+                                            log::debug!("{:?},addr={}", e, addr);
+                                            continue;
+                                        }
+                                    };
                                     recv_handler.handle(
-                                        &mut buf[..len],
+                                        &mut buf[range],
                                         &mut extend,
                                         RouteKey::new(ConnectProtocol::UDP, index + main_len, addr),
                                         &context,
@@ -282,8 +289,15 @@ where
             loop {
                 match udp.recv_from(&mut buf) {
                     Ok((len, addr)) => {
+                        let range = match context.obfs().decode(&mut buf[..len]) {
+                            Ok(range) => range,
+                            Err(e) => {
+                                log::debug!("{:?},addr={}", e, addr);
+                                continue;
+                            }
+                        };
                         recv_handler.handle(
-                            &mut buf[..len],
+                            &mut buf[range],
                             &mut extend,
                             RouteKey::new(ConnectProtocol::UDP, index, addr),
                             &context,
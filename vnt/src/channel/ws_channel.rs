@@ -7,14 +7,16 @@ use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::thread;
 use std::time::Duration;
 use tokio::net::TcpStream;
-use tokio::sync::mpsc::{channel, Receiver};
-use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio::sync::mpsc::Receiver;
+use tokio_tungstenite::tungstenite::error::UrlError;
+use tokio_tungstenite::tungstenite::handshake::client::Response;
+use tokio_tungstenite::tungstenite::http::{StatusCode, Uri};
 use tokio_tungstenite::tungstenite::{Error, Message};
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::{client_async_tls, connect_async, MaybeTlsStream, WebSocketStream};
 
 use crate::channel::context::ChannelContext;
 use crate::channel::handler::RecvChannelHandler;
-use crate::channel::sender::PacketSender;
+use crate::channel::sender::packet_channel;
 use crate::util::StopManager;
 
 /// ws协议，
@@ -68,6 +70,34 @@ async fn connect_ws_handle<H>(
         });
     }
 }
+
+/// 配置了上游代理时先通过代理建立tcp连接，再进行ws握手
+async fn ws_connect(
+    url: String,
+    context: &ChannelContext,
+) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, Response), Error> {
+    let proxy = match context.outbound_proxy() {
+        None => return connect_async(url).await,
+        Some(proxy) => proxy,
+    };
+    let uri: Uri = url.parse().map_err(|e| Error::HttpFormat(From::from(e)))?;
+    let host = uri.host().ok_or(Error::Url(UrlError::NoHostName))?;
+    let port = uri.port_u16().unwrap_or_else(|| {
+        if uri.scheme_str() == Some("wss") {
+            443
+        } else {
+            80
+        }
+    });
+    // ipv6的host自带[]
+    let target = format!("{}:{}", host, port);
+    let stream = proxy
+        .connect(&target, context.default_interface())
+        .await
+        .map_err(|e| Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+    client_async_tls(url, stream).await
+}
+
 const WS_ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
 
 async fn connect_ws<H>(
@@ -86,7 +116,7 @@ where
         if count > 3 {
             Err(anyhow::anyhow!("发生多次重定向，链接终止"))?
         }
-        match tokio::time::timeout(Duration::from_secs(3), connect_async(url)).await? {
+        match tokio::time::timeout(Duration::from_secs(3), ws_connect(url, &context)).await? {
             Ok(rs) => break rs,
             Err(e) => {
                 if let Error::Http(res) = &e {
@@ -113,11 +143,8 @@ where
     log::info!("ws协议握手 {:?}", response);
     ws.send(Message::Binary(data)).await?;
     let (mut ws_write, ws_read) = ws.split();
-    let (sender, mut receiver) = channel::<Vec<u8>>(100);
-    context
-        .packet_map
-        .write()
-        .insert(WS_ADDR, PacketSender::new(sender));
+    let (sender, mut receiver) = packet_channel(100);
+    context.packet_map.write().insert(WS_ADDR, sender);
     tokio::spawn(async move {
         while let Some(data) = receiver.recv().await {
             if let Err(e) = ws_write.send(Message::Binary(data)).await {
@@ -142,7 +169,12 @@ where
     H: RecvChannelHandler,
 {
     let mut extend = [0; BUFFER_SIZE];
-    let route_key = RouteKey::new(ConnectProtocol::WS, 0, WS_ADDR);
+    let protocol = if context.main_protocol().is_wss() {
+        ConnectProtocol::WSS
+    } else {
+        ConnectProtocol::WS
+    };
+    let route_key = RouteKey::new(protocol, 0, WS_ADDR);
     while let Some(msg) = ws_read.next().await {
         let msg = msg.context("Error during WebSocket ")?;
         match msg {
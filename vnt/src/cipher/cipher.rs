@@ -62,6 +62,21 @@ impl Display for CipherModel {
     }
 }
 
+impl CipherModel {
+    /// 使用aes的加密模式，没有硬件加速时性能较差
+    pub fn is_aes(&self) -> bool {
+        match self {
+            #[cfg(any(feature = "aes_gcm", feature = "server_encrypt"))]
+            CipherModel::AesGcm => true,
+            #[cfg(feature = "aes_cbc")]
+            CipherModel::AesCbc => true,
+            #[cfg(feature = "aes_ecb")]
+            CipherModel::AesEcb => true,
+            _ => false,
+        }
+    }
+}
+
 impl FromStr for CipherModel {
     type Err = String;
 
@@ -99,6 +114,47 @@ impl FromStr for CipherModel {
     }
 }
 
+/// 客户端之间的数据使用组网密码加密的范围，控制数据和广播始终加密
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EncryptScope {
+    // 全部加密
+    All,
+    // 只加密经过服务端或其他客户端中转的数据，直连的数据不加密
+    Relay,
+    // 只加密直连的数据，中转的数据不加密
+    Direct,
+}
+
+impl EncryptScope {
+    /// direct为true时是直连的路径
+    pub fn encrypt(&self, direct: bool) -> bool {
+        match self {
+            EncryptScope::All => true,
+            EncryptScope::Relay => !direct,
+            EncryptScope::Direct => direct,
+        }
+    }
+}
+
+impl FromStr for EncryptScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim() {
+            "all" => Ok(EncryptScope::All),
+            "relay" => Ok(EncryptScope::Relay),
+            "direct" => Ok(EncryptScope::Direct),
+            _ => Err(format!("not match '{}', enum: all/relay/direct", s)),
+        }
+    }
+}
+
+impl Default for EncryptScope {
+    fn default() -> Self {
+        EncryptScope::All
+    }
+}
+
 #[derive(Clone)]
 pub enum Cipher {
     #[cfg(any(feature = "aes_gcm", feature = "server_encrypt"))]
@@ -0,0 +1,15 @@
+/// cpu是否支持aes硬件加速，不支持时aes的速度远低于chacha20_poly1305
+pub fn aes_hardware() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        return std::arch::is_x86_feature_detected!("aes")
+            && std::arch::is_x86_feature_detected!("pclmulqdq");
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        return std::arch::is_aarch64_feature_detected!("aes")
+            && std::arch::is_aarch64_feature_detected!("pmull");
+    }
+    #[allow(unreachable_code)]
+    false
+}
@@ -0,0 +1,101 @@
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::protocol::control_packet::FEATURE_INTEGRITY;
+use crate::protocol::NetPacket;
+
+// 附加在数据末尾的校验码长度，HMAC-SHA256截断到128位
+pub const INTEGRITY_TAG_LEN: usize = 16;
+
+/// 不加密时客户端之间数据的完整性校验，使用组网token派生的密钥对头部和数据计算HMAC，
+/// 防止中转的服务端或客户端篡改数据，数据本身仍是明文
+#[derive(Clone)]
+pub struct Integrity {
+    key: [u8; 32],
+    // 兼容没有开启校验的旧版本客户端
+    compat: bool,
+}
+
+impl Integrity {
+    pub fn new(token: &str, compat: bool) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"vnt-integrity");
+        hasher.update(token.as_bytes());
+        Self {
+            key: hasher.finalize().into(),
+            compat,
+        }
+    }
+    /// 对端是否必须使用校验码，默认所有对端都必须使用，能力标记没有校验，不能用来决定是否校验。
+    /// 兼容模式下只要求通告了支持的对端
+    pub fn required(&self, peer_features: u16) -> bool {
+        !self.compat || peer_features & FEATURE_INTEGRITY != 0
+    }
+    fn mac<B: AsRef<[u8]>>(&self, net_packet: &NetPacket<B>, payload_len: usize) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("hmac key");
+        // ttl中转时会变化，不参与计算
+        mac.update(&net_packet.head_tag());
+        mac.update(&net_packet.payload()[..payload_len]);
+        mac
+    }
+    /// 在数据末尾附加校验码并设置加密标记，加密标记让未开启校验的旧版本客户端丢弃数据
+    pub fn sign<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        net_packet: &mut NetPacket<B>,
+    ) -> anyhow::Result<()> {
+        if net_packet.reserve() < INTEGRITY_TAG_LEN {
+            return Err(anyhow!("too short"));
+        }
+        let payload_len = net_packet.payload().len();
+        let tag = self.mac(net_packet, payload_len).finalize().into_bytes();
+        net_packet.set_data_len(net_packet.data_len() + INTEGRITY_TAG_LEN)?;
+        net_packet.payload_mut()[payload_len..].copy_from_slice(&tag[..INTEGRITY_TAG_LEN]);
+        net_packet.set_encrypt_flag(true);
+        Ok(())
+    }
+    /// 校验并去掉末尾的校验码
+    pub fn verify<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        net_packet: &mut NetPacket<B>,
+    ) -> anyhow::Result<()> {
+        if !net_packet.is_encrypt() {
+            return Err(anyhow!("not signed"));
+        }
+        let payload_len = match net_packet.payload().len().checked_sub(INTEGRITY_TAG_LEN) {
+            Some(len) => len,
+            None => return Err(anyhow!("data err")),
+        };
+        let mac = self.mac(net_packet, payload_len);
+        if mac
+            .verify_truncated_left(&net_packet.payload()[payload_len..])
+            .is_err()
+        {
+            return Err(anyhow!("integrity err"));
+        }
+        net_packet.set_payload_len(payload_len)?;
+        net_packet.set_encrypt_flag(false);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_integrity() {
+    let integrity = Integrity::new("token", false);
+    assert!(integrity.required(0));
+    assert!(!Integrity::new("token", true).required(0));
+    assert!(Integrity::new("token", true).required(FEATURE_INTEGRITY));
+    let mut p =
+        NetPacket::new_encrypt([1; 13 + crate::protocol::body::ENCRYPTION_RESERVED]).unwrap();
+    let src = p.buffer().to_vec();
+    integrity.sign(&mut p).unwrap();
+    assert_eq!(p.data_len(), src.len() + INTEGRITY_TAG_LEN);
+    let mut tampered = NetPacket::new(p.buffer().to_vec()).unwrap();
+    integrity.verify(&mut p).unwrap();
+    assert_eq!(p.buffer(), &src);
+    // 篡改数据或使用其他token时校验失败
+    tampered.payload_mut()[0] ^= 1;
+    assert!(integrity.verify(&mut tampered).is_err());
+    integrity.sign(&mut p).unwrap();
+    assert!(Integrity::new("other", false).verify(&mut p).is_err());
+}
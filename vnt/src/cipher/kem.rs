@@ -0,0 +1,132 @@
+use anyhow::anyhow;
+use ml_kem::kem::{Decapsulate, Encapsulate};
+use ml_kem::{Ciphertext, Encoded, EncodedSizeUser, KemCore, MlKem768};
+use sha2::Digest;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+type DecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
+type EncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+
+pub const KEM_PUBLIC_KEY_LEN: usize = 1184;
+pub const KEM_CIPHERTEXT_LEN: usize = 1088;
+// x25519公钥(32) | ML-KEM-768公钥
+pub const HYBRID_PUBLIC_KEY_LEN: usize = 32 + KEM_PUBLIC_KEY_LEN;
+// x25519临时公钥(32) | ML-KEM-768密文
+pub const HYBRID_CIPHERTEXT_LEN: usize = 32 + KEM_CIPHERTEXT_LEN;
+
+/// ML-KEM-768密钥，每次握手临时生成
+pub struct KemKey {
+    key: DecapsulationKey,
+    public_key: Vec<u8>,
+}
+
+impl KemKey {
+    pub fn generate() -> Self {
+        let (key, public_key) = MlKem768::generate(&mut rand::thread_rng());
+        Self {
+            key,
+            public_key: public_key.as_bytes().to_vec(),
+        }
+    }
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+    pub fn decapsulate(&self, ciphertext: &[u8]) -> anyhow::Result<[u8; 32]> {
+        let ciphertext = Ciphertext::<MlKem768>::try_from(ciphertext)
+            .map_err(|_| anyhow!("ml-kem ciphertext len {}", ciphertext.len()))?;
+        let shared = self
+            .key
+            .decapsulate(&ciphertext)
+            .map_err(|e| anyhow!("ml-kem decapsulate {:?}", e))?;
+        Ok(shared.into())
+    }
+}
+
+/// 用对端的ML-KEM公钥封装一个共享密钥，返回密文和共享密钥
+pub fn kem_encapsulate(public_key: &[u8]) -> anyhow::Result<(Vec<u8>, [u8; 32])> {
+    let encoded = Encoded::<EncapsulationKey>::try_from(public_key)
+        .map_err(|_| anyhow!("ml-kem public key len {}", public_key.len()))?;
+    let (ciphertext, shared) = EncapsulationKey::from_bytes(&encoded)
+        .encapsulate(&mut rand::thread_rng())
+        .map_err(|e| anyhow!("ml-kem encapsulate {:?}", e))?;
+    Ok((ciphertext.to_vec(), shared.into()))
+}
+
+/// x25519和ML-KEM的混合密钥封装，两种算法都被破解才能得到共享密钥
+pub struct HybridKem {
+    x25519: StaticSecret,
+    kem: KemKey,
+    public_key: Vec<u8>,
+}
+
+impl HybridKem {
+    pub fn generate() -> Self {
+        let x25519 = StaticSecret::from(rand::random::<[u8; 32]>());
+        let kem = KemKey::generate();
+        let mut public_key = Vec::with_capacity(HYBRID_PUBLIC_KEY_LEN);
+        public_key.extend_from_slice(PublicKey::from(&x25519).as_bytes());
+        public_key.extend_from_slice(kem.public_key());
+        Self {
+            x25519,
+            kem,
+            public_key,
+        }
+    }
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+    pub fn decapsulate(&self, ciphertext: &[u8]) -> anyhow::Result<[u8; 32]> {
+        if ciphertext.len() != HYBRID_CIPHERTEXT_LEN {
+            return Err(anyhow!("hybrid ciphertext len {}", ciphertext.len()));
+        }
+        let remote: [u8; 32] = ciphertext[..32].try_into().unwrap();
+        let x25519 = self.x25519.diffie_hellman(&PublicKey::from(remote));
+        let kem = self.kem.decapsulate(&ciphertext[32..])?;
+        Ok(combine(
+            x25519.as_bytes(),
+            &kem,
+            ciphertext,
+            &self.public_key,
+        ))
+    }
+}
+
+/// 用对端的混合公钥封装一个共享密钥，返回密文和共享密钥
+pub fn hybrid_encapsulate(public_key: &[u8]) -> anyhow::Result<(Vec<u8>, [u8; 32])> {
+    if public_key.len() != HYBRID_PUBLIC_KEY_LEN {
+        return Err(anyhow!("hybrid public key len {}", public_key.len()));
+    }
+    let remote: [u8; 32] = public_key[..32].try_into().unwrap();
+    let ephemeral = StaticSecret::from(rand::random::<[u8; 32]>());
+    let x25519 = ephemeral.diffie_hellman(&PublicKey::from(remote));
+    let (kem_ciphertext, kem) = kem_encapsulate(&public_key[32..])?;
+    let mut ciphertext = Vec::with_capacity(HYBRID_CIPHERTEXT_LEN);
+    ciphertext.extend_from_slice(PublicKey::from(&ephemeral).as_bytes());
+    ciphertext.extend_from_slice(&kem_ciphertext);
+    let shared = combine(x25519.as_bytes(), &kem, &ciphertext, public_key);
+    Ok((ciphertext, shared))
+}
+
+// 两个共享密钥和双方公开的数据一起哈希，任何一个被替换都会得到不同的结果
+fn combine(x25519: &[u8], kem: &[u8], ciphertext: &[u8], public_key: &[u8]) -> [u8; 32] {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(b"vnt-hybrid-kem");
+    hasher.update(x25519);
+    hasher.update(kem);
+    hasher.update(ciphertext);
+    hasher.update(public_key);
+    hasher.finalize().into()
+}
+
+#[test]
+fn test_hybrid_kem() {
+    let key = HybridKem::generate();
+    assert_eq!(key.public_key().len(), HYBRID_PUBLIC_KEY_LEN);
+    let (ciphertext, shared) = hybrid_encapsulate(key.public_key()).unwrap();
+    assert_eq!(ciphertext.len(), HYBRID_CIPHERTEXT_LEN);
+    assert_eq!(key.decapsulate(&ciphertext).unwrap(), shared);
+    // 换一个公钥解出的共享密钥不同
+    let other = HybridKem::generate();
+    assert_ne!(other.decapsulate(&ciphertext).unwrap(), shared);
+    assert!(hybrid_encapsulate(&key.public_key()[1..]).is_err());
+}
@@ -4,6 +4,7 @@ mod finger;
 
 pub use cipher::Cipher;
 pub use cipher::CipherModel;
+pub use cipher::EncryptScope;
 #[cfg(cipher)]
 pub use finger::Finger;
 #[cfg(feature = "server_encrypt")]
@@ -19,6 +20,27 @@ mod chacha20;
 #[cfg(feature = "chacha20_poly1305")]
 mod chacha20_poly1305;
 
+#[cfg(any(feature = "wireguard", feature = "peer_key"))]
+pub mod noise;
+#[cfg(any(feature = "wireguard", feature = "peer_key"))]
+mod replay;
+#[cfg(any(feature = "wireguard", feature = "peer_key"))]
+pub use replay::ReplayFilter;
+
+#[cfg(feature = "pq")]
+mod kem;
+#[cfg(feature = "pq")]
+pub use kem::{hybrid_encapsulate, kem_encapsulate, HybridKem, KemKey};
+#[cfg(feature = "pq")]
+pub use kem::{
+    HYBRID_CIPHERTEXT_LEN, HYBRID_PUBLIC_KEY_LEN, KEM_CIPHERTEXT_LEN, KEM_PUBLIC_KEY_LEN,
+};
+
+#[cfg(feature = "integrity")]
+mod integrity;
+#[cfg(feature = "integrity")]
+pub use integrity::{Integrity, INTEGRITY_TAG_LEN};
+
 #[cfg(feature = "aes_ecb")]
 mod aes_ecb;
 
@@ -30,3 +52,6 @@ mod sm4_cbc;
 
 mod xor;
 pub use xor::simple_hash;
+
+mod hardware;
+pub use hardware::aes_hardware;
@@ -0,0 +1,86 @@
+use blake2::digest::consts::U16;
+use blake2::digest::Mac;
+use blake2::{Blake2s256, Blake2sMac, Digest};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// Noise协议框架用到的基础运算，wg互通和客户端之间的密钥协商共用
+
+pub(crate) fn hash(inputs: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Blake2s256::new();
+    for input in inputs {
+        Digest::update(&mut hasher, input);
+    }
+    hasher.finalize().into()
+}
+
+pub(crate) fn mac(key: &[u8], input: &[u8]) -> [u8; 16] {
+    let mut mac = <Blake2sMac<U16> as Mac>::new_from_slice(key).unwrap();
+    Mac::update(&mut mac, input);
+    mac.finalize().into_bytes().into()
+}
+
+fn hmac(key: &[u8; 32], inputs: &[&[u8]]) -> [u8; 32] {
+    let mut ipad = [0x36u8; 64];
+    let mut opad = [0x5cu8; 64];
+    for (i, v) in key.iter().enumerate() {
+        ipad[i] ^= v;
+        opad[i] ^= v;
+    }
+    let mut list = vec![&ipad[..]];
+    list.extend_from_slice(inputs);
+    let inner = hash(&list);
+    hash(&[&opad, &inner])
+}
+
+pub(crate) fn kdf<const N: usize>(key: &[u8; 32], input: &[u8]) -> [[u8; 32]; N] {
+    let t0 = hmac(key, &[input]);
+    let mut out = [[0u8; 32]; N];
+    for i in 0..N {
+        out[i] = if i == 0 {
+            hmac(&t0, &[&[1]])
+        } else {
+            hmac(&t0, &[&out[i - 1], &[i as u8 + 1]])
+        };
+    }
+    out
+}
+
+fn nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+pub(crate) fn seal(key: &[u8; 32], counter: u64, msg: &[u8], aad: &[u8]) -> Vec<u8> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .encrypt(Nonce::from_slice(&nonce(counter)), Payload { msg, aad })
+        .unwrap()
+}
+
+pub(crate) fn open(key: &[u8; 32], counter: u64, msg: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .decrypt(Nonce::from_slice(&nonce(counter)), Payload { msg, aad })
+        .ok()
+}
+
+pub(crate) fn dh(secret: &StaticSecret, public: &[u8; 32]) -> [u8; 32] {
+    secret.diffie_hellman(&PublicKey::from(*public)).to_bytes()
+}
+
+pub(crate) fn new_ephemeral() -> StaticSecret {
+    StaticSecret::from(rand::random::<[u8; 32]>())
+}
+
+pub fn public_key(private_key: &[u8; 32]) -> [u8; 32] {
+    PublicKey::from(&StaticSecret::from(*private_key)).to_bytes()
+}
+
+/// 生成一对x25519密钥，返回(私钥,公钥)
+pub fn generate_key() -> ([u8; 32], [u8; 32]) {
+    let private_key = new_ephemeral().to_bytes();
+    (private_key, public_key(&private_key))
+}
@@ -0,0 +1,55 @@
+// 防重放窗口，单位是包
+pub const REPLAY_WINDOW: u64 = 2048;
+
+/// 滑动窗口防重放，和WireGuard、IPsec一样按计数判断，需要在解密成功后检查
+#[derive(Default)]
+pub struct ReplayFilter {
+    last: u64,
+    bitmap: [u64; (REPLAY_WINDOW / 64) as usize],
+}
+
+impl ReplayFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn bit(counter: u64) -> (usize, u64) {
+        let index = counter % REPLAY_WINDOW;
+        ((index / 64) as usize, 1 << (index % 64))
+    }
+    /// 计数没有出现过时记录并返回true
+    pub fn check(&mut self, counter: u64) -> bool {
+        if counter + REPLAY_WINDOW <= self.last {
+            return false;
+        }
+        if counter > self.last {
+            if counter - self.last >= REPLAY_WINDOW {
+                self.bitmap = [0; (REPLAY_WINDOW / 64) as usize];
+            } else {
+                for v in self.last + 1..=counter {
+                    let (i, bit) = Self::bit(v);
+                    self.bitmap[i] &= !bit;
+                }
+            }
+            self.last = counter;
+        }
+        let (i, bit) = Self::bit(counter);
+        if self.bitmap[i] & bit != 0 {
+            return false;
+        }
+        self.bitmap[i] |= bit;
+        true
+    }
+}
+
+#[test]
+fn test_replay_filter() {
+    let mut filter = ReplayFilter::new();
+    assert!(filter.check(0));
+    assert!(!filter.check(0));
+    assert!(filter.check(5));
+    assert!(filter.check(3));
+    assert!(!filter.check(3));
+    assert!(filter.check(5000));
+    assert!(!filter.check(5000 - REPLAY_WINDOW));
+    assert!(filter.check(5000 - REPLAY_WINDOW + 1));
+}
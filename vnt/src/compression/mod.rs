@@ -12,6 +12,13 @@ use zstd::zstd_safe::CompressionLevel;
 
 use crate::protocol::NetPacket;
 
+// 小于这个长度的包不压缩
+#[cfg(any(feature = "lz4_compress", feature = "zstd_compress"))]
+const MIN_COMPRESS_LEN: usize = 128;
+// 熵检测的采样长度
+#[cfg(any(feature = "lz4_compress", feature = "zstd_compress"))]
+const ENTROPY_SAMPLE_LEN: usize = 512;
+
 #[cfg(feature = "lz4_compress")]
 mod lz4_compress;
 #[cfg(feature = "zstd_compress")]
@@ -61,6 +68,24 @@ impl FromStr for Compressor {
     }
 }
 
+impl Compressor {
+    /// 对端需要具备的解压能力，对端能力通过ping包获得，不支持的对端按原数据发送
+    pub fn feature(&self) -> u16 {
+        match self {
+            #[cfg(feature = "lz4_compress")]
+            Compressor::Lz4 => crate::protocol::control_packet::FEATURE_LZ4,
+            #[cfg(feature = "zstd_compress")]
+            Compressor::Zstd(_) => crate::protocol::control_packet::FEATURE_ZSTD,
+            Compressor::None => 0,
+        }
+    }
+    /// 对端是否能解压
+    pub fn is_supported(&self, peer_features: u16) -> bool {
+        let feature = self.feature();
+        feature != 0 && peer_features & feature == feature
+    }
+}
+
 #[cfg(not(any(feature = "lz4_compress", feature = "zstd_compress")))]
 impl Compressor {
     pub fn compress<I: AsRef<[u8]>, O: AsRef<[u8]> + AsMut<[u8]>>(
@@ -89,7 +114,9 @@ impl Compressor {
         match self {
             #[cfg(feature = "lz4_compress")]
             Compressor::Lz4 => {
-                if in_net_packet.data_len() < 128 {
+                if in_net_packet.data_len() < MIN_COMPRESS_LEN
+                    || is_high_entropy(in_net_packet.payload())
+                {
                     return Ok(false);
                 }
                 Lz4Compressor::compress(in_net_packet, out)?;
@@ -103,7 +130,9 @@ impl Compressor {
             }
             #[cfg(feature = "zstd_compress")]
             Compressor::Zstd(level) => {
-                if in_net_packet.data_len() < 128 {
+                if in_net_packet.data_len() < MIN_COMPRESS_LEN
+                    || is_high_entropy(in_net_packet.payload())
+                {
                     return Ok(false);
                 }
                 ZstdCompressor::compress(*level, in_net_packet, out)?;
@@ -134,6 +163,41 @@ impl Compressor {
     }
 }
 
+/// 已经加密或压缩过的数据熵接近最大值，再压缩没有效果，只会浪费cpu
+#[cfg(any(feature = "lz4_compress", feature = "zstd_compress"))]
+fn is_high_entropy(data: &[u8]) -> bool {
+    // 取末尾采样，避开ip头和tcp头
+    let sample = &data[data.len().saturating_sub(ENTROPY_SAMPLE_LEN)..];
+    if sample.is_empty() {
+        return false;
+    }
+    let mut counts = [0u32; 256];
+    for v in sample {
+        counts[*v as usize] += 1;
+    }
+    let len = sample.len() as f64;
+    let entropy: f64 = counts
+        .iter()
+        .filter(|count| **count != 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum();
+    // 样本较小时熵的最大值达不到8
+    entropy > len.min(256.0).log2() * 0.85
+}
+
+#[test]
+#[cfg(any(feature = "lz4_compress", feature = "zstd_compress"))]
+fn test_entropy() {
+    let text = b"Alice was beginning to get very tired of sitting by her sister on the bank, and of having nothing to do: once or twice she had peeped into the book her sister was reading";
+    assert!(!is_high_entropy(text));
+    let random: Vec<u8> = (0..1024).map(|_| rand::random::<u8>()).collect();
+    assert!(is_high_entropy(&random));
+    assert!(is_high_entropy(&random[..128]));
+}
+
 #[test]
 #[cfg(feature = "zstd_compress")]
 fn test_lz4() {
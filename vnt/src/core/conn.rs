@@ -1,36 +1,48 @@
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crossbeam_utils::atomic::AtomicCell;
 use parking_lot::{Mutex, RwLock};
 use rand::Rng;
 
+use crate::acl::firewall::{Firewall, FirewallRule};
+use crate::acl::Acl;
+use crate::channel::aggregate::Aggregator;
+use crate::channel::capture::Capture;
 use crate::channel::context::ChannelContext;
 use crate::channel::idle::Idle;
-use crate::channel::punch::{NatInfo, Punch};
+use crate::channel::punch::{NatInfo, Punch, PunchSettings};
+use crate::channel::quality::PathQualityInfo;
 use crate::channel::sender::IpPacketSender;
 use crate::channel::socket::LocalInterface;
-use crate::channel::{init_channel, init_context, Route, RouteKey};
-use crate::cipher::Cipher;
+use crate::channel::stats::{CounterSnapshot, DropReason, FlowCounter, FlowKey, PeerCounter};
+use crate::channel::{init_channel, init_context, Route, RouteKey, UseChannelType};
 #[cfg(feature = "server_encrypt")]
 use crate::cipher::RsaCipher;
+use crate::cipher::{aes_hardware, Cipher};
 use crate::compression::Compressor;
 use crate::core::Config;
 use crate::external_route::{AllowExternalRoute, ExternalRoute};
+use crate::fec::Fec;
 use crate::handle::handshaker::Handshake;
-use crate::handle::maintain::PunchReceiver;
+use crate::handle::maintain::{DiagEcho, DiagHop, DiagThroughput, PunchReceiver};
 use crate::handle::recv_data::RecvDataHandler;
-use crate::handle::{maintain, BaseConfigInfo, ConnectStatus, CurrentDeviceInfo, PeerDeviceInfo};
+use crate::handle::{
+    change_status, maintain, BaseConfigInfo, ConnectStatus, CurrentDeviceInfo, PeerDeviceInfo,
+    ServerAddress,
+};
 use crate::nat::NatTest;
+use crate::obfs::Obfs;
 #[cfg(feature = "integrated_tun")]
 use crate::tun_tap_device::tun_create_helper::{DeviceAdapter, TunDeviceHelper};
 use crate::tun_tap_device::vnt_device::DeviceWrite;
-use crate::util::limit::TrafficMeterMultiAddress;
-use crate::util::{Scheduler, StopManager};
-use crate::{channel, nat, VntCallback};
+use crate::util::limit::{BandwidthLimiter, TrafficMeterMultiAddress};
+use crate::util::{EventLog, Scheduler, StopManager};
+use crate::{channel, nat, VntCallback, VntEvent};
 
 #[derive(Clone)]
 pub struct Vnt {
@@ -75,8 +87,21 @@ pub struct VntInner {
     client_cipher: Cipher,
     server_cipher: Cipher,
     external_route: ExternalRoute,
+    out_external_route: AllowExternalRoute,
+    punch_settings: PunchSettings,
+    // 当前的端口映射配置和运行中的映射，重新加载配置或通过命令增删时替换
+    #[cfg(feature = "port_mapping")]
+    port_mapping: Mutex<(
+        Vec<crate::port_mapping::MappingRule>,
+        Option<crate::port_mapping::PortMapping>,
+    )>,
     up_traffic_meter: Option<TrafficMeterMultiAddress>,
     down_traffic_meter: Option<TrafficMeterMultiAddress>,
+    start_time: Instant,
+    watchdog_expired: Arc<AtomicBool>,
+    event_log: Arc<EventLog>,
+    #[cfg(feature = "integrated_tun")]
+    tun_device_helper: TunDeviceHelper,
 }
 
 impl VntInner {
@@ -123,6 +148,12 @@ impl VntInner {
         } else {
             None
         };
+        if config.cipher_model.is_aes() && !aes_hardware() {
+            log::warn!(
+                "cpu不支持aes硬件加速,{}性能较差,建议所有客户端使用chacha20_poly1305",
+                config.cipher_model
+            );
+        }
         //客户端对称加密
         let client_cipher =
             Cipher::new_password(config.cipher_model, config.password.clone(), finger)?;
@@ -139,7 +170,7 @@ impl VntInner {
             nat::local_ipv4()
         };
 
-        let default_interface = if config.in_ips.is_empty() {
+        let default_interface = if config.in_ips.is_empty() && config.route_policy.is_empty() {
             //没有改变路由，不需要绑定网卡
             LocalInterface::default()
         } else {
@@ -158,17 +189,36 @@ impl VntInner {
             config.password_hash(),
             config.server_encrypt,
             config.device_id.clone(),
-            config.server_address_str.clone(),
+            ServerAddress::new(config.server_address_list.clone()),
+            config.server_prefer,
             config.name_servers.clone(),
             config.mtu.unwrap_or(1420),
             #[cfg(feature = "integrated_tun")]
             #[cfg(target_os = "windows")]
             config.tap,
             #[cfg(feature = "integrated_tun")]
-            #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+            #[cfg(target_os = "windows")]
+            config.wintun_path.clone(),
+            #[cfg(feature = "integrated_tun")]
+            #[cfg(any(target_os = "windows", target_os = "linux"))]
+            config.layer2,
+            #[cfg(feature = "integrated_tun")]
+            #[cfg(any(
+                target_os = "windows",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd"
+            ))]
             config.device_name.clone(),
             config.allow_wire_guard,
             default_interface.clone(),
+            config.identity.clone(),
+            config.totp_secret.clone(),
+            config.advertise_routes.clone(),
+            config.allow_ip_change,
+            config.network,
+            config.dns_mode,
         );
         // 服务停止管理器
         let stop_manager = {
@@ -176,7 +226,7 @@ impl VntInner {
             StopManager::new(move || callback.stop())
         };
         #[cfg(feature = "port_mapping")]
-        crate::port_mapping::start_port_mapping(
+        let port_mapping = crate::port_mapping::start_port_mapping(
             stop_manager.clone(),
             config.port_mapping_list.clone(),
         )?;
@@ -187,10 +237,32 @@ impl VntInner {
                 v.clone()
             }
         });
+        let mut multipath_interfaces = Vec::with_capacity(config.multipath.len());
+        for ip in &config.multipath {
+            multipath_interfaces.push(channel::socket::get_interface(*ip)?);
+        }
         if config.use_channel_type.is_only_relay() {
             //中继模式下只监听一个端口就够了
             ports.truncate(1);
+            multipath_interfaces.clear();
         }
+        // wg互通，收到的数据直接写入虚拟网卡
+        #[cfg(feature = "wireguard")]
+        let wireguard = match &config.wireguard {
+            Some(wg_config) => {
+                let device = device.clone();
+                Some(crate::wireguard::WireGuard::new(
+                    stop_manager.clone(),
+                    wg_config,
+                    move |buf| device.write(buf),
+                )?)
+            }
+            None => None,
+        };
+        let capture = match config.capture.clone() {
+            Some(capture) => Some(Capture::new(capture, config.layer2)?),
+            None => None,
+        };
         //通道上下文
         let (context, tcp_listener) = init_context(
             ports,
@@ -202,10 +274,53 @@ impl VntInner {
             default_interface,
             up_traffic_meter.clone(),
             down_traffic_meter.clone(),
+            Obfs::new(config.obfs, &config.token),
+            config.outbound_proxy.clone(),
+            multipath_interfaces,
+            config.multipath_model,
+            Fec::new(config.fec),
+            config
+                .aggregate
+                .map(|v| Aggregator::new(Duration::from_millis(v as u64))),
+            config.layer2,
+            config.peer_relay,
+            Acl::new(
+                config.acl.clone(),
+                config.allowed_ips.clone(),
+                Firewall::new(config.firewall, config.firewall_allow.clone()),
+            ),
+            config.encrypt_scope,
+            BandwidthLimiter::new(config.bandwidth_limit, config.per_peer_limit.clone()),
+            config.qos,
+            config.path_switch,
+            capture,
+            #[cfg(feature = "wireguard")]
+            wireguard,
+            #[cfg(feature = "peer_key")]
+            config
+                .peer_key
+                .as_ref()
+                .map(|v| crate::peer_key::PeerKeys::new(v, config.pq_kem)),
+            #[cfg(feature = "integrity")]
+            config
+                .integrity
+                .then(|| crate::cipher::Integrity::new(&config.token, config.integrity_compat)),
         )?;
+        context.route_table.set_peer_channel(&config.peer_channel);
+        context.route_table.set_path_policy(&config.path_policy);
         let local_ipv6 = nat::local_ipv6();
         let udp_ports = context.main_local_udp_port()?;
         let tcp_port = tcp_listener.local_addr()?.port();
+        // 在系统防火墙中放行监听的端口，退出时删除
+        #[cfg(feature = "integrated_tun")]
+        let host_firewall = if config.host_firewall {
+            let name = config.device_name.as_deref().unwrap_or("vnt");
+            let host_firewall = crate::util::HostFirewall::new(name);
+            host_firewall.allow_ports(&udp_ports, tcp_port);
+            Some(host_firewall)
+        } else {
+            None
+        };
         //nat检测工具
         let nat_test = NatTest::new(
             context.channel_num(),
@@ -219,7 +334,9 @@ impl VntInner {
         // 定时器
         let scheduler = Scheduler::new(stop_manager.clone())?;
         let external_route = ExternalRoute::new(config.in_ips.clone());
+        external_route.set_domain_policy(config.domain_policy(), config.name_servers.clone());
         let out_external_route = AllowExternalRoute::new(config.out_ips.clone());
+        out_external_route.set_sources(config.out_sources.clone());
 
         #[cfg(feature = "ip_proxy")]
         #[cfg(feature = "integrated_tun")]
@@ -233,12 +350,19 @@ impl VntInner {
         } else {
             None
         };
-        let (punch_sender, punch_receiver) = maintain::punch_channel();
+        let punch_settings = PunchSettings::new(
+            config.punch_model,
+            config.punch_rate,
+            config.punch_concurrency as usize,
+        );
+        let (punch_sender, punch_receiver) = maintain::punch_channel(punch_settings.clone());
         let peer_nat_info_map: Arc<RwLock<HashMap<Ipv4Addr, NatInfo>>> =
             Arc::new(RwLock::new(HashMap::with_capacity(16)));
         let handshake = Handshake::new(
             #[cfg(feature = "server_encrypt")]
             rsa_cipher.clone(),
+            #[cfg(feature = "pq")]
+            config.pq_kem,
         );
         #[cfg(feature = "integrated_tun")]
         let tun_device_helper = {
@@ -254,6 +378,7 @@ impl VntInner {
                 device_map.clone(),
                 config.compressor,
                 device.clone().into_device_adapter(),
+                host_firewall,
             )
         };
 
@@ -271,22 +396,27 @@ impl VntInner {
             punch_sender,
             peer_nat_info_map.clone(),
             external_route.clone(),
-            out_external_route,
+            out_external_route.clone(),
             #[cfg(feature = "ip_proxy")]
             #[cfg(feature = "integrated_tun")]
             proxy_map.clone(),
             handshake.clone(),
             #[cfg(feature = "integrated_tun")]
-            tun_device_helper,
+            tun_device_helper.clone(),
         );
 
         //初始化网络数据通道
-        let (udp_socket_sender, connect_util) =
-            init_channel(tcp_listener, context.clone(), stop_manager.clone(), handler)?;
+        let (udp_socket_sender, connect_util) = init_channel(
+            tcp_listener,
+            context.clone(),
+            stop_manager.clone(),
+            handler,
+            config.server_cert.clone(),
+        )?;
         // 打洞逻辑
         let punch = Punch::new(
             context.clone(),
-            config.punch_model,
+            punch_settings.clone(),
             connect_util.clone(),
             nat_test.clone(),
             current_device.clone(),
@@ -303,8 +433,73 @@ impl VntInner {
             connect_util.clone(),
             callback.clone(),
             0,
+            handshake.clone(),
+        );
+        // 定时重新解析服务端域名
+        maintain::retrieve_domain(
+            &scheduler,
+            context.clone(),
+            current_device.clone(),
+            config_info.clone(),
             handshake,
+            Duration::from_secs(config.dns_refresh as u64),
         );
+        let watchdog_expired = Arc::new(AtomicBool::new(false));
+        if config.watchdog != 0 {
+            maintain::watchdog(
+                &stop_manager,
+                context.clone(),
+                current_device.clone(),
+                #[cfg(feature = "integrated_tun")]
+                tun_device_helper.clone(),
+                Duration::from_secs(config.watchdog as u64),
+                watchdog_expired.clone(),
+                callback.clone(),
+            )?;
+        }
+        #[cfg(feature = "integrated_tun")]
+        if let Some(mdns_reflect) = &config.mdns_reflect {
+            crate::reflector::start_reflect(&stop_manager, mdns_reflect, current_device.clone())?;
+        }
+        // 定时发出合并的小包
+        maintain::aggregate_flush(&scheduler, context.clone());
+        // 控制数据的重传
+        maintain::reliable_retransmit(&scheduler, context.clone());
+        // 状态变化的事件
+        let event_log = Arc::new(EventLog::new());
+        maintain::events(
+            &scheduler,
+            context.clone(),
+            current_device.clone(),
+            device_map.clone(),
+            event_log.clone(),
+            callback.clone(),
+        );
+        // 客户端之间的密钥协商
+        #[cfg(feature = "peer_key")]
+        if context.peer_key().is_some() {
+            maintain::peer_key(
+                &scheduler,
+                context.clone(),
+                current_device.clone(),
+                device_map.clone(),
+                client_cipher.clone(),
+            );
+        }
+        if let Some(path) = config.peer_cache.clone() {
+            if !config.use_channel_type.is_only_relay() {
+                // 不延迟启动，连上服务端后立即探测缓存的对端地址
+                maintain::peer_cache(
+                    &scheduler,
+                    context.clone(),
+                    current_device.clone(),
+                    peer_nat_info_map.clone(),
+                    client_cipher.clone(),
+                    path,
+                    &config.token,
+                );
+            }
+        }
         {
             let context = context.clone();
             let nat_test = nat_test.clone();
@@ -322,6 +517,11 @@ impl VntInner {
             }
             let client_cipher = client_cipher.clone();
             let server_cipher = server_cipher.clone();
+            let heartbeat = Duration::from_secs(config.heartbeat as u64);
+            let route_timeout = Duration::from_secs(config.route_timeout as u64);
+            // 连续dead_path次心跳没有响应则认为路径失效
+            let dead_idle = heartbeat * config.dead_path + Duration::from_secs(1);
+            let punch_settings = punch_settings.clone();
             //延迟启动
             scheduler.timeout(Duration::from_secs(3), move |scheduler| {
                 start(
@@ -336,6 +536,10 @@ impl VntInner {
                     config_info,
                     punch,
                     callback,
+                    heartbeat,
+                    route_timeout,
+                    dead_idle,
+                    punch_settings,
                 );
             });
         }
@@ -353,8 +557,17 @@ impl VntInner {
             client_cipher,
             server_cipher,
             external_route,
+            out_external_route,
+            punch_settings,
+            #[cfg(feature = "port_mapping")]
+            port_mapping: Mutex::new((config.port_mapping_list.clone(), port_mapping)),
             up_traffic_meter,
             down_traffic_meter,
+            start_time: Instant::now(),
+            watchdog_expired,
+            event_log,
+            #[cfg(feature = "integrated_tun")]
+            tun_device_helper,
         })
     }
 }
@@ -371,6 +584,10 @@ pub fn start<Call: VntCallback>(
     config_info: BaseConfigInfo,
     punch: Punch,
     callback: Call,
+    heartbeat: Duration,
+    route_timeout: Duration,
+    dead_idle: Duration,
+    punch_settings: PunchSettings,
 ) {
     // 定时心跳
     maintain::heartbeat(
@@ -380,9 +597,11 @@ pub fn start<Call: VntCallback>(
         device_map.clone(),
         client_cipher.clone(),
         server_cipher.clone(),
+        heartbeat,
+        dead_idle,
     );
     // 路由空闲检测逻辑
-    let idle = Idle::new(Duration::from_secs(10), context.clone());
+    let idle = Idle::new(route_timeout, dead_idle, context.clone());
     // 定时空闲检查
     maintain::idle_route(
         &scheduler,
@@ -421,6 +640,32 @@ pub fn start<Call: VntCallback>(
             client_cipher.clone(),
             punch_receiver,
             punch,
+            punch_settings,
+        );
+        // 定时探测直连路径的mtu
+        maintain::pmtu_discovery(
+            &scheduler,
+            context.clone(),
+            current_device.clone(),
+            client_cipher.clone(),
+        );
+        // 本地ip变化时迁移直连路由
+        maintain::migrate(
+            &scheduler,
+            context.clone(),
+            nat_test.clone(),
+            current_device.clone(),
+            client_cipher.clone(),
+        );
+        // 局域网发现
+        maintain::lan_discovery(
+            &scheduler,
+            context.clone(),
+            nat_test.nat_info().local_ipv4(),
+            device_map.clone(),
+            current_device.clone(),
+            client_cipher.clone(),
+            &config_info.token,
         );
     }
     maintain::up_status(scheduler, context.clone(), current_device.clone())
@@ -480,6 +725,50 @@ impl VntInner {
             vec![]
         }
     }
+    /// 到对端固定使用的通道，没有固定时为None
+    pub fn peer_channel(&self, ip: &Ipv4Addr) -> Option<UseChannelType> {
+        self.context.lock().as_ref()?.route_table.peer_channel(ip)
+    }
+    /// 到对端直连和服务端中转的质量，没有开启路径切换或者没有直连时为None
+    pub fn path_quality(&self, ip: &Ipv4Addr) -> Option<PathQualityInfo> {
+        self.context
+            .lock()
+            .as_ref()?
+            .route_table
+            .quality()?
+            .info(ip)
+    }
+    /// 对端的收发统计，关闭流量统计时为None
+    pub fn peer_stats(&self) -> Option<Vec<(Ipv4Addr, PeerCounter)>> {
+        self.context
+            .lock()
+            .as_ref()?
+            .peer_stats()
+            .map(|v| v.peers())
+    }
+    /// 按流的收发统计
+    pub fn flow_stats(&self) -> Option<Vec<(FlowKey, FlowCounter)>> {
+        self.context
+            .lock()
+            .as_ref()?
+            .peer_stats()
+            .map(|v| v.flows())
+    }
+    /// 握手失败、打洞、解密失败等计数
+    pub fn counters(&self) -> CounterSnapshot {
+        match self.context.lock().as_ref() {
+            Some(context) => {
+                let mut snapshot = context.counters().snapshot();
+                snapshot.direct_links = context.route_table.direct_links();
+                snapshot.acl_denied = context.acl().denied();
+                snapshot.drops[DropReason::AclDeny as usize] = snapshot.acl_denied;
+                snapshot.drops[DropReason::FirewallDeny as usize] =
+                    context.acl().firewall().denied();
+                snapshot
+            }
+            None => CounterSnapshot::default(),
+        }
+    }
     pub fn up_stream(&self) -> u64 {
         self.up_traffic_meter.as_ref().map_or(0, |v| v.total())
     }
@@ -502,7 +791,20 @@ impl VntInner {
     }
     pub fn stop(&self) {
         //退出协助回收资源
-        let _ = self.context.lock().take();
+        let context = self.context.lock().take();
+        if let Some(context) = context {
+            // 先停止读取网卡并发出已读取的数据，再通知服务端和对端本端离开
+            #[cfg(feature = "integrated_tun")]
+            self.tun_device_helper.shutdown();
+            context.flush_all_aggregate();
+            maintain::leave(
+                &context,
+                &self.current_device.load(),
+                &self.device_map,
+                &self.client_cipher,
+                &self.server_cipher,
+            );
+        }
         self.stop_manager.stop()
     }
     pub fn is_stopped(&self) -> bool {
@@ -523,6 +825,234 @@ impl VntInner {
     pub fn config(&self) -> &Config {
         &self.config
     }
+    /// 是否因为看门狗无法恢复而停止
+    pub fn watchdog_expired(&self) -> bool {
+        self.watchdog_expired.load(Ordering::Acquire)
+    }
+    /// 启动后运行的时长
+    pub fn uptime(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+    /// 订阅事件，返回序号不小于since的事件和下一次使用的序号，没有新事件时最多等待timeout。
+    /// since为0时返回保留的所有事件
+    pub fn events(&self, since: u64, timeout: Duration) -> (u64, Vec<(u64, VntEvent)>) {
+        self.event_log.wait(since, timeout)
+    }
+    /// 断开服务端连接并重新握手注册，和看门狗的重建连接相同
+    pub fn reconnect(&self) {
+        if let Some(context) = self.context.lock().as_ref() {
+            context.close_server(self.current_device.load().connect_server);
+        }
+        change_status(&self.current_device, ConnectStatus::Connecting);
+        log::info!("手动重新连接服务端");
+    }
+    /// 立即向对端发送心跳，等待对端的数据，超时返回None，否则返回当前到对端的路由。
+    /// 期间收到对端的任何数据都视为回应
+    pub fn ping_peer(&self, ip: &Ipv4Addr, timeout: Duration) -> anyhow::Result<Option<Route>> {
+        let context = match self.context.lock().as_ref() {
+            Some(context) => context.clone(),
+            None => return Err(anyhow::anyhow!("stopped")),
+        };
+        let current_device = self.current_device.load();
+        if current_device.status.offline() {
+            return Err(anyhow::anyhow!("not connected to the server"));
+        }
+        if !self.device_map.lock().1.contains_key(ip) {
+            return Err(anyhow::anyhow!("peer {} not found", ip));
+        }
+        let start = Instant::now();
+        maintain::ping_peer(&context, &current_device, &self.client_cipher, *ip)?;
+        while start.elapsed() < timeout {
+            std::thread::sleep(Duration::from_millis(20));
+            let replied = context
+                .route_table
+                .last_read(ip)
+                .map_or(false, |time| time < start.elapsed());
+            if replied {
+                return Ok(context.route_table.route_one(ip));
+            }
+        }
+        Ok(None)
+    }
+    /// vnt协议层的回显，不经过虚拟网卡和系统防火墙
+    pub fn diag_echo(&self, ip: &Ipv4Addr, timeout: Duration) -> anyhow::Result<DiagEcho> {
+        let (context, current_device) = self.diag_context(ip)?;
+        maintain::diag_echo(&context, &current_device, &self.client_cipher, *ip, timeout)
+    }
+    /// 到对端经过的服务端或者中转客户端，以及每一跳的往返时间
+    pub fn diag_traceroute(
+        &self,
+        ip: &Ipv4Addr,
+        timeout: Duration,
+    ) -> anyhow::Result<Vec<DiagHop>> {
+        let (context, current_device) = self.diag_context(ip)?;
+        maintain::diag_traceroute(&context, &current_device, &self.client_cipher, *ip, timeout)
+    }
+    /// 在vnt协议层向对端发送duration时长的测试数据，得到能达到的吞吐量
+    pub fn diag_bandwidth(
+        &self,
+        ip: &Ipv4Addr,
+        duration: Duration,
+    ) -> anyhow::Result<DiagThroughput> {
+        let (context, current_device) = self.diag_context(ip)?;
+        maintain::diag_bandwidth(
+            &context,
+            &current_device,
+            &self.client_cipher,
+            *ip,
+            duration,
+        )
+    }
+    fn diag_context(&self, ip: &Ipv4Addr) -> anyhow::Result<(ChannelContext, CurrentDeviceInfo)> {
+        let context = match self.context.lock().as_ref() {
+            Some(context) => context.clone(),
+            None => return Err(anyhow::anyhow!("stopped")),
+        };
+        if !self.device_map.lock().1.contains_key(ip) {
+            return Err(anyhow::anyhow!("peer {} not found", ip));
+        }
+        Ok((context, self.current_device.load()))
+    }
+    /// 重新加载配置中可以在运行时修改的部分：端口映射、acl、in_ips/out_ips和打洞参数，
+    /// 不会重建虚拟网卡，也不影响已有的连接，返回有变化的配置项。其他配置项的修改需要重启才能生效
+    pub fn reload(&self, config: &Config) -> anyhow::Result<Vec<&'static str>> {
+        let mut changed = Vec::new();
+        #[cfg(feature = "port_mapping")]
+        if self.port_mapping.lock().0 != config.port_mapping_list {
+            self.restart_port_mapping(config.port_mapping_list.clone())?;
+            changed.push("port_mapping");
+        }
+        if let Some(context) = self.context.lock().as_ref() {
+            if context.acl().set_rules(config.acl.clone()) {
+                changed.push("acl");
+            }
+            if context.acl().set_allowed_ips(config.allowed_ips.clone()) {
+                changed.push("allowed_ips");
+            }
+            if context
+                .acl()
+                .firewall()
+                .set(config.firewall, config.firewall_allow.clone())
+            {
+                changed.push("firewall");
+            }
+            if context.route_table.set_peer_channel(&config.peer_channel) {
+                changed.push("peer_channel");
+            }
+            if context.route_table.set_path_policy(&config.path_policy) {
+                changed.push("path_policy");
+            }
+        }
+        if self.external_route.update(config.in_ips.clone()) {
+            changed.push("in_ips");
+        }
+        self.external_route
+            .set_domain_policy(config.domain_policy(), config.name_servers.clone());
+        if self.out_external_route.update(config.out_ips.clone()) {
+            changed.push("out_ips");
+        }
+        if self
+            .out_external_route
+            .set_sources(config.out_sources.clone())
+        {
+            changed.push("out_sources");
+        }
+        if self.punch_settings.set(
+            config.punch_model,
+            config.punch_rate,
+            config.punch_concurrency as usize,
+        ) {
+            changed.push("punch");
+        }
+        log::info!("重新加载配置,变化的配置项:{:?}", changed);
+        Ok(changed)
+    }
+    /// 当前生效的端口映射，包含运行中通过命令添加的
+    #[cfg(feature = "port_mapping")]
+    pub fn port_mapping_list(&self) -> Vec<crate::port_mapping::MappingRule> {
+        self.port_mapping.lock().0.clone()
+    }
+    /// 运行中添加端口映射，不修改配置文件，重新加载配置后以配置文件为准
+    #[cfg(feature = "port_mapping")]
+    pub fn add_port_mapping(&self, rule: crate::port_mapping::MappingRule) -> anyhow::Result<()> {
+        let mut list = self.port_mapping_list();
+        if list.contains(&rule) {
+            return Err(anyhow::anyhow!("port mapping {} already exists", rule));
+        }
+        list.push(rule);
+        self.restart_port_mapping(list)
+    }
+    /// 运行中删除端口映射，不存在时返回false
+    #[cfg(feature = "port_mapping")]
+    pub fn remove_port_mapping(
+        &self,
+        rule: &crate::port_mapping::MappingRule,
+    ) -> anyhow::Result<bool> {
+        let mut list = self.port_mapping_list();
+        let len = list.len();
+        list.retain(|v| v != rule);
+        if list.len() == len {
+            return Ok(false);
+        }
+        self.restart_port_mapping(list)?;
+        Ok(true)
+    }
+    /// 防火墙是否开启和当前生效的放行规则，包含运行中通过命令添加的
+    pub fn firewall_rules(&self) -> anyhow::Result<(bool, Vec<FirewallRule>)> {
+        match self.context.lock().as_ref() {
+            Some(context) => {
+                let firewall = context.acl().firewall();
+                Ok((firewall.is_enabled(), firewall.rules()))
+            }
+            None => Err(anyhow::anyhow!("stopped")),
+        }
+    }
+    /// 运行中添加防火墙放行规则，不修改配置文件，重新加载配置后以配置文件为准
+    pub fn add_firewall_rule(&self, rule: FirewallRule) -> anyhow::Result<bool> {
+        match self.context.lock().as_ref() {
+            Some(context) => Ok(context.acl().firewall().add(rule)),
+            None => Err(anyhow::anyhow!("stopped")),
+        }
+    }
+    /// 运行中删除防火墙放行规则，不存在时返回false
+    pub fn remove_firewall_rule(&self, rule: &FirewallRule) -> anyhow::Result<bool> {
+        match self.context.lock().as_ref() {
+            Some(context) => Ok(context.acl().firewall().remove(rule)),
+            None => Err(anyhow::anyhow!("stopped")),
+        }
+    }
+    /// 按新的列表重启端口映射，新的映射启动失败时恢复原来的映射
+    #[cfg(feature = "port_mapping")]
+    fn restart_port_mapping(
+        &self,
+        list: Vec<crate::port_mapping::MappingRule>,
+    ) -> anyhow::Result<()> {
+        let mut guard = self.port_mapping.lock();
+        // 先释放旧的端口，新旧映射可能监听同一个端口
+        if let Some(port_mapping) = guard.1.take() {
+            port_mapping.stop();
+        }
+        match crate::port_mapping::start_port_mapping(self.stop_manager.clone(), list.clone()) {
+            Ok(port_mapping) => {
+                guard.0 = list;
+                guard.1 = port_mapping;
+                Ok(())
+            }
+            Err(e) => {
+                guard.1 = match crate::port_mapping::start_port_mapping(
+                    self.stop_manager.clone(),
+                    guard.0.clone(),
+                ) {
+                    Ok(port_mapping) => port_mapping,
+                    Err(e) => {
+                        log::warn!("恢复端口映射失败 {:?}", e);
+                        None
+                    }
+                };
+                Err(e)
+            }
+        }
+    }
     pub fn ipv4_packet_sender(&self) -> Option<IpPacketSender> {
         if let Some(c) = self.context.lock().as_ref() {
             Some(IpPacketSender::new(
@@ -539,6 +1069,18 @@ impl VntInner {
             None
         }
     }
+    /// 使用VpnService.establish()得到的fd作为虚拟网卡，fd的所有权交给vnt，
+    /// 地址和路由由Java层通过VpnService.Builder设置，这里只负责收发数据。
+    /// 已有网卡时先停止旧的，重新建立VpnService后再次调用即可替换
+    #[cfg(target_os = "android")]
+    #[cfg(feature = "integrated_tun")]
+    pub fn attach_tun_fd(&self, fd: i32) -> anyhow::Result<()> {
+        let device = tun::Device::new(fd as _)?;
+        self.tun_device_helper.stop();
+        self.tun_device_helper
+            .start(Arc::new(device), Vec::new(), self.config.allow_wire_guard)?;
+        Ok(())
+    }
 }
 
 impl Drop for VntInner {
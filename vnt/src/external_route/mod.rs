@@ -1,26 +1,162 @@
 use std::net::Ipv4Addr;
 use std::sync::Arc;
 
+use parking_lot::{Mutex, RwLock};
+
+mod policy;
+pub(crate) use policy::parse_network;
+pub use policy::{OutSourceRule, PolicyTarget, RoutePolicy};
+
+// 域名分流解析出的主机路由数量上限，超出时删除最早的
+const MAX_RESOLVED: usize = 1024;
+
+#[derive(Default)]
+struct RouteParts {
+    // 手动配置的路由
+    manual: Vec<(u32, u32, Ipv4Addr)>,
+    // 其他客户端宣告的路由
+    advertised: Vec<(u32, u32, Ipv4Addr)>,
+    // 域名分流解析出的主机路由，按添加顺序
+    resolved: Vec<(u32, u32, Ipv4Addr)>,
+    // 分流的域名和网关
+    domains: Vec<(String, Ipv4Addr)>,
+    // 解析分流域名使用的dns服务器
+    name_servers: Vec<String>,
+}
+
+impl RouteParts {
+    /// 宣告的网段和手动配置的相同时使用手动配置的
+    fn merge(&self) -> Vec<(u32, u32, Ipv4Addr)> {
+        let mut route_table = self.manual.clone();
+        for (dest, mask, gateway) in self.advertised.iter().chain(self.resolved.iter()) {
+            if !route_table.iter().any(|(d, m, _)| d == dest && m == mask) {
+                route_table.push((*dest, *mask, *gateway));
+            }
+        }
+        ExternalRoute::sort(route_table)
+    }
+    // 不是手动配置的网段，这些网段在网卡上的路由随宣告和解析增减
+    fn dynamic(&self) -> Vec<(Ipv4Addr, Ipv4Addr)> {
+        let mut list = Vec::new();
+        for (dest, mask, _) in self.advertised.iter().chain(self.resolved.iter()) {
+            let route = (Ipv4Addr::from(*dest), Ipv4Addr::from(*mask));
+            if !self.manual.iter().any(|(d, m, _)| d == dest && m == mask) && !list.contains(&route)
+            {
+                list.push(route);
+            }
+        }
+        list
+    }
+}
+
 // 目标网段，子网掩码，网关
 #[derive(Clone)]
 pub struct ExternalRoute {
-    route_table: Vec<(u32, u32, Ipv4Addr)>,
+    route_table: Arc<RwLock<Vec<(u32, u32, Ipv4Addr)>>>,
+    // 各个来源的路由，合并后就是route_table
+    parts: Arc<Mutex<RouteParts>>,
 }
 
 impl ExternalRoute {
-    pub fn new(mut route_table: Vec<(u32, u32, Ipv4Addr)>) -> Self {
+    pub fn new(route_table: Vec<(u32, u32, Ipv4Addr)>) -> Self {
+        let route_table = Self::sort(route_table);
+        Self {
+            route_table: Arc::new(RwLock::new(route_table.clone())),
+            parts: Arc::new(Mutex::new(RouteParts {
+                manual: route_table,
+                ..Default::default()
+            })),
+        }
+    }
+    fn sort(mut route_table: Vec<(u32, u32, Ipv4Addr)>) -> Vec<(u32, u32, Ipv4Addr)> {
         for (dest, mask, _) in &mut route_table {
             *dest = *mask & *dest;
         }
         route_table.sort_by(|(dest1, _, _), (dest2, _, _)| dest2.cmp(dest1));
-        Self { route_table }
+        route_table
+    }
+    /// 替换路由表，所有克隆共享同一份，返回是否有变化。
+    /// 只影响数据转发，虚拟网卡的系统路由在重新创建网卡时才会更新
+    pub fn update(&self, route_table: Vec<(u32, u32, Ipv4Addr)>) -> bool {
+        let mut parts = self.parts.lock();
+        parts.manual = Self::sort(route_table);
+        let route_table = parts.merge();
+        let mut guard = self.route_table.write();
+        if *guard == route_table {
+            return false;
+        }
+        *guard = route_table;
+        true
+    }
+    fn update_dynamic<F>(&self, f: F) -> (Vec<(Ipv4Addr, Ipv4Addr)>, Vec<(Ipv4Addr, Ipv4Addr)>)
+    where
+        F: FnOnce(&mut RouteParts),
+    {
+        let mut parts = self.parts.lock();
+        let old = parts.dynamic();
+        f(&mut parts);
+        let new = parts.dynamic();
+        *self.route_table.write() = parts.merge();
+        let removed = old.iter().filter(|v| !new.contains(v)).copied().collect();
+        let added = new.iter().filter(|v| !old.contains(v)).copied().collect();
+        (removed, added)
+    }
+    /// 替换其他客户端宣告的路由，返回网卡上需要删除和添加的网段
+    pub fn update_advertised(
+        &self,
+        advertised: Vec<(u32, u32, Ipv4Addr)>,
+    ) -> (Vec<(Ipv4Addr, Ipv4Addr)>, Vec<(Ipv4Addr, Ipv4Addr)>) {
+        self.update_dynamic(|parts| parts.advertised = Self::sort(advertised))
+    }
+    /// 设置域名分流规则，name_servers为空时使用系统的dns
+    pub fn set_domain_policy(&self, domains: Vec<(String, Ipv4Addr)>, name_servers: Vec<String>) {
+        let mut parts = self.parts.lock();
+        parts.domains = domains;
+        parts.name_servers = name_servers;
+    }
+    /// 匹配的分流域名的网关和解析使用的dns服务器
+    pub fn domain_route(&self, name: &str) -> Option<(Ipv4Addr, Vec<String>)> {
+        let parts = self.parts.lock();
+        parts
+            .domains
+            .iter()
+            .find(|(domain, _)| policy::domain_match(domain, name))
+            .map(|(_, gateway)| (*gateway, parts.name_servers.clone()))
+    }
+    /// 添加域名解析出的主机路由，返回网卡上需要删除和添加的路由
+    pub fn add_resolved(
+        &self,
+        ips: &[Ipv4Addr],
+        gateway: Ipv4Addr,
+    ) -> (Vec<(Ipv4Addr, Ipv4Addr)>, Vec<(Ipv4Addr, Ipv4Addr)>) {
+        self.update_dynamic(|parts| {
+            for ip in ips {
+                let ip = u32::from(*ip);
+                parts.resolved.retain(|(dest, _, _)| *dest != ip);
+                parts.resolved.push((ip, u32::MAX, gateway));
+            }
+            if parts.resolved.len() > MAX_RESOLVED {
+                let n = parts.resolved.len() - MAX_RESOLVED;
+                parts.resolved.drain(..n);
+            }
+        })
+    }
+    /// 域名分流添加的主机路由
+    pub fn resolved_routes(&self) -> Vec<(Ipv4Addr, Ipv4Addr)> {
+        self.parts
+            .lock()
+            .resolved
+            .iter()
+            .map(|(dest, mask, _)| (Ipv4Addr::from(*dest), Ipv4Addr::from(*mask)))
+            .collect()
     }
     pub fn route(&self, ip: &Ipv4Addr) -> Option<Ipv4Addr> {
-        if self.route_table.is_empty() {
+        let route_table = self.route_table.read();
+        if route_table.is_empty() {
             return None;
         }
         let ip = u32::from_be_bytes(ip.octets());
-        for (dest, mask, gateway) in self.route_table.iter() {
+        for (dest, mask, gateway) in route_table.iter() {
             if *mask & ip == *dest {
                 return Some(*gateway);
             }
@@ -29,6 +165,7 @@ impl ExternalRoute {
     }
     pub fn to_route(&self) -> Vec<(Ipv4Addr, Ipv4Addr)> {
         self.route_table
+            .read()
             .iter()
             .map(|(dest, mask, _)| (Ipv4Addr::from(*dest), Ipv4Addr::from(*mask)))
             .collect::<Vec<(Ipv4Addr, Ipv4Addr)>>()
@@ -38,25 +175,59 @@ impl ExternalRoute {
 // 目标网段，子网掩码
 #[derive(Clone)]
 pub struct AllowExternalRoute {
-    route_table: Arc<Vec<(u32, u32)>>,
+    route_table: Arc<RwLock<Vec<(u32, u32)>>>,
+    // 允许使用转发的来源，为空时不限制
+    sources: Arc<RwLock<Vec<OutSourceRule>>>,
 }
 
 impl AllowExternalRoute {
-    pub fn new(mut route_table: Vec<(u32, u32)>) -> Self {
+    pub fn new(route_table: Vec<(u32, u32)>) -> Self {
+        Self {
+            route_table: Arc::new(RwLock::new(Self::sort(route_table))),
+            sources: Default::default(),
+        }
+    }
+    /// 替换来源限制，返回是否有变化
+    pub fn set_sources(&self, sources: Vec<OutSourceRule>) -> bool {
+        let mut guard = self.sources.write();
+        if *guard == sources {
+            return false;
+        }
+        *guard = sources;
+        true
+    }
+    /// source是对端的虚拟ip，dest是实际目标
+    pub fn allow_from(&self, source: &Ipv4Addr, dest: &Ipv4Addr) -> bool {
+        if !self.allow(dest) {
+            return false;
+        }
+        let sources = self.sources.read();
+        sources.is_empty() || sources.iter().any(|v| v.matches(*source, *dest))
+    }
+    fn sort(mut route_table: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
         for (dest, mask) in &mut route_table {
             *dest = *mask & *dest;
         }
         route_table.sort_by(|(dest1, _), (dest2, _)| dest2.cmp(dest1));
-        Self {
-            route_table: Arc::new(route_table),
+        route_table
+    }
+    /// 替换允许转发的网段，所有克隆共享同一份，返回是否有变化
+    pub fn update(&self, route_table: Vec<(u32, u32)>) -> bool {
+        let route_table = Self::sort(route_table);
+        let mut guard = self.route_table.write();
+        if *guard == route_table {
+            return false;
         }
+        *guard = route_table;
+        true
     }
     pub fn allow(&self, ip: &Ipv4Addr) -> bool {
-        if self.route_table.is_empty() {
+        let route_table = self.route_table.read();
+        if route_table.is_empty() {
             return false;
         }
         let ip = u32::from_be_bytes(ip.octets());
-        for (dest, mask) in self.route_table.iter() {
+        for (dest, mask) in route_table.iter() {
             if *mask & ip == *mask & *dest {
                 return true;
             }
@@ -64,3 +235,50 @@ impl AllowExternalRoute {
         false
     }
 }
+
+#[test]
+fn advertised_route_test() {
+    let gateway = Ipv4Addr::new(10, 26, 0, 3);
+    let lan = u32::from(Ipv4Addr::new(192, 168, 1, 0));
+    let office = u32::from(Ipv4Addr::new(192, 168, 2, 0));
+    let mask = u32::from(Ipv4Addr::new(255, 255, 255, 0));
+    let route = ExternalRoute::new(vec![(lan, mask, Ipv4Addr::new(10, 26, 0, 2))]);
+    let (removed, added) =
+        route.update_advertised(vec![(lan, mask, gateway), (office, mask, gateway)]);
+    assert!(removed.is_empty());
+    assert_eq!(added, vec![(Ipv4Addr::from(office), Ipv4Addr::from(mask))]);
+    // 手动配置的优先
+    assert_eq!(
+        route.route(&Ipv4Addr::new(192, 168, 1, 9)),
+        Some(Ipv4Addr::new(10, 26, 0, 2))
+    );
+    assert_eq!(route.route(&Ipv4Addr::new(192, 168, 2, 9)), Some(gateway));
+    let (removed, added) = route.update_advertised(vec![]);
+    assert_eq!(
+        removed,
+        vec![(Ipv4Addr::from(office), Ipv4Addr::from(mask))]
+    );
+    assert!(added.is_empty());
+    assert_eq!(route.route(&Ipv4Addr::new(192, 168, 2, 9)), None);
+}
+
+#[test]
+fn resolved_route_test() {
+    let gateway = Ipv4Addr::new(10, 26, 0, 3);
+    let route = ExternalRoute::new(vec![]);
+    route.set_domain_policy(vec![("corp.example.com".into(), gateway)], vec![]);
+    assert_eq!(
+        route.domain_route("git.corp.example.com").map(|v| v.0),
+        Some(gateway)
+    );
+    assert!(route.domain_route("example.com").is_none());
+    let ip = Ipv4Addr::new(1, 2, 3, 4);
+    let (removed, added) = route.add_resolved(&[ip], gateway);
+    assert!(removed.is_empty());
+    assert_eq!(added, vec![(ip, Ipv4Addr::BROADCAST)]);
+    assert_eq!(route.route(&ip), Some(gateway));
+    assert_eq!(route.route(&Ipv4Addr::new(1, 2, 3, 5)), None);
+    // 已有的不重复添加
+    let (_, added) = route.add_resolved(&[ip], gateway);
+    assert!(added.is_empty());
+}
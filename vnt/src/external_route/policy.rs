@@ -0,0 +1,189 @@
+use std::fmt::{Display, Formatter};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// 分流的目标，网段直接转成路由，域名在内置dns解析时添加主机路由
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PolicyTarget {
+    Network(u32, u32),
+    // 小写，匹配自身和所有子域名
+    Domain(String),
+}
+
+/// 一条分流规则，格式 `<ip/掩码位数>|domain:<域名> <网关虚拟ip>`，
+/// 例如 `10.1.0.0/16 10.26.0.2`、`domain:corp.example.com 10.26.0.2`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RoutePolicy {
+    pub target: PolicyTarget,
+    pub gateway: Ipv4Addr,
+}
+
+impl FromStr for RoutePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |e: &str| {
+            format!(
+                "'{}' {}, example: 10.1.0.0/16 10.26.0.2, domain:corp.example.com 10.26.0.2",
+                s, e
+            )
+        };
+        let mut items = s.split_whitespace();
+        let target = match items.next() {
+            Some(v) => v,
+            None => return Err(err("missing target")),
+        };
+        let gateway = match items.next() {
+            Some(v) => Ipv4Addr::from_str(v).map_err(|_| err("invalid gateway"))?,
+            None => return Err(err("missing gateway")),
+        };
+        if items.next().is_some() {
+            return Err(err("too many items"));
+        }
+        if gateway.is_unspecified() || gateway.is_broadcast() || gateway.is_multicast() {
+            return Err(err("invalid gateway"));
+        }
+        let target = if let Some(domain) = target.strip_prefix("domain:") {
+            let domain = domain.trim_matches('.').to_lowercase();
+            if domain.is_empty() || domain.split('.').any(|v| v.is_empty()) {
+                return Err(err("invalid domain"));
+            }
+            PolicyTarget::Domain(domain)
+        } else if target.starts_with("process:") {
+            // 按进程分流需要系统的策略路由，没有跨平台的实现
+            return Err(err("process rules are not supported"));
+        } else {
+            let (network, mask) = parse_network(target).map_err(err)?;
+            PolicyTarget::Network(network, mask)
+        };
+        Ok(RoutePolicy { target, gateway })
+    }
+}
+
+impl Display for RoutePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.target {
+            PolicyTarget::Network(network, mask) => write!(
+                f,
+                "{}/{} {}",
+                Ipv4Addr::from(*network),
+                mask.count_ones(),
+                self.gateway
+            ),
+            PolicyTarget::Domain(domain) => write!(f, "domain:{} {}", domain, self.gateway),
+        }
+    }
+}
+
+/// ip/掩码位数，省略掩码位数时为32
+pub(crate) fn parse_network(s: &str) -> Result<(u32, u32), &'static str> {
+    let (ip, bits) = s.split_once('/').unwrap_or((s, "32"));
+    let ip = Ipv4Addr::from_str(ip).map_err(|_| "invalid ip")?;
+    let bits = match u32::from_str(bits) {
+        Ok(bits) if bits <= 32 => bits,
+        _ => return Err("invalid mask"),
+    };
+    let mask = if bits == 0 {
+        0
+    } else {
+        u32::MAX << (32 - bits)
+    };
+    Ok((u32::from(ip) & mask, mask))
+}
+
+/// 允许通过本机转发(-o)的来源，格式 `<来源虚拟ip/掩码位数> [目标ip/掩码位数]`，
+/// 例如 `10.26.0.5 192.168.1.0/24`，省略目标时可以访问全部允许转发的网段
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct OutSourceRule {
+    pub source: u32,
+    pub source_mask: u32,
+    pub dest: u32,
+    pub dest_mask: u32,
+}
+
+impl FromStr for OutSourceRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |e: &str| {
+            format!(
+                "'{}' {}, example: 10.26.0.5 192.168.1.0/24, 10.26.0.0/28",
+                s, e
+            )
+        };
+        let mut items = s.split_whitespace();
+        let (source, source_mask) = match items.next() {
+            Some(v) => parse_network(v).map_err(err)?,
+            None => return Err(err("missing source")),
+        };
+        let (dest, dest_mask) = match items.next() {
+            Some(v) => parse_network(v).map_err(err)?,
+            None => (0, 0),
+        };
+        if items.next().is_some() {
+            return Err(err("too many items"));
+        }
+        Ok(OutSourceRule {
+            source,
+            source_mask,
+            dest,
+            dest_mask,
+        })
+    }
+}
+
+impl Display for OutSourceRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} {}/{}",
+            Ipv4Addr::from(self.source),
+            self.source_mask.count_ones(),
+            Ipv4Addr::from(self.dest),
+            self.dest_mask.count_ones()
+        )
+    }
+}
+
+impl OutSourceRule {
+    pub fn matches(&self, source: Ipv4Addr, dest: Ipv4Addr) -> bool {
+        u32::from(source) & self.source_mask == self.source
+            && u32::from(dest) & self.dest_mask == self.dest
+    }
+}
+
+/// name是查询的域名，不区分大小写
+pub(crate) fn domain_match(domain: &str, name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    if name.len() < domain.len() {
+        return false;
+    }
+    let (prefix, suffix) = name.split_at(name.len() - domain.len());
+    suffix.eq_ignore_ascii_case(domain) && (prefix.is_empty() || prefix.ends_with('.'))
+}
+
+#[test]
+fn route_policy_test() {
+    let policy = RoutePolicy::from_str("10.1.2.3/16 10.26.0.2").unwrap();
+    assert_eq!(
+        policy.target,
+        PolicyTarget::Network(u32::from(Ipv4Addr::new(10, 1, 0, 0)), 0xffff0000)
+    );
+    assert_eq!(policy.to_string(), "10.1.0.0/16 10.26.0.2");
+    let policy = RoutePolicy::from_str("domain:Corp.Example.com 10.26.0.2").unwrap();
+    assert_eq!(
+        policy.target,
+        PolicyTarget::Domain("corp.example.com".into())
+    );
+    assert!(RoutePolicy::from_str("process:chrome 10.26.0.2").is_err());
+    assert!(RoutePolicy::from_str("10.1.0.0/16").is_err());
+    assert!(domain_match("corp.example.com", "git.CORP.example.com."));
+    assert!(domain_match("corp.example.com", "corp.example.com"));
+    assert!(!domain_match("corp.example.com", "xcorp.example.com"));
+    let rule = OutSourceRule::from_str("10.26.0.5 192.168.1.0/24").unwrap();
+    assert!(rule.matches(Ipv4Addr::new(10, 26, 0, 5), Ipv4Addr::new(192, 168, 1, 9)));
+    assert!(!rule.matches(Ipv4Addr::new(10, 26, 0, 6), Ipv4Addr::new(192, 168, 1, 9)));
+    assert!(!rule.matches(Ipv4Addr::new(10, 26, 0, 5), Ipv4Addr::new(192, 168, 2, 9)));
+    let rule = OutSourceRule::from_str("10.26.0.0/28").unwrap();
+    assert!(rule.matches(Ipv4Addr::new(10, 26, 0, 15), Ipv4Addr::new(8, 8, 8, 8)));
+}
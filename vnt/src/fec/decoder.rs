@@ -0,0 +1,257 @@
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use crate::fec::{MAX_DATA_SHARDS, MAX_PARITY_SHARDS};
+use crate::protocol::fec_packet::FecPacket;
+use crate::protocol::{fec_packet, NetPacket, Protocol};
+
+// 分组超过这个时间还没恢复就放弃
+const GROUP_TIMEOUT: Duration = Duration::from_secs(2);
+// 每个来源同时跟踪的分组数
+const MAX_GROUPS_PER_SOURCE: usize = 32;
+// 所有来源同时跟踪的分组数，每个分组最多缓存96个分片
+const MAX_GROUPS: usize = 256;
+
+#[derive(Default)]
+struct GroupDecoder {
+    // 数据包数和冗余包数，收到冗余包后才知道
+    data_shards: usize,
+    parity_shards: usize,
+    data: Vec<Option<Vec<u8>>>,
+    parity: Vec<Option<Vec<u8>>>,
+    // 已经恢复过了
+    done: bool,
+}
+
+pub struct FecDecoder {
+    inner: Mutex<DecoderInner>,
+}
+
+struct DecoderInner {
+    groups: FnvHashMap<(Ipv4Addr, u16), (Instant, GroupDecoder)>,
+    codecs: FnvHashMap<(usize, usize), ReedSolomon>,
+    last_clear: Instant,
+}
+
+impl FecDecoder {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(DecoderInner {
+                groups: FnvHashMap::default(),
+                codecs: FnvHashMap::default(),
+                last_clear: Instant::now(),
+            }),
+        }
+    }
+    pub fn decode<B: AsRef<[u8]>>(&self, net_packet: &NetPacket<B>) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        let fec_packet = match FecPacket::new(net_packet.payload()) {
+            Ok(fec_packet) => fec_packet,
+            Err(_) => return out,
+        };
+        let index = fec_packet.index() as usize;
+        let now = Instant::now();
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        if now.duration_since(inner.last_clear) > Duration::from_secs(1) {
+            inner.last_clear = now;
+            inner
+                .groups
+                .retain(|_, (time, _)| now.duration_since(*time) < GROUP_TIMEOUT);
+        }
+        let key = (net_packet.source(), fec_packet.group());
+        if !inner.groups.contains_key(&key) {
+            Self::evict(&mut inner.groups, key.0);
+        }
+        let (_, group) = inner
+            .groups
+            .entry(key)
+            .or_insert_with(|| (now, GroupDecoder::default()));
+        match fec_packet::Protocol::from(net_packet.transport_protocol()) {
+            fec_packet::Protocol::Data => {
+                if index >= MAX_DATA_SHARDS as usize {
+                    return out;
+                }
+                if group.data.len() <= index {
+                    group.data.resize(index + 1, None);
+                }
+                if group.data[index].is_some() {
+                    // 重复的包或者已经恢复过
+                    return out;
+                }
+                let shard = fec_packet.shard();
+                if let Ok(packet) = NetPacket::new(shard) {
+                    // 不处理嵌套的fec包
+                    if packet.protocol() != Protocol::Fec {
+                        out.push(shard.to_vec());
+                    }
+                }
+                group.data[index] = Some(shard.to_vec());
+            }
+            fec_packet::Protocol::Parity => {
+                let data_shards = fec_packet.data_shards() as usize;
+                let parity_shards = fec_packet.parity_shards() as usize;
+                if data_shards == 0
+                    || data_shards > MAX_DATA_SHARDS as usize
+                    || parity_shards == 0
+                    || parity_shards > MAX_PARITY_SHARDS as usize
+                    || index >= parity_shards
+                    // 至少要有2字节长度和1字节数据
+                    || fec_packet.shard().len() < 3
+                {
+                    return out;
+                }
+                if group.data_shards == 0 {
+                    group.data_shards = data_shards;
+                    group.parity_shards = parity_shards;
+                } else if group.data_shards != data_shards || group.parity_shards != parity_shards {
+                    return out;
+                }
+                if group.parity.len() <= index {
+                    group.parity.resize(index + 1, None);
+                }
+                if group.parity[index].is_none() {
+                    group.parity[index] = Some(fec_packet.shard().to_vec());
+                }
+            }
+            fec_packet::Protocol::Unknown(_) => return out,
+        }
+        if !group.done && group.data_shards > 0 {
+            Self::reconstruct(&mut inner.codecs, group, &mut out);
+        }
+        out
+    }
+    /// 分组数达到上限时丢弃最早的分组，避免对端用大量分组号占用内存
+    fn evict(groups: &mut FnvHashMap<(Ipv4Addr, u16), (Instant, GroupDecoder)>, source: Ipv4Addr) {
+        let source_count = groups.keys().filter(|(ip, _)| *ip == source).count();
+        let oldest = if source_count >= MAX_GROUPS_PER_SOURCE {
+            groups
+                .iter()
+                .filter(|((ip, _), _)| *ip == source)
+                .min_by_key(|(_, (time, _))| *time)
+                .map(|(key, _)| *key)
+        } else if groups.len() >= MAX_GROUPS {
+            groups
+                .iter()
+                .min_by_key(|(_, (time, _))| *time)
+                .map(|(key, _)| *key)
+        } else {
+            None
+        };
+        if let Some(key) = oldest {
+            groups.remove(&key);
+        }
+    }
+    /// 收到的包数达到数据包数时恢复丢失的数据包
+    fn reconstruct(
+        codecs: &mut FnvHashMap<(usize, usize), ReedSolomon>,
+        group: &mut GroupDecoder,
+        out: &mut Vec<Vec<u8>>,
+    ) {
+        let data_shards = group.data_shards;
+        let parity_shards = group.parity_shards;
+        group.data.resize(data_shards.max(group.data.len()), None);
+        group.parity.resize(parity_shards, None);
+        let data_count = group.data[..data_shards]
+            .iter()
+            .filter(|v| v.is_some())
+            .count();
+        if data_count == data_shards {
+            group.done = true;
+            return;
+        }
+        let parity_count = group.parity.iter().filter(|v| v.is_some()).count();
+        if data_count + parity_count < data_shards {
+            return;
+        }
+        group.done = true;
+        let shard_len = match group.parity.iter().flatten().next() {
+            Some(v) => v.len(),
+            None => return,
+        };
+        let mut shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(data_shards + parity_shards);
+        for data in &group.data[..data_shards] {
+            match data {
+                Some(data) => {
+                    if data.len() + 2 > shard_len {
+                        return;
+                    }
+                    let mut shard = vec![0u8; shard_len];
+                    shard[..2].copy_from_slice(&(data.len() as u16).to_be_bytes());
+                    shard[2..2 + data.len()].copy_from_slice(data);
+                    shards.push(Some(shard));
+                }
+                None => shards.push(None),
+            }
+        }
+        for parity in &group.parity {
+            match parity {
+                Some(parity) if parity.len() != shard_len => return,
+                _ => shards.push(parity.clone()),
+            }
+        }
+        let codec = codecs
+            .entry((data_shards, parity_shards))
+            .or_insert_with(|| ReedSolomon::new(data_shards, parity_shards).unwrap());
+        if let Err(e) = codec.reconstruct_data(&mut shards) {
+            log::warn!("fec reconstruct {:?}", e);
+            return;
+        }
+        for (index, shard) in shards.into_iter().take(data_shards).enumerate() {
+            if group.data[index].is_some() {
+                continue;
+            }
+            let shard = match shard {
+                Some(shard) => shard,
+                None => continue,
+            };
+            let len = u16::from_be_bytes([shard[0], shard[1]]) as usize;
+            if len + 2 > shard_len {
+                continue;
+            }
+            let packet = shard[2..2 + len].to_vec();
+            if let Ok(net_packet) = NetPacket::new(&packet) {
+                if net_packet.protocol() != Protocol::Fec {
+                    out.push(packet.clone());
+                }
+            }
+            group.data[index] = Some(packet);
+        }
+    }
+}
+
+#[test]
+fn test_evict() {
+    let mut groups = FnvHashMap::default();
+    let now = Instant::now();
+    let source = Ipv4Addr::new(10, 26, 0, 2);
+    for i in 0..MAX_GROUPS_PER_SOURCE as u16 {
+        FecDecoder::evict(&mut groups, source);
+        groups.insert(
+            (source, i),
+            (
+                now + Duration::from_millis(i as u64),
+                GroupDecoder::default(),
+            ),
+        );
+    }
+    // 同一来源达到上限时丢弃它最早的分组
+    FecDecoder::evict(&mut groups, source);
+    assert_eq!(groups.len(), MAX_GROUPS_PER_SOURCE - 1);
+    assert!(!groups.contains_key(&(source, 0)));
+    // 总数达到上限时丢弃所有来源中最早的分组
+    for i in 0..MAX_GROUPS as u16 {
+        let other = Ipv4Addr::new(10, 26, 1, (i / 16) as u8);
+        FecDecoder::evict(&mut groups, other);
+        groups.insert(
+            (other, i),
+            (now + Duration::from_secs(1), GroupDecoder::default()),
+        );
+    }
+    assert_eq!(groups.len(), MAX_GROUPS);
+    assert!(!groups.contains_key(&(source, 1)));
+}
@@ -0,0 +1,170 @@
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use crate::fec::FecConfig;
+use crate::protocol::fec_packet::{FecPacket, FEC_HEAD_LEN};
+use crate::protocol::{fec_packet, NetPacket, Protocol, HEAD_LEN};
+
+// 分组未满时，超过这个时间后下一个包到来时结束分组，避免冗余包等待太久
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(50);
+// 长时间没有数据的对端，清除分组状态
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct GroupEncoder {
+    group: u16,
+    // 分组内数据包(加密后的完整包)
+    shards: Vec<Vec<u8>>,
+    // 最后一个包的头部，冗余包使用相同的来源和目标
+    head: [u8; HEAD_LEN],
+    start: Instant,
+    last: Instant,
+}
+
+pub struct FecEncoder {
+    config: FecConfig,
+    inner: Mutex<EncoderInner>,
+}
+
+struct EncoderInner {
+    groups: FnvHashMap<Ipv4Addr, GroupEncoder>,
+    codecs: FnvHashMap<(usize, usize), ReedSolomon>,
+    last_clear: Instant,
+}
+
+impl FecEncoder {
+    pub fn new(config: FecConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(EncoderInner {
+                groups: FnvHashMap::default(),
+                codecs: FnvHashMap::default(),
+                last_clear: Instant::now(),
+            }),
+        }
+    }
+    pub fn encode<B: AsRef<[u8]>>(
+        &self,
+        net_packet: &NetPacket<B>,
+        dest: &Ipv4Addr,
+    ) -> Vec<Vec<u8>> {
+        let now = Instant::now();
+        let data_shards = self.config.data_shards as usize;
+        let mut out = Vec::with_capacity(2);
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        if now.duration_since(inner.last_clear) > IDLE_TIMEOUT {
+            inner.last_clear = now;
+            inner
+                .groups
+                .retain(|_, group| now.duration_since(group.last) < IDLE_TIMEOUT);
+        }
+        let group = inner.groups.entry(*dest).or_insert_with(|| GroupEncoder {
+            group: rand::random(),
+            shards: Vec::with_capacity(data_shards),
+            head: [0; HEAD_LEN],
+            start: now,
+            last: now,
+        });
+        if !group.shards.is_empty() && now.duration_since(group.start) > FLUSH_TIMEOUT {
+            Self::flush(&self.config, &mut inner.codecs, group, &mut out);
+        }
+        if group.shards.is_empty() {
+            group.start = now;
+        }
+        group.last = now;
+        group.head.copy_from_slice(&net_packet.buffer()[..HEAD_LEN]);
+        out.push(wrap(
+            &group.head,
+            fec_packet::Protocol::Data,
+            group.group,
+            group.shards.len() as u8,
+            self.config.data_shards,
+            self.config.parity_shards,
+            net_packet.buffer(),
+        ));
+        group.shards.push(net_packet.buffer().to_vec());
+        if group.shards.len() >= data_shards {
+            Self::flush(&self.config, &mut inner.codecs, group, &mut out);
+        }
+        out
+    }
+    /// 结束分组，生成冗余包
+    fn flush(
+        config: &FecConfig,
+        codecs: &mut FnvHashMap<(usize, usize), ReedSolomon>,
+        group: &mut GroupEncoder,
+        out: &mut Vec<Vec<u8>>,
+    ) {
+        let data_shards = group.shards.len();
+        // 分组未满时按比例减少冗余包，至少一个
+        let parity_shards =
+            ((config.parity_shards as usize * data_shards + config.data_shards as usize - 1)
+                / config.data_shards as usize)
+                .max(1);
+        let shard_len = group.shards.iter().map(|v| v.len()).max().unwrap_or(0) + 2;
+        // 数据分片 = 2字节长度+数据+补0
+        let data: Vec<Vec<u8>> = group
+            .shards
+            .drain(..)
+            .map(|v| {
+                let mut shard = vec![0u8; shard_len];
+                shard[..2].copy_from_slice(&(v.len() as u16).to_be_bytes());
+                shard[2..2 + v.len()].copy_from_slice(&v);
+                shard
+            })
+            .collect();
+        let mut parity = vec![vec![0u8; shard_len]; parity_shards];
+        let codec = codecs
+            .entry((data_shards, parity_shards))
+            .or_insert_with(|| ReedSolomon::new(data_shards, parity_shards).unwrap());
+        match codec.encode_sep(&data, &mut parity) {
+            Ok(_) => {
+                for (index, shard) in parity.iter().enumerate() {
+                    out.push(wrap(
+                        &group.head,
+                        fec_packet::Protocol::Parity,
+                        group.group,
+                        index as u8,
+                        data_shards as u8,
+                        parity_shards as u8,
+                        shard,
+                    ));
+                }
+            }
+            Err(e) => {
+                log::warn!("fec encode {:?}", e);
+            }
+        }
+        group.group = group.group.wrapping_add(1);
+    }
+}
+
+fn wrap(
+    head: &[u8; HEAD_LEN],
+    protocol: fec_packet::Protocol,
+    group: u16,
+    index: u8,
+    data_shards: u8,
+    parity_shards: u8,
+    shard: &[u8],
+) -> Vec<u8> {
+    let mut buf = vec![0u8; HEAD_LEN + FEC_HEAD_LEN + shard.len()];
+    buf[..HEAD_LEN].copy_from_slice(head);
+    let mut net_packet = NetPacket::new(&mut buf).unwrap();
+    net_packet.set_encrypt_flag(false);
+    net_packet.set_extension_flag(false);
+    net_packet.set_gateway_flag(false);
+    net_packet.set_protocol(Protocol::Fec);
+    net_packet.set_transport_protocol(protocol.into());
+    let mut fec_packet = FecPacket::new(net_packet.payload_mut()).unwrap();
+    fec_packet.set_group(group);
+    fec_packet.set_index(index);
+    fec_packet.set_data_shards(data_shards);
+    fec_packet.set_parity_shards(parity_shards);
+    fec_packet.shard_mut().copy_from_slice(shard);
+    buf
+}
@@ -0,0 +1,154 @@
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+#[cfg(feature = "fec")]
+use crate::fec::decoder::FecDecoder;
+#[cfg(feature = "fec")]
+use crate::fec::encoder::FecEncoder;
+use crate::protocol::NetPacket;
+
+#[cfg(feature = "fec")]
+mod decoder;
+#[cfg(feature = "fec")]
+mod encoder;
+
+pub const MAX_DATA_SHARDS: u8 = 64;
+pub const MAX_PARITY_SHARDS: u8 = 32;
+
+/// 前向纠错参数，每data_shards个数据包生成parity_shards个冗余包，
+/// 同一分组内丢失不超过parity_shards个包时可以恢复
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FecConfig {
+    pub data_shards: u8,
+    pub parity_shards: u8,
+}
+
+impl FromStr for FecConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("not match '{}', example: 10:3", s);
+        let (data, parity) = s.trim().split_once(':').ok_or_else(err)?;
+        let data_shards = data.trim().parse::<u8>().map_err(|_| err())?;
+        let parity_shards = parity.trim().parse::<u8>().map_err(|_| err())?;
+        if data_shards == 0 || data_shards > MAX_DATA_SHARDS {
+            return Err(format!("data shards range 1-{}", MAX_DATA_SHARDS));
+        }
+        if parity_shards == 0 || parity_shards > MAX_PARITY_SHARDS {
+            return Err(format!("parity shards range 1-{}", MAX_PARITY_SHARDS));
+        }
+        Ok(FecConfig {
+            data_shards,
+            parity_shards,
+        })
+    }
+}
+
+/// 前向纠错，只对支持fec的对端启用，对端能力见ChannelContext::peer_features
+pub struct Fec {
+    config: Option<FecConfig>,
+    #[cfg(feature = "fec")]
+    encoder: Option<FecEncoder>,
+    #[cfg(feature = "fec")]
+    decoder: FecDecoder,
+}
+
+impl Fec {
+    pub fn new(config: Option<FecConfig>) -> Self {
+        Self {
+            config,
+            #[cfg(feature = "fec")]
+            encoder: config.map(FecEncoder::new),
+            #[cfg(feature = "fec")]
+            decoder: FecDecoder::new(),
+        }
+    }
+    pub fn config(&self) -> Option<FecConfig> {
+        self.config
+    }
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+    /// 编码要发给dest的包，返回需要发送的包(数据包和可能生成的冗余包)，
+    /// 返回None表示没有开启fec，原样发送
+    pub fn encode<B: AsRef<[u8]>>(
+        &self,
+        net_packet: &NetPacket<B>,
+        dest: &Ipv4Addr,
+    ) -> Option<Vec<Vec<u8>>> {
+        #[cfg(feature = "fec")]
+        if let Some(encoder) = &self.encoder {
+            return Some(encoder.encode(net_packet, dest));
+        }
+        #[cfg(not(feature = "fec"))]
+        let _ = (net_packet, dest);
+        None
+    }
+    /// 解码fec包，返回需要继续处理的原始包(包括恢复出来的包)
+    pub fn decode<B: AsRef<[u8]>>(&self, net_packet: &NetPacket<B>) -> Vec<Vec<u8>> {
+        #[cfg(feature = "fec")]
+        {
+            self.decoder.decode(net_packet)
+        }
+        #[cfg(not(feature = "fec"))]
+        {
+            let _ = net_packet;
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(feature = "fec")]
+#[test]
+fn test_fec() {
+    use crate::protocol::fec_packet::{self, FecPacket, FEC_HEAD_LEN};
+    use crate::protocol::{Protocol, HEAD_LEN};
+    let config = FecConfig::from_str("4:2").unwrap();
+    let encoder = FecEncoder::new(config);
+    let decoder = FecDecoder::new();
+    let src = Ipv4Addr::new(10, 26, 0, 2);
+    let dest = Ipv4Addr::new(10, 26, 0, 3);
+    let mut packets = Vec::new();
+    let mut out = Vec::new();
+    for i in 0..4 {
+        let mut buf = vec![i as u8; HEAD_LEN + 10 + i * 7];
+        let mut net_packet = NetPacket::new(&mut buf).unwrap();
+        net_packet.set_default_version();
+        net_packet.set_protocol(Protocol::IpTurn);
+        net_packet.first_set_ttl(5);
+        net_packet.set_source(src);
+        net_packet.set_destination(dest);
+        out.extend(encoder.encode(&net_packet, &dest));
+        packets.push(buf);
+    }
+    // 4个数据包+2个冗余包
+    assert_eq!(out.len(), 6);
+    // 丢失第1、3个数据包
+    let mut recv = Vec::new();
+    for (index, buf) in out.iter().enumerate() {
+        if index == 0 || index == 2 {
+            continue;
+        }
+        let net_packet = NetPacket::new(buf).unwrap();
+        assert_eq!(net_packet.protocol(), Protocol::Fec);
+        recv.extend(decoder.decode(&net_packet));
+    }
+    recv.sort();
+    packets.sort();
+    assert_eq!(recv, packets);
+
+    // 对端发来过短的冗余包，不能恢复出不完整的分片
+    let mut buf = vec![0u8; HEAD_LEN + FEC_HEAD_LEN + 1];
+    let mut net_packet = NetPacket::new(&mut buf).unwrap();
+    net_packet.set_default_version();
+    net_packet.set_protocol(Protocol::Fec);
+    net_packet.set_transport_protocol(fec_packet::Protocol::Parity.into());
+    net_packet.first_set_ttl(5);
+    net_packet.set_source(src);
+    net_packet.set_destination(dest);
+    let mut fec = FecPacket::new(net_packet.payload_mut()).unwrap();
+    fec.set_data_shards(1);
+    fec.set_parity_shards(1);
+    assert!(decoder.decode(&NetPacket::new(&buf).unwrap()).is_empty());
+}
@@ -3,23 +3,41 @@ use crate::handle::PeerDeviceStatus;
 use rsa::RsaPublicKey;
 use std::fmt::{Display, Formatter};
 use std::io;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 
-#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+#[cfg(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
 #[derive(Debug)]
 pub struct DeviceInfo {
     pub name: String,
     pub version: String,
 }
 
-#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+#[cfg(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
 impl Display for DeviceInfo {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!("name={} ,version={}", self.name, self.version))
     }
 }
 
-#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+#[cfg(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
 impl DeviceInfo {
     pub fn new(name: String, version: String) -> Self {
         return Self { name, version };
@@ -172,6 +190,19 @@ pub enum ErrorType {
     IpAlreadyExists,
     InvalidIp,
     LocalIpExists,
+    IdentityError,
+    InviteError,
+    TotpError,
+    /// 创建虚拟网卡失败
+    DeviceError,
+    /// 看门狗判定无法连接服务端
+    ServerUnreachable,
+    /// 看门狗无法恢复
+    WatchdogExpired,
+    /// 被服务端管理员踢出，会重新注册
+    Kicked,
+    /// 被服务端管理员封禁
+    Banned,
     Unknown,
 }
 
@@ -184,6 +215,14 @@ impl Into<u8> for ErrorType {
             ErrorType::IpAlreadyExists => 4,
             ErrorType::InvalidIp => 5,
             ErrorType::LocalIpExists => 6,
+            ErrorType::IdentityError => 7,
+            ErrorType::InviteError => 8,
+            ErrorType::TotpError => 9,
+            ErrorType::DeviceError => 10,
+            ErrorType::ServerUnreachable => 11,
+            ErrorType::WatchdogExpired => 12,
+            ErrorType::Kicked => 13,
+            ErrorType::Banned => 14,
             ErrorType::Unknown => 255,
         }
     }
@@ -195,7 +234,20 @@ pub struct DeviceConfig {
     #[cfg(target_os = "windows")]
     pub tap: bool,
     #[cfg(feature = "integrated_tun")]
-    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    #[cfg(target_os = "windows")]
+    pub wintun_path: Option<String>,
+    // 二层模式
+    #[cfg(feature = "integrated_tun")]
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    pub layer2: bool,
+    #[cfg(feature = "integrated_tun")]
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
     pub device_name: Option<String>,
     //虚拟网卡mtu值
     pub mtu: u32,
@@ -207,6 +259,9 @@ pub struct DeviceConfig {
     pub virtual_gateway: Ipv4Addr,
     //虚拟网段
     pub virtual_network: Ipv4Addr,
+    //本机虚拟IPv6及前缀长度
+    pub virtual_ipv6: Ipv6Addr,
+    pub virtual_ipv6_prefix: u8,
     // 额外的路由
     pub external_route: Vec<(Ipv4Addr, Ipv4Addr)>,
 }
@@ -217,13 +272,27 @@ impl DeviceConfig {
         #[cfg(target_os = "windows")]
         tap: bool,
         #[cfg(feature = "integrated_tun")]
-        #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+        #[cfg(target_os = "windows")]
+        wintun_path: Option<String>,
+        #[cfg(feature = "integrated_tun")]
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
+        layer2: bool,
+        #[cfg(feature = "integrated_tun")]
+        #[cfg(any(
+            target_os = "windows",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        ))]
         device_name: Option<String>,
         mtu: u32,
         virtual_ip: Ipv4Addr,
         virtual_netmask: Ipv4Addr,
         virtual_gateway: Ipv4Addr,
         virtual_network: Ipv4Addr,
+        virtual_ipv6: Ipv6Addr,
+        virtual_ipv6_prefix: u8,
         external_route: Vec<(Ipv4Addr, Ipv4Addr)>,
     ) -> Self {
         Self {
@@ -231,13 +300,27 @@ impl DeviceConfig {
             #[cfg(target_os = "windows")]
             tap,
             #[cfg(feature = "integrated_tun")]
-            #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+            #[cfg(target_os = "windows")]
+            wintun_path,
+            #[cfg(feature = "integrated_tun")]
+            #[cfg(any(target_os = "windows", target_os = "linux"))]
+            layer2,
+            #[cfg(feature = "integrated_tun")]
+            #[cfg(any(
+                target_os = "windows",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd"
+            ))]
             device_name,
             mtu,
             virtual_ip,
             virtual_netmask,
             virtual_gateway,
             virtual_network,
+            virtual_ipv6,
+            virtual_ipv6_prefix,
             external_route,
         }
     }
@@ -246,8 +329,13 @@ impl DeviceConfig {
 impl Display for DeviceConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!(
-            "ip={} ,netmask={} ,gateway={}, external_route={:?}",
-            self.virtual_ip, self.virtual_netmask, self.virtual_gateway, self.external_route
+            "ip={} ,netmask={} ,gateway={}, ipv6={}/{}, external_route={:?}",
+            self.virtual_ip,
+            self.virtual_netmask,
+            self.virtual_gateway,
+            self.virtual_ipv6,
+            self.virtual_ipv6_prefix,
+            self.external_route
         ))
     }
 }
@@ -285,12 +373,86 @@ impl Display for PeerClientInfo {
     }
 }
 
+/// 状态变化的事件，由定时检查产生，最多有1秒的延迟
+#[derive(Clone, Debug)]
+pub enum VntEvent {
+    /// 连上服务端，包括断开后重连
+    ServerConnected {
+        server: SocketAddr,
+    },
+    ServerDisconnected,
+    /// 服务端分配的虚拟ip变化
+    IpChanged {
+        old: Ipv4Addr,
+        new: Ipv4Addr,
+    },
+    PeerOnline {
+        virtual_ip: Ipv4Addr,
+        name: String,
+    },
+    PeerOffline {
+        virtual_ip: Ipv4Addr,
+        name: String,
+    },
+    /// 和对端建立了直连
+    DirectLinkUp {
+        virtual_ip: Ipv4Addr,
+        addr: SocketAddr,
+    },
+    /// 和对端的直连都失效了，之后经过中继
+    DirectLinkDown {
+        virtual_ip: Ipv4Addr,
+    },
+}
+
+impl VntEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VntEvent::ServerConnected { .. } => "server_connected",
+            VntEvent::ServerDisconnected => "server_disconnected",
+            VntEvent::IpChanged { .. } => "ip_changed",
+            VntEvent::PeerOnline { .. } => "peer_online",
+            VntEvent::PeerOffline { .. } => "peer_offline",
+            VntEvent::DirectLinkUp { .. } => "direct_link_up",
+            VntEvent::DirectLinkDown { .. } => "direct_link_down",
+        }
+    }
+}
+
+impl Display for VntEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VntEvent::ServerConnected { server } => write!(f, "{} server={}", self.name(), server),
+            VntEvent::ServerDisconnected => write!(f, "{}", self.name()),
+            VntEvent::IpChanged { old, new } => {
+                write!(f, "{} old={} new={}", self.name(), old, new)
+            }
+            VntEvent::PeerOnline { virtual_ip, name }
+            | VntEvent::PeerOffline { virtual_ip, name } => {
+                write!(f, "{} ip={} name={}", self.name(), virtual_ip, name)
+            }
+            VntEvent::DirectLinkUp { virtual_ip, addr } => {
+                write!(f, "{} ip={} addr={}", self.name(), virtual_ip, addr)
+            }
+            VntEvent::DirectLinkDown { virtual_ip } => {
+                write!(f, "{} ip={}", self.name(), virtual_ip)
+            }
+        }
+    }
+}
+
 pub trait VntCallback: Clone + Send + Sync + 'static {
     /// 启动成功
     fn success(&self) {}
 
     /// 创建网卡的信息
-    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
     #[cfg(feature = "integrated_tun")]
     fn create_tun(&self, _info: DeviceInfo) {}
     /// 连接
@@ -305,12 +467,16 @@ pub trait VntCallback: Clone + Send + Sync + 'static {
     }
     #[cfg(not(feature = "integrated_tun"))]
     fn create_device(&self, _info: DeviceConfig) {}
+    /// 按info中的地址、mtu和路由建立VpnService，返回fd，
+    /// 返回0表示稍后通过Vnt::attach_tun_fd传入
     #[cfg(target_os = "android")]
     #[cfg(feature = "integrated_tun")]
     fn generate_tun(&self, _info: DeviceConfig) -> usize {
         0
     }
     fn peer_client_list(&self, _info: Vec<PeerClientInfo>) {}
+    /// 对端上下线、直连建立和断开、服务端重连等事件
+    fn event(&self, _event: VntEvent) {}
     /// 异常信息
     fn error(&self, _info: ErrorInfo) {}
     /// 服务停止
@@ -9,6 +9,8 @@ use parking_lot::Mutex;
 use protobuf::Message;
 
 use crate::channel::context::ChannelContext;
+#[cfg(feature = "pq")]
+use crate::cipher::HybridKem;
 #[cfg(feature = "server_encrypt")]
 use crate::cipher::RsaCipher;
 use crate::handle::{GATEWAY_IP, SELF_IP};
@@ -24,10 +26,17 @@ pub struct Handshake {
     time: Arc<AtomicCell<Instant>>,
     #[cfg(feature = "server_encrypt")]
     rsa_cipher: Arc<Mutex<Option<RsaCipher>>>,
+    // 混合密钥交换使用的密钥，为None时只使用rsa
+    #[cfg(feature = "pq")]
+    kem: Option<Arc<HybridKem>>,
+    // 和服务端混合密钥交换得到的共享密钥
+    #[cfg(feature = "pq")]
+    kem_shared: Arc<AtomicCell<Option<[u8; 32]>>>,
 }
 impl Handshake {
     pub fn new(
         #[cfg(feature = "server_encrypt")] rsa_cipher: Arc<Mutex<Option<RsaCipher>>>,
+        #[cfg(feature = "pq")] pq_kem: bool,
     ) -> Self {
         Handshake {
             time: Arc::new(AtomicCell::new(
@@ -37,8 +46,35 @@ impl Handshake {
             )),
             #[cfg(feature = "server_encrypt")]
             rsa_cipher,
+            #[cfg(feature = "pq")]
+            kem: if pq_kem {
+                Some(Arc::new(HybridKem::generate()))
+            } else {
+                None
+            },
+            #[cfg(feature = "pq")]
+            kem_shared: Arc::new(AtomicCell::new(None)),
         }
     }
+    /// 处理服务端握手响应中的密文，服务端不支持时密文为空，退回只使用rsa
+    #[cfg(feature = "pq")]
+    pub fn kem_response(&self, ciphertext: &[u8]) -> anyhow::Result<()> {
+        let kem = match &self.kem {
+            Some(kem) => kem,
+            None => return Ok(()),
+        };
+        if ciphertext.is_empty() {
+            self.kem_shared.store(None);
+            log::warn!("服务端不支持混合密钥交换,只使用rsa加密握手");
+            return Ok(());
+        }
+        self.kem_shared.store(Some(kem.decapsulate(ciphertext)?));
+        Ok(())
+    }
+    #[cfg(feature = "pq")]
+    pub fn kem_shared(&self) -> Option<[u8; 32]> {
+        self.kem_shared.load()
+    }
     pub fn send(&self, context: &ChannelContext, secret: bool, addr: SocketAddr) -> io::Result<()> {
         let last = self.time.load();
         //短时间不重复发送
@@ -60,6 +96,12 @@ impl Handshake {
         if let Some(finger) = self.rsa_cipher.lock().as_ref().map(|v| v.finger().clone()) {
             request.key_finger = finger;
         }
+        #[cfg(feature = "pq")]
+        if secret {
+            if let Some(kem) = &self.kem {
+                request.kem_public_key = kem.public_key().to_vec();
+            }
+        }
         let bytes = request.write_to_bytes().map_err(|e| {
             io::Error::new(
                 io::ErrorKind::Other,
@@ -80,16 +122,38 @@ impl Handshake {
     }
 }
 
-/// 第二次加密握手
+/// 用于探测服务端延迟的握手数据
+pub fn probe_packet() -> io::Result<NetPacket<Vec<u8>>> {
+    Handshake::new(
+        #[cfg(feature = "server_encrypt")]
+        Arc::new(Mutex::new(None)),
+        #[cfg(feature = "pq")]
+        false,
+    )
+    .handshake_request_packet(false)
+}
+
+/// 第二次加密握手，有混合密钥交换的共享密钥时，密钥和共享密钥异或后再用rsa加密
 #[cfg(feature = "server_encrypt")]
 pub fn secret_handshake_request_packet(
     rsa_cipher: &RsaCipher,
     token: String,
     key: &[u8],
+    #[cfg(feature = "pq")] kem_shared: Option<[u8; 32]>,
 ) -> io::Result<NetPacket<Vec<u8>>> {
     let mut request = SecretHandshakeRequest::new();
     request.token = token;
     request.key = key.to_vec();
+    #[cfg(feature = "pq")]
+    if let Some(shared) = kem_shared {
+        if request.key.len() > shared.len() {
+            return Err(io::Error::new(io::ErrorKind::Other, "key too long"));
+        }
+        for (v, k) in request.key.iter_mut().zip(shared) {
+            *v ^= k;
+        }
+        request.kem = true;
+    }
     let bytes = request.write_to_bytes().map_err(|e| {
         io::Error::new(
             io::ErrorKind::Other,
@@ -0,0 +1,17 @@
+use std::time::Duration;
+
+use crate::channel::context::ChannelContext;
+use crate::util::Scheduler;
+
+/// 定时发出缓存时间超过合并延迟的小包
+pub fn aggregate_flush(scheduler: &Scheduler, context: ChannelContext) {
+    let delay = match context.aggregator() {
+        Some(aggregator) => aggregator.delay(),
+        None => return,
+    };
+    context.flush_aggregate();
+    let rs = scheduler.timeout(delay, move |s| aggregate_flush(s, context));
+    if !rs {
+        log::info!("定时任务停止");
+    }
+}
@@ -0,0 +1,283 @@
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use crate::channel::context::ChannelContext;
+use crate::channel::Route;
+use crate::cipher::Cipher;
+use crate::handle::CurrentDeviceInfo;
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::control_packet::{
+    DiagPacket, DIAG_DATA, DIAG_ECHO, DIAG_HEAD_LEN, DIAG_REPORT, FEATURE_DIAG,
+};
+use crate::protocol::{control_packet, NetPacket, Protocol, HEAD_LEN, MAX_TTL};
+
+// 带宽测试每个包的填充，加上包头和加密后不超过常见的路径mtu
+const DATA_PADDING: usize = 1200;
+// 带宽测试结束后等待在途的数据
+const DRAIN_TIME: Duration = Duration::from_millis(500);
+// 查询带宽测试结果的次数
+const REPORT_RETRY: u16 = 3;
+
+/// 一次回显的结果
+#[derive(Copy, Clone, Debug)]
+pub struct DiagEcho {
+    /// 发出时使用的路由，None表示经服务端转发
+    pub route: Option<Route>,
+    /// 往返时间，超时为None
+    pub rtt: Option<Duration>,
+    /// 响应经过的跳数，1表示直连
+    pub hops: u8,
+}
+
+/// 到对端路径上的一跳
+#[derive(Copy, Clone, Debug)]
+pub struct DiagHop {
+    pub ip: Ipv4Addr,
+    pub server: bool,
+    /// 往返时间，服务端的是心跳测得的延迟，超时为None
+    pub rtt: Option<Duration>,
+}
+
+/// 带宽测试的结果
+#[derive(Copy, Clone, Debug)]
+pub struct DiagThroughput {
+    pub route: Option<Route>,
+    pub duration: Duration,
+    pub sent_bytes: u64,
+    pub sent_packets: u64,
+    /// 对端收到的字节数和包数，查询超时为None
+    pub received: Option<(u64, u64)>,
+}
+
+/// 构建未加密的诊断包
+pub(crate) fn diag_packet(
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+    protocol: control_packet::Protocol,
+    kind: u8,
+    id: u32,
+    seq: u16,
+    padding: usize,
+) -> anyhow::Result<NetPacket<Vec<u8>>> {
+    let len = HEAD_LEN + DIAG_HEAD_LEN + padding;
+    let mut net_packet = NetPacket::new0(len, vec![0u8; len + ENCRYPTION_RESERVED])?;
+    net_packet.set_default_version();
+    net_packet.set_protocol(Protocol::Control);
+    net_packet.set_transport_protocol(protocol.into());
+    net_packet.first_set_ttl(MAX_TTL);
+    net_packet.set_source(src);
+    net_packet.set_destination(dest);
+    let mut diag_packet = DiagPacket::new(net_packet.payload_mut())?;
+    diag_packet.set_kind(kind);
+    diag_packet.set_id(id);
+    diag_packet.set_seq(seq);
+    Ok(net_packet)
+}
+
+fn check_peer(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    dest: &Ipv4Addr,
+) -> anyhow::Result<()> {
+    if current_device.status.offline() {
+        return Err(anyhow::anyhow!("not connected to the server"));
+    }
+    if current_device.is_gateway(dest) || dest == &current_device.virtual_ip {
+        return Err(anyhow::anyhow!("{} is not a peer", dest));
+    }
+    if context.peer_features(dest) & FEATURE_DIAG == 0 {
+        return Err(anyhow::anyhow!(
+            "peer {} does not support diagnostics or has not sent a heartbeat yet",
+            dest
+        ));
+    }
+    Ok(())
+}
+
+// 有路由时按路由发送，否则经服务端转发
+fn send(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    net_packet: &NetPacket<Vec<u8>>,
+    route: Option<Route>,
+) -> io::Result<()> {
+    match route {
+        Some(route) => context.send_by_key(net_packet, route.route_key()),
+        None => context.send_default(net_packet, current_device.connect_server),
+    }
+}
+
+/// 向对端发送一次回显并等待响应，不经过虚拟网卡，用于区分是vnt的问题还是系统防火墙的问题
+pub fn diag_echo(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    dest: Ipv4Addr,
+    timeout: Duration,
+) -> anyhow::Result<DiagEcho> {
+    check_peer(context, current_device, &dest)?;
+    let id = context.diag().register();
+    let rs = diag_echo0(context, current_device, client_cipher, dest, id, timeout);
+    context.diag().remove(id);
+    rs
+}
+
+fn diag_echo0(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    dest: Ipv4Addr,
+    id: u32,
+    timeout: Duration,
+) -> anyhow::Result<DiagEcho> {
+    let mut net_packet = diag_packet(
+        current_device.virtual_ip,
+        dest,
+        control_packet::Protocol::Diag,
+        DIAG_ECHO,
+        id,
+        0,
+        0,
+    )?;
+    client_cipher.encrypt_ipv4(&mut net_packet)?;
+    let route = context.route_table.route_one(&dest);
+    let start = Instant::now();
+    send(context, current_device, &net_packet, route)?;
+    let response = context.diag().wait(id, 0, timeout);
+    Ok(DiagEcho {
+        route,
+        rtt: response.map(|v| v.time.duration_since(start)),
+        hops: response.map_or(0, |v| v.metric),
+    })
+}
+
+/// 到对端经过的中继节点(服务端或者中转的客户端)和每一跳的往返时间
+pub fn diag_traceroute(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    dest: Ipv4Addr,
+    timeout: Duration,
+) -> anyhow::Result<Vec<DiagHop>> {
+    check_peer(context, current_device, &dest)?;
+    let mut hops = Vec::new();
+    let route = context.route_table.route_one(&dest);
+    if !route.map_or(false, |route| route.is_p2p()) {
+        let relay = route
+            .and_then(|route| context.route_table.route_to_id(&route.route_key()))
+            .filter(|ip| !current_device.is_gateway(ip));
+        match relay {
+            Some(relay) => {
+                // 中转的客户端不支持时只显示地址
+                let rtt = diag_echo(context, current_device, client_cipher, relay, timeout)
+                    .ok()
+                    .and_then(|echo| echo.rtt);
+                hops.push(DiagHop {
+                    ip: relay,
+                    server: false,
+                    rtt,
+                });
+            }
+            None => {
+                let rtt = context
+                    .route_table
+                    .route_one(&current_device.virtual_gateway)
+                    .filter(|route| route.rt >= 0)
+                    .map(|route| Duration::from_millis(route.rt as u64));
+                hops.push(DiagHop {
+                    ip: current_device.virtual_gateway,
+                    server: true,
+                    rtt,
+                });
+            }
+        }
+    }
+    let echo = diag_echo(context, current_device, client_cipher, dest, timeout)?;
+    hops.push(DiagHop {
+        ip: dest,
+        server: false,
+        rtt: echo.rtt,
+    });
+    Ok(hops)
+}
+
+/// 向对端发送duration时长的测试数据，再查询对端收到的数据量
+pub fn diag_bandwidth(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    dest: Ipv4Addr,
+    duration: Duration,
+) -> anyhow::Result<DiagThroughput> {
+    check_peer(context, current_device, &dest)?;
+    let id = context.diag().register();
+    let rs = diag_bandwidth0(context, current_device, client_cipher, dest, id, duration);
+    context.diag().remove(id);
+    rs
+}
+
+fn diag_bandwidth0(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    dest: Ipv4Addr,
+    id: u32,
+    duration: Duration,
+) -> anyhow::Result<DiagThroughput> {
+    let route = context.route_table.route_one(&dest);
+    let start = Instant::now();
+    let mut sent_bytes = 0;
+    let mut sent_packets = 0u64;
+    while start.elapsed() < duration {
+        // 和正常的数据一样每个包都加密
+        let mut net_packet = diag_packet(
+            current_device.virtual_ip,
+            dest,
+            control_packet::Protocol::Diag,
+            DIAG_DATA,
+            id,
+            sent_packets as u16,
+            DATA_PADDING,
+        )?;
+        client_cipher.encrypt_ipv4(&mut net_packet)?;
+        match send(context, current_device, &net_packet, route) {
+            Ok(_) => {
+                sent_bytes += (DIAG_HEAD_LEN + DATA_PADDING) as u64;
+                sent_packets += 1;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_micros(200));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let duration = start.elapsed();
+    std::thread::sleep(DRAIN_TIME);
+    // 对端查询后不删除统计，重试的查询使用相同的序号，收到任意一次的响应即可
+    let mut received = None;
+    for _ in 0..REPORT_RETRY {
+        let mut net_packet = diag_packet(
+            current_device.virtual_ip,
+            dest,
+            control_packet::Protocol::Diag,
+            DIAG_REPORT,
+            id,
+            0,
+            0,
+        )?;
+        client_cipher.encrypt_ipv4(&mut net_packet)?;
+        send(context, current_device, &net_packet, route)?;
+        if let Some(response) = context.diag().wait(id, 0, Duration::from_secs(1)) {
+            received = Some((response.bytes, response.packets));
+            break;
+        }
+    }
+    Ok(DiagThroughput {
+        route,
+        duration,
+        sent_bytes,
+        sent_packets,
+        received,
+    })
+}
@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_utils::atomic::AtomicCell;
+use parking_lot::Mutex;
+
+use crate::channel::context::ChannelContext;
+use crate::handle::{ConnectStatus, CurrentDeviceInfo, PeerDeviceInfo};
+use crate::util::{EventLog, Scheduler};
+use crate::{VntCallback, VntEvent};
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// 上一次检查时的状态
+struct State {
+    status: ConnectStatus,
+    virtual_ip: Ipv4Addr,
+    // 在线的对端和名称
+    peers: HashMap<Ipv4Addr, String>,
+    // 有直连的对端
+    direct: HashSet<Ipv4Addr>,
+}
+
+/// 定时比较连接状态、虚拟ip、对端列表和直连路由，有变化时产生事件
+pub fn events<Call: VntCallback>(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
+    event_log: Arc<EventLog>,
+    call: Call,
+) {
+    let state = State {
+        status: ConnectStatus::Connecting,
+        virtual_ip: Ipv4Addr::UNSPECIFIED,
+        peers: HashMap::new(),
+        direct: HashSet::new(),
+    };
+    events0(
+        scheduler,
+        context,
+        current_device,
+        device_map,
+        event_log,
+        call,
+        state,
+    )
+}
+
+fn events0<Call: VntCallback>(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
+    event_log: Arc<EventLog>,
+    call: Call,
+    mut state: State,
+) {
+    for event in check(&context, &current_device.load(), &device_map, &mut state) {
+        log::info!("event {}", event);
+        event_log.push(event.clone());
+        call.event(event);
+    }
+    let rs = scheduler.timeout(INTERVAL, move |s| {
+        events0(
+            s,
+            context,
+            current_device,
+            device_map,
+            event_log,
+            call,
+            state,
+        )
+    });
+    if !rs {
+        log::info!("定时任务停止");
+    }
+}
+
+fn check(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
+    state: &mut State,
+) -> Vec<VntEvent> {
+    let mut events = Vec::new();
+    if current_device.status != state.status {
+        state.status = current_device.status;
+        events.push(match current_device.status {
+            ConnectStatus::Connected => VntEvent::ServerConnected {
+                server: current_device.connect_server,
+            },
+            ConnectStatus::Connecting => VntEvent::ServerDisconnected,
+        });
+    }
+    if current_device.virtual_ip != state.virtual_ip && !current_device.virtual_ip.is_unspecified()
+    {
+        // 第一次分配的ip不算变化
+        if !state.virtual_ip.is_unspecified() {
+            events.push(VntEvent::IpChanged {
+                old: state.virtual_ip,
+                new: current_device.virtual_ip,
+            });
+        }
+        state.virtual_ip = current_device.virtual_ip;
+    }
+
+    let peers: HashMap<Ipv4Addr, String> = device_map
+        .lock()
+        .1
+        .values()
+        .filter(|peer| peer.status.is_online())
+        .map(|peer| (peer.virtual_ip, peer.name.clone()))
+        .collect();
+    for (virtual_ip, name) in &peers {
+        if !state.peers.contains_key(virtual_ip) {
+            events.push(VntEvent::PeerOnline {
+                virtual_ip: *virtual_ip,
+                name: name.clone(),
+            });
+        }
+    }
+    for (virtual_ip, name) in &state.peers {
+        if !peers.contains_key(virtual_ip) {
+            events.push(VntEvent::PeerOffline {
+                virtual_ip: *virtual_ip,
+                name: name.clone(),
+            });
+        }
+    }
+    state.peers = peers;
+
+    let routes: Vec<_> = context
+        .route_table
+        .route_table_p2p()
+        .into_iter()
+        .filter(|(ip, _)| !current_device.is_gateway(ip))
+        .collect();
+    for (virtual_ip, route) in &routes {
+        if !state.direct.contains(virtual_ip) {
+            events.push(VntEvent::DirectLinkUp {
+                virtual_ip: *virtual_ip,
+                addr: route.addr,
+            });
+        }
+    }
+    let direct: HashSet<Ipv4Addr> = routes.into_iter().map(|(ip, _)| ip).collect();
+    for virtual_ip in &state.direct {
+        if !direct.contains(virtual_ip) {
+            events.push(VntEvent::DirectLinkDown {
+                virtual_ip: *virtual_ip,
+            });
+        }
+    }
+    state.direct = direct;
+    events
+}
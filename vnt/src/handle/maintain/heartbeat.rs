@@ -8,10 +8,15 @@ use parking_lot::Mutex;
 use rand::prelude::SliceRandom;
 
 use crate::channel::context::ChannelContext;
+use crate::channel::policy::PathPolicy;
+use crate::channel::quality::PathQuality;
+use crate::channel::Route;
 use crate::cipher::Cipher;
 use crate::handle::{CurrentDeviceInfo, PeerDeviceInfo};
 use crate::protocol::body::ENCRYPTION_RESERVED;
-use crate::protocol::control_packet::PingPacket;
+use crate::protocol::control_packet::{
+    PingPacket, RelayPathsPacket, DIAG_PROBE, FEATURE_DIAG, FEATURE_RELAY_PATHS,
+};
 use crate::protocol::{control_packet, NetPacket, Protocol};
 use crate::util::Scheduler;
 
@@ -23,6 +28,8 @@ pub fn heartbeat(
     device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
     client_cipher: Cipher,
     server_cipher: Cipher,
+    interval: Duration,
+    dead_idle: Duration,
 ) {
     heartbeat0(
         &context,
@@ -30,9 +37,10 @@ pub fn heartbeat(
         &device_map,
         &client_cipher,
         &server_cipher,
+        dead_idle,
     );
-    // 心跳包 3秒发送一次
-    let rs = scheduler.timeout(Duration::from_secs(3), |s| {
+    // 心跳包 默认3秒发送一次
+    let rs = scheduler.timeout(interval, move |s| {
         heartbeat(
             s,
             context,
@@ -40,6 +48,8 @@ pub fn heartbeat(
             device_map,
             client_cipher,
             server_cipher,
+            interval,
+            dead_idle,
         )
     });
     if !rs {
@@ -53,6 +63,7 @@ fn heartbeat0(
     device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
     client_cipher: &Cipher,
     server_cipher: &Cipher,
+    dead_idle: Duration,
 ) {
     let gateway_ip = current_device.virtual_gateway;
     let src_ip = current_device.virtual_ip;
@@ -72,25 +83,25 @@ fn heartbeat0(
     }
 
     for (dest_ip, routes) in context.route_table.route_table() {
-        let net_packet = if current_device.is_gateway(&dest_ip) {
+        // 客户端之间的心跳包带有本端能力，和服务端之间的不带
+        let rs = if current_device.is_gateway(&dest_ip) {
             if is_send_gateway {
                 continue;
             }
             heartbeat_packet_server(device_map, server_cipher, src_ip, gateway_ip)
+                .map(|net_packet| send_to_routes(context, &net_packet, &routes))
         } else {
-            heartbeat_packet_client(client_cipher, src_ip, dest_ip)
+            heartbeat_packet_client(
+                client_cipher,
+                src_ip,
+                dest_ip,
+                context.local_features(),
+                context.session_id(),
+            )
+            .map(|net_packet| send_to_routes(context, &net_packet, &routes))
         };
-        let net_packet = match net_packet {
-            Ok(net_packet) => net_packet,
-            Err(e) => {
-                log::error!("heartbeat_packet err={:?}", e);
-                continue;
-            }
-        };
-        for route in routes {
-            if let Err(e) = context.send_by_key(&net_packet, route.route_key()) {
-                log::warn!("heartbeat err={:?}", e)
-            }
+        if let Err(e) = rs {
+            log::error!("heartbeat_packet err={:?}", e);
         }
     }
     let peer_list = { device_map.lock().1.clone() };
@@ -104,9 +115,34 @@ fn heartbeat0(
         if current_device.status.offline() {
             continue;
         }
-        if context.route_table.route_one(&peer.virtual_ip).is_none() {
-            //路由为空，则向服务端地址发送
-            let net_packet = match heartbeat_packet_client(client_cipher, src_ip, peer.virtual_ip) {
+        if let Some(peer_stats) = context.peer_stats() {
+            // 每轮心跳按对端计一次，统计对端的丢包率
+            peer_stats.ping(peer.virtual_ip);
+        }
+        if let Some(quality) = context.route_table.quality() {
+            if let Err(e) = probe_paths(
+                context,
+                current_device,
+                client_cipher,
+                quality,
+                peer.virtual_ip,
+            ) {
+                log::warn!("probe_paths {} {:?}", peer.virtual_ip, e);
+            }
+        }
+        let stale = context
+            .route_table
+            .last_read(&peer.virtual_ip)
+            .map_or(true, |last_read| last_read >= dead_idle);
+        if stale {
+            //路由为空或者都已失效，则向服务端地址发送，不用等到路由超时就能经服务端中继
+            let net_packet = match heartbeat_packet_client(
+                client_cipher,
+                src_ip,
+                peer.virtual_ip,
+                context.local_features(),
+                context.session_id(),
+            ) {
                 Ok(net_packet) => net_packet,
                 Err(e) => {
                     log::error!("heartbeat_packet err={:?}", e);
@@ -120,6 +156,89 @@ fn heartbeat0(
     }
 }
 
+/// 有直连时分别经直连和服务端发送探测，根据上一轮的结果选择使用的路径
+fn probe_paths(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    quality: &PathQuality,
+    dest: Ipv4Addr,
+) -> anyhow::Result<()> {
+    // 固定使用直连的对端不切换到中转
+    let route = match context.route_table.route_one_p2p(&dest) {
+        Some(route)
+            if context.peer_features(&dest) & FEATURE_DIAG != 0
+                && !context.use_channel_type_of(&dest).is_only_p2p() =>
+        {
+            route
+        }
+        _ => {
+            quality.remove(&dest);
+            return Ok(());
+        }
+    };
+    match quality.update(&dest) {
+        Some(true) => log::info!("{} 改用服务端中转 {:?}", dest, quality.info(&dest)),
+        Some(false) => log::info!("{} 恢复使用直连 {:?}", dest, quality.info(&dest)),
+        None => {}
+    }
+    let mut net_packet = super::diag_packet(
+        current_device.virtual_ip,
+        dest,
+        control_packet::Protocol::Diag,
+        DIAG_PROBE,
+        0,
+        crate::handle::now_time() as u16,
+        0,
+    )?;
+    client_cipher.encrypt_ipv4(&mut net_packet)?;
+    context.send_by_key(&net_packet, route.route_key())?;
+    quality.ping(dest, false);
+    context.send_default(&net_packet, current_device.connect_server)?;
+    quality.ping(dest, true);
+    Ok(())
+}
+
+/// 立即向对端发送一个心跳，对端回应后更新路由的延迟，没有路由时经服务端转发
+pub fn ping_peer(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    dest: Ipv4Addr,
+) -> anyhow::Result<()> {
+    let net_packet = heartbeat_packet_client(
+        client_cipher,
+        current_device.virtual_ip,
+        dest,
+        context.local_features(),
+        context.session_id(),
+    )?;
+    match context.route_table.route(&dest) {
+        Some(routes) if !routes.is_empty() => {
+            for route in routes {
+                context.send_by_key(&net_packet, route.route_key())?;
+            }
+        }
+        _ => context.send_default(&net_packet, current_device.connect_server)?,
+    }
+    Ok(())
+}
+
+fn send_to_routes<B: AsRef<[u8]>>(
+    context: &ChannelContext,
+    net_packet: &NetPacket<B>,
+    routes: &[Route],
+) {
+    for route in routes {
+        if let Err(e) = context.send_by_key(net_packet, route.route_key()) {
+            log::warn!("heartbeat err={:?}", e)
+        } else if let Some(multipath) = context.route_table.multipath() {
+            // 统计每条路径的丢包
+            multipath.ping(&route.route_key());
+        }
+    }
+}
+
 /// 客户端中继路径探测,延迟启动
 pub fn client_relay(
     scheduler: &Scheduler,
@@ -172,6 +291,10 @@ fn client_relay0(
     }
     let peer_list = { device_map.lock().1.clone() };
     let mut routes = context.route_table.route_table_p2p();
+    routes.retain(|(ip, _)| !current_device.is_gateway(ip));
+    if context.peer_relay() {
+        relay_paths_notify(context, current_device, client_cipher, &routes)?;
+    }
     for peer in peer_list.values() {
         if peer.wireguard
             || !peer.status.is_online()
@@ -183,14 +306,41 @@ fn client_relay0(
             .route_table
             .route_one_p2p(&peer.virtual_ip)
             .is_some()
-            && !context.first_latency()
+            && context.route_table.path_policy(&peer.virtual_ip) != PathPolicy::FirstLatency
         {
             continue;
         }
-        let client_packet =
-            heartbeat_packet_client(client_cipher, current_device.virtual_ip, peer.virtual_ip)?;
+        let client_packet = heartbeat_packet_client(
+            client_cipher,
+            current_device.virtual_ip,
+            peer.virtual_ip,
+            context.local_features(),
+            context.session_id(),
+        )?;
+
+        // 优先经由通告了直达路径的客户端中转，按两段延迟之和选择
+        let mut relays: Vec<(i64, Route)> = context
+            .relay_paths()
+            .relays(&peer.virtual_ip)
+            .into_iter()
+            .filter_map(|(relay, rt)| {
+                context
+                    .route_table
+                    .route_one_p2p(&relay)
+                    .map(|route| (route.rt + rt as i64, route))
+            })
+            .collect();
+        if !relays.is_empty() {
+            relays.sort_by_key(|(rt, _)| *rt);
+            for (_, route) in relays.iter().take(2) {
+                if let Err(e) = context.send_by_key(&client_packet, route.route_key()) {
+                    log::error!("{:?}", e);
+                }
+            }
+            continue;
+        }
 
-        //随机发送到其他地址，看有没有客户端符合转发条件
+        //没有通告(旧版本)时随机发送到其他地址，看有没有客户端符合转发条件
         routes.shuffle(&mut rand::thread_rng());
 
         for (index, (ip, route)) in routes.iter().enumerate() {
@@ -208,12 +358,45 @@ fn client_relay0(
     Ok(())
 }
 
+/// 向直连的客户端通告本端能直达的客户端及延迟，对端据此选择经由本端中转
+fn relay_paths_notify(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    routes: &[(Ipv4Addr, Route)],
+) -> anyhow::Result<()> {
+    for (dest, route) in routes {
+        if context.peer_features(dest) & FEATURE_RELAY_PATHS == 0 {
+            continue;
+        }
+        let paths: Vec<(Ipv4Addr, u16)> = routes
+            .iter()
+            .filter(|(ip, _)| ip != dest)
+            .map(|(ip, route)| (*ip, route.rt.clamp(0, u16::MAX as i64) as u16))
+            .collect();
+        let mut net_packet =
+            NetPacket::new_encrypt(vec![0u8; 12 + paths.len() * 6 + ENCRYPTION_RESERVED])?;
+        net_packet.set_default_version();
+        net_packet.set_protocol(Protocol::Control);
+        net_packet.set_transport_protocol(control_packet::Protocol::RelayPaths.into());
+        net_packet.first_set_ttl(1);
+        net_packet.set_source(current_device.virtual_ip);
+        net_packet.set_destination(*dest);
+        RelayPathsPacket::new(net_packet.payload_mut())?.set_paths(&paths)?;
+        client_cipher.encrypt_ipv4(&mut net_packet)?;
+        if let Err(e) = context.send_by_key(&net_packet, route.route_key()) {
+            log::warn!("relay paths {} {:?}", dest, e);
+        }
+    }
+    Ok(())
+}
+
 /// 构建心跳包
-fn heartbeat_packet(
+fn heartbeat_packet<const N: usize>(
     src: Ipv4Addr,
     dest: Ipv4Addr,
-) -> anyhow::Result<NetPacket<[u8; 12 + 4 + ENCRYPTION_RESERVED]>> {
-    let mut net_packet = NetPacket::new_encrypt([0u8; 12 + 4 + ENCRYPTION_RESERVED])?;
+) -> anyhow::Result<NetPacket<[u8; N]>> {
+    let mut net_packet = NetPacket::new_encrypt([0u8; N])?;
     net_packet.set_default_version();
     net_packet.set_protocol(Protocol::Control);
     net_packet.set_transport_protocol(control_packet::Protocol::Ping.into());
@@ -229,8 +412,13 @@ fn heartbeat_packet_client(
     client_cipher: &Cipher,
     src: Ipv4Addr,
     dest: Ipv4Addr,
-) -> anyhow::Result<NetPacket<[u8; 12 + 4 + ENCRYPTION_RESERVED]>> {
+    features: u16,
+    session_id: u64,
+) -> anyhow::Result<NetPacket<[u8; 12 + 14 + ENCRYPTION_RESERVED]>> {
     let mut net_packet = heartbeat_packet(src, dest)?;
+    let mut ping = PingPacket::new(net_packet.payload_mut())?;
+    ping.set_features(features)?;
+    ping.set_session_id(session_id)?;
     client_cipher.encrypt_ipv4(&mut net_packet)?;
     Ok(net_packet)
 }
@@ -11,6 +11,7 @@ use crate::channel::ConnectProtocol;
 use crate::handle::callback::{ConnectInfo, ErrorType};
 use crate::handle::handshaker::Handshake;
 use crate::handle::{BaseConfigInfo, ConnectStatus, CurrentDeviceInfo};
+use crate::obfs::Obfs;
 use crate::util::{address_choose, dns_query_all, Scheduler};
 use crate::{ErrorInfo, VntCallback};
 
@@ -110,6 +111,11 @@ fn idle_route0<Call: VntCallback>(
             }
             Duration::from_millis(100)
         }
+        IdleType::Dead(ip, route) => {
+            log::info!("route Dead {:?},{:?}", ip, route);
+            context.remove_route(&ip, route.route_key());
+            Duration::from_millis(100)
+        }
         IdleType::Sleep(duration) => duration,
         IdleType::None => Duration::from_millis(3000),
     }
@@ -125,12 +131,22 @@ fn check_gateway_channel<Call: VntCallback>(
     handshake: &Handshake,
 ) -> io::Result<()> {
     let mut current_device = current_device_info.load();
-    if current_device.status.offline() {
+    if current_device.status.online() {
+        config.server_addr.success();
+    } else {
         *count += 1;
+        if config.server_addr.fail() {
+            log::info!("切换服务端地址:{}", config.server_addr.current());
+        }
         let connect_protocol = context.main_protocol();
         if connect_protocol.is_transport() {
             // 传输层的协议需要探测服务器地址
-            current_device = domain_request0(current_device_info, config);
+            current_device = domain_request0(
+                current_device_info,
+                config,
+                connect_protocol,
+                context.obfs(),
+            );
         }
         //需要重连
         call.connect(ConnectInfo::new(*count, current_device.connect_server));
@@ -142,14 +158,28 @@ fn check_gateway_channel<Call: VntCallback>(
             match connect_protocol {
                 ConnectProtocol::UDP => {}
                 ConnectProtocol::TCP => {
-                    connect_util.try_connect_tcp(
+                    connect_util.try_connect_server_tcp(
                         request_packet.into_buffer(),
                         current_device.connect_server,
                     );
                 }
                 ConnectProtocol::WS | ConnectProtocol::WSS => {
                     connect_util
-                        .try_connect_ws(request_packet.into_buffer(), config.server_addr.clone());
+                        .try_connect_ws(request_packet.into_buffer(), config.server_addr.current());
+                }
+                ConnectProtocol::TLS => {
+                    connect_util.try_connect_tls(
+                        request_packet.into_buffer(),
+                        current_device.connect_server,
+                        config.server_addr.current(),
+                    );
+                }
+                ConnectProtocol::QUIC => {
+                    connect_util.try_connect_quic(
+                        request_packet.into_buffer(),
+                        current_device.connect_server,
+                        config.server_addr.current(),
+                    );
                 }
             }
         }
@@ -160,20 +190,22 @@ fn check_gateway_channel<Call: VntCallback>(
 pub fn domain_request0(
     current_device: &AtomicCell<CurrentDeviceInfo>,
     config: &BaseConfigInfo,
+    protocol: ConnectProtocol,
+    obfs: &Obfs,
 ) -> CurrentDeviceInfo {
     let mut current_dev = current_device.load();
-
+    let server_addr = config.server_addr.current();
     // 探测服务端地址变化
-    match dns_query_all(&config.server_addr, config.name_servers.clone()) {
+    match dns_query_all(&server_addr, config.name_servers.clone(), config.dns_mode) {
         Ok(addrs) => {
             log::info!(
                 "domain {} dns {:?} addr {:?}",
-                config.server_addr,
+                server_addr,
                 config.name_servers,
                 addrs
             );
 
-            match address_choose(addrs) {
+            match address_choose(addrs, config.server_prefer, protocol, obfs) {
                 Ok(addr) => {
                     if addr != current_dev.connect_server {
                         let mut tmp = current_dev.clone();
@@ -191,12 +223,12 @@ pub fn domain_request0(
                     }
                 }
                 Err(e) => {
-                    log::error!("域名地址选择失败:{:?},domain={}", e, config.server_addr);
+                    log::error!("域名地址选择失败:{:?},domain={}", e, server_addr);
                 }
             }
         }
         Err(e) => {
-            log::error!("域名解析失败:{:?},domain={}", e, config.server_addr);
+            log::error!("域名解析失败:{:?},domain={}", e, server_addr);
         }
     }
     current_dev
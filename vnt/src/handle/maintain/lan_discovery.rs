@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_utils::atomic::AtomicCell;
+use parking_lot::Mutex;
+use sha2::Digest;
+
+use crate::channel::context::ChannelContext;
+use crate::cipher::Cipher;
+use crate::handle::{CurrentDeviceInfo, PeerDeviceInfo};
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::{control_packet, NetPacket, Protocol};
+use crate::util::Scheduler;
+
+// 局域网发现使用的组播地址和端口
+const LAN_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 118, 116);
+const LAN_PORT: u16 = 29871;
+const MAGIC: &[u8; 4] = b"vntl";
+// 信标: magic(4) | token hash(16) | 虚拟ip(4) | udp端口(2)*n
+const HEAD_LEN: usize = 24;
+// 每秒检查一次收到的信标，每10秒发送一次信标
+const BEACON_INTERVAL: usize = 10;
+
+/// 局域网发现，同一局域网内的客户端通过组播信标互相发现，直接向局域网地址打洞
+pub fn lan_discovery(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    local_ipv4: Option<Ipv4Addr>,
+    device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    client_cipher: Cipher,
+    token: &str,
+) {
+    let socket = match lan_socket(local_ipv4) {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::warn!("局域网发现不可用 {:?}", e);
+            return;
+        }
+    };
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(b"vnt-lan");
+    hasher.update(token.as_bytes());
+    let hash: [u8; 32] = hasher.finalize().into();
+    let discovery = LanDiscovery {
+        socket: Arc::new(socket),
+        token_hash: hash[..16].try_into().unwrap(),
+        context,
+        device_map,
+        current_device,
+        client_cipher,
+    };
+    discovery.run(scheduler, 0);
+}
+
+fn lan_socket(local_ipv4: Option<Ipv4Addr>) -> anyhow::Result<UdpSocket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::IPV4,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    // 同一台机器上可能运行多个客户端
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    let addr: SocketAddr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, LAN_PORT).into();
+    socket.bind(&addr.into())?;
+    let interface = local_ipv4.unwrap_or(Ipv4Addr::UNSPECIFIED);
+    socket.join_multicast_v4(&LAN_GROUP, &interface)?;
+    if !interface.is_unspecified() {
+        socket.set_multicast_if_v4(&interface)?;
+    }
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+#[derive(Clone)]
+struct LanDiscovery {
+    socket: Arc<UdpSocket>,
+    token_hash: [u8; 16],
+    context: ChannelContext,
+    device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    client_cipher: Cipher,
+}
+
+impl LanDiscovery {
+    fn run(self, scheduler: &Scheduler, count: usize) {
+        let current_device = self.current_device.load();
+        if current_device.status.online() {
+            if count % BEACON_INTERVAL == 0 {
+                if let Err(e) = self.send_beacon(&current_device) {
+                    log::warn!("局域网信标发送失败 {:?}", e);
+                }
+            }
+            if let Err(e) = self.recv_beacon(&current_device) {
+                log::warn!("局域网信标 {:?}", e);
+            }
+        }
+        let rs = scheduler.timeout(Duration::from_secs(1), move |s| self.run(s, count + 1));
+        if !rs {
+            log::info!("定时任务停止");
+        }
+    }
+    fn send_beacon(&self, current_device: &CurrentDeviceInfo) -> io::Result<()> {
+        let ports = self.context.main_local_udp_port()?;
+        let mut buf = Vec::with_capacity(HEAD_LEN + ports.len() * 2);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&self.token_hash);
+        buf.extend_from_slice(&current_device.virtual_ip.octets());
+        for port in ports {
+            buf.extend_from_slice(&port.to_be_bytes());
+        }
+        self.socket
+            .send_to(&buf, SocketAddrV4::new(LAN_GROUP, LAN_PORT))?;
+        Ok(())
+    }
+    fn recv_beacon(&self, current_device: &CurrentDeviceInfo) -> anyhow::Result<()> {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, addr) = match self.socket.recv_from(&mut buf) {
+                Ok(rs) => rs,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => Err(e)?,
+            };
+            let buf = &buf[..len];
+            if len < HEAD_LEN || &buf[..4] != MAGIC || buf[4..20] != self.token_hash {
+                continue;
+            }
+            let peer_ip = Ipv4Addr::new(buf[20], buf[21], buf[22], buf[23]);
+            let lan_ip = match addr.ip() {
+                IpAddr::V4(ip) => ip,
+                IpAddr::V6(_) => continue,
+            };
+            if peer_ip == current_device.virtual_ip || !current_device.not_in_network(lan_ip) {
+                continue;
+            }
+            if let Some(peer) = self.device_map.lock().1.get(&peer_ip) {
+                if !peer.status.is_online() || peer.wireguard {
+                    continue;
+                }
+            } else {
+                continue;
+            }
+            if let Some(routes) = self.context.route_table.route(&peer_ip) {
+                if routes.iter().any(|route| route.addr.ip() == lan_ip) {
+                    // 已经通过局域网直连
+                    continue;
+                }
+            }
+            log::info!("局域网发现客户端 {} {}", peer_ip, lan_ip);
+            let mut packet = NetPacket::new_encrypt([0u8; 12 + ENCRYPTION_RESERVED])?;
+            packet.set_default_version();
+            packet.first_set_ttl(1);
+            packet.set_protocol(Protocol::Control);
+            packet.set_transport_protocol(control_packet::Protocol::PunchRequest.into());
+            packet.set_source(current_device.virtual_ip);
+            packet.set_destination(peer_ip);
+            self.client_cipher.encrypt_ipv4(&mut packet)?;
+            let channel_num = self.context.channel_num();
+            for (index, port) in buf[HEAD_LEN..].chunks_exact(2).enumerate() {
+                let port = u16::from_be_bytes([port[0], port[1]]);
+                let addr = SocketAddr::V4(SocketAddrV4::new(lan_ip, port));
+                if let Err(e) =
+                    self.context
+                        .send_main_udp(index % channel_num, packet.buffer(), addr)
+                {
+                    log::warn!("{:?} {:?}", addr, e);
+                }
+            }
+        }
+    }
+}
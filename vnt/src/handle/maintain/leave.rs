@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use parking_lot::Mutex;
+
+use crate::channel::context::ChannelContext;
+use crate::cipher::Cipher;
+use crate::handle::{CurrentDeviceInfo, PeerDeviceInfo};
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::control_packet::FEATURE_LEAVE;
+use crate::protocol::{control_packet, service_packet, NetPacket, Protocol, MAX_TTL};
+
+/// 正常退出前通知服务端和对端，其他客户端可以立即看到本端离线，不用等到超时
+pub fn leave(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
+    client_cipher: &Cipher,
+    server_cipher: &Cipher,
+) {
+    if current_device.status.offline() {
+        return;
+    }
+    if let Err(e) = leave_server(context, current_device, server_cipher) {
+        log::warn!("leave server {:?}", e);
+    }
+    let mut notified = Vec::new();
+    for (dest, routes) in context.route_table.route_table() {
+        if current_device.is_gateway(&dest) || context.peer_features(&dest) & FEATURE_LEAVE == 0 {
+            continue;
+        }
+        let net_packet = match leave_packet(client_cipher, current_device.virtual_ip, dest) {
+            Ok(net_packet) => net_packet,
+            Err(e) => {
+                log::warn!("leave {} {:?}", dest, e);
+                continue;
+            }
+        };
+        for route in routes {
+            if let Err(e) = context.send_by_key(&net_packet, route.route_key()) {
+                log::warn!("leave {} {:?}", dest, e);
+            }
+        }
+        notified.push(dest);
+    }
+    // 没有路由的对端经服务端转发
+    let peer_list = { device_map.lock().1.clone() };
+    for peer in peer_list.values() {
+        if !peer.status.is_online() || peer.wireguard || notified.contains(&peer.virtual_ip) {
+            continue;
+        }
+        if current_device.is_gateway(&peer.virtual_ip)
+            || context.peer_features(&peer.virtual_ip) & FEATURE_LEAVE == 0
+        {
+            continue;
+        }
+        let net_packet =
+            match leave_packet(client_cipher, current_device.virtual_ip, peer.virtual_ip) {
+                Ok(net_packet) => net_packet,
+                Err(e) => {
+                    log::warn!("leave {} {:?}", peer.virtual_ip, e);
+                    continue;
+                }
+            };
+        if let Err(e) = context.send_default(&net_packet, current_device.connect_server) {
+            log::warn!("leave {} {:?}", peer.virtual_ip, e);
+        }
+    }
+}
+
+fn leave_server(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    server_cipher: &Cipher,
+) -> anyhow::Result<()> {
+    let mut net_packet = NetPacket::new_encrypt([0u8; 12 + ENCRYPTION_RESERVED])?;
+    net_packet.set_default_version();
+    net_packet.set_gateway_flag(true);
+    net_packet.set_protocol(Protocol::Service);
+    net_packet.set_transport_protocol_into(service_packet::Protocol::Leave);
+    net_packet.first_set_ttl(MAX_TTL);
+    net_packet.set_source(current_device.virtual_ip);
+    net_packet.set_destination(current_device.virtual_gateway);
+    server_cipher.encrypt_ipv4(&mut net_packet)?;
+    context.send_default(&net_packet, current_device.connect_server)?;
+    Ok(())
+}
+
+fn leave_packet(
+    client_cipher: &Cipher,
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+) -> anyhow::Result<NetPacket<[u8; 12 + ENCRYPTION_RESERVED]>> {
+    let mut net_packet = NetPacket::new_encrypt([0u8; 12 + ENCRYPTION_RESERVED])?;
+    net_packet.set_default_version();
+    net_packet.set_protocol(Protocol::Control);
+    net_packet.set_transport_protocol(control_packet::Protocol::Leave.into());
+    net_packet.first_set_ttl(MAX_TTL);
+    net_packet.set_source(src);
+    net_packet.set_destination(dest);
+    client_cipher.encrypt_ipv4(&mut net_packet)?;
+    Ok(net_packet)
+}
@@ -0,0 +1,119 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_utils::atomic::AtomicCell;
+
+use crate::channel::context::ChannelContext;
+use crate::cipher::Cipher;
+use crate::handle::CurrentDeviceInfo;
+use crate::nat;
+use crate::nat::NatTest;
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::control_packet::{MigratePacket, FEATURE_MIGRATE};
+use crate::protocol::{control_packet, NetPacket, Protocol};
+use crate::util::Scheduler;
+
+// 本地地址变化后连续通知几次，防止丢包
+const NOTIFY_COUNT: usize = 3;
+
+/// 检测本地ip变化(如wifi切换到有线或移动网络)，变化后从新地址通知直连的客户端迁移路由
+pub fn migrate(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    nat_test: NatTest,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    client_cipher: Cipher,
+) {
+    let local_ip = (nat::local_ipv4_().ok(), nat::local_ipv6_().ok());
+    migrate0(
+        scheduler,
+        context,
+        nat_test,
+        current_device,
+        client_cipher,
+        local_ip,
+        0,
+        0,
+    );
+}
+
+fn migrate0(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    nat_test: NatTest,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    client_cipher: Cipher,
+    last: (Option<Ipv4Addr>, Option<Ipv6Addr>),
+    mut generation: u16,
+    mut remaining: usize,
+) {
+    // 切换网络的过程中可能暂时获取不到地址，保留之前的地址
+    let local_ipv4 = if nat_test.update_local_ipv4 {
+        nat::local_ipv4_().ok().or(last.0)
+    } else {
+        last.0
+    };
+    let local_ip = (local_ipv4, nat::local_ipv6_().ok().or(last.1));
+    if local_ip != last {
+        log::info!("本地地址变化 {:?} -> {:?}", last, local_ip);
+        generation = generation.wrapping_add(1);
+        remaining = NOTIFY_COUNT;
+    }
+    if remaining > 0 {
+        remaining -= 1;
+        if let Err(e) = migrate_notify(&context, &current_device.load(), &client_cipher, generation)
+        {
+            log::warn!("migrate {:?}", e);
+        }
+    }
+    let rs = scheduler.timeout(Duration::from_secs(2), move |s| {
+        migrate0(
+            s,
+            context,
+            nat_test,
+            current_device,
+            client_cipher,
+            local_ip,
+            generation,
+            remaining,
+        )
+    });
+    if !rs {
+        log::info!("定时任务停止");
+    }
+}
+
+fn migrate_notify(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    generation: u16,
+) -> anyhow::Result<()> {
+    for (dest, routes) in context.route_table.route_table() {
+        if current_device.is_gateway(&dest) || context.peer_features(&dest) & FEATURE_MIGRATE == 0 {
+            continue;
+        }
+        let mut net_packet = NetPacket::new_encrypt([0u8; 12 + 10 + ENCRYPTION_RESERVED])?;
+        net_packet.set_default_version();
+        net_packet.set_protocol(Protocol::Control);
+        net_packet.set_transport_protocol(control_packet::Protocol::Migrate.into());
+        net_packet.first_set_ttl(1);
+        net_packet.set_source(current_device.virtual_ip);
+        net_packet.set_destination(dest);
+        let mut migrate_packet = MigratePacket::new(net_packet.payload_mut())?;
+        migrate_packet.set_session_id(context.session_id());
+        migrate_packet.set_generation(generation);
+        client_cipher.encrypt_ipv4(&mut net_packet)?;
+        // socket绑定的是通配地址，从原来的socket发送时系统会选择新的出口地址
+        for route in routes {
+            if !route.is_p2p() || route.route_key().protocol().is_base_tcp() {
+                continue;
+            }
+            if let Err(e) = context.send_by_key(&net_packet, route.route_key()) {
+                log::warn!("migrate {} {:?}", dest, e);
+            }
+        }
+    }
+    Ok(())
+}
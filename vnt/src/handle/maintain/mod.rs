@@ -1,6 +1,7 @@
 mod heartbeat;
 pub use heartbeat::client_relay;
 pub use heartbeat::heartbeat;
+pub use heartbeat::ping_peer;
 
 mod re_nat_type;
 pub use re_nat_type::retrieve_nat_type;
@@ -15,5 +16,47 @@ mod idle;
 pub use idle::idle_gateway;
 pub use idle::idle_route;
 
+mod re_dns;
+pub use re_dns::retrieve_domain;
+
 mod up_status;
 pub use up_status::*;
+
+mod pmtu;
+pub use pmtu::pmtu_discovery;
+
+mod aggregate;
+pub use aggregate::aggregate_flush;
+
+mod reliable;
+pub use reliable::reliable_retransmit;
+
+mod lan_discovery;
+pub use lan_discovery::lan_discovery;
+
+mod migrate;
+pub use migrate::migrate;
+
+mod watchdog;
+pub use watchdog::watchdog;
+
+mod events;
+pub use events::events;
+
+mod leave;
+pub use leave::leave;
+
+mod diag;
+pub(crate) use diag::diag_packet;
+pub use diag::{diag_bandwidth, diag_echo, diag_traceroute, DiagEcho, DiagHop, DiagThroughput};
+
+mod peer_cache;
+pub(crate) use peer_cache::cookie_punch_packet;
+pub use peer_cache::peer_cache;
+
+#[cfg(feature = "peer_key")]
+mod peer_key;
+#[cfg(feature = "peer_key")]
+pub use peer_key::peer_key;
+#[cfg(feature = "peer_key")]
+pub(crate) use peer_key::peer_key_packet;
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crossbeam_utils::atomic::AtomicCell;
+use parking_lot::RwLock;
+use sha2::Digest;
+
+use crate::channel::context::ChannelContext;
+use crate::channel::punch::{NatInfo, NatType};
+use crate::cipher::Cipher;
+use crate::handle::CurrentDeviceInfo;
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::{control_packet, NetPacket, Protocol};
+use crate::util::Scheduler;
+
+const HEAD: &str = "vnt-peer-cache";
+// 超过这个时间(秒)的地址不再使用
+const MAX_AGE: u64 = 7 * 24 * 3600;
+// 地址没变化时每隔这个时间(秒)也重新保存，刷新记录时间
+const REFRESH_AGE: u64 = 3600;
+// 最多缓存的客户端数
+const MAX_PEERS: usize = 256;
+// 每秒检查一次，每30秒保存一次
+const SAVE_INTERVAL: usize = 30;
+
+struct CacheItem {
+    addr: SocketAddr,
+    nat_type: Option<NatType>,
+    time: u64,
+}
+
+/// 缓存对端最近可用的直连地址，重启后连上服务端就直接探测这些地址，不等服务端协商打洞
+pub fn peer_cache(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    peer_nat_info_map: Arc<RwLock<HashMap<Ipv4Addr, NatInfo>>>,
+    client_cipher: Cipher,
+    path: String,
+    token: &str,
+) {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(b"vnt-peer-cache");
+    hasher.update(token.as_bytes());
+    let hash: [u8; 32] = hasher.finalize().into();
+    let token_hash: String = hash[..16].iter().map(|v| format!("{:02x}", v)).collect();
+    let path = PathBuf::from(path);
+    let items = load(&path, &token_hash);
+    log::info!("对端地址缓存 {:?} {}个客户端", path, items.len());
+    let cache = PeerCache {
+        path,
+        token_hash,
+        items,
+        context,
+        current_device,
+        peer_nat_info_map,
+        client_cipher,
+    };
+    cache.run(scheduler, false, 0);
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |v| v.as_secs())
+}
+
+fn load(path: &PathBuf, token_hash: &str) -> HashMap<Ipv4Addr, Vec<CacheItem>> {
+    let mut items: HashMap<Ipv4Addr, Vec<CacheItem>> = HashMap::new();
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            log::info!("读取对端地址缓存 {:?} {:?}", path, e);
+            return items;
+        }
+    };
+    let mut lines = content.lines();
+    let head = format!("{} {}", HEAD, token_hash);
+    // 组网标识不同的缓存不能使用
+    if lines.next() != Some(head.as_str()) {
+        return items;
+    }
+    let now = now();
+    for line in lines {
+        let mut split = line.split_whitespace();
+        let (ip, addr, nat_type, time) = match (
+            split.next().map(Ipv4Addr::from_str),
+            split.next().map(SocketAddr::from_str),
+            split.next(),
+            split.next().map(u64::from_str),
+        ) {
+            (Some(Ok(ip)), Some(Ok(addr)), Some(nat_type), Some(Ok(time))) => {
+                (ip, addr, nat_type, time)
+            }
+            _ => continue,
+        };
+        if now.saturating_sub(time) > MAX_AGE {
+            continue;
+        }
+        let nat_type = match nat_type {
+            "cone" => Some(NatType::Cone),
+            "symmetric" => Some(NatType::Symmetric),
+            _ => None,
+        };
+        items.entry(ip).or_default().push(CacheItem {
+            addr,
+            nat_type,
+            time,
+        });
+    }
+    items
+}
+
+struct PeerCache {
+    path: PathBuf,
+    token_hash: String,
+    items: HashMap<Ipv4Addr, Vec<CacheItem>>,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    peer_nat_info_map: Arc<RwLock<HashMap<Ipv4Addr, NatInfo>>>,
+    client_cipher: Cipher,
+}
+
+impl PeerCache {
+    fn run(mut self, scheduler: &Scheduler, mut probed: bool, count: usize) {
+        let current_device = self.current_device.load();
+        if current_device.status.online() {
+            if !probed {
+                // 注册成功后只探测一次，之后由正常的打洞流程负责
+                probed = true;
+                if let Err(e) = self.probe(&current_device) {
+                    log::warn!("探测缓存地址 {:?}", e);
+                }
+            } else if count % SAVE_INTERVAL == 0 {
+                if self.update(&current_device) {
+                    if let Err(e) = self.save() {
+                        log::warn!("保存对端地址缓存 {:?} {:?}", self.path, e);
+                    }
+                }
+            }
+        }
+        let rs = scheduler.timeout(Duration::from_secs(1), move |s| {
+            self.run(s, probed, count + 1)
+        });
+        if !rs {
+            log::info!("定时任务停止");
+        }
+    }
+    fn probe(&self, current_device: &CurrentDeviceInfo) -> anyhow::Result<()> {
+        let channel_num = self.context.channel_num();
+        let main_len = self.context.main_len();
+        for (ip, items) in &self.items {
+            if *ip == current_device.virtual_ip || current_device.is_gateway(ip) {
+                continue;
+            }
+            if self.context.route_table.no_need_punch(ip) {
+                continue;
+            }
+            // 每个客户端一个cookie，对端回应时原样带回，防止地址被其他人占用时添加错误的路由
+            let cookie = self.context.new_punch_cookie(*ip);
+            let packet =
+                cookie_punch_packet(&self.client_cipher, current_device.virtual_ip, *ip, cookie)?;
+            for item in items {
+                // 对称网络的映射地址和目标有关，本端重启后大概率不可用
+                if item.addr.is_ipv4() && item.nat_type == Some(NatType::Symmetric) {
+                    continue;
+                }
+                log::info!("探测缓存地址 {} {}", ip, item.addr);
+                let range = if item.addr.is_ipv4() {
+                    0..channel_num
+                } else {
+                    channel_num..main_len
+                };
+                for index in range {
+                    if let Err(e) = self
+                        .context
+                        .send_main_udp(index, packet.buffer(), item.addr)
+                    {
+                        log::warn!("{:?} {:?}", item.addr, e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /// 用当前的直连路由更新缓存，有变化时返回true
+    fn update(&mut self, current_device: &CurrentDeviceInfo) -> bool {
+        let now = now();
+        let mut updated = false;
+        for (ip, routes) in self.context.route_table.route_table() {
+            if current_device.is_gateway(&ip) {
+                continue;
+            }
+            let addrs: Vec<SocketAddr> = routes
+                .iter()
+                .filter(|route| route.is_p2p() && !route.route_key().protocol().is_base_tcp())
+                .map(|route| route.addr)
+                .collect();
+            if addrs.is_empty() {
+                continue;
+            }
+            let nat_type = self
+                .peer_nat_info_map
+                .read()
+                .get(&ip)
+                .map(|info| info.nat_type);
+            // 地址变化或者记录的时间太久时需要重新保存
+            updated |= self.items.get(&ip).map_or(true, |items| {
+                items.iter().map(|item| item.addr).ne(addrs.iter().copied())
+                    || items
+                        .iter()
+                        .any(|item| now.saturating_sub(item.time) > REFRESH_AGE)
+            });
+            let items = addrs
+                .into_iter()
+                .map(|addr| CacheItem {
+                    addr,
+                    nat_type,
+                    time: now,
+                })
+                .collect();
+            self.items.insert(ip, items);
+        }
+        let len = self.items.len();
+        self.items.retain(|_, items| {
+            items.retain(|item| now.saturating_sub(item.time) <= MAX_AGE);
+            !items.is_empty()
+        });
+        if self.items.len() > MAX_PEERS {
+            // 丢弃最久没有直连的客户端
+            let mut list: Vec<(Ipv4Addr, u64)> = self
+                .items
+                .iter()
+                .map(|(ip, items)| (*ip, items.iter().map(|v| v.time).max().unwrap_or(0)))
+                .collect();
+            list.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+            for (ip, _) in &list[MAX_PEERS..] {
+                self.items.remove(ip);
+            }
+        }
+        updated || len != self.items.len()
+    }
+    fn save(&self) -> std::io::Result<()> {
+        let mut content = format!("{} {}\n", HEAD, self.token_hash);
+        for (ip, items) in &self.items {
+            for item in items {
+                let nat_type = match item.nat_type {
+                    Some(NatType::Cone) => "cone",
+                    Some(NatType::Symmetric) => "symmetric",
+                    None => "unknown",
+                };
+                content.push_str(&format!(
+                    "{} {} {} {}\n",
+                    ip, item.addr, nat_type, item.time
+                ));
+            }
+        }
+        // 先写临时文件再替换，避免写到一半退出导致缓存损坏
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+/// 带cookie的打洞请求，对端回应时原样带回cookie
+pub(crate) fn cookie_punch_packet(
+    client_cipher: &Cipher,
+    source: Ipv4Addr,
+    dest: Ipv4Addr,
+    cookie: u64,
+) -> anyhow::Result<NetPacket<Vec<u8>>> {
+    let mut packet = NetPacket::new_encrypt(vec![0u8; 12 + 8 + ENCRYPTION_RESERVED])?;
+    packet.set_default_version();
+    packet.first_set_ttl(1);
+    packet.set_protocol(Protocol::Control);
+    packet.set_transport_protocol(control_packet::Protocol::PunchRequest.into());
+    packet.set_source(source);
+    packet.set_destination(dest);
+    packet.payload_mut()[..8].copy_from_slice(&cookie.to_be_bytes());
+    client_cipher.encrypt_ipv4(&mut packet)?;
+    Ok(packet)
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_utils::atomic::AtomicCell;
+use parking_lot::Mutex;
+
+use crate::channel::context::ChannelContext;
+use crate::cipher::Cipher;
+use crate::handle::{CurrentDeviceInfo, PeerDeviceInfo};
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::control_packet::{PeerKeyPacket, FEATURE_PEER_KEY, FEATURE_PQ_KEM};
+use crate::protocol::{control_packet, NetPacket, Protocol, MAX_TTL};
+use crate::util::Scheduler;
+
+/// 定时和开启了密钥协商的客户端握手，握手消息丢失时重发
+pub fn peer_key(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
+    client_cipher: Cipher,
+) {
+    peer_key_loop(
+        scheduler,
+        context,
+        current_device,
+        device_map,
+        client_cipher,
+        0,
+    )
+}
+
+fn peer_key_loop(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
+    client_cipher: Cipher,
+    replayed: u64,
+) {
+    if let Err(e) = peer_key0(
+        &context,
+        &current_device.load(),
+        &device_map,
+        &client_cipher,
+    ) {
+        log::warn!("peer_key {:?}", e);
+    }
+    let replayed = match context.peer_key() {
+        Some(peer_keys) => {
+            let count = peer_keys.replayed();
+            if count != replayed {
+                log::warn!("丢弃重放的数据包 {}个,累计{}个", count - replayed, count);
+            }
+            count
+        }
+        None => replayed,
+    };
+    let rs = scheduler.timeout(Duration::from_secs(1), move |s| {
+        peer_key_loop(
+            s,
+            context,
+            current_device,
+            device_map,
+            client_cipher,
+            replayed,
+        )
+    });
+    if !rs {
+        log::info!("定时任务停止");
+    }
+}
+
+fn peer_key0(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
+    client_cipher: &Cipher,
+) -> anyhow::Result<()> {
+    let peer_keys = match context.peer_key() {
+        Some(peer_keys) => peer_keys,
+        None => return Ok(()),
+    };
+    if current_device.status.offline() {
+        return Ok(());
+    }
+    let peers: Vec<Ipv4Addr> = device_map
+        .lock()
+        .1
+        .values()
+        .filter(|v| {
+            v.status.is_online()
+                && !v.wireguard
+                && context.peer_features(&v.virtual_ip) & FEATURE_PEER_KEY != 0
+        })
+        .map(|v| v.virtual_ip)
+        .collect();
+    // 离线或关闭了密钥协商的客户端不再使用会话密钥
+    peer_keys.retain(|ip| peers.contains(ip));
+    for peer in peers {
+        let peer_hybrid = context.peer_features(&peer) & FEATURE_PQ_KEM != 0;
+        if let Some((kind, msg)) = peer_keys.poll(current_device.virtual_ip, peer, peer_hybrid) {
+            let packet =
+                peer_key_packet(client_cipher, current_device.virtual_ip, peer, kind, &msg)?;
+            if let Err(e) =
+                context.send_ipv4_by_id(&packet, &peer, current_device.connect_server, true)
+            {
+                log::warn!("peer_key {} {:?}", peer, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 握手消息使用组网密码加密
+pub(crate) fn peer_key_packet(
+    client_cipher: &Cipher,
+    source: Ipv4Addr,
+    dest: Ipv4Addr,
+    kind: u8,
+    msg: &[u8],
+) -> anyhow::Result<NetPacket<Vec<u8>>> {
+    let mut net_packet =
+        NetPacket::new_encrypt(vec![0u8; 12 + 1 + msg.len() + ENCRYPTION_RESERVED])?;
+    net_packet.set_default_version();
+    net_packet.set_protocol(Protocol::Control);
+    net_packet.set_transport_protocol(control_packet::Protocol::PeerKey.into());
+    net_packet.first_set_ttl(MAX_TTL);
+    net_packet.set_source(source);
+    net_packet.set_destination(dest);
+    let mut packet = PeerKeyPacket::new(net_packet.payload_mut())?;
+    packet.set_kind(kind);
+    packet.body_mut().copy_from_slice(msg);
+    client_cipher.encrypt_ipv4(&mut net_packet)?;
+    Ok(net_packet)
+}
@@ -0,0 +1,114 @@
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_utils::atomic::AtomicCell;
+
+use crate::channel::context::ChannelContext;
+use crate::channel::pmtu::{ip_udp_head_len, PROBE_MTU};
+use crate::cipher::Cipher;
+use crate::handle::CurrentDeviceInfo;
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::control_packet::{PmtuPacket, FEATURE_PMTU};
+use crate::protocol::{control_packet, NetPacket, Protocol, HEAD_LEN, MAX_TTL};
+use crate::util::Scheduler;
+
+/// 定时探测到其他客户端的直连udp路径的mtu
+pub fn pmtu_discovery(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    client_cipher: Cipher,
+) {
+    if let Err(e) = pmtu_discovery0(&context, &current_device.load(), &client_cipher) {
+        log::warn!("pmtu_discovery {:?}", e);
+    }
+    // 新打通的路径5秒内完成探测，已探测的路径由Pmtu控制重新探测的间隔
+    let rs = scheduler.timeout(Duration::from_secs(5), move |s| {
+        pmtu_discovery(s, context, current_device, client_cipher)
+    });
+    if !rs {
+        log::info!("定时任务停止");
+    }
+}
+
+fn pmtu_discovery0(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    client_cipher: &Cipher,
+) -> anyhow::Result<()> {
+    if current_device.status.offline() {
+        return Ok(());
+    }
+    for (dest_ip, routes) in context.route_table.route_table() {
+        if current_device.is_gateway(&dest_ip) {
+            continue;
+        }
+        // 旧版本不认识探测包
+        if context.peer_features(&dest_ip) & FEATURE_PMTU == 0 {
+            continue;
+        }
+        for route in routes {
+            if !route.is_p2p() || !route.protocol.is_udp() {
+                continue;
+            }
+            let route_key = route.route_key();
+            if !context.route_table.pmtu().start_probe(&route_key) {
+                continue;
+            }
+            let head_len = ip_udp_head_len(&route.addr);
+            let mut packets = Vec::with_capacity(PROBE_MTU.len());
+            for mtu in PROBE_MTU {
+                packets.push(probe_packet(
+                    client_cipher,
+                    current_device.virtual_ip,
+                    dest_ip,
+                    mtu,
+                    (mtu - head_len) as usize,
+                )?);
+            }
+            if let Err(e) = context.send_by_key_df(&packets, route_key) {
+                if e.kind() == io::ErrorKind::Unsupported {
+                    // 当前系统不支持设置不分片
+                    return Ok(());
+                }
+                log::warn!("pmtu probe {:?} {:?}", route_key, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 构建探测包，加密后的长度为len
+fn probe_packet(
+    client_cipher: &Cipher,
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+    mtu: u16,
+    len: usize,
+) -> anyhow::Result<NetPacket<Vec<u8>>> {
+    let mut payload_len = len - HEAD_LEN;
+    // 加密的开销是固定的，第一次按实际开销修正长度
+    for _ in 0..2 {
+        let mut net_packet = NetPacket::new0(
+            HEAD_LEN + payload_len,
+            vec![0u8; HEAD_LEN + payload_len + ENCRYPTION_RESERVED],
+        )?;
+        net_packet.set_default_version();
+        net_packet.set_protocol(Protocol::Control);
+        net_packet.set_transport_protocol(control_packet::Protocol::PmtuProbe.into());
+        net_packet.first_set_ttl(MAX_TTL);
+        net_packet.set_source(src);
+        net_packet.set_destination(dest);
+        let mut probe_packet = PmtuPacket::new(net_packet.payload_mut())?;
+        probe_packet.set_mtu(mtu);
+        client_cipher.encrypt_ipv4(&mut net_packet)?;
+        let data_len = net_packet.data_len();
+        if data_len <= len {
+            return Ok(net_packet);
+        }
+        payload_len -= data_len - len;
+    }
+    Err(anyhow::anyhow!("probe packet len {} invalid", len))
+}
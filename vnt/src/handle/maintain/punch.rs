@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::Ipv4Addr;
-use std::ops::{Div, Mul};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::anyhow;
 use crossbeam_utils::atomic::AtomicCell;
@@ -13,11 +13,12 @@ use protobuf::Message;
 use rand::prelude::SliceRandom;
 
 use crate::channel::context::ChannelContext;
-use crate::channel::punch::{NatInfo, NatType, Punch};
+use crate::channel::punch::{NatInfo, NatType, Punch, PunchSettings};
+use crate::channel::reliable::ReliableTarget;
 use crate::cipher::Cipher;
 use crate::handle::{CurrentDeviceInfo, PeerDeviceInfo};
 use crate::nat::NatTest;
-use crate::proto::message::{PunchInfo, PunchNatType};
+use crate::proto::message::{PunchInfo, PunchNatBehavior, PunchNatType};
 use crate::protocol::body::ENCRYPTION_RESERVED;
 use crate::protocol::{control_packet, other_turn_packet, NetPacket, Protocol, MAX_TTL};
 use crate::util::Scheduler;
@@ -28,6 +29,9 @@ pub struct PunchSender {
     sender_peer: SyncSender<(Ipv4Addr, NatInfo)>,
     sender_cone_self: SyncSender<(Ipv4Addr, NatInfo)>,
     sender_cone_peer: SyncSender<(Ipv4Addr, NatInfo)>,
+    // 正在进行的打洞数
+    active: Arc<AtomicUsize>,
+    settings: PunchSettings,
 }
 
 impl PunchSender {
@@ -54,7 +58,17 @@ impl PunchSender {
                 }
             }
         };
-        sender.try_send((ip, info)).is_ok()
+        // 超过并发上限时放弃，对端会在退避后重新发起
+        if self.active.fetch_add(1, Ordering::AcqRel) >= self.settings.concurrency() {
+            self.active.fetch_sub(1, Ordering::AcqRel);
+            log::info!("打洞并发数已满,放弃:{}", ip);
+            return false;
+        }
+        if sender.try_send((ip, info)).is_err() {
+            self.active.fetch_sub(1, Ordering::AcqRel);
+            return false;
+        }
+        true
     }
 }
 
@@ -63,9 +77,12 @@ pub struct PunchReceiver {
     receiver_self: Receiver<(Ipv4Addr, NatInfo)>,
     receiver_cone_peer: Receiver<(Ipv4Addr, NatInfo)>,
     receiver_cone_self: Receiver<(Ipv4Addr, NatInfo)>,
+    active: Arc<AtomicUsize>,
 }
 
-pub fn punch_channel() -> (PunchSender, PunchReceiver) {
+/// 同时进行的打洞数上限从settings中读取
+pub fn punch_channel(settings: PunchSettings) -> (PunchSender, PunchReceiver) {
+    let active = Arc::new(AtomicUsize::new(0));
     let (sender_self, receiver_self) = sync_channel(0);
     let (sender_peer, receiver_peer) = sync_channel(0);
     let (sender_cone_peer, receiver_cone_peer) = sync_channel(0);
@@ -76,12 +93,15 @@ pub fn punch_channel() -> (PunchSender, PunchReceiver) {
             sender_peer,
             sender_cone_peer,
             sender_cone_self,
+            active: active.clone(),
+            settings,
         },
         PunchReceiver {
             receiver_peer,
             receiver_self,
             receiver_cone_peer,
             receiver_cone_self,
+            active,
         },
     )
 }
@@ -95,9 +115,14 @@ pub fn punch(
     client_cipher: Cipher,
     receiver: PunchReceiver,
     punch: Punch,
+    settings: PunchSettings,
 ) {
     let punch_record = Arc::new(Mutex::new(HashMap::new()));
-    let last_punch_record = HashMap::new();
+    let schedule = PunchSchedule {
+        settings,
+        last_punch_record: HashMap::new(),
+        traffic_record: HashMap::new(),
+    };
     punch_request(
         scheduler,
         context,
@@ -107,17 +132,26 @@ pub fn punch(
         client_cipher.clone(),
         0,
         punch_record.clone(),
-        last_punch_record,
+        schedule,
     );
+    let receiver_active = receiver.active;
     let f = |receiver: Receiver<(Ipv4Addr, NatInfo)>| {
         let punch = punch.clone();
         let current_device = current_device.clone();
         let client_cipher = client_cipher.clone();
         let punch_record = punch_record.clone();
+        let active = receiver_active.clone();
         thread::Builder::new()
             .name("punch".into())
             .spawn(move || {
-                punch_start(receiver, punch, current_device, client_cipher, punch_record);
+                punch_start(
+                    receiver,
+                    punch,
+                    current_device,
+                    client_cipher,
+                    punch_record,
+                    active,
+                );
             })
             .expect("punch");
     };
@@ -134,6 +168,7 @@ fn punch_start(
     current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
     client_cipher: Cipher,
     punch_record: Arc<Mutex<HashMap<Ipv4Addr, usize>>>,
+    active: Arc<AtomicUsize>,
 ) {
     while let Ok((peer_ip, nat_info)) = receiver.recv() {
         let mut packet = NetPacket::new_encrypt([0u8; 12 + ENCRYPTION_RESERVED]).unwrap();
@@ -157,11 +192,10 @@ fn punch_start(
 
         if let Err(e) = client_cipher.encrypt_ipv4(&mut packet) {
             log::error!("{:?}", e);
-            continue;
-        }
-        if let Err(e) = punch.punch(packet.buffer(), peer_ip, nat_info, count < 2, count) {
+        } else if let Err(e) = punch.punch(packet.buffer(), peer_ip, nat_info, count < 2, count) {
             log::warn!("{:?}", e)
         }
+        active.fetch_sub(1, Ordering::AcqRel);
     }
 }
 
@@ -175,7 +209,7 @@ fn punch_request(
     client_cipher: Cipher,
     count: usize,
     punch_record: Arc<Mutex<HashMap<Ipv4Addr, usize>>>,
-    mut last_punch_record: HashMap<Ipv4Addr, PunchRecordItem>,
+    mut schedule: PunchSchedule,
 ) {
     let curr = current_device.load();
     let secs = if curr.status.online() {
@@ -186,13 +220,14 @@ fn punch_request(
             curr,
             &client_cipher,
             &punch_record,
-            &mut last_punch_record,
+            &mut schedule,
             count,
         ) {
             log::warn!("{:?}", e)
         }
-        let sleep_time = [6, 7];
-        Duration::from_secs(sleep_time[count % sleep_time.len()])
+        // 间隔交替变化，避免和对端同时发起
+        let interval = schedule.settings.interval();
+        interval + interval / 6 * (count % 2) as u32
     } else {
         Duration::from_secs(5)
     };
@@ -206,7 +241,7 @@ fn punch_request(
             client_cipher,
             count + 1,
             punch_record,
-            last_punch_record,
+            schedule,
         );
     });
     if !rs {
@@ -215,11 +250,51 @@ fn punch_request(
 }
 #[derive(Copy, Clone, Default)]
 struct PunchRecordItem {
-    pub punch_record: usize,
+    pub last_time: Option<Instant>,
     pub last_p2p_num: usize,
 }
 
-/// 随机对需要打洞的客户端发起打洞请求
+struct PunchSchedule {
+    // 发起打洞协商的间隔从这里读取
+    settings: PunchSettings,
+    last_punch_record: HashMap<Ipv4Addr, PunchRecordItem>,
+    // 上一轮各客户端的累计流量
+    traffic_record: HashMap<Ipv4Addr, u64>,
+}
+
+// 打洞退避的上限
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+// 已有p2p通道但数量不够时的退避上限
+const MAX_BACKOFF_P2P: Duration = Duration::from_secs(30 * 60);
+// 一轮之间的流量超过这个值才算有数据往来，排除心跳包
+const ACTIVE_TRAFFIC: u64 = 4096;
+
+/// 两轮之间有数据往来的客户端，没有开启流量统计时为空
+fn active_peers(
+    context: &ChannelContext,
+    traffic_record: &mut HashMap<Ipv4Addr, u64>,
+) -> HashSet<Ipv4Addr> {
+    let mut current: HashMap<Ipv4Addr, u64> = HashMap::new();
+    for meter in [&context.up_traffic_meter, &context.down_traffic_meter]
+        .into_iter()
+        .flatten()
+    {
+        for (ip, total) in meter.get_all().1 {
+            *current.entry(ip).or_default() += total;
+        }
+    }
+    let active = current
+        .iter()
+        .filter(|(ip, total)| {
+            **total >= traffic_record.get(ip).copied().unwrap_or(0) + ACTIVE_TRAFFIC
+        })
+        .map(|(ip, _)| *ip)
+        .collect();
+    *traffic_record = current;
+    active
+}
+
+/// 对需要打洞的客户端发起打洞请求，有数据往来的优先，其余随机
 fn punch0(
     context: &ChannelContext,
     nat_test: &NatTest,
@@ -227,11 +302,13 @@ fn punch0(
     current_device: CurrentDeviceInfo,
     client_cipher: &Cipher,
     punch_record: &Mutex<HashMap<Ipv4Addr, usize>>,
-    last_punch_record: &mut HashMap<Ipv4Addr, PunchRecordItem>,
+    schedule: &mut PunchSchedule,
     total_count: usize,
 ) -> anyhow::Result<()> {
     let nat_info = nat_test.nat_info();
+    // 只有ipv6的网络获取不到公网ipv4，直接使用ipv6打洞
     if total_count < 10
+        && nat_info.ipv6.is_none()
         && (nat_info.public_ips.is_empty()
             || nat_info.public_ports.is_empty()
             || nat_info.public_ports[0] == 0
@@ -250,21 +327,27 @@ fn punch0(
         .cloned()
         .collect();
     list.shuffle(&mut rand::thread_rng());
+    let active = active_peers(context, &mut schedule.traffic_record);
+    list.sort_by_key(|info| !active.contains(&info.virtual_ip));
     for info in list {
         if info.status.is_offline() {
             // 客户端掉线了要重置打洞记录
             punch_record.lock().remove(&info.virtual_ip);
             continue;
         }
+        if context
+            .use_channel_type_of(&info.virtual_ip)
+            .is_only_relay()
+        {
+            continue;
+        }
         let mut punch_count = punch_record
             .lock()
             .get(&info.virtual_ip)
             .cloned()
-            .unwrap_or(0)
-            .mul(2)
-            .div(3);
+            .unwrap_or(0);
         let p2p_num = context.route_table.p2p_num(&info.virtual_ip);
-        let mut max_punch_interval = 50;
+        let mut max_backoff = MAX_BACKOFF;
         if p2p_num > 0 {
             if p2p_num >= context.channel_num() {
                 //通道数满足要求，不再打洞
@@ -275,20 +358,32 @@ fn punch0(
             }
             //有p2p通道，但是通道数量不够，则继续打洞
             // 提高等待上限
-            max_punch_interval = 300;
+            max_backoff = MAX_BACKOFF_P2P;
         }
         // 能发起打洞的前提是自己空闲，这里会间隔5秒以上发起一次打洞，所以假定上一轮打洞已结束
-        let last_punch = last_punch_record.entry(info.virtual_ip).or_default();
+        let last_punch = schedule
+            .last_punch_record
+            .entry(info.virtual_ip)
+            .or_default();
         if last_punch.last_p2p_num > p2p_num {
             // 打的洞掉线了,需要重置重新打
             punch_record.lock().remove(&info.virtual_ip);
             punch_count = 0;
+            last_punch.last_time = None;
         }
 
-        // 梯度增加打洞时间间隔
-        if total_count > last_punch.punch_record + punch_count.min(max_punch_interval) {
-            // 记录打洞周期，抑制下一次打洞，从而递减打洞频率
-            last_punch.punch_record = total_count;
+        // 按打洞次数指数增加打洞时间间隔
+        let backoff = schedule
+            .settings
+            .interval()
+            .saturating_mul(1u32 << punch_count.min(16))
+            .min(max_backoff);
+        if last_punch
+            .last_time
+            .map_or(true, |time| time.elapsed() >= backoff)
+        {
+            // 记录打洞时间，抑制下一次打洞，从而递减打洞频率
+            last_punch.last_time = Some(Instant::now());
             last_punch.last_p2p_num = p2p_num;
             let packet = punch_packet(
                 client_cipher,
@@ -303,7 +398,10 @@ fn punch0(
                 punch_count,
                 total_count,
             );
-            context.send_default(&packet, current_device.connect_server)?;
+            context.send_reliable(
+                &packet,
+                ReliableTarget::Server(current_device.connect_server),
+            )?;
             break;
         }
     }
@@ -335,6 +433,15 @@ fn punch_packet(
         punch_reply.ipv6 = ipv6.octets().to_vec();
     }
     punch_reply.nat_type = protobuf::EnumOrUnknown::new(PunchNatType::from(nat_info.nat_type));
+    punch_reply.mapping_behavior =
+        protobuf::EnumOrUnknown::new(PunchNatBehavior::from(nat_info.mapping));
+    punch_reply.filtering_behavior =
+        protobuf::EnumOrUnknown::new(PunchNatBehavior::from(nat_info.filtering));
+    punch_reply.candidates = nat_info
+        .gather_candidates()
+        .iter()
+        .map(|c| c.into())
+        .collect();
     log::info!("请求打洞={:?}", punch_reply);
     let bytes = punch_reply
         .write_to_bytes()
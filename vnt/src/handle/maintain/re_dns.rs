@@ -0,0 +1,110 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_utils::atomic::AtomicCell;
+
+use crate::channel::context::ChannelContext;
+use crate::handle::handshaker::Handshake;
+use crate::handle::{BaseConfigInfo, ConnectStatus, CurrentDeviceInfo};
+use crate::util::{address_choose, dns_query_all, nat64_synthesize, Scheduler};
+
+/// 定时重新解析服务端域名，地址变化后使用新地址重新握手
+pub fn retrieve_domain(
+    scheduler: &Scheduler,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    config: BaseConfigInfo,
+    handshake: Handshake,
+    interval: Duration,
+) {
+    if interval.is_zero() {
+        return;
+    }
+    let rs = scheduler.timeout(interval, move |s| {
+        retrieve_domain0(
+            context.clone(),
+            current_device.clone(),
+            config.clone(),
+            handshake.clone(),
+        );
+        retrieve_domain(s, context, current_device, config, handshake, interval)
+    });
+    if !rs {
+        log::info!("定时任务停止");
+    }
+}
+
+fn retrieve_domain0(
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    config: BaseConfigInfo,
+    handshake: Handshake,
+) {
+    let protocol = context.main_protocol();
+    if !protocol.is_transport() {
+        return;
+    }
+    if current_device.load().status.offline() {
+        // 离线时由idle_gateway重新解析
+        return;
+    }
+    let server_addr = config.server_addr.current();
+    if SocketAddr::from_str(&server_addr).is_ok() {
+        // ip地址不需要重新解析
+        return;
+    }
+    let rs = thread::Builder::new()
+        .name("reDns".into())
+        .spawn(move || {
+            let addrs = match dns_query_all(
+                &server_addr,
+                config.name_servers.clone(),
+                config.dns_mode,
+            ) {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    log::warn!("域名解析失败:{:?},domain={}", e, server_addr);
+                    return;
+                }
+            };
+            // 当前地址可能是NAT64合成的，按同样的方式合成后再比较
+            let addrs = nat64_synthesize(addrs);
+            let current_dev = current_device.load();
+            if addrs.contains(&current_dev.connect_server) {
+                return;
+            }
+            let addr = match address_choose(
+                addrs,
+                config.server_prefer,
+                protocol,
+                context.obfs(),
+            ) {
+                Ok(addr) => addr,
+                Err(e) => {
+                    log::warn!("域名地址选择失败:{:?},domain={}", e, server_addr);
+                    return;
+                }
+            };
+            let mut new_dev = current_dev;
+            new_dev.connect_server = addr;
+            new_dev.status = ConnectStatus::Connecting;
+            let rs = current_device.compare_exchange(current_dev, new_dev);
+            log::info!(
+                "服务端地址变化,旧地址:{}，新地址:{},替换结果:{}",
+                current_dev.connect_server,
+                addr,
+                rs.is_ok()
+            );
+            if rs.is_ok() {
+                if let Err(e) = handshake.send(&context, config.server_secret, addr) {
+                    log::warn!("{:?}", e);
+                }
+            }
+        });
+    if let Err(e) = rs {
+        log::warn!("{:?}", e);
+    }
+}
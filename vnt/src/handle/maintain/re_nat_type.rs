@@ -35,8 +35,13 @@ fn retrieve_nat_type0(
                     None
                 };
                 let local_ipv6 = nat::local_ipv6();
-                match nat_test.re_test(local_ipv4, local_ipv6, context.default_interface()) {
-                    Ok(nat_info) => {
+                let rs = nat_test.re_test(local_ipv4, local_ipv6, context.default_interface());
+                // 端口映射成功时按锥形网络处理，需要在切换前完成
+                #[cfg(feature = "upnp")]
+                nat_test.reset_upnp();
+                match rs {
+                    Ok(_) => {
+                        let nat_info = nat_test.nat_info();
                         log::info!("当前nat信息:{:?}", nat_info);
                         if let Err(e) = context.switch(nat_info.nat_type, &udp_socket_sender) {
                             log::warn!("{:?}", e);
@@ -46,8 +51,6 @@ fn retrieve_nat_type0(
                         log::warn!("nat re_test {:?}", e);
                     }
                 };
-                #[cfg(feature = "upnp")]
-                nat_test.reset_upnp();
                 log::info!("刷新nat结束")
             }
         })
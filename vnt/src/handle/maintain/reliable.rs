@@ -0,0 +1,15 @@
+use std::time::Duration;
+
+use crate::channel::context::ChannelContext;
+use crate::util::Scheduler;
+
+/// 定时重传超时未确认的控制数据
+pub fn reliable_retransmit(scheduler: &Scheduler, context: ChannelContext) {
+    context.retransmit_reliable();
+    let rs = scheduler.timeout(Duration::from_millis(100), move |s| {
+        reliable_retransmit(s, context)
+    });
+    if !rs {
+        log::info!("定时任务停止");
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crossbeam_utils::atomic::AtomicCell;
+
+use crate::channel::context::ChannelContext;
+use crate::handle::{change_status, ConnectStatus, CurrentDeviceInfo};
+#[cfg(feature = "integrated_tun")]
+use crate::tun_tap_device::tun_create_helper::TunDeviceHelper;
+use crate::util::StopManager;
+use crate::{ErrorInfo, ErrorType, VntCallback};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 恢复措施，卡住时逐级升级，每一级执行后等待一个超时时间仍未恢复则执行下一级
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum Stage {
+    Healthy,
+    // 重新握手和注册
+    Handshake,
+    // 断开服务端连接后重新连接
+    Reconnect,
+    // 重新注册并重建虚拟网卡
+    RecreateDevice,
+    // 停止运行，由服务管理器重启进程
+    Exit,
+}
+
+impl Stage {
+    fn next(self) -> Self {
+        match self {
+            Stage::Healthy => Stage::Handshake,
+            Stage::Handshake => Stage::Reconnect,
+            Stage::Reconnect => Stage::RecreateDevice,
+            Stage::RecreateDevice | Stage::Exit => Stage::Exit,
+        }
+    }
+}
+
+struct Watchdog {
+    stop_manager: StopManager,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    #[cfg(feature = "integrated_tun")]
+    tun_device_helper: TunDeviceHelper,
+    timeout: Duration,
+    stage: Stage,
+    stage_time: Instant,
+    // 开始断开服务端的时间
+    offline_time: Option<Instant>,
+}
+
+/// 看门狗，检测服务端长时间没有数据、tun读取循环卡住和线程崩溃，
+/// 依次尝试重新握手、重建连接、重建网卡，都无效时通过回调报告错误，然后停止运行并设置expired。
+/// 使用单独的线程，定时任务线程卡住时也能工作
+pub fn watchdog<Call: VntCallback>(
+    stop_manager: &StopManager,
+    context: ChannelContext,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    #[cfg(feature = "integrated_tun")] tun_device_helper: TunDeviceHelper,
+    timeout: Duration,
+    expired: Arc<AtomicBool>,
+    call: Call,
+) -> anyhow::Result<()> {
+    let (sender, receiver) = std::sync::mpsc::channel::<()>();
+    let worker = stop_manager.add_listener("watchdog".into(), move || {
+        let _ = sender.send(());
+    })?;
+    let mut watchdog = Watchdog {
+        stop_manager: stop_manager.clone(),
+        context,
+        current_device,
+        #[cfg(feature = "integrated_tun")]
+        tun_device_helper,
+        timeout,
+        stage: Stage::Healthy,
+        stage_time: Instant::now(),
+        offline_time: None,
+    };
+    std::thread::Builder::new()
+        .name("watchdog".into())
+        .spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(CHECK_INTERVAL) {
+                if let Err(e) = watchdog.check() {
+                    expired.store(true, Ordering::Release);
+                    call.error(e);
+                    worker.stop_all();
+                    return;
+                }
+            }
+            drop(worker);
+        })?;
+    Ok(())
+}
+
+impl Watchdog {
+    /// 返回错误表示无法恢复，需要退出
+    fn check(&mut self) -> Result<(), ErrorInfo> {
+        let panicked = self.stop_manager.panicked();
+        if !panicked.is_empty() {
+            // 崩溃的线程无法单独恢复
+            log::error!("看门狗:任务崩溃 {:?}", panicked);
+            return Err(ErrorInfo::new_msg(
+                ErrorType::WatchdogExpired,
+                format!("panicked {:?}", panicked),
+            ));
+        }
+        let current_device = self.current_device.load();
+        let server_stalled = if current_device.status.online() {
+            self.offline_time = None;
+            self.context
+                .last_read(&current_device.virtual_gateway)
+                .map_or(true, |time| time > self.timeout)
+        } else {
+            self.offline_time.get_or_insert_with(Instant::now).elapsed() > self.timeout
+        };
+        #[cfg(all(feature = "integrated_tun", unix))]
+        let device_stalled = self
+            .tun_device_helper
+            .device_idle()
+            .map_or(false, |time| time > self.timeout);
+        #[cfg(not(all(feature = "integrated_tun", unix)))]
+        let device_stalled = false;
+        if !server_stalled && !device_stalled {
+            if self.stage != Stage::Healthy {
+                log::info!("看门狗:已恢复,最后执行的是{:?}", self.stage);
+                self.stage = Stage::Healthy;
+            }
+            return Ok(());
+        }
+        if self.stage != Stage::Healthy && self.stage_time.elapsed() < self.timeout {
+            // 等待上一级措施生效
+            return Ok(());
+        }
+        let mut stage = self.stage.next();
+        if device_stalled && stage < Stage::RecreateDevice {
+            // 网卡卡住时重新握手没有用
+            stage = Stage::RecreateDevice;
+        }
+        #[cfg(not(feature = "integrated_tun"))]
+        if stage == Stage::RecreateDevice {
+            stage = Stage::Exit;
+        }
+        log::warn!(
+            "看门狗:服务端卡住={},网卡卡住={},执行{:?}",
+            server_stalled,
+            device_stalled,
+            stage
+        );
+        self.stage = stage;
+        self.stage_time = Instant::now();
+        match stage {
+            Stage::Healthy => {}
+            Stage::Handshake => {
+                change_status(&self.current_device, ConnectStatus::Connecting);
+            }
+            Stage::Reconnect => {
+                self.context.close_server(current_device.connect_server);
+                change_status(&self.current_device, ConnectStatus::Connecting);
+            }
+            Stage::RecreateDevice => {
+                // 注册成功时重建网卡
+                #[cfg(feature = "integrated_tun")]
+                self.tun_device_helper.request_recreate();
+                self.context.close_server(current_device.connect_server);
+                change_status(&self.current_device, ConnectStatus::Connecting);
+            }
+            Stage::Exit => {
+                // 一直连不上服务端和其他卡住的情况分开报告
+                let error_type = if server_stalled && current_device.status.offline() {
+                    ErrorType::ServerUnreachable
+                } else {
+                    ErrorType::WatchdogExpired
+                };
+                return Err(ErrorInfo::new_msg(
+                    error_type,
+                    format!(
+                        "server stalled={},device stalled={}",
+                        server_stalled, device_stalled
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
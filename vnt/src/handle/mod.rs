@@ -1,6 +1,11 @@
 use crate::channel::socket::LocalInterface;
+use crate::identity::totp::TotpSecret;
+use crate::identity::IdentityConfig;
+use crate::util::{AddressPrefer, DnsMode};
 use crossbeam_utils::atomic::AtomicCell;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 pub mod callback;
 mod extension;
@@ -13,6 +18,38 @@ pub mod tun_tap;
 
 const SELF_IP: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 2);
 const GATEWAY_IP: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 1);
+// 服务端没有分配虚拟ipv6时，使用 fd76:6e74::/96 + 虚拟ipv4
+const MAPPED_IPV6_PREFIX: [u16; 6] = [0xfd76, 0x6e74, 0, 0, 0, 0];
+pub const MAPPED_IPV6_PREFIX_LEN: u8 = 96;
+
+/// 由虚拟ipv4映射出的ula地址
+pub fn mapped_ipv6(ip: Ipv4Addr) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    for (index, v) in MAPPED_IPV6_PREFIX.iter().enumerate() {
+        octets[index * 2..index * 2 + 2].copy_from_slice(&v.to_be_bytes());
+    }
+    octets[12..].copy_from_slice(&ip.octets());
+    Ipv6Addr::from(octets)
+}
+
+/// 映射地址对应的虚拟ipv4
+pub fn mapped_ipv4(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    if ip.segments()[..6] != MAPPED_IPV6_PREFIX {
+        return None;
+    }
+    let octets = ip.octets();
+    Some(Ipv4Addr::new(
+        octets[12], octets[13], octets[14], octets[15],
+    ))
+}
+
+/// 服务端分配的ipv6地址，没有分配时使用映射地址
+pub fn virtual_ipv6(bytes: &[u8], virtual_ip: Ipv4Addr) -> Ipv6Addr {
+    match <[u8; 16]>::try_from(bytes) {
+        Ok(v) => Ipv6Addr::from(v),
+        Err(_) => mapped_ipv6(virtual_ip),
+    }
+}
 
 pub fn now_time() -> u64 {
     let now = std::time::SystemTime::now();
@@ -31,6 +68,11 @@ pub struct PeerDeviceInfo {
     pub client_secret: bool,
     pub client_secret_hash: Vec<u8>,
     pub wireguard: bool,
+    pub virtual_ipv6: Ipv6Addr,
+    // 对端注册时使用的身份公钥，没有开启身份认证时为空
+    pub identity_public_key: Vec<u8>,
+    // 对端宣告的局域网网段和掩码
+    pub advertise_routes: Vec<(u32, u32)>,
 }
 
 impl PeerDeviceInfo {
@@ -41,6 +83,9 @@ impl PeerDeviceInfo {
         client_secret: bool,
         client_secret_hash: Vec<u8>,
         wireguard: bool,
+        virtual_ipv6: Ipv6Addr,
+        identity_public_key: Vec<u8>,
+        advertise_routes: Vec<(u32, u32)>,
     ) -> Self {
         Self {
             virtual_ip,
@@ -49,10 +94,54 @@ impl PeerDeviceInfo {
             client_secret,
             client_secret_hash,
             wireguard,
+            virtual_ipv6,
+            identity_public_key,
+            advertise_routes,
         }
     }
 }
 
+/// 连续多少次连接失败后切换服务端地址
+const SWITCH_SERVER_COUNT: usize = 3;
+
+/// 服务端地址列表，当前地址连续多次连接失败后切换到下一个
+#[derive(Clone, Debug)]
+pub struct ServerAddress {
+    list: Arc<Vec<String>>,
+    index: Arc<AtomicUsize>,
+    fail_count: Arc<AtomicUsize>,
+}
+
+impl ServerAddress {
+    pub fn new(list: Vec<String>) -> Self {
+        assert!(!list.is_empty());
+        Self {
+            list: Arc::new(list),
+            index: Default::default(),
+            fail_count: Default::default(),
+        }
+    }
+    pub fn current(&self) -> String {
+        self.list[self.index.load(Ordering::Relaxed) % self.list.len()].clone()
+    }
+    /// 记录一次连接失败，返回是否切换了地址
+    pub fn fail(&self) -> bool {
+        if self.list.len() <= 1 {
+            return false;
+        }
+        let count = self.fail_count.fetch_add(1, Ordering::Relaxed) + 1;
+        if count >= SWITCH_SERVER_COUNT {
+            self.fail_count.store(0, Ordering::Relaxed);
+            self.index.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+    pub fn success(&self) {
+        self.fail_count.store(0, Ordering::Relaxed);
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BaseConfigInfo {
     pub name: String,
@@ -61,17 +150,39 @@ pub struct BaseConfigInfo {
     pub client_secret_hash: Option<[u8; 16]>,
     pub server_secret: bool,
     pub device_id: String,
-    pub server_addr: String,
+    pub server_addr: ServerAddress,
+    pub server_prefer: AddressPrefer,
     pub name_servers: Vec<String>,
     pub mtu: u32,
     #[cfg(feature = "integrated_tun")]
     #[cfg(target_os = "windows")]
     pub tap: bool,
     #[cfg(feature = "integrated_tun")]
-    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    #[cfg(target_os = "windows")]
+    pub wintun_path: Option<String>,
+    #[cfg(feature = "integrated_tun")]
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    pub layer2: bool,
+    #[cfg(feature = "integrated_tun")]
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
     pub device_name: Option<String>,
     pub allow_wire_guard: bool,
     pub default_interface: LocalInterface,
+    pub identity: Option<IdentityConfig>,
+    pub totp_secret: Option<TotpSecret>,
+    // 本机宣告的局域网网段和掩码
+    pub advertise_routes: Vec<(u32, u32)>,
+    // 指定的虚拟ip被占用时允许服务端分配其他ip
+    pub allow_ip_change: bool,
+    // 期望的虚拟网段和掩码
+    pub network: Option<(u32, u32)>,
+    pub dns_mode: DnsMode,
 }
 
 impl BaseConfigInfo {
@@ -82,17 +193,36 @@ impl BaseConfigInfo {
         client_secret_hash: Option<[u8; 16]>,
         server_secret: bool,
         device_id: String,
-        server_addr: String,
+        server_addr: ServerAddress,
+        server_prefer: AddressPrefer,
         name_servers: Vec<String>,
         mtu: u32,
         #[cfg(feature = "integrated_tun")]
         #[cfg(target_os = "windows")]
         tap: bool,
         #[cfg(feature = "integrated_tun")]
-        #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+        #[cfg(target_os = "windows")]
+        wintun_path: Option<String>,
+        #[cfg(feature = "integrated_tun")]
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
+        layer2: bool,
+        #[cfg(feature = "integrated_tun")]
+        #[cfg(any(
+            target_os = "windows",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        ))]
         device_name: Option<String>,
         allow_wire_guard: bool,
         default_interface: LocalInterface,
+        identity: Option<IdentityConfig>,
+        totp_secret: Option<TotpSecret>,
+        advertise_routes: Vec<(u32, u32)>,
+        allow_ip_change: bool,
+        network: Option<(u32, u32)>,
+        dns_mode: DnsMode,
     ) -> Self {
         Self {
             name,
@@ -102,16 +232,35 @@ impl BaseConfigInfo {
             server_secret,
             device_id,
             server_addr,
+            server_prefer,
             name_servers,
             mtu,
             #[cfg(feature = "integrated_tun")]
             #[cfg(target_os = "windows")]
             tap,
             #[cfg(feature = "integrated_tun")]
-            #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+            #[cfg(target_os = "windows")]
+            wintun_path,
+            #[cfg(feature = "integrated_tun")]
+            #[cfg(any(target_os = "windows", target_os = "linux"))]
+            layer2,
+            #[cfg(feature = "integrated_tun")]
+            #[cfg(any(
+                target_os = "windows",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd"
+            ))]
             device_name,
             allow_wire_guard,
             default_interface,
+            identity,
+            totp_secret,
+            advertise_routes,
+            allow_ip_change,
+            network,
+            dns_mode,
         }
     }
 }
@@ -176,6 +325,9 @@ pub struct CurrentDeviceInfo {
     pub virtual_network: Ipv4Addr,
     //直接广播地址
     pub broadcast_ip: Ipv4Addr,
+    //本机虚拟IPv6
+    pub virtual_ipv6: Ipv6Addr,
+    pub virtual_ipv6_prefix: u8,
     //链接的服务器地址
     pub connect_server: SocketAddr,
     //连接状态
@@ -201,6 +353,8 @@ impl CurrentDeviceInfo {
             virtual_gateway,
             virtual_network,
             broadcast_ip,
+            virtual_ipv6: mapped_ipv6(virtual_ip),
+            virtual_ipv6_prefix: MAPPED_IPV6_PREFIX_LEN,
             connect_server,
             status: ConnectStatus::Connecting,
         }
@@ -212,6 +366,8 @@ impl CurrentDeviceInfo {
             virtual_netmask: Ipv4Addr::UNSPECIFIED,
             virtual_network: Ipv4Addr::UNSPECIFIED,
             broadcast_ip: Ipv4Addr::UNSPECIFIED,
+            virtual_ipv6: Ipv6Addr::UNSPECIFIED,
+            virtual_ipv6_prefix: 0,
             connect_server,
             status: ConnectStatus::Connecting,
         }
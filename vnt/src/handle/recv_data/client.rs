@@ -1,30 +1,41 @@
 use anyhow::anyhow;
 use std::collections::HashMap;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
+use std::time::Instant;
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use protobuf::Message;
 
 use packet::icmp::{icmp, Kind};
 use packet::ip::ipv4;
 use packet::ip::ipv4::packet::IpV4Packet;
 
+use crate::acl::{ethernet_ip, IpInfo};
 use crate::channel::context::ChannelContext;
-use crate::channel::punch::NatInfo;
+use crate::channel::diag::DiagResponse;
+use crate::channel::pmtu::clamp_mss;
+use crate::channel::punch::{Candidate, NatInfo};
+use crate::channel::reliable::ReliableTarget;
+use crate::channel::stats::DropReason;
 use crate::channel::{Route, RouteKey};
 use crate::cipher::Cipher;
-use crate::external_route::AllowExternalRoute;
+use crate::external_route::{AllowExternalRoute, ExternalRoute};
 use crate::handle::extension::handle_extension_tail;
 use crate::handle::maintain::PunchSender;
 use crate::handle::recv_data::PacketHandler;
-use crate::handle::CurrentDeviceInfo;
+use crate::handle::{mapped_ipv4, mapped_ipv6, CurrentDeviceInfo, PeerDeviceInfo};
 #[cfg(feature = "ip_proxy")]
 use crate::ip_proxy::{IpProxyMap, ProxyHandler};
 use crate::nat::NatTest;
-use crate::proto::message::{PunchInfo, PunchNatType};
+use crate::peer_key::encrypt_to_peer;
+use crate::proto::message::{PunchInfo, PunchNatBehavior, PunchNatType};
 use crate::protocol::body::ENCRYPTION_RESERVED;
-use crate::protocol::control_packet::ControlPacket;
+#[cfg(feature = "peer_key")]
+use crate::protocol::control_packet::FEATURE_PQ_KEM;
+use crate::protocol::control_packet::{
+    ControlPacket, DIAG_DATA, DIAG_ECHO, DIAG_PROBE, DIAG_REPORT,
+};
 use crate::protocol::{
     control_packet, ip_turn_packet, other_turn_packet, NetPacket, Protocol, MAX_TTL,
 };
@@ -42,6 +53,10 @@ pub struct ClientPacketHandler<Device> {
     #[cfg(feature = "ip_proxy")]
     #[cfg(feature = "integrated_tun")]
     ip_proxy_map: Option<IpProxyMap>,
+    // 校验内层来源ip时查找经哪个对端路由
+    external_route: ExternalRoute,
+    // 校验ipv6来源时查找对端的虚拟ipv6
+    device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
 }
 
 impl<Device: DeviceWrite> ClientPacketHandler<Device> {
@@ -55,6 +70,8 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
         #[cfg(feature = "integrated_tun")]
         #[cfg(feature = "ip_proxy")]
         ip_proxy_map: Option<IpProxyMap>,
+        external_route: ExternalRoute,
+        device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
     ) -> Self {
         Self {
             device,
@@ -66,10 +83,46 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
             #[cfg(feature = "integrated_tun")]
             #[cfg(feature = "ip_proxy")]
             ip_proxy_map,
+            external_route,
+            device_map,
         }
     }
 }
 
+/// 防火墙只作用于发给本机的数据(包括广播和组播)
+fn firewall_allow_in(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    source: Ipv4Addr,
+    packet: &[u8],
+) -> bool {
+    let firewall = context.acl().firewall();
+    if !firewall.is_enabled() {
+        return true;
+    }
+    let local = match IpInfo::new(packet).map(|info| info.destination) {
+        Some(IpAddr::V4(ip)) => {
+            ip == current_device.virtual_ip
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip == current_device.broadcast_ip
+        }
+        Some(IpAddr::V6(ip)) => ip == current_device.virtual_ipv6 || ip.is_multicast(),
+        None => true,
+    };
+    !local || firewall.allow_in(source, packet)
+}
+
+/// 来源是服务端或者已经建立直连路由的地址
+fn validated_addr(
+    context: &ChannelContext,
+    current_device: &CurrentDeviceInfo,
+    route_key: &RouteKey,
+) -> bool {
+    route_key.addr == current_device.connect_server
+        || context.route_table.route_to_id(route_key).is_some()
+}
+
 impl<Device: DeviceWrite> PacketHandler for ClientPacketHandler<Device> {
     fn handle(
         &self,
@@ -79,7 +132,70 @@ impl<Device: DeviceWrite> PacketHandler for ClientPacketHandler<Device> {
         context: &ChannelContext,
         current_device: &CurrentDeviceInfo,
     ) -> anyhow::Result<()> {
-        self.client_cipher.decrypt_ipv4(&mut net_packet)?;
+        // 未验证地址发来的握手类控制包按来源ip限流，在解密前丢弃
+        if net_packet.protocol() == Protocol::Control
+            && control_packet::Protocol::from(net_packet.transport_protocol()).is_handshake()
+            && !validated_addr(context, current_device, &route_key)
+            && !context.handshake_guard().allow(route_key.addr.ip())
+        {
+            log::debug!("握手包超过限速 {:?}", route_key.addr);
+            return Ok(());
+        }
+        if net_packet.is_session() {
+            // 使用协商的会话密钥加密的数据
+            #[cfg(feature = "peer_key")]
+            match context.peer_key() {
+                Some(peer_key) => {
+                    if !peer_key.decrypt(&mut net_packet)? {
+                        return Ok(());
+                    }
+                }
+                None => return Err(anyhow!("peer_key not enabled")),
+            }
+            #[cfg(not(feature = "peer_key"))]
+            return Err(anyhow!("peer_key not supported"));
+        } else {
+            #[cfg(feature = "integrity")]
+            if let Some(integrity) = context.integrity() {
+                if net_packet.is_encrypt() {
+                    integrity.verify(&mut net_packet)?;
+                } else if matches!(
+                    net_packet.protocol(),
+                    Protocol::IpTurn | Protocol::OtherTurn
+                ) && net_packet.destination() == current_device.virtual_ip
+                    && integrity.required(context.peer_features(&net_packet.source()))
+                {
+                    // 发给本机的数据必须带校验码，广播和控制数据除外
+                    log::debug!("丢弃没有校验码的数据 {}", net_packet.source());
+                    return Ok(());
+                }
+            }
+            // 限定了加密范围时，对应路径上的转发数据不加密
+            let plain = !net_packet.is_encrypt()
+                && matches!(
+                    net_packet.protocol(),
+                    Protocol::IpTurn | Protocol::OtherTurn
+                )
+                && !context.encrypt_scope().encrypt(
+                    route_key.addr != current_device.connect_server
+                        && net_packet.source_ttl() == net_packet.ttl(),
+                );
+            if !plain {
+                if let Err(e) = self.client_cipher.decrypt_ipv4(&mut net_packet) {
+                    context.counters().cipher_error();
+                    return Err(e);
+                }
+            }
+            #[cfg(feature = "peer_key")]
+            if let Some(peer_key) = context.peer_key() {
+                // 会话确认后对端不会再用组网密码加密转发的数据
+                if net_packet.protocol() == Protocol::IpTurn
+                    && !peer_key.accept_plain(&net_packet.source())
+                {
+                    return Ok(());
+                }
+            }
+        }
         context
             .route_table
             .update_read_time(&net_packet.source(), &route_key);
@@ -106,6 +222,9 @@ impl<Device: DeviceWrite> PacketHandler for ClientPacketHandler<Device> {
             Protocol::OtherTurn => {
                 self.other_turn(context, current_device, net_packet, route_key)?;
             }
+            Protocol::Fec => {}
+            Protocol::Aggregate => {}
+            Protocol::Reliable => {}
             Protocol::Unknown(_) => {}
         }
         Ok(())
@@ -113,6 +232,54 @@ impl<Device: DeviceWrite> PacketHandler for ClientPacketHandler<Device> {
 }
 
 impl<Device: DeviceWrite> ClientPacketHandler<Device> {
+    /// 写入失败(如网卡队列满)时计数，方便监控丢包
+    fn write_device(&self, context: &ChannelContext, buf: &[u8]) -> std::io::Result<()> {
+        if let Some(capture) = context.capture() {
+            capture.inner(buf, true);
+        }
+        if let Err(e) = self.device.write(buf) {
+            context.counters().tun_drop();
+            return Err(e);
+        }
+        Ok(())
+    }
+    /// 对端发来的ipv6包的来源是否合法，对端的虚拟ipv6和映射地址合法，其他映射地址按对应的ipv4校验，
+    /// 链路本地地址只能用于组播
+    fn allow_source_ipv6(
+        &self,
+        context: &ChannelContext,
+        current_device: &CurrentDeviceInfo,
+        peer: Ipv4Addr,
+        source: Ipv6Addr,
+        destination: Ipv6Addr,
+    ) -> bool {
+        if source == mapped_ipv6(peer) {
+            return true;
+        }
+        if let Some(real_source) = mapped_ipv4(&source) {
+            let virtual_network = (
+                u32::from(current_device.virtual_network),
+                u32::from(current_device.virtual_netmask),
+            );
+            let gateway = self.external_route.route(&real_source);
+            return context
+                .acl()
+                .allow_source(peer, real_source, virtual_network, gateway);
+        }
+        if source.segments()[0] & 0xffc0 == 0xfe80 {
+            return destination.is_multicast();
+        }
+        let allow = self
+            .device_map
+            .lock()
+            .1
+            .get(&peer)
+            .map_or(false, |info| info.virtual_ipv6 == source);
+        if !allow {
+            log::debug!("丢弃来源ip不合法的数据 peer={} source={}", peer, source);
+        }
+        allow
+    }
     fn ip_turn(
         &self,
         mut net_packet: NetPacket<&mut [u8]>,
@@ -122,9 +289,38 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
     ) -> anyhow::Result<()> {
         let destination = net_packet.destination();
         let source = net_packet.source();
-        match ip_turn_packet::Protocol::from(net_packet.transport_protocol()) {
+        let protocol = ip_turn_packet::Protocol::from(net_packet.transport_protocol());
+        // 二层和三层模式的数据不能混用，对端模式不一致时在交换能力时已有提示
+        if context.layer2() != (protocol == ip_turn_packet::Protocol::Ethernet) {
+            return Ok(());
+        }
+        match protocol {
             ip_turn_packet::Protocol::Ipv4 => {
                 let mut ipv4 = IpV4Packet::new(net_packet.payload_mut())?;
+                let real_source = ipv4.source_ip();
+                if real_source != source {
+                    let virtual_network = (
+                        u32::from(current_device.virtual_network),
+                        u32::from(current_device.virtual_netmask),
+                    );
+                    let gateway = self.external_route.route(&real_source);
+                    if !context
+                        .acl()
+                        .allow_source(source, real_source, virtual_network, gateway)
+                    {
+                        context.counters().packet_drop(DropReason::SpoofedSource);
+                        return Ok(());
+                    }
+                }
+                if !context.acl().allow_in(source, &ipv4.buffer[..]) {
+                    return Ok(());
+                }
+                if !firewall_allow_in(context, current_device, source, &ipv4.buffer[..]) {
+                    return Ok(());
+                }
+                if let Some(peer_stats) = context.peer_stats() {
+                    peer_stats.flow_in(source, &ipv4);
+                }
                 match ipv4.protocol() {
                     ipv4::protocol::Protocol::Icmp => {
                         if ipv4.destination_ip() == destination {
@@ -139,7 +335,12 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                                 net_packet.set_source(destination);
                                 net_packet.set_destination(source);
                                 //不管加不加密，和接收到的数据长度都一致
-                                self.client_cipher.encrypt_ipv4(&mut net_packet)?;
+                                encrypt_to_peer(
+                                    context,
+                                    &self.client_cipher,
+                                    &mut net_packet,
+                                    &source,
+                                )?;
                                 context.send_by_key(&net_packet, route_key)?;
                                 return Ok(());
                             }
@@ -149,13 +350,20 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                 }
                 // ip代理只关心实际目标
                 let real_dest = ipv4.destination_ip();
+                #[cfg(feature = "wireguard")]
+                if let Some(wireguard) = context.wireguard() {
+                    // 其他vnt客户端发给wg对端的数据
+                    if wireguard.send(real_dest, &ipv4.buffer[..])? {
+                        return Ok(());
+                    }
+                }
                 if real_dest != destination
                     && !(real_dest.is_broadcast()
                         || real_dest.is_multicast()
                         || real_dest == current_device.broadcast_ip
                         || real_dest.is_unspecified())
                 {
-                    if !self.route.allow(&real_dest) {
+                    if !self.route.allow_from(&source, &real_dest) {
                         //拦截不符合的目标
                         return Ok(());
                     }
@@ -192,11 +400,66 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                         }
                     }
                 }
-                self.device.write(net_packet.payload())?;
+                // 对端是旧版本时不会钳制，这里再钳制一次
+                if let Some(mss) = context.route_table.path_mss(&source) {
+                    clamp_mss(net_packet.payload_mut(), mss);
+                }
+                self.write_device(context, net_packet.payload())?;
             }
             ip_turn_packet::Protocol::WGIpv4 => {
                 // WG客户端的数据不会直接发过来，不用处理
             }
+            ip_turn_packet::Protocol::Ipv6 => {
+                let payload = net_packet.payload();
+                if payload.len() < 40 || payload[0] >> 4 != 6 {
+                    return Ok(());
+                }
+                let real_source: [u8; 16] = payload[8..24].try_into().unwrap();
+                let real_dest: [u8; 16] = payload[24..40].try_into().unwrap();
+                if !self.allow_source_ipv6(
+                    context,
+                    current_device,
+                    source,
+                    Ipv6Addr::from(real_source),
+                    Ipv6Addr::from(real_dest),
+                ) {
+                    context.counters().packet_drop(DropReason::SpoofedSource);
+                    return Ok(());
+                }
+                if !context.acl().allow_in(source, payload)
+                    || !firewall_allow_in(context, current_device, source, payload)
+                {
+                    return Ok(());
+                }
+                self.write_device(context, payload)?;
+            }
+            ip_turn_packet::Protocol::Ethernet => {
+                let payload = net_packet.payload();
+                if payload.len() < 14 {
+                    return Ok(());
+                }
+                match ethernet_ip(payload) {
+                    Some(ip) => {
+                        if !context.acl().allow_in(source, ip)
+                            || !firewall_allow_in(context, current_device, source, ip)
+                        {
+                            return Ok(());
+                        }
+                    }
+                    None => {
+                        // 开启防火墙时非ip帧只放行arp
+                        if payload[12..14] != [0x08, 0x06] && !context.acl().firewall().deny_other()
+                        {
+                            return Ok(());
+                        }
+                    }
+                }
+                // 学习源mac，之后发往这个mac的帧直接发给对应的客户端
+                context
+                    .mac_table()
+                    .learn(payload[6..12].try_into().unwrap(), source);
+                self.write_device(context, payload)?;
+            }
             ip_turn_packet::Protocol::Ipv4Broadcast => {
                 //客户端不帮忙转发广播包，所以不会出现这种类型的数据
             }
@@ -214,7 +477,10 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
         let metric = net_packet.source_ttl() - net_packet.ttl() + 1;
         let source = net_packet.source();
         match ControlPacket::new(net_packet.transport_protocol(), net_packet.payload())? {
-            ControlPacket::PingPacket(_) => {
+            ControlPacket::PingPacket(ping_packet) => {
+                // 只从ping包获取对端能力，旧版本回复的pong包会原样带回本端的能力
+                context.update_peer_features(source, ping_packet.features());
+                context.update_peer_session(source, ping_packet.session_id());
                 let route = Route::from_default_rt(route_key, metric);
                 if context.route_table.add_route_if_absent(source, route)
                     || net_packet.source() < current_device.virtual_ip
@@ -236,10 +502,16 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                 let rt = (current_time - pong_packet.time()) as i64;
                 let route = Route::from(route_key, metric, rt);
                 context.route_table.add_route(source, route);
+                if let Some(multipath) = context.route_table.multipath() {
+                    multipath.pong(&route_key);
+                }
+                if let Some(peer_stats) = context.peer_stats() {
+                    peer_stats.pong(source);
+                }
             }
             ControlPacket::PunchRequest => {
                 log::info!("PunchRequest={:?},source={}", route_key, source);
-                if context.use_channel_type().is_only_relay() {
+                if context.use_channel_type_of(&source).is_only_relay() {
                     return Ok(());
                 }
                 //忽略掉来源于自己的包
@@ -263,9 +535,22 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
             }
             ControlPacket::PunchResponse => {
                 log::info!("PunchResponse={:?},source={}", route_key, source);
-                if context.use_channel_type().is_only_relay() {
+                if context.use_channel_type_of(&source).is_only_relay() {
                     return Ok(());
                 }
+                // 带cookie的是向缓存地址发起的探测，cookie不匹配时不能添加路由
+                let payload = net_packet.payload();
+                if payload.len() >= 8 {
+                    let cookie = u64::from_be_bytes(payload[..8].try_into().unwrap());
+                    if !context.check_punch_cookie(&source, cookie)
+                        && !context
+                            .handshake_guard()
+                            .check_cookie(&route_key.addr, cookie)
+                    {
+                        log::warn!("PunchResponse cookie无效={:?},source={}", route_key, source);
+                        return Ok(());
+                    }
+                }
                 if self
                     .nat_test
                     .is_local_address(route_key.protocol().is_base_tcp(), route_key.addr)
@@ -275,6 +560,8 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                 let route = Route::from_default_rt(route_key, 1);
                 context.route_table.add_route_if_absent(source, route);
             }
+            // 回应比请求大，只回应验证过的地址
+            ControlPacket::AddrRequest if !validated_addr(context, current_device, &route_key) => {}
             ControlPacket::AddrRequest => match route_key.addr.ip() {
                 std::net::IpAddr::V4(ipv4) => {
                     let mut packet = NetPacket::new_encrypt([0; 12 + 6 + ENCRYPTION_RESERVED])?;
@@ -293,6 +580,162 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                 std::net::IpAddr::V6(_) => {}
             },
             ControlPacket::AddrResponse(_) => {}
+            ControlPacket::PmtuProbe(probe_packet) => {
+                // 探测包能到达说明这个长度可用，回复一个小包
+                let mut packet = NetPacket::new_encrypt([0; 12 + 2 + ENCRYPTION_RESERVED])?;
+                packet.set_default_version();
+                packet.set_protocol(Protocol::Control);
+                packet.set_transport_protocol(control_packet::Protocol::PmtuReply.into());
+                packet.first_set_ttl(MAX_TTL);
+                packet.set_source(current_device.virtual_ip);
+                packet.set_destination(source);
+                let mut reply_packet = control_packet::PmtuPacket::new(packet.payload_mut())?;
+                reply_packet.set_mtu(probe_packet.mtu());
+                self.client_cipher.encrypt_ipv4(&mut packet)?;
+                context.send_by_key(&packet, route_key)?;
+            }
+            ControlPacket::PmtuReply(reply_packet) => {
+                context
+                    .route_table
+                    .pmtu()
+                    .reply(&route_key, reply_packet.mtu());
+            }
+            ControlPacket::RelayPaths(paths_packet) => {
+                // 只接收直连客户端的通告
+                if metric == 1 {
+                    context.relay_paths().update(source, paths_packet.paths());
+                }
+            }
+            ControlPacket::Migrate(migrate_packet) => {
+                if metric != 1
+                    || route_key.protocol().is_base_tcp()
+                    || context.use_channel_type_of(&source).is_only_relay()
+                {
+                    return Ok(());
+                }
+                let is_new = match context.migrate_peer_session(
+                    source,
+                    migrate_packet.session_id(),
+                    migrate_packet.generation(),
+                ) {
+                    Some(is_new) => is_new,
+                    None => {
+                        log::warn!("迁移请求会话不匹配 {} {:?}", source, route_key);
+                        return Ok(());
+                    }
+                };
+                if is_new {
+                    log::info!("对端{}地址迁移到{:?}", source, route_key);
+                    // 对端的本地ip已经变化，旧地址的直连路由不再可用
+                    if let Some(routes) = context.route_table.route(&source) {
+                        for route in routes {
+                            if route.is_p2p() && route.route_key() != route_key {
+                                context.remove_route(&source, route.route_key());
+                            }
+                        }
+                    }
+                }
+                let route = Route::from_default_rt(route_key, 1);
+                context.route_table.add_route_if_absent(source, route);
+            }
+            #[cfg(feature = "peer_key")]
+            ControlPacket::PeerKey(peer_key_packet) => {
+                let peer_key = match context.peer_key() {
+                    Some(peer_key) => peer_key,
+                    None => return Ok(()),
+                };
+                if !validated_addr(context, current_device, &route_key) {
+                    // 直连地址未验证时不分配握手状态，先发送带cookie的打洞请求，
+                    // 对端回应后添加路由，重发的握手消息再处理
+                    let cookie = context.handshake_guard().cookie(&route_key.addr);
+                    let packet = crate::handle::maintain::cookie_punch_packet(
+                        &self.client_cipher,
+                        current_device.virtual_ip,
+                        source,
+                        cookie,
+                    )?;
+                    context.send_by_key(&packet, route_key)?;
+                    return Ok(());
+                }
+                if let Some((kind, msg)) = peer_key.handle(
+                    current_device.virtual_ip,
+                    source,
+                    peer_key_packet.kind(),
+                    peer_key_packet.body(),
+                    context.peer_features(&source) & FEATURE_PQ_KEM != 0,
+                ) {
+                    let packet = crate::handle::maintain::peer_key_packet(
+                        &self.client_cipher,
+                        current_device.virtual_ip,
+                        source,
+                        kind,
+                        &msg,
+                    )?;
+                    context.send_by_key(&packet, route_key)?;
+                }
+            }
+            #[cfg(not(feature = "peer_key"))]
+            ControlPacket::PeerKey(_) => {}
+            ControlPacket::Leave if !validated_addr(context, current_device, &route_key) => {}
+            ControlPacket::Leave => {
+                log::info!("对端{}已退出", source);
+                // 不用等到路由过期，也不再经由它中转
+                if let Some(routes) = context.route_table.route(&source) {
+                    for route in routes {
+                        context.remove_route(&source, route.route_key());
+                    }
+                }
+                context.relay_paths().update(source, Vec::new());
+                self.peer_nat_info_map.write().remove(&source);
+            }
+            ControlPacket::Diag(diag_packet) => {
+                let (kind, id, seq) = (diag_packet.kind(), diag_packet.id(), diag_packet.seq());
+                let (bytes, packets) = match kind {
+                    DIAG_ECHO | DIAG_PROBE => (0, 0),
+                    DIAG_DATA => {
+                        context
+                            .diag()
+                            .recv_data(source, id, net_packet.payload().len());
+                        return Ok(());
+                    }
+                    DIAG_REPORT => context.diag().received(source, id),
+                    _ => return Ok(()),
+                };
+                // 响应不带填充，不会比请求大
+                let mut packet = crate::handle::maintain::diag_packet(
+                    current_device.virtual_ip,
+                    source,
+                    control_packet::Protocol::DiagReply,
+                    kind,
+                    id,
+                    seq,
+                    0,
+                )?;
+                let mut reply_packet = control_packet::DiagPacket::new(packet.payload_mut())?;
+                reply_packet.set_bytes(bytes);
+                reply_packet.set_packets(packets);
+                self.client_cipher.encrypt_ipv4(&mut packet)?;
+                context.send_by_key(&packet, route_key)?;
+            }
+            ControlPacket::DiagReply(reply_packet) => {
+                if reply_packet.kind() == DIAG_PROBE {
+                    if let Some(quality) = context.route_table.quality() {
+                        let current_time = crate::handle::now_time() as u16;
+                        let rt = current_time.wrapping_sub(reply_packet.seq()) as i64;
+                        quality.pong(&source, metric > 1, rt);
+                    }
+                    return Ok(());
+                }
+                let response = DiagResponse {
+                    seq: reply_packet.seq(),
+                    metric,
+                    route_key,
+                    bytes: reply_packet.bytes(),
+                    packets: reply_packet.packets(),
+                    time: Instant::now(),
+                };
+                context.diag().response(reply_packet.id(), response);
+            }
         }
         Ok(())
     }
@@ -309,6 +752,9 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
         let source = net_packet.source();
         match other_turn_packet::Protocol::from(net_packet.transport_protocol()) {
             other_turn_packet::Protocol::Punch => {
+                if context.use_channel_type_of(&source).is_only_relay() {
+                    return Ok(());
+                }
                 let mut punch_info = PunchInfo::parse_from_bytes(net_packet.payload())
                     .map_err(|e| anyhow!("PunchInfo {:?}", e))?;
                 let public_ips = punch_info
@@ -332,7 +778,7 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                 if punch_info.udp_ports.is_empty() {
                     punch_info.udp_ports.push(punch_info.local_port);
                 }
-                let peer_nat_info = NatInfo::new(
+                let mut peer_nat_info = NatInfo::new(
                     public_ips,
                     punch_info.public_ports.iter().map(|e| *e as u16).collect(),
                     punch_info.public_port_range as u16,
@@ -342,6 +788,16 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                     tcp_port,
                     punch_info.nat_type.enum_value_or_default().into(),
                 );
+                peer_nat_info.mapping = punch_info.mapping_behavior.enum_value_or_default().into();
+                peer_nat_info.filtering =
+                    punch_info.filtering_behavior.enum_value_or_default().into();
+                // 对端收集的候选地址，旧版本没有，数量做个限制
+                peer_nat_info.candidates = punch_info
+                    .candidates
+                    .iter()
+                    .take(64)
+                    .filter_map(|c| Candidate::try_from(c).ok())
+                    .collect();
                 {
                     let peer_nat_info = peer_nat_info.clone();
                     self.peer_nat_info_map.write().insert(source, peer_nat_info);
@@ -370,6 +826,15 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                         punch_reply.ipv6 = ipv6.octets().to_vec();
                         punch_reply.ipv6_port = nat_info.udp_ports[0] as u32;
                     }
+                    punch_reply.mapping_behavior =
+                        protobuf::EnumOrUnknown::new(PunchNatBehavior::from(nat_info.mapping));
+                    punch_reply.filtering_behavior =
+                        protobuf::EnumOrUnknown::new(PunchNatBehavior::from(nat_info.filtering));
+                    punch_reply.candidates = nat_info
+                        .gather_candidates()
+                        .iter()
+                        .map(|c| c.into())
+                        .collect();
                     let bytes = punch_reply
                         .write_to_bytes()
                         .map_err(|e| anyhow!("punch_reply {:?}", e))?;
@@ -384,7 +849,7 @@ impl<Device: DeviceWrite> ClientPacketHandler<Device> {
                     punch_packet.set_payload(&bytes)?;
                     self.client_cipher.encrypt_ipv4(&mut punch_packet)?;
                     if self.punch_sender.send(true, source, peer_nat_info) {
-                        context.send_by_key(&punch_packet, route_key)?;
+                        context.send_reliable(&punch_packet, ReliableTarget::Route(route_key))?;
                     }
                 } else {
                     self.punch_sender.send(false, source, peer_nat_info);
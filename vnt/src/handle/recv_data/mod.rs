@@ -9,7 +9,9 @@ use parking_lot::{Mutex, RwLock};
 use crate::channel::context::ChannelContext;
 use crate::channel::handler::RecvChannelHandler;
 use crate::channel::punch::NatInfo;
+use crate::channel::stats::DropReason;
 use crate::channel::RouteKey;
+use crate::channel::{aggregate, reliable};
 use crate::cipher::Cipher;
 #[cfg(feature = "server_encrypt")]
 use crate::cipher::RsaCipher;
@@ -24,7 +26,8 @@ use crate::handle::{BaseConfigInfo, CurrentDeviceInfo, PeerDeviceInfo, SELF_IP};
 #[cfg(feature = "ip_proxy")]
 use crate::ip_proxy::IpProxyMap;
 use crate::nat::NatTest;
-use crate::protocol::{NetPacket, HEAD_LEN};
+use crate::protocol::reliable_packet::{ReliablePacket, RELIABLE_HEAD_LEN};
+use crate::protocol::{reliable_packet, NetPacket, Protocol, HEAD_LEN};
 use crate::tun_tap_device::vnt_device::DeviceWrite;
 
 mod client;
@@ -38,6 +41,7 @@ pub struct RecvDataHandler<Call, Device> {
     client: ClientPacketHandler<Device>,
     server: ServerPacketHandler<Call, Device>,
     nat_test: NatTest,
+    client_cipher: Cipher,
 }
 
 impl<Call: VntCallback, Device: DeviceWrite> RecvChannelHandler for RecvDataHandler<Call, Device> {
@@ -62,6 +66,9 @@ impl<Call: VntCallback, Device: DeviceWrite> RecvChannelHandler for RecvDataHand
                 }
             }
         }
+        if let Some(capture) = context.capture() {
+            capture.outer(buf, true, route_key.protocol(), route_key.addr);
+        }
         if let Err(e) = self.handle0(buf, extend, route_key, context) {
             log::error!(
                 "[{}]-{:?}-{:?}",
@@ -101,7 +108,7 @@ impl<Call: VntCallback, Device: DeviceWrite> RecvDataHandler<Call, Device> {
             server_cipher,
             current_device.clone(),
             device.clone(),
-            device_map,
+            device_map.clone(),
             config_info,
             nat_test.clone(),
             callback,
@@ -112,7 +119,7 @@ impl<Call: VntCallback, Device: DeviceWrite> RecvDataHandler<Call, Device> {
         );
         let client = ClientPacketHandler::new(
             device.clone(),
-            client_cipher,
+            client_cipher.clone(),
             punch_sender,
             peer_nat_info_map,
             nat_test.clone(),
@@ -120,6 +127,8 @@ impl<Call: VntCallback, Device: DeviceWrite> RecvDataHandler<Call, Device> {
             #[cfg(feature = "integrated_tun")]
             #[cfg(feature = "ip_proxy")]
             ip_proxy_map,
+            external_route,
+            device_map,
         );
         let turn = TurnPacketHandler::new();
         Self {
@@ -128,6 +137,7 @@ impl<Call: VntCallback, Device: DeviceWrite> RecvDataHandler<Call, Device> {
             client,
             server,
             nat_test,
+            client_cipher,
         }
     }
     fn handle0(
@@ -139,9 +149,9 @@ impl<Call: VntCallback, Device: DeviceWrite> RecvDataHandler<Call, Device> {
     ) -> anyhow::Result<()> {
         let net_packet = NetPacket::new(buf)?;
 
-        let extend = NetPacket::unchecked(extend);
         if net_packet.ttl() == 0 || net_packet.source_ttl() < net_packet.ttl() {
             log::warn!("丢弃过时包:{:?} {}", net_packet.head(), route_key.addr);
+            context.counters().packet_drop(DropReason::TtlExceeded);
             return Ok(());
         }
         let current_device = self.current_device.load();
@@ -153,10 +163,96 @@ impl<Call: VntCallback, Device: DeviceWrite> RecvDataHandler<Call, Device> {
             || dest.is_unspecified()
             || dest == current_device.broadcast_ip
         {
+            if net_packet.protocol() == Protocol::Fec {
+                //前向纠错包，取出原始包和恢复的包再处理
+                for mut packet in context.fec().decode(&net_packet) {
+                    if let Err(e) = self.handle0(&mut packet, extend, route_key, context) {
+                        log::warn!("fec {:?} {:?}", route_key.addr, e);
+                    }
+                }
+                return Ok(());
+            }
+            if net_packet.protocol() == Protocol::Reliable {
+                //可靠传输包，回复确认后处理原始包
+                let source = net_packet.source();
+                match reliable_packet::Protocol::from(net_packet.transport_protocol()) {
+                    reliable_packet::Protocol::Data => {
+                        let seq = ReliablePacket::new(net_packet.payload())?.seq();
+                        let buf = net_packet.into_buffer();
+                        let packet = &mut buf[HEAD_LEN + RELIABLE_HEAD_LEN..];
+                        if nested(packet) {
+                            return Ok(());
+                        }
+                        let new = context.reliable().recv(source, seq);
+                        // 重复的包也要确认，之前的确认可能丢了
+                        let ack = reliable::ack_packet(
+                            &self.client_cipher,
+                            current_device.virtual_ip,
+                            source,
+                            seq,
+                        )?;
+                        context.send_by_key(&ack, route_key)?;
+                        if new {
+                            return self.handle0(packet, extend, route_key, context);
+                        }
+                    }
+                    reliable_packet::Protocol::Ack => {
+                        // 解密失败的确认可能是伪造的，直接丢弃
+                        let mut net_packet = net_packet;
+                        if let Err(e) = self.client_cipher.decrypt_ipv4(&mut net_packet) {
+                            context.counters().cipher_error();
+                            return Err(e);
+                        }
+                        let seq = ReliablePacket::new(net_packet.payload())?.seq();
+                        context.reliable().ack(source, seq);
+                    }
+                    reliable_packet::Protocol::Unknown(e) => {
+                        log::warn!("不支持的可靠传输协议 {:?},source:{:?}", e, source);
+                    }
+                }
+                return Ok(());
+            }
+            if net_packet.protocol() == Protocol::Aggregate {
+                //合并的小包，拆开后逐个处理
+                let ranges = aggregate::split(net_packet.payload());
+                let buf = net_packet.into_buffer();
+                for range in ranges {
+                    let packet = &mut buf[HEAD_LEN + range.start..HEAD_LEN + range.end];
+                    if nested(packet) {
+                        continue;
+                    }
+                    if let Err(e) = self.handle0(packet, extend, route_key, context) {
+                        log::warn!("aggregate {:?} {:?}", route_key.addr, e);
+                    }
+                }
+                return Ok(());
+            }
+            let extend = NetPacket::unchecked(extend);
             // 统计流量
             if let Some(down_traffic_meter) = &context.down_traffic_meter {
                 down_traffic_meter.add_traffic(net_packet.source(), net_packet.data_len())
             }
+            let is_data = matches!(
+                net_packet.protocol(),
+                Protocol::IpTurn | Protocol::OtherTurn
+            ) && !net_packet.is_gateway();
+            if let Some(bandwidth) = context.bandwidth() {
+                // 超过下行带宽限制的网络数据直接丢弃
+                if is_data && !bandwidth.allow_down(net_packet.source(), net_packet.data_len()) {
+                    if let Some(peer_stats) = context.peer_stats() {
+                        peer_stats.recv_drop(net_packet.source());
+                    }
+                    return Ok(());
+                }
+            }
+            if is_data {
+                if let Some(peer_stats) = context.peer_stats() {
+                    // 没有经过转发并且不是从服务器收到的才是直连
+                    let direct = net_packet.source_ttl() == net_packet.ttl()
+                        && route_key.addr != current_device.connect_server;
+                    peer_stats.recv(net_packet.source(), net_packet.data_len(), direct);
+                }
+            }
             //发给自己的包
             if net_packet.is_gateway() {
                 //服务端-客户端包
@@ -168,6 +264,7 @@ impl<Call: VntCallback, Device: DeviceWrite> RecvDataHandler<Call, Device> {
                     .handle(net_packet, extend, route_key, context, &current_device)
             }
         } else {
+            let extend = NetPacket::unchecked(extend);
             //转发包
             self.turn
                 .handle(net_packet, extend, route_key, context, &current_device)
@@ -175,6 +272,17 @@ impl<Call: VntCallback, Device: DeviceWrite> RecvDataHandler<Call, Device> {
     }
 }
 
+/// 聚合包和可靠传输包中的包不能再是这几种封装，避免对端构造多层嵌套的包耗尽栈
+fn nested(packet: &[u8]) -> bool {
+    match NetPacket::new(packet) {
+        Ok(packet) => matches!(
+            packet.protocol(),
+            Protocol::Aggregate | Protocol::Reliable | Protocol::Fec
+        ),
+        Err(_) => true,
+    }
+}
+
 pub trait PacketHandler {
     fn handle(
         &self,
@@ -25,12 +25,14 @@ use crate::handle::callback::{ErrorInfo, ErrorType, HandshakeInfo, RegisterInfo,
 use crate::handle::handshaker;
 use crate::handle::handshaker::Handshake;
 use crate::handle::recv_data::PacketHandler;
-use crate::handle::{registrar, BaseConfigInfo, ConnectStatus, CurrentDeviceInfo, PeerDeviceInfo};
+use crate::handle::{
+    self, registrar, BaseConfigInfo, ConnectStatus, CurrentDeviceInfo, PeerDeviceInfo,
+};
 use crate::nat::NatTest;
 use crate::proto::message::{DeviceList, HandshakeResponse, RegistrationResponse};
 use crate::protocol::body::ENCRYPTION_RESERVED;
 use crate::protocol::control_packet::ControlPacket;
-use crate::protocol::error_packet::InErrorPacket;
+use crate::protocol::error_packet::{ErrorPacket, InErrorPacket};
 use crate::protocol::{ip_turn_packet, service_packet, NetPacket, Protocol, MAX_TTL};
 use crate::tun_tap_device::vnt_device::DeviceWrite;
 use crate::{proto, PeerClientInfo};
@@ -139,6 +141,8 @@ impl<Call: VntCallback, Device: DeviceWrite> PacketHandler for ServerPacketHandl
                             rsa_cipher,
                             self.config_info.token.clone(),
                             key,
+                            #[cfg(feature = "pq")]
+                            self.handshake.kem_shared(),
                         )?;
                         context.send_by_key(&packet, route_key)?;
                     }
@@ -154,6 +158,8 @@ impl<Call: VntCallback, Device: DeviceWrite> PacketHandler for ServerPacketHandl
             //如果开启了加密，则发送加密握手请求
             #[cfg(feature = "server_encrypt")]
             if let Some(key) = self.server_cipher.key() {
+                #[cfg(feature = "pq")]
+                self.handshake.kem_response(&response.kem_ciphertext)?;
                 {
                     let guard = self.rsa_cipher.lock();
                     if let Some(rsa_cipher) = guard.as_ref() {
@@ -162,6 +168,8 @@ impl<Call: VntCallback, Device: DeviceWrite> PacketHandler for ServerPacketHandl
                                 rsa_cipher,
                                 self.config_info.token.clone(),
                                 key,
+                                #[cfg(feature = "pq")]
+                                self.handshake.kem_shared(),
                             )?;
                             drop(guard);
                             context.send_by_key(&packet, route_key)?;
@@ -198,6 +206,8 @@ impl<Call: VntCallback, Device: DeviceWrite> PacketHandler for ServerPacketHandl
                         &rsa_cipher,
                         self.config_info.token.clone(),
                         key,
+                        #[cfg(feature = "pq")]
+                        self.handshake.kem_shared(),
                     )?;
                     context.send_by_key(&packet, route_key)?;
                     self.rsa_cipher.lock().replace(rsa_cipher);
@@ -217,7 +227,10 @@ impl<Call: VntCallback, Device: DeviceWrite> PacketHandler for ServerPacketHandl
             return Ok(());
         }
         //服务端数据解密
-        self.server_cipher.decrypt_ipv4(&mut net_packet)?;
+        if let Err(e) = self.server_cipher.decrypt_ipv4(&mut net_packet) {
+            context.counters().cipher_error();
+            return Err(e);
+        }
         match net_packet.protocol() {
             Protocol::Service => {
                 self.service(context, current_device, net_packet, route_key)?;
@@ -251,11 +264,16 @@ impl<Call: VntCallback, Device: DeviceWrite> PacketHandler for ServerPacketHandl
                             self.device.write(net_packet.payload())?;
                         }
                     }
+                    ip_turn_packet::Protocol::Ipv6 => {}
+                    ip_turn_packet::Protocol::Ethernet => {}
                     ip_turn_packet::Protocol::Ipv4Broadcast => {}
                     ip_turn_packet::Protocol::Unknown(_) => {}
                 }
             }
             Protocol::OtherTurn => {}
+            Protocol::Fec => {}
+            Protocol::Aggregate => {}
+            Protocol::Reliable => {}
             Protocol::Unknown(_) => {}
         }
         Ok(())
@@ -284,8 +302,19 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                 let virtual_gateway = Ipv4Addr::from(response.virtual_gateway);
                 let virtual_network =
                     Ipv4Addr::from(response.virtual_ip & response.virtual_netmask);
+                let virtual_ipv6 = handle::virtual_ipv6(&response.virtual_ipv6, virtual_ip);
+                let virtual_ipv6_prefix = if response.virtual_ipv6.len() == 16 {
+                    response.virtual_ipv6_prefix.min(128) as u8
+                } else {
+                    handle::MAPPED_IPV6_PREFIX_LEN
+                };
                 let register_info = RegisterInfo::new(virtual_ip, virtual_netmask, virtual_gateway);
                 log::info!("注册成功：{:?}", register_info);
+                if let Some(ip) = self.config_info.ip {
+                    if ip != virtual_ip {
+                        log::warn!("虚拟ip {} 已被占用，服务端分配了 {}", ip, virtual_ip);
+                    }
+                }
                 if self.callback.register(register_info) {
                     let route = Route::from_default_rt(route_key, 1);
                     context
@@ -303,6 +332,8 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                         new_current_device.virtual_ip = virtual_ip;
                         new_current_device.virtual_netmask = virtual_netmask;
                         new_current_device.virtual_gateway = virtual_gateway;
+                        new_current_device.virtual_ipv6 = virtual_ipv6;
+                        new_current_device.virtual_ipv6_prefix = virtual_ipv6_prefix;
                         new_current_device.status = ConnectStatus::Connected;
                         if let Err(c) = self
                             .current_device
@@ -314,22 +345,42 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                         }
                     }
 
+                    #[cfg(feature = "integrated_tun")]
+                    let recreate = self.tun_device_helper.take_recreate();
+                    #[cfg(not(feature = "integrated_tun"))]
+                    let recreate = false;
                     if old.virtual_ip != virtual_ip
                         || old.virtual_gateway != virtual_gateway
                         || old.virtual_netmask != virtual_netmask
+                        || old.virtual_ipv6 != virtual_ipv6
+                        || recreate
                     {
                         if old.virtual_ip != Ipv4Addr::UNSPECIFIED {
                             log::info!("ip发生变化,old:{:?},response={:?}", old, response);
                         }
+                        check_network(
+                            self.config_info.network,
+                            virtual_network,
+                            virtual_netmask,
+                            &[old.virtual_ip, virtual_ip],
+                        );
                         let device_config = crate::handle::callback::DeviceConfig::new(
                             #[cfg(feature = "integrated_tun")]
                             #[cfg(target_os = "windows")]
                             self.config_info.tap,
                             #[cfg(feature = "integrated_tun")]
+                            #[cfg(target_os = "windows")]
+                            self.config_info.wintun_path.clone(),
+                            #[cfg(feature = "integrated_tun")]
+                            #[cfg(any(target_os = "windows", target_os = "linux"))]
+                            self.config_info.layer2,
+                            #[cfg(feature = "integrated_tun")]
                             #[cfg(any(
                                 target_os = "windows",
                                 target_os = "linux",
-                                target_os = "macos"
+                                target_os = "macos",
+                                target_os = "freebsd",
+                                target_os = "openbsd"
                             ))]
                             self.config_info.device_name.clone(),
                             self.config_info.mtu,
@@ -337,7 +388,9 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                             virtual_netmask,
                             virtual_gateway,
                             virtual_network,
-                            self.external_route.to_route(),
+                            virtual_ipv6,
+                            virtual_ipv6_prefix,
+                            device_routes(&self.external_route, context),
                         );
                         #[cfg(not(feature = "integrated_tun"))]
                         self.callback.create_device(device_config);
@@ -347,10 +400,12 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                             #[cfg(any(
                                 target_os = "windows",
                                 target_os = "linux",
-                                target_os = "macos"
+                                target_os = "macos",
+                                target_os = "freebsd",
+                                target_os = "openbsd"
                             ))]
                             match crate::tun_tap_device::create_device(device_config) {
-                                Ok(device) => {
+                                Ok((device, routes)) => {
                                     use tun::device::IFace;
                                     let tun_info = crate::handle::callback::DeviceInfo::new(
                                         device.name().unwrap_or("unknown".into()),
@@ -358,8 +413,11 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                                     );
                                     log::info!("tun信息{:?}", tun_info);
                                     self.callback.create_tun(tun_info);
-                                    self.tun_device_helper
-                                        .start(device, self.config_info.allow_wire_guard)?;
+                                    self.tun_device_helper.start(
+                                        device,
+                                        routes,
+                                        self.config_info.allow_wire_guard,
+                                    )?;
                                 }
                                 Err(e) => {
                                     log::error!("{:?}", e);
@@ -374,19 +432,21 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                                     virtual_netmask,
                                     virtual_gateway,
                                     virtual_network,
-                                    self.external_route.to_route(),
+                                    virtual_ipv6,
+                                    virtual_ipv6_prefix,
+                                    device_routes(&self.external_route, context),
                                 );
                                 let device_fd = self.callback.generate_tun(device_config);
                                 if device_fd == 0 {
-                                    self.callback.error(ErrorInfo::new_msg(
-                                        ErrorType::Unknown,
-                                        "device_fd == 0".into(),
-                                    ));
+                                    // 建立VpnService可能需要等待用户授权，由调用方稍后通过attach_tun_fd传入
+                                    log::info!("等待传入VpnService的fd");
                                 } else {
                                     match tun::Device::new(device_fd as _) {
                                         Ok(device) => {
+                                            // 路由由系统的VpnService管理
                                             if let Err(e) = self.tun_device_helper.start(
                                                 Arc::new(device),
+                                                Vec::new(),
                                                 self.config_info.allow_wire_guard,
                                             ) {
                                                 self.callback.error(ErrorInfo::new_msg(
@@ -436,16 +496,24 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
         let ip_list: Vec<PeerDeviceInfo> = device_info_list
             .into_iter()
             .map(|info| {
+                let virtual_ip = Ipv4Addr::from(info.virtual_ip);
                 PeerDeviceInfo::new(
-                    Ipv4Addr::from(info.virtual_ip),
+                    virtual_ip,
                     info.name,
                     info.device_status as u8,
                     info.client_secret,
                     info.client_secret_hash,
                     info.wireguard,
+                    handle::virtual_ipv6(&info.virtual_ipv6, virtual_ip),
+                    info.identity_public_key,
+                    info.advertise_routes
+                        .iter()
+                        .map(|v| (v.network & v.netmask, v.netmask))
+                        .collect(),
                 )
             })
             .collect();
+        self.import_routes(&ip_list);
         {
             let mut dev = self.device_map.lock();
             //这里可能会收到旧的消息，但是随着时间推移总会收到新的
@@ -462,6 +530,46 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                 .collect(),
         );
     }
+    /// 其他在线客户端宣告的网段加入路由表，和虚拟网段或本机宣告的网段重叠的忽略
+    fn import_routes(&self, ip_list: &[PeerDeviceInfo]) {
+        let current_device = self.current_device.load();
+        let overlap = |(network1, mask1): (u32, u32), (network2, mask2): (u32, u32)| {
+            let mask = mask1 & mask2;
+            network1 & mask == network2 & mask
+        };
+        let virtual_network = (
+            u32::from(current_device.virtual_network),
+            u32::from(current_device.virtual_netmask),
+        );
+        let mut routes = Vec::new();
+        for info in ip_list {
+            if !info.status.is_online() || info.wireguard {
+                continue;
+            }
+            for route in &info.advertise_routes {
+                if overlap(*route, virtual_network)
+                    || self
+                        .config_info
+                        .advertise_routes
+                        .iter()
+                        .any(|v| overlap(*route, *v))
+                {
+                    continue;
+                }
+                routes.push((route.0, route.1, info.virtual_ip));
+            }
+        }
+        // 相同网段有多个宣告者时使用虚拟ip较小的
+        routes.sort_by_key(|(_, _, gateway)| *gateway);
+        let (removed, added) = self.external_route.update_advertised(routes);
+        if removed.is_empty() && added.is_empty() {
+            return;
+        }
+        log::info!("宣告的路由变化,删除:{:?},添加:{:?}", removed, added);
+        #[cfg(feature = "integrated_tun")]
+        #[cfg(not(target_os = "android"))]
+        self.tun_device_helper.update_routes(&removed, &added);
+    }
     fn register(
         &self,
         current_device: &CurrentDeviceInfo,
@@ -490,8 +598,13 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
             name,
             ip,
             false,
-            false,
+            self.config_info.allow_ip_change,
             client_secret,
+            self.nat_test.mapped_addr(),
+            self.config_info.identity.as_ref(),
+            self.config_info.totp_secret.as_ref(),
+            &self.config_info.advertise_routes,
+            self.config_info.network,
         )?;
         log::info!("发送注册请求，{:?}", self.config_info);
         //注册请求只发送到默认通道
@@ -501,11 +614,19 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
     fn error(
         &self,
         context: &ChannelContext,
-        _current_device: &CurrentDeviceInfo,
+        current_device: &CurrentDeviceInfo,
         net_packet: NetPacket<&mut [u8]>,
         route_key: RouteKey,
     ) -> io::Result<()> {
-        match InErrorPacket::new(net_packet.transport_protocol(), net_packet.payload())? {
+        let error_packet =
+            InErrorPacket::new(net_packet.transport_protocol(), net_packet.payload())?;
+        if !matches!(
+            error_packet,
+            InErrorPacket::Disconnect | InErrorPacket::NoKey | InErrorPacket::Kicked(_)
+        ) {
+            context.counters().handshake_failure();
+        }
+        match error_packet {
             InErrorPacket::TokenError => {
                 // token错误，可能是服务端设置了白名单
                 let err = ErrorInfo::new(ErrorType::TokenError);
@@ -534,14 +655,60 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                 let err = ErrorInfo::new_msg(ErrorType::Unknown, e.message()?);
                 self.callback.error(err);
             }
-            InErrorPacket::IpAlreadyExists => {
-                let err = ErrorInfo::new(ErrorType::IpAlreadyExists);
+            InErrorPacket::IpAlreadyExists(e) => {
+                // 服务端会给出占用该ip的设备，开启allow_ip_change时服务端会直接分配其他ip
+                let err = match e.message() {
+                    Ok(holder) if !holder.trim().is_empty() => ErrorInfo::new_msg(
+                        ErrorType::IpAlreadyExists,
+                        format!(
+                            "virtual ip {} in use by device {}",
+                            self.config_info.ip.unwrap_or(current_device.virtual_ip),
+                            holder.trim()
+                        ),
+                    ),
+                    _ => ErrorInfo::new(ErrorType::IpAlreadyExists),
+                };
                 self.callback.error(err);
             }
             InErrorPacket::InvalidIp => {
                 let err = ErrorInfo::new(ErrorType::InvalidIp);
                 self.callback.error(err);
             }
+            InErrorPacket::IdentityError => {
+                // 身份公钥不在服务端白名单中，或者签名、证书校验失败
+                let err = ErrorInfo::new(ErrorType::IdentityError);
+                self.callback.error(err);
+            }
+            InErrorPacket::InviteError => {
+                // 邀请码签名错误、已过期或已被使用
+                let err = ErrorInfo::new(ErrorType::InviteError);
+                self.callback.error(err);
+            }
+            InErrorPacket::TotpError => {
+                // 服务端要求totp校验，没有配置密钥或验证码错误，也可能是本机时间不准
+                let err = ErrorInfo::new(ErrorType::TotpError);
+                self.callback.error(err);
+            }
+            InErrorPacket::Kicked(e) => {
+                // 被管理员踢出，重新注册，服务端不允许时会再返回错误
+                crate::handle::change_status(&self.current_device, ConnectStatus::Connecting);
+                let err = ErrorInfo::new_msg(
+                    ErrorType::Kicked,
+                    admin_message("kicked by the server administrator", &e),
+                );
+                self.callback.error(err);
+                self.device_map.lock().0 = 0;
+                self.handshake
+                    .send(context, self.config_info.server_secret, route_key.addr)?;
+            }
+            InErrorPacket::Banned(e) => {
+                // 设备或ip被管理员封禁，重新注册也会被拒绝
+                let err = ErrorInfo::new_msg(
+                    ErrorType::Banned,
+                    admin_message("banned by the server administrator", &e),
+                );
+                self.callback.error(err);
+            }
             InErrorPacket::NoKey => {
                 //这个类型最开头已经处理过，这里忽略
             }
@@ -565,6 +732,9 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
                 let rt = (current_time - pong_packet.time()) as i64;
                 let route = Route::from(route_key, metric, rt);
                 context.route_table.add_route(net_packet.source(), route);
+                if let Some(multipath) = context.route_table.multipath() {
+                    multipath.pong(&route_key);
+                }
                 let epoch = self.device_map.lock().0;
                 if pong_packet.epoch() != epoch {
                     //纪元不一致，可能有新客户端连接，向服务端拉取客户端列表
@@ -595,3 +765,70 @@ impl<Call: VntCallback, Device: DeviceWrite> ServerPacketHandler<Call, Device> {
         Ok(())
     }
 }
+
+/// 虚拟网卡的路由，开启wg互通时加上wg对端的网段。
+/// 默认路由拆成0.0.0.0/1和128.0.0.0/1，比系统的默认路由更精确，不需要替换系统路由
+fn device_routes(
+    external_route: &ExternalRoute,
+    _context: &ChannelContext,
+) -> Vec<(Ipv4Addr, Ipv4Addr)> {
+    let mut routes = Vec::new();
+    for (dest, mask) in external_route.to_route() {
+        if mask.is_unspecified() {
+            let half = Ipv4Addr::new(128, 0, 0, 0);
+            routes.push((Ipv4Addr::UNSPECIFIED, half));
+            routes.push((half, half));
+        } else {
+            routes.push((dest, mask));
+        }
+    }
+    #[cfg(feature = "wireguard")]
+    if let Some(wireguard) = _context.wireguard() {
+        routes.extend(wireguard.routes());
+    }
+    routes
+}
+
+/// 管理员操作的提示，附带服务端给出的原因
+fn admin_message<B: AsRef<[u8]>>(msg: &str, packet: &ErrorPacket<B>) -> String {
+    match packet.message() {
+        Ok(reason) if !reason.trim().is_empty() => format!("{}: {}", msg, reason.trim()),
+        _ => msg.to_string(),
+    }
+}
+
+/// 服务端分配的虚拟网段和期望的不同，或者和本地局域网重叠时给出提示
+fn check_network(
+    expect: Option<(u32, u32)>,
+    virtual_network: Ipv4Addr,
+    virtual_netmask: Ipv4Addr,
+    exclude: &[Ipv4Addr],
+) {
+    let prefix = u32::from(virtual_netmask).count_ones();
+    if let Some((network, netmask)) = expect {
+        if network != u32::from(virtual_network) || netmask != u32::from(virtual_netmask) {
+            log::warn!(
+                "服务端使用的虚拟网段 {}/{} 和期望的 {}/{} 不同，组已存在时使用已有的网段",
+                virtual_network,
+                prefix,
+                Ipv4Addr::from(network),
+                netmask.count_ones()
+            );
+        }
+    }
+    match crate::channel::socket::overlapping_interfaces(virtual_network, virtual_netmask, exclude)
+    {
+        Ok(list) => {
+            for (name, ip) in list {
+                log::warn!(
+                    "虚拟网段 {}/{} 和本地网卡 {} 的地址 {} 重叠，可能无法访问该局域网，可以使用--network换一个网段",
+                    virtual_network,
+                    prefix,
+                    name,
+                    ip
+                );
+            }
+        }
+        Err(e) => log::warn!("overlapping_interfaces {:?}", e),
+    }
+}
@@ -1,4 +1,5 @@
 use crate::channel::context::ChannelContext;
+use crate::channel::stats::DropReason;
 use crate::channel::RouteKey;
 use crate::handle::recv_data::PacketHandler;
 use crate::handle::CurrentDeviceInfo;
@@ -31,8 +32,13 @@ impl PacketHandler for TurnPacketHandler {
                 // 暂时不转发服务端包
                 return Ok(());
             }
+            if !context.peer_relay() {
+                // 不为其他客户端中转
+                return Ok(());
+            }
             let destination = net_packet.destination();
-            if let Some(route) = context.route_table.route_one(&destination) {
+            // 只转发给直连的目标，最多经过一个客户端中转，防止形成环路
+            if let Some(route) = context.route_table.route_one_p2p(&destination) {
                 if route.addr == route_key.addr {
                     //防止环路
                     log::warn!("来源和目标相同 {:?},{:?}", route_key, net_packet.head());
@@ -45,6 +51,9 @@ impl PacketHandler for TurnPacketHandler {
                 }
             }
             //其他没有路由的不转发
+            context.counters().packet_drop(DropReason::UnknownDest);
+        } else {
+            context.counters().packet_drop(DropReason::TtlExceeded);
         }
         log::info!("没有路由 {:?},{:?}", route_key, net_packet.head());
         Ok(())
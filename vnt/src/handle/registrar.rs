@@ -1,15 +1,21 @@
 use anyhow::anyhow;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddrV4};
 
 use protobuf::Message;
 
 use crate::cipher::Cipher;
 use crate::handle::{GATEWAY_IP, SELF_IP};
-use crate::proto::message::RegistrationRequest;
+use crate::identity::totp::TotpSecret;
+use crate::identity::IdentityConfig;
+use crate::proto::message::{AdvertiseRoute, RegistrationRequest};
 use crate::protocol::body::ENCRYPTION_RESERVED;
 use crate::protocol::{service_packet, NetPacket, Protocol, MAX_TTL};
 
 /// 注册数据
+#[cfg_attr(
+    not(all(feature = "identity", feature = "totp")),
+    allow(unused_variables)
+)]
 pub fn registration_request_packet(
     server_cipher: &Cipher,
     token: String,
@@ -19,6 +25,11 @@ pub fn registration_request_packet(
     is_fast: bool,
     allow_ip_change: bool,
     client_secret_hash: Option<&[u8]>,
+    mapped_addr: Option<SocketAddrV4>,
+    identity: Option<&IdentityConfig>,
+    totp_secret: Option<&TotpSecret>,
+    advertise_routes: &[(u32, u32)],
+    network: Option<(u32, u32)>,
 ) -> anyhow::Result<NetPacket<Vec<u8>>> {
     let mut request = RegistrationRequest::new();
     request.token = token;
@@ -36,6 +47,54 @@ pub fn registration_request_packet(
             .client_secret_hash
             .extend_from_slice(client_secret_hash);
     }
+    for (network, netmask) in advertise_routes {
+        let mut route = AdvertiseRoute::new();
+        route.network = *network;
+        route.netmask = *netmask;
+        request.advertise_routes.push(route);
+    }
+    if let Some((network, netmask)) = network {
+        request.network = network;
+        request.netmask = netmask;
+    }
+    if let Some(addr) = mapped_addr {
+        // 路由器端口映射得到的外网地址
+        request.mapped_ip = (*addr.ip()).into();
+        request.mapped_port = addr.port() as u32;
+    }
+    #[cfg(feature = "identity")]
+    if let Some(identity) = identity {
+        // 用身份私钥对注册信息签名，服务端校验通过才允许接入
+        let public_key = crate::identity::public_key(&identity.private_key);
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let invite = identity
+            .invite
+            .as_ref()
+            .map(|v| v.data.clone())
+            .unwrap_or_default();
+        let message = crate::identity::registration_message(
+            &request.token,
+            &request.device_id,
+            &public_key,
+            &invite,
+            timestamp,
+        );
+        let signature = crate::identity::sign(&identity.private_key, &message);
+        request.identity_public_key = public_key.to_vec();
+        request.identity_signature = signature.to_vec();
+        request.identity_timestamp = timestamp;
+        request.identity_cert = identity.cert.clone();
+        request.invite = invite;
+    }
+    #[cfg(feature = "totp")]
+    if let Some(totp_secret) = totp_secret {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        request.totp_code = totp_secret.code(now);
+    }
     let bytes = request
         .write_to_bytes()
         .map_err(|e| anyhow!("RegistrationRequest {:?}", e))?;
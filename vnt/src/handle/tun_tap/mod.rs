@@ -1,3 +1,4 @@
+mod name_dns;
 pub mod tun_handler;
 
 #[cfg(unix)]
@@ -7,6 +8,8 @@ use crossbeam_utils::atomic::AtomicCell;
 use parking_lot::Mutex;
 use std::sync::Arc;
 #[cfg(unix)]
+use std::time::{Duration, Instant};
+#[cfg(unix)]
 pub(crate) use unix::*;
 
 #[cfg(target_os = "windows")]
@@ -20,6 +23,9 @@ pub(crate) use windows::*;
 pub struct DeviceStop {
     f: Arc<Mutex<Option<Box<dyn FnOnce() + Send>>>>,
     stopped: Arc<AtomicCell<bool>>,
+    // 读取循环最近一次运行的时间，用于看门狗检测卡住
+    #[cfg(unix)]
+    active: Arc<AtomicCell<Option<Instant>>>,
 }
 
 impl DeviceStop {
@@ -40,4 +46,13 @@ impl DeviceStop {
     pub fn is_stopped(&self) -> bool {
         self.stopped.load()
     }
+    #[cfg(unix)]
+    pub fn active(&self) {
+        self.active.store(Some(Instant::now()));
+    }
+    /// 距离读取循环上次运行的时间
+    #[cfg(unix)]
+    pub fn idle(&self) -> Option<Duration> {
+        self.active.load().map(|time| time.elapsed())
+    }
 }
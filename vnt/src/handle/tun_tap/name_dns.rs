@@ -0,0 +1,294 @@
+//! 虚拟网络内的名称解析，发往虚拟网关53端口的dns查询直接在本地应答，
+//! <设备名称>.vnt解析为对端的虚拟ip，数据来自服务端同步的设备列表；
+//! 分流规则中的域名转发到上游dns，同时添加经过网关的主机路由
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use packet::ip::ipv4::packet::IpV4Packet;
+use packet::ip::ipv4::protocol::Protocol;
+use packet::udp::udp::UdpPacket;
+use parking_lot::Mutex;
+use tun::device::IFace;
+use tun::Device;
+
+use crate::external_route::ExternalRoute;
+use crate::handle::PeerDeviceInfo;
+use crate::util::{a_dns, system_name_servers};
+
+pub const DOMAIN_SUFFIX: &str = ".vnt";
+const DNS_PORT: u16 = 53;
+const TTL: u32 = 60;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_REFUSED: u8 = 5;
+// 同时转发到上游的分流域名查询数量上限
+const MAX_PENDING: usize = 32;
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn is_query(ipv4_packet: &IpV4Packet<&mut [u8]>) -> bool {
+    if ipv4_packet.protocol() != Protocol::Udp {
+        return false;
+    }
+    let payload = ipv4_packet.payload();
+    payload.len() > 8 && u16::from_be_bytes([payload[2], payload[3]]) == DNS_PORT
+}
+
+pub(crate) fn reply(
+    device_writer: &Arc<Device>,
+    ipv4_packet: &IpV4Packet<&mut [u8]>,
+    device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
+    ip_route: &ExternalRoute,
+) -> anyhow::Result<()> {
+    let query = UdpPacket::new(
+        ipv4_packet.source_ip(),
+        ipv4_packet.destination_ip(),
+        ipv4_packet.payload(),
+    )?;
+    let src = (ipv4_packet.source_ip(), query.source_port());
+    let dest = ipv4_packet.destination_ip();
+    // 分流的域名转发到上游dns，解析出的地址经过网关
+    if let Some((name, qtype, question_end)) = question(query.payload()) {
+        if let Some((gateway, name_servers)) = ip_route.domain_route(&name) {
+            if PENDING.fetch_add(1, Ordering::AcqRel) >= MAX_PENDING {
+                PENDING.fetch_sub(1, Ordering::AcqRel);
+                return Ok(());
+            }
+            let query = query.payload()[..question_end].to_vec();
+            let device = device_writer.clone();
+            let ip_route = ip_route.clone();
+            let rs = thread::Builder::new()
+                .name("policyDns".into())
+                .spawn(move || {
+                    let response = resolve_policy(
+                        &device,
+                        &ip_route,
+                        &query,
+                        &name,
+                        qtype,
+                        gateway,
+                        name_servers,
+                        dest,
+                    );
+                    if let Err(e) = write(&device, src, dest, &response) {
+                        log::warn!("policy dns {} {:?}", name, e);
+                    }
+                    PENDING.fetch_sub(1, Ordering::AcqRel);
+                });
+            if let Err(e) = rs {
+                PENDING.fetch_sub(1, Ordering::AcqRel);
+                Err(e)?;
+            }
+            return Ok(());
+        }
+    }
+    let response = match answer(query.payload(), |name| lookup(device_map, name)) {
+        Some(response) => response,
+        None => return Ok(()),
+    };
+    write(device_writer, src, dest, &response)
+}
+
+fn write(
+    device_writer: &Device,
+    (src_ip, src_port): (Ipv4Addr, u16),
+    dest_ip: Ipv4Addr,
+    response: &[u8],
+) -> anyhow::Result<()> {
+    let total_len = 20 + 8 + response.len();
+    let mut buf = vec![0u8; total_len];
+    buf[0] = 0x45;
+    buf[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    // 不分片
+    buf[6] = 0x40;
+    buf[8] = 64;
+    buf[9] = 17;
+    buf[24..26].copy_from_slice(&((8 + response.len()) as u16).to_be_bytes());
+    buf[28..].copy_from_slice(response);
+    let mut udp = UdpPacket::unchecked(dest_ip, src_ip, &mut buf[20..]);
+    udp.set_source_port(DNS_PORT);
+    udp.set_destination_port(src_port);
+    udp.update_checksum();
+    let mut ipv4 = IpV4Packet::unchecked(&mut buf[..]);
+    ipv4.set_source_ip(dest_ip);
+    ipv4.set_destination_ip(src_ip);
+    ipv4.update_checksum();
+    device_writer.write(&buf)?;
+    Ok(())
+}
+
+/// 向上游dns查询A记录，先添加经过网关的主机路由再应答，
+/// 这样后续的连接一开始就走虚拟网络。只分流ipv4，AAAA查询返回空应答
+fn resolve_policy(
+    device: &Device,
+    ip_route: &ExternalRoute,
+    query: &[u8],
+    name: &str,
+    qtype: u16,
+    gateway: Ipv4Addr,
+    mut name_servers: Vec<String>,
+    virtual_gateway: Ipv4Addr,
+) -> Vec<u8> {
+    if qtype != TYPE_A {
+        return response(query, 0, qtype, &[], TTL);
+    }
+    if name_servers.is_empty() {
+        name_servers = system_name_servers();
+        // 系统dns指向虚拟网关时不能再发给自己
+        let local = format!("{}:{}", virtual_gateway, DNS_PORT);
+        name_servers.retain(|v| v != &local);
+    }
+    for name_server in name_servers {
+        let (ips, ttl) = match a_dns(name.to_string(), name_server.clone()) {
+            Ok((ips, ttl)) if !ips.is_empty() => (ips, ttl),
+            Ok(_) => continue,
+            Err(e) => {
+                log::warn!("policy dns {} {} {:?}", name, name_server, e);
+                continue;
+            }
+        };
+        let (removed, added) = ip_route.add_resolved(&ips, gateway);
+        for (dest, mask) in removed {
+            if let Err(e) = device.delete_route(dest, mask) {
+                log::warn!("删除路由失败 {}/{} {:?}", dest, mask, e);
+            }
+        }
+        for (dest, mask) in added {
+            if let Err(e) = device.add_route(dest, mask, 1) {
+                log::warn!("添加路由失败 {}/{} {:?}", dest, mask, e);
+            }
+        }
+        let records: Vec<Vec<u8>> = ips.iter().map(|ip| ip.octets().to_vec()).collect();
+        return response(query, 0, qtype, &records, ttl.min(TTL));
+    }
+    response(query, RCODE_SERVFAIL, qtype, &[], TTL)
+}
+
+// 设备名称中的空格换成'-'，不区分大小写
+fn lookup(
+    device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
+    name: &str,
+) -> Option<(Ipv4Addr, Ipv6Addr)> {
+    device_map
+        .lock()
+        .1
+        .values()
+        .find(|v| v.name.replace(' ', "-").eq_ignore_ascii_case(name))
+        .map(|v| (v.virtual_ip, v.virtual_ipv6))
+}
+
+/// 解析第一个问题，返回名称、类型和问题结束的位置，格式错误时返回None
+fn question(query: &[u8]) -> Option<(String, u16, usize)> {
+    // 12字节头部，QR=0，问题数量至少1
+    if query.len() < 12 || query[2] & 0x80 != 0 {
+        return None;
+    }
+    if u16::from_be_bytes([query[4], query[5]]) == 0 {
+        return None;
+    }
+    let mut labels = Vec::new();
+    let mut offset = 12;
+    loop {
+        let len = *query.get(offset)? as usize;
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+        // 查询中不会有压缩指针
+        if len & 0xc0 != 0 {
+            return None;
+        }
+        labels.push(std::str::from_utf8(query.get(offset..offset + len)?).ok()?);
+        offset += len;
+    }
+    let qtype = u16::from_be_bytes([*query.get(offset)?, *query.get(offset + 1)?]);
+    let question_end = offset + 4;
+    if query.len() < question_end {
+        return None;
+    }
+    Some((labels.join("."), qtype, question_end))
+}
+
+/// query只包含头部和第一个问题，records是每条记录的数据
+fn response(query: &[u8], rcode: u8, qtype: u16, records: &[Vec<u8>], ttl: u32) -> Vec<u8> {
+    let mut response = Vec::with_capacity(query.len() + records.len() * 28);
+    response.extend_from_slice(&query[..2]);
+    // QR=1，AA=1，保留opcode和RD
+    response.push(0x80 | (query[2] & 0x79) | 0x04);
+    response.push(rcode);
+    response.extend_from_slice(&[0, 1]);
+    response.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(&query[12..]);
+    for rdata in records {
+        // 指向问题中的名称
+        response.extend_from_slice(&[0xc0, 12]);
+        response.extend_from_slice(&qtype.to_be_bytes());
+        response.extend_from_slice(&1u16.to_be_bytes());
+        response.extend_from_slice(&ttl.to_be_bytes());
+        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        response.extend_from_slice(rdata);
+    }
+    response
+}
+
+/// 根据查询生成应答，只处理第一个问题，不是.vnt结尾的名称返回REFUSED，
+/// 格式错误时返回None，直接丢弃
+fn answer<F>(query: &[u8], resolve: F) -> Option<Vec<u8>>
+where
+    F: Fn(&str) -> Option<(Ipv4Addr, Ipv6Addr)>,
+{
+    let (name, qtype, question_end) = question(query)?;
+    let query = &query[..question_end];
+    let suffix = &DOMAIN_SUFFIX[1..];
+    let device_name = match name.rsplit_once('.') {
+        Some((device_name, domain)) if domain.eq_ignore_ascii_case(suffix) => device_name,
+        _ => return Some(response(query, RCODE_REFUSED, qtype, &[], TTL)),
+    };
+    let record = resolve(device_name);
+    let rdata: Option<Vec<u8>> = match (record, qtype) {
+        (Some((ip, _)), TYPE_A) => Some(ip.octets().to_vec()),
+        (Some((_, ipv6)), TYPE_AAAA) if !ipv6.is_unspecified() => Some(ipv6.octets().to_vec()),
+        _ => None,
+    };
+    // 名称存在但没有对应类型的记录时返回空应答
+    let rcode = if record.is_some() { 0 } else { RCODE_NXDOMAIN };
+    let records: Vec<Vec<u8>> = rdata.into_iter().collect();
+    Some(response(query, rcode, qtype, &records, TTL))
+}
+
+#[test]
+fn answer_test() {
+    let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    query.extend_from_slice(b"\x03nas\x03vnt\x00");
+    query.extend_from_slice(&[0, 1, 0, 1]);
+    let ip = Ipv4Addr::new(10, 26, 0, 5);
+    let resolve = |name: &str| {
+        if name.eq_ignore_ascii_case("nas") {
+            Some((ip, Ipv6Addr::UNSPECIFIED))
+        } else {
+            None
+        }
+    };
+    let response = answer(&query, resolve).unwrap();
+    assert_eq!(&response[..2], &[0x12, 0x34]);
+    assert_eq!(response[3] & 0x0f, 0);
+    assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+    assert_eq!(&response[response.len() - 4..], &ip.octets());
+
+    let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    query.extend_from_slice(b"\x05other\x03vnt\x00");
+    query.extend_from_slice(&[0, 1, 0, 1]);
+    let response = answer(&query, resolve).unwrap();
+    assert_eq!(response[3] & 0x0f, RCODE_NXDOMAIN);
+
+    let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    query.extend_from_slice(b"\x07example\x03com\x00");
+    query.extend_from_slice(&[0, 1, 0, 1]);
+    let response = answer(&query, resolve).unwrap();
+    assert_eq!(response[3] & 0x0f, RCODE_REFUSED);
+}
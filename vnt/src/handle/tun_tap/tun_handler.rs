@@ -1,28 +1,34 @@
 use crossbeam_utils::atomic::AtomicCell;
 use parking_lot::Mutex;
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
 use std::{io, thread};
 
 use packet::icmp::icmp::IcmpPacket;
 use packet::icmp::Kind;
+use packet::icmpv6;
 use packet::ip::ipv4::packet::IpV4Packet;
 use packet::ip::ipv4::protocol::Protocol;
 use tun::device::IFace;
 use tun::Device;
 
+use crate::acl::{ethernet_ip, IpInfo};
 use crate::channel::context::ChannelContext;
+use crate::channel::pmtu::clamp_mss;
+use crate::channel::qos::QosMark;
 use crate::channel::sender::{send_to_wg, send_to_wg_broadcast};
+use crate::channel::stats::DropReason;
 use crate::cipher::Cipher;
 use crate::compression::Compressor;
 use crate::external_route::ExternalRoute;
-use crate::handle::tun_tap::DeviceStop;
-use crate::handle::{CurrentDeviceInfo, PeerDeviceInfo};
+use crate::handle::tun_tap::{name_dns, DeviceStop};
+use crate::handle::{mapped_ipv4, CurrentDeviceInfo, PeerDeviceInfo};
 #[cfg(feature = "ip_proxy")]
 use crate::ip_proxy::IpProxyMap;
 #[cfg(feature = "ip_proxy")]
 use crate::ip_proxy::ProxyHandler;
+use crate::peer_key::encrypt_to_peer;
 use crate::protocol;
 use crate::protocol::body::ENCRYPTION_RESERVED;
 use crate::protocol::ip_turn_packet::BroadcastPacket;
@@ -94,7 +100,9 @@ fn broadcast(
         .lock()
         .1
         .values()
-        .filter(|info| !info.wireguard && info.status.is_online())
+        .filter(|info| {
+            !info.wireguard && info.status.is_online() && sender.same_layer(&info.virtual_ip)
+        })
         .map(|info| info.virtual_ip)
         .collect();
     if list.is_empty() {
@@ -160,7 +168,7 @@ pub(crate) fn handle(
     buf: &mut [u8],
     data_len: usize, //数据总长度=12+ip包长度
     extend: &mut [u8],
-    device_writer: &Device,
+    device_writer: &Arc<Device>,
     current_device: CurrentDeviceInfo,
     ip_route: &ExternalRoute,
     #[cfg(feature = "ip_proxy")] proxy_map: &Option<IpProxyMap>,
@@ -170,7 +178,37 @@ pub(crate) fn handle(
     compressor: &Compressor,
     allow_wire_guard: bool,
 ) -> anyhow::Result<()> {
-    //忽略掉结构不对的情况（ipv6数据、win tap会读到空数据），不然日志打印太多了
+    if let Some(capture) = context.capture() {
+        if data_len > 12 {
+            capture.inner(&buf[12..data_len], false);
+        }
+    }
+    if context.layer2() {
+        return ethernet(
+            context,
+            buf,
+            data_len,
+            current_device,
+            client_cipher,
+            server_cipher,
+            device_map,
+        );
+    }
+    // 开启qos时按内层ip包的DSCP和长度决定发送的优先级
+    let _qos_mark = context.qos().then(|| QosMark::new(&buf[12..data_len]));
+    if data_len > 12 && buf[12] >> 4 == 6 {
+        return ipv6(
+            context,
+            buf,
+            data_len,
+            device_writer,
+            current_device,
+            client_cipher,
+            server_cipher,
+            device_map,
+        );
+    }
+    //忽略掉结构不对的情况（win tap会读到空数据），不然日志打印太多了
     let ipv4_packet = match IpV4Packet::new(&mut buf[12..data_len]) {
         Ok(packet) => packet,
         Err(_) => return Ok(()),
@@ -180,9 +218,20 @@ pub(crate) fn handle(
     if src_ip == dest_ip {
         return icmp(&device_writer, ipv4_packet);
     }
+    // 发往网关的dns查询在本地解析设备名称
+    if dest_ip == current_device.virtual_gateway && name_dns::is_query(&ipv4_packet) {
+        return name_dns::reply(device_writer, &ipv4_packet, device_map, ip_route);
+    }
     let protocol = ipv4_packet.protocol();
     let src_ip = ipv4_packet.source_ip();
     let mut dest_ip = ipv4_packet.destination_ip();
+    #[cfg(feature = "wireguard")]
+    if let Some(wireguard) = context.wireguard() {
+        // 发往wg对端的数据不经过vnt通道
+        if wireguard.send(dest_ip, &buf[12..data_len])? {
+            return Ok(());
+        }
+    }
     let mut net_packet = NetPacket::new0(data_len, buf)?;
     let mut out = NetPacket::unchecked(extend);
     net_packet.set_default_version();
@@ -208,10 +257,17 @@ pub(crate) fn handle(
                 if r_dest_ip == src_ip {
                     return Ok(());
                 }
+                // 网关离线时直接丢弃，不会从其他网卡发出去
+                if let Some(peer_info) = device_map.lock().1.get(&r_dest_ip) {
+                    if peer_info.status.is_offline() {
+                        return Ok(());
+                    }
+                }
                 //需要修改目的地址
                 dest_ip = r_dest_ip;
                 net_packet.set_destination(r_dest_ip);
             } else {
+                context.counters().packet_drop(DropReason::UnknownDest);
                 return Ok(());
             }
         }
@@ -228,6 +284,10 @@ pub(crate) fn handle(
         net_packet.set_destination(Ipv4Addr::BROADCAST);
     }
     let is_broadcast = dest_ip.is_broadcast() || current_device.broadcast_ip == dest_ip;
+    if !is_broadcast && !context.acl().allow_out(dest_ip, net_packet.payload()) {
+        return Ok(());
+    }
+    firewall_track_out(context, &current_device, net_packet.payload());
     if allow_wire_guard {
         if is_broadcast {
             // wg客户端和vnt客户端分开广播
@@ -255,7 +315,18 @@ pub(crate) fn handle(
         }
     }
 
-    let mut net_packet = if compressor.compress(&net_packet, &mut out)? {
+    if !is_broadcast {
+        if let Some(peer_stats) = context.peer_stats() {
+            peer_stats.flow_out(dest_ip, &IpV4Packet::new(net_packet.payload())?);
+        }
+        // 按路径mtu钳制tcp mss，避免大包被静默丢弃
+        if let Some(mss) = context.route_table.path_mss(&dest_ip) {
+            clamp_mss(net_packet.payload_mut(), mss);
+        }
+    }
+    // 广播包的接收方不确定，只压缩发给支持对应解压算法的对端的包
+    let compress = !is_broadcast && compressor.is_supported(context.peer_features(&dest_ip));
+    let mut net_packet = if compress && compressor.compress(&net_packet, &mut out)? {
         out.set_default_version();
         out.set_protocol(protocol::Protocol::IpTurn);
         out.set_transport_protocol(ip_turn_packet::Protocol::Ipv4.into());
@@ -279,7 +350,199 @@ pub(crate) fn handle(
         return Ok(());
     }
 
-    client_cipher.encrypt_ipv4(&mut net_packet)?;
+    encrypt_to_peer(context, client_cipher, &mut net_packet, &dest_ip)?;
+    context.send_ipv4_by_id(
+        &net_packet,
+        &dest_ip,
+        current_device.connect_server,
+        current_device.status.online(),
+    )?;
+    Ok(())
+}
+
+/// 虚拟ipv6对应的客户端
+fn ipv6_peer(
+    dest: &Ipv6Addr,
+    device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
+) -> Option<Ipv4Addr> {
+    if let Some(ip) = mapped_ipv4(dest) {
+        return Some(ip);
+    }
+    let guard = device_map.lock();
+    guard
+        .1
+        .values()
+        .find(|info| info.virtual_ipv6 == *dest)
+        .map(|info| info.virtual_ip)
+}
+
+/// 回复邻居请求，目标是在线的客户端时代替对端回复邻居通告
+fn neighbor_solicitation(
+    packet: &[u8],
+    device_writer: &Device,
+    current_device: &CurrentDeviceInfo,
+    device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
+) -> anyhow::Result<()> {
+    let (source, target) = match icmpv6::neighbor_solicitation(packet) {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    // 重复地址检测不用回复
+    if source.is_unspecified() || target == current_device.virtual_ipv6 {
+        return Ok(());
+    }
+    let ip = match ipv6_peer(&target, device_map) {
+        Some(ip) => ip,
+        None => return Ok(()),
+    };
+    let online = device_map
+        .lock()
+        .1
+        .get(&ip)
+        .map_or(false, |info| info.status.is_online());
+    if online {
+        device_writer.write(&icmpv6::neighbor_advertisement(target, source, None))?;
+    }
+    Ok(())
+}
+
+/// 转发ipv6包，目标是虚拟ipv6时发到对应的客户端，组播当作广播处理
+fn ipv6(
+    context: &ChannelContext,
+    buf: &mut [u8],
+    data_len: usize,
+    device_writer: &Device,
+    current_device: CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    server_cipher: &Cipher,
+    device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
+) -> anyhow::Result<()> {
+    if data_len < 12 + 40 {
+        return Ok(());
+    }
+    // 邻居发现和组播监听只在本地链路有意义，不转发给其他客户端
+    if let Some(kind) = icmpv6::link_control_kind(&buf[12..data_len]) {
+        if kind == icmpv6::NEIGHBOR_SOLICITATION {
+            neighbor_solicitation(
+                &buf[12..data_len],
+                device_writer,
+                &current_device,
+                device_map,
+            )?;
+        }
+        return Ok(());
+    }
+    let dest: [u8; 16] = buf[12 + 24..12 + 40].try_into().unwrap();
+    let dest = Ipv6Addr::from(dest);
+    if dest == current_device.virtual_ipv6 {
+        return Ok(());
+    }
+    let dest_ip = if dest.is_multicast() {
+        Ipv4Addr::BROADCAST
+    } else {
+        match ipv6_peer(&dest, device_map) {
+            Some(ip) => ip,
+            None => return Ok(()),
+        }
+    };
+    if dest_ip == current_device.virtual_ip || current_device.is_gateway(&dest_ip) {
+        return Ok(());
+    }
+    if !dest_ip.is_broadcast() && !context.acl().allow_out(dest_ip, &buf[12..data_len]) {
+        return Ok(());
+    }
+    firewall_track_out(context, &current_device, &buf[12..data_len]);
+    let mut net_packet = NetPacket::new0(data_len, buf)?;
+    net_packet.set_default_version();
+    net_packet.set_protocol(protocol::Protocol::IpTurn);
+    net_packet.set_transport_protocol(ip_turn_packet::Protocol::Ipv6.into());
+    net_packet.first_set_ttl(6);
+    net_packet.set_source(current_device.virtual_ip);
+    net_packet.set_destination(dest_ip);
+    if dest_ip.is_broadcast() {
+        client_cipher.encrypt_ipv4(&mut net_packet)?;
+        broadcast(
+            server_cipher,
+            context,
+            &mut net_packet,
+            &current_device,
+            device_map,
+        )?;
+        return Ok(());
+    }
+    encrypt_to_peer(context, client_cipher, &mut net_packet, &dest_ip)?;
+    context.send_ipv4_by_id(
+        &net_packet,
+        &dest_ip,
+        current_device.connect_server,
+        current_device.status.online(),
+    )?;
+    Ok(())
+}
+
+/// 记录本机发起的连接，防火墙放行回包，转发其他设备的数据不记录
+fn firewall_track_out(context: &ChannelContext, current_device: &CurrentDeviceInfo, packet: &[u8]) {
+    let firewall = context.acl().firewall();
+    if !firewall.is_enabled() {
+        return;
+    }
+    let local = match IpInfo::new(packet).map(|info| info.source) {
+        Some(IpAddr::V4(ip)) => ip == current_device.virtual_ip,
+        Some(IpAddr::V6(ip)) => ip == current_device.virtual_ipv6,
+        None => false,
+    };
+    if local {
+        firewall.track_out(packet);
+    }
+}
+
+/// 二层模式，转发以太网帧，目标mac已学习时发到对应的客户端，广播、组播和未知的mac泛洪
+fn ethernet(
+    context: &ChannelContext,
+    buf: &mut [u8],
+    data_len: usize,
+    current_device: CurrentDeviceInfo,
+    client_cipher: &Cipher,
+    server_cipher: &Cipher,
+    device_map: &Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>,
+) -> anyhow::Result<()> {
+    if data_len < 12 + 14 {
+        return Ok(());
+    }
+    let dest_mac: [u8; 6] = buf[12..18].try_into().unwrap();
+    let dest_ip = if dest_mac[0] & 1 == 1 {
+        Ipv4Addr::BROADCAST
+    } else {
+        context
+            .mac_table()
+            .lookup(&dest_mac)
+            .unwrap_or(Ipv4Addr::BROADCAST)
+    };
+    if let Some(ip) = ethernet_ip(&buf[12..data_len]) {
+        if !dest_ip.is_broadcast() && !context.acl().allow_out(dest_ip, ip) {
+            return Ok(());
+        }
+        firewall_track_out(context, &current_device, ip);
+    }
+    let mut net_packet = NetPacket::new0(data_len, buf)?;
+    net_packet.set_default_version();
+    net_packet.set_protocol(protocol::Protocol::IpTurn);
+    net_packet.set_transport_protocol(ip_turn_packet::Protocol::Ethernet.into());
+    net_packet.first_set_ttl(6);
+    net_packet.set_source(current_device.virtual_ip);
+    net_packet.set_destination(dest_ip);
+    if dest_ip.is_broadcast() {
+        client_cipher.encrypt_ipv4(&mut net_packet)?;
+        broadcast(
+            server_cipher,
+            context,
+            &mut net_packet,
+            &current_device,
+            device_map,
+        )?;
+        return Ok(());
+    }
+    encrypt_to_peer(context, client_cipher, &mut net_packet, &dest_ip)?;
     context.send_ipv4_by_id(
         &net_packet,
         &dest_ip,
@@ -18,10 +18,15 @@ use std::io;
 use std::net::Ipv4Addr;
 use std::os::fd::AsRawFd;
 use std::sync::Arc;
+use std::time::Duration;
 use tun::Device;
 
 const STOP: Token = Token(0);
 const FD: Token = Token(1);
+// 没有数据时也定时唤醒，表示读取循环没有卡住
+const ACTIVE_INTERVAL: Duration = Duration::from_secs(1);
+// 停止时最多再读取的包数，流量持续不断时也能停下来
+const FLUSH_MAX: usize = 256;
 
 pub(crate) fn start_simple(
     stop_manager: StopManager,
@@ -67,6 +72,7 @@ pub(crate) fn start_simple(
         device_map,
         compressor,
         allow_wire_guard,
+        &device_stop,
     ) {
         log::error!("{:?}", e);
     };
@@ -90,6 +96,7 @@ fn start_simple0(
     device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
     compressor: Compressor,
     allow_wire_guard: bool,
+    device_stop: &DeviceStop,
 ) -> anyhow::Result<()> {
     let mut buf = [0; BUFFER_SIZE];
     let mut extend = [0; BUFFER_SIZE];
@@ -97,20 +104,30 @@ fn start_simple0(
     fd.set_nonblock()?;
     SourceFd(&fd.as_raw_fd()).register(poll.registry(), FD, Interest::READABLE)?;
     let mut events = Events::with_capacity(4);
-    #[cfg(not(target_os = "macos"))]
+    #[cfg(not(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd")))]
     let start = 12;
-    #[cfg(target_os = "macos")]
+    // 读取的数据前面有4字节的地址族
+    #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
     let start = 12 - 4;
     loop {
-        if let Err(e) = poll.poll(&mut events, None) {
+        device_stop.active();
+        if let Err(e) = poll.poll(&mut events, Some(ACTIVE_INTERVAL)) {
             crate::ignore_io_interrupted(e)?;
             continue;
         }
+        let mut stop = false;
+        let mut readable = false;
         for event in events.iter() {
             if event.token() == STOP {
-                return Ok(());
+                stop = true;
+            } else {
+                readable = true;
             }
+        }
+        // 停止时把网卡队列中已有的包读完发出
+        if readable || stop {
             let mut retries = 0;
+            let mut flushed = 0;
             loop {
                 let len = match fd.read(&mut buf[start..]) {
                     Ok(len) => len + start,
@@ -125,6 +142,12 @@ fn start_simple0(
                         Err(e)?
                     }
                 };
+                if stop {
+                    flushed += 1;
+                    if flushed > FLUSH_MAX {
+                        break;
+                    }
+                }
                 // buf是重复利用的，需要重置头部
                 buf[..12].fill(0);
                 match crate::handle::tun_tap::tun_handler::handle(
@@ -150,5 +173,8 @@ fn start_simple0(
                 }
             }
         }
+        if stop {
+            return Ok(());
+        }
     }
 }
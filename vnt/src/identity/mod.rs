@@ -0,0 +1,279 @@
+use std::fmt::{Debug, Formatter};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use base64::Engine;
+use protobuf::Message;
+use sha2::Digest;
+
+use crate::proto::message::InviteToken;
+use crate::wireguard::decode_key;
+
+pub mod totp;
+
+// CA公钥(32) | 过期时间(8) | 签名(64)
+pub const CERT_LEN: usize = 32 + 8 + 64;
+
+/// 客户端身份，每个客户端持有自己的ed25519私钥，注册时对注册信息签名，
+/// 服务端用公钥白名单或CA校验，单个设备的私钥泄露不影响其他设备，也不需要更换token
+#[derive(Clone)]
+pub struct IdentityConfig {
+    pub private_key: [u8; 32],
+    // CA签发的证书，服务端使用CA认证时需要
+    pub cert: Vec<u8>,
+    // 通过邀请码加入，服务端校验后把身份公钥加入白名单
+    pub invite: Option<Invite>,
+}
+
+impl IdentityConfig {
+    pub fn new(
+        private_key: &str,
+        cert: Option<&str>,
+        invite: Option<Invite>,
+    ) -> anyhow::Result<Self> {
+        let private_key = decode_key(private_key)?;
+        let cert = match cert {
+            Some(cert) => {
+                let cert = base64::engine::general_purpose::STANDARD
+                    .decode(cert.trim())
+                    .map_err(|e| anyhow!("cert {:?} {}", cert, e))?;
+                if cert.len() != CERT_LEN {
+                    return Err(anyhow!("cert must be {} bytes", CERT_LEN));
+                }
+                cert
+            }
+            None => Vec::new(),
+        };
+        if let Some(invite) = invite.as_ref() {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs();
+            if invite.is_expired(now) {
+                return Err(anyhow!("invite expired"));
+            }
+        }
+        Ok(Self {
+            private_key,
+            cert,
+            invite,
+        })
+    }
+}
+
+impl Debug for IdentityConfig {
+    // 不能打印私钥
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdentityConfig")
+            .field("cert", &!self.cert.is_empty())
+            .field("invite", &self.invite)
+            .finish()
+    }
+}
+
+/// 服务端签发的邀请码，包含组网token、服务端地址和过期时间，签名由服务端校验，
+/// 客户端只解析出连接需要的信息，注册时把原始数据发给服务端
+#[derive(Clone)]
+pub struct Invite {
+    pub token: String,
+    pub server: String,
+    // 过期的unix时间(秒)，0表示不过期
+    pub expire: u64,
+    // 只能使用一次
+    pub one_time: bool,
+    pub data: Vec<u8>,
+}
+
+impl Invite {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expire != 0 && self.expire < now
+    }
+}
+
+impl FromStr for Invite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(s.trim())
+            .map_err(|e| format!("invite decode {}", e))?;
+        let invite =
+            InviteToken::parse_from_bytes(&data).map_err(|e| format!("invite parse {}", e))?;
+        if invite.token.is_empty() || invite.signature.is_empty() {
+            return Err("invalid invite".to_string());
+        }
+        Ok(Self {
+            token: invite.token,
+            server: invite.server,
+            expire: invite.expire,
+            one_time: invite.one_time,
+            data,
+        })
+    }
+}
+
+impl Debug for Invite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Invite")
+            .field("server", &self.server)
+            .field("expire", &self.expire)
+            .field("one_time", &self.one_time)
+            .finish()
+    }
+}
+
+/// 公钥指纹，和ssh的格式一样，用于人工核对设备身份
+pub fn fingerprint(public_key: &[u8]) -> String {
+    format!(
+        "SHA256:{}",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(sha2::Sha256::digest(public_key))
+    )
+}
+
+/// 注册时签名的数据，带上时间戳，服务端拒绝时间偏差过大的请求防止重放，
+/// 使用邀请码时邀请码也参与签名，和身份公钥绑定
+pub fn registration_message(
+    token: &str,
+    device_id: &str,
+    public_key: &[u8],
+    invite: &[u8],
+    timestamp: u64,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(64 + token.len() + device_id.len() + invite.len());
+    message.extend_from_slice(b"vnt-registration");
+    for item in [token.as_bytes(), device_id.as_bytes(), public_key, invite] {
+        message.extend_from_slice(&(item.len() as u32).to_be_bytes());
+        message.extend_from_slice(item);
+    }
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message
+}
+
+/// CA签发证书时签名的数据，expire是过期的unix时间(秒)，0表示不过期
+pub fn cert_message(public_key: &[u8], expire: u64) -> Vec<u8> {
+    let mut message = Vec::with_capacity(17 + public_key.len() + 8);
+    message.extend_from_slice(b"vnt-identity-cert");
+    message.extend_from_slice(public_key);
+    message.extend_from_slice(&expire.to_be_bytes());
+    message
+}
+
+#[cfg(feature = "identity")]
+pub fn generate_key() -> ([u8; 32], [u8; 32]) {
+    let private_key = rand::random::<[u8; 32]>();
+    (private_key, public_key(&private_key))
+}
+
+#[cfg(feature = "identity")]
+pub fn public_key(private_key: &[u8; 32]) -> [u8; 32] {
+    ed25519_dalek::SigningKey::from_bytes(private_key)
+        .verifying_key()
+        .to_bytes()
+}
+
+#[cfg(feature = "identity")]
+pub fn sign(private_key: &[u8; 32], message: &[u8]) -> [u8; 64] {
+    use ed25519_dalek::Signer;
+    ed25519_dalek::SigningKey::from_bytes(private_key)
+        .sign(message)
+        .to_bytes()
+}
+
+#[cfg(feature = "identity")]
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    use ed25519_dalek::Verifier;
+    let public_key: [u8; 32] = match public_key.try_into() {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    let signature: [u8; 64] = match signature.try_into() {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    match ed25519_dalek::VerifyingKey::from_bytes(&public_key) {
+        Ok(key) => key
+            .verify(message, &ed25519_dalek::Signature::from_bytes(&signature))
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// 用CA私钥给客户端公钥签发证书，返回base64编码
+#[cfg(feature = "identity")]
+pub fn issue_cert(ca_private_key: &[u8; 32], public_key: &[u8; 32], expire: u64) -> String {
+    let mut cert = Vec::with_capacity(CERT_LEN);
+    cert.extend_from_slice(&self::public_key(ca_private_key));
+    cert.extend_from_slice(&expire.to_be_bytes());
+    cert.extend_from_slice(&sign(ca_private_key, &cert_message(public_key, expire)));
+    base64::engine::general_purpose::STANDARD.encode(cert)
+}
+
+/// 校验证书，返回签发证书的CA公钥，服务端还需要检查CA公钥是否可信
+#[cfg(feature = "identity")]
+pub fn verify_cert(cert: &[u8], public_key: &[u8], now: u64) -> Option<[u8; 32]> {
+    if cert.len() != CERT_LEN {
+        return None;
+    }
+    let ca_public_key: [u8; 32] = cert[..32].try_into().unwrap();
+    let expire = u64::from_be_bytes(cert[32..40].try_into().unwrap());
+    if expire != 0 && expire < now {
+        return None;
+    }
+    if verify(
+        &ca_public_key,
+        &cert_message(public_key, expire),
+        &cert[40..],
+    ) {
+        Some(ca_public_key)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "identity")]
+#[test]
+fn test_identity() {
+    let (private_key, public_key) = generate_key();
+    let message = registration_message("token", "device", &public_key, &[], 100);
+    let signature = sign(&private_key, &message);
+    assert!(verify(&public_key, &message, &signature));
+    let message = registration_message("token", "device", &public_key, &[], 101);
+    assert!(!verify(&public_key, &message, &signature));
+    // CA签发的证书
+    let (ca_private_key, ca_public_key) = generate_key();
+    let cert = issue_cert(&ca_private_key, &public_key, 200);
+    let cert = IdentityConfig::new(
+        &crate::wireguard::encode_key(&private_key),
+        Some(&cert),
+        None,
+    )
+    .unwrap()
+    .cert;
+    assert_eq!(verify_cert(&cert, &public_key, 100), Some(ca_public_key));
+    assert_eq!(verify_cert(&cert, &public_key, 201), None);
+    assert_eq!(verify_cert(&cert, &ca_public_key, 100), None);
+    assert!(fingerprint(&public_key).starts_with("SHA256:"));
+}
+
+#[test]
+fn test_invite() {
+    let mut token = InviteToken::new();
+    token.token = "abc".to_string();
+    token.server = "tcp://127.0.0.1:29872".to_string();
+    token.expire = 100;
+    token.one_time = true;
+    token.signature = vec![1; 64];
+    let blob =
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(token.write_to_bytes().unwrap());
+    let invite = Invite::from_str(&blob).unwrap();
+    assert_eq!(invite.token, "abc");
+    assert_eq!(invite.server, "tcp://127.0.0.1:29872");
+    assert!(invite.one_time);
+    assert!(!invite.is_expired(100));
+    assert!(invite.is_expired(101));
+    // 没有签名的邀请码
+    token.signature.clear();
+    let blob =
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(token.write_to_bytes().unwrap());
+    assert!(Invite::from_str(&blob).is_err());
+    assert!(Invite::from_str("not base64!").is_err());
+}
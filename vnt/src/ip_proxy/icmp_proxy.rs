@@ -17,6 +17,7 @@ use crate::channel::socket::{LocalInterface, VntSocketTrait};
 use crate::cipher::Cipher;
 use crate::handle::CurrentDeviceInfo;
 use crate::ip_proxy::ProxyHandler;
+use crate::peer_key::encrypt_to_peer;
 use crate::protocol;
 use crate::protocol::{NetPacket, MAX_TTL};
 #[derive(Clone)]
@@ -33,7 +34,13 @@ impl IcmpProxy {
         client_cipher: Cipher,
         default_interface: &LocalInterface,
     ) -> anyhow::Result<Self> {
-        #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+        #[cfg(any(
+            target_os = "windows",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        ))]
         let icmp_socket = socket2::Socket::new(
             socket2::Domain::IPV4,
             socket2::Type::RAW,
@@ -90,7 +97,13 @@ async fn icmp_proxy(
     client_cipher: Cipher,
 ) -> io::Result<()> {
     let mut buf = [0u8; 65535 - 20 - 8];
-    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
     let start = 12;
     #[cfg(target_os = "android")]
     let start = 12 + 20;
@@ -153,7 +166,9 @@ fn recv_handle(
                         net_packet.first_set_ttl(MAX_TTL);
                         net_packet.set_source(virtual_ip);
                         net_packet.set_destination(dest_ip);
-                        if let Err(e) = client_cipher.encrypt_ipv4(&mut net_packet) {
+                        if let Err(e) =
+                            encrypt_to_peer(context, client_cipher, &mut net_packet, &dest_ip)
+                        {
                             log::warn!("加密失败:{}", e);
                             return;
                         }
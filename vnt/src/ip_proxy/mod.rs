@@ -10,13 +10,25 @@ use packet::ip::ipv4::packet::IpV4Packet;
 use crate::channel::context::ChannelContext;
 use crate::cipher::Cipher;
 use crate::handle::CurrentDeviceInfo;
-#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+#[cfg(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
 use crate::ip_proxy::icmp_proxy::IcmpProxy;
 use crate::ip_proxy::tcp_proxy::TcpProxy;
 use crate::ip_proxy::udp_proxy::UdpProxy;
 use crate::util::StopManager;
 
-#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+#[cfg(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
 pub mod icmp_proxy;
 pub mod tcp_proxy;
 pub mod udp_proxy;
@@ -33,7 +45,13 @@ pub trait ProxyHandler {
 
 #[derive(Clone)]
 pub struct IpProxyMap {
-    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
     icmp_proxy: IcmpProxy,
     tcp_proxy: TcpProxy,
     udp_proxy: UdpProxy,
@@ -73,14 +91,26 @@ async fn init_proxy0(
     _client_cipher: Cipher,
 ) -> anyhow::Result<IpProxyMap> {
     let default_interface = context.default_interface().clone();
-    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
     let icmp_proxy =
         IcmpProxy::new(context, _current_device, _client_cipher, &default_interface).await?;
     let tcp_proxy = TcpProxy::new(default_interface.clone()).await?;
     let udp_proxy = UdpProxy::new(default_interface.clone()).await?;
 
     Ok(IpProxyMap {
-        #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+        #[cfg(any(
+            target_os = "windows",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        ))]
         icmp_proxy,
         tcp_proxy,
         udp_proxy,
@@ -97,7 +127,13 @@ impl ProxyHandler for IpProxyMap {
         match ipv4.protocol() {
             ipv4::protocol::Protocol::Tcp => self.tcp_proxy.recv_handle(ipv4, source, destination),
             ipv4::protocol::Protocol::Udp => self.udp_proxy.recv_handle(ipv4, source, destination),
-            #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+            #[cfg(any(
+                target_os = "windows",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd"
+            ))]
             ipv4::protocol::Protocol::Icmp => {
                 self.icmp_proxy.recv_handle(ipv4, source, destination)
             }
@@ -118,7 +154,13 @@ impl ProxyHandler for IpProxyMap {
         match ipv4.protocol() {
             ipv4::protocol::Protocol::Tcp => self.tcp_proxy.send_handle(ipv4),
             ipv4::protocol::Protocol::Udp => self.udp_proxy.send_handle(ipv4),
-            #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+            #[cfg(any(
+                target_os = "windows",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd"
+            ))]
             ipv4::protocol::Protocol::Icmp => self.icmp_proxy.send_handle(ipv4),
             _ => Ok(()),
         }
@@ -1,20 +1,30 @@
 pub const VNT_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+pub mod acl;
+#[cfg(target_os = "android")]
+#[cfg(feature = "integrated_tun")]
+pub mod android;
 pub mod channel;
 pub mod cipher;
 pub mod core;
-mod external_route;
+pub mod external_route;
+pub mod fec;
 pub mod handle;
+pub mod identity;
 #[cfg(feature = "ip_proxy")]
 mod ip_proxy;
 pub mod nat;
+pub mod obfs;
+pub mod peer_key;
 #[cfg(feature = "port_mapping")]
-mod port_mapping;
+pub mod port_mapping;
 mod proto;
 pub mod protocol;
+pub mod reflector;
 mod tun_tap_device;
 pub use tun_tap_device::*;
 pub mod util;
+pub mod wireguard;
 
 pub use handle::callback::*;
 
@@ -1,7 +1,7 @@
 use anyhow::{anyhow, Context};
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
-use std::net::{SocketAddr, UdpSocket};
+use std::net::{SocketAddr, SocketAddrV4, UdpSocket};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -10,9 +10,9 @@ use parking_lot::Mutex;
 use rand::prelude::SliceRandom;
 use rand::Rng;
 
-use crate::channel::punch::{NatInfo, NatType};
+use crate::channel::punch::{Candidate, CandidateType, NatBehavior, NatInfo, NatType};
 use crate::channel::socket::LocalInterface;
-use crate::proto::message::PunchNatType;
+use crate::proto::message::{PunchCandidate, PunchCandidateType, PunchNatBehavior, PunchNatType};
 #[cfg(feature = "upnp")]
 use crate::util::UPnP;
 
@@ -138,6 +138,72 @@ impl Into<NatType> for PunchNatType {
     }
 }
 
+impl From<NatBehavior> for PunchNatBehavior {
+    fn from(value: NatBehavior) -> Self {
+        match value {
+            NatBehavior::Unknown => PunchNatBehavior::Unknown,
+            NatBehavior::EndpointIndependent => PunchNatBehavior::EndpointIndependent,
+            NatBehavior::AddressDependent => PunchNatBehavior::AddressDependent,
+            NatBehavior::AddressAndPortDependent => PunchNatBehavior::AddressAndPortDependent,
+        }
+    }
+}
+
+impl Into<NatBehavior> for PunchNatBehavior {
+    fn into(self) -> NatBehavior {
+        match self {
+            PunchNatBehavior::Unknown => NatBehavior::Unknown,
+            PunchNatBehavior::EndpointIndependent => NatBehavior::EndpointIndependent,
+            PunchNatBehavior::AddressDependent => NatBehavior::AddressDependent,
+            PunchNatBehavior::AddressAndPortDependent => NatBehavior::AddressAndPortDependent,
+        }
+    }
+}
+
+impl From<&Candidate> for PunchCandidate {
+    fn from(value: &Candidate) -> Self {
+        let mut candidate = PunchCandidate::new();
+        candidate.kind = protobuf::EnumOrUnknown::new(match value.kind {
+            CandidateType::Host => PunchCandidateType::Host,
+            CandidateType::Mapped => PunchCandidateType::Mapped,
+            CandidateType::ServerReflexive => PunchCandidateType::ServerReflexive,
+        });
+        candidate.ip = match value.addr.ip() {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        candidate.port = value.addr.port() as u32;
+        candidate.priority = value.priority;
+        candidate
+    }
+}
+
+impl TryFrom<&PunchCandidate> for Candidate {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &PunchCandidate) -> Result<Self, Self::Error> {
+        let kind = match value.kind.enum_value() {
+            Ok(PunchCandidateType::Host) => CandidateType::Host,
+            Ok(PunchCandidateType::Mapped) => CandidateType::Mapped,
+            Ok(PunchCandidateType::ServerReflexive) => CandidateType::ServerReflexive,
+            Err(e) => Err(anyhow!("candidate kind {}", e))?,
+        };
+        let ip = match value.ip.len() {
+            4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(&value.ip[..])?)),
+            16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&value.ip[..])?)),
+            len => Err(anyhow!("candidate ip len {}", len))?,
+        };
+        if value.port == 0 || value.port > u16::MAX as u32 {
+            Err(anyhow!("candidate port {}", value.port))?
+        }
+        Ok(Candidate {
+            kind,
+            addr: SocketAddr::new(ip, value.port as u16),
+            priority: value.priority,
+        })
+    }
+}
+
 impl NatTest {
     pub fn new(
         _channel_num: usize,
@@ -191,7 +257,55 @@ impl NatTest {
     }
 
     pub fn nat_info(&self) -> NatInfo {
-        self.info.lock().clone()
+        #[allow(unused_mut)]
+        let mut nat_info = self.info.lock().clone();
+        #[cfg(feature = "upnp")]
+        self.apply_mapped(&mut nat_info);
+        nat_info
+    }
+    /// 路由器映射的外网地址，映射的是主udp端口
+    pub fn mapped_addr(&self) -> Option<SocketAddrV4> {
+        #[cfg(feature = "upnp")]
+        if let Some(port) = self.udp_ports.first() {
+            return self
+                .upnp
+                .mapped_udp(*port)
+                .filter(|addr| is_ipv4_global(addr.ip()));
+        }
+        None
+    }
+    /// 端口映射成功时，对端直接使用映射的地址即可连通，当作锥形网络处理
+    #[cfg(feature = "upnp")]
+    fn apply_mapped(&self, nat_info: &mut NatInfo) {
+        let mut mapped = false;
+        for (index, port) in self.udp_ports.iter().enumerate() {
+            let addr = match self.upnp.mapped_udp(*port) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            if !is_ipv4_global(addr.ip()) {
+                continue;
+            }
+            if !nat_info.public_ips.contains(addr.ip()) {
+                nat_info.public_ips.insert(0, *addr.ip());
+            }
+            if let Some(public_port) = nat_info.public_ports.get_mut(index) {
+                *public_port = addr.port();
+            }
+            let local_preference = u16::MAX - 1 - index as u16;
+            nat_info.candidates.push(Candidate::new(
+                CandidateType::Mapped,
+                SocketAddr::V4(addr),
+                local_preference,
+            ));
+            mapped |= index == 0;
+        }
+        if mapped {
+            // 映射的端口固定且允许任意来源
+            nat_info.nat_type = NatType::Cone;
+            nat_info.mapping = NatBehavior::EndpointIndependent;
+            nat_info.filtering = NatBehavior::EndpointIndependent;
+        }
     }
     pub fn is_local_udp(&self, ipv4: Ipv4Addr, port: u16) -> bool {
         for x in &self.udp_ports {
@@ -269,19 +383,27 @@ impl NatTest {
             stun_server.truncate(5);
             log::info!("stun_server truncate {:?}", stun_server);
         }
-        let (nat_type, public_ips, port_range) =
-            stun::stun_test_nat(stun_server, default_interface)?;
+        // 只有ipv6的网络stun测试会失败，ipv6地址要先更新
+        self.info.lock().ipv6 = ipv6;
+        let (mut nat_type, public_ips, port_range) =
+            stun::stun_test_nat(stun_server.clone(), default_interface)?;
         if public_ips.is_empty() {
             Err(anyhow!("public_ips.is_empty"))?
         }
+        let (mapping, filtering) = stun::stun_test_behavior(stun_server, default_interface)?;
+        if mapping != NatBehavior::Unknown && mapping != NatBehavior::EndpointIndependent {
+            // 多个服务器看到的地址可能恰好相同，以映射行为为准
+            nat_type = NatType::Symmetric;
+        }
         let mut guard = self.info.lock();
         guard.nat_type = nat_type;
+        guard.mapping = mapping;
+        guard.filtering = filtering;
         guard.public_ips = public_ips;
         guard.public_port_range = port_range;
         if local_ipv4.is_some() {
             guard.local_ipv4 = local_ipv4;
         }
-        guard.ipv6 = ipv6;
 
         Ok(guard.clone())
     }
@@ -1,9 +1,9 @@
 use std::collections::HashSet;
 use std::io;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
 use std::time::Duration;
 
-use crate::channel::punch::NatType;
+use crate::channel::punch::{NatBehavior, NatType};
 use crate::channel::socket::{bind_udp, LocalInterface};
 use rand::RngCore;
 use std::net::UdpSocket;
@@ -176,6 +176,153 @@ fn test_nat_(
     Err(io::Error::new(io::ErrorKind::Other, "stun response err"))
 }
 
+/// 按rfc5780探测nat的映射行为和过滤行为，返回(映射,过滤)
+/// 需要stun服务器支持变更地址，依次尝试直到有服务器返回变更地址
+pub fn stun_test_behavior(
+    stun_servers: Vec<String>,
+    default_interface: &LocalInterface,
+) -> anyhow::Result<(NatBehavior, NatBehavior)> {
+    for stun_server in &stun_servers {
+        let server = match stun_server
+            .to_socket_addrs()
+            .map(|mut v| v.find(|addr| addr.is_ipv4()))
+        {
+            Ok(Some(addr)) => addr,
+            _ => continue,
+        };
+        // 每个服务器使用新的端口，避免之前的请求在nat上留下的映射和过滤规则影响结果
+        let udp = bind_udp("0.0.0.0:0".parse().unwrap(), default_interface)?;
+        udp.set_nonblocking(false)?;
+        let udp: UdpSocket = udp.into();
+        udp.set_read_timeout(Some(Duration::from_millis(500)))?;
+        match test_behavior(&udp, server) {
+            Ok(Some(rs)) => {
+                log::info!(
+                    "stun {} nat行为 映射:{:?} 过滤:{:?}",
+                    stun_server,
+                    rs.0,
+                    rs.1
+                );
+                return Ok(rs);
+            }
+            Ok(None) => {
+                log::info!("stun {} 不支持变更地址", stun_server);
+            }
+            Err(e) => {
+                log::warn!("stun {} error {:?} ", stun_server, e);
+            }
+        }
+    }
+    Ok((NatBehavior::Unknown, NatBehavior::Unknown))
+}
+
+fn test_behavior(
+    udp: &UdpSocket,
+    server: SocketAddr,
+) -> io::Result<Option<(NatBehavior, NatBehavior)>> {
+    // test I
+    let (mapped1, other) = match binding(udp, server, false, false)? {
+        (Some(mapped), Some(other)) => (mapped, other),
+        _ => return Ok(None),
+    };
+    if other.ip() == server.ip() || other.port() == server.port() {
+        return Ok(None);
+    }
+    // 过滤行为要先测，映射测试会向其他地址发包，打开nat的过滤规则
+    let filtering = if binding(udp, server, true, true)?.0.is_some() {
+        NatBehavior::EndpointIndependent
+    } else if binding(udp, server, false, true)?.0.is_some() {
+        NatBehavior::AddressDependent
+    } else {
+        NatBehavior::AddressAndPortDependent
+    };
+    // test II，变更ip，端口不变
+    let mapped2 = match binding(
+        udp,
+        SocketAddr::new(other.ip(), server.port()),
+        false,
+        false,
+    )?
+    .0
+    {
+        Some(mapped) => mapped,
+        None => return Ok(Some((NatBehavior::Unknown, filtering))),
+    };
+    let mapping = if mapped1 == mapped2 {
+        NatBehavior::EndpointIndependent
+    } else {
+        // test III，ip和端口都变更
+        match binding(udp, other, false, false)?.0 {
+            Some(mapped3) if mapped3 == mapped2 => NatBehavior::AddressDependent,
+            Some(_) => NatBehavior::AddressAndPortDependent,
+            None => NatBehavior::Unknown,
+        }
+    };
+    Ok(Some((mapping, filtering)))
+}
+
+/// 发送绑定请求，返回(映射地址,变更地址)，超时返回None
+/// 使用未connect的socket，变更地址后的响应来自其他地址
+fn binding(
+    udp: &UdpSocket,
+    server: SocketAddr,
+    change_ip: bool,
+    change_port: bool,
+) -> io::Result<(Option<SocketAddr>, Option<SocketAddr>)> {
+    let tid = rand::thread_rng().next_u64() as u128;
+    let mut buf = [0u8; 28];
+    let mut msg = stun_format::MsgBuilder::from(buf.as_mut_slice());
+    msg.typ(stun_format::MsgType::BindingRequest);
+    msg.tid(tid);
+    msg.add_attr(Attr::ChangeRequest {
+        change_ip,
+        change_port,
+    });
+    let request = msg.as_bytes().to_vec();
+    for _ in 0..2 {
+        udp.send_to(&request, server)?;
+        let mut buf = [0; 10240];
+        loop {
+            let len = match udp.recv_from(&mut buf) {
+                Ok((len, _)) => len,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            let msg = stun_format::Msg::from(&buf[..len]);
+            if msg.tid() != Some(tid) {
+                // 之前请求的迟到响应
+                continue;
+            }
+            let mut mapped_addr = None;
+            let mut changed_addr = None;
+            for x in msg.attrs_iter() {
+                match x {
+                    Attr::MappedAddress(addr) | Attr::XorMappedAddress(addr) => {
+                        if mapped_addr.is_none() {
+                            mapped_addr = Some(stun_addr(addr));
+                        }
+                    }
+                    Attr::ChangedAddress(addr) => {
+                        if changed_addr.is_none() {
+                            changed_addr = Some(stun_addr(addr));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if mapped_addr.is_some() {
+                return Ok((mapped_addr, changed_addr));
+            }
+        }
+    }
+    Ok((None, None))
+}
+
 fn stun_addr(addr: stun_format::SocketAddr) -> SocketAddr {
     match addr {
         stun_format::SocketAddr::V4(ip, port) => {
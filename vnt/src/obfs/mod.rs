@@ -0,0 +1,143 @@
+use std::ops::Range;
+use std::str::FromStr;
+
+use sha2::Digest;
+
+use crate::obfs::salt::SaltObfs;
+use crate::obfs::tls::TlsObfs;
+use crate::obfs::xor::XorObfs;
+
+mod salt;
+mod tls;
+mod xor;
+
+/// udp数据混淆，用于避免被按报文特征识别/限速，不提供安全性(加密由cipher负责)
+/// 混淆需要通信双方(服务端和其他客户端)使用相同的配置
+pub trait Obfuscator {
+    /// 混淆数据写入out
+    fn encode(&self, buf: &[u8], out: &mut Vec<u8>);
+    /// 原地还原，返回还原后的数据在buf中的范围
+    fn decode(&self, buf: &mut [u8]) -> anyhow::Result<Range<usize>>;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ObfsModel {
+    None,
+    /// 固定密钥异或，没有额外开销
+    Xor,
+    /// 每个包带4字节随机盐，相同数据每次混淆结果都不同
+    Salt,
+    /// 在Salt的基础上增加TLS应用数据记录头
+    Tls,
+}
+
+impl FromStr for ObfsModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim() {
+            "none" => Ok(ObfsModel::None),
+            "xor" => Ok(ObfsModel::Xor),
+            "salt" => Ok(ObfsModel::Salt),
+            "tls" => Ok(ObfsModel::Tls),
+            _ => Err(format!("not match '{}', enum: none/xor/salt/tls", s)),
+        }
+    }
+}
+
+impl Default for ObfsModel {
+    fn default() -> Self {
+        ObfsModel::None
+    }
+}
+
+#[derive(Clone)]
+pub enum Obfs {
+    None,
+    Xor(XorObfs),
+    Salt(SaltObfs),
+    Tls(TlsObfs),
+}
+
+impl Obfs {
+    /// 密钥由token生成，同一组网内的设备一致
+    pub fn new(model: ObfsModel, token: &str) -> Self {
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(b"vnt-obfs");
+        hasher.update(token.as_bytes());
+        let key: [u8; 32] = hasher.finalize().into();
+        match model {
+            ObfsModel::None => Obfs::None,
+            ObfsModel::Xor => Obfs::Xor(XorObfs::new(key)),
+            ObfsModel::Salt => Obfs::Salt(SaltObfs::new(key)),
+            ObfsModel::Tls => Obfs::Tls(TlsObfs::new(key)),
+        }
+    }
+    #[inline]
+    pub fn is_none(&self) -> bool {
+        matches!(self, Obfs::None)
+    }
+}
+
+impl Obfuscator for Obfs {
+    fn encode(&self, buf: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Obfs::None => out.extend_from_slice(buf),
+            Obfs::Xor(obfs) => obfs.encode(buf, out),
+            Obfs::Salt(obfs) => obfs.encode(buf, out),
+            Obfs::Tls(obfs) => obfs.encode(buf, out),
+        }
+    }
+
+    fn decode(&self, buf: &mut [u8]) -> anyhow::Result<Range<usize>> {
+        match self {
+            Obfs::None => Ok(0..buf.len()),
+            Obfs::Xor(obfs) => obfs.decode(buf),
+            Obfs::Salt(obfs) => obfs.decode(buf),
+            Obfs::Tls(obfs) => obfs.decode(buf),
+        }
+    }
+}
+
+/// xorshift64*生成的密钥流，只用于混淆
+struct KeyStream(u64);
+
+impl KeyStream {
+    fn new(seed: u64) -> Self {
+        // 种子不能为0
+        KeyStream(if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        })
+    }
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+    fn apply(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let k = self.next().to_le_bytes();
+            for (v, k) in chunk.iter_mut().zip(k) {
+                *v ^= k;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_obfs() {
+    let data: Vec<u8> = (0..200u8).collect();
+    for model in [ObfsModel::Xor, ObfsModel::Salt, ObfsModel::Tls] {
+        let obfs = Obfs::new(model, "test");
+        let mut out = Vec::new();
+        obfs.encode(&data, &mut out);
+        assert_ne!(&out[..], &data[..]);
+        let range = obfs.decode(&mut out).unwrap();
+        assert_eq!(&out[range], &data[..]);
+    }
+}
@@ -0,0 +1,44 @@
+use std::ops::Range;
+
+use anyhow::anyhow;
+
+use crate::obfs::{KeyStream, Obfuscator};
+
+const SALT_LEN: usize = 4;
+
+#[derive(Clone)]
+pub struct SaltObfs {
+    seed: u64,
+}
+
+impl SaltObfs {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            seed: u64::from_le_bytes(key[..8].try_into().unwrap()),
+        }
+    }
+    fn key_stream(&self, salt: &[u8]) -> KeyStream {
+        let salt = u32::from_be_bytes(salt.try_into().unwrap()) as u64;
+        KeyStream::new(self.seed ^ salt.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+}
+
+impl Obfuscator for SaltObfs {
+    /// 4字节盐 + 混淆数据
+    fn encode(&self, buf: &[u8], out: &mut Vec<u8>) {
+        let salt: [u8; SALT_LEN] = rand::random();
+        let start = out.len();
+        out.extend_from_slice(&salt);
+        out.extend_from_slice(buf);
+        self.key_stream(&salt).apply(&mut out[start + SALT_LEN..]);
+    }
+
+    fn decode(&self, buf: &mut [u8]) -> anyhow::Result<Range<usize>> {
+        if buf.len() <= SALT_LEN {
+            return Err(anyhow!("obfs data too short"));
+        }
+        let (salt, data) = buf.split_at_mut(SALT_LEN);
+        self.key_stream(salt).apply(data);
+        Ok(SALT_LEN..buf.len())
+    }
+}
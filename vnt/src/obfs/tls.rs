@@ -0,0 +1,44 @@
+use std::ops::Range;
+
+use anyhow::anyhow;
+
+use crate::obfs::salt::SaltObfs;
+use crate::obfs::Obfuscator;
+
+/// TLS1.2/1.3 应用数据记录头 content_type(0x17) version(0x0303) length
+const HEAD_LEN: usize = 5;
+
+#[derive(Clone)]
+pub struct TlsObfs {
+    salt: SaltObfs,
+}
+
+impl TlsObfs {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            salt: SaltObfs::new(key),
+        }
+    }
+}
+
+impl Obfuscator for TlsObfs {
+    fn encode(&self, buf: &[u8], out: &mut Vec<u8>) {
+        let start = out.len();
+        out.extend_from_slice(&[0x17, 0x03, 0x03, 0, 0]);
+        self.salt.encode(buf, out);
+        let len = (out.len() - start - HEAD_LEN) as u16;
+        out[start + 3..start + HEAD_LEN].copy_from_slice(&len.to_be_bytes());
+    }
+
+    fn decode(&self, buf: &mut [u8]) -> anyhow::Result<Range<usize>> {
+        if buf.len() <= HEAD_LEN || buf[..3] != [0x17, 0x03, 0x03] {
+            return Err(anyhow!("obfs tls head error"));
+        }
+        let len = u16::from_be_bytes([buf[3], buf[4]]) as usize;
+        if len != buf.len() - HEAD_LEN {
+            return Err(anyhow!("obfs tls length error"));
+        }
+        let range = self.salt.decode(&mut buf[HEAD_LEN..])?;
+        Ok(range.start + HEAD_LEN..range.end + HEAD_LEN)
+    }
+}
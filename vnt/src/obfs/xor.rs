@@ -0,0 +1,27 @@
+use std::ops::Range;
+
+use crate::obfs::Obfuscator;
+
+#[derive(Clone)]
+pub struct XorObfs {
+    key: [u8; 32],
+}
+
+impl XorObfs {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+}
+
+impl Obfuscator for XorObfs {
+    fn encode(&self, buf: &[u8], out: &mut Vec<u8>) {
+        out.extend(buf.iter().enumerate().map(|(i, v)| v ^ self.key[i & 31]));
+    }
+
+    fn decode(&self, buf: &mut [u8]) -> anyhow::Result<Range<usize>> {
+        for (i, v) in buf.iter_mut().enumerate() {
+            *v ^= self.key[i & 31];
+        }
+        Ok(0..buf.len())
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use aes_gcm::Aes256Gcm;
+use anyhow::anyhow;
+use chacha20poly1305::aead::{Nonce, Tag};
+use chacha20poly1305::{AeadInPlace, ChaCha20Poly1305, Key, KeyInit};
+use parking_lot::Mutex;
+
+use crate::cipher::ReplayFilter;
+use crate::protocol::NetPacket;
+
+// 计数(4) | tag(16)，和组网密码的chacha20_poly1305长度一致
+const COUNTER_LEN: usize = 4;
+const TAG_LEN: usize = 16;
+pub const SESSION_RESERVED: usize = COUNTER_LEN + TAG_LEN;
+// 计数用完之前必须换密钥
+const REJECT_AFTER_MESSAGES: u64 = u32::MAX as u64;
+
+fn nonce(counter: u32) -> Nonce<ChaCha20Poly1305> {
+    let mut nonce = [0u8; 12];
+    nonce[8..].copy_from_slice(&counter.to_be_bytes());
+    nonce.into()
+}
+
+/// 会话密钥使用的加密算法，握手时协商，双方都有aes硬件加速才使用aes
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Suite {
+    ChaCha20Poly1305,
+    AesGcm,
+}
+
+impl Suite {
+    pub fn select(local_aes: bool, peer_aes: bool) -> Self {
+        if local_aes && peer_aes {
+            Suite::AesGcm
+        } else {
+            Suite::ChaCha20Poly1305
+        }
+    }
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Suite::ChaCha20Poly1305),
+            1 => Some(Suite::AesGcm),
+            _ => None,
+        }
+    }
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Suite::ChaCha20Poly1305 => 0,
+            Suite::AesGcm => 1,
+        }
+    }
+}
+
+// 两种算法的nonce和tag长度相同，数据格式不变
+enum SessionAead {
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    AesGcm(Aes256Gcm),
+}
+
+impl SessionAead {
+    fn new(suite: Suite, key: &[u8; 32]) -> Self {
+        match suite {
+            Suite::ChaCha20Poly1305 => {
+                SessionAead::ChaCha20Poly1305(ChaCha20Poly1305::new(Key::from_slice(key)))
+            }
+            Suite::AesGcm => SessionAead::AesGcm(Aes256Gcm::new(&(*key).into())),
+        }
+    }
+    fn encrypt(
+        &self,
+        counter: u32,
+        aad: &[u8],
+        data: &mut [u8],
+    ) -> anyhow::Result<Tag<ChaCha20Poly1305>> {
+        let nonce = nonce(counter);
+        match self {
+            SessionAead::ChaCha20Poly1305(cipher) => {
+                cipher.encrypt_in_place_detached(&nonce, aad, data)
+            }
+            SessionAead::AesGcm(cipher) => cipher.encrypt_in_place_detached(&nonce, aad, data),
+        }
+        .map_err(|e| anyhow!("session encrypt {:?}", e))
+    }
+    fn decrypt(
+        &self,
+        counter: u32,
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &Tag<ChaCha20Poly1305>,
+    ) -> anyhow::Result<()> {
+        let nonce = nonce(counter);
+        match self {
+            SessionAead::ChaCha20Poly1305(cipher) => {
+                cipher.decrypt_in_place_detached(&nonce, aad, data, tag)
+            }
+            SessionAead::AesGcm(cipher) => cipher.decrypt_in_place_detached(&nonce, aad, data, tag),
+        }
+        .map_err(|e| anyhow!("session decrypt {:?}", e))
+    }
+}
+
+/// 发送方向的会话密钥，nonce使用递增计数，同一个密钥下不会重复
+pub struct SendKey {
+    cipher: SessionAead,
+    counter: AtomicU64,
+    bytes: AtomicU64,
+    pub time: Instant,
+}
+
+impl SendKey {
+    pub fn new(suite: Suite, key: [u8; 32]) -> Self {
+        Self {
+            cipher: SessionAead::new(suite, &key),
+            counter: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            time: Instant::now(),
+        }
+    }
+    /// 已加密的字节数
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+    /// 载荷 | 计数 | tag，头部作为附加数据参与认证
+    pub fn encrypt<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        net_packet: &mut NetPacket<B>,
+    ) -> anyhow::Result<()> {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed);
+        if counter >= REJECT_AFTER_MESSAGES {
+            return Err(anyhow!("session key exhausted"));
+        }
+        let counter = counter as u32;
+        let head_tag = net_packet.head_tag();
+        let data_len = net_packet.data_len();
+        net_packet.set_data_len(data_len + SESSION_RESERVED)?;
+        let payload = net_packet.payload_mut();
+        let payload_len = payload.len() - SESSION_RESERVED;
+        let (data, tail) = payload.split_at_mut(payload_len);
+        let tag = self.cipher.encrypt(counter, &head_tag, data)?;
+        tail[..COUNTER_LEN].copy_from_slice(&counter.to_be_bytes());
+        tail[COUNTER_LEN..].copy_from_slice(&tag);
+        self.bytes.fetch_add(payload_len as u64, Ordering::Relaxed);
+        net_packet.set_encrypt_flag(true);
+        Ok(())
+    }
+}
+
+/// 接收方向的会话密钥
+pub struct RecvKey {
+    cipher: SessionAead,
+    replay: Mutex<ReplayFilter>,
+}
+
+impl RecvKey {
+    pub fn new(suite: Suite, key: [u8; 32]) -> Self {
+        Self {
+            cipher: SessionAead::new(suite, &key),
+            replay: Mutex::new(ReplayFilter::new()),
+        }
+    }
+    /// 解密成功后检查计数，重复或太旧的计数返回false
+    pub fn check_replay(&self, counter: u32) -> bool {
+        self.replay.lock().check(counter as u64)
+    }
+    /// 校验失败时数据不会被修改，可以再用其他密钥尝试，成功时返回计数
+    pub fn decrypt<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        net_packet: &mut NetPacket<B>,
+    ) -> anyhow::Result<u32> {
+        let head_tag = net_packet.head_tag();
+        let payload = net_packet.payload_mut();
+        if payload.len() < SESSION_RESERVED {
+            return Err(anyhow!("session data len {}", payload.len()));
+        }
+        let payload_len = payload.len() - SESSION_RESERVED;
+        let (data, tail) = payload.split_at_mut(payload_len);
+        let counter = u32::from_be_bytes(tail[..COUNTER_LEN].try_into().unwrap());
+        let tag = Tag::<ChaCha20Poly1305>::from_slice(&tail[COUNTER_LEN..]);
+        self.cipher.decrypt(counter, &head_tag, data, tag)?;
+        net_packet.set_encrypt_flag(false);
+        net_packet.set_payload_len(payload_len)?;
+        Ok(counter)
+    }
+}
+
+#[test]
+fn test_session_cipher() {
+    for suite in [Suite::ChaCha20Poly1305, Suite::AesGcm] {
+        let send = SendKey::new(suite, [3; 32]);
+        let recv = RecvKey::new(suite, [3; 32]);
+        let other = RecvKey::new(suite, [4; 32]);
+        let mut buf = [7u8; 12 + 20 + SESSION_RESERVED];
+        let mut p = NetPacket::new0(12 + 20, &mut buf[..]).unwrap();
+        let src = p.buffer().to_vec();
+        send.encrypt(&mut p).unwrap();
+        let encrypted = p.buffer().to_vec();
+        // 密钥不对时数据不变
+        assert!(other.decrypt(&mut p).is_err());
+        assert_eq!(p.buffer(), &encrypted[..]);
+        assert_eq!(recv.decrypt(&mut p).unwrap(), 0);
+        assert_eq!(p.buffer(), &src[..]);
+        assert!(recv.check_replay(0));
+        send.encrypt(&mut p).unwrap();
+        let encrypted = p.buffer().to_vec();
+        assert_eq!(recv.decrypt(&mut p).unwrap(), 1);
+        assert!(recv.check_replay(1));
+        assert_eq!(send.bytes(), 40);
+        // 重放的数据能解密，但计数已经出现过
+        p.set_data_len(encrypted.len()).unwrap();
+        p.buffer_mut().copy_from_slice(&encrypted);
+        assert_eq!(recv.decrypt(&mut p).unwrap(), 1);
+        assert!(!recv.check_replay(1));
+    }
+    // 密钥相同，算法不同也无法解密
+    let send = SendKey::new(Suite::AesGcm, [3; 32]);
+    let recv = RecvKey::new(Suite::ChaCha20Poly1305, [3; 32]);
+    let mut buf = [7u8; 12 + 20 + SESSION_RESERVED];
+    let mut p = NetPacket::new0(12 + 20, &mut buf[..]).unwrap();
+    send.encrypt(&mut p).unwrap();
+    assert!(recv.decrypt(&mut p).is_err());
+}
@@ -0,0 +1,342 @@
+use std::net::Ipv4Addr;
+
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::cipher::noise::{dh, hash, kdf, new_ephemeral, open, seal};
+#[cfg(feature = "pq")]
+use crate::cipher::{kem_encapsulate, KemKey, KEM_CIPHERTEXT_LEN, KEM_PUBLIC_KEY_LEN};
+use crate::peer_key::cipher::Suite;
+
+const PROTOCOL_NAME: &[u8] = b"Noise_XX_25519_ChaChaPoly_BLAKE2s";
+const PROLOGUE: &[u8] = b"vnt-peer-key";
+
+pub const MSG_INIT: u8 = 1;
+pub const MSG_RESPONSE: u8 = 2;
+pub const MSG_FINISH: u8 = 3;
+pub const MSG_CONFIRM: u8 = 4;
+// -> e，混合模式时后面跟着ML-KEM公钥
+const INIT_LEN: usize = 32;
+// <- e, ee, s, es，载荷是响应方是否有aes硬件加速，混合模式时ee后面跟着ML-KEM密文
+const RESPONSE_LEN: usize = 32 + 48 + 1 + 16;
+// -> s, se，载荷是发起方选定的加密算法
+const FINISH_LEN: usize = 48 + 1 + 16;
+
+/// 本端的静态密钥
+pub struct LocalKey {
+    secret: StaticSecret,
+    pub public: [u8; 32],
+}
+
+impl LocalKey {
+    pub fn new(private_key: &[u8; 32]) -> Self {
+        let secret = StaticSecret::from(*private_key);
+        let public = PublicKey::from(&secret).to_bytes();
+        Self { secret, public }
+    }
+}
+
+/// 握手完成后的密钥，confirm只用于响应方的确认消息
+pub struct SessionKeys {
+    pub send: [u8; 32],
+    pub recv: [u8; 32],
+    pub confirm: [u8; 32],
+    pub suite: Suite,
+    // 混入了ML-KEM的共享密钥
+    pub hybrid: bool,
+}
+
+/// 发起方等待第二个消息
+pub struct Initiator {
+    ephemeral: StaticSecret,
+    ck: [u8; 32],
+    h: [u8; 32],
+    #[cfg(feature = "pq")]
+    kem: Option<KemKey>,
+}
+
+/// 响应方等待第三个消息
+pub struct Responder {
+    ephemeral: StaticSecret,
+    ck: [u8; 32],
+    h: [u8; 32],
+    k: [u8; 32],
+    pub hybrid: bool,
+}
+
+/// 把双方的虚拟ip绑定到握手中，防止握手消息被转给其他客户端
+fn initialize(initiator: Ipv4Addr, responder: Ipv4Addr) -> ([u8; 32], [u8; 32]) {
+    let ck = hash(&[PROTOCOL_NAME]);
+    let h = hash(&[&ck, PROLOGUE, &initiator.octets(), &responder.octets()]);
+    (ck, h)
+}
+
+/// hybrid为true时附带临时的ML-KEM公钥，响应方封装的共享密钥会混入会话密钥
+#[cfg_attr(not(feature = "pq"), allow(unused_variables))]
+pub fn create_init(local_ip: Ipv4Addr, peer_ip: Ipv4Addr, hybrid: bool) -> (Vec<u8>, Initiator) {
+    let (ck, h) = initialize(local_ip, peer_ip);
+    let ephemeral = new_ephemeral();
+    let e = PublicKey::from(&ephemeral).to_bytes();
+    let h = hash(&[&h, &e]);
+    #[allow(unused_mut)]
+    let mut buf = e.to_vec();
+    #[cfg(feature = "pq")]
+    let (h, kem) = if hybrid {
+        let kem = KemKey::generate();
+        buf.extend_from_slice(kem.public_key());
+        (hash(&[&h, kem.public_key()]), Some(kem))
+    } else {
+        (h, None)
+    };
+    (
+        buf,
+        Initiator {
+            ephemeral,
+            ck,
+            h,
+            #[cfg(feature = "pq")]
+            kem,
+        },
+    )
+}
+
+/// hybrid为true时接受带ML-KEM公钥的第一个消息
+#[cfg_attr(not(feature = "pq"), allow(unused_variables))]
+pub fn create_response(
+    local: &LocalKey,
+    local_ip: Ipv4Addr,
+    peer_ip: Ipv4Addr,
+    msg: &[u8],
+    aes_hardware: bool,
+    hybrid: bool,
+) -> Option<(Vec<u8>, Responder)> {
+    #[cfg(feature = "pq")]
+    let kem_public_key = if hybrid && msg.len() == INIT_LEN + KEM_PUBLIC_KEY_LEN {
+        Some(&msg[INIT_LEN..])
+    } else {
+        None
+    };
+    #[cfg(not(feature = "pq"))]
+    let kem_public_key: Option<&[u8]> = None;
+    if msg.len() != INIT_LEN && kem_public_key.is_none() {
+        return None;
+    }
+    let remote_e: [u8; 32] = msg[..INIT_LEN].try_into().unwrap();
+    let (ck, h) = initialize(peer_ip, local_ip);
+    let h = hash(&[&h, &remote_e]);
+    let h = match kem_public_key {
+        Some(kem_public_key) => hash(&[&h, kem_public_key]),
+        None => h,
+    };
+    let ephemeral = new_ephemeral();
+    let e = PublicKey::from(&ephemeral).to_bytes();
+    let h = hash(&[&h, &e]);
+    let [ck, k] = kdf(&ck, &dh(&ephemeral, &remote_e));
+    let mut buf = Vec::with_capacity(RESPONSE_LEN);
+    buf.extend_from_slice(&e);
+    // ekem，密文明文发送，共享密钥混入链密钥，之后的静态公钥也受保护
+    #[cfg(feature = "pq")]
+    let (ck, k, h) = match kem_public_key {
+        Some(kem_public_key) => {
+            let (ciphertext, shared) = kem_encapsulate(kem_public_key).ok()?;
+            buf.extend_from_slice(&ciphertext);
+            let h = hash(&[&h, &ciphertext]);
+            let [ck, k] = kdf(&ck, &shared);
+            (ck, k, h)
+        }
+        None => (ck, k, h),
+    };
+    let encrypted_static = seal(&k, 0, &local.public, &h);
+    let h = hash(&[&h, &encrypted_static]);
+    let [ck, k] = kdf(&ck, &dh(&local.secret, &remote_e));
+    let payload = seal(&k, 0, &[aes_hardware as u8], &h);
+    let h = hash(&[&h, &payload]);
+    buf.extend_from_slice(&encrypted_static);
+    buf.extend_from_slice(&payload);
+    Some((
+        buf,
+        Responder {
+            ephemeral,
+            ck,
+            h,
+            k,
+            hybrid: kem_public_key.is_some(),
+        },
+    ))
+}
+
+/// 发起方处理第二个消息，返回第三个消息、对端公钥和会话密钥
+pub fn consume_response(
+    local: &LocalKey,
+    state: &Initiator,
+    msg: &[u8],
+    aes_hardware: bool,
+) -> Option<(Vec<u8>, [u8; 32], SessionKeys)> {
+    #[cfg(feature = "pq")]
+    let kem_len = if state.kem.is_some() {
+        KEM_CIPHERTEXT_LEN
+    } else {
+        0
+    };
+    #[cfg(not(feature = "pq"))]
+    let kem_len = 0;
+    if msg.len() != RESPONSE_LEN + kem_len {
+        return None;
+    }
+    let remote_e: [u8; 32] = msg[..32].try_into().unwrap();
+    let h = hash(&[&state.h, &remote_e]);
+    let [ck, k] = kdf(&state.ck, &dh(&state.ephemeral, &remote_e));
+    #[cfg(feature = "pq")]
+    let (ck, k, h) = match &state.kem {
+        Some(kem) => {
+            let ciphertext = &msg[32..32 + kem_len];
+            let shared = kem.decapsulate(ciphertext).ok()?;
+            let h = hash(&[&h, ciphertext]);
+            let [ck, k] = kdf(&ck, &shared);
+            (ck, k, h)
+        }
+        None => (ck, k, h),
+    };
+    let msg = &msg[32 + kem_len..];
+    let remote_static: [u8; 32] = open(&k, 0, &msg[..48], &h)?.try_into().ok()?;
+    let h = hash(&[&h, &msg[..48]]);
+    let [ck, k] = kdf(&ck, &dh(&state.ephemeral, &remote_static));
+    let payload = open(&k, 0, &msg[48..], &h)?;
+    let suite = Suite::select(aes_hardware, payload[0] == 1);
+    let h = hash(&[&h, &msg[48..]]);
+    // 同一个密钥加密的第二个数据，nonce为1
+    let encrypted_static = seal(&k, 1, &local.public, &h);
+    let h = hash(&[&h, &encrypted_static]);
+    let [ck, k] = kdf(&ck, &dh(&local.secret, &remote_e));
+    let payload = seal(&k, 0, &[suite.as_u8()], &h);
+    let [send, recv, confirm] = kdf(&ck, &[]);
+    let mut buf = Vec::with_capacity(FINISH_LEN);
+    buf.extend_from_slice(&encrypted_static);
+    buf.extend_from_slice(&payload);
+    Some((
+        buf,
+        remote_static,
+        SessionKeys {
+            send,
+            recv,
+            confirm,
+            suite,
+            hybrid: kem_len != 0,
+        },
+    ))
+}
+
+/// 响应方处理第三个消息，返回对端公钥和会话密钥
+pub fn consume_finish(state: &Responder, msg: &[u8]) -> Option<([u8; 32], SessionKeys)> {
+    if msg.len() != FINISH_LEN {
+        return None;
+    }
+    let remote_static: [u8; 32] = open(&state.k, 1, &msg[..48], &state.h)?.try_into().ok()?;
+    let h = hash(&[&state.h, &msg[..48]]);
+    let [ck, k] = kdf(&state.ck, &dh(&state.ephemeral, &remote_static));
+    let suite = Suite::from_u8(open(&k, 0, &msg[48..], &h)?[0])?;
+    let [recv, send, confirm] = kdf(&ck, &[]);
+    Some((
+        remote_static,
+        SessionKeys {
+            send,
+            recv,
+            confirm,
+            suite,
+            hybrid: state.hybrid,
+        },
+    ))
+}
+
+pub fn create_confirm(confirm: &[u8; 32]) -> Vec<u8> {
+    seal(confirm, 0, &[], &[])
+}
+
+pub fn check_confirm(confirm: &[u8; 32], msg: &[u8]) -> bool {
+    open(confirm, 0, msg, &[]).is_some()
+}
+
+#[test]
+fn test_peer_handshake() {
+    let initiator_key = LocalKey::new(&rand::random());
+    let responder_key = LocalKey::new(&rand::random());
+    let initiator_ip = Ipv4Addr::new(10, 26, 0, 2);
+    let responder_ip = Ipv4Addr::new(10, 26, 0, 3);
+    let (msg, initiator) = create_init(initiator_ip, responder_ip, false);
+    // 虚拟ip不一致时握手失败
+    let (other, _) = create_response(
+        &responder_key,
+        Ipv4Addr::new(10, 26, 0, 4),
+        initiator_ip,
+        &msg,
+        true,
+        false,
+    )
+    .unwrap();
+    assert!(consume_response(&initiator_key, &initiator, &other, true).is_none());
+    let (msg, responder) = create_response(
+        &responder_key,
+        responder_ip,
+        initiator_ip,
+        &msg,
+        true,
+        false,
+    )
+    .unwrap();
+    let (msg, remote, initiator_keys) =
+        consume_response(&initiator_key, &initiator, &msg, true).unwrap();
+    assert_eq!(remote, responder_key.public);
+    let (remote, responder_keys) = consume_finish(&responder, &msg).unwrap();
+    assert_eq!(remote, initiator_key.public);
+    assert_eq!(initiator_keys.send, responder_keys.recv);
+    assert_eq!(initiator_keys.recv, responder_keys.send);
+    assert_eq!(initiator_keys.suite, Suite::AesGcm);
+    assert_eq!(responder_keys.suite, Suite::AesGcm);
+    let confirm = create_confirm(&responder_keys.confirm);
+    assert!(check_confirm(&initiator_keys.confirm, &confirm));
+    assert!(!check_confirm(&initiator_keys.send, &confirm));
+    // 有一方没有aes硬件加速时使用chacha20_poly1305
+    let (msg, initiator) = create_init(initiator_ip, responder_ip, false);
+    let (msg, responder) = create_response(
+        &responder_key,
+        responder_ip,
+        initiator_ip,
+        &msg,
+        false,
+        false,
+    )
+    .unwrap();
+    let (msg, _, initiator_keys) =
+        consume_response(&initiator_key, &initiator, &msg, true).unwrap();
+    let (_, responder_keys) = consume_finish(&responder, &msg).unwrap();
+    assert_eq!(initiator_keys.suite, Suite::ChaCha20Poly1305);
+    assert_eq!(responder_keys.suite, Suite::ChaCha20Poly1305);
+}
+
+#[cfg(feature = "pq")]
+#[test]
+fn test_peer_handshake_hybrid() {
+    let initiator_key = LocalKey::new(&rand::random());
+    let responder_key = LocalKey::new(&rand::random());
+    let initiator_ip = Ipv4Addr::new(10, 26, 0, 2);
+    let responder_ip = Ipv4Addr::new(10, 26, 0, 3);
+    let (msg, initiator) = create_init(initiator_ip, responder_ip, true);
+    // 响应方没有开启时不接受混合握手
+    assert!(create_response(
+        &responder_key,
+        responder_ip,
+        initiator_ip,
+        &msg,
+        true,
+        false
+    )
+    .is_none());
+    let (msg, responder) =
+        create_response(&responder_key, responder_ip, initiator_ip, &msg, true, true).unwrap();
+    assert!(responder.hybrid);
+    let (msg, _, initiator_keys) =
+        consume_response(&initiator_key, &initiator, &msg, true).unwrap();
+    let (_, responder_keys) = consume_finish(&responder, &msg).unwrap();
+    assert!(initiator_keys.hybrid && responder_keys.hybrid);
+    assert_eq!(initiator_keys.send, responder_keys.recv);
+    assert_eq!(initiator_keys.recv, responder_keys.send);
+}
@@ -0,0 +1,409 @@
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use fnv::FnvHashMap;
+use parking_lot::{Mutex, RwLock};
+
+use crate::cipher::aes_hardware;
+use crate::peer_key::cipher::{RecvKey, SendKey};
+use crate::peer_key::handshake::{
+    check_confirm, consume_finish, consume_response, create_confirm, create_init, create_response,
+    Initiator, LocalKey, Responder, SessionKeys, MSG_CONFIRM, MSG_FINISH, MSG_INIT, MSG_RESPONSE,
+};
+use crate::peer_key::PeerKeyConfig;
+use crate::protocol::NetPacket;
+use crate::wireguard::encode_key;
+
+// 握手超时，超时后重新发起
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// 会话确认后还接收组网密码加密的数据的时间，避免切换时丢包
+const PLAIN_GRACE: Duration = Duration::from_secs(10);
+// 密钥使用超过这个时间或加密超过这个数据量后重新握手换密钥
+const REKEY_AFTER_TIME: Duration = Duration::from_secs(120);
+const REKEY_AFTER_BYTES: u64 = 1 << 30;
+// 换密钥后旧密钥还能解密的时间，保证在途的数据不丢
+const REKEY_OVERLAP: Duration = Duration::from_secs(10);
+
+struct KeyPair {
+    send: SendKey,
+    recv: RecvKey,
+}
+
+impl KeyPair {
+    fn new(keys: &SessionKeys) -> Self {
+        Self {
+            send: SendKey::new(keys.suite, keys.send),
+            recv: RecvKey::new(keys.suite, keys.recv),
+        }
+    }
+}
+
+struct Session {
+    public_key: [u8; 32],
+    current: KeyPair,
+    // 双方都已持有当前密钥，之后发送的数据才能使用当前密钥加密
+    confirmed: AtomicBool,
+    // 换密钥前的密钥，当前密钥确认前继续用它发送，确认后一段时间内还用它解密
+    previous: Option<KeyPair>,
+    // 第一次协商成功的时间
+    established: Instant,
+}
+
+impl Session {
+    fn is_confirmed(&self) -> bool {
+        self.confirmed.load(Ordering::Relaxed)
+    }
+    fn previous_valid(&self) -> bool {
+        !self.is_confirmed() || self.current.send.time.elapsed() < REKEY_OVERLAP
+    }
+    fn need_rekey(&self) -> bool {
+        self.is_confirmed()
+            && (self.current.send.time.elapsed() >= REKEY_AFTER_TIME
+                || self.current.send.bytes() >= REKEY_AFTER_BYTES)
+    }
+}
+
+enum Handshake {
+    // 已发送第一个消息
+    Initiator(Initiator, Instant),
+    // 已发送第三个消息，等待确认，期间每次检查都重发
+    Finish(Vec<u8>, [u8; 32], Instant),
+    // 已回复第二个消息
+    Responder(Responder, Instant),
+    // 已回复确认，对端重发第三个消息时再回复一次
+    Confirmed(Vec<u8>, Vec<u8>, Instant),
+}
+
+impl Handshake {
+    fn time(&self) -> Instant {
+        match self {
+            Handshake::Initiator(_, time) => *time,
+            Handshake::Finish(_, _, time) => *time,
+            Handshake::Responder(_, time) => *time,
+            Handshake::Confirmed(_, _, time) => *time,
+        }
+    }
+}
+
+/// 和其他客户端协商的会话密钥
+pub struct PeerKeys {
+    local: LocalKey,
+    // 本机有aes硬件加速时，和同样有加速的对端使用aes_gcm
+    aes_hardware: bool,
+    // 开启了混合密钥交换，和同样开启的对端握手时混入ML-KEM
+    hybrid: bool,
+    trusted_keys: Vec<[u8; 32]>,
+    sessions: RwLock<FnvHashMap<Ipv4Addr, Session>>,
+    handshakes: Mutex<FnvHashMap<Ipv4Addr, Handshake>>,
+    replayed: AtomicU64,
+}
+
+impl PeerKeys {
+    pub fn new(config: &PeerKeyConfig, hybrid: bool) -> Self {
+        let local = LocalKey::new(&config.private_key);
+        log::info!("本端公钥 {}", encode_key(&local.public));
+        let aes_hardware = aes_hardware();
+        log::info!("aes硬件加速 {}", aes_hardware);
+        Self {
+            local,
+            aes_hardware,
+            hybrid,
+            trusted_keys: config.trusted_keys.clone(),
+            sessions: RwLock::new(FnvHashMap::default()),
+            handshakes: Mutex::new(FnvHashMap::default()),
+            replayed: AtomicU64::new(0),
+        }
+    }
+    fn trusted(&self, public_key: &[u8; 32]) -> bool {
+        self.trusted_keys.is_empty() || self.trusted_keys.contains(public_key)
+    }
+    fn install(&self, ip: Ipv4Addr, public_key: [u8; 32], keys: &SessionKeys, confirmed: bool) {
+        let mut sessions = self.sessions.write();
+        // 同一个对端重新握手是在换密钥，保留旧密钥
+        let (previous, established) = match sessions.remove(&ip) {
+            Some(old) if old.public_key == public_key => {
+                let previous = if old.is_confirmed() {
+                    Some(old.current)
+                } else {
+                    old.previous
+                };
+                (previous, old.established)
+            }
+            _ => (None, Instant::now()),
+        };
+        if previous.is_some() {
+            log::info!(
+                "和{}更换会话密钥,算法 {:?},混合密钥交换 {}",
+                ip,
+                keys.suite,
+                keys.hybrid
+            );
+        } else {
+            log::info!(
+                "和{}协商会话密钥成功,算法 {:?},混合密钥交换 {},对端公钥 {}",
+                ip,
+                keys.suite,
+                keys.hybrid,
+                encode_key(&public_key)
+            );
+        }
+        let session = Session {
+            public_key,
+            current: KeyPair::new(keys),
+            confirmed: AtomicBool::new(confirmed),
+            previous,
+            established,
+        };
+        sessions.insert(ip, session);
+    }
+    /// 换密钥没有完成，恢复使用旧密钥
+    fn rollback(&self, ip: &Ipv4Addr) {
+        let mut sessions = self.sessions.write();
+        let restored = match sessions.get_mut(ip) {
+            Some(session) => match session.previous.take() {
+                Some(previous) => {
+                    session.current = previous;
+                    session.confirmed.store(true, Ordering::Relaxed);
+                    true
+                }
+                None => false,
+            },
+            None => true,
+        };
+        if !restored {
+            sessions.remove(ip);
+        }
+    }
+    /// 是否开启了混合密钥交换
+    pub fn hybrid(&self) -> bool {
+        self.hybrid
+    }
+    /// 对端的公钥，未协商时为None
+    pub fn peer_public_key(&self, ip: &Ipv4Addr) -> Option<[u8; 32]> {
+        self.sessions.read().get(ip).map(|v| v.public_key)
+    }
+    /// 有已确认的会话时使用会话密钥加密，返回false时由调用方使用组网密码加密
+    pub fn encrypt<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        net_packet: &mut NetPacket<B>,
+        dest: &Ipv4Addr,
+    ) -> anyhow::Result<bool> {
+        let sessions = self.sessions.read();
+        let session = match sessions.get(dest) {
+            Some(session) => session,
+            None => return Ok(false),
+        };
+        let key = if session.is_confirmed() {
+            &session.current
+        } else {
+            match &session.previous {
+                Some(previous) => previous,
+                None => return Ok(false),
+            }
+        };
+        key.send.encrypt(net_packet)?;
+        net_packet.set_session_flag(true);
+        Ok(true)
+    }
+    /// 返回false时是重放的数据，直接丢弃
+    pub fn decrypt<B: AsRef<[u8]> + AsMut<[u8]>>(
+        &self,
+        net_packet: &mut NetPacket<B>,
+    ) -> anyhow::Result<bool> {
+        let source = net_packet.source();
+        let sessions = self.sessions.read();
+        let session = match sessions.get(&source) {
+            Some(session) => session,
+            None => return Err(anyhow!("{} 没有会话密钥", source)),
+        };
+        net_packet.set_session_flag(false);
+        let (key, counter) = match session.current.recv.decrypt(net_packet) {
+            Ok(counter) => {
+                // 能收到对端用当前密钥加密的数据，说明对端已完成握手
+                session.confirmed.store(true, Ordering::Relaxed);
+                (&session.current.recv, counter)
+            }
+            Err(e) => match &session.previous {
+                Some(previous) if session.previous_valid() => {
+                    (&previous.recv, previous.recv.decrypt(net_packet)?)
+                }
+                _ => return Err(e),
+            },
+        };
+        if !key.check_replay(counter) {
+            self.replayed.fetch_add(1, Ordering::Relaxed);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+    /// 丢弃的重放数据包数
+    pub fn replayed(&self) -> u64 {
+        self.replayed.load(Ordering::Relaxed)
+    }
+    /// 会话确认一段时间后，不再接收组网密码加密的转发数据
+    pub fn accept_plain(&self, source: &Ipv4Addr) -> bool {
+        match self.sessions.read().get(source) {
+            Some(session) => {
+                !(session.is_confirmed() || session.previous.is_some())
+                    || session.established.elapsed() < PLAIN_GRACE
+            }
+            None => true,
+        }
+    }
+    /// 只保留满足条件的对端
+    pub fn retain<F: Fn(&Ipv4Addr) -> bool>(&self, f: F) {
+        self.sessions.write().retain(|ip, _| f(ip));
+        self.handshakes.lock().retain(|ip, _| f(ip));
+    }
+    /// 定时检查，返回需要发给对端的握手消息，peer_hybrid表示对端开启了混合密钥交换
+    pub fn poll(
+        &self,
+        local_ip: Ipv4Addr,
+        peer: Ipv4Addr,
+        peer_hybrid: bool,
+    ) -> Option<(u8, Vec<u8>)> {
+        let mut handshakes = self.handshakes.lock();
+        if let Some(handshake) = handshakes.get(&peer) {
+            let timeout = handshake.time().elapsed() > HANDSHAKE_TIMEOUT;
+            match handshake {
+                Handshake::Finish(msg, _, _) => {
+                    let confirmed = self
+                        .sessions
+                        .read()
+                        .get(&peer)
+                        .map_or(false, |v| v.is_confirmed());
+                    if confirmed {
+                        handshakes.remove(&peer);
+                        return None;
+                    }
+                    if !timeout {
+                        return Some((MSG_FINISH, msg.clone()));
+                    }
+                    // 一直没有确认，重新握手
+                    self.rollback(&peer);
+                }
+                _ => {
+                    if !timeout {
+                        return None;
+                    }
+                }
+            }
+            handshakes.remove(&peer);
+        }
+        let (exists, expired) = match self.sessions.read().get(&peer) {
+            Some(session) => {
+                if session.need_rekey() {
+                    (false, false)
+                } else {
+                    (
+                        true,
+                        session.previous.is_some() && !session.previous_valid(),
+                    )
+                }
+            }
+            None => (false, false),
+        };
+        if expired {
+            if let Some(session) = self.sessions.write().get_mut(&peer) {
+                session.previous = None;
+            }
+        }
+        if exists {
+            return None;
+        }
+        let (msg, initiator) = create_init(local_ip, peer, self.hybrid && peer_hybrid);
+        handshakes.insert(peer, Handshake::Initiator(initiator, Instant::now()));
+        Some((MSG_INIT, msg))
+    }
+    /// 处理对端的握手消息，返回需要回复的消息
+    pub fn handle(
+        &self,
+        local_ip: Ipv4Addr,
+        source: Ipv4Addr,
+        kind: u8,
+        msg: &[u8],
+        peer_hybrid: bool,
+    ) -> Option<(u8, Vec<u8>)> {
+        let mut handshakes = self.handshakes.lock();
+        match kind {
+            MSG_INIT => {
+                // 双方同时发起时，ip小的一方作为发起方
+                if let Some(Handshake::Initiator(_, _)) = handshakes.get(&source) {
+                    if local_ip < source {
+                        return None;
+                    }
+                }
+                let (msg, responder) = create_response(
+                    &self.local,
+                    local_ip,
+                    source,
+                    msg,
+                    self.aes_hardware,
+                    self.hybrid,
+                )?;
+                // 双方都开启了混合密钥交换，不接受降级的握手
+                if self.hybrid && peer_hybrid && !responder.hybrid {
+                    log::warn!("{}的握手没有使用混合密钥交换,拒绝", source);
+                    return None;
+                }
+                handshakes.insert(source, Handshake::Responder(responder, Instant::now()));
+                Some((MSG_RESPONSE, msg))
+            }
+            MSG_RESPONSE => {
+                let initiator = match handshakes.get(&source) {
+                    Some(Handshake::Initiator(initiator, _)) => initiator,
+                    _ => return None,
+                };
+                let (msg, public_key, keys) =
+                    consume_response(&self.local, initiator, msg, self.aes_hardware)?;
+                if !self.trusted(&public_key) {
+                    log::warn!("{}的公钥不受信任 {}", source, encode_key(&public_key));
+                    handshakes.remove(&source);
+                    return None;
+                }
+                self.install(source, public_key, &keys, false);
+                handshakes.insert(
+                    source,
+                    Handshake::Finish(msg.clone(), keys.confirm, Instant::now()),
+                );
+                Some((MSG_FINISH, msg))
+            }
+            MSG_FINISH => match handshakes.get(&source) {
+                Some(Handshake::Responder(responder, _)) => {
+                    let (public_key, keys) = consume_finish(responder, msg)?;
+                    if !self.trusted(&public_key) {
+                        log::warn!("{}的公钥不受信任 {}", source, encode_key(&public_key));
+                        handshakes.remove(&source);
+                        return None;
+                    }
+                    self.install(source, public_key, &keys, true);
+                    let confirm = create_confirm(&keys.confirm);
+                    handshakes.insert(
+                        source,
+                        Handshake::Confirmed(msg.to_vec(), confirm.clone(), Instant::now()),
+                    );
+                    Some((MSG_CONFIRM, confirm))
+                }
+                Some(Handshake::Confirmed(finish, confirm, _)) if finish == msg => {
+                    // 确认消息丢失了
+                    Some((MSG_CONFIRM, confirm.clone()))
+                }
+                _ => None,
+            },
+            MSG_CONFIRM => {
+                if let Some(Handshake::Finish(_, confirm, _)) = handshakes.get(&source) {
+                    if check_confirm(confirm, msg) {
+                        handshakes.remove(&source);
+                        if let Some(session) = self.sessions.read().get(&source) {
+                            session.confirmed.store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
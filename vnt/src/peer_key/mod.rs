@@ -0,0 +1,94 @@
+use std::fmt::{Debug, Formatter};
+use std::net::Ipv4Addr;
+
+use anyhow::anyhow;
+
+use crate::channel::context::ChannelContext;
+use crate::cipher::{Cipher, EncryptScope};
+use crate::protocol::{NetPacket, Protocol};
+use crate::wireguard::{decode_key, encode_key};
+
+#[cfg(feature = "peer_key")]
+mod cipher;
+#[cfg(feature = "peer_key")]
+mod handshake;
+#[cfg(feature = "peer_key")]
+mod keys;
+#[cfg(feature = "peer_key")]
+pub use crate::cipher::noise::{generate_key, public_key};
+#[cfg(feature = "peer_key")]
+pub use keys::PeerKeys;
+
+/// 客户端之间的密钥协商，每个客户端持有自己的x25519私钥，
+/// 和对端握手后得到前向安全的会话密钥，组网密码泄露也无法解密之前的数据
+#[derive(Clone)]
+pub struct PeerKeyConfig {
+    pub private_key: [u8; 32],
+    // 信任的对端公钥，为空时接受所有对端
+    pub trusted_keys: Vec<[u8; 32]>,
+}
+
+impl PeerKeyConfig {
+    pub fn new(private_key: &str, trusted_keys: &[String]) -> anyhow::Result<Self> {
+        let private_key = decode_key(private_key)?;
+        let mut list = Vec::with_capacity(trusted_keys.len());
+        for key in trusted_keys {
+            let key = decode_key(key)?;
+            if list.contains(&key) {
+                return Err(anyhow!("trusted key {:?} repeated", encode_key(&key)));
+            }
+            list.push(key);
+        }
+        Ok(Self {
+            private_key,
+            trusted_keys: list,
+        })
+    }
+}
+
+impl Debug for PeerKeyConfig {
+    // 不能打印私钥
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let trusted_keys: Vec<String> = self.trusted_keys.iter().map(encode_key).collect();
+        f.debug_struct("PeerKeyConfig")
+            .field("trusted_keys", &trusted_keys)
+            .finish()
+    }
+}
+
+/// 发给单个客户端的数据，已协商会话密钥时使用会话密钥加密，否则使用组网密码加密，
+/// 不加密且双方都开启了完整性校验时附加校验码，限定了加密范围时按路径决定是否加密
+#[cfg_attr(
+    not(any(feature = "peer_key", feature = "integrity")),
+    allow(unused_variables)
+)]
+pub(crate) fn encrypt_to_peer<B: AsRef<[u8]> + AsMut<[u8]>>(
+    context: &ChannelContext,
+    client_cipher: &Cipher,
+    net_packet: &mut NetPacket<B>,
+    dest: &Ipv4Addr,
+) -> anyhow::Result<()> {
+    #[cfg(feature = "peer_key")]
+    if let Some(peer_key) = context.peer_key() {
+        if peer_key.encrypt(net_packet, dest)? {
+            return Ok(());
+        }
+    }
+    #[cfg(feature = "integrity")]
+    if let Some(integrity) = context.integrity() {
+        if integrity.required(context.peer_features(dest)) {
+            return integrity.sign(net_packet);
+        }
+    }
+    let scope = context.encrypt_scope();
+    if scope != EncryptScope::All
+        && matches!(
+            net_packet.protocol(),
+            Protocol::IpTurn | Protocol::OtherTurn
+        )
+        && !scope.encrypt(context.route_table.all_p2p(dest))
+    {
+        return Ok(());
+    }
+    client_cipher.encrypt_ipv4(net_packet)
+}
@@ -1,8 +1,12 @@
+use std::fmt::{Display, Formatter};
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::thread;
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
 
 use crate::util::StopManager;
 
@@ -10,72 +14,182 @@ mod tcp_mapping;
 
 mod udp_mapping;
 
-pub fn convert(vec: Vec<String>) -> anyhow::Result<Vec<(bool, SocketAddr, String)>> {
-    let mut rs = Vec::with_capacity(vec.len());
-    for x in vec {
-        let string = x.trim().to_lowercase();
-        if let Some(udp_mapping) = string.strip_prefix("udp:") {
-            let mut split = udp_mapping.split("-");
-            let bind_addr = split.next().with_context(|| {
-                format!(
-                    "udp_mapping error {:?},eg: udp:127.0.0.1:80-10.26.0.10:8080",
-                    x
-                )
-            })?;
-            let bind_addr = SocketAddr::from_str(bind_addr)
-                .with_context(|| format!("udp_mapping error {}", bind_addr))?;
-            let dest = split.next().with_context(|| {
-                format!(
-                    "udp_mapping error {:?},eg: udp:127.0.0.1:80-10.26.0.10:8080",
-                    x
-                )
-            })?;
-            rs.push((false, bind_addr, dest.to_string()));
-            continue;
+// 一条规则最多映射的端口数量
+const MAX_RANGE: u16 = 1024;
+
+/// 一条端口映射，格式 `tcp|udp|tcp+udp:<监听地址>:<端口[-端口]>-><目标>:<端口[-端口]>`，
+/// 例如 `tcp+udp:0.0.0.0:27015-27030->192.168.1.10:27015-27030`，
+/// 也兼容旧格式 `udp:0.0.0.0:80-10.26.0.10:80`。端口范围按顺序一一对应
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MappingRule {
+    pub tcp: bool,
+    pub udp: bool,
+    // 第一个监听的地址
+    pub bind_addr: SocketAddr,
+    // 端口数量
+    pub count: u16,
+    // 目标的域名或ip
+    pub dest_host: String,
+    pub dest_port: u16,
+}
+
+impl MappingRule {
+    /// 监听端口对应的目标地址
+    pub fn destination(&self, bind_port: u16) -> String {
+        let port = self.dest_port + (bind_port - self.bind_addr.port());
+        if self.dest_host.contains(':') {
+            format!("[{}]:{}", self.dest_host, port)
+        } else {
+            format!("{}:{}", self.dest_host, port)
+        }
+    }
+    pub fn bind_addrs(&self) -> Vec<SocketAddr> {
+        (0..self.count)
+            .map(|i| SocketAddr::new(self.bind_addr.ip(), self.bind_addr.port() + i))
+            .collect()
+    }
+}
+
+// 端口或端口范围，返回起始端口和数量
+fn port_range(s: &str) -> Option<(u16, u16)> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let start = u16::from_str(start).ok()?;
+    let end = u16::from_str(end).ok()?;
+    if start > end || end - start >= MAX_RANGE {
+        return None;
+    }
+    Some((start, end - start + 1))
+}
+
+impl FromStr for MappingRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            anyhow!(
+                "port_mapping error {:?},eg: tcp:127.0.0.1:80-10.26.0.10:8080, tcp+udp:0.0.0.0:27015-27030->10.26.0.10:27015-27030",
+                s
+            )
+        };
+        let string = s.trim().to_lowercase();
+        let (protocol, mapping) = string.split_once(':').ok_or_else(err)?;
+        let (tcp, udp) = match protocol {
+            "tcp" => (true, false),
+            "udp" => (false, true),
+            "tcp+udp" | "udp+tcp" => (true, true),
+            _ => Err(err())?,
+        };
+        let (bind, dest) = match mapping.split_once("->") {
+            Some(v) => v,
+            None => mapping.split_once('-').ok_or_else(err)?,
+        };
+        let (bind_ip, bind_port) = bind.rsplit_once(':').ok_or_else(err)?;
+        let (bind_port, count) = port_range(bind_port).ok_or_else(err)?;
+        let bind_addr = SocketAddr::from_str(&format!("{}:{}", bind_ip, bind_port))
+            .with_context(|| format!("port_mapping error {}", bind))?;
+        let (dest_host, dest_port) = dest.rsplit_once(':').ok_or_else(err)?;
+        let (dest_port, dest_count) = port_range(dest_port).ok_or_else(err)?;
+        if dest_count != count {
+            Err(anyhow!(
+                "port_mapping error {:?},port ranges differ in size",
+                s
+            ))?;
         }
-        if let Some(tcp_mapping) = string.strip_prefix("tcp:") {
-            let mut split = tcp_mapping.split("-");
-            let bind_addr = split.next().with_context(|| {
-                format!(
-                    "tcp_mapping error {:?},eg: tcp:127.0.0.1:80-10.26.0.10:8080",
-                    x
-                )
-            })?;
-            let bind_addr = SocketAddr::from_str(bind_addr)
-                .with_context(|| format!("udp_mapping error {}", bind_addr))?;
-            let dest = split.next().with_context(|| {
-                format!(
-                    "tcp_mapping error {:?},eg: tcp:127.0.0.1:80-10.26.0.10:8080",
-                    x
-                )
-            })?;
-            rs.push((true, bind_addr, dest.to_string()));
-            continue;
+        let dest_host = dest_host.trim_start_matches('[').trim_end_matches(']');
+        if dest_host.is_empty() {
+            Err(err())?;
         }
-        Err(anyhow::anyhow!(
-            "port_mapping error {:?},eg: tcp:127.0.0.1:80-10.26.0.10:8080",
-            x
-        ))?;
+        Ok(MappingRule {
+            tcp,
+            udp,
+            bind_addr,
+            count,
+            dest_host: dest_host.to_string(),
+            dest_port,
+        })
+    }
+}
+
+impl Display for MappingRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let protocol = match (self.tcp, self.udp) {
+            (true, true) => "tcp+udp",
+            (true, false) => "tcp",
+            _ => "udp",
+        };
+        let range = |start: u16| {
+            if self.count == 1 {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, start + self.count - 1)
+            }
+        };
+        let dest_host = if self.dest_host.contains(':') {
+            format!("[{}]", self.dest_host)
+        } else {
+            self.dest_host.clone()
+        };
+        write!(
+            f,
+            "{}:{}:{}->{}:{}",
+            protocol,
+            match self.bind_addr {
+                SocketAddr::V4(addr) => addr.ip().to_string(),
+                SocketAddr::V6(addr) => format!("[{}]", addr.ip()),
+            },
+            range(self.bind_addr.port()),
+            dest_host,
+            range(self.dest_port)
+        )
+    }
+}
+
+pub fn convert(vec: Vec<String>) -> anyhow::Result<Vec<MappingRule>> {
+    let mut rs = Vec::with_capacity(vec.len());
+    for x in vec {
+        rs.push(MappingRule::from_str(&x)?);
     }
     Ok(rs)
 }
+/// 运行中的端口映射，重新加载配置时先停止再按新的配置启动
+pub struct PortMapping {
+    sender: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl PortMapping {
+    /// 停止并等待监听的端口释放
+    pub fn stop(self) {
+        if let Some(sender) = self.sender.lock().take() {
+            let _ = sender.send(());
+        }
+        let _ = self.handle.join();
+    }
+}
+
 pub fn start_port_mapping(
     stop_manager: StopManager,
-    vec: Vec<(bool, SocketAddr, String)>,
-) -> anyhow::Result<()> {
+    vec: Vec<MappingRule>,
+) -> anyhow::Result<Option<PortMapping>> {
     if vec.is_empty() {
-        return Ok(());
+        return Ok(None);
     }
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .thread_name("portMapping")
         .build()?;
     runtime.block_on(start_port_mapping0(vec))?;
-    let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
-    let worker = stop_manager.add_listener("portMapping".into(), move || {
-        let _ = sender.send(());
-    })?;
-    thread::Builder::new()
+    let (sender, receiver) = oneshot::channel::<()>();
+    let sender = Arc::new(Mutex::new(Some(sender)));
+    let worker = {
+        let sender = sender.clone();
+        stop_manager.add_listener("portMapping".into(), move || {
+            if let Some(sender) = sender.lock().take() {
+                let _ = sender.send(());
+            }
+        })?
+    };
+    let handle = thread::Builder::new()
         .name("portMapping".into())
         .spawn(move || {
             runtime.block_on(async {
@@ -85,16 +199,39 @@ pub fn start_port_mapping(
             drop(worker);
         })?;
 
-    Ok(())
+    Ok(Some(PortMapping { sender, handle }))
 }
 
-async fn start_port_mapping0(vec: Vec<(bool, SocketAddr, String)>) -> anyhow::Result<()> {
-    for (is_tcp, bind_addr, destination) in vec {
-        if is_tcp {
-            tcp_mapping::tcp_mapping(bind_addr, destination).await?;
-        } else {
-            udp_mapping::udp_mapping(bind_addr, destination).await?;
+async fn start_port_mapping0(vec: Vec<MappingRule>) -> anyhow::Result<()> {
+    for rule in vec {
+        let rule = Arc::new(rule);
+        if rule.tcp {
+            tcp_mapping::tcp_mapping(rule.clone()).await?;
+        }
+        if rule.udp {
+            udp_mapping::udp_mapping(rule).await?;
         }
     }
     Ok(())
 }
+
+#[test]
+fn mapping_rule_test() {
+    let rule = MappingRule::from_str("udp:0.0.0.0:80-10.26.0.10:8080").unwrap();
+    assert!(rule.udp && !rule.tcp);
+    assert_eq!(rule.count, 1);
+    assert_eq!(rule.destination(80), "10.26.0.10:8080");
+    assert_eq!(rule.to_string(), "udp:0.0.0.0:80->10.26.0.10:8080");
+    let rule =
+        MappingRule::from_str("tcp+udp:0.0.0.0:27015-27030->192.168.1.10:28015-28030").unwrap();
+    assert!(rule.udp && rule.tcp);
+    assert_eq!(rule.count, 16);
+    assert_eq!(rule.bind_addrs().len(), 16);
+    assert_eq!(rule.destination(27020), "192.168.1.10:28020");
+    assert_eq!(
+        rule.to_string(),
+        "tcp+udp:0.0.0.0:27015-27030->192.168.1.10:28015-28030"
+    );
+    assert!(MappingRule::from_str("tcp:0.0.0.0:80-81->10.26.0.10:80").is_err());
+    assert!(MappingRule::from_str("icmp:0.0.0.0:80-10.26.0.10:80").is_err());
+}
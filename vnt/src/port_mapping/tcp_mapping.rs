@@ -1,36 +1,53 @@
 use anyhow::Context;
-use std::net::SocketAddr;
+use futures_util::stream::{select_all, unfold};
+use futures_util::StreamExt;
+use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
 
-pub async fn tcp_mapping(bind_addr: SocketAddr, destination: String) -> anyhow::Result<()> {
-    let tcp_listener = TcpListener::bind(bind_addr)
-        .await
-        .with_context(|| format!("TCP binding {:?} failed", bind_addr))?;
+use crate::port_mapping::MappingRule;
+
+/// 端口范围内的所有监听在同一个任务中accept，按本地端口计算目标
+pub async fn tcp_mapping(rule: Arc<MappingRule>) -> anyhow::Result<()> {
+    let mut listeners = Vec::with_capacity(rule.count as usize);
+    for bind_addr in rule.bind_addrs() {
+        let tcp_listener = TcpListener::bind(bind_addr)
+            .await
+            .with_context(|| format!("TCP binding {:?} failed", bind_addr))?;
+        listeners.push(tcp_listener);
+    }
+    let mut incoming = select_all(listeners.into_iter().map(|listener| {
+        Box::pin(unfold(listener, |listener| async move {
+            let rs = listener.accept().await;
+            Some((rs, listener))
+        }))
+    }));
     tokio::spawn(async move {
-        if let Err(e) = tcp_mapping_(bind_addr, tcp_listener, destination).await {
-            log::warn!("tcp_mapping {:?}", e);
+        while let Some(rs) = incoming.next().await {
+            let tcp_stream = match rs {
+                Ok((tcp_stream, _)) => tcp_stream,
+                Err(e) => {
+                    log::warn!("tcp_mapping {} {:?}", rule, e);
+                    continue;
+                }
+            };
+            let bind_port = match tcp_stream.local_addr() {
+                Ok(addr) => addr.port(),
+                Err(e) => {
+                    log::warn!("tcp_mapping {} {:?}", rule, e);
+                    continue;
+                }
+            };
+            let destination = rule.destination(bind_port);
+            tokio::spawn(async move {
+                if let Err(e) = copy(tcp_stream, &destination).await {
+                    log::warn!("tcp port mapping {}->{} {:?}", bind_port, destination, e);
+                }
+            });
         }
     });
     Ok(())
 }
 
-async fn tcp_mapping_(
-    bind_addr: SocketAddr,
-    tcp_listener: TcpListener,
-    destination: String,
-) -> anyhow::Result<()> {
-    loop {
-        let (tcp_stream, _) = tcp_listener.accept().await?;
-
-        let destination = destination.clone();
-        tokio::spawn(async move {
-            if let Err(e) = copy(tcp_stream, &destination).await {
-                log::warn!("tcp port mapping {}->{} {:?}", bind_addr, destination, e);
-            }
-        });
-    }
-}
-
 async fn copy(source_tcp: TcpStream, destination: &String) -> anyhow::Result<()> {
     // 或许这里也应该绑定最匹配的网卡，不然全局代理会影响映射
     let dest_tcp = TcpStream::connect(destination)
@@ -1,5 +1,7 @@
 use anyhow::Context;
 use crossbeam_utils::atomic::AtomicCell;
+use futures_util::stream::{select_all, unfold};
+use futures_util::StreamExt;
 use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -7,22 +9,41 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
 
-pub async fn udp_mapping(bind_addr: SocketAddr, destination: String) -> anyhow::Result<()> {
-    let udp = UdpSocket::bind(bind_addr)
-        .await
-        .with_context(|| format!("port proxy UDP binding {:?} failed", bind_addr))?;
-    let udp = Arc::new(udp);
+use crate::port_mapping::MappingRule;
 
-    let inner_map: Arc<Mutex<HashMap<SocketAddr, (Arc<UdpSocket>, Arc<AtomicCell<Instant>>)>>> =
-        Arc::new(Mutex::new(HashMap::with_capacity(64)));
+type SessionMap = HashMap<(u16, SocketAddr), (Arc<UdpSocket>, Arc<AtomicCell<Instant>>)>;
+
+/// 端口范围内的所有socket在同一个任务中接收，会话按(本地端口,来源地址)区分
+pub async fn udp_mapping(rule: Arc<MappingRule>) -> anyhow::Result<()> {
+    let mut sockets = Vec::with_capacity(rule.count as usize);
+    for bind_addr in rule.bind_addrs() {
+        let udp = UdpSocket::bind(bind_addr)
+            .await
+            .with_context(|| format!("port proxy UDP binding {:?} failed", bind_addr))?;
+        sockets.push((bind_addr.port(), Arc::new(udp)));
+    }
+    let inner_map: Arc<Mutex<SessionMap>> = Arc::new(Mutex::new(HashMap::with_capacity(64)));
+    let mut incoming = select_all(sockets.into_iter().map(|(bind_port, udp)| {
+        Box::pin(unfold(
+            (udp, vec![0u8; 65536]),
+            move |(udp, mut buf)| async move {
+                let rs = udp
+                    .recv_from(&mut buf)
+                    .await
+                    .map(|(len, src_addr)| (buf[..len].to_vec(), src_addr));
+                Some(((bind_port, udp.clone(), rs), (udp, buf)))
+            },
+        ))
+    }));
 
     tokio::spawn(async move {
-        let mut buf = [0; 65536];
-        loop {
-            match udp.recv_from(&mut buf).await {
-                Ok((len, src_addr)) => {
+        while let Some((bind_port, udp, rs)) = incoming.next().await {
+            match rs {
+                Ok((buf, src_addr)) => {
+                    let destination = rule.destination(bind_port);
                     if let Err(e) =
-                        udp_mapping0(&buf[..len], src_addr, &inner_map, &udp, &destination).await
+                        udp_mapping0(&buf, (bind_port, src_addr), &inner_map, &udp, &destination)
+                            .await
                     {
                         log::warn!("udp port mapping {}->{} {:?}", src_addr, destination, e);
                     }
@@ -38,12 +59,13 @@ pub async fn udp_mapping(bind_addr: SocketAddr, destination: String) -> anyhow::
 
 async fn udp_mapping0(
     buf: &[u8],
-    src_addr: SocketAddr,
-    inner_map: &Arc<Mutex<HashMap<SocketAddr, (Arc<UdpSocket>, Arc<AtomicCell<Instant>>)>>>,
+    key: (u16, SocketAddr),
+    inner_map: &Arc<Mutex<SessionMap>>,
     udp_socket: &Arc<UdpSocket>,
     destination: &String,
 ) -> anyhow::Result<()> {
-    let option = inner_map.lock().get(&src_addr).cloned();
+    let src_addr = key.1;
+    let option = inner_map.lock().get(&key).cloned();
     if let Some((udp, time)) = option {
         time.store(Instant::now());
         udp.send(buf).await?;
@@ -58,7 +80,7 @@ async fn udp_mapping0(
         let time = Arc::new(AtomicCell::new(Instant::now()));
         inner_map
             .lock()
-            .insert(src_addr, (dest_udp.clone(), time.clone()));
+            .insert(key, (dest_udp.clone(), time.clone()));
         tokio::spawn(async move {
             let mut buf = [0u8; 65536];
             loop {
@@ -100,7 +122,7 @@ async fn udp_mapping0(
                     }
                 }
             }
-            inner_map.lock().remove(&src_addr);
+            inner_map.lock().remove(&key);
         });
     }
     Ok(())
@@ -10,6 +10,11 @@ pub enum Protocol {
         +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
         |              time                          |                    echo                        |
         +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |              features(可选)                 |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |                                     session id(可选,64位)                                     |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        注：客户端之间的ping包会带上本端支持的能力和会话id，旧版本没有这些字段
     */
     Ping,
     /*
@@ -27,6 +32,75 @@ pub enum Protocol {
     ///获取对端看到的地址
     AddrRequest,
     AddrResponse,
+    /// 路径mtu探测，不允许分片，填充到mtu对应的长度
+    /*
+         0                                            15
+         0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |              mtu                            |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |              padding(n)                     |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    */
+    PmtuProbe,
+    /// 路径mtu探测响应，只带回mtu
+    PmtuReply,
+    /// 中继路径通告，允许中转的客户端告知直连对端自己能直达哪些客户端以及延迟
+    /*
+         0                                            15                                              31
+         0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |                                          virtual ip                                           |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |              rt                            |                    ...                         |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        注：每项6字节，重复n次
+    */
+    RelayPaths,
+    /// 连接迁移，本端ip变化后从新地址通知对端，对端校验会话id后把直连路由切换到新地址
+    /*
+         0                                            15                                              31
+         0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |                                       session id(64位)                                        |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |              generation                    |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        注：generation每次迁移加1，对端收到更新的generation时移除旧地址的直连路由
+    */
+    Migrate,
+    /// 客户端之间的密钥协商(Noise XX)，协商出的会话密钥只用于转发的数据
+    /*
+         0                                            15                                              31
+         0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |     type(8)          |                             body(n)                                    |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        注：type 1-3为握手的三个消息，4为响应方的确认
+    */
+    PeerKey,
+    /// 本端正常退出，对端收到后立即删除到本端的路由，没有数据
+    Leave,
+    /// 诊断请求，用于vnt协议层的ping、traceroute和带宽测试
+    /*
+         0                                            15                                              31
+         0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |     kind(8)          |     reserved(8)     |                    seq                         |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |                                              id                                               |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |                                          bytes(64位)                                          |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |                                         packets(64位)                                         |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        |                                          padding(n)                                           |
+        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+        注：kind 1为回显，2为带宽测试的数据，不回应，3为查询带宽测试收到的字节数和包数
+    */
+    Diag,
+    /// 诊断响应，格式和请求相同，不带填充，查询的结果放在bytes和packets
+    DiagReply,
     Unknown(u8),
 }
 
@@ -39,6 +113,14 @@ impl From<u8> for Protocol {
             4 => Protocol::PunchResponse,
             5 => Protocol::AddrRequest,
             6 => Protocol::AddrResponse,
+            7 => Protocol::PmtuProbe,
+            8 => Protocol::PmtuReply,
+            9 => Protocol::RelayPaths,
+            10 => Protocol::Migrate,
+            11 => Protocol::PeerKey,
+            12 => Protocol::Leave,
+            13 => Protocol::Diag,
+            14 => Protocol::DiagReply,
             val => Protocol::Unknown(val),
         }
     }
@@ -53,11 +135,34 @@ impl Into<u8> for Protocol {
             Protocol::PunchResponse => 4,
             Protocol::AddrRequest => 5,
             Protocol::AddrResponse => 6,
+            Protocol::PmtuProbe => 7,
+            Protocol::PmtuReply => 8,
+            Protocol::RelayPaths => 9,
+            Protocol::Migrate => 10,
+            Protocol::PeerKey => 11,
+            Protocol::Leave => 12,
+            Protocol::Diag => 13,
+            Protocol::DiagReply => 14,
             Protocol::Unknown(val) => val,
         }
     }
 }
 
+impl Protocol {
+    /// 还没有路由的对端也会发来的控制包，来源地址未验证时需要限流
+    pub fn is_handshake(&self) -> bool {
+        matches!(
+            self,
+            Protocol::Ping
+                | Protocol::PunchRequest
+                | Protocol::AddrRequest
+                | Protocol::PmtuProbe
+                | Protocol::Migrate
+                | Protocol::PeerKey
+        )
+    }
+}
+
 pub enum ControlPacket<B> {
     PingPacket(PingPacket<B>),
     PongPacket(PongPacket<B>),
@@ -65,6 +170,14 @@ pub enum ControlPacket<B> {
     PunchResponse,
     AddrRequest,
     AddrResponse(AddrPacket<B>),
+    PmtuProbe(PmtuPacket<B>),
+    PmtuReply(PmtuPacket<B>),
+    RelayPaths(RelayPathsPacket<B>),
+    Migrate(MigratePacket<B>),
+    PeerKey(PeerKeyPacket<B>),
+    Leave,
+    Diag(DiagPacket<B>),
+    DiagReply(DiagPacket<B>),
 }
 
 impl<B: AsRef<[u8]>> ControlPacket<B> {
@@ -76,11 +189,73 @@ impl<B: AsRef<[u8]>> ControlPacket<B> {
             Protocol::PunchResponse => Ok(ControlPacket::PunchResponse),
             Protocol::AddrRequest => Ok(ControlPacket::AddrRequest),
             Protocol::AddrResponse => Ok(ControlPacket::AddrResponse(AddrPacket::new(buffer)?)),
+            Protocol::PmtuProbe => Ok(ControlPacket::PmtuProbe(PmtuPacket::new(buffer)?)),
+            Protocol::PmtuReply => Ok(ControlPacket::PmtuReply(PmtuPacket::new(buffer)?)),
+            Protocol::RelayPaths => Ok(ControlPacket::RelayPaths(RelayPathsPacket::new(buffer)?)),
+            Protocol::Migrate => Ok(ControlPacket::Migrate(MigratePacket::new(buffer)?)),
+            Protocol::PeerKey => Ok(ControlPacket::PeerKey(PeerKeyPacket::new(buffer)?)),
+            Protocol::Leave => Ok(ControlPacket::Leave),
+            Protocol::Diag => Ok(ControlPacket::Diag(DiagPacket::new(buffer)?)),
+            Protocol::DiagReply => Ok(ControlPacket::DiagReply(DiagPacket::new(buffer)?)),
             Protocol::Unknown(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported")),
         }
     }
 }
 
+/// 支持fec解码
+pub const FEATURE_FEC: u16 = 0b1;
+/// 支持路径mtu探测
+pub const FEATURE_PMTU: u16 = 0b10;
+/// 支持拆分合并的小包
+pub const FEATURE_AGGREGATE: u16 = 0b100;
+/// 支持lz4解压
+pub const FEATURE_LZ4: u16 = 0b1000;
+/// 支持zstd解压
+pub const FEATURE_ZSTD: u16 = 0b1_0000;
+/// 支持可靠传输
+pub const FEATURE_RELIABLE: u16 = 0b10_0000;
+/// 本端为二层模式，转发以太网帧，和三层模式的对端不互通
+pub const FEATURE_LAYER2: u16 = 0b100_0000;
+/// 支持中继路径通告
+pub const FEATURE_RELAY_PATHS: u16 = 0b1000_0000;
+/// 支持连接迁移
+pub const FEATURE_MIGRATE: u16 = 0b1_0000_0000;
+/// 开启了客户端之间的密钥协商，由配置决定，不在local_features中
+pub const FEATURE_PEER_KEY: u16 = 0b10_0000_0000;
+/// 密钥协商开启了x25519+ML-KEM混合密钥交换，由配置决定，不在local_features中
+pub const FEATURE_PQ_KEM: u16 = 0b100_0000_0000;
+/// 不加密时开启了数据完整性校验，由配置决定，不在local_features中
+pub const FEATURE_INTEGRITY: u16 = 0b1000_0000_0000;
+/// 支持退出通知
+pub const FEATURE_LEAVE: u16 = 0b1_0000_0000_0000;
+/// 支持诊断请求
+pub const FEATURE_DIAG: u16 = 0b10_0000_0000_0000;
+
+/// 本端支持的能力，通过ping包告知对端
+pub fn local_features() -> u16 {
+    #[allow(unused_mut)]
+    let mut features = FEATURE_PMTU
+        | FEATURE_AGGREGATE
+        | FEATURE_RELIABLE
+        | FEATURE_RELAY_PATHS
+        | FEATURE_MIGRATE
+        | FEATURE_LEAVE
+        | FEATURE_DIAG;
+    #[cfg(feature = "fec")]
+    {
+        features |= FEATURE_FEC;
+    }
+    #[cfg(feature = "lz4_compress")]
+    {
+        features |= FEATURE_LZ4;
+    }
+    #[cfg(feature = "zstd_compress")]
+    {
+        features |= FEATURE_ZSTD;
+    }
+    features
+}
+
 /// 网络探针
 pub struct PingPacket<B> {
     buffer: B,
@@ -105,6 +280,22 @@ impl<B: AsRef<[u8]>> PingPacket<B> {
     pub fn epoch(&self) -> u16 {
         u16::from_be_bytes(self.buffer.as_ref()[2..4].try_into().unwrap())
     }
+    /// 对端支持的能力，旧版本为0
+    pub fn features(&self) -> u16 {
+        let buf = self.buffer.as_ref();
+        if buf.len() < 6 {
+            return 0;
+        }
+        u16::from_be_bytes(buf[4..6].try_into().unwrap())
+    }
+    /// 对端的会话id，旧版本为0
+    pub fn session_id(&self) -> u64 {
+        let buf = self.buffer.as_ref();
+        if buf.len() < 14 {
+            return 0;
+        }
+        u64::from_be_bytes(buf[6..14].try_into().unwrap())
+    }
 }
 
 impl<B: AsRef<[u8]> + AsMut<[u8]>> PingPacket<B> {
@@ -114,6 +305,22 @@ impl<B: AsRef<[u8]> + AsMut<[u8]>> PingPacket<B> {
     pub fn set_epoch(&mut self, epoch: u16) {
         self.buffer.as_mut()[2..4].copy_from_slice(&epoch.to_be_bytes())
     }
+    pub fn set_features(&mut self, features: u16) -> io::Result<()> {
+        let buf = self.buffer.as_mut();
+        if buf.len() < 6 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len < 6"));
+        }
+        buf[4..6].copy_from_slice(&features.to_be_bytes());
+        Ok(())
+    }
+    pub fn set_session_id(&mut self, session_id: u64) -> io::Result<()> {
+        let buf = self.buffer.as_mut();
+        if buf.len() < 14 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len < 14"));
+        }
+        buf[6..14].copy_from_slice(&session_id.to_be_bytes());
+        Ok(())
+    }
 }
 
 impl<B: AsRef<[u8]>> fmt::Debug for PingPacket<B> {
@@ -121,6 +328,8 @@ impl<B: AsRef<[u8]>> fmt::Debug for PingPacket<B> {
         f.debug_struct("PingPacket")
             .field("time", &self.time())
             .field("epoch", &self.epoch())
+            .field("features", &self.features())
+            .field("session_id", &self.session_id())
             .finish()
     }
 }
@@ -163,3 +372,236 @@ impl<B: AsRef<[u8]>> fmt::Debug for AddrPacket<B> {
             .finish()
     }
 }
+
+/// 路径mtu探测
+pub struct PmtuPacket<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> PmtuPacket<B> {
+    pub fn new(buffer: B) -> io::Result<PmtuPacket<B>> {
+        let len = buffer.as_ref().len();
+        if len < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len < 2"));
+        }
+        Ok(PmtuPacket { buffer })
+    }
+    pub fn mtu(&self) -> u16 {
+        u16::from_be_bytes(self.buffer.as_ref()[..2].try_into().unwrap())
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> PmtuPacket<B> {
+    pub fn set_mtu(&mut self, mtu: u16) {
+        self.buffer.as_mut()[..2].copy_from_slice(&mtu.to_be_bytes())
+    }
+}
+
+impl<B: AsRef<[u8]>> fmt::Debug for PmtuPacket<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PmtuPacket")
+            .field("mtu", &self.mtu())
+            .field("len", &self.buffer.as_ref().len())
+            .finish()
+    }
+}
+
+/// 中继路径通告
+pub struct RelayPathsPacket<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> RelayPathsPacket<B> {
+    pub fn new(buffer: B) -> io::Result<RelayPathsPacket<B>> {
+        let len = buffer.as_ref().len();
+        if len % 6 != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len % 6 != 0"));
+        }
+        Ok(RelayPathsPacket { buffer })
+    }
+    /// 能直达的客户端和延迟
+    pub fn paths(&self) -> Vec<(Ipv4Addr, u16)> {
+        self.buffer
+            .as_ref()
+            .chunks_exact(6)
+            .map(|v| {
+                (
+                    Ipv4Addr::new(v[0], v[1], v[2], v[3]),
+                    u16::from_be_bytes([v[4], v[5]]),
+                )
+            })
+            .collect()
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> RelayPathsPacket<B> {
+    pub fn set_paths(&mut self, paths: &[(Ipv4Addr, u16)]) -> io::Result<()> {
+        let buf = self.buffer.as_mut();
+        if buf.len() != paths.len() * 6 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len"));
+        }
+        for (v, (ip, rt)) in buf.chunks_exact_mut(6).zip(paths) {
+            v[..4].copy_from_slice(&ip.octets());
+            v[4..].copy_from_slice(&rt.to_be_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl<B: AsRef<[u8]>> fmt::Debug for RelayPathsPacket<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelayPathsPacket")
+            .field("paths", &self.paths())
+            .finish()
+    }
+}
+
+/// 连接迁移
+pub struct MigratePacket<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> MigratePacket<B> {
+    pub fn new(buffer: B) -> io::Result<MigratePacket<B>> {
+        let len = buffer.as_ref().len();
+        if len < 10 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len < 10"));
+        }
+        Ok(MigratePacket { buffer })
+    }
+    pub fn session_id(&self) -> u64 {
+        u64::from_be_bytes(self.buffer.as_ref()[..8].try_into().unwrap())
+    }
+    pub fn generation(&self) -> u16 {
+        u16::from_be_bytes(self.buffer.as_ref()[8..10].try_into().unwrap())
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> MigratePacket<B> {
+    pub fn set_session_id(&mut self, session_id: u64) {
+        self.buffer.as_mut()[..8].copy_from_slice(&session_id.to_be_bytes())
+    }
+    pub fn set_generation(&mut self, generation: u16) {
+        self.buffer.as_mut()[8..10].copy_from_slice(&generation.to_be_bytes())
+    }
+}
+
+impl<B: AsRef<[u8]>> fmt::Debug for MigratePacket<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MigratePacket")
+            .field("session_id", &self.session_id())
+            .field("generation", &self.generation())
+            .finish()
+    }
+}
+
+/// 密钥协商
+pub struct PeerKeyPacket<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> PeerKeyPacket<B> {
+    pub fn new(buffer: B) -> io::Result<PeerKeyPacket<B>> {
+        let len = buffer.as_ref().len();
+        if len < 1 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len < 1"));
+        }
+        Ok(PeerKeyPacket { buffer })
+    }
+    pub fn kind(&self) -> u8 {
+        self.buffer.as_ref()[0]
+    }
+    pub fn body(&self) -> &[u8] {
+        &self.buffer.as_ref()[1..]
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> PeerKeyPacket<B> {
+    pub fn set_kind(&mut self, kind: u8) {
+        self.buffer.as_mut()[0] = kind
+    }
+    pub fn body_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer.as_mut()[1..]
+    }
+}
+
+impl<B: AsRef<[u8]>> fmt::Debug for PeerKeyPacket<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerKeyPacket")
+            .field("kind", &self.kind())
+            .field("len", &self.body().len())
+            .finish()
+    }
+}
+
+/// 回显，对端原样带回
+pub const DIAG_ECHO: u8 = 1;
+/// 带宽测试的数据
+pub const DIAG_DATA: u8 = 2;
+/// 查询带宽测试收到的数据
+pub const DIAG_REPORT: u8 = 3;
+/// 路径质量探测，和回显一样原样带回，序号是发送时的时间
+pub const DIAG_PROBE: u8 = 4;
+/// 诊断包头部的长度
+pub const DIAG_HEAD_LEN: usize = 24;
+
+/// 诊断请求和响应
+pub struct DiagPacket<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> DiagPacket<B> {
+    pub fn new(buffer: B) -> io::Result<DiagPacket<B>> {
+        let len = buffer.as_ref().len();
+        if len < DIAG_HEAD_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len < 24"));
+        }
+        Ok(DiagPacket { buffer })
+    }
+    pub fn kind(&self) -> u8 {
+        self.buffer.as_ref()[0]
+    }
+    pub fn seq(&self) -> u16 {
+        u16::from_be_bytes(self.buffer.as_ref()[2..4].try_into().unwrap())
+    }
+    pub fn id(&self) -> u32 {
+        u32::from_be_bytes(self.buffer.as_ref()[4..8].try_into().unwrap())
+    }
+    pub fn bytes(&self) -> u64 {
+        u64::from_be_bytes(self.buffer.as_ref()[8..16].try_into().unwrap())
+    }
+    pub fn packets(&self) -> u64 {
+        u64::from_be_bytes(self.buffer.as_ref()[16..24].try_into().unwrap())
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> DiagPacket<B> {
+    pub fn set_kind(&mut self, kind: u8) {
+        self.buffer.as_mut()[0] = kind
+    }
+    pub fn set_seq(&mut self, seq: u16) {
+        self.buffer.as_mut()[2..4].copy_from_slice(&seq.to_be_bytes())
+    }
+    pub fn set_id(&mut self, id: u32) {
+        self.buffer.as_mut()[4..8].copy_from_slice(&id.to_be_bytes())
+    }
+    pub fn set_bytes(&mut self, bytes: u64) {
+        self.buffer.as_mut()[8..16].copy_from_slice(&bytes.to_be_bytes())
+    }
+    pub fn set_packets(&mut self, packets: u64) {
+        self.buffer.as_mut()[16..24].copy_from_slice(&packets.to_be_bytes())
+    }
+}
+
+impl<B: AsRef<[u8]>> fmt::Debug for DiagPacket<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiagPacket")
+            .field("kind", &self.kind())
+            .field("seq", &self.seq())
+            .field("id", &self.id())
+            .field("bytes", &self.bytes())
+            .field("packets", &self.packets())
+            .field("len", &self.buffer.as_ref().len())
+            .finish()
+    }
+}
@@ -8,6 +8,12 @@ pub enum Protocol {
     IpAlreadyExists,
     InvalidIp,
     NoKey,
+    IdentityError,
+    InviteError,
+    TotpError,
+    // 被服务端管理员踢出或封禁，负载是utf8的原因，可以为空
+    Kicked,
+    Banned,
     Other(u8),
 }
 
@@ -20,6 +26,11 @@ impl From<u8> for Protocol {
             4 => Self::IpAlreadyExists,
             5 => Self::InvalidIp,
             6 => Self::NoKey,
+            7 => Self::IdentityError,
+            8 => Self::InviteError,
+            9 => Self::TotpError,
+            10 => Self::Kicked,
+            11 => Self::Banned,
             val => Self::Other(val),
         }
     }
@@ -34,6 +45,11 @@ impl Into<u8> for Protocol {
             Protocol::IpAlreadyExists => 4,
             Protocol::InvalidIp => 5,
             Protocol::NoKey => 6,
+            Protocol::IdentityError => 7,
+            Protocol::InviteError => 8,
+            Protocol::TotpError => 9,
+            Protocol::Kicked => 10,
+            Protocol::Banned => 11,
             Protocol::Other(val) => val,
         }
     }
@@ -43,9 +59,15 @@ pub enum InErrorPacket<B> {
     TokenError,
     Disconnect,
     AddressExhausted,
-    IpAlreadyExists,
+    // 负载是占用该ip的设备，旧版本的服务端为空
+    IpAlreadyExists(ErrorPacket<B>),
     InvalidIp,
     NoKey,
+    IdentityError,
+    InviteError,
+    TotpError,
+    Kicked(ErrorPacket<B>),
+    Banned(ErrorPacket<B>),
     OtherError(ErrorPacket<B>),
 }
 
@@ -55,9 +77,16 @@ impl<B: AsRef<[u8]>> InErrorPacket<B> {
             Protocol::TokenError => Ok(InErrorPacket::TokenError),
             Protocol::Disconnect => Ok(InErrorPacket::Disconnect),
             Protocol::AddressExhausted => Ok(InErrorPacket::AddressExhausted),
-            Protocol::IpAlreadyExists => Ok(InErrorPacket::IpAlreadyExists),
+            Protocol::IpAlreadyExists => {
+                Ok(InErrorPacket::IpAlreadyExists(ErrorPacket::new(buffer)?))
+            }
             Protocol::InvalidIp => Ok(InErrorPacket::InvalidIp),
             Protocol::NoKey => Ok(InErrorPacket::NoKey),
+            Protocol::IdentityError => Ok(InErrorPacket::IdentityError),
+            Protocol::InviteError => Ok(InErrorPacket::InviteError),
+            Protocol::TotpError => Ok(InErrorPacket::TotpError),
+            Protocol::Kicked => Ok(InErrorPacket::Kicked(ErrorPacket::new(buffer)?)),
+            Protocol::Banned => Ok(InErrorPacket::Banned(ErrorPacket::new(buffer)?)),
             Protocol::Other(_) => Ok(InErrorPacket::OtherError(ErrorPacket::new(buffer)?)),
         }
     }
@@ -0,0 +1,103 @@
+use std::{fmt, io};
+
+/* 前向纠错包
+   0                                            15                                              31
+   0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1
+  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+  |                   分组(16)                   |       序号(8)          |      数据包数(8)       |
+  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+  |      冗余包数(8)      |                              分片(n)                                  |
+  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+  注：数据包的分片是原始的加密包，冗余包的分片由同一分组的数据包(2字节长度+数据+补0)计算得出，
+  数据包发出时分组还没结束，所以数据包数只有在冗余包中是准确的
+*/
+pub const FEC_HEAD_LEN: usize = 5;
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Protocol {
+    Data,
+    Parity,
+    Unknown(u8),
+}
+
+impl From<u8> for Protocol {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Protocol::Data,
+            2 => Protocol::Parity,
+            val => Protocol::Unknown(val),
+        }
+    }
+}
+
+impl Into<u8> for Protocol {
+    fn into(self) -> u8 {
+        match self {
+            Protocol::Data => 1,
+            Protocol::Parity => 2,
+            Protocol::Unknown(val) => val,
+        }
+    }
+}
+
+pub struct FecPacket<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> FecPacket<B> {
+    pub fn new(buffer: B) -> io::Result<FecPacket<B>> {
+        let len = buffer.as_ref().len();
+        if len <= FEC_HEAD_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len <= 5"));
+        }
+        Ok(FecPacket { buffer })
+    }
+}
+
+impl<B: AsRef<[u8]>> FecPacket<B> {
+    pub fn group(&self) -> u16 {
+        u16::from_be_bytes(self.buffer.as_ref()[..2].try_into().unwrap())
+    }
+    pub fn index(&self) -> u8 {
+        self.buffer.as_ref()[2]
+    }
+    pub fn data_shards(&self) -> u8 {
+        self.buffer.as_ref()[3]
+    }
+    pub fn parity_shards(&self) -> u8 {
+        self.buffer.as_ref()[4]
+    }
+    pub fn shard(&self) -> &[u8] {
+        &self.buffer.as_ref()[FEC_HEAD_LEN..]
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> FecPacket<B> {
+    pub fn set_group(&mut self, group: u16) {
+        self.buffer.as_mut()[..2].copy_from_slice(&group.to_be_bytes())
+    }
+    pub fn set_index(&mut self, index: u8) {
+        self.buffer.as_mut()[2] = index
+    }
+    pub fn set_data_shards(&mut self, data_shards: u8) {
+        self.buffer.as_mut()[3] = data_shards
+    }
+    pub fn set_parity_shards(&mut self, parity_shards: u8) {
+        self.buffer.as_mut()[4] = parity_shards
+    }
+    pub fn shard_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer.as_mut()[FEC_HEAD_LEN..]
+    }
+}
+
+impl<B: AsRef<[u8]>> fmt::Debug for FecPacket<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FecPacket")
+            .field("group", &self.group())
+            .field("index", &self.index())
+            .field("data_shards", &self.data_shards())
+            .field("parity_shards", &self.parity_shards())
+            .field("shard_len", &self.shard().len())
+            .finish()
+    }
+}
@@ -7,6 +7,9 @@ use std::net::Ipv4Addr;
 pub enum Protocol {
     Ipv4,
     WGIpv4,
+    Ipv6,
+    // 二层模式下的以太网帧
+    Ethernet,
     Ipv4Broadcast,
     Unknown(u8),
 }
@@ -16,6 +19,8 @@ impl From<u8> for Protocol {
         match value {
             4 => Protocol::Ipv4,
             5 => Protocol::WGIpv4,
+            6 => Protocol::Ipv6,
+            7 => Protocol::Ethernet,
             201 => Protocol::Ipv4Broadcast,
             val => Protocol::Unknown(val),
         }
@@ -27,6 +32,8 @@ impl From<Protocol> for u8 {
         match val {
             Protocol::Ipv4 => 4,
             Protocol::WGIpv4 => 5,
+            Protocol::Ipv6 => 6,
+            Protocol::Ethernet => 7,
             Protocol::Ipv4Broadcast => 201,
             Protocol::Unknown(val) => val,
         }
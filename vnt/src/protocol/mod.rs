@@ -14,7 +14,7 @@ use std::{fmt, io};
   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
   |                                           数据体                                              |
   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-  注：e为是否加密标志，s为服务端通信包标志，x扩展标志，u未使用
+  注：e为是否加密标志，s为服务端通信包标志，x扩展标志，u为会话密钥加密标志(客户端之间协商的密钥)
 */
 pub const HEAD_LEN: usize = 12;
 
@@ -22,8 +22,10 @@ pub mod body;
 pub mod control_packet;
 pub mod error_packet;
 pub mod extension;
+pub mod fec_packet;
 pub mod ip_turn_packet;
 pub mod other_turn_packet;
+pub mod reliable_packet;
 pub mod service_packet;
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
@@ -63,6 +65,12 @@ pub enum Protocol {
     IpTurn,
     /// 转发其他数据
     OtherTurn,
+    /// 前向纠错，数据体是一个完整的加密包或者冗余包
+    Fec,
+    /// 多个小包合并，数据体是多个 长度(16)+完整的加密包
+    Aggregate,
+    /// 可靠传输，用于客户端之间的控制数据，数据体是序号+完整的加密包
+    Reliable,
     Unknown(u8),
 }
 
@@ -74,6 +82,9 @@ impl From<u8> for Protocol {
             3 => Protocol::Control,
             4 => Protocol::IpTurn,
             5 => Protocol::OtherTurn,
+            6 => Protocol::Fec,
+            7 => Protocol::Aggregate,
+            8 => Protocol::Reliable,
             val => Protocol::Unknown(val),
         }
     }
@@ -87,6 +98,9 @@ impl Into<u8> for Protocol {
             Protocol::Control => 3,
             Protocol::IpTurn => 4,
             Protocol::OtherTurn => 5,
+            Protocol::Fec => 6,
+            Protocol::Aggregate => 7,
+            Protocol::Reliable => 8,
             Protocol::Unknown(val) => val,
         }
     }
@@ -167,6 +181,10 @@ impl<B: AsRef<[u8]>> NetPacket<B> {
     pub fn is_extension(&self) -> bool {
         self.buffer.as_ref()[0] & 0x20 == 0x20
     }
+    /// 使用会话密钥加密
+    pub fn is_session(&self) -> bool {
+        self.buffer.as_ref()[0] & 0x10 == 0x10
+    }
     pub fn version(&self) -> Version {
         Version::from(self.buffer.as_ref()[0] & 0x0F)
     }
@@ -226,6 +244,13 @@ impl<B: AsRef<[u8]> + AsMut<[u8]>> NetPacket<B> {
             self.buffer.as_mut()[0] = self.buffer.as_ref()[0] & 0xDF
         };
     }
+    pub fn set_session_flag(&mut self, is_session: bool) {
+        if is_session {
+            self.buffer.as_mut()[0] = self.buffer.as_ref()[0] | 0x10
+        } else {
+            self.buffer.as_mut()[0] = self.buffer.as_ref()[0] & 0xEF
+        };
+    }
     pub fn set_default_version(&mut self) {
         let v: u8 = Version::V2.into();
         self.buffer.as_mut()[0] = (self.buffer.as_ref()[0] & 0xF0) | (0x0F & v);
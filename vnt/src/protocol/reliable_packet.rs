@@ -0,0 +1,81 @@
+use std::{fmt, io};
+
+/* 可靠传输包
+   0                                            15                                              31
+   0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5  6  7  8  9  0  1
+  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+  |                                          序号(32)                                             |
+  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+  |                                          数据(n)                                              |
+  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+  注：数据包的数据是原始的加密包，确认包没有数据
+*/
+pub const RELIABLE_HEAD_LEN: usize = 4;
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Protocol {
+    Data,
+    Ack,
+    Unknown(u8),
+}
+
+impl From<u8> for Protocol {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Protocol::Data,
+            2 => Protocol::Ack,
+            val => Protocol::Unknown(val),
+        }
+    }
+}
+
+impl Into<u8> for Protocol {
+    fn into(self) -> u8 {
+        match self {
+            Protocol::Data => 1,
+            Protocol::Ack => 2,
+            Protocol::Unknown(val) => val,
+        }
+    }
+}
+
+pub struct ReliablePacket<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> ReliablePacket<B> {
+    pub fn new(buffer: B) -> io::Result<ReliablePacket<B>> {
+        let len = buffer.as_ref().len();
+        if len < RELIABLE_HEAD_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len < 4"));
+        }
+        Ok(ReliablePacket { buffer })
+    }
+}
+
+impl<B: AsRef<[u8]>> ReliablePacket<B> {
+    pub fn seq(&self) -> u32 {
+        u32::from_be_bytes(self.buffer.as_ref()[..4].try_into().unwrap())
+    }
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[RELIABLE_HEAD_LEN..]
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> ReliablePacket<B> {
+    pub fn set_seq(&mut self, seq: u32) {
+        self.buffer.as_mut()[..4].copy_from_slice(&seq.to_be_bytes())
+    }
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer.as_mut()[RELIABLE_HEAD_LEN..]
+    }
+}
+
+impl<B: AsRef<[u8]>> fmt::Debug for ReliablePacket<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReliablePacket")
+            .field("seq", &self.seq())
+            .field("payload_len", &self.payload().len())
+            .finish()
+    }
+}
@@ -15,6 +15,8 @@ pub enum Protocol {
     SecretHandshakeResponse,
     /// 客户端上报状态
     ClientStatusInfo,
+    /// 客户端正常退出，服务端收到后立即标记为离线并推送设备列表，没有数据
+    Leave,
     Unknown(u8),
 }
 
@@ -30,6 +32,7 @@ impl From<u8> for Protocol {
             7 => Self::SecretHandshakeRequest,
             8 => Self::SecretHandshakeResponse,
             9 => Self::ClientStatusInfo,
+            10 => Self::Leave,
             val => Self::Unknown(val),
         }
     }
@@ -47,6 +50,7 @@ impl Into<u8> for Protocol {
             Self::SecretHandshakeRequest => 7,
             Self::SecretHandshakeResponse => 8,
             Self::ClientStatusInfo => 9,
+            Self::Leave => 10,
             Self::Unknown(val) => val,
         }
     }
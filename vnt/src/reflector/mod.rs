@@ -0,0 +1,422 @@
+//! mDNS/LLMNR反射，在虚拟网络和本地局域网之间互相转发组播的服务发现和名称查询，
+//! 让打印机、AirPlay等局域网服务和windows主机名解析可以跨站点使用。
+//! 通过普通的udp socket同时加入虚拟网卡和局域网网卡上的组播，从一侧收到的报文转发到另一侧
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use crossbeam_utils::atomic::AtomicCell;
+use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+
+use crate::handle::CurrentDeviceInfo;
+use crate::util::limit::RateLimiter;
+use crate::util::StopManager;
+
+pub const DEFAULT_MDNS_RATE: u32 = 20;
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const LLMNR_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 252);
+const LLMNR_PORT: u16 = 5355;
+// 重新检查网卡和虚拟ip的间隔
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+// 这个时间内内容相同的报文只转发一次，避免多个反射器之间来回转发
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(1);
+// 等待LLMNR单播应答的时间
+const LLMNR_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_PENDING: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct ReflectConfig {
+    // 允许转发的服务类型，例如_ipp._tcp，为空时不过滤
+    pub services: Vec<String>,
+    // 每个方向每秒最多转发的报文数
+    pub rate: u32,
+}
+
+impl ReflectConfig {
+    /// services中的每一项可以是逗号分隔的多个服务类型
+    pub fn new(services: &[String], rate: u32) -> anyhow::Result<Self> {
+        if rate == 0 || rate > 1000 {
+            return Err(anyhow!("mdns rate range 1-1000"));
+        }
+        let mut list = Vec::new();
+        for x in services.iter().flat_map(|v| v.split(',')) {
+            let service = x.trim().trim_end_matches('.').to_ascii_lowercase();
+            if service.is_empty() {
+                continue;
+            }
+            let service = service
+                .strip_suffix(".local")
+                .unwrap_or(&service)
+                .to_string();
+            if service_type(&service).as_ref() != Some(&service) {
+                return Err(anyhow!("mdns service {:?} invalid,eg: _ipp._tcp", x));
+            }
+            if !list.contains(&service) {
+                list.push(service);
+            }
+        }
+        Ok(Self {
+            services: list,
+            rate,
+        })
+    }
+    /// 有服务类型的名称时至少一个在允许的列表中，只有主机名时不过滤
+    fn allow(&self, names: &[String]) -> bool {
+        if self.services.is_empty() {
+            return true;
+        }
+        let mut has_service = false;
+        for name in names {
+            if let Some(service) = service_type(name) {
+                if self.services.contains(&service) {
+                    return true;
+                }
+                has_service = true;
+            }
+        }
+        !has_service
+    }
+}
+
+/// 从名称中取出服务类型，例如 printer._ipp._tcp.local 的服务类型为 _ipp._tcp
+fn service_type(name: &str) -> Option<String> {
+    let labels: Vec<&str> = name.split('.').collect();
+    let index = labels.iter().position(|v| *v == "_tcp" || *v == "_udp")?;
+    if index == 0 || !labels[index - 1].starts_with('_') {
+        return None;
+    }
+    Some(format!("{}.{}", labels[index - 1], labels[index]))
+}
+
+/// 读取报文中的名称，返回小写的名称和名称之后的偏移
+fn read_name(buf: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    // 限制标签和指针的数量，避免恶意报文中的指针循环
+    for _ in 0..128 {
+        let len = *buf.get(offset)? as usize;
+        if len & 0xc0 == 0xc0 {
+            let pointer = (len & 0x3f) << 8 | *buf.get(offset + 1)? as usize;
+            end.get_or_insert(offset + 2);
+            offset = pointer;
+            continue;
+        }
+        if len == 0 {
+            return Some((name, end.unwrap_or(offset + 1)));
+        }
+        let label = buf.get(offset + 1..offset + 1 + len)?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(&String::from_utf8_lossy(label).to_ascii_lowercase());
+        offset += 1 + len;
+    }
+    None
+}
+
+/// 报文中问题和所有记录的名称，格式错误时返回None
+fn names(buf: &[u8]) -> Option<Vec<String>> {
+    if buf.len() < 12 {
+        return None;
+    }
+    let count = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]) as usize;
+    let questions = count(4);
+    let records = count(6) + count(8) + count(10);
+    let mut names = Vec::with_capacity(questions + records);
+    let mut offset = 12;
+    for _ in 0..questions {
+        let (name, end) = read_name(buf, offset)?;
+        names.push(name);
+        // type class
+        offset = end + 4;
+    }
+    for _ in 0..records {
+        let (name, end) = read_name(buf, offset)?;
+        names.push(name);
+        // type class ttl rdlength
+        let rdlength = u16::from_be_bytes([*buf.get(end + 8)?, *buf.get(end + 9)?]) as usize;
+        offset = end + 10 + rdlength;
+    }
+    if offset > buf.len() {
+        return None;
+    }
+    Some(names)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Kind {
+    Mdns,
+    Llmnr,
+}
+
+impl Kind {
+    fn group(&self) -> SocketAddrV4 {
+        match self {
+            Kind::Mdns => SocketAddrV4::new(MDNS_GROUP, MDNS_PORT),
+            Kind::Llmnr => SocketAddrV4::new(LLMNR_GROUP, LLMNR_PORT),
+        }
+    }
+}
+
+/// 启动反射，mDNS和LLMNR各使用一个线程
+pub fn start_reflect(
+    stop_manager: &StopManager,
+    config: &ReflectConfig,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+) -> anyhow::Result<()> {
+    for kind in [Kind::Mdns, Kind::Llmnr] {
+        let socket = bind(kind).map_err(|e| anyhow!("{:?} reflect {}", kind, e))?;
+        let mut reflector = Reflector {
+            kind,
+            socket,
+            config: config.clone(),
+            current_device: current_device.clone(),
+            virtual_ip: None,
+            lan_ips: HashSet::new(),
+            refresh_time: Instant::now(),
+            to_lan: RateLimiter::new(config.rate as usize, config.rate as usize),
+            to_virtual: RateLimiter::new(config.rate as usize, config.rate as usize),
+            recent: VecDeque::new(),
+            pending: HashMap::new(),
+        };
+        let name = format!("{:?}Reflect", kind);
+        let worker = stop_manager.add_listener(name.clone(), || {})?;
+        let stop_manager = stop_manager.clone();
+        thread::Builder::new().name(name).spawn(move || {
+            reflector.recv_loop(&stop_manager);
+            drop(worker);
+        })?;
+    }
+    log::info!("mDNS/LLMNR反射已开启 {:?}", config);
+    Ok(())
+}
+
+fn bind(kind: Kind) -> anyhow::Result<UdpSocket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::IPV4,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    // 系统自带的mDNS服务也会监听同样的端口
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    let addr: SocketAddr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, kind.group().port()).into();
+    socket.bind(&addr.into())?;
+    // 不接收自己发出的报文
+    socket.set_multicast_loop_v4(false)?;
+    match kind {
+        Kind::Mdns => socket.set_multicast_ttl_v4(255)?,
+        Kind::Llmnr => socket.set_multicast_ttl_v4(1)?,
+    }
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    Ok(socket.into())
+}
+
+/// 本机局域网网卡的ipv4地址，不包括回环地址和虚拟网段的地址
+fn lan_ips(current_device: &CurrentDeviceInfo) -> anyhow::Result<HashSet<Ipv4Addr>> {
+    let mut ips = HashSet::new();
+    for iface in NetworkInterface::show()? {
+        for addr in iface.addr {
+            if let IpAddr::V4(ip) = addr.ip() {
+                if ip.is_loopback() || ip.is_unspecified() || !current_device.not_in_network(ip) {
+                    continue;
+                }
+                ips.insert(ip);
+            }
+        }
+    }
+    Ok(ips)
+}
+
+struct Reflector {
+    kind: Kind,
+    socket: UdpSocket,
+    config: ReflectConfig,
+    current_device: Arc<AtomicCell<CurrentDeviceInfo>>,
+    // 已在虚拟网卡上加入组播的虚拟ip
+    virtual_ip: Option<Ipv4Addr>,
+    lan_ips: HashSet<Ipv4Addr>,
+    refresh_time: Instant,
+    // 两个方向分别限速
+    to_lan: RateLimiter,
+    to_virtual: RateLimiter,
+    // 最近转发过的报文摘要
+    recent: VecDeque<(u64, Instant)>,
+    // LLMNR的应答是单播，记录事务id对应的查询者
+    pending: HashMap<u16, (SocketAddr, Instant)>,
+}
+
+impl Reflector {
+    fn recv_loop(&mut self, stop_manager: &StopManager) {
+        let mut buf = [0u8; 9000];
+        self.refresh(&self.current_device.load());
+        loop {
+            if stop_manager.is_stopped() {
+                return;
+            }
+            let current_device = self.current_device.load();
+            if self.refresh_time.elapsed() >= REFRESH_INTERVAL {
+                self.refresh_time = Instant::now();
+                self.refresh(&current_device);
+            }
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, SocketAddr::V4(addr))) => {
+                    if current_device.status.offline() || self.virtual_ip.is_none() {
+                        continue;
+                    }
+                    if let Err(e) = self.handle(&buf[..len], addr, &current_device) {
+                        log::warn!("{:?} reflect {} {:?}", self.kind, addr, e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::WouldBlock && e.kind() != io::ErrorKind::TimedOut
+                    {
+                        log::warn!("{:?} reflect recv {:?}", self.kind, e);
+                    }
+                }
+            }
+        }
+    }
+    /// 虚拟ip变化或者网卡增减时重新加入组播
+    fn refresh(&mut self, current_device: &CurrentDeviceInfo) {
+        let group = *self.kind.group().ip();
+        let virtual_ip = current_device.virtual_ip;
+        if self.virtual_ip != Some(virtual_ip) {
+            if let Some(old) = self.virtual_ip.take() {
+                let _ = self.socket.leave_multicast_v4(&group, &old);
+            }
+            // 虚拟网卡可能还没有创建，下次再试
+            if !virtual_ip.is_unspecified()
+                && self.socket.join_multicast_v4(&group, &virtual_ip).is_ok()
+            {
+                self.virtual_ip = Some(virtual_ip);
+            }
+        }
+        let lan_ips = match lan_ips(current_device) {
+            Ok(lan_ips) => lan_ips,
+            Err(e) => {
+                log::warn!("{:?} reflect {:?}", self.kind, e);
+                return;
+            }
+        };
+        for ip in self.lan_ips.difference(&lan_ips) {
+            let _ = self.socket.leave_multicast_v4(&group, ip);
+        }
+        for ip in lan_ips.difference(&self.lan_ips) {
+            if let Err(e) = self.socket.join_multicast_v4(&group, ip) {
+                log::warn!("{:?} reflect join {} {:?}", self.kind, ip, e);
+            }
+        }
+        self.lan_ips = lan_ips;
+    }
+    fn handle(
+        &mut self,
+        buf: &[u8],
+        addr: SocketAddrV4,
+        current_device: &CurrentDeviceInfo,
+    ) -> io::Result<()> {
+        let ip = *addr.ip();
+        if ip == current_device.virtual_ip || self.lan_ips.contains(&ip) || buf.len() < 12 {
+            return Ok(());
+        }
+        let from_virtual = !current_device.not_in_network(ip);
+        let id = u16::from_be_bytes([buf[0], buf[1]]);
+        if self.kind == Kind::Llmnr && buf[2] & 0x80 != 0 {
+            // 单播的应答，发回给原始查询者，查询者在另一侧
+            if let Some((querier, time)) = self.pending.remove(&id) {
+                let querier_virtual = match querier.ip() {
+                    IpAddr::V4(querier) => !current_device.not_in_network(querier),
+                    IpAddr::V6(_) => false,
+                };
+                if time.elapsed() < LLMNR_TIMEOUT && querier_virtual != from_virtual {
+                    self.socket.send_to(buf, querier)?;
+                }
+            }
+            return Ok(());
+        }
+        let names = match names(buf) {
+            Some(names) => names,
+            None => return Ok(()),
+        };
+        if self.kind == Kind::Mdns && !self.config.allow(&names) {
+            return Ok(());
+        }
+        if self.duplicate(buf) {
+            return Ok(());
+        }
+        let limiter = if from_virtual {
+            &mut self.to_lan
+        } else {
+            &mut self.to_virtual
+        };
+        if !limiter.try_acquire() {
+            log::debug!("{:?} reflect rate limit {}", self.kind, addr);
+            return Ok(());
+        }
+        if self.kind == Kind::Llmnr {
+            self.pending
+                .retain(|_, (_, time)| time.elapsed() < LLMNR_TIMEOUT);
+            if self.pending.len() < MAX_PENDING {
+                self.pending.insert(id, (addr.into(), Instant::now()));
+            }
+        }
+        let group = self.kind.group();
+        if from_virtual {
+            for lan_ip in &self.lan_ips {
+                self.socket.set_multicast_if_v4(lan_ip)?;
+                if let Err(e) = self.socket.send_to(buf, group) {
+                    log::debug!("{:?} reflect send {} {:?}", self.kind, lan_ip, e);
+                }
+            }
+        } else {
+            self.socket
+                .set_multicast_if_v4(&current_device.virtual_ip)?;
+            self.socket.send_to(buf, group)?;
+        }
+        Ok(())
+    }
+    fn duplicate(&mut self, buf: &[u8]) -> bool {
+        let mut hasher = DefaultHasher::new();
+        buf.hash(&mut hasher);
+        let hash = hasher.finish();
+        while let Some((_, time)) = self.recent.front() {
+            if time.elapsed() < DUPLICATE_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        if self.recent.iter().any(|(v, _)| *v == hash) {
+            return true;
+        }
+        self.recent.push_back((hash, Instant::now()));
+        false
+    }
+}
+
+#[test]
+fn reflect_filter_test() {
+    let config = ReflectConfig::new(&["_ipp._tcp,_airplay._tcp.local.".into()], 20).unwrap();
+    assert_eq!(config.services, vec!["_ipp._tcp", "_airplay._tcp"]);
+    assert!(ReflectConfig::new(&["ipp".into()], 20).is_err());
+    assert!(ReflectConfig::new(&[], 0).is_err());
+    // 问题 _ipp._tcp.local PTR，回答使用压缩指针指向问题的名称
+    let mut buf = vec![0, 0, 0x84, 0, 0, 1, 0, 1, 0, 0, 0, 0];
+    buf.extend_from_slice(b"\x04_ipp\x04_tcp\x05local\x00");
+    buf.extend_from_slice(&[0, 12, 0, 1]);
+    buf.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1, 0, 0, 0, 120, 0, 2, 0xc0, 12]);
+    let list = names(&buf).unwrap();
+    assert_eq!(list, vec!["_ipp._tcp.local", "_ipp._tcp.local"]);
+    assert!(config.allow(&list));
+    assert!(!config.allow(&["tv._googlecast._tcp.local".to_string()]));
+    assert!(config.allow(&["printer.local".to_string()]));
+    assert!(names(&buf[..buf.len() - 1]).is_none());
+}
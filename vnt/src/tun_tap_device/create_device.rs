@@ -7,15 +7,18 @@ use tun::Device;
 
 #[cfg(any(target_os = "windows", target_os = "linux"))]
 const DEFAULT_TUN_NAME: &str = "vnt-tun";
-#[cfg(target_os = "windows")]
+#[cfg(any(target_os = "windows", target_os = "linux"))]
 const DEFAULT_TAP_NAME: &str = "vnt-tap";
 
-pub fn create_device(config: DeviceConfig) -> Result<Arc<Device>, ErrorInfo> {
+/// 返回创建的网卡和添加成功的路由，退出时删除这些路由
+pub fn create_device(
+    config: DeviceConfig,
+) -> Result<(Arc<Device>, Vec<(Ipv4Addr, Ipv4Addr)>), ErrorInfo> {
     let device = match create_device0(&config) {
         Ok(device) => device,
         Err(e) => {
             return Err(ErrorInfo::new_msg(
-                ErrorType::Unknown,
+                ErrorType::DeviceError,
                 format!("create device {:?}", e),
             ));
         }
@@ -27,72 +30,110 @@ pub fn create_device(config: DeviceConfig) -> Result<Arc<Device>, ErrorInfo> {
             format!("set_ip {:?}", e),
         ));
     }
+    if let Err(e) = device.set_ipv6(config.virtual_ipv6, config.virtual_ipv6_prefix) {
+        log::warn!("设置ipv6地址失败 ={:?}", e);
+    }
+    let mut routes = Vec::new();
     if let Err(e) = device.add_route(config.virtual_network, config.virtual_netmask, 1) {
         log::warn!("添加默认路由失败 ={:?}", e);
+    } else {
+        routes.push((config.virtual_network, config.virtual_netmask));
     }
     if let Err(e) = device.add_route(Ipv4Addr::BROADCAST, Ipv4Addr::BROADCAST, 1) {
         log::warn!("添加广播路由失败 ={:?}", e);
+    } else {
+        routes.push((Ipv4Addr::BROADCAST, Ipv4Addr::BROADCAST));
     }
 
-    if let Err(e) = device.add_route(
+    let multicast = (
         Ipv4Addr::from([224, 0, 0, 0]),
         Ipv4Addr::from([240, 0, 0, 0]),
-        1,
-    ) {
+    );
+    if let Err(e) = device.add_route(multicast.0, multicast.1, 1) {
         log::warn!("添加组播路由失败 ={:?}", e);
+    } else {
+        routes.push(multicast);
     }
 
     for (dest, mask) in config.external_route {
         if let Err(e) = device.add_route(dest, mask, 1) {
             log::warn!("添加路由失败 ={:?}", e);
+        } else {
+            routes.push((dest, mask));
         }
     }
-    Ok(device)
+    Ok((device, routes))
 }
 
 fn create_device0(config: &DeviceConfig) -> io::Result<Arc<Device>> {
     #[cfg(target_os = "windows")]
-    let default_name: &str = if config.tap {
+    let default_name: &str = if config.tap || config.layer2 {
         DEFAULT_TAP_NAME
     } else {
         DEFAULT_TUN_NAME
     };
     #[cfg(target_os = "linux")]
     let device = {
+        let default_name: &str = if config.layer2 {
+            DEFAULT_TAP_NAME
+        } else {
+            DEFAULT_TUN_NAME
+        };
         let device_name = config
             .device_name
             .clone()
-            .unwrap_or(DEFAULT_TUN_NAME.to_string());
-        if &device_name == DEFAULT_TUN_NAME {
-            delete_device(DEFAULT_TUN_NAME);
+            .unwrap_or(default_name.to_string());
+        if &device_name == default_name {
+            delete_device(default_name);
         }
-        Arc::new(Device::new(Some(device_name))?)
+        Arc::new(Device::new(Some(device_name), config.layer2)?)
     };
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
     let device = Arc::new(Device::new(config.device_name.clone())?);
     #[cfg(target_os = "windows")]
-    let device = Arc::new(Device::new(
+    let device = match Device::new(
         config
             .device_name
             .clone()
             .unwrap_or(default_name.to_string()),
         config.tap,
-    )?);
+        config.layer2,
+        config.wintun_path.as_deref(),
+    ) {
+        Ok(device) => Arc::new(device),
+        Err(e) if !config.tap && !config.layer2 => Arc::new(tap_fallback(config, e)?),
+        Err(e) => return Err(e),
+    };
     device.set_mtu(config.mtu)?;
     Ok(device)
 }
 
+/// wintun无法使用时(缺少dll、驱动策略限制等)，尝试使用已经创建好的tap-windows6网卡，
+/// 网卡名称为'--nic'指定的名称或者vnt-tap，都失败时返回wintun的错误
+#[cfg(target_os = "windows")]
+fn tap_fallback(config: &DeviceConfig, tun_error: io::Error) -> io::Result<Device> {
+    let name = config
+        .device_name
+        .clone()
+        .unwrap_or(DEFAULT_TAP_NAME.to_string());
+    match Device::new(name.clone(), true, false, None) {
+        Ok(device) => {
+            log::warn!("wintun不可用,使用tap网卡{} {:?}", name, tun_error);
+            Ok(device)
+        }
+        Err(e) => {
+            log::warn!("tap网卡{}不可用 {:?}", name, e);
+            Err(tun_error)
+        }
+    }
+}
+
 #[cfg(target_os = "linux")]
 fn delete_device(name: &str) {
     // 删除默认网卡，此操作有风险，后续可能去除
-    use std::process::Command;
-    let cmd = format!("ip link delete {}", name);
-    let delete_tun = Command::new("sh")
-        .arg("-c")
-        .arg(&cmd)
-        .output()
-        .expect("sh exec error!");
-    if !delete_tun.status.success() {
-        log::warn!("删除网卡失败:{:?}", delete_tun);
+    if let Err(e) = Device::delete(name) {
+        if e.raw_os_error() != Some(libc::ENODEV) {
+            log::warn!("删除网卡失败:{:?}", e);
+        }
     }
 }
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::io;
 use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use crossbeam_utils::atomic::AtomicCell;
@@ -18,7 +19,7 @@ use crate::handle::{CurrentDeviceInfo, PeerDeviceInfo};
 #[cfg(feature = "ip_proxy")]
 use crate::ip_proxy::IpProxyMap;
 use crate::tun_tap_device::vnt_device::DeviceWrite;
-use crate::util::StopManager;
+use crate::util::{HostFirewall, StopManager};
 
 #[repr(transparent)]
 #[derive(Clone, Default)]
@@ -57,6 +58,12 @@ pub struct TunDeviceHelper {
     inner: Arc<Mutex<TunDeviceHelperInner>>,
     device_adapter: DeviceAdapter,
     device_stop: Arc<Mutex<Option<DeviceStop>>>,
+    // 下次注册成功时即使地址没变也重建网卡
+    recreate: Arc<AtomicBool>,
+    // 当前网卡上添加的路由
+    routes: Arc<Mutex<Vec<(Ipv4Addr, Ipv4Addr)>>>,
+    // 在系统防火墙中添加的规则
+    host_firewall: Arc<Option<HostFirewall>>,
 }
 
 #[derive(Clone)]
@@ -85,6 +92,7 @@ impl TunDeviceHelper {
         device_map: Arc<Mutex<(u16, HashMap<Ipv4Addr, PeerDeviceInfo>)>>,
         compressor: Compressor,
         device_adapter: DeviceAdapter,
+        host_firewall: Option<HostFirewall>,
     ) -> Self {
         let inner = TunDeviceHelperInner {
             stop_manager,
@@ -102,25 +110,106 @@ impl TunDeviceHelper {
             inner: Arc::new(Mutex::new(inner)),
             device_adapter,
             device_stop: Default::default(),
+            recreate: Default::default(),
+            routes: Default::default(),
+            host_firewall: Arc::new(host_firewall),
         }
     }
     pub fn stop(&self) {
         //先停止旧的，再启动新的，改变旧网卡的IP太麻烦
         if let Some(device_stop) = self.device_stop.lock().take() {
             self.device_adapter.remove();
-            loop {
+            // 卡住的读取循环可能一直停不下来，等待一段时间后放弃
+            for _ in 0..20 {
                 device_stop.stop();
                 std::thread::sleep(std::time::Duration::from_millis(300));
                 //确保停止了
                 if device_stop.is_stopped() {
-                    break;
+                    return;
                 }
             }
+            log::warn!("tun读取循环没有停止");
         }
     }
-    /// 要保证先stop 再start
-    pub fn start(&self, device: Arc<Device>, allow_wire_guard: bool) -> io::Result<()> {
+    /// 退出时使用，停止读取循环(会先发出网卡中已有的包)，再删除网卡上添加的路由和系统防火墙规则
+    pub fn shutdown(&self) {
+        let device = self.device_adapter.tun.lock().clone();
+        self.stop();
+        if let Some(host_firewall) = self.host_firewall.as_ref() {
+            host_firewall.clear();
+        }
+        let device = match device {
+            Some(device) => device,
+            None => return,
+        };
+        let mut routes: Vec<(Ipv4Addr, Ipv4Addr)> = self.routes.lock().drain(..).collect();
+        // 域名分流添加的主机路由不在routes中
+        for route in self.inner.lock().ip_route.resolved_routes() {
+            if !routes.contains(&route) {
+                routes.push(route);
+            }
+        }
+        for (dest, mask) in routes {
+            if let Err(e) = device.delete_route(dest, mask) {
+                log::warn!("删除路由失败 {}/{} {:?}", dest, mask, e);
+            }
+        }
+    }
+    /// 其他客户端宣告的网段变化时增减网卡上的路由，没有网卡时在创建网卡时添加
+    #[cfg(not(target_os = "android"))]
+    pub fn update_routes(&self, removed: &[(Ipv4Addr, Ipv4Addr)], added: &[(Ipv4Addr, Ipv4Addr)]) {
+        let device = match self.device_adapter.tun.lock().clone() {
+            Some(device) => device,
+            None => return,
+        };
+        let mut routes = self.routes.lock();
+        for (dest, mask) in removed {
+            if let Some(index) = routes.iter().position(|v| v == &(*dest, *mask)) {
+                routes.remove(index);
+                if let Err(e) = device.delete_route(*dest, *mask) {
+                    log::warn!("删除路由失败 {}/{} {:?}", dest, mask, e);
+                }
+            }
+        }
+        for (dest, mask) in added {
+            if routes.contains(&(*dest, *mask)) {
+                continue;
+            }
+            match device.add_route(*dest, *mask, 1) {
+                Ok(_) => routes.push((*dest, *mask)),
+                Err(e) => log::warn!("添加路由失败 {}/{} {:?}", dest, mask, e),
+            }
+        }
+    }
+    /// 请求在下次注册成功时重建网卡，用于网卡卡住时恢复
+    pub fn request_recreate(&self) {
+        self.recreate.store(true, Ordering::Release);
+    }
+    pub fn take_recreate(&self) -> bool {
+        self.recreate.swap(false, Ordering::AcqRel)
+    }
+    /// 距离读取循环上次运行的时间，没有网卡时为None
+    #[cfg(unix)]
+    pub fn device_idle(&self) -> Option<std::time::Duration> {
+        self.device_stop.lock().as_ref()?.idle()
+    }
+    /// 要保证先stop 再start，routes是网卡上添加的路由
+    pub fn start(
+        &self,
+        device: Arc<Device>,
+        routes: Vec<(Ipv4Addr, Ipv4Addr)>,
+        allow_wire_guard: bool,
+    ) -> io::Result<()> {
         self.device_adapter.insert(device.clone());
+        *self.routes.lock() = routes;
+        if let Some(host_firewall) = self.host_firewall.as_ref() {
+            let current = self.inner.lock().current_device.load();
+            host_firewall.allow_device(
+                &device.name().unwrap_or_default(),
+                current.virtual_network,
+                current.virtual_netmask,
+            );
+        }
         let device_stop = DeviceStop::default();
         let s = self.device_stop.lock().replace(device_stop.clone());
         assert!(s.is_none());
@@ -1,19 +1,147 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{io, thread};
 
 use anyhow::Context;
 use dns_parser::{Builder, Packet, QueryClass, QueryType, RData, ResponseCode};
+use parking_lot::Mutex;
+use rand::Rng;
+
+use crate::channel::ConnectProtocol;
+use crate::obfs::{Obfs, Obfuscator};
+use crate::util::nat64_synthesize;
+use crate::util::system_dns::{hosts_lookup, system_name_servers};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DnsMode {
+    /// 普通udp dns，https://开头的地址使用DoH，tls://开头的地址使用DoT
+    Udp,
+    /// 仅使用DoH
+    Doh,
+    /// 仅使用DoT
+    Dot,
+    /// 使用系统解析(getaddrinfo)，忽略指定的dns服务器
+    System,
+}
+
+impl DnsMode {
+    /// 按模式整理dns服务器列表
+    pub fn name_servers(&self, name_servers: Vec<String>) -> Vec<String> {
+        match self {
+            DnsMode::Udp => name_servers,
+            DnsMode::System => vec![],
+            DnsMode::Doh => {
+                let name_servers: Vec<String> = name_servers
+                    .into_iter()
+                    .filter(|v| is_doh(v))
+                    .collect();
+                if name_servers.is_empty() {
+                    vec![
+                        "https://223.5.5.5/dns-query".into(),
+                        "https://1.1.1.1/dns-query".into(),
+                    ]
+                } else {
+                    name_servers
+                }
+            }
+            DnsMode::Dot => {
+                let name_servers: Vec<String> = name_servers
+                    .into_iter()
+                    .filter(|v| is_dot(v))
+                    .collect();
+                if name_servers.is_empty() {
+                    vec!["tls://223.5.5.5".into(), "tls://1.1.1.1".into()]
+                } else {
+                    name_servers
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for DnsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim() {
+            "udp" => Ok(DnsMode::Udp),
+            "doh" => Ok(DnsMode::Doh),
+            "dot" => Ok(DnsMode::Dot),
+            "system" => Ok(DnsMode::System),
+            _ => Err(format!("not match '{}', enum: udp/doh/dot/system", s)),
+        }
+    }
+}
+
+impl Default for DnsMode {
+    fn default() -> Self {
+        DnsMode::Udp
+    }
+}
+
+pub fn is_doh(name_server: &str) -> bool {
+    name_server.to_lowercase().starts_with("https://")
+}
+
+pub fn is_dot(name_server: &str) -> bool {
+    name_server.to_lowercase().starts_with("tls://")
+}
 
 thread_local! {
     static HISTORY: RefCell<HashMap<SocketAddr,usize>> = RefCell::new(HashMap::new());
 }
 
+/// 服务端地址的ip类型偏好
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AddressPrefer {
+    Any,
+    V4,
+    V6,
+}
+
+impl AddressPrefer {
+    fn is_prefer(&self, addr: &SocketAddr) -> bool {
+        match self {
+            AddressPrefer::Any => true,
+            AddressPrefer::V4 => addr.is_ipv4(),
+            AddressPrefer::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+impl FromStr for AddressPrefer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim() {
+            "any" => Ok(AddressPrefer::Any),
+            "v4" | "ipv4" => Ok(AddressPrefer::V4),
+            "v6" | "ipv6" => Ok(AddressPrefer::V6),
+            _ => Err(format!("not match '{}', enum: any/v4/v6", s)),
+        }
+    }
+}
+
+impl Default for AddressPrefer {
+    fn default() -> Self {
+        AddressPrefer::Any
+    }
+}
+
 /// 保留一个地址使用记录，使用过的地址后续不再选中，直到地址全使用过
-pub fn address_choose(addrs: Vec<SocketAddr>) -> anyhow::Result<SocketAddr> {
+pub fn address_choose(
+    addrs: Vec<SocketAddr>,
+    prefer: AddressPrefer,
+    protocol: ConnectProtocol,
+    obfs: &Obfs,
+) -> anyhow::Result<SocketAddr> {
+    // 只有ipv6网络时通过NAT64访问ipv4服务端
+    let addrs = nat64_synthesize(addrs);
     HISTORY.with(|history| {
         let mut available = Vec::new();
         for x in &addrs {
@@ -26,7 +154,7 @@ pub fn address_choose(addrs: Vec<SocketAddr>) -> anyhow::Result<SocketAddr> {
             available = addrs;
             history.borrow_mut().clear();
         }
-        let addr = address_choose0(available)?;
+        let addr = address_choose0(available, prefer, protocol, obfs)?;
         history
             .borrow_mut()
             .entry(addr)
@@ -38,9 +166,27 @@ pub fn address_choose(addrs: Vec<SocketAddr>) -> anyhow::Result<SocketAddr> {
     })
 }
 
-/// 后续实现选择延迟最低的可用地址，需要服务端配合
-/// 现在是选择第一个地址，优先ipv6
-fn address_choose0(addrs: Vec<SocketAddr>) -> anyhow::Result<SocketAddr> {
+/// 所有地址同时探测(偏好的ip类型先发起)，在响应的地址中选择延迟最低的，偏好的ip类型优先
+/// 都探测失败时选择第一个可用地址
+fn address_choose0(
+    addrs: Vec<SocketAddr>,
+    prefer: AddressPrefer,
+    protocol: ConnectProtocol,
+    obfs: &Obfs,
+) -> anyhow::Result<SocketAddr> {
+    // quic服务端不响应普通握手包，不做探测
+    if addrs.len() > 1 && !protocol.is_quic() {
+        let latency = happy_eyeballs(&addrs, prefer, protocol, obfs);
+        log::info!("服务端地址延迟 {:?}", latency);
+        if let Some(addr) = latency_choose(latency, prefer) {
+            return Ok(addr);
+        }
+    }
+    address_choose1(addrs, prefer)
+}
+
+/// 选择第一个地址，默认优先ipv6
+fn address_choose1(addrs: Vec<SocketAddr>, prefer: AddressPrefer) -> anyhow::Result<SocketAddr> {
     let v4: Vec<SocketAddr> = addrs.iter().filter(|v| v.is_ipv4()).copied().collect();
     let v6: Vec<SocketAddr> = addrs.iter().filter(|v| v.is_ipv6()).copied().collect();
     let check_addr = |addrs: &Vec<SocketAddr>| -> anyhow::Result<SocketAddr> {
@@ -67,45 +213,288 @@ fn address_choose0(addrs: Vec<SocketAddr>) -> anyhow::Result<SocketAddr> {
     if v4.is_empty() {
         return check_addr(&v6);
     }
-    match check_addr(&v6) {
+    let (first, second) = if prefer == AddressPrefer::V4 {
+        (&v4, &v6)
+    } else {
+        (&v6, &v4)
+    };
+    match check_addr(first) {
         Ok(addr) => Ok(addr),
-        Err(e1) => match check_addr(&v4) {
+        Err(e1) => match check_addr(second) {
             Ok(addr) => Ok(addr),
             Err(e2) => Err(anyhow::anyhow!("{} , {}", e1, e2)),
         },
     }
 }
 
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// 另一种ip类型延迟发起探测的时间
+const RESOLUTION_DELAY: Duration = Duration::from_millis(250);
+
+/// RFC8305，偏好的ip类型(默认ipv6)先发起探测，另一类型延迟250ms发起，
+/// 收到第一个响应后再等待250ms，返回这段时间内响应的地址和延迟
+/// udp使用握手请求(和数据通道一样做混淆)，tcp使用建立连接的耗时
+fn happy_eyeballs(
+    addrs: &[SocketAddr],
+    prefer: AddressPrefer,
+    protocol: ConnectProtocol,
+    obfs: &Obfs,
+) -> Vec<(SocketAddr, Duration)> {
+    let packet = if protocol.is_udp() {
+        match crate::handle::handshaker::probe_packet() {
+            Ok(packet) => {
+                let mut out = Vec::new();
+                obfs.encode(packet.buffer(), &mut out);
+                out
+            }
+            Err(e) => {
+                log::warn!("probe_packet {:?}", e);
+                return vec![];
+            }
+        }
+    } else {
+        vec![]
+    };
+    let first_v4 = if prefer == AddressPrefer::V4 {
+        addrs.iter().any(|v| v.is_ipv4())
+    } else {
+        !addrs.iter().any(|v| v.is_ipv6())
+    };
+    let packet = Arc::new(packet);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for addr in addrs.iter().copied().take(16) {
+        let sender = sender.clone();
+        let packet = packet.clone();
+        let delay = addr.is_ipv4() != first_v4;
+        let rs = thread::Builder::new()
+            .name("probeLatency".into())
+            .spawn(move || {
+                if delay {
+                    thread::sleep(RESOLUTION_DELAY);
+                }
+                let rs = if protocol.is_udp() {
+                    udp_latency(addr, &packet)
+                } else {
+                    tcp_latency(addr)
+                };
+                match rs {
+                    Ok(rtt) => {
+                        let _ = sender.send((addr, rtt));
+                    }
+                    Err(e) => {
+                        log::warn!("探测延迟失败 {} {:?}", addr, e);
+                    }
+                }
+            });
+        if let Err(e) = rs {
+            log::warn!("{:?}", e);
+        }
+    }
+    drop(sender);
+    collect_latency(&receiver, RESOLUTION_DELAY)
+}
+
+/// 等待第一个响应，之后在window内继续收集，全部探测结束时提前返回
+fn collect_latency(
+    receiver: &Receiver<(SocketAddr, Duration)>,
+    window: Duration,
+) -> Vec<(SocketAddr, Duration)> {
+    let mut list = Vec::new();
+    // 全部失败时所有sender都已释放，recv返回错误
+    match receiver.recv() {
+        Ok(v) => list.push(v),
+        Err(_) => return list,
+    }
+    let deadline = Instant::now() + window;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(timeout) {
+            Ok(v) => list.push(v),
+            Err(_) => return list,
+        }
+    }
+}
+
+/// 选择延迟最低的地址，有偏好的ip类型响应时只在这一类型中选择
+fn latency_choose(
+    mut latency: Vec<(SocketAddr, Duration)>,
+    prefer: AddressPrefer,
+) -> Option<SocketAddr> {
+    latency.sort_by_key(|(_, rtt)| *rtt);
+    latency
+        .iter()
+        .find(|(addr, _)| prefer.is_prefer(addr))
+        .or(latency.first())
+        .map(|(addr, _)| *addr)
+}
+
+fn udp_latency(addr: SocketAddr, packet: &[u8]) -> io::Result<Duration> {
+    let udp = if addr.is_ipv4() {
+        UdpSocket::bind("0.0.0.0:0")?
+    } else {
+        UdpSocket::bind("[::]:0")?
+    };
+    udp.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    udp.connect(addr)?;
+    let mut buf = [0; 65536];
+    let mut count = 0;
+    loop {
+        let start = Instant::now();
+        udp.send(packet)?;
+        match udp.recv(&mut buf) {
+            Ok(_) => return Ok(start.elapsed()),
+            Err(e) => {
+                count += 1;
+                if count < 2
+                    && (e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::WouldBlock)
+                {
+                    continue;
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+fn tcp_latency(addr: SocketAddr) -> io::Result<Duration> {
+    let start = Instant::now();
+    let _tcp = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT * 2)?;
+    Ok(start.elapsed())
+}
+
+/// 解析结果最长缓存时间(秒)
+const CACHE_MAX_TTL: u32 = 600;
+/// 无法获取ttl时(hosts文件、系统解析)的缓存时间(秒)
+const CACHE_DEFAULT_TTL: u32 = 60;
+/// 解析失败的缓存时间，避免网络波动时频繁重连导致大量dns请求
+const CACHE_NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
+struct CacheItem {
+    expire: Instant,
+    rs: Result<Vec<SocketAddr>, String>,
+}
+
+static DNS_CACHE: Mutex<Option<HashMap<String, CacheItem>>> = parking_lot::const_mutex(None);
+
+fn cache_get(key: &str) -> Option<anyhow::Result<Vec<SocketAddr>>> {
+    let guard = DNS_CACHE.lock();
+    let item = guard.as_ref()?.get(key)?;
+    if item.expire <= Instant::now() {
+        return None;
+    }
+    Some(match &item.rs {
+        Ok(addrs) => Ok(addrs.clone()),
+        Err(e) => Err(anyhow::anyhow!("{}", e)),
+    })
+}
+
+fn cache_put(key: String, rs: &anyhow::Result<(Vec<SocketAddr>, u32)>) {
+    let now = Instant::now();
+    let item = match rs {
+        Ok((addrs, ttl)) => {
+            let ttl = (*ttl).min(CACHE_MAX_TTL);
+            if ttl == 0 {
+                return;
+            }
+            CacheItem {
+                expire: now + Duration::from_secs(ttl as u64),
+                rs: Ok(addrs.clone()),
+            }
+        }
+        Err(e) => CacheItem {
+            expire: now + CACHE_NEGATIVE_TTL,
+            rs: Err(format!("{}", e)),
+        },
+    };
+    let mut guard = DNS_CACHE.lock();
+    let cache = guard.get_or_insert_with(HashMap::new);
+    cache.retain(|_, v| v.expire > now);
+    cache.insert(key, item);
+}
+
+/// 解析服务端地址，结果按记录的ttl缓存，解析失败也会缓存一小段时间
 pub fn dns_query_all(
     domain: &str,
-    mut name_servers: Vec<String>,
+    name_servers: Vec<String>,
+    dns_mode: DnsMode,
 ) -> anyhow::Result<Vec<SocketAddr>> {
+    if let Ok(addr) = SocketAddr::from_str(domain) {
+        return Ok(vec![addr]);
+    }
+    let key = domain.to_lowercase();
+    if let Some(rs) = cache_get(&key) {
+        return rs;
+    }
+    let rs = dns_query_all0(domain, name_servers, dns_mode);
+    cache_put(key, &rs);
+    rs.map(|(addrs, _)| addrs)
+}
+
+/// 返回地址和ttl
+fn dns_query_all0(
+    domain: &str,
+    mut name_servers: Vec<String>,
+    dns_mode: DnsMode,
+) -> anyhow::Result<(Vec<SocketAddr>, u32)> {
     match SocketAddr::from_str(domain) {
-        Ok(addr) => Ok(vec![addr]),
+        Ok(addr) => Ok((vec![addr], CACHE_MAX_TTL)),
         Err(_) => {
             let txt_domain = domain
                 .to_lowercase()
                 .strip_prefix("txt:")
                 .map(|v| v.to_string());
+            let srv_domain = domain
+                .to_lowercase()
+                .strip_prefix("srv:")
+                .map(|v| v.to_string());
             if name_servers.is_empty() {
-                if txt_domain.is_some() {
-                    name_servers.push("223.5.5.5:53".into());
-                    name_servers.push("114.114.114.114:53".into());
+                if txt_domain.is_some() || srv_domain.is_some() {
+                    // 优先使用系统配置的dns，system模式不使用内置的dns
+                    name_servers = system_name_servers();
+                    if dns_mode != DnsMode::System {
+                        name_servers.push("223.5.5.5:53".into());
+                        name_servers.push("114.114.114.114:53".into());
+                    } else if name_servers.is_empty() {
+                        return Err(anyhow::anyhow!("no system name server {:?}", domain));
+                    }
                 } else {
-                    return Ok(domain
+                    let addrs = domain
                         .to_socket_addrs()
                         .with_context(|| format!("DNS query failed {:?}", domain))?
-                        .collect());
+                        .collect();
+                    return Ok((addrs, CACHE_DEFAULT_TTL));
                 }
             }
+            // 普通域名先查找hosts文件
+            let host_port = if txt_domain.is_none() && srv_domain.is_none() {
+                let end_index = domain
+                    .rfind(':')
+                    .with_context(|| format!("{:?} not port", domain))?;
+                let host = &domain[..end_index];
+                let port = u16::from_str(&domain[end_index + 1..])
+                    .with_context(|| format!("{:?} not port", domain))?;
+                let hosts = hosts_lookup(host);
+                if !hosts.is_empty() {
+                    let addrs = hosts
+                        .into_iter()
+                        .map(|ip| SocketAddr::new(ip, port))
+                        .collect();
+                    return Ok((addrs, CACHE_DEFAULT_TTL));
+                }
+                Some((host, port))
+            } else {
+                None
+            };
 
             let mut err: Option<anyhow::Error> = None;
             for name_server in name_servers {
                 if let Some(domain) = txt_domain.as_ref() {
                     match txt_dns(domain, name_server) {
-                        Ok(addr) => {
+                        Ok((addr, ttl)) => {
                             if !addr.is_empty() {
-                                return Ok(addr);
+                                return Ok((addr, ttl));
                             }
                         }
                         Err(e) => {
@@ -118,12 +507,27 @@ pub fn dns_query_all(
                     }
                     continue;
                 }
-                let end_index = domain
-                    .rfind(':')
-                    .with_context(|| format!("{:?} not port", domain))?;
-                let host = &domain[..end_index];
-                let port = u16::from_str(&domain[end_index + 1..])
-                    .with_context(|| format!("{:?} not port", domain))?;
+                if let Some(domain) = srv_domain.as_ref() {
+                    match srv_dns(domain, name_server) {
+                        Ok((addr, ttl)) => {
+                            if !addr.is_empty() {
+                                return Ok((addr, ttl));
+                            }
+                        }
+                        Err(e) => {
+                            if let Some(err) = &mut err {
+                                *err = anyhow::anyhow!("{} {}", err, e);
+                            } else {
+                                err.replace(anyhow::anyhow!("{}", e));
+                            }
+                        }
+                    }
+                    continue;
+                }
+                let (host, port) = match host_port {
+                    Some(host_port) => host_port,
+                    None => continue,
+                };
                 let th1 = {
                     let host = host.to_string();
                     let name_server = name_server.clone();
@@ -135,8 +539,10 @@ pub fn dns_query_all(
                     thread::spawn(move || aaaa_dns(host, name_server))
                 };
                 let mut addr = Vec::new();
+                let mut min_ttl = u32::MAX;
                 match th1.join().unwrap() {
-                    Ok(rs) => {
+                    Ok((rs, ttl)) => {
+                        min_ttl = min_ttl.min(ttl);
                         for ip in rs {
                             addr.push(SocketAddr::new(ip.into(), port));
                         }
@@ -146,7 +552,8 @@ pub fn dns_query_all(
                     }
                 }
                 match th2.join().unwrap() {
-                    Ok(rs) => {
+                    Ok((rs, ttl)) => {
+                        min_ttl = min_ttl.min(ttl);
                         for ip in rs {
                             addr.push(SocketAddr::new(ip.into(), port));
                         }
@@ -165,7 +572,7 @@ pub fn dns_query_all(
                 if addr.is_empty() {
                     continue;
                 }
-                return Ok(addr);
+                return Ok((addr, min_ttl));
             }
             if let Some(e) = err {
                 Err(e)
@@ -176,6 +583,73 @@ pub fn dns_query_all(
     }
 }
 
+/// 根据dns服务器地址选择查询方式
+fn query_by<'a>(
+    domain: &str,
+    name_server: &str,
+    record_type: QueryType,
+    buf: &'a mut [u8],
+) -> anyhow::Result<Packet<'a>> {
+    if is_doh(name_server) {
+        return doh_query(domain, name_server, record_type, buf);
+    }
+    if is_dot(name_server) {
+        return dot_query(domain, name_server, record_type, buf);
+    }
+    let name_server: SocketAddr = name_server.parse()?;
+    let udp = bind_udp(name_server)?;
+    query(&udp, domain, name_server, record_type, buf)
+}
+
+#[cfg(feature = "doh")]
+fn doh_query<'a>(
+    domain: &str,
+    url: &str,
+    record_type: QueryType,
+    buf: &'a mut [u8],
+) -> anyhow::Result<Packet<'a>> {
+    // DoH要求id为0，便于缓存
+    let mut builder = Builder::new_query(0, true);
+    builder.add_question(domain, false, record_type, QueryClass::IN);
+    let packet = builder.build().unwrap();
+    let len = crate::util::secure_dns::doh_query(url, &packet, buf)?;
+    check_packet(&buf[..len], domain, url)
+}
+
+#[cfg(not(feature = "doh"))]
+fn doh_query<'a>(
+    _domain: &str,
+    url: &str,
+    _record_type: QueryType,
+    _buf: &'a mut [u8],
+) -> anyhow::Result<Packet<'a>> {
+    Err(anyhow::anyhow!("DoH not supported {:?}", url))
+}
+
+#[cfg(feature = "dot")]
+fn dot_query<'a>(
+    domain: &str,
+    url: &str,
+    record_type: QueryType,
+    buf: &'a mut [u8],
+) -> anyhow::Result<Packet<'a>> {
+    let mut builder = Builder::new_query(1, true);
+    builder.add_question(domain, false, record_type, QueryClass::IN);
+    let packet = builder.build().unwrap();
+    let len = crate::util::secure_dns::dot_query(url, &packet, buf)?;
+    check_packet(&buf[..len], domain, url)
+}
+
+#[cfg(not(feature = "dot"))]
+fn dot_query<'a>(
+    _domain: &str,
+    url: &str,
+    _record_type: QueryType,
+    _buf: &'a mut [u8],
+) -> anyhow::Result<Packet<'a>> {
+    Err(anyhow::anyhow!("DoT not supported {:?}", url))
+}
+
 fn query<'a>(
     udp: &UdpSocket,
     domain: &str,
@@ -209,7 +683,15 @@ fn query<'a>(
         };
     };
 
-    let pkt = Packet::parse(&buf[..len])
+    check_packet(&buf[..len], domain, name_server)
+}
+
+fn check_packet<'a, N: std::fmt::Debug>(
+    buf: &'a [u8],
+    domain: &str,
+    name_server: N,
+) -> anyhow::Result<Packet<'a>> {
+    let pkt = Packet::parse(buf)
         .with_context(|| format!("domain {:?} DNS {:?} data error ", domain, name_server))?;
     if pkt.header.response_code != ResponseCode::NoError {
         return Err(anyhow::anyhow!(
@@ -230,23 +712,151 @@ fn query<'a>(
     Ok(pkt)
 }
 
-pub fn txt_dns(domain: &str, name_server: String) -> anyhow::Result<Vec<SocketAddr>> {
-    let name_server: SocketAddr = name_server.parse()?;
-    let udp = bind_udp(name_server)?;
+/// 查询TXT记录，一条记录可以包含多个地址，使用空白或','分隔，'#'之后为注释
+/// 每个地址可以带权重，例如 "1.1.1.1:29872;weight=10 [::1]:29872 # 备用"
+/// 格式错误的地址会被跳过，结果按权重随机排序，同时返回最小的ttl
+pub fn txt_dns(domain: &str, name_server: String) -> anyhow::Result<(Vec<SocketAddr>, u32)> {
     let mut buf = [0; 65536];
-    let message = query(&udp, domain, name_server, QueryType::TXT, &mut buf)?;
+    let message = query_by(domain, &name_server, QueryType::TXT, &mut buf)?;
     let mut rs = Vec::new();
+    let mut min_ttl = u32::MAX;
     for record in message.answers {
         if let RData::TXT(txt) = record.data {
+            min_ttl = min_ttl.min(record.ttl);
+            // 超过255字节的记录会被拆分成多段
+            let mut data = Vec::new();
             for x in txt.iter() {
-                let txt = std::str::from_utf8(x).context("record type txt is not string")?;
-                let addr =
-                    SocketAddr::from_str(txt).context("record type txt is not SocketAddr")?;
-                rs.push(addr);
+                data.extend_from_slice(x);
+            }
+            match std::str::from_utf8(&data) {
+                Ok(txt) => rs.extend(txt_parse(txt)),
+                Err(e) => {
+                    log::warn!("record type txt is not string {:?}", e);
+                }
+            }
+        }
+    }
+    if rs.is_empty() {
+        return Err(anyhow::anyhow!(
+            "record type txt is not SocketAddr,domain {:?}",
+            domain
+        ));
+    }
+    Ok((weighted_order(rs), min_ttl))
+}
+
+fn txt_parse(txt: &str) -> Vec<(u32, SocketAddr)> {
+    let txt = txt.split('#').next().unwrap_or_default();
+    let mut rs = Vec::new();
+    for item in txt.split(|c: char| c.is_whitespace() || c == ',') {
+        if item.is_empty() {
+            continue;
+        }
+        let mut split = item.split(';');
+        let addr = match SocketAddr::from_str(split.next().unwrap_or_default()) {
+            Ok(addr) => addr,
+            Err(e) => {
+                log::warn!("txt {:?} is not SocketAddr {:?}", item, e);
+                continue;
+            }
+        };
+        let mut weight = 1;
+        for param in split {
+            if let Some(v) = param.strip_prefix("weight=") {
+                match u32::from_str(v) {
+                    Ok(v) => weight = v,
+                    Err(e) => {
+                        log::warn!("txt {:?} weight error {:?}", item, e);
+                    }
+                }
+            }
+        }
+        rs.push((weight, addr));
+    }
+    rs
+}
+
+/// 查询SRV记录，例如 _vnt._udp.example.com
+/// 按优先级升序，同优先级按权重随机排序后，再解析目标主机的地址，同时返回最小的ttl
+pub fn srv_dns(domain: &str, name_server: String) -> anyhow::Result<(Vec<SocketAddr>, u32)> {
+    let mut records = Vec::new();
+    let mut min_ttl = u32::MAX;
+    {
+        let mut buf = [0; 65536];
+        let message = query_by(domain, &name_server, QueryType::SRV, &mut buf)?;
+        for record in message.answers {
+            if let RData::SRV(srv) = record.data {
+                min_ttl = min_ttl.min(record.ttl);
+                let target = srv.target.to_string();
+                // 目标为'.'表示服务不可用
+                if target.is_empty() || target == "." {
+                    continue;
+                }
+                records.push((srv.priority, srv.weight, srv.port, target));
             }
         }
     }
-    Ok(rs)
+    let mut rs = Vec::new();
+    for (_, _, port, target) in srv_order(records) {
+        if let Ok(ip) = IpAddr::from_str(&target) {
+            rs.push(SocketAddr::new(ip, port));
+            continue;
+        }
+        match a_dns(target.clone(), name_server.clone()) {
+            Ok((ips, ttl)) => {
+                min_ttl = min_ttl.min(ttl);
+                rs.extend(ips.into_iter().map(|ip| SocketAddr::new(ip.into(), port)))
+            }
+            Err(e) => log::warn!("srv target {} A {:?}", target, e),
+        }
+        match aaaa_dns(target.clone(), name_server.clone()) {
+            Ok((ips, ttl)) => {
+                min_ttl = min_ttl.min(ttl);
+                rs.extend(ips.into_iter().map(|ip| SocketAddr::new(ip.into(), port)))
+            }
+            Err(e) => log::warn!("srv target {} AAAA {:?}", target, e),
+        }
+    }
+    Ok((rs, min_ttl))
+}
+
+/// RFC2782 排序，(priority,weight,port,target)
+fn srv_order(mut records: Vec<(u16, u16, u16, String)>) -> Vec<(u16, u16, u16, String)> {
+    records.sort_by_key(|v| v.0);
+    let mut rs = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let priority = records[0].0;
+        let end = records
+            .iter()
+            .position(|v| v.0 != priority)
+            .unwrap_or(records.len());
+        let group = records.drain(..end).map(|v| (v.1 as u32, v)).collect();
+        rs.extend(weighted_order(group));
+    }
+    rs
+}
+
+/// 按权重随机排序，权重越大越可能排在前面
+fn weighted_order<T>(mut list: Vec<(u32, T)>) -> Vec<T> {
+    let mut rs = Vec::with_capacity(list.len());
+    let mut rng = rand::thread_rng();
+    while !list.is_empty() {
+        let total: u64 = list.iter().map(|v| v.0 as u64).sum();
+        let index = if total == 0 {
+            0
+        } else {
+            let r = rng.gen_range(1..=total);
+            let mut sum = 0;
+            list.iter()
+                .position(|v| {
+                    sum += v.0 as u64;
+                    sum >= r
+                })
+                .unwrap_or(0)
+        };
+        rs.push(list.remove(index).1);
+    }
+    rs
 }
 
 fn bind_udp(name_server: SocketAddr) -> anyhow::Result<UdpSocket> {
@@ -259,30 +869,115 @@ fn bind_udp(name_server: SocketAddr) -> anyhow::Result<UdpSocket> {
     Ok(udp)
 }
 
-pub fn a_dns(domain: String, name_server: String) -> anyhow::Result<Vec<Ipv4Addr>> {
-    let name_server: SocketAddr = name_server.parse()?;
-    let udp = bind_udp(name_server)?;
+/// 同时返回最小的ttl
+pub fn a_dns(domain: String, name_server: String) -> anyhow::Result<(Vec<Ipv4Addr>, u32)> {
     let mut buf = [0; 65536];
-    let message = query(&udp, &domain, name_server, QueryType::A, &mut buf)?;
+    let message = query_by(&domain, &name_server, QueryType::A, &mut buf)?;
     let mut rs = Vec::new();
+    let mut min_ttl = u32::MAX;
     for record in message.answers {
         if let RData::A(a) = record.data {
+            min_ttl = min_ttl.min(record.ttl);
             rs.push(a.0);
         }
     }
-    Ok(rs)
+    Ok((rs, min_ttl))
 }
 
-pub fn aaaa_dns(domain: String, name_server: String) -> anyhow::Result<Vec<Ipv6Addr>> {
-    let name_server: SocketAddr = name_server.parse()?;
-    let udp = bind_udp(name_server)?;
+/// 同时返回最小的ttl
+pub fn aaaa_dns(domain: String, name_server: String) -> anyhow::Result<(Vec<Ipv6Addr>, u32)> {
     let mut buf = [0; 65536];
-    let message = query(&udp, &domain, name_server, QueryType::AAAA, &mut buf)?;
+    let message = query_by(&domain, &name_server, QueryType::AAAA, &mut buf)?;
     let mut rs = Vec::new();
+    let mut min_ttl = u32::MAX;
     for record in message.answers {
         if let RData::AAAA(a) = record.data {
+            min_ttl = min_ttl.min(record.ttl);
             rs.push(a.0);
         }
     }
-    Ok(rs)
+    Ok((rs, min_ttl))
+}
+
+#[test]
+fn test_txt_parse() {
+    let addr = |s: &str| SocketAddr::from_str(s).unwrap();
+    // 带权重
+    assert_eq!(
+        txt_parse("1.1.1.1:29872;weight=10 [::1]:29872,2.2.2.2:1 # 3.3.3.3:1"),
+        vec![
+            (10, addr("1.1.1.1:29872")),
+            (1, addr("[::1]:29872")),
+            (1, addr("2.2.2.2:1"))
+        ]
+    );
+    // 不带权重
+    assert_eq!(txt_parse("1.1.1.1:29872"), vec![(1, addr("1.1.1.1:29872"))]);
+    assert!(txt_parse("").is_empty());
+    assert!(txt_parse("# 1.1.1.1:29872").is_empty());
+    // 格式错误的地址跳过，格式错误的权重按1处理
+    assert_eq!(
+        txt_parse("abc 1.1.1.1 ;weight=3 1.1.1.1:1;weight=x 1.1.1.1:2;weight=-1;foo=1"),
+        vec![(1, addr("1.1.1.1:1")), (1, addr("1.1.1.1:2"))]
+    );
+}
+
+#[test]
+fn test_weighted_order() {
+    for _ in 0..100 {
+        let list = weighted_order(vec![(0, 'a'), (5, 'b'), (3, 'c')]);
+        assert_eq!(list.len(), 3);
+        assert!(list.contains(&'b') && list.contains(&'c'));
+        // 权重为0的排在最后
+        assert_eq!(list[2], 'a');
+    }
+}
+
+#[test]
+fn test_latency_choose() {
+    let addr = |s: &str| SocketAddr::from_str(s).unwrap();
+    let ms = Duration::from_millis;
+    let latency = vec![
+        (addr("1.1.1.1:1"), ms(30)),
+        (addr("[::1]:1"), ms(20)),
+        (addr("2.2.2.2:1"), ms(10)),
+    ];
+    // 选择延迟最低的地址，有偏好时偏好的类型优先
+    assert_eq!(
+        latency_choose(latency.clone(), AddressPrefer::Any),
+        Some(addr("2.2.2.2:1"))
+    );
+    assert_eq!(
+        latency_choose(latency.clone(), AddressPrefer::V6),
+        Some(addr("[::1]:1"))
+    );
+    assert_eq!(
+        latency_choose(latency[..1].to_vec(), AddressPrefer::V6),
+        Some(addr("1.1.1.1:1"))
+    );
+    assert_eq!(latency_choose(vec![], AddressPrefer::Any), None);
+
+    // 第一个响应后在窗口内继续收集，晚于窗口的响应不再等待
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender1 = sender.clone();
+    thread::spawn(move || {
+        let _ = sender1.send((addr("[::1]:1"), ms(80)));
+        thread::sleep(ms(50));
+        let _ = sender1.send((addr("2.2.2.2:1"), ms(10)));
+    });
+    thread::spawn(move || {
+        thread::sleep(ms(1000));
+        let _ = sender.send((addr("1.1.1.1:1"), ms(1)));
+    });
+    let start = Instant::now();
+    let list = collect_latency(&receiver, ms(300));
+    assert!(start.elapsed() < ms(900));
+    assert_eq!(
+        latency_choose(list, AddressPrefer::Any),
+        Some(addr("2.2.2.2:1"))
+    );
+    // 全部探测失败
+    let (sender, receiver) = std::sync::mpsc::channel::<(SocketAddr, Duration)>();
+    drop(sender);
+    assert!(collect_latency(&receiver, ms(300)).is_empty());
 }
@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::VntEvent;
+
+// 保留最近的事件数量，取得太慢的订阅方会丢失更早的事件
+const CAPACITY: usize = 256;
+
+/// 最近的事件，按序号订阅，没有新事件时可以等待
+pub struct EventLog {
+    // 下一个事件的序号和最近的事件
+    events: Mutex<(u64, VecDeque<(u64, VntEvent)>)>,
+    condvar: Condvar,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self {
+            events: Mutex::new((1, VecDeque::with_capacity(CAPACITY))),
+            condvar: Condvar::new(),
+        }
+    }
+    pub fn push(&self, event: VntEvent) {
+        let mut guard = self.events.lock();
+        let seq = guard.0;
+        guard.0 += 1;
+        if guard.1.len() >= CAPACITY {
+            guard.1.pop_front();
+        }
+        guard.1.push_back((seq, event));
+        self.condvar.notify_all();
+    }
+    /// 返回序号不小于since的事件和下一次订阅使用的序号，没有时最多等待timeout
+    pub fn wait(&self, since: u64, timeout: Duration) -> (u64, Vec<(u64, VntEvent)>) {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.events.lock();
+        while guard.0 <= since {
+            if self.condvar.wait_until(&mut guard, deadline).timed_out() {
+                break;
+            }
+        }
+        let events = guard
+            .1
+            .iter()
+            .filter(|(seq, _)| *seq >= since)
+            .cloned()
+            .collect();
+        (guard.0, events)
+    }
+}
+
+#[test]
+fn event_log_test() {
+    let log = EventLog::new();
+    let (next, events) = log.wait(0, Duration::ZERO);
+    assert_eq!((next, events.len()), (1, 0));
+    log.push(VntEvent::ServerDisconnected);
+    log.push(VntEvent::DirectLinkDown {
+        virtual_ip: std::net::Ipv4Addr::new(10, 26, 0, 2),
+    });
+    let (next, events) = log.wait(2, Duration::from_secs(1));
+    assert_eq!(next, 3);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].1.name(), "direct_link_down");
+}
@@ -0,0 +1,246 @@
+use std::io;
+use std::net::Ipv4Addr;
+use std::process::Command;
+
+use parking_lot::Mutex;
+
+/// 在系统防火墙中添加vnt需要的放行规则：本地监听的udp/tcp端口和从虚拟网卡进入的数据，退出时删除。
+/// windows使用PowerShell的New-NetFirewallRule(netsh不能把规则绑定到指定网卡)，
+/// linux优先使用iptables(兼容nftables后端)，没有iptables时使用nft，其他系统不处理
+pub struct HostFirewall {
+    // 规则名称的前缀，使用网卡名称区分同时运行的多个vnt
+    name: String,
+    // 已添加的规则，(是否是网卡规则, 删除规则的命令)
+    added: Mutex<Vec<(bool, Vec<String>)>>,
+}
+
+impl HostFirewall {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            added: Mutex::new(Vec::new()),
+        }
+    }
+    /// 放行本地监听的端口
+    pub fn allow_ports(&self, udp_ports: &[u16], tcp_port: u16) {
+        for port in udp_ports {
+            let name = format!("{}-udp-{}", self.name, port);
+            self.add(false, Rule::Port("udp", *port, &name));
+        }
+        let name = format!("{}-tcp-{}", self.name, tcp_port);
+        self.add(false, Rule::Port("tcp", tcp_port, &name));
+    }
+    /// 放行从虚拟网卡进入的数据，重建网卡时替换原来的规则
+    pub fn allow_device(&self, device_name: &str, network: Ipv4Addr, netmask: Ipv4Addr) {
+        self.remove(true);
+        let network = format!("{}/{}", network, u32::from(netmask).count_ones());
+        let name = format!("{}-net", self.name);
+        self.add(true, Rule::Device(device_name, &network, &name));
+    }
+    /// 删除添加的全部规则
+    pub fn clear(&self) {
+        self.remove(true);
+        self.remove(false);
+    }
+    fn add(&self, device: bool, rule: Rule) {
+        match add_rule(&rule) {
+            Ok(Some(undo)) => {
+                log::info!("系统防火墙添加规则 {:?}", rule);
+                self.added.lock().push((device, undo));
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("系统防火墙添加规则失败 {:?} {:?}", rule, e),
+        }
+    }
+    fn remove(&self, device: bool) {
+        let mut guard = self.added.lock();
+        guard.retain(|(is_device, undo)| {
+            if *is_device != device {
+                return true;
+            }
+            if let Err(e) = run(undo) {
+                log::warn!("系统防火墙删除规则失败 {:?} {:?}", undo, e);
+            }
+            false
+        });
+    }
+}
+
+impl Drop for HostFirewall {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(any(windows, target_os = "linux")), allow(dead_code))]
+enum Rule<'a> {
+    // (协议, 端口, 规则名称)
+    Port(&'static str, u16, &'a str),
+    // (网卡名称, 虚拟网段, 规则名称)
+    Device(&'a str, &'a str, &'a str),
+}
+
+/// 添加规则，返回删除规则的命令，系统中没有防火墙时返回None
+#[cfg(windows)]
+fn add_rule(rule: &Rule) -> io::Result<Option<Vec<String>>> {
+    let (name, args) = match rule {
+        Rule::Port(protocol, port, name) => {
+            (name, format!("-Protocol {} -LocalPort {}", protocol, port))
+        }
+        // 只放行从虚拟网卡进入、来源是虚拟网段的数据，wintun网卡的网络类型可能是公用网络
+        Rule::Device(device, network, name) => (
+            name,
+            format!(
+                "-InterfaceAlias {} -RemoteAddress {}",
+                ps_quote(device),
+                network
+            ),
+        ),
+    };
+    let delete = powershell(format!("Remove-NetFirewallRule -Name {}", ps_quote(name)));
+    // 删除上次异常退出时残留的规则
+    let _ = run(&delete);
+    run(&powershell(format!(
+        "New-NetFirewallRule -Name {0} -DisplayName {0} -Direction Inbound -Action Allow {1}",
+        ps_quote(name),
+        args
+    )))?;
+    Ok(Some(delete))
+}
+
+/// -Command后的参数由powershell解析，参数中的值需要加引号
+#[cfg(windows)]
+fn powershell(script: String) -> Vec<String> {
+    vec![
+        "powershell".into(),
+        "-NoProfile".into(),
+        "-NonInteractive".into(),
+        "-Command".into(),
+        script,
+    ]
+}
+
+// 单引号字符串中的单引号写两次
+#[cfg(windows)]
+fn ps_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+#[cfg(target_os = "linux")]
+fn add_rule(rule: &Rule) -> io::Result<Option<Vec<String>>> {
+    let (name, matches) = match rule {
+        Rule::Port(protocol, port, name) => (
+            name,
+            vec![
+                "-p".into(),
+                protocol.to_string(),
+                "--dport".into(),
+                port.to_string(),
+            ],
+        ),
+        // 只放行从虚拟网卡进入、来源是虚拟网段的数据
+        Rule::Device(device, network, name) => (
+            name,
+            vec![
+                "-i".into(),
+                device.to_string(),
+                "-s".into(),
+                network.to_string(),
+            ],
+        ),
+    };
+    let iptables = |op: &str| -> Vec<String> {
+        let mut args = vec!["iptables".into(), "-w".into(), op.into(), "INPUT".into()];
+        args.extend(matches.iter().cloned());
+        args.extend(["-m", "comment", "--comment", *name, "-j", "ACCEPT"].map(String::from));
+        args
+    };
+    // 删除上次异常退出时残留的规则，重新添加的规则在退出时删除
+    for _ in 0..8 {
+        match run(&iptables("-D")) {
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return add_nft_rule(rule),
+            Err(_) => break,
+        }
+    }
+    run(&iptables("-I"))?;
+    Ok(Some(iptables("-D")))
+}
+
+/// 没有iptables命令时直接添加到nftables的inet filter表中，没有这个表时说明没有启用防火墙
+#[cfg(target_os = "linux")]
+fn add_nft_rule(rule: &Rule) -> io::Result<Option<Vec<String>>> {
+    let list = args_of(&["nft", "list", "chain", "inet", "filter", "input"]);
+    if run(&list).is_err() {
+        log::info!("没有iptables和nftables的inet filter input链，不添加规则");
+        return Ok(None);
+    }
+    let (name, matches) = match rule {
+        Rule::Port(protocol, port, name) => (
+            name,
+            vec![protocol.to_string(), "dport".into(), port.to_string()],
+        ),
+        // nft会把参数拼接后再解析，名称需要加引号
+        Rule::Device(device, network, name) => (
+            name,
+            vec![
+                "iifname".into(),
+                format!("\"{}\"", device),
+                "ip".into(),
+                "saddr".into(),
+                network.to_string(),
+            ],
+        ),
+    };
+    let mut args = args_of(&[
+        "nft", "--echo", "--handle", "insert", "rule", "inet", "filter", "input",
+    ]);
+    args.extend(matches);
+    args.extend(["accept".into(), "comment".into(), format!("\"{}\"", name)]);
+    let out = run(&args)?;
+    // 输出中带有'# handle <n>'
+    let handle = match out.rsplit_once("# handle ") {
+        Some((_, handle)) => handle.trim().to_string(),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("nft handle not found {}", out),
+            ))
+        }
+    };
+    let mut undo = args_of(&["nft", "delete", "rule", "inet", "filter", "input", "handle"]);
+    undo.push(handle);
+    Ok(Some(undo))
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn add_rule(rule: &Rule) -> io::Result<Option<Vec<String>>> {
+    log::warn!("当前系统不支持添加系统防火墙规则 {:?}", rule);
+    Ok(None)
+}
+
+#[cfg(target_os = "linux")]
+fn args_of(args: &[&str]) -> Vec<String> {
+    args.iter().map(|v| v.to_string()).collect()
+}
+
+/// 执行命令，返回标准输出，命令不存在时错误类型是NotFound
+fn run(args: &[String]) -> io::Result<String> {
+    let mut command = Command::new(&args[0]);
+    command.args(&args[1..]);
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW
+        command.creation_flags(0x08000000);
+    }
+    let out = command.output()?;
+    if !out.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{:?} {}", args, String::from_utf8_lossy(&out.stderr).trim()),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
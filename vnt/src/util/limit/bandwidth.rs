@@ -0,0 +1,264 @@
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+use std::fmt::{Display, Formatter};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::Instant;
+
+// 突发量按200ms的流量计算，不小于64KB
+const BURST_DIVISOR: u64 = 5;
+const MIN_BURST: u64 = 64 * 1024;
+
+/// 上行和下行的带宽限制(字节/秒)，0表示不限制。
+/// 格式 `<上行>[/<下行>]`，省略下行时和上行相同，单位 bit/kbit/mbit/gbit 或 b/kb/mb/gb，
+/// 例如 `20mbit`、`20mbit/100mbit`、`0/10mb`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BandwidthLimit {
+    pub up: u64,
+    pub down: u64,
+}
+
+impl FromStr for BandwidthLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |e: &str| format!("'{}' {}, example: 20mbit, 20mbit/100mbit, 0/10mb", s, e);
+        let (up, down) = s.trim().split_once('/').unwrap_or((s.trim(), s.trim()));
+        let up = parse_rate(up).ok_or_else(|| err("invalid up rate"))?;
+        let down = parse_rate(down).ok_or_else(|| err("invalid down rate"))?;
+        Ok(BandwidthLimit { up, down })
+    }
+}
+
+impl Display for BandwidthLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", format_rate(self.up), format_rate(self.down))
+    }
+}
+
+/// 单位为bit的按8换算成字节
+fn parse_rate(s: &str) -> Option<u64> {
+    let s = s.trim().to_lowercase();
+    let index = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(index);
+    let num = u64::from_str(num).ok()?;
+    let (multiple, bit) = match unit {
+        "" if num == 0 => (1, false),
+        "b" => (1, false),
+        "kb" => (1000, false),
+        "mb" => (1_000_000, false),
+        "gb" => (1_000_000_000, false),
+        "bit" => (1, true),
+        "kbit" => (1000, true),
+        "mbit" => (1_000_000, true),
+        "gbit" => (1_000_000_000, true),
+        _ => return None,
+    };
+    let rate = num.checked_mul(multiple)?;
+    Some(if bit { rate / 8 } else { rate })
+}
+
+fn format_rate(rate: u64) -> String {
+    if rate == 0 {
+        "0".to_string()
+    } else if rate % 125_000_000 == 0 {
+        format!("{}gbit", rate / 125_000_000)
+    } else if rate % 125_000 == 0 {
+        format!("{}mbit", rate / 125_000)
+    } else if rate % 125 == 0 {
+        format!("{}kbit", rate / 125)
+    } else {
+        format!("{}b", rate)
+    }
+}
+
+/// 对端虚拟ip的带宽限制，格式 `<虚拟ip/掩码位数> <上行>[/<下行>]`，
+/// 例如 `10.26.0.5 5mbit`，网段中的每个对端单独计算
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PeerLimit {
+    pub network: u32,
+    pub mask: u32,
+    pub limit: BandwidthLimit,
+}
+
+impl FromStr for PeerLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |e: &str| {
+            format!(
+                "'{}' {}, example: 10.26.0.5 5mbit, 10.26.0.0/24 1mb/10mb",
+                s, e
+            )
+        };
+        let mut items = s.split_whitespace();
+        let (network, mask) = match items.next() {
+            Some(v) => crate::external_route::parse_network(v).map_err(err)?,
+            None => return Err(err("missing peer")),
+        };
+        let limit = match items.next() {
+            Some(v) => BandwidthLimit::from_str(v)?,
+            None => return Err(err("missing limit")),
+        };
+        if items.next().is_some() {
+            return Err(err("too many items"));
+        }
+        Ok(PeerLimit {
+            network,
+            mask,
+            limit,
+        })
+    }
+}
+
+impl Display for PeerLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} {}",
+            Ipv4Addr::from(self.network),
+            self.mask.count_ones(),
+            self.limit
+        )
+    }
+}
+
+struct TokenBucket {
+    rate: u64,
+    capacity: u64,
+    tokens: u64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+        let capacity = (rate / BURST_DIVISOR).max(MIN_BURST);
+        Some(Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        })
+    }
+    fn try_acquire(&mut self, len: u64, force: bool) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_micros() as u64;
+        let new_tokens = elapsed.saturating_mul(self.rate) / 1_000_000;
+        if new_tokens > 0 {
+            self.tokens = self.capacity.min(self.tokens + new_tokens);
+            self.last_refill = now;
+        }
+        if self.tokens >= len {
+            self.tokens -= len;
+            true
+        } else if force {
+            self.tokens = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct Buckets {
+    up: Option<TokenBucket>,
+    down: Option<TokenBucket>,
+}
+
+impl Buckets {
+    fn new(limit: BandwidthLimit) -> Self {
+        Self {
+            up: TokenBucket::new(limit.up),
+            down: TokenBucket::new(limit.down),
+        }
+    }
+    fn try_acquire(&mut self, up: bool, len: u64, force: bool) -> bool {
+        let bucket = if up { &mut self.up } else { &mut self.down };
+        bucket.as_mut().map_or(true, |v| v.try_acquire(len, force))
+    }
+}
+
+/// 令牌桶限速，超过限制的数据直接丢弃，由内层的tcp等协议自行降速。
+/// 先按对端限速，再按全局限速
+pub struct BandwidthLimiter {
+    global: Mutex<Buckets>,
+    rules: Vec<PeerLimit>,
+    // 对端的令牌桶，没有匹配的规则时为None
+    peers: Mutex<FnvHashMap<Ipv4Addr, Option<Buckets>>>,
+}
+
+impl BandwidthLimiter {
+    /// 没有任何限制时返回None
+    pub fn new(global: Option<BandwidthLimit>, rules: Vec<PeerLimit>) -> Option<Self> {
+        let global = global.unwrap_or(BandwidthLimit { up: 0, down: 0 });
+        if global.up == 0 && global.down == 0 && rules.is_empty() {
+            return None;
+        }
+        Some(Self {
+            global: Mutex::new(Buckets::new(global)),
+            rules,
+            peers: Mutex::new(FnvHashMap::default()),
+        })
+    }
+    /// 发往对端的数据，priority为true时总是允许，但同样消耗令牌
+    pub fn allow_up(&self, peer: Ipv4Addr, len: usize, priority: bool) -> bool {
+        self.try_acquire(peer, true, len as u64, priority)
+    }
+    /// 对端发来的数据
+    pub fn allow_down(&self, peer: Ipv4Addr, len: usize) -> bool {
+        self.try_acquire(peer, false, len as u64, false)
+    }
+    fn try_acquire(&self, peer: Ipv4Addr, up: bool, len: u64, force: bool) -> bool {
+        if !self.rules.is_empty() {
+            let mut guard = self.peers.lock();
+            let buckets = guard.entry(peer).or_insert_with(|| {
+                self.rules
+                    .iter()
+                    .find(|v| u32::from(peer) & v.mask == v.network)
+                    .map(|v| Buckets::new(v.limit))
+            });
+            if let Some(buckets) = buckets {
+                if !buckets.try_acquire(up, len, force) {
+                    return false;
+                }
+            }
+        }
+        self.global.lock().try_acquire(up, len, force)
+    }
+}
+
+#[test]
+fn bandwidth_limit_test() {
+    let limit = BandwidthLimit::from_str("20mbit/100MBIT").unwrap();
+    assert_eq!(limit.up, 2_500_000);
+    assert_eq!(limit.down, 12_500_000);
+    assert_eq!(limit.to_string(), "20mbit/100mbit");
+    let limit = BandwidthLimit::from_str("0/10mb").unwrap();
+    assert_eq!(
+        limit,
+        BandwidthLimit {
+            up: 0,
+            down: 10_000_000
+        }
+    );
+    assert!(BandwidthLimit::from_str("20").is_err());
+    let rule = PeerLimit::from_str("10.26.0.0/24 8kbit").unwrap();
+    assert_eq!(
+        rule.limit,
+        BandwidthLimit {
+            up: 1000,
+            down: 1000
+        }
+    );
+    let limiter = BandwidthLimiter::new(None, vec![rule]).unwrap();
+    let peer = Ipv4Addr::new(10, 26, 0, 5);
+    assert!(limiter.allow_up(peer, MIN_BURST as usize, false));
+    assert!(!limiter.allow_up(peer, 1500, false));
+    assert!(limiter.allow_up(peer, 100, true));
+    assert!(limiter.allow_down(peer, 1500));
+    assert!(limiter.allow_up(Ipv4Addr::new(10, 26, 1, 5), 1500, false));
+    assert!(BandwidthLimiter::new(None, vec![]).is_none());
+}
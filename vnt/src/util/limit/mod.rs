@@ -1,3 +1,6 @@
+mod bandwidth;
+pub use bandwidth::*;
+
 mod rate_limiter;
 pub use rate_limiter::*;
 
@@ -8,10 +8,26 @@ pub use scheduler::Scheduler;
 
 mod dns_query;
 pub use dns_query::*;
+mod system_dns;
+pub(crate) use system_dns::system_name_servers;
+mod nat64;
+pub use nat64::*;
+#[cfg(any(feature = "doh", feature = "dot"))]
+mod secure_dns;
 
+#[cfg(feature = "upnp")]
+mod nat_pmp;
 #[cfg(feature = "upnp")]
 mod upnp;
 #[cfg(feature = "upnp")]
 pub use upnp::*;
 
 pub mod limit;
+
+mod event_log;
+pub use event_log::EventLog;
+
+#[cfg(feature = "integrated_tun")]
+mod host_firewall;
+#[cfg(feature = "integrated_tun")]
+pub use host_firewall::HostFirewall;
@@ -0,0 +1,178 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::util::aaaa_dns;
+use crate::util::system_dns::system_name_servers;
+
+// RFC7050 只有A记录的域名，在DNS64下查询AAAA记录能得到合成地址
+const IPV4ONLY_ARPA: &str = "ipv4only.arpa";
+const WELL_KNOWN_IPV4: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+// RFC6052 允许的前缀长度
+const PREFIX_LENS: [u8; 6] = [96, 64, 56, 48, 40, 32];
+// 切换网络后前缀可能变化，检测结果只缓存一段时间
+const CACHE_TIME: Duration = Duration::from_secs(300);
+
+static NAT64_PREFIX: Mutex<Option<(Instant, Option<Nat64Prefix>)>> = parking_lot::const_mutex(None);
+
+/// NAT64前缀，用于把ipv4地址合成ipv6地址
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Nat64Prefix {
+    prefix: Ipv6Addr,
+    len: u8,
+}
+
+/// ipv4地址在ipv6地址中的字节位置，跳过第8个字节(RFC6052 u位)
+fn ipv4_index(len: u8) -> impl Iterator<Item = usize> {
+    (len as usize / 8..16).filter(|i| *i != 8).take(4)
+}
+
+impl Nat64Prefix {
+    pub fn new(prefix: Ipv6Addr, len: u8) -> Option<Self> {
+        if !PREFIX_LENS.contains(&len) {
+            return None;
+        }
+        let mut octets = prefix.octets();
+        for v in &mut octets[len as usize / 8..] {
+            *v = 0;
+        }
+        Some(Self {
+            prefix: octets.into(),
+            len,
+        })
+    }
+    /// 从ipv4only.arpa的合成地址中找出前缀
+    pub fn from_synthesized(addr: &Ipv6Addr) -> Option<Self> {
+        let octets = addr.octets();
+        for len in PREFIX_LENS {
+            if len != 96 && octets[8] != 0 {
+                continue;
+            }
+            let mut ipv4 = [0u8; 4];
+            for (v, i) in ipv4.iter_mut().zip(ipv4_index(len)) {
+                *v = octets[i];
+            }
+            if WELL_KNOWN_IPV4.contains(&Ipv4Addr::from(ipv4)) {
+                return Self::new(*addr, len);
+            }
+        }
+        None
+    }
+    pub fn synthesize(&self, ip: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.prefix.octets();
+        for (i, v) in ipv4_index(self.len).zip(ip.octets()) {
+            octets[i] = v;
+        }
+        octets.into()
+    }
+}
+
+/// 检测当前网络的NAT64前缀，优先使用系统解析，再直接查询系统配置的dns服务器
+pub fn nat64_prefix() -> Option<Nat64Prefix> {
+    if let Some((time, prefix)) = *NAT64_PREFIX.lock() {
+        if time.elapsed() < CACHE_TIME {
+            return prefix;
+        }
+    }
+    let prefix = nat64_prefix0();
+    log::info!("NAT64前缀 {:?}", prefix);
+    NAT64_PREFIX.lock().replace((Instant::now(), prefix));
+    prefix
+}
+
+fn nat64_prefix0() -> Option<Nat64Prefix> {
+    let mut list: Vec<Ipv6Addr> = match (IPV4ONLY_ARPA, 0).to_socket_addrs() {
+        Ok(addrs) => addrs
+            .filter_map(|addr| match addr.ip() {
+                IpAddr::V4(_) => None,
+                IpAddr::V6(ip) => Some(ip),
+            })
+            .collect(),
+        Err(e) => {
+            log::warn!("{} {:?}", IPV4ONLY_ARPA, e);
+            vec![]
+        }
+    };
+    if list.is_empty() {
+        for name_server in system_name_servers() {
+            match aaaa_dns(IPV4ONLY_ARPA.into(), name_server) {
+                Ok((rs, _)) => {
+                    if !rs.is_empty() {
+                        list = rs;
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("{} {:?}", IPV4ONLY_ARPA, e);
+                }
+            }
+        }
+    }
+    list.iter().find_map(Nat64Prefix::from_synthesized)
+}
+
+/// 只有ipv6网络时，地址全是ipv4的使用NAT64前缀合成ipv6地址，其余情况原样返回
+pub fn nat64_synthesize(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    if addrs.is_empty() || addrs.iter().any(|v| v.is_ipv6()) || ipv4_reachable(&addrs) {
+        return addrs;
+    }
+    match nat64_prefix() {
+        Some(prefix) => {
+            let rs: Vec<SocketAddr> = addrs
+                .iter()
+                .filter_map(|addr| match addr.ip() {
+                    IpAddr::V4(ip) => Some(SocketAddr::new(
+                        IpAddr::V6(prefix.synthesize(ip)),
+                        addr.port(),
+                    )),
+                    IpAddr::V6(_) => None,
+                })
+                .collect();
+            log::info!("ipv4不可达，使用NAT64地址 {:?} -> {:?}", addrs, rs);
+            rs
+        }
+        None => addrs,
+    }
+}
+
+fn ipv4_reachable(addrs: &[SocketAddr]) -> bool {
+    let udp = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(udp) => udp,
+        Err(_) => return false,
+    };
+    addrs.iter().any(|addr| udp.connect(addr).is_ok())
+}
+
+#[test]
+fn test_nat64_synthesize() {
+    // RFC6052 2.4
+    let ip = Ipv4Addr::new(192, 0, 2, 33);
+    let list = [
+        (32, "2001:db8:c000:221::"),
+        (40, "2001:db8:1c0:2:21::"),
+        (48, "2001:db8:122:c000:2:2100::"),
+        (56, "2001:db8:122:3c0:0:221::"),
+        (64, "2001:db8:122:344:c0:2:2100:0"),
+        (96, "2001:db8:122:344::c000:221"),
+    ];
+    for (len, addr) in list {
+        let prefix = Nat64Prefix::new("2001:db8:122:344::".parse().unwrap(), len).unwrap();
+        assert_eq!(prefix.synthesize(ip), addr.parse::<Ipv6Addr>().unwrap());
+    }
+    let prefix = Nat64Prefix::from_synthesized(&"64:ff9b::c000:aa".parse().unwrap()).unwrap();
+    assert_eq!(
+        prefix,
+        Nat64Prefix::new("64:ff9b::".parse().unwrap(), 96).unwrap()
+    );
+    let prefix = Nat64Prefix::from_synthesized(&"2001:db8:c000:aa::".parse().unwrap()).unwrap();
+    assert_eq!(
+        prefix,
+        Nat64Prefix::new("2001:db8::".parse().unwrap(), 32).unwrap()
+    );
+    assert_eq!(
+        Nat64Prefix::from_synthesized(&"2001:db8::1".parse().unwrap()),
+        None
+    );
+}
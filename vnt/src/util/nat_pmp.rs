@@ -0,0 +1,121 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::time::Duration;
+
+// NAT-PMP和PCP共用的服务端口
+const SERVER_PORT: u16 = 5351;
+const TIMEOUT: Duration = Duration::from_millis(250);
+const RETRY: usize = 2;
+
+/// 本机的默认网关，linux从路由表读取，其他系统按常见的x.x.x.1猜测
+pub fn default_gateway(local_ip: Ipv4Addr) -> Ipv4Addr {
+    #[cfg(target_os = "linux")]
+    if let Ok(route) = std::fs::read_to_string("/proc/net/route") {
+        // Iface Destination Gateway Flags ...，地址是小端的十六进制
+        for line in route.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() > 2 && fields[1] == "00000000" {
+                if let Ok(v) = u32::from_str_radix(fields[2], 16) {
+                    if v != 0 {
+                        return Ipv4Addr::from(v.swap_bytes());
+                    }
+                }
+            }
+        }
+    }
+    let mut octets = local_ip.octets();
+    octets[3] = 1;
+    Ipv4Addr::from(octets)
+}
+
+fn request(gateway: Ipv4Addr, req: &[u8], resp: &mut [u8]) -> io::Result<usize> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(SocketAddrV4::new(gateway, SERVER_PORT))?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    let mut err = io::Error::from(io::ErrorKind::TimedOut);
+    for _ in 0..RETRY {
+        socket.send(req)?;
+        match socket.recv(resp) {
+            Ok(len) => return Ok(len),
+            Err(e) => err = e,
+        }
+    }
+    Err(err)
+}
+
+/// 使用PCP(rfc6887)映射udp端口，返回映射后的外网地址
+pub fn pcp_map_udp(
+    gateway: Ipv4Addr,
+    local_ip: Ipv4Addr,
+    port: u16,
+    lifetime: u32,
+) -> io::Result<SocketAddrV4> {
+    let mut req = [0u8; 60];
+    req[0] = 2;
+    // MAP
+    req[1] = 1;
+    req[4..8].copy_from_slice(&lifetime.to_be_bytes());
+    req[8..24].copy_from_slice(&local_ip.to_ipv6_mapped().octets());
+    let nonce: [u8; 12] = rand::random();
+    req[24..36].copy_from_slice(&nonce);
+    req[36] = 17;
+    req[40..42].copy_from_slice(&port.to_be_bytes());
+    req[42..44].copy_from_slice(&port.to_be_bytes());
+    req[44..60].copy_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+    let mut resp = [0u8; 1100];
+    let len = request(gateway, &req, &mut resp)?;
+    if len < 60 || resp[0] != 2 || resp[1] != 0x81 || resp[24..36] != nonce {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "pcp response"));
+    }
+    if resp[3] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("pcp result code {}", resp[3]),
+        ));
+    }
+    let external_port = u16::from_be_bytes([resp[42], resp[43]]);
+    let external_ip: [u8; 16] = resp[44..60].try_into().unwrap();
+    match std::net::Ipv6Addr::from(external_ip).to_ipv4_mapped() {
+        Some(ip) => Ok(SocketAddrV4::new(ip, external_port)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "pcp external ip",
+        )),
+    }
+}
+
+/// 使用NAT-PMP(rfc6886)映射udp端口，返回映射后的外网地址
+pub fn nat_pmp_map_udp(gateway: Ipv4Addr, port: u16, lifetime: u32) -> io::Result<SocketAddrV4> {
+    let mut resp = [0u8; 16];
+    // 先获取外网ip
+    let len = request(gateway, &[0, 0], &mut resp)?;
+    if len < 12 || resp[1] != 128 || resp[2..4] != [0, 0] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "nat-pmp address",
+        ));
+    }
+    let external_ip = Ipv4Addr::new(resp[8], resp[9], resp[10], resp[11]);
+    let mut req = [0u8; 12];
+    // 映射udp
+    req[1] = 1;
+    req[4..6].copy_from_slice(&port.to_be_bytes());
+    req[6..8].copy_from_slice(&port.to_be_bytes());
+    req[8..12].copy_from_slice(&lifetime.to_be_bytes());
+    let len = request(gateway, &req, &mut resp)?;
+    if len < 16 || resp[1] != 129 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "nat-pmp response",
+        ));
+    }
+    let result = u16::from_be_bytes([resp[2], resp[3]]);
+    if result != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("nat-pmp result code {}", result),
+        ));
+    }
+    let external_port = u16::from_be_bytes([resp[10], resp[11]]);
+    Ok(SocketAddrV4::new(external_ip, external_port))
+}
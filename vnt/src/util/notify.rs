@@ -39,6 +39,10 @@ impl StopManager {
     pub fn is_stopped(&self) -> bool {
         self.inner.is_stopped()
     }
+    /// 因为panic退出的任务名称
+    pub fn panicked(&self) -> Vec<String> {
+        self.inner.panicked.lock().clone()
+    }
 }
 
 struct StopManagerInner {
@@ -47,6 +51,7 @@ struct StopManagerInner {
     worker_num: AtomicUsize,
     state: AtomicBool,
     stop_call: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    panicked: Mutex<Vec<String>>,
 }
 
 impl StopManagerInner {
@@ -60,6 +65,7 @@ impl StopManagerInner {
             worker_num: AtomicUsize::new(0),
             state: AtomicBool::new(false),
             stop_call: Mutex::new(Some(Box::new(f))),
+            panicked: Mutex::new(Vec::new()),
         }
     }
     fn add_listener<F>(self: &Arc<Self>, name: String, f: F) -> anyhow::Result<Worker>
@@ -168,6 +174,11 @@ impl Worker {
 
 impl Drop for Worker {
     fn drop(&mut self) {
+        if thread::panicking() {
+            // 线程崩溃时只会释放自己，其他任务继续运行，记录下来交给看门狗处理
+            log::error!("panicked {}", self.name);
+            self.inner.panicked.lock().push(self.name.clone());
+        }
         self.release0();
         log::info!("stop {}", self.name);
     }
@@ -0,0 +1,217 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+
+// dns报文最长65535字节，再留出http头部的长度
+#[cfg(feature = "doh")]
+const DOH_RESPONSE_LIMIT: usize = 65536 + 4096;
+
+/// DNS over HTTPS(RFC8484)，使用POST方式发送 application/dns-message
+/// url 例如 https://1.1.1.1/dns-query
+#[cfg(feature = "doh")]
+pub fn doh_query(url: &str, packet: &[u8], buf: &mut [u8]) -> anyhow::Result<usize> {
+    let s = url
+        .strip_prefix("https://")
+        .with_context(|| format!("{:?} not https url", url))?;
+    let (authority, path) = match s.find('/') {
+        Some(index) => (&s[..index], &s[index..]),
+        None => (s, "/dns-query"),
+    };
+    let (host, port) = parse_authority(authority, 443).with_context(|| format!("{:?}", url))?;
+    let mut tls = connect_tls(&host, port).with_context(|| format!("DoH {} connect error", url))?;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nAccept: application/dns-message\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host.clone()
+        },
+        packet.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(packet);
+    tls.write_all(&request)
+        .with_context(|| format!("DoH {} send error", url))?;
+    tls.flush()?;
+    let mut response = Vec::with_capacity(1024);
+    let limit = DOH_RESPONSE_LIMIT as u64 + 1;
+    if let Err(e) = (&mut tls).take(limit).read_to_end(&mut response) {
+        // 部分服务端不发送close_notify，已读取到数据时忽略该错误
+        if response.is_empty() {
+            Err(e).with_context(|| format!("DoH {} recv error", url))?
+        }
+    }
+    if response.len() > DOH_RESPONSE_LIMIT {
+        return Err(anyhow::anyhow!("DoH {} response too long", url));
+    }
+    let body = http_body(&response).with_context(|| format!("DoH {} response error", url))?;
+    if body.len() > buf.len() {
+        return Err(anyhow::anyhow!("DoH {} response too long", url));
+    }
+    buf[..body.len()].copy_from_slice(&body);
+    Ok(body.len())
+}
+
+/// DNS over TLS(RFC7858)，tcp格式的dns报文，前两字节为长度
+/// 地址例如 tls://1.1.1.1 ，默认端口853
+#[cfg(feature = "dot")]
+pub fn dot_query(url: &str, packet: &[u8], buf: &mut [u8]) -> anyhow::Result<usize> {
+    let authority = url
+        .strip_prefix("tls://")
+        .with_context(|| format!("{:?} not tls url", url))?;
+    let (host, port) = parse_authority(authority, 853).with_context(|| format!("{:?}", url))?;
+    let mut tls = connect_tls(&host, port).with_context(|| format!("DoT {} connect error", url))?;
+    let mut request = Vec::with_capacity(2 + packet.len());
+    request.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+    request.extend_from_slice(packet);
+    tls.write_all(&request)
+        .with_context(|| format!("DoT {} send error", url))?;
+    tls.flush()?;
+    let mut head = [0u8; 2];
+    tls.read_exact(&mut head)
+        .with_context(|| format!("DoT {} recv error", url))?;
+    let len = u16::from_be_bytes(head) as usize;
+    if len > buf.len() {
+        return Err(anyhow::anyhow!("DoT {} response too long", url));
+    }
+    tls.read_exact(&mut buf[..len])
+        .with_context(|| format!("DoT {} recv error", url))?;
+    Ok(len)
+}
+
+/// host:port 或 [ipv6]:port
+fn parse_authority(authority: &str, default_port: u16) -> anyhow::Result<(String, u16)> {
+    let authority = authority.trim_end_matches('/');
+    if authority.is_empty() {
+        return Err(anyhow::anyhow!("host is empty"));
+    }
+    if let Some(v6) = authority.strip_prefix('[') {
+        let end = v6.find(']').context("host error")?;
+        let port = match v6[end + 1..].strip_prefix(':') {
+            Some(port) => u16::from_str(port).context("port error")?,
+            None => default_port,
+        };
+        return Ok((v6[..end].to_string(), port));
+    }
+    if std::net::Ipv6Addr::from_str(authority).is_ok() {
+        return Ok((authority.to_string(), default_port));
+    }
+    match authority.rfind(':') {
+        Some(index) => {
+            let port = u16::from_str(&authority[index + 1..]).context("port error")?;
+            Ok((authority[..index].to_string(), port))
+        }
+        None => Ok((authority.to_string(), default_port)),
+    }
+}
+
+fn connect_tls(
+    host: &str,
+    port: u16,
+) -> anyhow::Result<rustls::StreamOwned<rustls::ClientConnection, TcpStream>> {
+    let addr = host_addr(host, port)?;
+    let tcp = TcpStream::connect_timeout(&addr, Duration::from_secs(3))?;
+    tcp.set_read_timeout(Some(Duration::from_secs(3)))?;
+    tcp.set_write_timeout(Some(Duration::from_secs(3)))?;
+    tls_stream(host, tcp)
+}
+
+fn host_addr(host: &str, port: u16) -> anyhow::Result<SocketAddr> {
+    if let Ok(ip) = std::net::IpAddr::from_str(host) {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    // DoH/DoT服务器本身是域名时，只能依赖系统解析
+    (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("DoH host {:?} resolve error", host))?
+        .next()
+        .with_context(|| format!("DoH host {:?} resolve error", host))
+}
+
+fn tls_stream(
+    host: &str,
+    tcp: TcpStream,
+) -> anyhow::Result<rustls::StreamOwned<rustls::ClientConnection, TcpStream>> {
+    let mut root_store = rustls::RootCertStore::empty();
+    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(root_store)
+    .with_no_client_auth();
+    let server_name = rustls::pki_types::ServerName::try_from(host.to_string())
+        .with_context(|| format!("server name {:?} error", host))?;
+    let conn = rustls::ClientConnection::new(Arc::new(config), server_name)?;
+    Ok(rustls::StreamOwned::new(conn, tcp))
+}
+
+#[cfg(feature = "doh")]
+fn http_body(response: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let head_end = response
+        .windows(4)
+        .position(|v| v == b"\r\n\r\n")
+        .context("http head error")?;
+    let head = std::str::from_utf8(&response[..head_end]).context("http head error")?;
+    let body = &response[head_end + 4..];
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(anyhow::anyhow!("http status {:?}", status_line));
+    }
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        if let Some((k, v)) = line.split_once(':') {
+            let k = k.trim().to_lowercase();
+            let v = v.trim();
+            if k == "content-length" {
+                content_length = Some(usize::from_str(v).context("content-length error")?);
+            } else if k == "transfer-encoding" && v.to_lowercase().contains("chunked") {
+                chunked = true;
+            }
+        }
+    }
+    if chunked {
+        return chunked_body(body);
+    }
+    match content_length {
+        Some(len) => {
+            if body.len() < len {
+                return Err(anyhow::anyhow!("http body too short"));
+            }
+            Ok(body[..len].to_vec())
+        }
+        None => Ok(body.to_vec()),
+    }
+}
+
+#[cfg(feature = "doh")]
+fn chunked_body(mut body: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut rs = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|v| v == b"\r\n")
+            .context("chunked error")?;
+        let size = std::str::from_utf8(&body[..line_end]).context("chunked error")?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).context("chunked error")?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(rs);
+        }
+        if body.len() < size {
+            return Err(anyhow::anyhow!("chunked error"));
+        }
+        rs.extend_from_slice(&body[..size]);
+        body = &body[size..];
+        body = body.strip_prefix(b"\r\n").unwrap_or(body);
+    }
+}
@@ -0,0 +1,169 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// 系统配置的dns服务器
+/// unix读取/etc/resolv.conf，windows使用GetNetworkParams
+pub fn system_name_servers() -> Vec<String> {
+    let mut rs = Vec::new();
+    for ip in system_name_servers0() {
+        if ip.is_unspecified() {
+            continue;
+        }
+        let addr = std::net::SocketAddr::new(ip, 53).to_string();
+        if !rs.contains(&addr) {
+            rs.push(addr);
+        }
+    }
+    rs
+}
+
+#[cfg(unix)]
+fn system_name_servers0() -> Vec<IpAddr> {
+    let conf = match std::fs::read_to_string("/etc/resolv.conf") {
+        Ok(conf) => conf,
+        Err(e) => {
+            log::warn!("read /etc/resolv.conf {:?}", e);
+            return vec![];
+        }
+    };
+    resolv_conf_parse(&conf)
+}
+
+#[cfg(windows)]
+fn system_name_servers0() -> Vec<IpAddr> {
+    use windows_sys::Win32::Foundation::{ERROR_BUFFER_OVERFLOW, ERROR_SUCCESS};
+    use windows_sys::Win32::NetworkManagement::IpHelper::{
+        GetNetworkParams, FIXED_INFO_W2KSP1, IP_ADDR_STRING,
+    };
+    let mut rs = Vec::new();
+    unsafe {
+        let mut len = 0u32;
+        if GetNetworkParams(std::ptr::null_mut(), &mut len) != ERROR_BUFFER_OVERFLOW {
+            return rs;
+        }
+        // 按u64对齐
+        let mut buf = vec![0u64; (len as usize + 7) / 8];
+        let info = buf.as_mut_ptr() as *mut FIXED_INFO_W2KSP1;
+        if GetNetworkParams(info, &mut len) != ERROR_SUCCESS {
+            log::warn!("GetNetworkParams {:?}", std::io::Error::last_os_error());
+            return rs;
+        }
+        let mut addr = &(*info).DnsServerList as *const IP_ADDR_STRING;
+        while !addr.is_null() {
+            let s = &(*addr).IpAddress.String;
+            let end = s.iter().position(|v| *v == 0).unwrap_or(s.len());
+            if let Ok(s) = std::str::from_utf8(&s[..end]) {
+                if let Ok(ip) = IpAddr::from_str(s.trim()) {
+                    rs.push(ip);
+                }
+            }
+            addr = (*addr).Next;
+        }
+    }
+    rs
+}
+
+#[cfg(not(any(unix, windows)))]
+fn system_name_servers0() -> Vec<IpAddr> {
+    vec![]
+}
+
+#[cfg(unix)]
+fn resolv_conf_parse(conf: &str) -> Vec<IpAddr> {
+    let mut rs = Vec::new();
+    for line in conf.lines() {
+        let line = line.split(|c| c == '#' || c == ';').next().unwrap_or_default();
+        let mut split = line.split_whitespace();
+        if split.next() != Some("nameserver") {
+            continue;
+        }
+        if let Some(ip) = split.next() {
+            // ipv6可能带有 %网卡 后缀
+            let ip = ip.split('%').next().unwrap_or_default();
+            if let Ok(ip) = IpAddr::from_str(ip) {
+                rs.push(ip);
+            }
+        }
+    }
+    rs
+}
+
+fn hosts_path() -> PathBuf {
+    #[cfg(windows)]
+    {
+        let root = std::env::var("SystemRoot").unwrap_or("C:\\Windows".into());
+        PathBuf::from(root).join("System32\\drivers\\etc\\hosts")
+    }
+    #[cfg(not(windows))]
+    PathBuf::from("/etc/hosts")
+}
+
+/// 从系统hosts文件查找域名
+pub fn hosts_lookup(host: &str) -> Vec<IpAddr> {
+    match std::fs::read_to_string(hosts_path()) {
+        Ok(hosts) => hosts_parse(&hosts, host),
+        Err(_) => vec![],
+    }
+}
+
+fn hosts_parse(hosts: &str, host: &str) -> Vec<IpAddr> {
+    let mut rs = Vec::new();
+    for line in hosts.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut split = line.split_whitespace();
+        let ip = match split.next().map(IpAddr::from_str) {
+            Some(Ok(ip)) => ip,
+            _ => continue,
+        };
+        if split.any(|name| name.eq_ignore_ascii_case(host)) && !rs.contains(&ip) {
+            rs.push(ip);
+        }
+    }
+    rs
+}
+
+#[cfg(unix)]
+#[test]
+fn test_resolv_conf_parse() {
+    let conf = "# comment\n\
+                nameserver 192.168.1.1\n\
+                nameserver fe80::1%eth0 ; link-local\n\
+                search example.com\n\
+                nameserver invalid\n\
+                #nameserver 8.8.8.8\n";
+    assert_eq!(
+        resolv_conf_parse(conf),
+        vec![
+            IpAddr::from_str("192.168.1.1").unwrap(),
+            IpAddr::from_str("fe80::1").unwrap()
+        ]
+    );
+    assert!(resolv_conf_parse("").is_empty());
+}
+
+#[test]
+fn test_hosts_parse() {
+    let hosts = "127.0.0.1 localhost\n\
+                 10.0.0.1 server.vnt Server.Alias # comment\n\
+                 ::1 localhost\n\
+                 10.0.0.1 server.vnt\n\
+                 # 10.0.0.2 server.vnt\n\
+                 invalid server.vnt\n";
+    assert_eq!(
+        hosts_parse(hosts, "SERVER.vnt"),
+        vec![IpAddr::from_str("10.0.0.1").unwrap()]
+    );
+    assert_eq!(
+        hosts_parse(hosts, "localhost"),
+        vec![
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            IpAddr::from_str("::1").unwrap()
+        ]
+    );
+    assert_eq!(
+        hosts_parse(hosts, "server.alias"),
+        vec![IpAddr::from_str("10.0.0.1").unwrap()]
+    );
+    assert!(hosts_parse(hosts, "comment").is_empty());
+}
@@ -5,6 +5,11 @@ use std::sync::Arc;
 
 use parking_lot::Mutex;
 
+use crate::util::nat_pmp;
+
+// 映射的有效期，每10分钟刷新一次
+const LEASE_DURATION: u32 = 700;
+
 #[derive(Clone, Default)]
 pub struct UPnP {
     inner: Arc<UpnpInner>,
@@ -21,6 +26,8 @@ impl Deref for UPnP {
 #[derive(Default)]
 pub struct UpnpInner {
     list: Mutex<Vec<(PortMappingProtocol, u16)>>,
+    // udp端口映射成功后的外网地址
+    mapped: Mutex<Vec<(u16, SocketAddrV4)>>,
 }
 
 impl UpnpInner {
@@ -30,30 +37,98 @@ impl UpnpInner {
     pub fn add_udp_port(&self, port: u16) {
         self.list.lock().push((PortMappingProtocol::UDP, port));
     }
+    /// udp端口映射后的外网地址
+    pub fn mapped_udp(&self, port: u16) -> Option<SocketAddrV4> {
+        self.mapped
+            .lock()
+            .iter()
+            .find(|(v, _)| *v == port)
+            .map(|(_, addr)| *addr)
+    }
+    /// 依次尝试upnp、PCP、NAT-PMP请求路由器映射端口
     pub fn reset(&self, local_ip: Ipv4Addr) {
+        let list = self.list.lock().clone();
+        let mut mapped = self.reset_upnp(local_ip, &list);
+        if mapped.is_empty() {
+            mapped = reset_pmp(local_ip, &list);
+        }
+        for (port, addr) in &mapped {
+            log::info!("端口映射成功 udp {} -> {}", port, addr);
+        }
+        *self.mapped.lock() = mapped;
+    }
+    fn reset_upnp(
+        &self,
+        local_ip: Ipv4Addr,
+        list: &[(PortMappingProtocol, u16)],
+    ) -> Vec<(u16, SocketAddrV4)> {
+        let mut mapped = Vec::new();
         let gateway = match search_gateway(Default::default()) {
             Ok(gateway) => gateway,
             Err(e) => {
                 log::warn!("search_gateway {:?}", e);
-                return;
+                return mapped;
+            }
+        };
+        let external_ip = match gateway.get_external_ip() {
+            Ok(ip) => Some(ip),
+            Err(e) => {
+                log::warn!("upnp get_external_ip {:?}", e);
+                None
             }
         };
-        let guard = self.list.lock();
 
         // 不支持upnp的情况会阻塞30秒，之后再改这个库
-        for (protocol, port) in guard.iter() {
+        for (protocol, port) in list.iter() {
             let local_addr = SocketAddrV4::new(local_ip, *port);
             log::info!("add upnp protocol={} {}", protocol, local_addr);
-            if let Err(e) = gateway.add_port(*protocol, *port, local_addr, 700, "upnp") {
-                log::warn!(
-                    "add upnp failed protocol={},port={} err:{:?}",
-                    protocol,
-                    port,
-                    e
-                );
+            // 优先使用相同的外网端口，被占用时由路由器分配
+            let rs = gateway
+                .add_port(*protocol, *port, local_addr, LEASE_DURATION, "upnp")
+                .map(|_| *port)
+                .or_else(|_| gateway.add_any_port(*protocol, local_addr, LEASE_DURATION, "upnp"));
+            match rs {
+                Ok(external_port) => {
+                    if *protocol == PortMappingProtocol::UDP {
+                        if let Some(ip) = external_ip {
+                            mapped.push((*port, SocketAddrV4::new(ip, external_port)));
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::warn!(
+                        "add upnp failed protocol={},port={} err:{:?}",
+                        protocol,
+                        port,
+                        e
+                    );
+                }
+            }
+        }
+        mapped
+    }
+}
+
+/// upnp不可用时，使用PCP或NAT-PMP映射udp端口
+fn reset_pmp(local_ip: Ipv4Addr, list: &[(PortMappingProtocol, u16)]) -> Vec<(u16, SocketAddrV4)> {
+    let gateway = nat_pmp::default_gateway(local_ip);
+    let mut mapped = Vec::new();
+    for (protocol, port) in list.iter() {
+        if *protocol != PortMappingProtocol::UDP {
+            continue;
+        }
+        let rs = nat_pmp::pcp_map_udp(gateway, local_ip, *port, LEASE_DURATION)
+            .or_else(|_| nat_pmp::nat_pmp_map_udp(gateway, *port, LEASE_DURATION));
+        match rs {
+            Ok(addr) => mapped.push((*port, addr)),
+            Err(e) => {
+                log::warn!("pcp/nat-pmp gateway={},port={} err:{:?}", gateway, port, e);
+                // 网关不支持时不再尝试其他端口
+                break;
             }
         }
     }
+    mapped
 }
 
 impl Drop for UpnpInner {
@@ -0,0 +1,488 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use parking_lot::{Mutex, RwLock};
+
+use crate::channel::guard::HandshakeGuard;
+use crate::cipher::ReplayFilter;
+use crate::util::StopManager;
+use crate::wireguard::noise::{
+    consume_initiation, consume_response, create_initiation, create_response, decrypt_data,
+    encrypt_data, public_key, InitiatorState, StaticKeys, DATA_HEAD_LEN, MSG_COOKIE, MSG_DATA,
+    MSG_INITIATION, MSG_RESPONSE, TAG_LEN,
+};
+use crate::wireguard::{encode_key, WgConfig};
+
+// 以下时间和官方实现保持一致
+const REKEY_AFTER_TIME: Duration = Duration::from_secs(120);
+const REJECT_AFTER_TIME: Duration = Duration::from_secs(180);
+const REKEY_TIMEOUT: Duration = Duration::from_secs(5);
+// 重试握手的最长时间，超过后丢弃待发送的数据
+const REKEY_ATTEMPT_TIME: Duration = Duration::from_secs(90);
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+// 配置了对端地址时主动保活，维持nat映射
+const PERSISTENT_KEEPALIVE: Duration = Duration::from_secs(25);
+struct Session {
+    local_index: u32,
+    remote_index: u32,
+    send_key: [u8; 32],
+    recv_key: [u8; 32],
+    send_counter: u64,
+    replay: ReplayFilter,
+    time: Instant,
+    // 是否是本端发起的握手，由发起方负责定期更新密钥
+    initiator: bool,
+}
+
+struct PeerState {
+    endpoint: Option<SocketAddr>,
+    // 等待回应的握手和发起时间
+    handshake: Option<(InitiatorState, Instant)>,
+    // 从第一次发起握手开始计算，超时后放弃
+    handshake_start: Option<Instant>,
+    // 对端最近一次握手的时间戳，拒绝重放
+    last_timestamp: [u8; 12],
+    current: Option<Session>,
+    previous: Option<Session>,
+    // 作为响应方时，收到对端用新会话发的数据后才切换
+    next: Option<Session>,
+    // 会话建立前缓存最近一个待发送的包
+    pending: Option<Vec<u8>>,
+    last_send: Instant,
+    last_recv: Option<Instant>,
+}
+
+struct Peer {
+    public_key: [u8; 32],
+    ip: Ipv4Addr,
+    mask: Ipv4Addr,
+    persistent: bool,
+    state: Mutex<PeerState>,
+}
+
+impl Peer {
+    fn contains(&self, ip: &Ipv4Addr) -> bool {
+        u32::from(*ip) & u32::from(self.mask) == u32::from(self.ip)
+    }
+}
+
+struct Inner {
+    udp: UdpSocket,
+    keys: StaticKeys,
+    peers: Vec<Peer>,
+    // 本端会话索引 -> 对端下标
+    index_map: RwLock<FnvHashMap<u32, usize>>,
+    writer: Box<dyn Fn(&[u8]) -> io::Result<usize> + Send + Sync>,
+    // 握手请求按来源ip限流，每次握手都要做多次DH运算
+    guard: HandshakeGuard,
+}
+
+/// wg协议的接入端，收到的ip包写入虚拟网卡，发往wg对端网段的数据加密后直接发给对端
+#[derive(Clone)]
+pub struct WireGuard {
+    inner: Arc<Inner>,
+}
+
+impl WireGuard {
+    pub fn new<F>(stop_manager: StopManager, config: &WgConfig, writer: F) -> anyhow::Result<Self>
+    where
+        F: Fn(&[u8]) -> io::Result<usize> + Send + Sync + 'static,
+    {
+        let udp = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], config.port)))?;
+        udp.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let now = Instant::now();
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer| Peer {
+                public_key: peer.public_key,
+                ip: peer.ip,
+                mask: peer.mask,
+                persistent: peer.endpoint.is_some(),
+                state: Mutex::new(PeerState {
+                    endpoint: peer.endpoint,
+                    handshake: None,
+                    handshake_start: None,
+                    last_timestamp: [0; 12],
+                    current: None,
+                    previous: None,
+                    next: None,
+                    pending: None,
+                    last_send: now,
+                    last_recv: None,
+                }),
+            })
+            .collect();
+        log::info!(
+            "wg端口 {:?} 公钥 {}",
+            udp.local_addr()?,
+            encode_key(&public_key(&config.private_key))
+        );
+        let wireguard = Self {
+            inner: Arc::new(Inner {
+                udp,
+                keys: StaticKeys::new(&config.private_key),
+                peers,
+                index_map: RwLock::new(FnvHashMap::default()),
+                writer: Box::new(writer),
+                guard: HandshakeGuard::new(),
+            }),
+        };
+        let worker = stop_manager.add_listener("wireguard".into(), || {})?;
+        let wg = wireguard.clone();
+        thread::Builder::new()
+            .name("wireguard".into())
+            .spawn(move || {
+                wg.recv_loop(&stop_manager);
+                worker.stop_all();
+            })?;
+        Ok(wireguard)
+    }
+    /// 需要添加到虚拟网卡的路由
+    pub fn routes(&self) -> Vec<(Ipv4Addr, Ipv4Addr)> {
+        self.inner.peers.iter().map(|v| (v.ip, v.mask)).collect()
+    }
+    /// 目标属于wg对端时发送并返回true
+    pub fn send(&self, dest: Ipv4Addr, packet: &[u8]) -> io::Result<bool> {
+        let index = match self.inner.peers.iter().position(|v| v.contains(&dest)) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let peer = &self.inner.peers[index];
+        let mut state = peer.state.lock();
+        let usable = state
+            .current
+            .as_ref()
+            .map_or(false, |v| v.time.elapsed() < REJECT_AFTER_TIME);
+        if usable {
+            self.send_data(&mut state, packet)?;
+            let rekey = state.current.as_ref().map_or(false, |v| {
+                v.initiator && v.time.elapsed() >= REKEY_AFTER_TIME
+            });
+            if rekey && state.handshake.is_none() {
+                self.initiate(index, &mut state)?;
+            }
+        } else {
+            state.pending.replace(packet.to_vec());
+            if state.handshake.is_none() {
+                self.initiate(index, &mut state)?;
+            }
+        }
+        Ok(true)
+    }
+    fn new_index(&self, peer_index: usize) -> u32 {
+        let mut index_map = self.inner.index_map.write();
+        loop {
+            let index = rand::random::<u32>();
+            if !index_map.contains_key(&index) {
+                index_map.insert(index, peer_index);
+                return index;
+            }
+        }
+    }
+    fn remove_index(&self, session: Option<Session>) {
+        if let Some(session) = session {
+            self.inner.index_map.write().remove(&session.local_index);
+        }
+    }
+    fn initiate(&self, peer_index: usize, state: &mut PeerState) -> io::Result<()> {
+        let endpoint = match state.endpoint {
+            Some(endpoint) => endpoint,
+            // 不知道对端地址，只能等对端发起
+            None => return Ok(()),
+        };
+        if let Some((old, _)) = state.handshake.take() {
+            self.inner.index_map.write().remove(&old.local_index);
+        }
+        let peer = &self.inner.peers[peer_index];
+        let local_index = self.new_index(peer_index);
+        let (msg, handshake) = create_initiation(&self.inner.keys, &peer.public_key, local_index);
+        state.handshake.replace((handshake, Instant::now()));
+        if state.handshake_start.is_none() {
+            state.handshake_start = Some(Instant::now());
+        }
+        self.inner.udp.send_to(&msg, endpoint)?;
+        Ok(())
+    }
+    fn send_data(&self, state: &mut PeerState, packet: &[u8]) -> io::Result<()> {
+        let endpoint = match state.endpoint {
+            Some(endpoint) => endpoint,
+            None => return Ok(()),
+        };
+        let session = match state.current.as_mut() {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        let buf = encrypt_data(
+            &session.send_key,
+            session.remote_index,
+            session.send_counter,
+            packet,
+        );
+        session.send_counter += 1;
+        state.last_send = Instant::now();
+        self.inner.udp.send_to(&buf, endpoint)?;
+        Ok(())
+    }
+    fn recv_loop(&self, stop_manager: &StopManager) {
+        let mut buf = [0u8; 65536];
+        let mut last_timer = Instant::now();
+        loop {
+            if stop_manager.is_stopped() {
+                return;
+            }
+            match self.inner.udp.recv_from(&mut buf) {
+                Ok((len, addr)) => {
+                    if let Err(e) = self.handle(&buf[..len], addr) {
+                        log::warn!("wg {} {:?}", addr, e);
+                    }
+                }
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::WouldBlock && e.kind() != io::ErrorKind::TimedOut
+                    {
+                        log::warn!("wg recv {:?}", e);
+                    }
+                }
+            }
+            if last_timer.elapsed() >= Duration::from_secs(1) {
+                last_timer = Instant::now();
+                self.timer();
+            }
+        }
+    }
+    fn handle(&self, buf: &[u8], addr: SocketAddr) -> io::Result<()> {
+        if buf.len() < 4 || buf[1..4] != [0, 0, 0] {
+            return Ok(());
+        }
+        match buf[0] {
+            MSG_INITIATION => self.handle_initiation(buf, addr),
+            MSG_RESPONSE => self.handle_response(buf, addr),
+            // 本端不会发送mac2，不需要处理cookie
+            MSG_COOKIE => Ok(()),
+            MSG_DATA => self.handle_data(buf, addr),
+            _ => Ok(()),
+        }
+    }
+    fn handle_initiation(&self, buf: &[u8], addr: SocketAddr) -> io::Result<()> {
+        if !self.inner.guard.allow(addr.ip()) {
+            log::debug!("wg 握手请求超过限速 {}", addr);
+            return Ok(());
+        }
+        let initiation = match consume_initiation(&self.inner.keys, buf) {
+            Some(initiation) => initiation,
+            None => return Ok(()),
+        };
+        let peer_index = match self
+            .inner
+            .peers
+            .iter()
+            .position(|v| v.public_key == initiation.peer_public)
+        {
+            Some(index) => index,
+            None => {
+                log::warn!(
+                    "wg 未配置的公钥 {} {}",
+                    encode_key(&initiation.peer_public),
+                    addr
+                );
+                return Ok(());
+            }
+        };
+        let mut state = self.inner.peers[peer_index].state.lock();
+        // 时间戳是大端序，直接按字节比较
+        if initiation.timestamp <= state.last_timestamp {
+            return Ok(());
+        }
+        state.last_timestamp = initiation.timestamp;
+        state.endpoint = Some(addr);
+        let local_index = self.new_index(peer_index);
+        let (msg, keys) = create_response(&self.inner.keys, &initiation, local_index);
+        let session = Session {
+            local_index,
+            remote_index: initiation.sender_index,
+            send_key: keys.send,
+            recv_key: keys.recv,
+            send_counter: 0,
+            replay: ReplayFilter::new(),
+            time: Instant::now(),
+            initiator: false,
+        };
+        let old = state.next.replace(session);
+        self.remove_index(old);
+        self.inner.udp.send_to(&msg, addr)?;
+        Ok(())
+    }
+    fn handle_response(&self, buf: &[u8], addr: SocketAddr) -> io::Result<()> {
+        if buf.len() < 12 {
+            return Ok(());
+        }
+        let receiver = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+        let peer_index = match self.inner.index_map.read().get(&receiver) {
+            Some(index) => *index,
+            None => return Ok(()),
+        };
+        let mut state = self.inner.peers[peer_index].state.lock();
+        let (remote_index, keys) = match state.handshake.as_ref() {
+            Some((handshake, _)) => match consume_response(&self.inner.keys, handshake, buf) {
+                Some(rs) => rs,
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        let (handshake, _) = state.handshake.take().unwrap();
+        state.handshake_start = None;
+        state.endpoint = Some(addr);
+        let session = Session {
+            local_index: handshake.local_index,
+            remote_index,
+            send_key: keys.send,
+            recv_key: keys.recv,
+            send_counter: 0,
+            replay: ReplayFilter::new(),
+            time: Instant::now(),
+            initiator: true,
+        };
+        self.rotate(&mut state, session);
+        match state.pending.take() {
+            Some(packet) => self.send_data(&mut state, &packet),
+            // 没有数据时发一个空包，让对端确认会话
+            None => self.send_data(&mut state, &[]),
+        }
+    }
+    fn rotate(&self, state: &mut PeerState, session: Session) {
+        let old = state.previous.take();
+        self.remove_index(old);
+        state.previous = state.current.replace(session);
+    }
+    fn handle_data(&self, buf: &[u8], addr: SocketAddr) -> io::Result<()> {
+        if buf.len() < DATA_HEAD_LEN + TAG_LEN {
+            return Ok(());
+        }
+        let receiver = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+        let counter = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+        let peer_index = match self.inner.index_map.read().get(&receiver) {
+            Some(index) => *index,
+            None => return Ok(()),
+        };
+        let peer = &self.inner.peers[peer_index];
+        let mut state = peer.state.lock();
+        let state = &mut *state;
+        let is_next = state
+            .next
+            .as_ref()
+            .map_or(false, |v| v.local_index == receiver);
+        let session = if is_next {
+            state.next.as_mut()
+        } else {
+            [state.current.as_mut(), state.previous.as_mut()]
+                .into_iter()
+                .flatten()
+                .find(|v| v.local_index == receiver)
+        };
+        let session = match session {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        if session.time.elapsed() >= REJECT_AFTER_TIME {
+            return Ok(());
+        }
+        let plain = match decrypt_data(&session.recv_key, counter, &buf[DATA_HEAD_LEN..]) {
+            Some(plain) => plain,
+            None => return Ok(()),
+        };
+        if !session.replay.check(counter) {
+            return Ok(());
+        }
+        state.endpoint = Some(addr);
+        state.last_recv = Some(Instant::now());
+        if is_next {
+            // 对端已经在用新会话，切换过去
+            let session = state.next.take().unwrap();
+            self.rotate(state, session);
+            if let Some(packet) = state.pending.take() {
+                self.send_data(state, &packet)?;
+            }
+        }
+        if plain.is_empty() {
+            // 保活包
+            return Ok(());
+        }
+        if plain.len() < 20 || plain[0] >> 4 != 4 {
+            return Ok(());
+        }
+        let total_len = u16::from_be_bytes(plain[2..4].try_into().unwrap()) as usize;
+        let source = Ipv4Addr::new(plain[12], plain[13], plain[14], plain[15]);
+        if total_len < 20 || total_len > plain.len() || !peer.contains(&source) {
+            return Ok(());
+        }
+        (self.inner.writer)(&plain[..total_len])?;
+        Ok(())
+    }
+    fn timer(&self) {
+        for (index, peer) in self.inner.peers.iter().enumerate() {
+            let mut state = peer.state.lock();
+            if let Err(e) = self.timer0(index, peer, &mut state) {
+                log::warn!("wg {:?} {:?}", state.endpoint, e);
+            }
+        }
+    }
+    fn timer0(&self, index: usize, peer: &Peer, state: &mut PeerState) -> io::Result<()> {
+        // 过期的会话不能再使用
+        for session in [&mut state.current, &mut state.previous, &mut state.next] {
+            if session
+                .as_ref()
+                .map_or(false, |v| v.time.elapsed() >= REJECT_AFTER_TIME)
+            {
+                let old = session.take();
+                self.remove_index(old);
+            }
+        }
+        if let Some((_, time)) = state.handshake.as_ref() {
+            if time.elapsed() < REKEY_TIMEOUT {
+                return Ok(());
+            }
+            if state
+                .handshake_start
+                .map_or(false, |v| v.elapsed() >= REKEY_ATTEMPT_TIME)
+            {
+                // 一直没有回应，放弃
+                if let Some((old, _)) = state.handshake.take() {
+                    self.inner.index_map.write().remove(&old.local_index);
+                }
+                state.handshake_start = None;
+                state.pending = None;
+                return Ok(());
+            }
+            return self.initiate(index, state);
+        }
+        let received = state
+            .last_recv
+            .map_or(false, |v| v.elapsed() < KEEPALIVE_TIMEOUT);
+        // 没有会话或者本端发起的会话快过期时重新握手
+        let rekey = state.current.as_ref().map_or(true, |v| {
+            v.initiator && v.time.elapsed() >= REKEY_AFTER_TIME
+        });
+        if rekey && (peer.persistent || received) {
+            return self.initiate(index, state);
+        }
+        if state.current.is_none() {
+            return Ok(());
+        }
+        let keepalive = if peer.persistent {
+            state.last_send.elapsed() >= PERSISTENT_KEEPALIVE
+        } else {
+            // 收到了数据但是一直没有回复，发个空包让对端知道会话还在
+            received
+                && state.last_send.elapsed() >= KEEPALIVE_TIMEOUT
+                && state.last_recv.map_or(false, |v| v > state.last_send)
+        };
+        if keepalive {
+            self.send_data(state, &[])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::{Debug, Formatter};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use base64::Engine;
+
+#[cfg(feature = "wireguard")]
+mod endpoint;
+#[cfg(feature = "wireguard")]
+mod noise;
+#[cfg(feature = "wireguard")]
+pub use endpoint::WireGuard;
+
+pub const DEFAULT_WG_PORT: u16 = 51820;
+
+/// 解析base64编码的32字节密钥
+pub fn decode_key(s: &str) -> anyhow::Result<[u8; 32]> {
+    let key = base64::engine::general_purpose::STANDARD
+        .decode(s.trim())
+        .map_err(|e| anyhow!("key {:?} {}", s, e))?;
+    key.try_into()
+        .map_err(|_| anyhow!("key {:?} must be 32 bytes", s))
+}
+
+pub fn encode_key(key: &[u8; 32]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(key)
+}
+
+/// 以wg协议直接接入的对端，格式 公钥,ip/前缀[,对端地址]
+#[derive(Clone, Eq, PartialEq)]
+pub struct WgPeerConfig {
+    pub public_key: [u8; 32],
+    // 对端的地址段，只有来源在这个网段的数据才会接收，发往这个网段的数据交给该对端
+    pub ip: Ipv4Addr,
+    pub mask: Ipv4Addr,
+    // 没有配置时等待对端先发起握手
+    pub endpoint: Option<SocketAddr>,
+}
+
+impl WgPeerConfig {
+    pub fn contains(&self, ip: &Ipv4Addr) -> bool {
+        u32::from(*ip) & u32::from(self.mask) == u32::from(self.ip)
+    }
+}
+
+impl FromStr for WgPeerConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            format!(
+                "not match '{}', example: <public key>,10.27.0.2/32,1.2.3.4:51820",
+                s
+            )
+        };
+        let mut split = s.split(',').map(|v| v.trim());
+        let public_key = decode_key(split.next().ok_or_else(err)?).map_err(|e| e.to_string())?;
+        let (ip, prefix) = match split.next().ok_or_else(err)?.split_once('/') {
+            Some((ip, prefix)) => (ip, prefix.parse::<u8>().map_err(|_| err())?),
+            None => return Err(err()),
+        };
+        if prefix > 32 {
+            return Err(err());
+        }
+        let mask = Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0));
+        let ip = Ipv4Addr::from_str(ip).map_err(|_| err())?;
+        let ip = Ipv4Addr::from(u32::from(ip) & u32::from(mask));
+        let endpoint = match split.next() {
+            Some(endpoint) => Some(SocketAddr::from_str(endpoint).map_err(|_| err())?),
+            None => None,
+        };
+        if split.next().is_some() {
+            return Err(err());
+        }
+        Ok(WgPeerConfig {
+            public_key,
+            ip,
+            mask,
+            endpoint,
+        })
+    }
+}
+
+impl Debug for WgPeerConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WgPeerConfig")
+            .field("public_key", &encode_key(&self.public_key))
+            .field("ip", &self.ip)
+            .field("mask", &self.mask)
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+/// wg互通模式，本机作为wg节点，让只能安装官方wg客户端的设备接入
+#[derive(Clone)]
+pub struct WgConfig {
+    pub private_key: [u8; 32],
+    pub port: u16,
+    pub peers: Vec<WgPeerConfig>,
+}
+
+impl WgConfig {
+    pub fn new(private_key: &str, port: u16, peers: &[String]) -> anyhow::Result<Self> {
+        let private_key = decode_key(private_key)?;
+        let mut list = Vec::with_capacity(peers.len());
+        for peer in peers {
+            let peer = WgPeerConfig::from_str(peer).map_err(|e| anyhow!("{}", e))?;
+            if list
+                .iter()
+                .any(|v: &WgPeerConfig| v.public_key == peer.public_key)
+            {
+                return Err(anyhow!(
+                    "wg peer {:?} repeated",
+                    encode_key(&peer.public_key)
+                ));
+            }
+            list.push(peer);
+        }
+        if list.is_empty() {
+            return Err(anyhow!("wg peer is empty"));
+        }
+        Ok(Self {
+            private_key,
+            port,
+            peers: list,
+        })
+    }
+}
+
+impl Debug for WgConfig {
+    // 不能打印私钥
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WgConfig")
+            .field("port", &self.port)
+            .field("peers", &self.peers)
+            .finish()
+    }
+}
+
+#[test]
+fn test_wg_peer_config() {
+    let key = encode_key(&[7u8; 32]);
+    let peer = WgPeerConfig::from_str(&format!("{},10.27.0.9/24,1.2.3.4:51820", key)).unwrap();
+    assert_eq!(peer.public_key, [7u8; 32]);
+    assert_eq!(peer.ip, Ipv4Addr::new(10, 27, 0, 0));
+    assert_eq!(peer.mask, Ipv4Addr::new(255, 255, 255, 0));
+    assert_eq!(peer.endpoint, Some("1.2.3.4:51820".parse().unwrap()));
+    assert!(peer.contains(&Ipv4Addr::new(10, 27, 0, 200)));
+    assert!(!peer.contains(&Ipv4Addr::new(10, 26, 0, 2)));
+    let peer = WgPeerConfig::from_str(&format!("{},10.27.0.2/32", key)).unwrap();
+    assert_eq!(peer.endpoint, None);
+    assert!(WgPeerConfig::from_str(&format!("{},10.27.0.2", key)).is_err());
+    assert!(WgPeerConfig::from_str("abc,10.27.0.2/32").is_err());
+}
@@ -0,0 +1,237 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub use crate::cipher::noise::public_key;
+use crate::cipher::noise::{dh, hash, kdf, mac, new_ephemeral, open, seal};
+
+// Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s，和官方wg保持一致
+const CONSTRUCTION: &[u8] = b"Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+const LABEL_MAC1: &[u8] = b"mac1----";
+
+pub const MSG_INITIATION: u8 = 1;
+pub const MSG_RESPONSE: u8 = 2;
+pub const MSG_COOKIE: u8 = 3;
+pub const MSG_DATA: u8 = 4;
+pub const INITIATION_LEN: usize = 148;
+pub const RESPONSE_LEN: usize = 92;
+// 类型(4) | 接收方索引(4) | 计数(8)
+pub const DATA_HEAD_LEN: usize = 16;
+pub const TAG_LEN: usize = 16;
+
+/// TAI64N时间戳，对端用来拒绝重放的握手包
+fn timestamp() -> [u8; 12] {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut buf = [0u8; 12];
+    buf[..8].copy_from_slice(&(now.as_secs() + 0x4000_0000_0000_000a).to_be_bytes());
+    buf[8..].copy_from_slice(&now.subsec_nanos().to_be_bytes());
+    buf
+}
+
+/// 本端的静态密钥
+pub struct StaticKeys {
+    secret: StaticSecret,
+    pub public: [u8; 32],
+    // 校验发给自己的握手包mac1
+    mac1_key: [u8; 32],
+}
+
+impl StaticKeys {
+    pub fn new(private_key: &[u8; 32]) -> Self {
+        let secret = StaticSecret::from(*private_key);
+        let public = PublicKey::from(&secret).to_bytes();
+        Self {
+            secret,
+            public,
+            mac1_key: hash(&[LABEL_MAC1, &public]),
+        }
+    }
+    fn check_mac1(&self, msg: &[u8], mac1_offset: usize) -> bool {
+        mac(&self.mac1_key, &msg[..mac1_offset]) == msg[mac1_offset..mac1_offset + 16]
+    }
+}
+
+/// 握手完成后的传输密钥
+pub struct SessionKeys {
+    pub send: [u8; 32],
+    pub recv: [u8; 32],
+}
+
+/// 发起握手后等待回应的状态
+pub struct InitiatorState {
+    pub local_index: u32,
+    ck: [u8; 32],
+    h: [u8; 32],
+    ephemeral: StaticSecret,
+}
+
+/// 收到的握手请求
+pub struct Initiation {
+    pub sender_index: u32,
+    pub peer_public: [u8; 32],
+    pub timestamp: [u8; 12],
+    ck: [u8; 32],
+    h: [u8; 32],
+    ephemeral: [u8; 32],
+}
+
+fn init_ck_h(responder_public: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let ck = hash(&[CONSTRUCTION]);
+    let h = hash(&[&ck, IDENTIFIER]);
+    (ck, hash(&[&h, responder_public]))
+}
+
+/// 握手请求: 类型(4) | 发送方索引(4) | 临时公钥(32) | 静态公钥(32+16) | 时间戳(12+16) | mac1(16) | mac2(16)
+pub fn create_initiation(
+    keys: &StaticKeys,
+    peer_public: &[u8; 32],
+    local_index: u32,
+) -> ([u8; INITIATION_LEN], InitiatorState) {
+    let mut msg = [0u8; INITIATION_LEN];
+    msg[0] = MSG_INITIATION;
+    msg[4..8].copy_from_slice(&local_index.to_le_bytes());
+    let (ck, h) = init_ck_h(peer_public);
+    let ephemeral = new_ephemeral();
+    let ephemeral_public = PublicKey::from(&ephemeral).to_bytes();
+    msg[8..40].copy_from_slice(&ephemeral_public);
+    let [ck] = kdf(&ck, &ephemeral_public);
+    let h = hash(&[&h, &ephemeral_public]);
+    let [ck, k] = kdf(&ck, &dh(&ephemeral, peer_public));
+    let encrypted_static = seal(&k, 0, &keys.public, &h);
+    msg[40..88].copy_from_slice(&encrypted_static);
+    let h = hash(&[&h, &encrypted_static]);
+    let [ck, k] = kdf(&ck, &dh(&keys.secret, peer_public));
+    let encrypted_timestamp = seal(&k, 0, &timestamp(), &h);
+    msg[88..116].copy_from_slice(&encrypted_timestamp);
+    let h = hash(&[&h, &encrypted_timestamp]);
+    let mac1 = mac(&hash(&[LABEL_MAC1, peer_public]), &msg[..116]);
+    msg[116..132].copy_from_slice(&mac1);
+    (
+        msg,
+        InitiatorState {
+            local_index,
+            ck,
+            h,
+            ephemeral,
+        },
+    )
+}
+
+pub fn consume_initiation(keys: &StaticKeys, msg: &[u8]) -> Option<Initiation> {
+    if msg.len() != INITIATION_LEN || msg[0] != MSG_INITIATION || !keys.check_mac1(msg, 116) {
+        return None;
+    }
+    let sender_index = u32::from_le_bytes(msg[4..8].try_into().unwrap());
+    let ephemeral: [u8; 32] = msg[8..40].try_into().unwrap();
+    let (ck, h) = init_ck_h(&keys.public);
+    let [ck] = kdf(&ck, &ephemeral);
+    let h = hash(&[&h, &ephemeral]);
+    let [ck, k] = kdf(&ck, &dh(&keys.secret, &ephemeral));
+    let peer_public: [u8; 32] = open(&k, 0, &msg[40..88], &h)?.try_into().ok()?;
+    let h = hash(&[&h, &msg[40..88]]);
+    let [ck, k] = kdf(&ck, &dh(&keys.secret, &peer_public));
+    let timestamp: [u8; 12] = open(&k, 0, &msg[88..116], &h)?.try_into().ok()?;
+    let h = hash(&[&h, &msg[88..116]]);
+    Some(Initiation {
+        sender_index,
+        peer_public,
+        timestamp,
+        ck,
+        h,
+        ephemeral,
+    })
+}
+
+/// 握手回应: 类型(4) | 发送方索引(4) | 接收方索引(4) | 临时公钥(32) | 空(0+16) | mac1(16) | mac2(16)
+pub fn create_response(
+    keys: &StaticKeys,
+    initiation: &Initiation,
+    local_index: u32,
+) -> ([u8; RESPONSE_LEN], SessionKeys) {
+    let mut msg = [0u8; RESPONSE_LEN];
+    msg[0] = MSG_RESPONSE;
+    msg[4..8].copy_from_slice(&local_index.to_le_bytes());
+    msg[8..12].copy_from_slice(&initiation.sender_index.to_le_bytes());
+    let ephemeral = new_ephemeral();
+    let ephemeral_public = PublicKey::from(&ephemeral).to_bytes();
+    msg[12..44].copy_from_slice(&ephemeral_public);
+    let [ck] = kdf(&initiation.ck, &ephemeral_public);
+    let h = hash(&[&initiation.h, &ephemeral_public]);
+    let [ck] = kdf(&ck, &dh(&ephemeral, &initiation.ephemeral));
+    let [ck] = kdf(&ck, &dh(&ephemeral, &initiation.peer_public));
+    // 不使用预共享密钥
+    let [ck, t, k] = kdf(&ck, &[0u8; 32]);
+    let h = hash(&[&h, &t]);
+    let empty = seal(&k, 0, &[], &h);
+    msg[44..60].copy_from_slice(&empty);
+    let mac1 = mac(&hash(&[LABEL_MAC1, &initiation.peer_public]), &msg[..60]);
+    msg[60..76].copy_from_slice(&mac1);
+    let [recv, send] = kdf(&ck, &[]);
+    (msg, SessionKeys { send, recv })
+}
+
+/// 校验握手回应，返回对端索引和传输密钥
+pub fn consume_response(
+    keys: &StaticKeys,
+    state: &InitiatorState,
+    msg: &[u8],
+) -> Option<(u32, SessionKeys)> {
+    if msg.len() != RESPONSE_LEN || msg[0] != MSG_RESPONSE || !keys.check_mac1(msg, 60) {
+        return None;
+    }
+    let sender_index = u32::from_le_bytes(msg[4..8].try_into().unwrap());
+    if u32::from_le_bytes(msg[8..12].try_into().unwrap()) != state.local_index {
+        return None;
+    }
+    let ephemeral: [u8; 32] = msg[12..44].try_into().unwrap();
+    let [ck] = kdf(&state.ck, &ephemeral);
+    let h = hash(&[&state.h, &ephemeral]);
+    let [ck] = kdf(&ck, &dh(&state.ephemeral, &ephemeral));
+    let [ck] = kdf(&ck, &dh(&keys.secret, &ephemeral));
+    let [ck, t, k] = kdf(&ck, &[0u8; 32]);
+    let h = hash(&[&h, &t]);
+    open(&k, 0, &msg[44..60], &h)?;
+    let [send, recv] = kdf(&ck, &[]);
+    Some((sender_index, SessionKeys { send, recv }))
+}
+
+/// 数据包: 类型(4) | 接收方索引(4) | 计数(8) | 加密的ip包(按16字节补齐)
+pub fn encrypt_data(key: &[u8; 32], receiver_index: u32, counter: u64, packet: &[u8]) -> Vec<u8> {
+    let mut plain = packet.to_vec();
+    plain.resize((packet.len() + 15) / 16 * 16, 0);
+    let mut buf = Vec::with_capacity(DATA_HEAD_LEN + plain.len() + TAG_LEN);
+    buf.extend_from_slice(&[MSG_DATA, 0, 0, 0]);
+    buf.extend_from_slice(&receiver_index.to_le_bytes());
+    buf.extend_from_slice(&counter.to_le_bytes());
+    buf.extend_from_slice(&seal(key, counter, &plain, &[]));
+    buf
+}
+
+pub fn decrypt_data(key: &[u8; 32], counter: u64, data: &[u8]) -> Option<Vec<u8>> {
+    open(key, counter, data, &[])
+}
+
+#[test]
+fn test_handshake() {
+    let initiator = StaticKeys::new(&rand::random());
+    let responder = StaticKeys::new(&rand::random());
+    let (msg, state) = create_initiation(&initiator, &responder.public, 1);
+    let initiation = consume_initiation(&responder, &msg).unwrap();
+    assert_eq!(initiation.peer_public, initiator.public);
+    assert_eq!(initiation.sender_index, 1);
+    // 发给其他节点的握手包mac1校验不通过
+    assert!(consume_initiation(&initiator, &msg).is_none());
+    let (msg, responder_keys) = create_response(&responder, &initiation, 2);
+    let (index, initiator_keys) = consume_response(&initiator, &state, &msg).unwrap();
+    assert_eq!(index, 2);
+    assert_eq!(initiator_keys.send, responder_keys.recv);
+    assert_eq!(initiator_keys.recv, responder_keys.send);
+    let data = encrypt_data(&initiator_keys.send, 2, 9, &[1, 2, 3]);
+    assert_eq!(data.len(), DATA_HEAD_LEN + 16 + TAG_LEN);
+    let plain = decrypt_data(&responder_keys.recv, 9, &data[DATA_HEAD_LEN..]).unwrap();
+    assert_eq!(&plain[..3], &[1, 2, 3]);
+    assert!(decrypt_data(&responder_keys.recv, 8, &data[DATA_HEAD_LEN..]).is_none());
+}
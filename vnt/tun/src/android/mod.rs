@@ -1,7 +1,7 @@
 use crate::device::IFace;
 use crate::Fd;
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::os::fd::RawFd;
 
 pub struct Device {
@@ -35,6 +35,10 @@ impl IFace for Device {
         Err(io::Error::from(io::ErrorKind::Unsupported))
     }
 
+    fn set_ipv6(&self, _address: Ipv6Addr, _prefix: u8) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
     fn mtu(&self) -> io::Result<u32> {
         Err(io::Error::from(io::ErrorKind::Unsupported))
     }
@@ -0,0 +1,250 @@
+#![allow(dead_code)]
+use std::ffi::CString;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::fd::AsRawFd;
+use std::{io, mem, ptr};
+
+use libc::{c_char, c_short, AF_INET, AF_INET6, IFF_RUNNING, IFF_UP, IFNAMSIZ, O_RDWR, SOCK_DGRAM};
+
+use crate::bsd::sys::*;
+use crate::device::IFace;
+use crate::unix::{route, sockaddr_v4, sockaddr_v6, Fd, SockAddr};
+
+// 依次尝试打开/dev/tunN，FreeBSD和新版OpenBSD在打开时自动创建网卡
+const MAX_UNIT: u32 = 256;
+
+pub struct Device {
+    name: String,
+    ctl: Fd,
+    tun: Fd,
+}
+
+impl Device {
+    /// name为tunN，不指定时使用第一个空闲的tun网卡
+    pub fn new(name: Option<String>) -> io::Result<Self> {
+        let (name, tun) = match name {
+            Some(name) => {
+                if name.len() >= IFNAMSIZ {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "name too long"));
+                }
+                if !name.starts_with("tun") {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid name"));
+                }
+                let tun = open(&name)?;
+                (name, tun)
+            }
+            None => {
+                let mut device = None;
+                for unit in 0..MAX_UNIT {
+                    let name = format!("tun{}", unit);
+                    match open(&name) {
+                        Ok(tun) => {
+                            device = Some((name, tun));
+                            break;
+                        }
+                        // 被其他程序占用
+                        Err(e) if e.raw_os_error() == Some(libc::EBUSY) => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+                match device {
+                    Some(device) => device,
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            "no free tun device",
+                        ))
+                    }
+                }
+            }
+        };
+        #[cfg(target_os = "freebsd")]
+        unsafe {
+            // 开启后和OpenBSD一样，每个包前面都有4字节的地址族，才能同时收发ipv4和ipv6
+            let head: libc::c_int = 1;
+            if tunsifhead(tun.0, &head) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let ctl = Fd::new(unsafe { libc::socket(AF_INET, SOCK_DGRAM, 0) })?;
+        let device = Device { name, ctl, tun };
+        device.enabled(true)?;
+        Ok(device)
+    }
+}
+
+fn open(name: &str) -> io::Result<Fd> {
+    let path = CString::new(format!("/dev/{}", name))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    Fd::new(unsafe { libc::open(path.as_ptr(), O_RDWR) }).map_err(|_| io::Error::last_os_error())
+}
+
+impl Device {
+    fn enabled(&self, value: bool) -> io::Result<()> {
+        unsafe {
+            let mut req = self.request();
+
+            if siocgifflags(self.ctl.as_raw_fd(), &mut req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if value {
+                req.ifru.flags |= (IFF_UP | IFF_RUNNING) as c_short;
+            } else {
+                req.ifru.flags &= !(IFF_UP as c_short);
+            }
+
+            if siocsifflags(self.ctl.as_raw_fd(), &req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        }
+    }
+    unsafe fn request(&self) -> ifreq {
+        let mut req: ifreq = mem::zeroed();
+        ptr::copy_nonoverlapping(
+            self.name.as_ptr() as *const c_char,
+            req.name.as_mut_ptr(),
+            self.name.len(),
+        );
+        req
+    }
+    fn address(&self) -> io::Result<Ipv4Addr> {
+        unsafe {
+            let mut req = self.request();
+
+            if siocgifaddr(self.ctl.as_raw_fd(), &mut req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            SockAddr::new(&req.ifru.addr).map(Into::into)
+        }
+    }
+}
+
+impl Device {
+    pub fn as_tun_fd(&self) -> &Fd {
+        &self.tun
+    }
+}
+
+impl IFace for Device {
+    fn version(&self) -> io::Result<String> {
+        Ok(String::new())
+    }
+
+    fn name(&self) -> io::Result<String> {
+        Ok(self.name.clone())
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        unsafe {
+            let req = self.request();
+            if siocifdestroy(self.ctl.as_raw_fd(), &req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    /// 地址和掩码通过SIOCAIFADDR一次设置，旧地址先删除
+    fn set_ip(&self, address: Ipv4Addr, mask: Ipv4Addr) -> io::Result<()> {
+        unsafe {
+            if let Ok(old) = self.address() {
+                if old != address {
+                    let mut req = self.request();
+                    req.ifru.addr = sockaddr_v4(old);
+                    if siocdifaddr(self.ctl.as_raw_fd(), &req) < 0 {
+                        log::warn!("delete address {} {:?}", old, io::Error::last_os_error());
+                    }
+                }
+            }
+            let mut req: in_aliasreq = mem::zeroed();
+            ptr::copy_nonoverlapping(
+                self.name.as_ptr() as *const c_char,
+                req.ifra_name.as_mut_ptr(),
+                self.name.len(),
+            );
+            req.ifra_addr = sockaddr_v4(address);
+            // tun是点对点网卡，对端地址使用本机地址
+            req.ifra_dstaddr = sockaddr_v4(address);
+            req.ifra_mask = sockaddr_v4(mask);
+            if siocaifaddr(self.ctl.as_raw_fd(), &req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        unsafe {
+            let ctl = Fd::new(libc::socket(AF_INET6, SOCK_DGRAM, 0))?;
+            let mut req: in6_aliasreq = mem::zeroed();
+            ptr::copy_nonoverlapping(
+                self.name.as_ptr() as *const c_char,
+                req.ifra_name.as_mut_ptr(),
+                self.name.len(),
+            );
+            req.ifra_addr = sockaddr_v6(address);
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            req.ifra_prefixmask = sockaddr_v6(Ipv6Addr::from(mask));
+            req.ifra_lifetime.ia6t_vltime = ND6_INFINITE_LIFETIME;
+            req.ifra_lifetime.ia6t_pltime = ND6_INFINITE_LIFETIME;
+            if siocaifaddr_in6(ctl.as_raw_fd(), &req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    fn mtu(&self) -> io::Result<u32> {
+        unsafe {
+            let mut req = self.request();
+
+            if siocgifmtu(self.ctl.as_raw_fd(), &mut req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(req.ifru.mtu as _)
+        }
+    }
+
+    fn set_mtu(&self, value: u32) -> io::Result<()> {
+        unsafe {
+            let mut req = self.request();
+            req.ifru.mtu = value as _;
+
+            if siocsifmtu(self.ctl.as_raw_fd(), &req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        }
+    }
+
+    fn add_route(&self, dest: Ipv4Addr, netmask: Ipv4Addr, _metric: u16) -> io::Result<()> {
+        route::add_route(&self.name, dest, netmask)
+    }
+
+    fn delete_route(&self, dest: Ipv4Addr, netmask: Ipv4Addr) -> io::Result<()> {
+        route::del_route(&self.name, dest, netmask)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.tun.read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut packet = Vec::<u8>::with_capacity(4 + buf.len());
+        // 包头是网络字节序的地址族
+        let family = if buf.first().map(|v| v >> 4) == Some(6) {
+            AF_INET6
+        } else {
+            AF_INET
+        };
+        packet.extend_from_slice(&(family as u32).to_be_bytes());
+        packet.extend_from_slice(buf);
+        self.tun.write(&packet)
+    }
+}
@@ -0,0 +1,86 @@
+//! FreeBSD/OpenBSD的if_tun和网卡配置
+
+use ioctl::*;
+use libc::{c_char, c_int, c_short, c_void, sockaddr, sockaddr_in6, time_t, IFNAMSIZ};
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union ifru {
+    pub addr: sockaddr,
+    pub flags: c_short,
+    pub mtu: c_int,
+    pub data: *mut c_void,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ifreq {
+    pub name: [c_char; IFNAMSIZ],
+    pub ifru: ifru,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct in_aliasreq {
+    pub ifra_name: [c_char; IFNAMSIZ],
+    pub ifra_addr: sockaddr,
+    pub ifra_dstaddr: sockaddr,
+    pub ifra_mask: sockaddr,
+    #[cfg(target_os = "freebsd")]
+    pub ifra_vhid: c_int,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct in6_addrlifetime {
+    pub ia6t_expire: time_t,
+    pub ia6t_preferred: time_t,
+    pub ia6t_vltime: u32,
+    pub ia6t_pltime: u32,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct in6_aliasreq {
+    pub ifra_name: [c_char; IFNAMSIZ],
+    pub ifra_addr: sockaddr_in6,
+    pub ifra_dstaddr: sockaddr_in6,
+    pub ifra_prefixmask: sockaddr_in6,
+    pub ifra_flags: c_int,
+    pub ifra_lifetime: in6_addrlifetime,
+    #[cfg(target_os = "freebsd")]
+    pub ifra_vhid: c_int,
+}
+
+pub const ND6_INFINITE_LIFETIME: u32 = 0xffff_ffff;
+
+ioctl!(write siocsifflags with 'i', 16; ifreq);
+ioctl!(readwrite siocgifflags with 'i', 17; ifreq);
+ioctl!(readwrite siocgifaddr with 'i', 33; ifreq);
+ioctl!(write siocdifaddr with 'i', 25; ifreq);
+ioctl!(write siocifdestroy with 'i', 121; ifreq);
+
+#[cfg(target_os = "freebsd")]
+ioctl!(write siocaifaddr with 'i', 43; in_aliasreq);
+#[cfg(target_os = "freebsd")]
+ioctl!(write siocaifaddr_in6 with 'i', 27; in6_aliasreq);
+#[cfg(target_os = "freebsd")]
+ioctl!(write siocsifmtu with 'i', 52; ifreq);
+#[cfg(target_os = "freebsd")]
+ioctl!(readwrite siocgifmtu with 'i', 51; ifreq);
+// 读写的数据带上4字节的地址族
+#[cfg(target_os = "freebsd")]
+ioctl!(write tunsifhead with 't', 96; c_int);
+
+#[cfg(target_os = "openbsd")]
+ioctl!(write siocaifaddr with 'i', 26; in_aliasreq);
+#[cfg(target_os = "openbsd")]
+ioctl!(write siocaifaddr_in6 with 'i', 26; in6_aliasreq);
+#[cfg(target_os = "openbsd")]
+ioctl!(write siocsifmtu with 'i', 127; ifreq);
+#[cfg(target_os = "openbsd")]
+ioctl!(readwrite siocgifmtu with 'i', 126; ifreq);
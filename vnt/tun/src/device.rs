@@ -1,6 +1,6 @@
 use io::Result;
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 pub trait IFace {
     fn version(&self) -> Result<String>;
@@ -10,6 +10,8 @@ pub trait IFace {
     fn shutdown(&self) -> Result<()>;
 
     fn set_ip(&self, address: Ipv4Addr, mask: Ipv4Addr) -> Result<()>;
+    /// 添加ipv6地址
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> Result<()>;
 
     /// Get the MTU.
     fn mtu(&self) -> Result<u32>;
@@ -19,6 +19,11 @@ mod macos;
 #[cfg(target_os = "macos")]
 pub use macos::Device;
 
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+mod bsd;
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+pub use bsd::Device;
+
 #[cfg(unix)]
 mod unix;
 #[cfg(unix)]
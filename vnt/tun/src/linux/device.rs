@@ -1,27 +1,29 @@
 #![allow(dead_code)]
 use std::ffi::{CStr, CString};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::os::fd::AsRawFd;
 use std::{io, mem, ptr};
 
 use libc::{
-    c_char, c_short, ifreq, AF_INET, IFF_MULTI_QUEUE, IFF_NO_PI, IFF_RUNNING, IFF_TUN, IFF_UP,
-    IFNAMSIZ, O_RDWR, SOCK_DGRAM,
+    c_char, c_short, ifreq, AF_INET, IFF_MULTI_QUEUE, IFF_NO_PI, IFF_RUNNING, IFF_TAP, IFF_TUN,
+    IFF_UP, IFNAMSIZ, O_RDWR, SOCK_DGRAM,
 };
 
 use crate::device::IFace;
-use crate::linux::route;
 use crate::linux::sys::*;
-use crate::unix::{exe_cmd, Fd, SockAddr};
+use crate::linux::{netlink, route};
+use crate::unix::{Fd, SockAddr};
 
 pub struct Device {
     name: String,
+    index: u32,
     ctl: Fd,
     tun: Fd,
 }
 
 impl Device {
-    pub fn new(name: Option<String>) -> io::Result<Self> {
+    /// tap为true时创建二层设备，读写的是完整的以太网帧
+    pub fn new(name: Option<String>, tap: bool) -> io::Result<Self> {
         let device = unsafe {
             let dev = match name {
                 Some(name) => {
@@ -48,7 +50,7 @@ impl Device {
                 );
             }
 
-            let device_type: c_short = IFF_TUN as c_short; //if tap { IFF_TAP } else { IFF_TUN } as c_short;
+            let device_type: c_short = if tap { IFF_TAP } else { IFF_TUN } as c_short;
 
             let queues_num = 1;
 
@@ -71,15 +73,24 @@ impl Device {
             let name = CStr::from_ptr(req.ifr_name.as_ptr())
                 .to_string_lossy()
                 .to_string();
-            let set_txqueuelen = format!("ifconfig {} txqueuelen 1000", name);
-            if let Err(e) = exe_cmd(&set_txqueuelen) {
-                log::warn!("{:?}", e);
+            let index = netlink::if_index(&name)?;
+            if let Err(e) = netlink::set_link(index, None, Some(1000)) {
+                log::warn!("set txqueuelen {} {:?}", name, e);
+            }
+            Device {
+                name,
+                index,
+                tun,
+                ctl,
             }
-            Device { name, tun, ctl }
         };
         device.enabled(true)?;
         Ok(device)
     }
+    /// 删除指定名称的网卡
+    pub fn delete(name: &str) -> io::Result<()> {
+        netlink::delete_link(netlink::if_index(name)?)
+    }
 }
 
 impl Device {
@@ -230,12 +241,23 @@ impl IFace for Device {
     }
 
     fn shutdown(&self) -> io::Result<()> {
-        exe_cmd(&format!("ip link delete {}", self.name))?;
-        Ok(())
+        netlink::delete_link(self.index)
     }
+    /// 地址和掩码通过netlink一次设置，旧地址先删除
     fn set_ip(&self, address: Ipv4Addr, mask: Ipv4Addr) -> io::Result<()> {
-        self.set_address(address)?;
-        self.set_netmask(mask)
+        let prefix = u32::from(mask).count_ones() as u8;
+        if let (Ok(old), Ok(old_mask)) = (self.address(), self.netmask()) {
+            if old != address {
+                let old_prefix = u32::from(old_mask).count_ones() as u8;
+                if let Err(e) = netlink::del_address(self.index, old.into(), old_prefix) {
+                    log::warn!("delete address {} {:?}", old, e);
+                }
+            }
+        }
+        netlink::add_address(self.index, address.into(), prefix)
+    }
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        netlink::add_address(self.index, address.into(), prefix)
     }
 
     fn mtu(&self) -> io::Result<u32> {
@@ -251,24 +273,15 @@ impl IFace for Device {
     }
 
     fn set_mtu(&self, value: u32) -> io::Result<()> {
-        unsafe {
-            let mut req = self.request();
-            req.ifr_ifru.ifru_mtu = value as _;
-
-            if siocsifmtu(self.ctl.as_raw_fd(), &req) < 0 {
-                return Err(io::Error::last_os_error());
-            }
-
-            Ok(())
-        }
+        netlink::set_link(self.index, Some(value), None)
     }
 
     fn add_route(&self, dest: Ipv4Addr, netmask: Ipv4Addr, _metric: u16) -> io::Result<()> {
-        route::add_route(&self.name, dest, netmask)
+        route::add_route(self.index, dest, netmask)
     }
 
     fn delete_route(&self, dest: Ipv4Addr, netmask: Ipv4Addr) -> io::Result<()> {
-        route::del_route(&self.name, dest, netmask)
+        route::del_route(self.index, dest, netmask)
     }
 
     fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
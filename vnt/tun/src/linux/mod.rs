@@ -1,4 +1,5 @@
 mod device;
 pub use device::Device;
+mod netlink;
 mod route;
 mod sys;
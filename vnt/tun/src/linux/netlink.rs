@@ -0,0 +1,232 @@
+use std::ffi::CString;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::{io, mem};
+
+use libc::{c_void, sockaddr, sockaddr_nl, socklen_t, AF_NETLINK, SOCK_CLOEXEC, SOCK_RAW};
+
+use crate::linux::sys::*;
+use crate::unix::Fd;
+
+// 消息和属性都按4字节对齐
+const ALIGN: usize = 4;
+static SEQ: AtomicU32 = AtomicU32::new(1);
+
+/// 网卡名称转换成索引
+pub fn if_index(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+/// 设置网卡的mtu和发送队列长度
+pub fn set_link(index: u32, mtu: Option<u32>, txqlen: Option<u32>) -> io::Result<()> {
+    let mut request = Request::new(RTM_NEWLINK, 0, &link_msg(index));
+    if let Some(mtu) = mtu {
+        request.attr(IFLA_MTU, &mtu.to_ne_bytes());
+    }
+    if let Some(txqlen) = txqlen {
+        request.attr(IFLA_TXQLEN, &txqlen.to_ne_bytes());
+    }
+    request.send()
+}
+
+pub fn delete_link(index: u32) -> io::Result<()> {
+    Request::new(RTM_DELLINK, 0, &link_msg(index)).send()
+}
+
+/// 添加地址，已存在时替换
+pub fn add_address(index: u32, address: IpAddr, prefix: u8) -> io::Result<()> {
+    address_request(
+        RTM_NEWADDR,
+        NLM_F_CREATE | NLM_F_REPLACE,
+        index,
+        address,
+        prefix,
+    )
+}
+
+pub fn del_address(index: u32, address: IpAddr, prefix: u8) -> io::Result<()> {
+    address_request(RTM_DELADDR, 0, index, address, prefix)
+}
+
+/// 添加经过网卡的路由，已存在时替换
+pub fn add_route(index: u32, dest: Ipv4Addr, prefix: u8) -> io::Result<()> {
+    route_request(
+        RTM_NEWROUTE,
+        NLM_F_CREATE | NLM_F_REPLACE,
+        index,
+        dest,
+        prefix,
+    )
+}
+
+pub fn del_route(index: u32, dest: Ipv4Addr, prefix: u8) -> io::Result<()> {
+    route_request(RTM_DELROUTE, 0, index, dest, prefix)
+}
+
+fn link_msg(index: u32) -> ifinfomsg {
+    ifinfomsg {
+        ifi_family: libc::AF_UNSPEC as _,
+        __ifi_pad: 0,
+        ifi_type: 0,
+        ifi_index: index as _,
+        ifi_flags: 0,
+        ifi_change: 0,
+    }
+}
+
+fn address_request(
+    msg_type: u16,
+    flags: u16,
+    index: u32,
+    address: IpAddr,
+    prefix: u8,
+) -> io::Result<()> {
+    let (family, octets) = match address {
+        IpAddr::V4(ip) => (libc::AF_INET, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (libc::AF_INET6, ip.octets().to_vec()),
+    };
+    let msg = ifaddrmsg {
+        ifa_family: family as _,
+        ifa_prefixlen: prefix,
+        ifa_flags: 0,
+        ifa_scope: RT_SCOPE_UNIVERSE,
+        ifa_index: index,
+    };
+    let mut request = Request::new(msg_type, flags, &msg);
+    request.attr(IFA_LOCAL, &octets);
+    request.attr(IFA_ADDRESS, &octets);
+    if let IpAddr::V4(ip) = address {
+        if prefix < 31 {
+            // 二层模式下广播地址需要正确设置
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            let broadcast = Ipv4Addr::from(u32::from(ip) | !mask);
+            request.attr(IFA_BROADCAST, &broadcast.octets());
+        }
+    }
+    request.send()
+}
+
+fn route_request(
+    msg_type: u16,
+    flags: u16,
+    index: u32,
+    dest: Ipv4Addr,
+    prefix: u8,
+) -> io::Result<()> {
+    let msg = rtmsg {
+        rtm_family: libc::AF_INET as _,
+        rtm_dst_len: prefix,
+        rtm_src_len: 0,
+        rtm_tos: 0,
+        rtm_table: RT_TABLE_MAIN,
+        rtm_protocol: RTPROT_BOOT,
+        rtm_scope: RT_SCOPE_LINK,
+        rtm_type: RTN_UNICAST,
+        rtm_flags: 0,
+    };
+    let mut request = Request::new(msg_type, flags, &msg);
+    request.attr(RTA_DST, &dest.octets());
+    request.attr(RTA_OIF, &index.to_ne_bytes());
+    request.send()
+}
+
+struct Request {
+    buf: Vec<u8>,
+    seq: u32,
+}
+
+impl Request {
+    fn new<T>(msg_type: u16, flags: u16, msg: &T) -> Self {
+        let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+        let header = nlmsghdr {
+            nlmsg_len: 0,
+            nlmsg_type: msg_type,
+            nlmsg_flags: NLM_F_REQUEST | NLM_F_ACK | flags,
+            nlmsg_seq: seq,
+            nlmsg_pid: 0,
+        };
+        let mut request = Request {
+            buf: Vec::with_capacity(128),
+            seq,
+        };
+        request.put(as_bytes(&header));
+        request.put(as_bytes(msg));
+        request
+    }
+    fn attr(&mut self, attr_type: u16, value: &[u8]) {
+        let header = rtattr {
+            rta_len: (mem::size_of::<rtattr>() + value.len()) as u16,
+            rta_type: attr_type,
+        };
+        self.buf.extend_from_slice(as_bytes(&header));
+        self.put(value);
+    }
+    fn put(&mut self, value: &[u8]) {
+        self.buf.extend_from_slice(value);
+        self.buf
+            .resize(self.buf.len() + (ALIGN - value.len() % ALIGN) % ALIGN, 0);
+    }
+    /// 发送请求并等待内核的确认，失败时返回内核给出的错误码
+    fn send(mut self) -> io::Result<()> {
+        let len = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&len.to_ne_bytes());
+        let fd =
+            Fd::new(unsafe { libc::socket(AF_NETLINK, SOCK_RAW | SOCK_CLOEXEC, NETLINK_ROUTE) })
+                .map_err(|_| io::Error::last_os_error())?;
+        let mut addr: sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = AF_NETLINK as _;
+        let rs = unsafe {
+            libc::sendto(
+                fd.0,
+                self.buf.as_ptr() as *const c_void,
+                self.buf.len(),
+                0,
+                &addr as *const sockaddr_nl as *const sockaddr,
+                mem::size_of::<sockaddr_nl>() as socklen_t,
+            )
+        };
+        if rs < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = fd.read(&mut buf)?;
+            let mut offset = 0;
+            while offset + mem::size_of::<nlmsghdr>() <= len {
+                let header: nlmsghdr =
+                    unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+                let msg_len = header.nlmsg_len as usize;
+                if msg_len < mem::size_of::<nlmsghdr>() || offset + msg_len > len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "netlink message truncated",
+                    ));
+                }
+                if header.nlmsg_seq == self.seq && header.nlmsg_type == NLMSG_ERROR {
+                    let body = offset + mem::size_of::<nlmsghdr>();
+                    if body + 4 > len {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "netlink ack truncated",
+                        ));
+                    }
+                    let error = i32::from_ne_bytes(buf[body..body + 4].try_into().unwrap());
+                    return if error == 0 {
+                        Ok(())
+                    } else {
+                        Err(io::Error::from_raw_os_error(-error))
+                    };
+                }
+                offset += (msg_len + ALIGN - 1) & !(ALIGN - 1);
+            }
+        }
+    }
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
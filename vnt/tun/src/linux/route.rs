@@ -1,25 +1,25 @@
 use std::io;
 use std::net::Ipv4Addr;
 
-use crate::unix::exe_cmd;
+use crate::linux::netlink;
 
-pub fn add_route(name: &str, address: Ipv4Addr, netmask: Ipv4Addr) -> io::Result<()> {
-    let cmd = if netmask.is_broadcast() {
-        format!("route add -host {:?} {}", address, name)
-    } else {
-        format!(
-            "route add -net {}/{} {}",
-            address,
-            u32::from(netmask).count_ones(),
-            name
-        )
-    };
-    exe_cmd(&cmd)?;
-    Ok(())
+// 内核要求目的地址不能带主机位
+fn network(address: Ipv4Addr, netmask: Ipv4Addr) -> (Ipv4Addr, u8) {
+    let mask = u32::from(netmask);
+    (
+        Ipv4Addr::from(u32::from(address) & mask),
+        mask.count_ones() as u8,
+    )
 }
 
-pub fn del_route(name: &str, address: Ipv4Addr, netmask: Ipv4Addr) -> io::Result<()> {
-    let cmd = format!("ip route del {:?}/{:?} dev {}", address, netmask, name);
-    exe_cmd(&cmd)?;
-    Ok(())
+pub fn add_route(index: u32, address: Ipv4Addr, netmask: Ipv4Addr) -> io::Result<()> {
+    let (dest, prefix) = network(address, netmask);
+    netlink::add_route(index, dest, prefix)
+        .map_err(|e| io::Error::new(e.kind(), format!("route add {}/{} {}", dest, prefix, e)))
+}
+
+pub fn del_route(index: u32, address: Ipv4Addr, netmask: Ipv4Addr) -> io::Result<()> {
+    let (dest, prefix) = network(address, netmask);
+    netlink::del_route(index, dest, prefix)
+        .map_err(|e| io::Error::new(e.kind(), format!("route del {}/{} {}", dest, prefix, e)))
 }
@@ -1,18 +1,17 @@
 #![allow(dead_code)]
 use std::ffi::{c_void, CStr};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::os::fd::AsRawFd;
 use std::{io, mem, ptr};
 
 use libc::{
-    c_char, c_short, c_uint, sockaddr, socklen_t, AF_INET, AF_SYSTEM, AF_SYS_CONTROL, IFF_RUNNING,
-    IFF_UP, IFNAMSIZ, PF_SYSTEM, SOCK_DGRAM, SYSPROTO_CONTROL, UTUN_OPT_IFNAME,
+    c_char, c_short, c_uint, sockaddr, socklen_t, AF_INET, AF_INET6, AF_SYSTEM, AF_SYS_CONTROL,
+    IFF_RUNNING, IFF_UP, IFNAMSIZ, PF_SYSTEM, SOCK_DGRAM, SYSPROTO_CONTROL, UTUN_OPT_IFNAME,
 };
 
 use crate::device::IFace;
-use crate::macos::route;
 use crate::macos::sys::*;
-use crate::unix::{Fd, SockAddr};
+use crate::unix::{route, sockaddr_v4, sockaddr_v6, Fd, SockAddr};
 
 pub struct Device {
     name: String,
@@ -244,9 +243,54 @@ impl IFace for Device {
         Ok(())
     }
 
+    /// 地址和掩码通过SIOCAIFADDR一次设置，旧地址先删除
     fn set_ip(&self, address: Ipv4Addr, mask: Ipv4Addr) -> io::Result<()> {
-        self.set_address(address)?;
-        self.set_netmask(mask)
+        unsafe {
+            if let Ok(old) = self.address() {
+                if old != address {
+                    let mut req = self.request();
+                    req.ifru.addr = sockaddr_v4(old);
+                    if siocdifaddr(self.ctl.as_raw_fd(), &req) < 0 {
+                        log::warn!("delete address {} {:?}", old, io::Error::last_os_error());
+                    }
+                }
+            }
+            let mut req: ifaliasreq = mem::zeroed();
+            ptr::copy_nonoverlapping(
+                self.name.as_ptr() as *const c_char,
+                req.ifran.as_mut_ptr(),
+                self.name.len(),
+            );
+            req.addr = sockaddr_v4(address);
+            // utun是点对点网卡，对端地址使用本机地址
+            req.broadaddr = sockaddr_v4(address);
+            req.mask = sockaddr_v4(mask);
+            if siocaifaddr(self.ctl.as_raw_fd(), &req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        unsafe {
+            let ctl = Fd::new(libc::socket(AF_INET6, SOCK_DGRAM, 0))?;
+            let mut req: in6_aliasreq = mem::zeroed();
+            ptr::copy_nonoverlapping(
+                self.name.as_ptr() as *const c_char,
+                req.ifra_name.as_mut_ptr(),
+                self.name.len(),
+            );
+            req.ifra_addr = sockaddr_v6(address);
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            req.ifra_prefixmask = sockaddr_v6(Ipv6Addr::from(mask));
+            req.ifra_lifetime.ia6t_vltime = ND6_INFINITE_LIFETIME;
+            req.ifra_lifetime.ia6t_pltime = ND6_INFINITE_LIFETIME;
+            if siocaifaddr_in6(ctl.as_raw_fd(), &req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
     }
 
     fn mtu(&self) -> io::Result<u32> {
@@ -290,7 +334,13 @@ impl IFace for Device {
         let mut packet = Vec::<u8>::with_capacity(4 + buf.len());
         packet.push(0);
         packet.push(0);
-        packet.extend_from_slice(&(libc::PF_INET as u16).to_be_bytes());
+        // utun的包头需要标明协议族
+        let family = if buf.first().map(|v| v >> 4) == Some(6) {
+            libc::PF_INET6
+        } else {
+            libc::PF_INET
+        };
+        packet.extend_from_slice(&(family as u16).to_be_bytes());
         packet.extend_from_slice(buf);
         self.tun.write(&packet)
     }
@@ -1,5 +1,3 @@
 mod device;
 pub use device::Device;
 mod sys;
-
-mod route;
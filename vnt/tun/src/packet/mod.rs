@@ -19,6 +19,26 @@ where
         //处理arp包
         let mut ether = ethernet::packet::EthernetPacket::new(&mut eth_buf[..len])?;
         match ether.protocol() {
+            Protocol::Ipv6 => {
+                //邻居请求直接回复默认的MAC，和arp的处理一致
+                if let Some(reply) = neighbor_advertisement(ether.payload()) {
+                    let sender_h: [u8; 6] = ether.source().try_into().unwrap();
+                    let mut reply_ether =
+                        ethernet::packet::EthernetPacket::unchecked(vec![0; 14 + reply.len()]);
+                    reply_ether.set_source(&MAC);
+                    reply_ether.set_destination(&sender_h);
+                    reply_ether.set_protocol(Protocol::Ipv6);
+                    reply_ether.payload_mut().copy_from_slice(&reply);
+                    write_fn(&reply_ether.buffer)?;
+                    continue;
+                }
+                let len = ether.payload().len();
+                if len > buf.len() {
+                    return Err(io::Error::new(io::ErrorKind::Other, "short"));
+                }
+                buf[..len].copy_from_slice(ether.payload());
+                return Ok(len);
+            }
             Protocol::Ipv4 => {
                 let len = ether.payload().len();
                 if len > buf.len() {
@@ -61,7 +81,51 @@ where
     let mut ether = ethernet::packet::EthernetPacket::unchecked(vec![0; 14 + buf.len()]);
     ether.set_source(&MAC);
     ether.set_destination(mac);
-    ether.set_protocol(Protocol::Ipv4);
+    if !buf.is_empty() && buf[0] >> 4 == 6 {
+        ether.set_protocol(Protocol::Ipv6);
+    } else {
+        ether.set_protocol(Protocol::Ipv4);
+    }
     ether.payload_mut().copy_from_slice(buf);
     write_fn(&ether.buffer)
 }
+
+/// 邻居请求(icmpv6 type=135)的回复，带上默认MAC，重复地址检测的请求不回复
+fn neighbor_advertisement(packet: &[u8]) -> Option<Vec<u8>> {
+    if packet.len() < 40 + 24 || packet[6] != 58 || packet[40] != 135 {
+        return None;
+    }
+    let source: [u8; 16] = packet[8..24].try_into().unwrap();
+    if source == [0; 16] {
+        return None;
+    }
+    let target: [u8; 16] = packet[40 + 8..40 + 24].try_into().unwrap();
+    let mut buf = vec![0u8; 40 + 32];
+    buf[0] = 6 << 4;
+    buf[4..6].copy_from_slice(&32u16.to_be_bytes());
+    buf[6] = 58;
+    buf[7] = 255;
+    buf[8..24].copy_from_slice(&target);
+    buf[24..40].copy_from_slice(&source);
+    {
+        let icmp = &mut buf[40..];
+        icmp[0] = 136;
+        // Solicited | Override
+        icmp[4] = 0b0110_0000;
+        icmp[8..24].copy_from_slice(&target);
+        // 目标链路层地址选项
+        icmp[24] = 2;
+        icmp[25] = 1;
+        icmp[26..32].copy_from_slice(&MAC);
+    }
+    // 校验和，伪首部为源地址、目的地址、长度和下一个首部
+    let mut sum: u32 = 32 + 58;
+    for v in buf[8..40].chunks_exact(2).chain(buf[40..].chunks_exact(2)) {
+        sum += u32::from(u16::from_be_bytes([v[0], v[1]]));
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    buf[42..44].copy_from_slice(&(!sum as u16).to_be_bytes());
+    Some(buf)
+}
@@ -1,28 +1,10 @@
 mod fd;
 
 pub use fd::Fd;
-#[cfg(any(target_os = "macos", target_os = "linux"))]
-use std::process::Output;
-#[cfg(any(target_os = "macos", target_os = "linux"))]
+#[cfg(not(target_os = "android"))]
 mod sockaddr;
-#[cfg(any(target_os = "macos", target_os = "linux"))]
-pub use sockaddr::SockAddr;
-
-#[cfg(any(target_os = "macos", target_os = "linux"))]
-pub fn exe_cmd(cmd: &str) -> std::io::Result<Output> {
-    use std::io;
-    use std::process::Command;
-    println!("exe cmd: {}", cmd);
-    let out = Command::new("sh")
-        .arg("-c")
-        .arg(cmd)
-        .output()
-        .expect("sh exec error!");
-    if !out.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("cmd={},out={:?}", cmd, out),
-        ));
-    }
-    Ok(out)
-}
+#[cfg(not(target_os = "android"))]
+pub use sockaddr::*;
+// BSD系统通过路由套接字修改路由
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+pub mod route;
@@ -0,0 +1,90 @@
+use std::ffi::CString;
+use std::net::Ipv4Addr;
+use std::{io, mem, ptr};
+
+use libc::{c_int, sockaddr, sockaddr_dl};
+
+use crate::unix::{sockaddr_v4, Fd};
+
+// 路由消息中的地址按4字节对齐
+const ALIGN: usize = mem::size_of::<u32>();
+
+pub fn add_route(name: &str, address: Ipv4Addr, netmask: Ipv4Addr) -> io::Result<()> {
+    route(libc::RTM_ADD, name, address, netmask)
+}
+pub fn del_route(name: &str, address: Ipv4Addr, netmask: Ipv4Addr) -> io::Result<()> {
+    route(libc::RTM_DELETE, name, address, netmask)
+}
+
+/// 通过PF_ROUTE套接字修改路由，等同于'route -n add <address> -netmask <netmask> -interface <name>'，
+/// 内核直接返回错误码，路由已存在时为EEXIST，删除不存在的路由时为ESRCH
+fn route(rtm_type: c_int, name: &str, address: Ipv4Addr, netmask: Ipv4Addr) -> io::Result<()> {
+    let index = if_index(name)?;
+    let dest: sockaddr = sockaddr_v4(address);
+    let mask: sockaddr = sockaddr_v4(netmask);
+    // 网关为网卡的链路层地址，表示直接从网卡发出
+    let mut gateway: sockaddr_dl = unsafe { mem::zeroed() };
+    gateway.sdl_len = mem::size_of::<sockaddr_dl>() as _;
+    gateway.sdl_family = libc::AF_LINK as _;
+    gateway.sdl_index = index as _;
+
+    let mut buf = vec![0u8; mem::size_of::<libc::rt_msghdr>()];
+    put(&mut buf, &dest);
+    put(&mut buf, &gateway);
+    put(&mut buf, &mask);
+
+    let mut header: libc::rt_msghdr = unsafe { mem::zeroed() };
+    header.rtm_msglen = buf.len() as _;
+    #[cfg(target_os = "openbsd")]
+    {
+        header.rtm_hdrlen = mem::size_of::<libc::rt_msghdr>() as _;
+    }
+    header.rtm_version = libc::RTM_VERSION as _;
+    header.rtm_type = rtm_type as _;
+    header.rtm_index = index as _;
+    header.rtm_flags = libc::RTF_UP | libc::RTF_STATIC;
+    header.rtm_addrs = libc::RTA_DST | libc::RTA_GATEWAY | libc::RTA_NETMASK;
+    header.rtm_pid = std::process::id() as _;
+    header.rtm_seq = 1;
+    unsafe {
+        ptr::copy_nonoverlapping(
+            &header as *const _ as *const u8,
+            buf.as_mut_ptr(),
+            mem::size_of::<libc::rt_msghdr>(),
+        );
+    }
+    let fd = Fd::new(unsafe { libc::socket(libc::PF_ROUTE, libc::SOCK_RAW, libc::AF_UNSPEC) })?;
+    fd.write(&buf).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "route {} {}/{} {} {}",
+                if rtm_type == libc::RTM_ADD {
+                    "add"
+                } else {
+                    "delete"
+                },
+                address,
+                netmask,
+                name,
+                e
+            ),
+        )
+    })?;
+    Ok(())
+}
+
+fn put<T>(buf: &mut Vec<u8>, value: &T) {
+    let len = mem::size_of::<T>();
+    let bytes = unsafe { std::slice::from_raw_parts(value as *const T as *const u8, len) };
+    buf.extend_from_slice(bytes);
+    buf.resize(buf.len() + (ALIGN - len % ALIGN) % ALIGN, 0);
+}
+
+fn if_index(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
@@ -65,3 +65,20 @@ impl From<SockAddr> for sockaddr_in {
         addr.0
     }
 }
+
+/// 带长度的ipv4地址，BSD的ioctl和路由消息依赖sa_len
+#[cfg(not(target_os = "linux"))]
+pub fn sockaddr_v4(ip: Ipv4Addr) -> sockaddr {
+    let mut addr: sockaddr_in = SockAddr::from(ip).into();
+    addr.sin_len = mem::size_of::<sockaddr_in>() as _;
+    unsafe { mem::transmute(addr) }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sockaddr_v6(ip: std::net::Ipv6Addr) -> libc::sockaddr_in6 {
+    let mut addr: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+    addr.sin6_len = mem::size_of::<libc::sockaddr_in6>() as _;
+    addr.sin6_family = libc::AF_INET6 as _;
+    addr.sin6_addr.s6_addr = ip.octets();
+    addr
+}
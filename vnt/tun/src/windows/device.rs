@@ -1,7 +1,7 @@
 use crate::device::IFace;
 use crate::windows::{tap, tun};
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 pub enum Device {
     Tap(tap::Device),
@@ -9,11 +9,17 @@ pub enum Device {
 }
 
 impl Device {
-    pub fn new(name: String, tap: bool) -> io::Result<Self> {
-        if tap {
-            Ok(Device::Tap(tap::Device::new(name)?))
+    /// layer2为true时使用tap网卡，直接读写以太网帧，wintun_path为tun模式使用的wintun.dll路径
+    pub fn new(
+        name: String,
+        tap: bool,
+        layer2: bool,
+        wintun_path: Option<&str>,
+    ) -> io::Result<Self> {
+        if tap || layer2 {
+            Ok(Device::Tap(tap::Device::new(name, layer2)?))
         } else {
-            Ok(Device::Tun(tun::Device::new(name)?))
+            Ok(Device::Tun(tun::Device::new(name, wintun_path)?))
         }
     }
 }
@@ -47,6 +53,13 @@ impl IFace for Device {
         }
     }
 
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        match self {
+            Device::Tap(dev) => dev.set_ipv6(address, prefix),
+            Device::Tun(dev) => dev.set_ipv6(address, prefix),
+        }
+    }
+
     fn mtu(&self) -> io::Result<u32> {
         match self {
             Device::Tap(dev) => dev.mtu(),
@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use winapi::shared::ifdef::NET_LUID;
 use winapi::shared::minwindef::DWORD;
 use winapi::um::fileapi::OPEN_EXISTING;
@@ -43,6 +43,8 @@ pub struct Device {
     index: u32,
     luid: NET_LUID,
     mac: [u8; 6],
+    // 二层模式，不做三层的模拟，原样读写以太网帧
+    layer2: bool,
 }
 
 unsafe impl Send for Device {}
@@ -51,7 +53,7 @@ unsafe impl Sync for Device {}
 
 impl Device {
     /// 打开设备，设置为TUN模式，激活网卡
-    pub fn new(name: String) -> io::Result<Self> {
+    pub fn new(name: String, layer2: bool) -> io::Result<Self> {
         let luid = ffi::alias_to_luid(&encode_utf16(&name)).map_err(|e| {
             io::Error::new(e.kind(), format!("alias_to_luid name={},err={:?}", name, e))
         })?;
@@ -103,6 +105,7 @@ impl Device {
             index,
             luid,
             mac,
+            layer2,
         };
         device.enabled(true)?;
         Ok(device)
@@ -139,6 +142,10 @@ impl IFace for Device {
         netsh::set_interface_ip(self.index, &address, &mask)
     }
 
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        netsh::set_interface_ipv6(self.index, &address, prefix)
+    }
+
     fn mtu(&self) -> io::Result<u32> {
         let mut mtu = 0;
         ffi::device_io_control(self.handle, TAP_WIN_IOCTL_GET_MTU, &(), &mut mtu).map(|_| mtu)
@@ -159,6 +166,9 @@ impl IFace for Device {
     }
 
     fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.layer2 {
+            return ffi::read_file(self.handle, buf).map(|res| res as usize);
+        }
         packet::read_tap(
             buf,
             |eth_buf| ffi::read_file(self.handle, eth_buf).map(|res| res as usize),
@@ -167,6 +177,9 @@ impl IFace for Device {
     }
 
     fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        if self.layer2 {
+            return self.write_tap(buf);
+        }
         // 封装二层数据
         packet::write_tap(
             buf,
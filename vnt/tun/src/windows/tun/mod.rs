@@ -2,7 +2,7 @@
 use libloading::Library;
 use sha2::Digest;
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use winapi::um::winbase;
 use winapi::um::{synchapi, winnt};
 
@@ -11,6 +11,7 @@ use crate::windows::decode_utf16;
 use crate::windows::{encode_utf16, ffi, netsh, route};
 
 mod packet;
+mod wintun_dll;
 mod wintun_log;
 mod wintun_raw;
 
@@ -49,15 +50,14 @@ unsafe impl Send for Device {}
 unsafe impl Sync for Device {}
 
 impl Device {
-    pub fn new(name: String) -> io::Result<Self> {
+    /// wintun_path为wintun.dll的路径，None时在程序目录、当前目录和系统目录中查找
+    pub fn new(name: String, wintun_path: Option<&str>) -> io::Result<Self> {
         unsafe {
-            let library = match Library::new("wintun.dll") {
+            let path = wintun_dll::locate(wintun_path)?;
+            let library = match Library::new(&path) {
                 Ok(library) => library,
                 Err(e) => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("wintun.dll not found {:?}", e),
-                    ));
+                    return Err(wintun_dll::not_found(e));
                 }
             };
             let win_tun = match wintun_raw::wintun::from_library(library) {
@@ -191,6 +191,10 @@ impl IFace for Device {
         netsh::set_interface_ip(self.index, &address, &mask)
     }
 
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        netsh::set_interface_ipv6(self.index, &address, prefix)
+    }
+
     fn mtu(&self) -> io::Result<u32> {
         Err(io::Error::from(io::ErrorKind::Unsupported))
     }
@@ -0,0 +1,118 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DLL_NAME: &str = "wintun.dll";
+const DOWNLOAD_URL: &str = "https://www.wintun.net/builds/wintun-0.14.1.zip";
+
+const IMAGE_FILE_MACHINE_I386: u16 = 0x014c;
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+const IMAGE_FILE_MACHINE_ARMNT: u16 = 0x01c4;
+const IMAGE_FILE_MACHINE_ARM64: u16 = 0xaa64;
+
+#[cfg(target_arch = "x86")]
+const CURRENT_MACHINE: u16 = IMAGE_FILE_MACHINE_I386;
+#[cfg(target_arch = "x86_64")]
+const CURRENT_MACHINE: u16 = IMAGE_FILE_MACHINE_AMD64;
+#[cfg(target_arch = "arm")]
+const CURRENT_MACHINE: u16 = IMAGE_FILE_MACHINE_ARMNT;
+#[cfg(target_arch = "aarch64")]
+const CURRENT_MACHINE: u16 = IMAGE_FILE_MACHINE_ARM64;
+
+/// 确定要加载的wintun.dll并检查架构，
+/// 架构不匹配时LoadLibrary只会返回"%1 不是有效的 Win32 应用程序"，这里提前给出明确的提示。
+/// 没有指定路径时依次查找程序目录和当前目录，都没有时交给LoadLibrary按系统的搜索顺序查找
+pub fn locate(wintun_path: Option<&str>) -> io::Result<PathBuf> {
+    let path = match wintun_path {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            if !path.is_file() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("wintun.dll not found at {:?}", path),
+                ));
+            }
+            path
+        }
+        None => match search() {
+            Some(path) => path,
+            None => return Ok(PathBuf::from(DLL_NAME)),
+        },
+    };
+    check_machine(&path)?;
+    Ok(path)
+}
+
+/// 加载失败时的提示
+pub fn not_found(e: impl std::fmt::Debug) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "wintun.dll not found {:?}, download {} and put bin\\{}\\wintun.dll next to the program, or specify it with '--wintun-path'",
+            e,
+            DOWNLOAD_URL,
+            machine_name(CURRENT_MACHINE)
+        ),
+    )
+}
+
+fn search() -> Option<PathBuf> {
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|path| path.parent().map(|v| v.join(DLL_NAME)));
+    let current_dir = std::env::current_dir().ok().map(|v| v.join(DLL_NAME));
+    exe_dir
+        .into_iter()
+        .chain(current_dir)
+        .find(|path| path.is_file())
+}
+
+fn check_machine(path: &Path) -> io::Result<()> {
+    let buf = std::fs::read(path)?;
+    let machine = match pe_machine(&buf) {
+        Some(machine) => machine,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} is not a valid dll", path),
+            ));
+        }
+    };
+    if machine != CURRENT_MACHINE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{:?} is for {}, but the program is {}, use bin\\{}\\wintun.dll from {}",
+                path,
+                machine_name(machine),
+                machine_name(CURRENT_MACHINE),
+                machine_name(CURRENT_MACHINE),
+                DOWNLOAD_URL
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// PE文件头中的Machine字段，DOS头0x3c处是PE头的偏移，PE头为"PE\0\0"加上Machine
+fn pe_machine(buf: &[u8]) -> Option<u16> {
+    if buf.len() < 0x40 || &buf[..2] != b"MZ" {
+        return None;
+    }
+    let offset = u32::from_le_bytes(buf[0x3c..0x40].try_into().ok()?) as usize;
+    let header = buf.get(offset..offset + 6)?;
+    if &header[..4] != b"PE\0\0" {
+        return None;
+    }
+    Some(u16::from_le_bytes([header[4], header[5]]))
+}
+
+// 和wintun压缩包中的目录名一致
+fn machine_name(machine: u16) -> &'static str {
+    match machine {
+        IMAGE_FILE_MACHINE_I386 => "x86",
+        IMAGE_FILE_MACHINE_AMD64 => "amd64",
+        IMAGE_FILE_MACHINE_ARMNT => "arm",
+        IMAGE_FILE_MACHINE_ARM64 => "arm64",
+        _ => "unknown",
+    }
+}